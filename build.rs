@@ -0,0 +1,24 @@
+//! Record the git revision the backend is built from, which is part of the
+//! provenance of derived data products. Builds outside of a git checkout
+//! can set `SALSA_GIT_HASH` themselves.
+
+use std::process::Command;
+
+fn main() {
+    println!("cargo:rerun-if-env-changed=SALSA_GIT_HASH");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+    let revision = std::env::var("SALSA_GIT_HASH").ok().or_else(|| {
+        Command::new("git")
+            .args(["rev-parse", "HEAD"])
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .and_then(|output| String::from_utf8(output.stdout).ok())
+            .map(|revision| revision.trim().to_string())
+    });
+    println!(
+        "cargo:rustc-env=SALSA_GIT_HASH={}",
+        revision.unwrap_or_else(|| "unknown".to_string())
+    );
+}
@@ -0,0 +1,27 @@
+//! Download every archived measurement as FITS into a directory.
+//!
+//! cargo run --example download -- <url> <directory>
+
+use salsa_client::Client;
+use std::path::Path;
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = std::env::args().collect();
+    let [_, url, directory] = args.as_slice() else {
+        return Err("usage: download <url> <directory>".into());
+    };
+    let client = Client::new(url)?;
+    std::fs::create_dir_all(directory)?;
+    for measurement in client.measurements().await? {
+        let path = Path::new(directory).join(format!("salsa-{}.fits", measurement.id));
+        std::fs::write(&path, client.download_fits(measurement.id).await?)?;
+        println!(
+            "{} from {} at {}",
+            path.display(),
+            measurement.telescope_name,
+            measurement.start
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,61 @@
+//! Point a telescope at a galactic position and integrate for a fixed time.
+//!
+//! SALSA_TOKEN=<token> cargo run --example observe -- <url> <telescope> <l> <b> <seconds>
+
+use salsa_client::{Client, ObserveMode, TelescopeStatus, TelescopeTarget};
+use std::time::Duration;
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = std::env::args().collect();
+    let [_, url, telescope, l, b, seconds] = args.as_slice() else {
+        return Err("usage: observe <url> <telescope> <l> <b> <seconds>".into());
+    };
+    let token = std::env::var("SALSA_TOKEN").map_err(|_| "SALSA_TOKEN is not set")?;
+    let client = Client::new(url)?.login(&token);
+
+    client
+        .set_target(
+            telescope,
+            TelescopeTarget::galactic_degrees(l.parse()?, b.parse()?),
+        )
+        .await?;
+    loop {
+        let info = client
+            .telescope(telescope)
+            .await?
+            .map_err(|e| e.to_string())?;
+        if info.status == TelescopeStatus::Tracking {
+            break;
+        }
+        println!("Waiting for {} to reach the target", telescope);
+        tokio::time::sleep(Duration::from_secs(2)).await;
+    }
+
+    let seconds = seconds.parse()?;
+    client
+        .start_integration(telescope, ObserveMode::Fixed { seconds })
+        .await?;
+    println!("Integrating for {} s", seconds);
+    loop {
+        tokio::time::sleep(Duration::from_secs(5)).await;
+        let info = client
+            .telescope(telescope)
+            .await?
+            .map_err(|e| e.to_string())?;
+        if !info.measurement_in_progress {
+            break;
+        }
+    }
+
+    let latest = client
+        .measurements()
+        .await?
+        .into_iter()
+        .filter(|m| &m.telescope_name == telescope)
+        .max_by_key(|m| m.start);
+    if let Some(measurement) = latest {
+        println!("Saved as measurement {}", measurement.id);
+    }
+    Ok(())
+}
@@ -0,0 +1,391 @@
+//! Client for the JSON API of the backend.
+//!
+//! A [`Client`] authenticates as the user it acts for with their API
+//! token, and fetches a confirmation token by itself before actions that
+//! need an admin. The angle and direction types are the backend sources included as
+//! they are, the other types only have the fields needed by scripts.
+
+#[allow(dead_code)]
+#[path = "../../src/angles.rs"]
+pub mod angles;
+#[allow(dead_code)]
+#[path = "../../src/coords.rs"]
+pub mod coords;
+
+use angles::{Degrees, Radians};
+use chrono::{DateTime, Utc};
+use coords::Direction;
+use reqwest::{Method, RequestBuilder, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use thiserror::Error;
+
+/// Header with the confirmation token of an admin action.
+pub const CONFIRMATION_TOKEN_HEADER: &str = "confirmation-token";
+
+#[derive(Debug, Error)]
+pub enum ClientError {
+    #[error("request failed")]
+    Request {
+        #[from]
+        source: reqwest::Error,
+    },
+    #[error("{status}: {message}")]
+    Refused { status: StatusCode, message: String },
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub enum TelescopeTarget {
+    Equatorial {
+        ra: Radians,
+        dec: Radians,
+    },
+    Galactic {
+        l: Radians,
+        b: Radians,
+    },
+    Horizontal {
+        azimuth: Radians,
+        elevation: Radians,
+    },
+    /// The orbital elements are left as they are sent by the backend.
+    Satellite {
+        elements: serde_json::Value,
+    },
+    Parked,
+    Stopped,
+}
+
+impl TelescopeTarget {
+    pub fn galactic_degrees(l: f64, b: f64) -> TelescopeTarget {
+        TelescopeTarget::Galactic {
+            l: Degrees(l).to_radians(),
+            b: Degrees(b).to_radians(),
+        }
+    }
+
+    pub fn equatorial_degrees(ra: f64, dec: f64) -> TelescopeTarget {
+        TelescopeTarget::Equatorial {
+            ra: Degrees(ra).to_radians(),
+            dec: Degrees(dec).to_radians(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+pub enum TelescopeStatus {
+    Idle,
+    Slewing,
+    Tracking,
+}
+
+#[derive(Deserialize, PartialEq, Debug, Clone)]
+pub struct TelescopeInfo {
+    pub id: String,
+    pub status: TelescopeStatus,
+    pub commanded_horizontal: Option<Direction>,
+    pub current_horizontal: Direction,
+    pub current_target: TelescopeTarget,
+    pub measurement_in_progress: bool,
+}
+
+/// How long an integration runs.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone, Default)]
+pub enum ObserveMode {
+    /// Until stopped with [`Client::stop_integration`].
+    #[default]
+    UntilStop,
+    /// Stopped by the telescope after a fixed time.
+    Fixed { seconds: u64 },
+}
+
+/// The receiver settings sent to start or stop an integration. The backend
+/// uses the defaults of the telescope for everything else.
+#[derive(Serialize, PartialEq, Debug, Copy, Clone)]
+struct ReceiverConfiguration {
+    integrate: bool,
+    mode: ObserveMode,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Booking {
+    pub start_time: DateTime<Utc>,
+    pub end_time: DateTime<Utc>,
+    pub telescope_name: String,
+    pub user_name: String,
+}
+
+#[derive(Deserialize, PartialEq, Debug, Clone)]
+pub struct ArchivedMeasurementSummary {
+    pub id: u64,
+    pub telescope_name: String,
+    pub target: TelescopeTarget,
+    pub start: DateTime<Utc>,
+    pub duration: Duration,
+    pub interrupted: bool,
+}
+
+/// The frequency axis of a measurement as velocities relative to the LSR.
+#[derive(Deserialize, PartialEq, Debug, Clone)]
+pub struct VelocityAxis {
+    /// Correction in km/s from the topocentric frame to the LSR.
+    pub correction: f64,
+    /// Velocity of each channel in km/s.
+    pub velocities: Vec<f64>,
+}
+
+#[derive(Serialize, Debug)]
+struct ConfirmationRequest<'a> {
+    method: &'a str,
+    path: &'a str,
+    secret: &'a str,
+}
+
+#[derive(Deserialize, Debug)]
+struct ConfirmationToken {
+    token: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Client {
+    http: reqwest::Client,
+    base_url: String,
+    token: Option<String>,
+    confirmation_secret: Option<String>,
+}
+
+impl Client {
+    /// A client of the backend at `base_url`, e.g. `http://localhost:3000`,
+    /// making anonymous requests.
+    pub fn new(base_url: &str) -> Result<Client, ClientError> {
+        Ok(Client {
+            http: reqwest::Client::builder().build()?,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            token: None,
+            confirmation_secret: None,
+        })
+    }
+
+    /// Make the requests as the user with the API token `token`, see
+    /// `src/authentication/mod.rs`.
+    pub fn login(self, token: &str) -> Client {
+        Client {
+            token: Some(token.to_string()),
+            ..self
+        }
+    }
+
+    /// Confirm admin actions with the confirmation secret of the admin
+    /// logged in as.
+    pub fn with_confirmation_secret(self, secret: &str) -> Client {
+        Client {
+            confirmation_secret: Some(secret.to_string()),
+            ..self
+        }
+    }
+
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        let request = self
+            .http
+            .request(method, format!("{}{}", self.base_url, path));
+        match &self.token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        }
+    }
+
+    /// Ask for a confirmation token for an admin action and add it to the
+    /// request.
+    async fn confirmed(&self, method: Method, path: &str) -> Result<RequestBuilder, ClientError> {
+        let request = ConfirmationRequest {
+            method: method.as_str(),
+            path,
+            secret: self.confirmation_secret.as_deref().unwrap_or_default(),
+        };
+        let token: ConfirmationToken = receive(
+            self.request(Method::POST, "/api/confirmations")
+                .json(&request),
+        )
+        .await?
+        .json()
+        .await?;
+        Ok(self
+            .request(method, path)
+            .header(CONFIRMATION_TOKEN_HEADER, token.token))
+    }
+
+    pub async fn telescopes(&self) -> Result<Vec<TelescopeInfo>, ClientError> {
+        json(self.request(Method::GET, "/api/telescopes")).await
+    }
+
+    /// The telescope with its status, or an error from the telescope if it
+    /// could not be reached.
+    pub async fn telescope(
+        &self,
+        telescope_id: &str,
+    ) -> Result<Result<TelescopeInfo, serde_json::Value>, ClientError> {
+        json(self.request(Method::GET, &format!("/api/telescopes/{}", telescope_id))).await
+    }
+
+    pub async fn set_target(
+        &self,
+        telescope_id: &str,
+        target: TelescopeTarget,
+    ) -> Result<(), ClientError> {
+        receive(
+            self.request(
+                Method::POST,
+                &format!("/api/telescopes/{}/target", telescope_id),
+            )
+            .json(&target),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Start integrating on the current target. Repeating a recent
+    /// observation is refused by the backend unless `allow_duplicate` is
+    /// given, which this does not do.
+    pub async fn start_integration(
+        &self,
+        telescope_id: &str,
+        mode: ObserveMode,
+    ) -> Result<(), ClientError> {
+        self.set_integration(telescope_id, true, mode).await
+    }
+
+    /// Stop the integration, also a fixed one before its time is up.
+    pub async fn stop_integration(&self, telescope_id: &str) -> Result<(), ClientError> {
+        self.set_integration(telescope_id, false, ObserveMode::default())
+            .await
+    }
+
+    async fn set_integration(
+        &self,
+        telescope_id: &str,
+        integrate: bool,
+        mode: ObserveMode,
+    ) -> Result<(), ClientError> {
+        receive(
+            self.request(
+                Method::POST,
+                &format!("/api/telescopes/{}/receiver", telescope_id),
+            )
+            .json(&ReceiverConfiguration { integrate, mode }),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Restart a telescope, which only admins may do.
+    pub async fn restart(&self, telescope_id: &str) -> Result<(), ClientError> {
+        let path = format!("/api/telescopes/{}/restart", telescope_id);
+        receive(self.confirmed(Method::POST, &path).await?).await?;
+        Ok(())
+    }
+
+    pub async fn bookings(&self) -> Result<Vec<Booking>, ClientError> {
+        json(self.request(Method::GET, "/api/bookings")).await
+    }
+
+    pub async fn measurements(&self) -> Result<Vec<ArchivedMeasurementSummary>, ClientError> {
+        json(self.request(Method::GET, "/api/archive")).await
+    }
+
+    /// The archived measurement as the JSON file offered for download.
+    pub async fn download_measurement(&self, id: u64) -> Result<Vec<u8>, ClientError> {
+        bytes(self.request(Method::GET, &format!("/api/archive/{}/file", id))).await
+    }
+
+    /// Velocities relative to the LSR of the channels of an archived
+    /// measurement, for the spectral line named `line`, e.g. "HI".
+    pub async fn measurement_velocities(
+        &self,
+        id: u64,
+        line: &str,
+    ) -> Result<VelocityAxis, ClientError> {
+        json(
+            self.request(Method::GET, &format!("/api/archive/{}/velocity", id))
+                .query(&[("line", line)]),
+        )
+        .await
+    }
+
+    /// The measurements started between `from` and `to` on `telescope`, as
+    /// a ZIP file of CSV files with a manifest.
+    pub async fn export_measurements(
+        &self,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        telescope: &str,
+    ) -> Result<Vec<u8>, ClientError> {
+        bytes(self.request(Method::GET, "/api/archive/export").query(&[
+            ("from", from.to_rfc3339()),
+            ("to", to.to_rfc3339()),
+            ("telescope", telescope.to_string()),
+        ]))
+        .await
+    }
+
+    /// The archived measurement as a FITS file.
+    pub async fn download_fits(&self, id: u64) -> Result<Vec<u8>, ClientError> {
+        bytes(self.request(Method::GET, &format!("/api/archive/{}/fits", id))).await
+    }
+}
+
+/// Send the request, turning responses other than success into errors with
+/// the message from the backend.
+async fn receive(request: RequestBuilder) -> Result<reqwest::Response, ClientError> {
+    let response = request.send().await?;
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    Err(ClientError::Refused {
+        status,
+        message: response.text().await.unwrap_or_default(),
+    })
+}
+
+async fn json<T: DeserializeOwned>(request: RequestBuilder) -> Result<T, ClientError> {
+    Ok(receive(request).await?.json().await?)
+}
+
+async fn bytes(request: RequestBuilder) -> Result<Vec<u8>, ClientError> {
+    Ok(receive(request).await?.bytes().await?.to_vec())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_messages_match_backend() {
+        assert_eq!(
+            serde_json::to_value(TelescopeTarget::galactic_degrees(180.0, 0.0)).unwrap(),
+            serde_json::json!({"Galactic": {"l": std::f64::consts::PI, "b": 0.0}})
+        );
+        assert_eq!(
+            serde_json::to_value(ReceiverConfiguration {
+                integrate: true,
+                mode: ObserveMode::Fixed { seconds: 60 },
+            })
+            .unwrap(),
+            serde_json::json!({"integrate": true, "mode": {"Fixed": {"seconds": 60}}})
+        );
+        let info: TelescopeInfo = serde_json::from_value(serde_json::json!({
+            "id": "fake",
+            "status": "Tracking",
+            "commanded_horizontal": null,
+            "current_horizontal": {"azimuth": 1.0, "altitude": 0.5},
+            "current_target": "Parked",
+            "most_recent_error": null,
+            "measurement_in_progress": false,
+            "latest_observation": null,
+        }))
+        .unwrap();
+        assert_eq!(info.status, TelescopeStatus::Tracking);
+        assert_eq!(info.current_target, TelescopeTarget::Parked);
+    }
+}
@@ -0,0 +1,158 @@
+//! Coordinate conversions and LSR velocities of the backend, compiled to
+//! WebAssembly so that previews in the browser use the very same code as
+//! the telescopes.
+//!
+//! The backend modules are included as they are. The bindings take angles
+//! in degrees and times in milliseconds since the Unix epoch, as given by
+//! `Date.getTime()`.
+
+#[allow(dead_code)]
+#[path = "../../src/angles.rs"]
+mod angles;
+#[allow(dead_code)]
+#[path = "../../src/coords.rs"]
+mod coords;
+
+use angles::{Degrees, Radians};
+use chrono::{DateTime, TimeZone, Utc};
+use coords::{Direction, Location};
+use wasm_bindgen::prelude::*;
+
+#[wasm_bindgen]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct HorizontalPosition {
+    /// Degrees east of north.
+    pub azimuth: f64,
+    /// Degrees above the horizon.
+    pub altitude: f64,
+}
+
+impl From<Direction> for HorizontalPosition {
+    fn from(direction: Direction) -> Self {
+        HorizontalPosition {
+            azimuth: direction.azimuth.to_degrees().0,
+            altitude: direction.altitude.to_degrees().0,
+        }
+    }
+}
+
+fn location(longitude: f64, latitude: f64) -> Location {
+    Location {
+        longitude: Degrees(longitude).to_radians(),
+        latitude: Degrees(latitude).to_radians(),
+    }
+}
+
+fn time(unix_millis: f64) -> Result<DateTime<Utc>, JsError> {
+    Utc.timestamp_millis_opt(unix_millis as i64)
+        .single()
+        .ok_or_else(|| JsError::new("time out of range"))
+}
+
+fn radians(degrees: f64) -> Radians {
+    Degrees(degrees).to_radians()
+}
+
+#[wasm_bindgen(js_name = horizontalFromEquatorial)]
+pub fn horizontal_from_equatorial(
+    longitude: f64,
+    latitude: f64,
+    unix_millis: f64,
+    ra: f64,
+    dec: f64,
+) -> Result<HorizontalPosition, JsError> {
+    Ok(coords::horizontal_from_equatorial(
+        location(longitude, latitude),
+        time(unix_millis)?,
+        radians(ra),
+        radians(dec),
+    )
+    .into())
+}
+
+#[wasm_bindgen(js_name = horizontalFromGalactic)]
+pub fn horizontal_from_galactic(
+    longitude: f64,
+    latitude: f64,
+    unix_millis: f64,
+    l: f64,
+    b: f64,
+) -> Result<HorizontalPosition, JsError> {
+    Ok(coords::horizontal_from_galactic(
+        location(longitude, latitude),
+        time(unix_millis)?,
+        radians(l),
+        radians(b),
+    )
+    .into())
+}
+
+#[wasm_bindgen(js_name = horizontalFromSun)]
+pub fn horizontal_from_sun(
+    longitude: f64,
+    latitude: f64,
+    unix_millis: f64,
+) -> Result<HorizontalPosition, JsError> {
+    Ok(coords::horizontal_from_sun(location(longitude, latitude), time(unix_millis)?).into())
+}
+
+/// Set UT1 - UTC in seconds, as the backend does from its configuration.
+#[wasm_bindgen(js_name = setDut1)]
+pub fn set_dut1(seconds: f64) {
+    coords::set_dut1(seconds);
+}
+
+/// Correction in m/s from the topocentric frame to the LSR towards `l`, `b`.
+#[wasm_bindgen(js_name = vlsrCorrection)]
+pub fn vlsr_correction(l: f64, b: f64, unix_millis: f64) -> Result<f64, JsError> {
+    Ok(coords::vlsrcorr_from_galactic(
+        radians(l),
+        radians(b),
+        time(unix_millis)?,
+    ))
+}
+
+/// Velocity axis in km/s relative to the LSR of a spectrum with channels at
+/// `freqs` in Hz.
+#[wasm_bindgen(js_name = lsrVelocityAxis)]
+pub fn lsr_velocity_axis(
+    freqs: Vec<f64>,
+    rest_frequency: f64,
+    l: f64,
+    b: f64,
+    unix_millis: f64,
+) -> Result<Vec<f64>, JsError> {
+    Ok(coords::lsr_velocity_axis(
+        &freqs,
+        rest_frequency,
+        radians(l),
+        radians(b),
+        time(unix_millis)?,
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_bindings_match_backend() {
+        let when = Utc.with_ymd_and_hms(2024, 3, 20, 12, 0, 0).unwrap();
+        let millis = when.timestamp_millis() as f64;
+        let onsala = location(11.917778, 57.393056);
+        let position = horizontal_from_galactic(11.917778, 57.393056, millis, 120.0, 0.0).unwrap();
+        let direction =
+            coords::horizontal_from_galactic(onsala, when, radians(120.0), radians(0.0));
+        assert_eq!(position, HorizontalPosition::from(direction));
+        assert_eq!(
+            lsr_velocity_axis(vec![1.4204e9], 1.420405751768e9, 120.0, 0.0, millis).unwrap(),
+            coords::lsr_velocity_axis(
+                &[1.4204e9],
+                1.420405751768e9,
+                radians(120.0),
+                radians(0.0),
+                when
+            )
+        );
+    }
+}
@@ -0,0 +1,80 @@
+use crate::analysis::{
+    create_notebook, fetch_notebook, run_cells, AnalysisCache, AnalysisError, AnalysisState,
+    CellOutput, Notebook, Operation,
+};
+use crate::database::{DataBase, Storage};
+use axum::{
+    extract::{Json, Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Router,
+};
+use chrono::Utc;
+use serde::Deserialize;
+
+pub fn routes(database: DataBase<impl Storage + 'static>, cache: AnalysisCache) -> Router {
+    Router::new()
+        .route("/run", post(post_run))
+        .route("/notebooks", post(post_notebook))
+        .route("/notebooks/:id", get(get_notebook))
+        .route("/notebooks/:id/results", get(get_results))
+        .with_state(AnalysisState { database, cache })
+}
+
+impl IntoResponse for AnalysisError {
+    fn into_response(self) -> Response {
+        let status = match self {
+            AnalysisError::NotebookNotFound | AnalysisError::MeasurementNotFound(_) => {
+                StatusCode::NOT_FOUND
+            }
+            AnalysisError::NoSpectrum
+            | AnalysisError::InvalidParameter(_)
+            | AnalysisError::TooManyCells => StatusCode::BAD_REQUEST,
+            AnalysisError::ServiceUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+        };
+        (status, self.to_string()).into_response()
+    }
+}
+
+/// Run cells without saving them.
+async fn post_run(
+    State(state): State<AnalysisState<impl Storage>>,
+    Json(cells): Json<Vec<Operation>>,
+) -> Result<Json<Vec<CellOutput>>, AnalysisError> {
+    Ok(Json(
+        run_cells(&state.database, &state.cache, &cells).await?,
+    ))
+}
+
+#[derive(Deserialize, Debug)]
+struct NewNotebook {
+    title: String,
+    cells: Vec<Operation>,
+}
+
+async fn post_notebook(
+    State(state): State<AnalysisState<impl Storage>>,
+    Json(notebook): Json<NewNotebook>,
+) -> Result<(StatusCode, Json<Notebook>), AnalysisError> {
+    let notebook =
+        create_notebook(&state.database, &notebook.title, notebook.cells, Utc::now()).await?;
+    Ok((StatusCode::CREATED, Json(notebook)))
+}
+
+async fn get_notebook(
+    State(state): State<AnalysisState<impl Storage>>,
+    Path(id): Path<String>,
+) -> Result<Json<Notebook>, AnalysisError> {
+    Ok(Json(fetch_notebook(&state.database, &id).await?))
+}
+
+async fn get_results(
+    State(state): State<AnalysisState<impl Storage>>,
+    Path(id): Path<String>,
+) -> Result<Json<Vec<CellOutput>>, AnalysisError> {
+    let notebook = fetch_notebook(&state.database, &id).await?;
+    Ok(Json(
+        run_cells(&state.database, &state.cache, &notebook.cells).await?,
+    ))
+}
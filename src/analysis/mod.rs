@@ -0,0 +1,556 @@
+//! Notebook style analysis of archived measurements.
+//!
+//! A notebook is a chain of cells, each applying one predefined operation
+//! to the spectrum produced by the cells before it, starting from a
+//! selected measurement. Cells are run on the server and their results are
+//! cached by the chain leading up to them, so that adding a cell at the end
+//! only runs that cell. Notebooks are saved in the database and can be
+//! shared by their link.
+
+use crate::archive::column_density::fit_line;
+use crate::archive::files::load_all_spectra;
+use crate::archive::rotation_curve::lsr_velocities;
+use crate::archive::ArchivedMeasurement;
+use crate::database::{DataBase, DataBaseError, Storage};
+use crate::plot::{render_spectrum_svg, FrequencyUnit, PlotOptions};
+use crate::telescopes::{ObservedSpectra, TelescopeTarget};
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+pub mod api_routes;
+pub mod routes;
+
+/// Results kept in the cache before it is emptied.
+pub const MAX_CACHED_RESULTS: usize = 256;
+/// Longest chain of cells in a notebook.
+pub const MAX_CELLS: usize = 32;
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(tag = "operation", rename_all = "snake_case")]
+pub enum Operation {
+    /// Start from the spectrum of an archived measurement.
+    Select {
+        measurement_id: u64,
+    },
+    /// Fit a polynomial of `order` 0 or 1 to the channels outside of
+    /// `exclude_from..exclude_to` and subtract it.
+    Baseline {
+        order: u32,
+        exclude_from: f64,
+        exclude_to: f64,
+    },
+    /// Boxcar smoothing over `channels` channels.
+    Smooth {
+        channels: usize,
+    },
+    /// Gaussian fitted to the emission by its moments.
+    Fit,
+    Plot,
+}
+
+impl Operation {
+    pub fn description(&self) -> String {
+        match self {
+            Operation::Select { measurement_id } => {
+                format!("Select measurement {}", measurement_id)
+            }
+            Operation::Baseline {
+                order,
+                exclude_from,
+                exclude_to,
+            } => format!(
+                "Subtract baseline of order {} outside {} to {}",
+                order, exclude_from, exclude_to
+            ),
+            Operation::Smooth { channels } => format!("Smooth over {} channels", channels),
+            Operation::Fit => "Fit Gaussian".to_string(),
+            Operation::Plot => "Plot".to_string(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Notebook {
+    pub id: String,
+    pub title: String,
+    pub cells: Vec<Operation>,
+    pub created: DateTime<Utc>,
+    pub updated: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Spectrum {
+    pub measurement_id: u64,
+    /// LSR velocity in km/s for galactic targets, otherwise frequency in
+    /// MHz.
+    pub x: Vec<f64>,
+    pub y: Vec<f64>,
+    pub x_label: String,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct GaussianFit {
+    pub amplitude: f64,
+    pub center: f64,
+    /// Full width at half maximum.
+    pub width: f64,
+}
+
+/// The spectrum after a cell, with what the cell produced besides it.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct CellResult {
+    pub spectrum: Spectrum,
+    pub fit: Option<GaussianFit>,
+    /// SVG document.
+    pub plot: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct CellOutput {
+    pub result: Option<CellResult>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum AnalysisError {
+    NoSpectrum,
+    MeasurementNotFound(u64),
+    InvalidParameter(String),
+    TooManyCells,
+    NotebookNotFound,
+    ServiceUnavailable,
+}
+
+impl std::fmt::Display for AnalysisError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AnalysisError::NoSpectrum => write!(f, "Select a measurement first"),
+            AnalysisError::MeasurementNotFound(id) => write!(f, "Measurement {} not found", id),
+            AnalysisError::InvalidParameter(message) => write!(f, "{}", message),
+            AnalysisError::TooManyCells => {
+                write!(f, "A notebook can have at most {} cells", MAX_CELLS)
+            }
+            AnalysisError::NotebookNotFound => write!(f, "Notebook not found"),
+            AnalysisError::ServiceUnavailable => write!(f, "Failed to access the database"),
+        }
+    }
+}
+
+impl From<DataBaseError> for AnalysisError {
+    fn from(_source: DataBaseError) -> Self {
+        Self::ServiceUnavailable
+    }
+}
+
+/// State of the analysis routes.
+#[derive(Clone)]
+pub struct AnalysisState<StorageType: Storage> {
+    pub database: DataBase<StorageType>,
+    pub cache: AnalysisCache,
+}
+
+/// Results of cells, keyed by the chain of cells up to and including them.
+#[derive(Clone, Default)]
+pub struct AnalysisCache(Arc<Mutex<HashMap<String, CellResult>>>);
+
+impl AnalysisCache {
+    fn get(&self, key: &str) -> Option<CellResult> {
+        self.0.lock().unwrap().get(key).cloned()
+    }
+
+    fn insert(&self, key: String, result: CellResult) {
+        let mut results = self.0.lock().unwrap();
+        if results.len() >= MAX_CACHED_RESULTS {
+            results.clear();
+        }
+        results.insert(key, result);
+    }
+}
+
+fn select(measurement: &ArchivedMeasurement) -> Spectrum {
+    let m = &measurement.measurement;
+    let channels = m.amps.len().min(m.freqs.len());
+    let (x, x_label) = match m.target {
+        TelescopeTarget::Galactic { l, b } => (
+            lsr_velocities(&m.freqs[..channels], l, b, m.start),
+            "Velocity [km/s]",
+        ),
+        _ => (
+            m.freqs[..channels].iter().map(|f| f / 1e6).collect(),
+            "Frequency [MHz]",
+        ),
+    };
+    Spectrum {
+        measurement_id: measurement.id,
+        x,
+        y: m.amps[..channels].to_vec(),
+        x_label: x_label.to_string(),
+    }
+}
+
+fn subtract_baseline(
+    spectrum: &Spectrum,
+    order: u32,
+    exclude: (f64, f64),
+) -> Result<Spectrum, AnalysisError> {
+    let (low, high) = (exclude.0.min(exclude.1), exclude.0.max(exclude.1));
+    let points: Vec<(f64, f64)> = spectrum
+        .x
+        .iter()
+        .copied()
+        .zip(spectrum.y.iter().copied())
+        .filter(|(x, _)| *x < low || *x > high)
+        .collect();
+    let too_few = || {
+        AnalysisError::InvalidParameter("Too few channels outside the excluded range".to_string())
+    };
+    let (intercept, slope) = match order {
+        0 if points.is_empty() => return Err(too_few()),
+        0 => (
+            points.iter().map(|p| p.1).sum::<f64>() / points.len() as f64,
+            0.0,
+        ),
+        1 => fit_line(&points).ok_or_else(too_few)?,
+        _ => {
+            return Err(AnalysisError::InvalidParameter(
+                "The baseline order has to be 0 or 1".to_string(),
+            ))
+        }
+    };
+    Ok(Spectrum {
+        y: spectrum
+            .x
+            .iter()
+            .zip(&spectrum.y)
+            .map(|(x, y)| y - (intercept + slope * x))
+            .collect(),
+        ..spectrum.clone()
+    })
+}
+
+fn smooth(spectrum: &Spectrum, channels: usize) -> Result<Spectrum, AnalysisError> {
+    if channels == 0 || channels > spectrum.y.len().max(1) {
+        return Err(AnalysisError::InvalidParameter(format!(
+            "Smoothing has to be over 1 to {} channels",
+            spectrum.y.len()
+        )));
+    }
+    let half = channels / 2;
+    let y = (0..spectrum.y.len())
+        .map(|i| {
+            let window =
+                &spectrum.y[i.saturating_sub(half)..(i + channels - half).min(spectrum.y.len())];
+            window.iter().sum::<f64>() / window.len() as f64
+        })
+        .collect();
+    Ok(Spectrum {
+        y,
+        ..spectrum.clone()
+    })
+}
+
+/// Gaussian with the peak, mean and standard deviation of the positive part
+/// of the spectrum, which is a good estimate for a single line on a
+/// subtracted baseline.
+fn fit_gaussian(spectrum: &Spectrum) -> Result<GaussianFit, AnalysisError> {
+    let positive: Vec<(f64, f64)> = spectrum
+        .x
+        .iter()
+        .copied()
+        .zip(spectrum.y.iter().copied())
+        .filter(|(_, y)| *y > 0.0)
+        .collect();
+    let total: f64 = positive.iter().map(|p| p.1).sum();
+    if total <= 0.0 {
+        return Err(AnalysisError::InvalidParameter(
+            "There is no emission to fit".to_string(),
+        ));
+    }
+    let center = positive.iter().map(|(x, y)| x * y).sum::<f64>() / total;
+    let variance = positive
+        .iter()
+        .map(|(x, y)| y * (x - center).powi(2))
+        .sum::<f64>()
+        / total;
+    Ok(GaussianFit {
+        amplitude: positive.iter().map(|p| p.1).fold(0.0, f64::max),
+        center,
+        width: 2.0 * (2.0 * 2f64.ln()).sqrt() * variance.sqrt(),
+    })
+}
+
+fn plot(spectrum: &Spectrum) -> Result<String, AnalysisError> {
+    let options = PlotOptions {
+        title: Some(format!("Measurement {}", spectrum.measurement_id)),
+        x_label: Some(spectrum.x_label.clone()),
+        frequency_unit: Some(FrequencyUnit::Hz),
+        ..Default::default()
+    };
+    let spectra = ObservedSpectra {
+        frequencies: spectrum.x.clone(),
+        spectra: spectrum.y.clone(),
+        observation_time: std::time::Duration::ZERO,
+        cycles: 0,
+    };
+    render_spectrum_svg(&spectra, &options)
+        .map_err(|error| AnalysisError::InvalidParameter(error.to_string()))
+}
+
+fn run_cell(
+    operation: &Operation,
+    previous: Option<&CellResult>,
+    measurements: &[ArchivedMeasurement],
+) -> Result<CellResult, AnalysisError> {
+    let spectrum = previous.map(|p| &p.spectrum);
+    let result = |spectrum| CellResult {
+        spectrum,
+        fit: None,
+        plot: None,
+    };
+    match operation {
+        Operation::Select { measurement_id } => measurements
+            .iter()
+            .find(|m| m.id == *measurement_id)
+            .map(|m| result(select(m)))
+            .ok_or(AnalysisError::MeasurementNotFound(*measurement_id)),
+        Operation::Baseline {
+            order,
+            exclude_from,
+            exclude_to,
+        } => Ok(result(subtract_baseline(
+            spectrum.ok_or(AnalysisError::NoSpectrum)?,
+            *order,
+            (*exclude_from, *exclude_to),
+        )?)),
+        Operation::Smooth { channels } => Ok(result(smooth(
+            spectrum.ok_or(AnalysisError::NoSpectrum)?,
+            *channels,
+        )?)),
+        Operation::Fit => {
+            let spectrum = spectrum.ok_or(AnalysisError::NoSpectrum)?;
+            Ok(CellResult {
+                fit: Some(fit_gaussian(spectrum)?),
+                ..result(spectrum.clone())
+            })
+        }
+        Operation::Plot => {
+            let spectrum = spectrum.ok_or(AnalysisError::NoSpectrum)?;
+            Ok(CellResult {
+                plot: Some(plot(spectrum)?),
+                ..result(spectrum.clone())
+            })
+        }
+    }
+}
+
+/// Run a chain of cells, reusing cached results. Cells after one that
+/// failed are not run.
+pub async fn run_cells(
+    db: &DataBase<impl Storage>,
+    cache: &AnalysisCache,
+    cells: &[Operation],
+) -> Result<Vec<CellOutput>, AnalysisError> {
+    if cells.len() > MAX_CELLS {
+        return Err(AnalysisError::TooManyCells);
+    }
+    let mut measurements = None;
+    let mut outputs = Vec::new();
+    let mut previous: Option<CellResult> = None;
+    for (index, operation) in cells.iter().enumerate() {
+        let key =
+            serde_json::to_string(&cells[..=index]).expect("Operations can always be serialized");
+        let result = match cache.get(&key) {
+            Some(result) => Ok(result),
+            None => {
+                if measurements.is_none() {
+                    let selected: Vec<u64> = cells
+                        .iter()
+                        .filter_map(|operation| match operation {
+                            Operation::Select { measurement_id } => Some(*measurement_id),
+                            _ => None,
+                        })
+                        .collect();
+                    let archived = db
+                        .get_data()
+                        .await?
+                        .measurements
+                        .into_iter()
+                        .filter(|m| selected.contains(&m.id))
+                        .collect();
+                    measurements = Some(load_all_spectra(db, archived).await?);
+                }
+                let measurements = measurements.as_deref().unwrap_or_default();
+                run_cell(operation, previous.as_ref(), measurements)
+            }
+        };
+        match result {
+            Ok(result) => {
+                cache.insert(key, result.clone());
+                outputs.push(CellOutput {
+                    result: Some(result.clone()),
+                    error: None,
+                });
+                previous = Some(result);
+            }
+            Err(error) => {
+                outputs.push(CellOutput {
+                    result: None,
+                    error: Some(error.to_string()),
+                });
+                break;
+            }
+        }
+    }
+    Ok(outputs)
+}
+
+pub async fn create_notebook(
+    db: &DataBase<impl Storage>,
+    title: &str,
+    cells: Vec<Operation>,
+    now: DateTime<Utc>,
+) -> Result<Notebook, AnalysisError> {
+    if cells.len() > MAX_CELLS {
+        return Err(AnalysisError::TooManyCells);
+    }
+    let notebook = Notebook {
+        id: format!("{:016x}", rand::thread_rng().gen::<u64>()),
+        title: title.to_string(),
+        cells,
+        created: now,
+        updated: now,
+    };
+    db.update_data(|mut data_model| {
+        data_model.notebooks.push(notebook.clone());
+        data_model
+    })
+    .await?;
+    Ok(notebook)
+}
+
+pub async fn fetch_notebook(
+    db: &DataBase<impl Storage>,
+    id: &str,
+) -> Result<Notebook, AnalysisError> {
+    db.get_data()
+        .await?
+        .notebooks
+        .into_iter()
+        .find(|n| n.id == id)
+        .ok_or(AnalysisError::NotebookNotFound)
+}
+
+/// Change the cells of a notebook with `f`.
+pub async fn update_cells(
+    db: &DataBase<impl Storage>,
+    id: &str,
+    f: impl FnOnce(&mut Vec<Operation>),
+    now: DateTime<Utc>,
+) -> Result<Notebook, AnalysisError> {
+    let mut result = Err(AnalysisError::NotebookNotFound);
+    db.update_data(|mut data_model| {
+        if let Some(notebook) = data_model.notebooks.iter_mut().find(|n| n.id == id) {
+            let mut cells = notebook.cells.clone();
+            f(&mut cells);
+            if cells.len() > MAX_CELLS {
+                result = Err(AnalysisError::TooManyCells);
+            } else {
+                notebook.cells = cells;
+                notebook.updated = now;
+                result = Ok(notebook.clone());
+            }
+        }
+        data_model
+    })
+    .await?;
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::angles::{Degrees, Radians};
+    use crate::archive::archive_measurement;
+    use crate::archive::test_utils::galactic_measurement;
+    use crate::constants::HI_REST_FREQUENCY;
+    use crate::database::create_in_memory_database;
+
+    #[tokio::test]
+    async fn test_run_cells() {
+        let db = create_in_memory_database();
+        let mut measurement = galactic_measurement(Degrees(120.0), Utc::now());
+        measurement.freqs = (0..1000)
+            .map(|channel| HI_REST_FREQUENCY - 2.5e6 + channel as f64 * 5e3)
+            .collect();
+        let velocities = lsr_velocities(
+            &measurement.freqs,
+            Degrees(120.0).to_radians(),
+            Radians(0.0),
+            measurement.start,
+        );
+        // A sloping baseline with a line at -20 km/s, 10 km/s wide.
+        measurement.amps = velocities
+            .iter()
+            .map(|v| 2.0 + 0.001 * v + (-0.5 * ((v + 20.0) / 10.0).powi(2)).exp())
+            .collect();
+        let id = archive_measurement(&db, "fake", measurement).await.unwrap();
+
+        let cache = AnalysisCache::default();
+        let cells = vec![
+            Operation::Select { measurement_id: id },
+            Operation::Baseline {
+                order: 1,
+                exclude_from: -100.0,
+                exclude_to: 100.0,
+            },
+            Operation::Smooth { channels: 3 },
+            Operation::Fit,
+        ];
+        let outputs = run_cells(&db, &cache, &cells).await.unwrap();
+        assert_eq!(outputs.len(), 4);
+        let fit = outputs[3].result.as_ref().unwrap().fit.as_ref().unwrap();
+        assert!((fit.center + 20.0).abs() < 0.5, "{:?}", fit);
+        assert!((fit.amplitude - 1.0).abs() < 0.05, "{:?}", fit);
+        let expected_width = 2.0 * (2.0 * 2f64.ln()).sqrt() * 10.0;
+        assert!((fit.width - expected_width).abs() < 1.0, "{:?}", fit);
+
+        // Cached results are used, even after the measurement is gone.
+        db.update_data(|mut data_model| {
+            data_model.measurements.clear();
+            data_model
+        })
+        .await
+        .unwrap();
+        let mut extended = cells.clone();
+        extended.push(Operation::Plot);
+        let outputs = run_cells(&db, &cache, &extended).await.unwrap();
+        assert!(outputs[4].result.as_ref().unwrap().plot.is_some());
+
+        // A cell without a spectrum to work on stops the chain.
+        let outputs = run_cells(&db, &cache, &[Operation::Fit, Operation::Plot])
+            .await
+            .unwrap();
+        assert_eq!(outputs.len(), 1);
+        assert_eq!(
+            outputs[0].error.as_deref(),
+            Some("Select a measurement first")
+        );
+
+        let notebook = create_notebook(&db, "HI at l=120", cells, Utc::now())
+            .await
+            .unwrap();
+        let notebook = update_cells(
+            &db,
+            &notebook.id,
+            |cells| cells.push(Operation::Plot),
+            Utc::now(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(fetch_notebook(&db, &notebook.id).await.unwrap(), notebook);
+        assert_eq!(notebook.cells.len(), 5);
+    }
+}
@@ -0,0 +1,248 @@
+use crate::analysis::{
+    create_notebook, fetch_notebook, run_cells, update_cells, AnalysisCache, AnalysisError,
+    AnalysisState, CellOutput, Notebook, Operation, MAX_CELLS,
+};
+use crate::database::{DataBase, Storage};
+use crate::format::{filters, Locale};
+use crate::template::HtmlTemplate;
+use askama::Template;
+use axum::{
+    extract::{Form, Path, State},
+    http::HeaderMap,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Router,
+};
+use chrono::Utc;
+use serde::Deserialize;
+
+pub fn routes(database: DataBase<impl Storage + 'static>, cache: AnalysisCache) -> Router {
+    Router::new()
+        .route("/", get(get_analysis).post(post_notebook))
+        .route("/:id", get(get_notebook))
+        .route("/:id/cells", post(post_cell))
+        .route("/:id/cells/:index/delete", post(delete_cell))
+        .with_state(AnalysisState { database, cache })
+}
+
+#[derive(Template)]
+#[template(path = "analysis.html")]
+struct AnalysisTemplate {
+    notebooks: Vec<Notebook>,
+}
+
+/// Notebooks shown on the analysis page, most recently changed first.
+const LISTED_NOTEBOOKS: usize = 20;
+
+async fn get_analysis<StorageType>(
+    State(state): State<AnalysisState<StorageType>>,
+) -> impl IntoResponse
+where
+    StorageType: Storage,
+{
+    let mut notebooks = state
+        .database
+        .get_data()
+        .await
+        .expect("As long as no one is manually editing the database, this should never fail.")
+        .notebooks;
+    notebooks.sort_by_key(|n| std::cmp::Reverse(n.updated));
+    notebooks.truncate(LISTED_NOTEBOOKS);
+    HtmlTemplate(AnalysisTemplate { notebooks })
+}
+
+struct CellView {
+    index: usize,
+    description: String,
+    output: Option<CellOutput>,
+}
+
+#[derive(Template)]
+#[template(path = "analysis_notebook.html")]
+struct NotebookTemplate {
+    notebook: Notebook,
+    cells: Vec<CellView>,
+    error: Option<String>,
+    max_cells: usize,
+    locale: Locale,
+}
+
+#[derive(Template)]
+#[template(path = "analysis_page.html")]
+struct NotebookPageTemplate {
+    notebook: NotebookTemplate,
+}
+
+async fn render_notebook(
+    state: &AnalysisState<impl Storage>,
+    notebook: Notebook,
+    error: Option<String>,
+    locale: Locale,
+) -> Result<NotebookTemplate, AnalysisError> {
+    let mut outputs = run_cells(&state.database, &state.cache, &notebook.cells)
+        .await?
+        .into_iter();
+    let cells = notebook
+        .cells
+        .iter()
+        .enumerate()
+        .map(|(index, operation)| CellView {
+            index,
+            description: operation.description(),
+            output: outputs.next(),
+        })
+        .collect();
+    Ok(NotebookTemplate {
+        notebook,
+        cells,
+        error,
+        max_cells: MAX_CELLS,
+        locale,
+    })
+}
+
+#[derive(Deserialize, Debug)]
+struct NotebookForm {
+    title: String,
+}
+
+async fn post_notebook<StorageType>(
+    State(state): State<AnalysisState<StorageType>>,
+    locale: Locale,
+    Form(form): Form<NotebookForm>,
+) -> Result<Response, AnalysisError>
+where
+    StorageType: Storage,
+{
+    let notebook = create_notebook(&state.database, &form.title, Vec::new(), Utc::now()).await?;
+    Ok(HtmlTemplate(render_notebook(&state, notebook, None, locale).await?).into_response())
+}
+
+/// A shared link is opened directly, so anything but a request from the
+/// site itself gets a whole page.
+async fn get_notebook<StorageType>(
+    State(state): State<AnalysisState<StorageType>>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    locale: Locale,
+) -> Result<Response, AnalysisError>
+where
+    StorageType: Storage,
+{
+    let notebook = render_notebook(
+        &state,
+        fetch_notebook(&state.database, &id).await?,
+        None,
+        locale,
+    )
+    .await?;
+    if headers.contains_key("HX-Request") {
+        Ok(HtmlTemplate(notebook).into_response())
+    } else {
+        Ok(HtmlTemplate(NotebookPageTemplate { notebook }).into_response())
+    }
+}
+
+/// Fields of all operations, of which those of the chosen one are used.
+/// Empty fields in a form are sent as empty strings, so they are parsed
+/// here rather than by serde.
+#[derive(Deserialize, Debug)]
+struct CellForm {
+    operation: String,
+    #[serde(default)]
+    measurement_id: String,
+    #[serde(default)]
+    order: String,
+    #[serde(default)]
+    exclude_from: String,
+    #[serde(default)]
+    exclude_to: String,
+    #[serde(default)]
+    channels: String,
+}
+
+fn parse<T: std::str::FromStr>(value: &str, name: &str) -> Result<T, AnalysisError> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| AnalysisError::InvalidParameter(format!("Invalid {}", name)))
+}
+
+impl CellForm {
+    fn operation(&self) -> Result<Operation, AnalysisError> {
+        Ok(match self.operation.as_str() {
+            "select" => Operation::Select {
+                measurement_id: parse(&self.measurement_id, "measurement id")?,
+            },
+            "baseline" => Operation::Baseline {
+                order: parse(&self.order, "baseline order")?,
+                exclude_from: parse(&self.exclude_from, "start of the excluded range")?,
+                exclude_to: parse(&self.exclude_to, "end of the excluded range")?,
+            },
+            "smooth" => Operation::Smooth {
+                channels: parse(&self.channels, "number of channels")?,
+            },
+            "fit" => Operation::Fit,
+            "plot" => Operation::Plot,
+            _ => {
+                return Err(AnalysisError::InvalidParameter(
+                    "Unknown operation".to_string(),
+                ))
+            }
+        })
+    }
+}
+
+async fn post_cell<StorageType>(
+    State(state): State<AnalysisState<StorageType>>,
+    Path(id): Path<String>,
+    locale: Locale,
+    Form(form): Form<CellForm>,
+) -> Result<Response, AnalysisError>
+where
+    StorageType: Storage,
+{
+    let result = match form.operation() {
+        Ok(operation) => {
+            update_cells(
+                &state.database,
+                &id,
+                |cells| cells.push(operation),
+                Utc::now(),
+            )
+            .await
+        }
+        Err(error) => Err(error),
+    };
+    let (notebook, error) = match result {
+        Ok(notebook) => (notebook, None),
+        Err(AnalysisError::NotebookNotFound) => return Err(AnalysisError::NotebookNotFound),
+        Err(error) => (
+            fetch_notebook(&state.database, &id).await?,
+            Some(error.to_string()),
+        ),
+    };
+    Ok(HtmlTemplate(render_notebook(&state, notebook, error, locale).await?).into_response())
+}
+
+async fn delete_cell<StorageType>(
+    State(state): State<AnalysisState<StorageType>>,
+    Path((id, index)): Path<(String, usize)>,
+    locale: Locale,
+) -> Result<Response, AnalysisError>
+where
+    StorageType: Storage,
+{
+    let notebook = update_cells(
+        &state.database,
+        &id,
+        |cells| {
+            if index < cells.len() {
+                cells.remove(index);
+            }
+        },
+        Utc::now(),
+    )
+    .await?;
+    Ok(HtmlTemplate(render_notebook(&state, notebook, None, locale).await?).into_response())
+}
@@ -0,0 +1,395 @@
+use serde::{Deserialize, Serialize};
+use std::f64::consts::PI;
+use std::ops::{Add, AddAssign, Div, Mul, Neg, Sub, SubAssign};
+use thiserror::Error;
+
+/// An angle in radians.
+///
+/// All angles inside the backend, and in the database, are kept in radians.
+/// Degrees only show up at the edges (rot2prog controllers, query parameters
+/// and plots) where they are converted with [`Degrees`]. Keeping the two in
+/// separate types makes it impossible to mix them up by accident.
+#[derive(Serialize, Deserialize, PartialEq, PartialOrd, Debug, Copy, Clone, Default)]
+#[serde(transparent)]
+pub struct Radians(pub f64);
+
+/// An angle in degrees, only used at the edges of the backend.
+#[derive(Serialize, Deserialize, PartialEq, PartialOrd, Debug, Copy, Clone, Default)]
+#[serde(transparent)]
+pub struct Degrees(pub f64);
+
+pub const FULL_CIRCLE: Radians = Radians(2.0 * PI);
+pub const RIGHT_ANGLE: Radians = Radians(PI / 2.0);
+
+impl Radians {
+    pub fn sin(self) -> f64 {
+        self.0.sin()
+    }
+
+    pub fn cos(self) -> f64 {
+        self.0.cos()
+    }
+
+    pub fn tan(self) -> f64 {
+        self.0.tan()
+    }
+
+    pub fn asin(x: f64) -> Radians {
+        Radians(x.asin())
+    }
+
+    pub fn atan2(y: f64, x: f64) -> Radians {
+        Radians(y.atan2(x))
+    }
+
+    pub fn abs(self) -> Radians {
+        Radians(self.0.abs())
+    }
+
+    pub fn clamp(self, min: Radians, max: Radians) -> Radians {
+        Radians(self.0.clamp(min.0, max.0))
+    }
+
+    /// The same angle in the range [0, 2π).
+    pub fn normalized(self) -> Radians {
+        Radians(self.0.rem_euclid(FULL_CIRCLE.0))
+    }
+
+    pub fn to_degrees(self) -> Degrees {
+        Degrees(self.0.to_degrees())
+    }
+}
+
+impl Degrees {
+    pub fn to_radians(self) -> Radians {
+        Radians(self.0.to_radians())
+    }
+}
+
+impl From<Degrees> for Radians {
+    fn from(degrees: Degrees) -> Self {
+        degrees.to_radians()
+    }
+}
+
+impl From<Radians> for Degrees {
+    fn from(radians: Radians) -> Self {
+        radians.to_degrees()
+    }
+}
+
+impl std::fmt::Display for Degrees {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}°", self.0)
+    }
+}
+
+#[derive(Debug, PartialEq, Error)]
+pub enum AngleParseError {
+    #[error("no angle given")]
+    Empty,
+    #[error("{0:?} is not an angle")]
+    InvalidFormat(String),
+    #[error("{0:?} is not a number")]
+    InvalidNumber(String),
+    #[error("minutes and seconds must be less than 60")]
+    ComponentOutOfRange,
+    #[error("{0} is out of range")]
+    OutOfRange(Degrees),
+}
+
+/// Characters marking the unit of each component of a sexagesimal angle.
+type SexagesimalUnits = [&'static [char]; 3];
+
+const HOUR_UNITS: SexagesimalUnits = [&['h'], &['m'], &['s']];
+const DEGREE_UNITS: SexagesimalUnits = [&['°', 'd'], &['\'', '′', 'm'], &['"', '″', 's']];
+
+/// An angle typed as one to three components, e.g. `12:30:15.5`,
+/// `12 30 15,5` or `12h30m15.5s`.
+struct Sexagesimal {
+    /// Value in the unit of the first component.
+    value: f64,
+    components: usize,
+    unit_given: bool,
+}
+
+/// Parse an unsigned decimal number, accepting a comma as decimal separator.
+fn parse_number(text: &str) -> Result<f64, AngleParseError> {
+    let separators = text.chars().filter(|c| *c == '.' || *c == ',').count();
+    let digits = text.chars().filter(char::is_ascii_digit).count();
+    if separators > 1 || digits == 0 {
+        return Err(AngleParseError::InvalidNumber(text.to_string()));
+    }
+    text.replace(',', ".")
+        .parse()
+        .map_err(|_| AngleParseError::InvalidNumber(text.to_string()))
+}
+
+fn parse_sexagesimal(input: &str, units: SexagesimalUnits) -> Result<Sexagesimal, AngleParseError> {
+    let invalid = || AngleParseError::InvalidFormat(input.to_string());
+    let text = input.trim();
+    let (negative, text) = match text.chars().next() {
+        None => return Err(AngleParseError::Empty),
+        Some(sign @ ('-' | '−' | '+')) => (sign != '+', text[sign.len_utf8()..].trim_start()),
+        Some(_) => (false, text),
+    };
+
+    // Split into the text of each component and the position given by its
+    // unit, if any. Components are separated by colons, whitespace or units.
+    let mut components: Vec<(String, Option<usize>)> = vec![];
+    let mut number = String::new();
+    for c in text.chars() {
+        if c.is_ascii_digit() || c == '.' || c == ',' {
+            number.push(c);
+            continue;
+        }
+        let unit = units.iter().position(|u| u.contains(&c));
+        if unit.is_none() && c != ':' && !c.is_whitespace() {
+            return Err(invalid());
+        }
+        if number.is_empty() {
+            if c.is_whitespace() {
+                continue;
+            }
+            return Err(invalid());
+        }
+        components.push((std::mem::take(&mut number), unit));
+    }
+    if !number.is_empty() {
+        components.push((number, None));
+    }
+    if components.is_empty() || components.len() > 3 {
+        return Err(invalid());
+    }
+
+    let mut value = 0.0;
+    let mut next_position = 0;
+    for (index, (text, unit)) in components.iter().enumerate() {
+        // Without a unit, a component is in the unit following the previous one.
+        let position = unit.unwrap_or(next_position);
+        if position < next_position || position > 2 {
+            return Err(invalid());
+        }
+        next_position = position + 1;
+        let last = index + 1 == components.len();
+        if !last && (text.contains('.') || text.contains(',')) {
+            return Err(invalid());
+        }
+        let component = parse_number(text)?;
+        if position > 0 && component >= 60.0 {
+            return Err(AngleParseError::ComponentOutOfRange);
+        }
+        value += component / 60f64.powi(position as i32);
+    }
+    Ok(Sexagesimal {
+        value: if negative { -value } else { value },
+        components: components.len(),
+        unit_given: components.iter().any(|(_, unit)| unit.is_some()),
+    })
+}
+
+/// Parse an angle in degrees, either decimal or as degrees, arcminutes and
+/// arcseconds, e.g. `-12.5`, `-12,5`, `-12:30:00` or `-12°30'00"`.
+pub fn parse_degrees(input: &str) -> Result<Degrees, AngleParseError> {
+    parse_sexagesimal(input, DEGREE_UNITS).map(|angle| Degrees(angle.value))
+}
+
+/// Parse a declination, see [`parse_degrees`].
+pub fn parse_declination(input: &str) -> Result<Degrees, AngleParseError> {
+    let dec = parse_degrees(input)?;
+    if dec.0.abs() > 90.0 {
+        return Err(AngleParseError::OutOfRange(dec));
+    }
+    Ok(dec)
+}
+
+/// Parse a right ascension, either in hours, minutes and seconds, e.g.
+/// `12:30:15.5` or `12h30m15,5s`, or in decimal degrees, e.g. `187.56` or
+/// `187,56°`. A single number without unit is taken to be in degrees.
+pub fn parse_right_ascension(input: &str) -> Result<Degrees, AngleParseError> {
+    let ra = if input.contains(DEGREE_UNITS[0]) {
+        parse_degrees(input)?
+    } else {
+        let angle = parse_sexagesimal(input, HOUR_UNITS)?;
+        if angle.components == 1 && !angle.unit_given {
+            Degrees(angle.value)
+        } else {
+            Degrees(15.0 * angle.value)
+        }
+    };
+    if !(0.0..360.0).contains(&ra.0) {
+        return Err(AngleParseError::OutOfRange(ra));
+    }
+    Ok(ra)
+}
+
+macro_rules! angle_arithmetic {
+    ($angle:ident) => {
+        impl Add for $angle {
+            type Output = $angle;
+            fn add(self, other: $angle) -> $angle {
+                $angle(self.0 + other.0)
+            }
+        }
+
+        impl Sub for $angle {
+            type Output = $angle;
+            fn sub(self, other: $angle) -> $angle {
+                $angle(self.0 - other.0)
+            }
+        }
+
+        impl AddAssign for $angle {
+            fn add_assign(&mut self, other: $angle) {
+                self.0 += other.0;
+            }
+        }
+
+        impl SubAssign for $angle {
+            fn sub_assign(&mut self, other: $angle) {
+                self.0 -= other.0;
+            }
+        }
+
+        impl Neg for $angle {
+            type Output = $angle;
+            fn neg(self) -> $angle {
+                $angle(-self.0)
+            }
+        }
+
+        impl Mul<f64> for $angle {
+            type Output = $angle;
+            fn mul(self, factor: f64) -> $angle {
+                $angle(self.0 * factor)
+            }
+        }
+
+        impl Div<f64> for $angle {
+            type Output = $angle;
+            fn div(self, divisor: f64) -> $angle {
+                $angle(self.0 / divisor)
+            }
+        }
+
+        impl Div for $angle {
+            type Output = f64;
+            fn div(self, other: $angle) -> f64 {
+                self.0 / other.0
+            }
+        }
+    };
+}
+
+angle_arithmetic!(Radians);
+angle_arithmetic!(Degrees);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_conversions() {
+        assert_eq!(Radians::from(Degrees(180.0)), Radians(PI));
+        assert_eq!(Degrees::from(RIGHT_ANGLE), Degrees(90.0));
+        assert!((Radians(-RIGHT_ANGLE.0).normalized() - Radians(1.5 * PI)).abs() < Radians(1e-12));
+        assert_eq!(
+            serde_json::to_string(&Radians(0.5)).unwrap(),
+            serde_json::to_string(&0.5).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_parse_degrees() {
+        let close = |input: &str, expected: f64| {
+            let parsed = parse_degrees(input).unwrap();
+            assert!((parsed.0 - expected).abs() < 1e-9, "{}: {}", input, parsed);
+        };
+        close("12", 12.0);
+        close("12.5", 12.5);
+        close("12,5", 12.5);
+        close(".5", 0.5);
+        close(" +12.5 ", 12.5);
+        close("-12:30", -12.5);
+        close("-12 30 36", -12.51);
+        close("− 12:30:36,0", -12.51);
+        close("-0:30", -0.5);
+        close("12°30'36\"", 12.51);
+        close("12d 30m 36s", 12.51);
+        close("12°30′36.0″", 12.51);
+        close("12°30", 12.5);
+        close("12°36\"", 12.01);
+        close("30'", 0.5);
+
+        use AngleParseError::*;
+        assert_eq!(parse_degrees(""), Err(Empty));
+        assert_eq!(parse_degrees("   "), Err(Empty));
+        for input in [
+            "-",
+            "abc",
+            "12x",
+            "12:30:00:00",
+            "12::30",
+            ":30",
+            "12.5:30",
+            "12'30°",
+            "12°30'15'",
+            "1e3",
+            "12h",
+        ] {
+            assert_eq!(
+                parse_degrees(input),
+                Err(InvalidFormat(input.to_string())),
+                "{}",
+                input
+            );
+        }
+        assert_eq!(
+            parse_degrees("1.2.3"),
+            Err(InvalidNumber("1.2.3".to_string()))
+        );
+        assert_eq!(
+            parse_degrees("1,2.3"),
+            Err(InvalidNumber("1,2.3".to_string()))
+        );
+        assert_eq!(parse_degrees("12:60"), Err(ComponentOutOfRange));
+        assert_eq!(parse_degrees("12:30:60"), Err(ComponentOutOfRange));
+    }
+
+    #[test]
+    fn test_parse_equatorial() {
+        let close = |parsed: Degrees, expected: f64| {
+            assert!((parsed.0 - expected).abs() < 1e-9, "{}", parsed);
+        };
+        close(parse_right_ascension("187.5").unwrap(), 187.5);
+        close(parse_right_ascension("187,5").unwrap(), 187.5);
+        close(parse_right_ascension("187,5°").unwrap(), 187.5);
+        close(parse_right_ascension("12:30").unwrap(), 187.5);
+        close(parse_right_ascension("12 30 00").unwrap(), 187.5);
+        close(parse_right_ascension("12h30m").unwrap(), 187.5);
+        close(parse_right_ascension("12h 30m 0,0s").unwrap(), 187.5);
+        close(parse_right_ascension("12.5h").unwrap(), 187.5);
+        close(parse_right_ascension("0").unwrap(), 0.0);
+        assert_eq!(
+            parse_right_ascension("24:00"),
+            Err(AngleParseError::OutOfRange(Degrees(360.0)))
+        );
+        assert_eq!(
+            parse_right_ascension("-1"),
+            Err(AngleParseError::OutOfRange(Degrees(-1.0)))
+        );
+        close(parse_right_ascension("12°30'").unwrap(), 12.5);
+        assert_eq!(
+            parse_right_ascension("12h30'"),
+            Err(AngleParseError::InvalidFormat("12h30'".to_string()))
+        );
+
+        close(parse_declination("-45").unwrap(), -45.0);
+        close(parse_declination("+45:30").unwrap(), 45.5);
+        close(parse_declination("-90").unwrap(), -90.0);
+        assert_eq!(
+            parse_declination("90:00:01"),
+            Err(AngleParseError::OutOfRange(Degrees(90.0 + 1.0 / 3600.0)))
+        );
+    }
+}
@@ -0,0 +1,719 @@
+use crate::angles::{Degrees, Radians};
+use crate::archive::duplicates::{stack_measurements, StackError, StackRequest};
+use crate::archive::export::{ExportQuery, ZipExport, MAX_EXPORTED_MEASUREMENTS, ZIP_CONTENT_TYPE};
+use crate::archive::files::{
+    create_file_store, load_all_spectra, load_spectra, read_measurement_file, FileChecksum,
+    FileIntegrity, FileStoreError,
+};
+use crate::archive::fits::{measurement_fits, FITS_CONTENT_TYPE};
+use crate::archive::monitoring::{monitor_target, MonitoringPoint};
+use crate::archive::provenance::{monitoring_provenance, rotation_curve_provenance, Provenance};
+use crate::archive::reprocessing::{
+    reprocess_measurements, ReprocessingError, ReprocessingReport, ReprocessingRequest,
+};
+use crate::archive::rotation_curve::{rotation_curve, RotationCurvePoint};
+use crate::archive::thumbnails::{fetch_thumbnail, ThumbnailError};
+use crate::archive::{ArchivedMeasurement, ArchivedMeasurementSummary};
+use crate::constants::{fetch_spectral_lines, lsr_velocities};
+use crate::database::{DataBase, Storage};
+use crate::plot::{
+    render_continuum_svg, render_coverage_svg, render_rotation_curve_svg, render_spectrum,
+    render_time_series_svg, PlotOptions,
+};
+use crate::telescopes::{ContinuumSample, CoordinateSystem, TelescopeTarget};
+use crate::trash::{trash_measurement, TrashError, TrashedItem};
+use axum::{
+    body::StreamBody,
+    extract::{Json, Path, Query, RawQuery, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Router,
+};
+use chrono::Utc;
+use futures_util::stream;
+use serde::Deserialize;
+use serde_json::json;
+use std::convert::Infallible;
+
+pub fn routes(database: DataBase<impl Storage + 'static>) -> Router {
+    Router::new()
+        .route("/", get(get_measurements))
+        .route("/download", get(download_measurements))
+        .route("/export", get(get_export))
+        .route("/coverage", get(get_coverage))
+        .route("/monitoring", get(get_monitoring))
+        .route("/monitoring/plot", get(get_monitoring_plot))
+        .route("/monitoring/provenance", get(get_monitoring_provenance))
+        .route("/rotation_curve", get(get_rotation_curve))
+        .route("/rotation_curve/plot", get(get_rotation_curve_plot))
+        .route(
+            "/rotation_curve/provenance",
+            get(get_rotation_curve_provenance),
+        )
+        .route("/integrity", get(get_integrity))
+        .route("/reprocess", post(post_reprocess))
+        .route("/stack", post(post_stack))
+        .route("/:id", get(get_measurement).delete(delete_measurement))
+        .route("/:id/plot", get(get_measurement_plot))
+        .route("/:id/velocity", get(get_measurement_velocity))
+        .route("/:id/provenance", get(get_measurement_provenance))
+        .route("/:id/continuum", get(get_measurement_continuum))
+        .route("/:id/continuum/plot", get(get_measurement_continuum_plot))
+        .route("/:id/file", get(get_measurement_file))
+        .route("/:id/fits", get(get_measurement_fits))
+        .route("/:id/thumbnail", get(get_measurement_thumbnail))
+        .with_state(database)
+}
+
+#[derive(Debug)]
+enum MeasurementError {
+    NotFound,
+    /// The data file with the spectra could not be read.
+    Unreadable(FileStoreError),
+}
+
+impl IntoResponse for MeasurementError {
+    fn into_response(self) -> Response {
+        match self {
+            MeasurementError::NotFound => {
+                (StatusCode::NOT_FOUND, "Measurement not found".to_string()).into_response()
+            }
+            MeasurementError::Unreadable(error) => {
+                log::error!("Failed to read spectra: {}", error);
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "The data file of the measurement could not be read".to_string(),
+                )
+                    .into_response()
+            }
+        }
+    }
+}
+
+impl IntoResponse for StackError {
+    fn into_response(self) -> Response {
+        let status = match self {
+            StackError::TooFew | StackError::Mismatch(_) => StatusCode::BAD_REQUEST,
+            StackError::NotFound(_) => StatusCode::NOT_FOUND,
+            StackError::Unreadable(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            StackError::DataBase => StatusCode::SERVICE_UNAVAILABLE,
+        };
+        (status, self.to_string()).into_response()
+    }
+}
+
+impl IntoResponse for ThumbnailError {
+    fn into_response(self) -> Response {
+        match self {
+            ThumbnailError::Plot(error) => error.into_response(),
+            ThumbnailError::FileStore(error) => MeasurementError::Unreadable(error).into_response(),
+        }
+    }
+}
+
+impl IntoResponse for ReprocessingError {
+    fn into_response(self) -> Response {
+        match self {
+            ReprocessingError::WrongPipelineVersion(running) => (
+                StatusCode::CONFLICT,
+                format!("The running pipeline version is {}", running),
+            )
+                .into_response(),
+            ReprocessingError::ServiceUnavailable => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Failed to update the archive".to_string(),
+            )
+                .into_response(),
+        }
+    }
+}
+
+/// The archived measurements, without the spectra that are kept in data
+/// files.
+async fn fetch_measurements(db: &DataBase<impl Storage>) -> Vec<ArchivedMeasurement> {
+    db.get_data()
+        .await
+        .expect("As long as no one is manually editing the database, this should never fail.")
+        .measurements
+}
+
+async fn fetch_measurements_with_spectra(db: &DataBase<impl Storage>) -> Vec<ArchivedMeasurement> {
+    load_all_spectra(db, fetch_measurements(db).await)
+        .await
+        .expect("As long as no one is manually editing the database, this should never fail.")
+}
+
+/// The measurement `id`, without the spectra that are kept in its data
+/// file.
+async fn find_measurement(
+    db: &DataBase<impl Storage>,
+    id: u64,
+) -> Result<ArchivedMeasurement, MeasurementError> {
+    fetch_measurements(db)
+        .await
+        .into_iter()
+        .find(|m| m.id == id)
+        .ok_or(MeasurementError::NotFound)
+}
+
+async fn fetch_measurement(
+    db: &DataBase<impl Storage>,
+    id: u64,
+) -> Result<ArchivedMeasurement, MeasurementError> {
+    load_spectra(db, find_measurement(db, id).await?)
+        .await
+        .map_err(MeasurementError::Unreadable)
+}
+
+async fn get_measurements(
+    State(db): State<DataBase<impl Storage>>,
+) -> Json<Vec<ArchivedMeasurementSummary>> {
+    Json(
+        fetch_measurements(&db)
+            .await
+            .iter()
+            .map(ArchivedMeasurement::summary)
+            .collect(),
+    )
+}
+
+async fn download_measurements(State(db): State<DataBase<impl Storage>>) -> impl IntoResponse {
+    (
+        [(
+            header::CONTENT_DISPOSITION,
+            "attachment; filename=\"salsa-archive.json\"",
+        )],
+        Json(fetch_measurements_with_spectra(&db).await),
+    )
+}
+
+/// The measurements selected by the query as a ZIP file, streamed one
+/// measurement at a time.
+async fn get_export(
+    State(db): State<DataBase<impl Storage>>,
+    Query(query): Query<ExportQuery>,
+) -> Response {
+    let data_model = db
+        .get_data()
+        .await
+        .expect("As long as no one is manually editing the database, this should never fail.");
+    let measurements: Vec<ArchivedMeasurement> = data_model
+        .measurements
+        .into_iter()
+        .filter(|m| query.includes(m))
+        .collect();
+    if measurements.len() > MAX_EXPORTED_MEASUREMENTS {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!(
+                "{} measurements match, at most {} can be exported at once",
+                measurements.len(),
+                MAX_EXPORTED_MEASUREMENTS
+            ),
+        )
+            .into_response();
+    }
+    let locations = data_model
+        .telescopes
+        .into_iter()
+        .map(|t| (t.name, t.location))
+        .collect();
+    let measurements = load_all_spectra(&db, measurements)
+        .await
+        .expect("As long as no one is manually editing the database, this should never fail.");
+    let parts = ZipExport::new(measurements, query.format.unwrap_or_default(), locations);
+    (
+        [
+            (header::CONTENT_TYPE, ZIP_CONTENT_TYPE),
+            (
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"salsa-export.zip\"",
+            ),
+        ],
+        StreamBody::new(stream::iter(parts.map(Ok::<_, Infallible>))),
+    )
+        .into_response()
+}
+
+async fn get_coverage(State(db): State<DataBase<impl Storage>>) -> Response {
+    let positions: Vec<(f64, f64)> = fetch_measurements(&db)
+        .await
+        .iter()
+        .filter_map(ArchivedMeasurement::galactic_position)
+        .map(|(l, b)| (l.0, b.0))
+        .collect();
+    match render_coverage_svg(&positions) {
+        Ok(svg) => ([(header::CONTENT_TYPE, "image/svg+xml")], svg).into_response(),
+        Err(error) => error.into_response(),
+    }
+}
+
+#[derive(Deserialize, Debug, Copy, Clone, Default)]
+#[serde(rename_all = "lowercase")]
+enum MonitoredQuantity {
+    #[default]
+    Peak,
+    Area,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct MonitoringQuery {
+    system: CoordinateSystem,
+    /// Right ascension or galactic longitude
+    longitude: Degrees,
+    /// Declination or galactic latitude
+    latitude: Degrees,
+    /// Largest accepted pointing difference
+    tolerance: Option<Degrees>,
+    telescope: Option<String>,
+    quantity: Option<MonitoredQuantity>,
+}
+
+impl MonitoringQuery {
+    fn target(&self) -> TelescopeTarget {
+        self.system
+            .target(self.longitude.to_radians(), self.latitude.to_radians())
+    }
+
+    fn tolerance(&self) -> Radians {
+        self.tolerance.unwrap_or(Degrees(0.5)).to_radians()
+    }
+}
+
+async fn monitoring_points(
+    db: &DataBase<impl Storage>,
+    query: &MonitoringQuery,
+) -> Vec<MonitoringPoint> {
+    monitor_target(
+        &fetch_measurements_with_spectra(db).await,
+        query.target(),
+        query.tolerance(),
+        query.telescope.as_deref(),
+    )
+}
+
+/// Points to the provenance of a derived product, as a `Link` header.
+fn provenance_link(path: &str, query: Option<String>) -> [(header::HeaderName, String); 1] {
+    let query = query.map(|query| format!("?{}", query)).unwrap_or_default();
+    [(
+        header::LINK,
+        format!(
+            "</api/archive/{}/provenance{}>; rel=\"describedby\"",
+            path, query
+        ),
+    )]
+}
+
+async fn get_monitoring(
+    State(db): State<DataBase<impl Storage>>,
+    Query(query): Query<MonitoringQuery>,
+    RawQuery(raw_query): RawQuery,
+) -> impl IntoResponse {
+    (
+        provenance_link("monitoring", raw_query),
+        Json(monitoring_points(&db, &query).await),
+    )
+}
+
+async fn get_monitoring_provenance(
+    State(db): State<DataBase<impl Storage>>,
+    Query(query): Query<MonitoringQuery>,
+) -> Json<Provenance> {
+    let inputs = monitoring_points(&db, &query)
+        .await
+        .iter()
+        .map(|p| p.id)
+        .collect();
+    Json(monitoring_provenance(
+        inputs,
+        [
+            ("system", json!(query.system)),
+            ("longitude", json!(query.longitude.0)),
+            ("latitude", json!(query.latitude.0)),
+            ("tolerance", json!(query.tolerance().to_degrees().0)),
+            ("telescope", json!(query.telescope)),
+        ],
+    ))
+}
+
+async fn get_monitoring_plot(
+    State(db): State<DataBase<impl Storage>>,
+    Query(query): Query<MonitoringQuery>,
+    RawQuery(raw_query): RawQuery,
+) -> Response {
+    let points = monitoring_points(&db, &query).await;
+    let (values, y_label): (Vec<_>, _) = match query.quantity.unwrap_or_default() {
+        MonitoredQuantity::Peak => (
+            points.iter().map(|p| (p.start, p.peak_amplitude)).collect(),
+            "Peak amplitude",
+        ),
+        MonitoredQuantity::Area => (
+            points
+                .iter()
+                .map(|p| (p.start, p.integrated_area))
+                .collect(),
+            "Integrated area",
+        ),
+    };
+    let title = format!(
+        "Monitoring of ({:.1}, {:.1})",
+        query.longitude, query.latitude
+    );
+    match render_time_series_svg(&values, &title, y_label) {
+        Ok(svg) => (
+            [(header::CONTENT_TYPE, "image/svg+xml")],
+            provenance_link("monitoring", raw_query),
+            svg,
+        )
+            .into_response(),
+        Err(error) => error.into_response(),
+    }
+}
+
+async fn get_rotation_curve(State(db): State<DataBase<impl Storage>>) -> impl IntoResponse {
+    let points: Vec<RotationCurvePoint> =
+        rotation_curve(&fetch_measurements_with_spectra(&db).await);
+    (provenance_link("rotation_curve", None), Json(points))
+}
+
+async fn get_rotation_curve_provenance(
+    State(db): State<DataBase<impl Storage>>,
+) -> Json<Provenance> {
+    let inputs = rotation_curve(&fetch_measurements_with_spectra(&db).await)
+        .iter()
+        .map(|p| p.id)
+        .collect();
+    Json(rotation_curve_provenance(inputs))
+}
+
+async fn get_rotation_curve_plot(State(db): State<DataBase<impl Storage>>) -> Response {
+    let points: Vec<(f64, f64)> = rotation_curve(&fetch_measurements_with_spectra(&db).await)
+        .iter()
+        .map(|p| (p.radius, p.rotation_speed))
+        .collect();
+    match render_rotation_curve_svg(&points) {
+        Ok(svg) => (
+            [(header::CONTENT_TYPE, "image/svg+xml")],
+            provenance_link("rotation_curve", None),
+            svg,
+        )
+            .into_response(),
+        Err(error) => error.into_response(),
+    }
+}
+
+async fn get_measurement(
+    State(db): State<DataBase<impl Storage>>,
+    Path(id): Path<u64>,
+) -> Result<Json<ArchivedMeasurement>, MeasurementError> {
+    Ok(Json(fetch_measurement(&db, id).await?))
+}
+
+/// Move a measurement to the trash, from where it can be restored.
+async fn delete_measurement(
+    State(db): State<DataBase<impl Storage>>,
+    Path(id): Path<u64>,
+) -> Result<Json<TrashedItem>, TrashError> {
+    Ok(Json(trash_measurement(&db, id, Utc::now()).await?))
+}
+
+/// Stream the data file of a measurement from the file store. Measurements
+/// archived before a file store was configured are served from the
+/// database.
+async fn get_measurement_file(
+    State(db): State<DataBase<impl Storage>>,
+    Path(id): Path<u64>,
+) -> Result<Response, MeasurementError> {
+    let file_storage = db
+        .get_data()
+        .await
+        .expect("As long as no one is manually editing the database, this should never fail.")
+        .file_storage;
+    let headers = [
+        (header::CONTENT_TYPE, "application/json".to_string()),
+        (
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"salsa-{}.json\"", id),
+        ),
+    ];
+    if let Some(definition) = file_storage {
+        // A corrupted file has already been reported, the copy in the
+        // database is served instead.
+        match read_measurement_file(&db, &*create_file_store(&definition), id).await {
+            Ok(file) => return Ok((headers, file).into_response()),
+            Err(FileStoreError::NotFound(_)) | Err(FileStoreError::Corrupted(_)) => {}
+            Err(error) => log::error!("Failed to read file of measurement {}: {}", id, error),
+        }
+    }
+    Ok((headers, Json(fetch_measurement(&db, id).await?)).into_response())
+}
+
+async fn get_measurement_fits(
+    State(db): State<DataBase<impl Storage>>,
+    Path(id): Path<u64>,
+) -> Result<Response, MeasurementError> {
+    let measurement = fetch_measurement(&db, id).await?;
+    let location = db
+        .get_data()
+        .await
+        .expect("As long as no one is manually editing the database, this should never fail.")
+        .telescopes
+        .into_iter()
+        .find(|t| t.name == measurement.telescope_name)
+        .map(|t| t.location);
+    let headers = [
+        (header::CONTENT_TYPE, FITS_CONTENT_TYPE.to_string()),
+        (
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"salsa-{}.fits\"", id),
+        ),
+    ];
+    Ok((headers, measurement_fits(&measurement, location)).into_response())
+}
+
+/// Data files that are not intact.
+async fn get_integrity(State(db): State<DataBase<impl Storage>>) -> Json<Vec<FileChecksum>> {
+    Json(
+        db.get_data()
+            .await
+            .expect("As long as no one is manually editing the database, this should never fail.")
+            .file_checksums
+            .into_iter()
+            .filter(|c| c.integrity != FileIntegrity::Intact)
+            .collect(),
+    )
+}
+
+/// How the column density of a measurement was computed, null if it has
+/// none or it was archived before provenance was recorded.
+async fn get_measurement_provenance(
+    State(db): State<DataBase<impl Storage>>,
+    Path(id): Path<u64>,
+) -> Result<Json<Option<Provenance>>, MeasurementError> {
+    Ok(Json(find_measurement(&db, id).await?.provenance))
+}
+
+/// Total power about once a second over the integration.
+async fn get_measurement_continuum(
+    State(db): State<DataBase<impl Storage>>,
+    Path(id): Path<u64>,
+) -> Result<Json<Vec<ContinuumSample>>, MeasurementError> {
+    Ok(Json(
+        fetch_measurement(&db, id).await?.measurement.continuum,
+    ))
+}
+
+async fn get_measurement_continuum_plot(
+    State(db): State<DataBase<impl Storage>>,
+    Path(id): Path<u64>,
+) -> Result<Response, MeasurementError> {
+    let continuum = fetch_measurement(&db, id).await?.measurement.continuum;
+    let response = match render_continuum_svg(&continuum) {
+        Ok(svg) => ([(header::CONTENT_TYPE, "image/svg+xml")], svg).into_response(),
+        Err(error) => error.into_response(),
+    };
+    Ok(response)
+}
+
+async fn post_reprocess(
+    State(db): State<DataBase<impl Storage>>,
+    Json(request): Json<ReprocessingRequest>,
+) -> Result<Json<ReprocessingReport>, ReprocessingError> {
+    Ok(Json(
+        reprocess_measurements(&db, &request, Utc::now()).await?,
+    ))
+}
+
+/// Stack measurements of the same target into a new measurement, returning
+/// its id.
+async fn post_stack(
+    State(db): State<DataBase<impl Storage>>,
+    Json(request): Json<StackRequest>,
+) -> Result<Json<u64>, StackError> {
+    Ok(Json(
+        stack_measurements(&db, &request.measurement_ids).await?,
+    ))
+}
+
+async fn get_measurement_plot(
+    State(db): State<DataBase<impl Storage>>,
+    Path(id): Path<u64>,
+    Query(mut options): Query<PlotOptions>,
+) -> Result<Response, MeasurementError> {
+    let archived = fetch_measurement(&db, id).await?;
+    let spectrum = archived.spectrum();
+    options.spectral_lines = fetch_spectral_lines(&db).await;
+    if let Some(line) = &options.line {
+        let measurement = &archived.measurement;
+        match lsr_velocities(
+            &options.spectral_lines,
+            line,
+            &measurement.freqs,
+            measurement.target,
+            measurement.start,
+        ) {
+            Ok(axis) => options.velocities = Some(axis.velocities),
+            Err(error) => return Ok(error.into_response()),
+        }
+    }
+    let response = match render_spectrum(&spectrum, &options) {
+        Ok(plot) => (
+            [(header::CONTENT_TYPE, options.format().content_type())],
+            plot,
+        )
+            .into_response(),
+        Err(error) => error.into_response(),
+    };
+    Ok(response)
+}
+
+#[derive(Deserialize, Debug)]
+struct VelocityQuery {
+    /// Name of the spectral line, HI if not given.
+    line: Option<String>,
+}
+
+async fn get_measurement_velocity(
+    State(db): State<DataBase<impl Storage>>,
+    Path(id): Path<u64>,
+    Query(query): Query<VelocityQuery>,
+) -> Result<Response, MeasurementError> {
+    let measurement = fetch_measurement(&db, id).await?.measurement;
+    let lines = fetch_spectral_lines(&db).await;
+    let response = match lsr_velocities(
+        &lines,
+        query.line.as_deref().unwrap_or("HI"),
+        &measurement.freqs,
+        measurement.target,
+        measurement.start,
+    ) {
+        Ok(axis) => Json(axis).into_response(),
+        Err(error) => error.into_response(),
+    };
+    Ok(response)
+}
+
+async fn get_measurement_thumbnail(
+    State(db): State<DataBase<impl Storage>>,
+    Path(id): Path<u64>,
+) -> Result<Response, MeasurementError> {
+    let measurement = find_measurement(&db, id).await?;
+    let response = match fetch_thumbnail(&db, measurement).await {
+        Ok(png) => (
+            [
+                (header::CONTENT_TYPE, "image/png"),
+                // The spectrum of a measurement never changes.
+                (header::CACHE_CONTROL, "public, max-age=86400"),
+            ],
+            png,
+        )
+            .into_response(),
+        Err(error) => error.into_response(),
+    };
+    Ok(response)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::archive::archive_measurement;
+    use crate::archive::provenance::SOFTWARE_VERSION;
+    use crate::archive::test_utils::galactic_measurement;
+    use crate::database::create_in_memory_database;
+    use crate::test_utils::get;
+    use axum::http::StatusCode;
+
+    #[tokio::test]
+    async fn test_get_measurements() {
+        let db = create_in_memory_database();
+        let measurement = galactic_measurement(Degrees(30.0), chrono::Utc::now());
+        archive_measurement(&db, "fake", measurement.clone())
+            .await
+            .unwrap();
+
+        let response = get(routes(db), "/").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let summaries: Vec<ArchivedMeasurementSummary> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(summaries.len(), 1);
+        assert_eq!(summaries[0].id, 1);
+        assert_eq!(summaries[0].telescope_name, "fake");
+        assert_eq!(summaries[0].target, measurement.target);
+    }
+
+    #[tokio::test]
+    async fn test_download_measurements() {
+        let db = create_in_memory_database();
+        let measurement = galactic_measurement(Degrees(30.0), chrono::Utc::now());
+        archive_measurement(&db, "fake", measurement.clone())
+            .await
+            .unwrap();
+
+        let response = get(routes(db), "/download").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response
+            .headers()
+            .get(header::CONTENT_DISPOSITION)
+            .is_some());
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let measurements: Vec<ArchivedMeasurement> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(measurements.len(), 1);
+        assert_eq!(measurements[0].measurement, measurement);
+    }
+
+    #[tokio::test]
+    async fn test_get_monitoring() {
+        let db = create_in_memory_database();
+        archive_measurement(
+            &db,
+            "fake",
+            galactic_measurement(Degrees(30.0), chrono::Utc::now()),
+        )
+        .await
+        .unwrap();
+        archive_measurement(
+            &db,
+            "fake",
+            galactic_measurement(Degrees(60.0), chrono::Utc::now()),
+        )
+        .await
+        .unwrap();
+
+        let response = get(
+            routes(db.clone()),
+            "/monitoring?system=galactic&longitude=30&latitude=0",
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let link = response.headers()[header::LINK]
+            .to_str()
+            .unwrap()
+            .to_string();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let points: Vec<MonitoringPoint> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(points.len(), 1);
+        assert_eq!(points[0].id, 1);
+
+        // The series can be traced back to the measurements it came from.
+        let uri = link
+            .trim_start_matches("</api/archive")
+            .split('>')
+            .next()
+            .unwrap();
+        let response = get(routes(db), uri).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let provenance: Provenance = serde_json::from_slice(&body).unwrap();
+        assert_eq!(provenance.inputs, vec![1]);
+        assert_eq!(provenance.stages[0].parameters["longitude"], json!(30.0));
+        assert_eq!(provenance.software_version, SOFTWARE_VERSION);
+    }
+
+    #[tokio::test]
+    async fn test_get_unknown_measurement() {
+        let db = create_in_memory_database();
+        let response = get(routes(db), "/42").await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,113 @@
+use crate::archive::archive_measurement;
+use crate::database::{DataBase, DataBaseError, Storage};
+use crate::telescopes::Measurement;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// How often the measurement in progress is written to the database, which
+/// bounds how much of an integration is lost if the backend dies.
+pub const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Snapshot of a measurement that was still in progress.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct MeasurementCheckpoint {
+    pub telescope_name: String,
+    pub saved: DateTime<Utc>,
+    pub measurement: Measurement,
+}
+
+/// Replace the checkpoint of the measurement in progress on `telescope_name`.
+pub async fn save_checkpoint(
+    db: &DataBase<impl Storage>,
+    telescope_name: &str,
+    measurement: Measurement,
+) -> Result<(), DataBaseError> {
+    db.update_data(|mut data_model| {
+        data_model
+            .measurement_checkpoints
+            .retain(|c| c.telescope_name != telescope_name);
+        data_model
+            .measurement_checkpoints
+            .push(MeasurementCheckpoint {
+                telescope_name: telescope_name.to_string(),
+                saved: Utc::now(),
+                measurement,
+            });
+        data_model
+    })
+    .await
+}
+
+/// Forget the checkpoint of `telescope_name`, once its measurement has
+/// been archived or abandoned.
+pub async fn clear_checkpoint(
+    db: &DataBase<impl Storage>,
+    telescope_name: &str,
+) -> Result<(), DataBaseError> {
+    db.update_data(|mut data_model| {
+        data_model
+            .measurement_checkpoints
+            .retain(|c| c.telescope_name != telescope_name);
+        data_model
+    })
+    .await
+}
+
+/// Archive the measurements that were in progress when the backend stopped,
+/// flagged as interrupted.
+///
+/// Returns the ids of the archived measurements.
+pub async fn recover_interrupted_measurements(
+    db: &DataBase<impl Storage>,
+) -> Result<Vec<u64>, DataBaseError> {
+    let checkpoints = db.get_data().await?.measurement_checkpoints;
+    let mut ids = Vec::new();
+    for checkpoint in checkpoints {
+        log::warn!(
+            "Recovering interrupted measurement on {} from {}",
+            checkpoint.telescope_name,
+            checkpoint.saved
+        );
+        let measurement = Measurement {
+            interrupted: true,
+            ..checkpoint.measurement
+        };
+        ids.push(archive_measurement(db, &checkpoint.telescope_name, measurement).await?);
+        clear_checkpoint(db, &checkpoint.telescope_name).await?;
+    }
+    Ok(ids)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::angles::Degrees;
+    use crate::archive::test_utils::galactic_measurement;
+    use crate::database::create_in_memory_database;
+
+    #[tokio::test]
+    async fn test_recover_interrupted_measurements() {
+        let db = create_in_memory_database();
+        let measurement = galactic_measurement(Degrees(30.0), Utc::now());
+        save_checkpoint(&db, "fake", measurement.clone())
+            .await
+            .unwrap();
+        save_checkpoint(&db, "fake", measurement.clone())
+            .await
+            .unwrap();
+        save_checkpoint(&db, "brage", measurement.clone())
+            .await
+            .unwrap();
+        clear_checkpoint(&db, "brage").await.unwrap();
+
+        assert_eq!(
+            recover_interrupted_measurements(&db).await.unwrap(),
+            vec![1]
+        );
+        let data_model = db.get_data().await.unwrap();
+        assert!(data_model.measurement_checkpoints.is_empty());
+        assert_eq!(data_model.measurements[0].telescope_name, "fake");
+        assert!(data_model.measurements[0].measurement.interrupted);
+    }
+}
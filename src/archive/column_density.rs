@@ -0,0 +1,166 @@
+//! Column density of neutral hydrogen from a spectrum of the 21 cm line.
+//!
+//! Assuming the line is optically thin, the column density is proportional
+//! to the brightness temperature integrated over velocity. The spectrum is
+//! put on a temperature scale by taking the baseline to be the system
+//! temperature, so the result is only as good as the measured system
+//! temperature and the flatness of the bandpass.
+
+use crate::archive::rotation_curve::lsr_velocities;
+use crate::constants::{DEFAULT_SYSTEM_TEMPERATURE, HI_REST_FREQUENCY};
+use crate::telescopes::{Measurement, TelescopeTarget};
+use serde::{Deserialize, Serialize};
+
+/// Column density in cm^-2 per K km/s of integrated brightness temperature
+/// of an optically thin HI line.
+pub const HI_COLUMN_DENSITY_FACTOR: f64 = 1.823e18;
+/// Channels further from zero velocity than this, in km/s, are taken to be
+/// free of line emission and used to fit the baseline.
+pub const LINE_FREE_VELOCITY: f64 = 150.0;
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub enum BaselineFit {
+    /// Straight line fitted to the channels outside of the line window.
+    Linear,
+    /// Median of the spectrum, used if too few channels are outside of the
+    /// line window.
+    Median,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct ColumnDensity {
+    /// HI column density in cm^-2.
+    pub column_density: f64,
+    /// Brightness temperature integrated over velocity in K km/s.
+    pub integrated_intensity: f64,
+    /// System temperature in K used for the temperature scale.
+    pub system_temperature: f64,
+    /// The system temperature was not measured, so a default was used.
+    pub assumed_system_temperature: bool,
+    pub baseline: BaselineFit,
+}
+
+/// Straight line through `points` of (velocity, amplitude), by least squares.
+pub fn fit_line(points: &[(f64, f64)]) -> Option<(f64, f64)> {
+    let n = points.len() as f64;
+    let mean_x = points.iter().map(|p| p.0).sum::<f64>() / n;
+    let mean_y = points.iter().map(|p| p.1).sum::<f64>() / n;
+    let variance: f64 = points.iter().map(|p| (p.0 - mean_x).powi(2)).sum();
+    if points.len() < 2 || variance == 0.0 {
+        return None;
+    }
+    let covariance: f64 = points.iter().map(|p| (p.0 - mean_x) * (p.1 - mean_y)).sum();
+    let slope = covariance / variance;
+    Some((mean_y - slope * mean_x, slope))
+}
+
+/// Estimate the HI column density towards a galactic target, or None if
+/// the measurement does not cover the HI line.
+pub fn column_density(measurement: &Measurement) -> Option<ColumnDensity> {
+    let TelescopeTarget::Galactic { l, b } = measurement.target else {
+        return None;
+    };
+    let channels = measurement.amps.len().min(measurement.freqs.len());
+    let freqs = &measurement.freqs[..channels];
+    let amps = &measurement.amps[..channels];
+    let lowest = freqs.iter().copied().fold(f64::INFINITY, f64::min);
+    let highest = freqs.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    if channels < 3 || !(lowest..=highest).contains(&HI_REST_FREQUENCY) {
+        return None;
+    }
+    let velocities = lsr_velocities(freqs, l, b, measurement.start);
+
+    let line_free: Vec<(f64, f64)> = velocities
+        .iter()
+        .copied()
+        .zip(amps.iter().copied())
+        .filter(|(v, _)| v.abs() > LINE_FREE_VELOCITY)
+        .collect();
+    let (baseline_fit, (intercept, slope)) = match fit_line(&line_free) {
+        Some(line) => (BaselineFit::Linear, line),
+        None => {
+            let mut sorted = amps.to_vec();
+            sorted.sort_by(|a, b| a.total_cmp(b));
+            (BaselineFit::Median, (sorted[channels / 2], 0.0))
+        }
+    };
+    let (system_temperature, assumed_system_temperature) = match measurement.system_temperature {
+        Some(tsys) => (tsys, false),
+        None => (DEFAULT_SYSTEM_TEMPERATURE, true),
+    };
+
+    let brightness: Vec<f64> = velocities
+        .iter()
+        .zip(amps)
+        .map(|(v, amp)| {
+            let level = intercept + slope * v;
+            if level > 0.0 {
+                system_temperature * (amp - level) / level
+            } else {
+                0.0
+            }
+        })
+        .collect();
+    let integrated_intensity = velocities
+        .windows(2)
+        .zip(brightness.windows(2))
+        .filter(|(v, _)| v[0].abs() <= LINE_FREE_VELOCITY && v[1].abs() <= LINE_FREE_VELOCITY)
+        .map(|(v, t)| (v[1] - v[0]).abs() * 0.5 * (t[0] + t[1]))
+        .sum::<f64>();
+    Some(ColumnDensity {
+        column_density: HI_COLUMN_DENSITY_FACTOR * integrated_intensity,
+        integrated_intensity,
+        system_temperature,
+        assumed_system_temperature,
+        baseline: baseline_fit,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::angles::{Degrees, Radians};
+    use crate::archive::test_utils::galactic_measurement;
+    use chrono::Utc;
+
+    #[test]
+    fn test_column_density() {
+        let mut measurement = galactic_measurement(Degrees(120.0), Utc::now());
+        measurement.freqs = (0..1000)
+            .map(|channel| HI_REST_FREQUENCY - 2.5e6 + channel as f64 * 5e3)
+            .collect();
+        let velocities = lsr_velocities(
+            &measurement.freqs,
+            Degrees(120.0).to_radians(),
+            Radians(0.0),
+            measurement.start,
+        );
+        // A sloping bandpass at 100 K, with a Gaussian line peaking at 50 K
+        // and 10 km/s wide, which integrates to 50 * 10 * sqrt(2 pi) K km/s.
+        measurement.system_temperature = Some(100.0);
+        measurement.amps = velocities
+            .iter()
+            .map(|v| {
+                let bandpass = 2.0 + 0.001 * v;
+                bandpass * (1.0 + 0.5 * (-0.5 * ((v + 20.0) / 10.0).powi(2)).exp())
+            })
+            .collect();
+
+        let result = column_density(&measurement).unwrap();
+        assert_eq!(result.baseline, BaselineFit::Linear);
+        assert!(!result.assumed_system_temperature);
+        let expected = 50.0 * 10.0 * (2.0 * std::f64::consts::PI).sqrt();
+        assert!(
+            (result.integrated_intensity - expected).abs() < 0.01 * expected,
+            "{:?}",
+            result
+        );
+        assert!((result.column_density / (HI_COLUMN_DENSITY_FACTOR * expected) - 1.0).abs() < 0.01);
+
+        // The default spectrum does not cover the HI line.
+        assert_eq!(
+            column_density(&galactic_measurement(Degrees(120.0), Utc::now())),
+            None
+        );
+    }
+}
@@ -0,0 +1,344 @@
+//! Detection and stacking of repeated observations.
+//!
+//! Starting an integration of the same target with the same receiver
+//! settings as a measurement that finished on the telescope a few minutes
+//! earlier is usually a mistake, the time is better spent integrating
+//! longer. Such a start is refused with a warning unless it is confirmed,
+//! and the two measurements can instead be stacked into one afterwards.
+
+use crate::archive::files::load_spectra;
+use crate::archive::{archive_measurement, ArchivedMeasurement, ArchivedMeasurementSummary};
+use crate::database::{DataBase, DataBaseError, DataModel, Storage};
+use crate::telescopes::{
+    Measurement, PolarizationSpectrum, ReceiverConfiguration, TelescopeTarget,
+};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// How recently a measurement has to have finished to be repeated.
+pub const DUPLICATE_MINUTES: i64 = 15;
+
+/// A recent measurement that a new integration would repeat.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct DuplicateObservation {
+    pub measurement: ArchivedMeasurementSummary,
+    pub finished: DateTime<Utc>,
+}
+
+fn same_settings(previous: &ReceiverConfiguration, requested: &ReceiverConfiguration) -> bool {
+    // Without a reference frequency the telescope picks one, likely the
+    // same as last time.
+    let requested = ReceiverConfiguration {
+        reference_frequency: requested
+            .reference_frequency
+            .or(previous.reference_frequency),
+        ..*requested
+    };
+    previous.changes(&requested).is_empty()
+}
+
+/// The most recent measurement on `telescope_name` of `target` with the
+/// receiver settings of `requested`, if it finished within
+/// `DUPLICATE_MINUTES` of `now`.
+pub fn recent_duplicate(
+    data_model: &DataModel,
+    telescope_name: &str,
+    target: TelescopeTarget,
+    requested: &ReceiverConfiguration,
+    now: DateTime<Utc>,
+) -> Option<DuplicateObservation> {
+    let finished = |m: &ArchivedMeasurement| {
+        m.measurement.start + Duration::from_std(m.measurement.duration).unwrap_or(Duration::zero())
+    };
+    data_model
+        .measurements
+        .iter()
+        .filter(|m| m.telescope_name == telescope_name && m.measurement.target == target)
+        .filter(|m| {
+            m.measurement
+                .receiver_configuration
+                .is_some_and(|previous| same_settings(&previous, requested))
+        })
+        .filter(|m| now - finished(m) <= Duration::minutes(DUPLICATE_MINUTES))
+        .max_by_key(|m| finished(m))
+        .map(|m| DuplicateObservation {
+            measurement: m.summary(),
+            finished: finished(m),
+        })
+}
+
+#[derive(Debug, Error, PartialEq)]
+pub enum StackError {
+    #[error("at least two measurements are needed")]
+    TooFew,
+    #[error("measurement {0} not found")]
+    NotFound(u64),
+    #[error("measurement {0} differs in telescope, target or frequencies")]
+    Mismatch(u64),
+    #[error("the data file of measurement {0} could not be read")]
+    Unreadable(u64),
+    #[error("failed to update the archive")]
+    DataBase,
+}
+
+impl From<DataBaseError> for StackError {
+    fn from(_: DataBaseError) -> Self {
+        StackError::DataBase
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct StackRequest {
+    pub measurement_ids: Vec<u64>,
+}
+
+/// The average of `measurements`, weighted by their integration times.
+/// They have to be of the same target at the same frequencies.
+pub fn stack(measurements: &[&ArchivedMeasurement]) -> Result<Measurement, StackError> {
+    let (first, rest) = measurements.split_first().ok_or(StackError::TooFew)?;
+    if rest.is_empty() {
+        return Err(StackError::TooFew);
+    }
+    if let Some(other) = rest.iter().find(|m| {
+        m.telescope_name != first.telescope_name
+            || m.measurement.target != first.measurement.target
+            || m.measurement.freqs != first.measurement.freqs
+    }) {
+        return Err(StackError::Mismatch(other.id));
+    }
+    let weights: Vec<f64> = measurements
+        .iter()
+        .map(|m| {
+            m.measurement
+                .integration_time
+                .as_secs_f64()
+                .max(f64::EPSILON)
+        })
+        .collect();
+    let total_weight: f64 = weights.iter().sum();
+    let amps = weighted_average(
+        measurements.iter().map(|m| m.measurement.amps.as_slice()),
+        &weights,
+    );
+    // Polarizations are only kept if all measurements recorded the same.
+    let polarizations = if rest.iter().all(|m| {
+        m.measurement
+            .polarizations
+            .iter()
+            .map(|p| p.polarization)
+            .eq(first
+                .measurement
+                .polarizations
+                .iter()
+                .map(|p| p.polarization))
+    }) {
+        first
+            .measurement
+            .polarizations
+            .iter()
+            .enumerate()
+            .map(|(index, spectrum)| PolarizationSpectrum {
+                polarization: spectrum.polarization,
+                amps: weighted_average(
+                    measurements
+                        .iter()
+                        .map(|m| m.measurement.polarizations[index].amps.as_slice()),
+                    &weights,
+                ),
+            })
+            .collect()
+    } else {
+        vec![]
+    };
+    let system_temperatures: Option<Vec<f64>> = measurements
+        .iter()
+        .map(|m| m.measurement.system_temperature)
+        .collect();
+    Ok(Measurement {
+        amps,
+        start: measurements
+            .iter()
+            .map(|m| m.measurement.start)
+            .min()
+            .unwrap_or(first.measurement.start),
+        duration: measurements.iter().map(|m| m.measurement.duration).sum(),
+        integration_time: measurements
+            .iter()
+            .map(|m| m.measurement.integration_time)
+            .sum(),
+        requested_integration_time: measurements
+            .iter()
+            .map(|m| m.measurement.requested_integration_time)
+            .sum(),
+        dropped_samples: measurements
+            .iter()
+            .map(|m| m.measurement.dropped_samples)
+            .sum(),
+        system_temperature: system_temperatures.map(|temperatures| {
+            temperatures
+                .iter()
+                .zip(&weights)
+                .map(|(t, w)| t * w)
+                .sum::<f64>()
+                / total_weight
+        }),
+        interrupted: measurements.iter().any(|m| m.measurement.interrupted),
+        continuum: {
+            let mut continuum: Vec<_> = measurements
+                .iter()
+                .flat_map(|m| m.measurement.continuum.iter().cloned())
+                .collect();
+            continuum.sort_by_key(|sample| sample.time);
+            continuum
+        },
+        max_tracking_error: measurements
+            .iter()
+            .filter_map(|m| m.measurement.max_tracking_error)
+            .max_by(|a, b| a.0.total_cmp(&b.0)),
+        cycles: measurements.iter().map(|m| m.measurement.cycles).sum(),
+        polarizations,
+        // Older measurements have no flags to average.
+        flagged: if measurements
+            .iter()
+            .all(|m| m.measurement.flagged.len() == first.measurement.amps.len())
+        {
+            weighted_average(
+                measurements
+                    .iter()
+                    .map(|m| m.measurement.flagged.as_slice()),
+                &weights,
+            )
+        } else {
+            vec![]
+        },
+        ..first.measurement.clone()
+    })
+}
+
+/// Channel by channel average of spectra of the same length.
+fn weighted_average<'a>(spectra: impl Iterator<Item = &'a [f64]>, weights: &[f64]) -> Vec<f64> {
+    let total_weight: f64 = weights.iter().sum();
+    let mut average: Vec<f64> = vec![];
+    for (amps, weight) in spectra.zip(weights) {
+        average.resize(amps.len(), 0.0);
+        for (sum, amp) in average.iter_mut().zip(amps) {
+            *sum += weight * amp / total_weight;
+        }
+    }
+    average
+}
+
+/// Archive the stack of the measurements `ids` as a new measurement,
+/// returning its id. The stacked measurements are kept.
+pub async fn stack_measurements(
+    db: &DataBase<impl Storage>,
+    ids: &[u64],
+) -> Result<u64, StackError> {
+    let data_model = db.get_data().await?;
+    let mut measurements = Vec::new();
+    for id in ids {
+        let archived = data_model
+            .measurements
+            .iter()
+            .find(|m| m.id == *id)
+            .ok_or(StackError::NotFound(*id))?;
+        let archived = load_spectra(db, archived.clone()).await.map_err(|error| {
+            log::error!("Failed to read spectra of measurement {}: {}", id, error);
+            StackError::Unreadable(*id)
+        })?;
+        measurements.push(archived);
+    }
+    let stacked = stack(&measurements.iter().collect::<Vec<_>>())?;
+    let telescope_name = measurements[0].telescope_name.clone();
+    Ok(archive_measurement(db, &telescope_name, stacked).await?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::angles::Degrees;
+    use crate::archive::test_utils::galactic_measurement;
+    use crate::telescopes::{Polarization, WindowFunction};
+
+    #[test]
+    fn test_duplicates_and_stacking() {
+        let now = Utc::now();
+        let configuration = ReceiverConfiguration {
+            integrate: true,
+            reference_frequency: Some(1.41e9),
+            ..ReceiverConfiguration::stopped()
+        };
+        let archived = |id, l, minutes_ago, amps: Vec<f64>, seconds| {
+            let measurement =
+                galactic_measurement(Degrees(l), now - Duration::minutes(minutes_ago));
+            ArchivedMeasurement::new(
+                id,
+                "fake",
+                Measurement {
+                    amps,
+                    integration_time: std::time::Duration::from_secs(seconds),
+                    receiver_configuration: Some(configuration),
+                    ..measurement
+                },
+            )
+        };
+        let data_model = DataModel {
+            measurements: vec![
+                archived(1, 30.0, 5, vec![1.0, 1.0, 1.0], 60),
+                archived(2, 30.0, 30, vec![4.0, 4.0, 4.0], 180),
+                archived(3, 40.0, 5, vec![0.0, 0.0, 0.0], 60),
+            ],
+            ..Default::default()
+        };
+        let target = data_model.measurements[0].measurement.target;
+        let requested = ReceiverConfiguration {
+            reference_frequency: None,
+            ..configuration
+        };
+        let duplicate = recent_duplicate(&data_model, "fake", target, &requested, now).unwrap();
+        assert_eq!(duplicate.measurement.id, 1);
+        assert_eq!(
+            recent_duplicate(&data_model, "other", target, &requested, now),
+            None
+        );
+        let hann = ReceiverConfiguration {
+            window: WindowFunction::Hann,
+            ..requested
+        };
+        assert_eq!(
+            recent_duplicate(&data_model, "fake", target, &hann, now),
+            None
+        );
+        let later = now + Duration::minutes(DUPLICATE_MINUTES);
+        assert_eq!(
+            recent_duplicate(&data_model, "fake", target, &requested, later),
+            None
+        );
+
+        let measurements: Vec<_> = data_model.measurements.iter().collect();
+        let stacked = stack(&measurements[..2]).unwrap();
+        assert_eq!(stacked.amps, vec![3.25, 3.25, 3.25]);
+        assert_eq!(stacked.integration_time.as_secs(), 240);
+        assert_eq!(stacked.start, measurements[1].measurement.start);
+        assert_eq!(stack(&measurements), Err(StackError::Mismatch(3)));
+        assert_eq!(stack(&measurements[..1]), Err(StackError::TooFew));
+
+        let mut dual = data_model.measurements[..2].to_vec();
+        for archived in dual.iter_mut() {
+            archived.measurement.polarizations = [Polarization::X, Polarization::Y]
+                .into_iter()
+                .map(|polarization| PolarizationSpectrum {
+                    polarization,
+                    amps: archived.measurement.amps.clone(),
+                })
+                .collect();
+        }
+        let stacked = stack(&dual.iter().collect::<Vec<_>>()).unwrap();
+        assert_eq!(stacked.polarizations.len(), 2);
+        assert_eq!(stacked.polarizations[1].amps, vec![3.25, 3.25, 3.25]);
+        dual[1].measurement.polarizations.pop();
+        let stacked = stack(&dual.iter().collect::<Vec<_>>()).unwrap();
+        assert_eq!(stacked.polarizations, vec![]);
+    }
+}
@@ -0,0 +1,330 @@
+//! Archived measurements as a ZIP file, e.g. for a teacher collecting all
+//! spectra of a lab class.
+//!
+//! The ZIP file holds one file per measurement, CSV or FITS, and a manifest
+//! describing them. The files are stored without compression, which keeps
+//! the format simple enough to write here, and the ZIP file is produced one
+//! measurement at a time while it is sent.
+
+use crate::archive::fits::measurement_fits;
+use crate::archive::{ArchivedMeasurement, ArchivedMeasurementSummary};
+use crate::coords::Location;
+use chrono::{DateTime, Datelike, Timelike, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::Duration;
+
+pub const ZIP_CONTENT_TYPE: &str = "application/zip";
+pub const MANIFEST_FILE_NAME: &str = "manifest.json";
+/// The ZIP format without its 64 bit extensions can not hold more files.
+pub const MAX_EXPORTED_MEASUREMENTS: usize = u16::MAX as usize - 1;
+
+const LOCAL_FILE_HEADER_SIGNATURE: u32 = 0x04034b50;
+const CENTRAL_DIRECTORY_SIGNATURE: u32 = 0x02014b50;
+const END_OF_CENTRAL_DIRECTORY_SIGNATURE: u32 = 0x06054b50;
+/// Version 2.0, the first with folders, which is what readers expect.
+const ZIP_VERSION: u16 = 20;
+/// File names are UTF-8.
+const UTF8_FLAG: u16 = 0x0800;
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    Fits,
+}
+
+/// Which measurements to export, all if nothing is given.
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct ExportQuery {
+    /// Earliest start of a measurement.
+    pub from: Option<DateTime<Utc>>,
+    /// Latest start of a measurement.
+    pub to: Option<DateTime<Utc>>,
+    pub telescope: Option<String>,
+    pub format: Option<ExportFormat>,
+}
+
+impl ExportQuery {
+    pub fn includes(&self, archived: &ArchivedMeasurement) -> bool {
+        let start = archived.measurement.start;
+        self.from.is_none_or(|from| from <= start)
+            && self.to.is_none_or(|to| start <= to)
+            && self
+                .telescope
+                .as_ref()
+                .is_none_or(|telescope| *telescope == archived.telescope_name)
+    }
+}
+
+/// What the manifest says about each exported measurement.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct ManifestEntry {
+    /// Name of the file of the measurement in the ZIP file.
+    pub file: String,
+    #[serde(flatten)]
+    pub summary: ArchivedMeasurementSummary,
+    pub integration_time: Duration,
+    pub system_temperature: Option<f64>,
+    pub channels: usize,
+}
+
+/// The spectrum of `archived` as CSV, with the frequency of each channel in
+/// Hz. Comment lines before the header describe the measurement.
+pub fn measurement_csv(archived: &ArchivedMeasurement) -> String {
+    let measurement = &archived.measurement;
+    let mut csv = format!(
+        "# SALSA measurement {} with {}\n# start: {}\n# integration time: {} s\n",
+        archived.id,
+        archived.telescope_name,
+        measurement.start.to_rfc3339(),
+        measurement.integration_time.as_secs_f64(),
+    );
+    if let Some(system_temperature) = measurement.system_temperature {
+        csv.push_str(&format!("# system temperature: {} K\n", system_temperature));
+    }
+    csv.push_str("frequency,amplitude\n");
+    for (frequency, amplitude) in measurement.freqs.iter().zip(&measurement.amps) {
+        csv.push_str(&format!("{},{}\n", frequency, amplitude));
+    }
+    csv
+}
+
+/// CRC-32 of `data`, as used by ZIP.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffffffffu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb88320 & mask);
+        }
+    }
+    !crc
+}
+
+/// Time and date in the MS-DOS format of ZIP, which starts in 1980 and has
+/// a resolution of two seconds.
+fn dos_time(time: DateTime<Utc>) -> (u16, u16) {
+    let year = time.year().clamp(1980, 2107) as u16;
+    let date = ((year - 1980) << 9) | ((time.month() as u16) << 5) | time.day() as u16;
+    let time =
+        ((time.hour() as u16) << 11) | ((time.minute() as u16) << 5) | (time.second() as u16 / 2);
+    (time, date)
+}
+
+/// Writes the parts of a ZIP file of uncompressed files, one file at a
+/// time, remembering what goes in the central directory at the end.
+#[derive(Default)]
+struct ZipWriter {
+    offset: u32,
+    entries: u16,
+    central_directory: Vec<u8>,
+}
+
+impl ZipWriter {
+    /// The local header and data of a file.
+    fn file(&mut self, name: &str, data: &[u8], modified: DateTime<Utc>) -> Vec<u8> {
+        let (time, date) = dos_time(modified);
+        let crc = crc32(data);
+        let mut common = Vec::new();
+        common.extend(ZIP_VERSION.to_le_bytes());
+        common.extend(UTF8_FLAG.to_le_bytes());
+        // Stored, not compressed.
+        common.extend(0u16.to_le_bytes());
+        common.extend(time.to_le_bytes());
+        common.extend(date.to_le_bytes());
+        common.extend(crc.to_le_bytes());
+        common.extend((data.len() as u32).to_le_bytes());
+        common.extend((data.len() as u32).to_le_bytes());
+        common.extend((name.len() as u16).to_le_bytes());
+        // No extra field.
+        common.extend(0u16.to_le_bytes());
+
+        let mut local = Vec::with_capacity(30 + name.len() + data.len());
+        local.extend(LOCAL_FILE_HEADER_SIGNATURE.to_le_bytes());
+        local.extend(&common);
+        local.extend(name.as_bytes());
+        local.extend(data);
+
+        let central = &mut self.central_directory;
+        central.extend(CENTRAL_DIRECTORY_SIGNATURE.to_le_bytes());
+        central.extend(ZIP_VERSION.to_le_bytes());
+        central.extend(&common);
+        // No comment, on the first disk, without attributes.
+        central.extend([0u8; 6]);
+        central.extend([0u8; 4]);
+        central.extend(self.offset.to_le_bytes());
+        central.extend(name.as_bytes());
+
+        self.offset += local.len() as u32;
+        self.entries += 1;
+        local
+    }
+
+    /// The central directory, which ends the ZIP file.
+    fn finish(self) -> Vec<u8> {
+        let mut end = self.central_directory;
+        let size = end.len() as u32;
+        end.extend(END_OF_CENTRAL_DIRECTORY_SIGNATURE.to_le_bytes());
+        // Everything is on the first disk.
+        end.extend([0u8; 4]);
+        end.extend(self.entries.to_le_bytes());
+        end.extend(self.entries.to_le_bytes());
+        end.extend(size.to_le_bytes());
+        end.extend(self.offset.to_le_bytes());
+        // No comment.
+        end.extend(0u16.to_le_bytes());
+        end
+    }
+}
+
+/// The parts of a ZIP file of `measurements`, produced as they are
+/// iterated. Locations of the telescopes by name go into FITS files.
+pub struct ZipExport {
+    writer: Option<ZipWriter>,
+    measurements: std::vec::IntoIter<ArchivedMeasurement>,
+    format: ExportFormat,
+    locations: HashMap<String, Location>,
+    manifest: Vec<ManifestEntry>,
+}
+
+impl ZipExport {
+    pub fn new(
+        measurements: Vec<ArchivedMeasurement>,
+        format: ExportFormat,
+        locations: HashMap<String, Location>,
+    ) -> ZipExport {
+        ZipExport {
+            writer: Some(ZipWriter::default()),
+            measurements: measurements.into_iter(),
+            format,
+            locations,
+            manifest: Vec::new(),
+        }
+    }
+}
+
+impl Iterator for ZipExport {
+    type Item = Vec<u8>;
+
+    fn next(&mut self) -> Option<Vec<u8>> {
+        let writer = self.writer.as_mut()?;
+        if let Some(archived) = self.measurements.next() {
+            let (file, data) = match self.format {
+                ExportFormat::Csv => (
+                    format!("salsa-{}.csv", archived.id),
+                    measurement_csv(&archived).into_bytes(),
+                ),
+                ExportFormat::Fits => (
+                    format!("salsa-{}.fits", archived.id),
+                    measurement_fits(
+                        &archived,
+                        self.locations.get(&archived.telescope_name).copied(),
+                    ),
+                ),
+            };
+            let part = writer.file(&file, &data, archived.measurement.start);
+            self.manifest.push(ManifestEntry {
+                file,
+                summary: archived.summary(),
+                integration_time: archived.measurement.integration_time,
+                system_temperature: archived.measurement.system_temperature,
+                channels: archived.measurement.amps.len(),
+            });
+            return Some(part);
+        }
+        let manifest = serde_json::to_vec_pretty(&self.manifest)
+            .expect("The manifest can always be serialized");
+        let mut writer = self.writer.take()?;
+        let mut part = writer.file(MANIFEST_FILE_NAME, &manifest, Utc::now());
+        part.extend(writer.finish());
+        Some(part)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::angles::Degrees;
+    use crate::archive::test_utils::galactic_measurement;
+    use chrono::TimeZone;
+
+    fn u16_at(data: &[u8], offset: usize) -> u16 {
+        u16::from_le_bytes(data[offset..offset + 2].try_into().unwrap())
+    }
+
+    fn u32_at(data: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn test_export_zip() {
+        let start = Utc.with_ymd_and_hms(2024, 3, 1, 13, 0, 0).unwrap();
+        let measurements: Vec<ArchivedMeasurement> = (1..=2)
+            .map(|id| {
+                let mut measurement = galactic_measurement(Degrees(30.0), start);
+                measurement.freqs = vec![1.42e9, 1.4201e9];
+                measurement.amps = vec![1.0, 2.5];
+                ArchivedMeasurement::new(id, "brage", measurement)
+            })
+            .collect();
+        let zip: Vec<u8> = ZipExport::new(measurements, ExportFormat::Csv, HashMap::new())
+            .flatten()
+            .collect();
+
+        // The first file starts the ZIP file, with its name and contents.
+        assert_eq!(u32_at(&zip, 0), LOCAL_FILE_HEADER_SIGNATURE);
+        let name_length = u16_at(&zip, 26) as usize;
+        let size = u32_at(&zip, 18) as usize;
+        assert_eq!(&zip[30..30 + name_length], b"salsa-1.csv");
+        let csv = std::str::from_utf8(&zip[30 + name_length..30 + name_length + size]).unwrap();
+        assert!(csv.starts_with("# SALSA measurement 1 with brage\n"));
+        assert!(csv.ends_with("frequency,amplitude\n1420000000,1\n1420100000,2.5\n"));
+        assert_eq!(u32_at(&zip, 14), crc32(csv.as_bytes()));
+
+        // The end record counts both measurements and the manifest, and
+        // points at the central directory.
+        let end = zip.len() - 22;
+        assert_eq!(u32_at(&zip, end), END_OF_CENTRAL_DIRECTORY_SIGNATURE);
+        assert_eq!(u16_at(&zip, end + 10), 3);
+        let directory = u32_at(&zip, end + 16) as usize;
+        assert_eq!(u32_at(&zip, directory), CENTRAL_DIRECTORY_SIGNATURE);
+        assert_eq!(directory + u32_at(&zip, end + 12) as usize, end);
+        let manifest = String::from_utf8_lossy(&zip);
+        assert!(manifest.contains(MANIFEST_FILE_NAME));
+        assert!(manifest.contains("\"file\": \"salsa-2.csv\""));
+    }
+
+    #[test]
+    fn test_export_query() {
+        let start = Utc.with_ymd_and_hms(2024, 3, 1, 13, 0, 0).unwrap();
+        let archived =
+            ArchivedMeasurement::new(1, "brage", galactic_measurement(Degrees(30.0), start));
+        assert!(ExportQuery::default().includes(&archived));
+        let query = ExportQuery {
+            from: Some(start - chrono::Duration::hours(1)),
+            to: Some(start + chrono::Duration::hours(1)),
+            telescope: Some("brage".to_string()),
+            format: None,
+        };
+        assert!(query.includes(&archived));
+        let other = ExportQuery {
+            telescope: Some("vale".to_string()),
+            ..query.clone()
+        };
+        assert!(!other.includes(&archived));
+        let later = ExportQuery {
+            from: Some(start + chrono::Duration::minutes(1)),
+            ..query
+        };
+        assert!(!later.includes(&archived));
+    }
+}
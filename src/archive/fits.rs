@@ -0,0 +1,310 @@
+//! Archived measurements as FITS files, for opening in standard astronomy
+//! tools.
+//!
+//! The spectrum is the primary array, 32 bit floats on a linear frequency
+//! axis. Where the telescope pointed and the velocity correction to the LSR
+//! are given in the header.
+
+use crate::angles::Radians;
+use crate::archive::ArchivedMeasurement;
+use crate::constants::HI_REST_FREQUENCY;
+use crate::coords::{
+    equatorial_from_galactic, horizontal_from_equatorial, vlsrcorr_from_equatorial, Location,
+};
+use crate::telescopes::TelescopeTarget;
+
+pub const FITS_CONTENT_TYPE: &str = "application/fits";
+
+const BLOCK_SIZE: usize = 2880;
+const CARD_SIZE: usize = 80;
+/// Longest string value, so that it fits on a card even if every
+/// character is a quote that has to be doubled.
+const MAX_TEXT_LENGTH: usize = 34;
+
+enum Value {
+    Logical(bool),
+    Integer(i64),
+    Real(f64),
+    Text(String),
+}
+
+impl Value {
+    /// The value as written from column 11 of a card. Numbers and logicals
+    /// end in column 30, as the fixed format asks for.
+    fn format(&self) -> String {
+        match self {
+            Value::Logical(value) => format!("{:>20}", if *value { "T" } else { "F" }),
+            Value::Integer(value) => format!("{:>20}", value),
+            Value::Real(value) => {
+                // A real needs a decimal point, which is left out for whole
+                // numbers.
+                let text = format!("{:E}", value);
+                let text = if text.contains('.') {
+                    text
+                } else {
+                    text.replacen('E', ".0E", 1)
+                };
+                format!("{:>20}", text)
+            }
+            Value::Text(value) => {
+                let value: String = ascii(value).chars().take(MAX_TEXT_LENGTH).collect();
+                format!("'{:<8}'", value.replace('\'', "''"))
+            }
+        }
+    }
+}
+
+/// Header cards can only hold printable ASCII.
+fn ascii(text: &str) -> String {
+    text.chars()
+        .map(|c| if (' '..='~').contains(&c) { c } else { '?' })
+        .collect()
+}
+
+#[derive(Default)]
+struct Header {
+    cards: Vec<String>,
+}
+
+impl Header {
+    fn card(&mut self, keyword: &str, value: Value, comment: &str) {
+        // FITS has no representation of NaN or infinity in headers.
+        if let Value::Real(value) = value {
+            if !value.is_finite() {
+                return;
+            }
+        }
+        let mut card = format!("{:<8}= {}", keyword, value.format());
+        if !comment.is_empty() {
+            card.push_str(" / ");
+            card.push_str(&ascii(comment));
+        }
+        card.truncate(CARD_SIZE);
+        self.cards.push(card);
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for card in self.cards.iter().map(String::as_str).chain(["END"]) {
+            bytes.extend(format!("{:<80}", card).bytes());
+        }
+        pad_to_block(&mut bytes, b' ');
+        bytes
+    }
+}
+
+fn pad_to_block(bytes: &mut Vec<u8>, fill: u8) {
+    let padding = (BLOCK_SIZE - bytes.len() % BLOCK_SIZE) % BLOCK_SIZE;
+    bytes.resize(bytes.len() + padding, fill);
+}
+
+/// Direction of `target` in equatorial coordinates, if it is on the sky.
+fn equatorial(target: TelescopeTarget) -> Option<(Radians, Radians)> {
+    match target {
+        TelescopeTarget::Equatorial { ra, dec } => Some((ra, dec)),
+        TelescopeTarget::Galactic { l, b } => Some(equatorial_from_galactic(l, b)),
+        TelescopeTarget::Horizontal { .. }
+        | TelescopeTarget::Satellite { .. }
+        | TelescopeTarget::Parked
+        | TelescopeTarget::Stopped => None,
+    }
+}
+
+/// `archived` as a FITS file. The direction of the telescope is included
+/// if its `location` is known.
+pub fn measurement_fits(archived: &ArchivedMeasurement, location: Option<Location>) -> Vec<u8> {
+    let measurement = &archived.measurement;
+    let freqs = &measurement.freqs;
+    let channel_width = match (freqs.first(), freqs.last()) {
+        (Some(first), Some(last)) if freqs.len() > 1 => (last - first) / (freqs.len() - 1) as f64,
+        _ => 0.0,
+    };
+
+    let mut header = Header::default();
+    header.card("SIMPLE", Value::Logical(true), "conforms to FITS standard");
+    header.card("BITPIX", Value::Integer(-32), "32 bit floats");
+    header.card("NAXIS", Value::Integer(1), "");
+    header.card(
+        "NAXIS1",
+        Value::Integer(measurement.amps.len() as i64),
+        "number of channels",
+    );
+    header.card("BUNIT", Value::Text("K".to_string()), "antenna temperature");
+    header.card("CTYPE1", Value::Text("FREQ".to_string()), "");
+    header.card("CUNIT1", Value::Text("Hz".to_string()), "");
+    header.card("CRPIX1", Value::Real(1.0), "reference channel");
+    header.card(
+        "CRVAL1",
+        Value::Real(freqs.first().copied().unwrap_or(0.0)),
+        "frequency of reference channel",
+    );
+    header.card("CDELT1", Value::Real(channel_width), "channel width");
+    header.card("SPECSYS", Value::Text("TOPOCENT".to_string()), "");
+    header.card("RESTFREQ", Value::Real(HI_REST_FREQUENCY), "[Hz] HI line");
+
+    header.card("TELESCOP", Value::Text(archived.telescope_name.clone()), "");
+    header.card("OBJECT", Value::Text(measurement.target.to_string()), "");
+    header.card(
+        "SALSAID",
+        Value::Integer(archived.id as i64),
+        "id in the SALSA archive",
+    );
+    header.card(
+        "DATE-OBS",
+        Value::Text(
+            measurement
+                .start
+                .format("%Y-%m-%dT%H:%M:%S%.3f")
+                .to_string(),
+        ),
+        "start of observation (UTC)",
+    );
+    header.card(
+        "OBSTIME",
+        Value::Real(measurement.duration.as_secs_f64()),
+        "[s] duration of observation",
+    );
+    header.card(
+        "EXPTIME",
+        Value::Real(measurement.integration_time.as_secs_f64()),
+        "[s] time covered by samples",
+    );
+    if let Some(system_temperature) = measurement.system_temperature {
+        header.card(
+            "TSYS",
+            Value::Real(system_temperature),
+            "[K] system temperature",
+        );
+    }
+
+    if let TelescopeTarget::Galactic { l, b } = measurement.target {
+        header.card(
+            "GLON",
+            Value::Real(l.normalized().to_degrees().0),
+            "[deg] galactic longitude",
+        );
+        header.card(
+            "GLAT",
+            Value::Real(b.to_degrees().0),
+            "[deg] galactic latitude",
+        );
+    }
+    if let Some((ra, dec)) = equatorial(measurement.target) {
+        header.card("EQUINOX", Value::Real(2000.0), "");
+        header.card(
+            "RA",
+            Value::Real(ra.normalized().to_degrees().0),
+            "[deg] right ascension",
+        );
+        header.card("DEC", Value::Real(dec.to_degrees().0), "[deg] declination");
+        header.card(
+            "VLSR",
+            Value::Real(vlsrcorr_from_equatorial(ra, dec, measurement.start) / 1e3),
+            "[km/s] add to velocities for LSR",
+        );
+        if let Some(location) = location {
+            let horizontal = horizontal_from_equatorial(location, measurement.start, ra, dec);
+            header.card(
+                "SITELONG",
+                Value::Real(location.longitude.to_degrees().0),
+                "[deg] east longitude of telescope",
+            );
+            header.card(
+                "SITELAT",
+                Value::Real(location.latitude.to_degrees().0),
+                "[deg] latitude of telescope",
+            );
+            header.card(
+                "AZIMUTH",
+                Value::Real(horizontal.azimuth.normalized().to_degrees().0),
+                "[deg] at start of observation",
+            );
+            header.card(
+                "ELEVATIO",
+                Value::Real(horizontal.altitude.to_degrees().0),
+                "[deg] at start of observation",
+            );
+        }
+    }
+
+    if let TelescopeTarget::Horizontal { azimuth, elevation } = measurement.target {
+        header.card(
+            "AZIMUTH",
+            Value::Real(azimuth.normalized().to_degrees().0),
+            "[deg] fixed during observation",
+        );
+        header.card(
+            "ELEVATIO",
+            Value::Real(elevation.to_degrees().0),
+            "[deg] fixed during observation",
+        );
+    }
+
+    let mut bytes = header.to_bytes();
+    for amp in &measurement.amps {
+        bytes.extend((*amp as f32).to_be_bytes());
+    }
+    pad_to_block(&mut bytes, 0);
+    bytes
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::angles::Degrees;
+    use crate::archive::test_utils::galactic_measurement;
+    use chrono::TimeZone;
+    use chrono::Utc;
+
+    fn card_value<'a>(fits: &'a [u8], keyword: &str) -> Option<&'a str> {
+        fits[..BLOCK_SIZE]
+            .chunks(CARD_SIZE)
+            .map(|card| std::str::from_utf8(card).unwrap())
+            .find(|card| card[..8].trim_end() == keyword)
+            .map(|card| card[10..].split(" / ").next().unwrap().trim())
+    }
+
+    #[test]
+    fn test_measurement_fits() {
+        let start = Utc.with_ymd_and_hms(2024, 3, 1, 20, 0, 0).unwrap();
+        let measurement = galactic_measurement(Degrees(120.0), start);
+        let archived = ArchivedMeasurement::new(7, "Bro's telescope", measurement.clone());
+        let onsala = Location {
+            longitude: Degrees(11.92).to_radians(),
+            latitude: Degrees(57.39).to_radians(),
+        };
+        let fits = measurement_fits(&archived, Some(onsala));
+
+        assert_eq!(fits.len() % BLOCK_SIZE, 0);
+        assert!(fits.starts_with(format!("{:<8}= {:>20}", "SIMPLE", "T").as_bytes()));
+        let channels = measurement.amps.len();
+        assert_eq!(
+            card_value(&fits, "NAXIS1"),
+            Some(channels.to_string().as_str())
+        );
+        assert_eq!(card_value(&fits, "TELESCOP"), Some("'Bro''s telescope'"));
+        let glon: f64 = card_value(&fits, "GLON").unwrap().parse().unwrap();
+        assert!((glon - 120.0).abs() < 1e-9);
+        assert_eq!(
+            card_value(&fits, "DATE-OBS"),
+            Some("'2024-03-01T20:00:00.000'")
+        );
+        for keyword in ["CRVAL1", "CDELT1", "VLSR", "AZIMUTH", "ELEVATIO"] {
+            let value: f64 = card_value(&fits, keyword).unwrap().parse().unwrap();
+            assert!(value.is_finite(), "{}", keyword);
+        }
+        let crval: f64 = card_value(&fits, "CRVAL1").unwrap().parse().unwrap();
+        assert_eq!(crval, measurement.freqs[0]);
+
+        // The data follow the header, big endian.
+        let end = fits
+            .chunks(CARD_SIZE)
+            .position(|card| card.starts_with(b"END "))
+            .unwrap();
+        let data = BLOCK_SIZE * ((end * CARD_SIZE) / BLOCK_SIZE + 1);
+        for (i, amp) in measurement.amps.iter().enumerate() {
+            let bytes = &fits[data + 4 * i..data + 4 * i + 4];
+            assert_eq!(f32::from_be_bytes(bytes.try_into().unwrap()), *amp as f32);
+        }
+    }
+}
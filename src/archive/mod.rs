@@ -0,0 +1,280 @@
+use crate::angles::Degrees;
+use crate::archive::column_density::{column_density, ColumnDensity};
+use crate::archive::files::{create_file_store, record_measurement_files, write_measurement_file};
+use crate::archive::provenance::{column_density_provenance, Provenance};
+use crate::archive::quality::{assess_quality, QualityAssessment, QualityGrade};
+use crate::archive::reprocessing::SupersededProducts;
+use crate::archive::thumbnails::write_thumbnail;
+use crate::clock::clock_offset_during;
+use crate::database::{DataBase, DataBaseError, Storage};
+use crate::telescopes::{Measurement, ObservedSpectra, TelescopeTarget};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+pub mod api_routes;
+pub mod checkpoints;
+pub mod column_density;
+pub mod duplicates;
+pub mod export;
+pub mod files;
+pub mod fits;
+pub mod monitoring;
+pub mod provenance;
+pub mod quality;
+pub mod reprocessing;
+pub mod rotation_curve;
+pub mod routes;
+pub mod thumbnails;
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct ArchivedMeasurement {
+    pub id: u64,
+    pub telescope_name: String,
+    pub measurement: Measurement,
+    /// Estimated when the measurement was archived, if it covers the HI
+    /// line.
+    #[serde(default)]
+    pub column_density: Option<ColumnDensity>,
+    /// How the column density was computed. Missing for measurements
+    /// archived before provenance was recorded.
+    #[serde(default)]
+    pub provenance: Option<Provenance>,
+    /// Derived products replaced by reprocessing, oldest first.
+    #[serde(default)]
+    pub superseded: Vec<SupersededProducts>,
+    /// Largest offset of the system clock, in seconds, if it was out of
+    /// tolerance while the measurement was taken.
+    #[serde(default)]
+    pub clock_offset: Option<f64>,
+    /// Assessed when the measurement was archived. Missing for older
+    /// measurements and those too short to tell.
+    #[serde(default)]
+    pub quality: Option<QualityAssessment>,
+    /// Key of the data file in the file store, once it has been written.
+    /// The spectra are then only kept in the file, see [files::load_spectra].
+    #[serde(default)]
+    pub file_key: Option<String>,
+}
+
+/// Everything about an archived measurement except the spectrum itself.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct ArchivedMeasurementSummary {
+    pub id: u64,
+    pub telescope_name: String,
+    pub target: TelescopeTarget,
+    pub start: DateTime<Utc>,
+    pub duration: Duration,
+    pub interrupted: bool,
+    #[serde(default)]
+    pub quality: Option<QualityGrade>,
+}
+
+impl ArchivedMeasurement {
+    /// Archive entry for a measurement, with the analysis that is done on
+    /// all measurements.
+    pub fn new(id: u64, telescope_name: &str, measurement: Measurement) -> ArchivedMeasurement {
+        let column_density = column_density(&measurement);
+        let quality = assess_quality(&measurement);
+        ArchivedMeasurement {
+            id,
+            telescope_name: telescope_name.to_string(),
+            provenance: column_density
+                .as_ref()
+                .map(|c| column_density_provenance(id, c)),
+            column_density,
+            measurement,
+            superseded: Vec::new(),
+            clock_offset: None,
+            quality,
+            file_key: None,
+        }
+    }
+
+    pub fn summary(&self) -> ArchivedMeasurementSummary {
+        ArchivedMeasurementSummary {
+            id: self.id,
+            telescope_name: self.telescope_name.clone(),
+            target: self.measurement.target,
+            start: self.measurement.start,
+            duration: self.measurement.duration,
+            interrupted: self.measurement.interrupted,
+            quality: self.quality.as_ref().map(|q| q.grade),
+        }
+    }
+
+    pub fn spectrum(&self) -> ObservedSpectra {
+        ObservedSpectra {
+            frequencies: self.measurement.freqs.clone(),
+            spectra: self.measurement.amps.clone(),
+            observation_time: self.measurement.duration,
+            cycles: self.measurement.cycles,
+        }
+    }
+
+    /// Galactic coordinates (l, b) of the observation in degrees, if the
+    /// measurement was made towards a galactic target.
+    pub fn galactic_position(&self) -> Option<(Degrees, Degrees)> {
+        match self.measurement.target {
+            TelescopeTarget::Galactic { l, b } => Some((
+                l.normalized().to_degrees(),
+                Degrees(b.to_degrees().0.clamp(-90.0, 90.0)),
+            )),
+            _ => None,
+        }
+    }
+}
+
+/// Store a completed measurement in the archive.
+///
+/// Returns the id assigned to the archived measurement.
+pub async fn archive_measurement(
+    db: &DataBase<impl Storage>,
+    telescope_name: &str,
+    measurement: Measurement,
+) -> Result<u64, DataBaseError> {
+    let mut id = 0;
+    let mut file = None;
+    db.update_data(|mut data_model| {
+        // Trashed measurements keep their ids, so that they can be restored.
+        id = data_model
+            .measurements
+            .iter()
+            .map(|m| m.id)
+            .chain(data_model.trash.iter().filter_map(|t| t.measurement_id()))
+            .map(|id| id + 1)
+            .max()
+            .unwrap_or(1);
+        let end = measurement.start
+            + chrono::Duration::from_std(measurement.duration).unwrap_or(chrono::Duration::zero());
+        let clock_offset = clock_offset_during(&data_model, measurement.start, end);
+        let archived = ArchivedMeasurement {
+            clock_offset,
+            ..ArchivedMeasurement::new(id, telescope_name, measurement)
+        };
+        file = data_model
+            .file_storage
+            .clone()
+            .map(|definition| (definition, archived.clone()));
+        data_model.measurements.push(archived);
+        data_model
+    })
+    .await?;
+    // The measurement is safe in the database, so a failure to write the
+    // file is only logged. The spectra are then kept in the database.
+    if let Some((definition, archived)) = file {
+        let store = create_file_store(&definition);
+        match write_measurement_file(&*store, &archived).await {
+            Ok(checksum) => record_measurement_files(db, vec![checksum]).await?,
+            Err(error) => log::error!("Failed to write file of measurement {}: {}", id, error),
+        }
+        if let Err(error) = write_thumbnail(&*store, &archived).await {
+            log::error!("Failed to write thumbnail of measurement {}: {}", id, error);
+        }
+    }
+    log::info!(
+        "Archived measurement {} from telescope {}",
+        id,
+        telescope_name
+    );
+    Ok(id)
+}
+
+/// Pick the most recent galactic measurement for each whole degree of
+/// galactic longitude, sorted by longitude.
+pub fn latest_per_longitude(measurements: &[ArchivedMeasurement]) -> Vec<&ArchivedMeasurement> {
+    let mut latest = BTreeMap::<i64, &ArchivedMeasurement>::new();
+    for measurement in measurements {
+        if let Some((l, _)) = measurement.galactic_position() {
+            let longitude_bin = l.0.round() as i64 % 360;
+            let entry = latest.entry(longitude_bin).or_insert(measurement);
+            if measurement.measurement.start > entry.measurement.start {
+                *entry = measurement;
+            }
+        }
+    }
+    latest.into_values().collect()
+}
+
+#[cfg(test)]
+pub mod test_utils {
+    use super::*;
+    use crate::angles::Radians;
+
+    pub fn galactic_measurement(l: Degrees, start: DateTime<Utc>) -> Measurement {
+        Measurement {
+            amps: vec![1.0, 2.0, 3.0],
+            freqs: vec![1.4200e9, 1.4201e9, 1.4202e9],
+            target: TelescopeTarget::Galactic {
+                l: l.to_radians(),
+                b: Radians(0.0),
+            },
+            start,
+            duration: Duration::from_secs(60),
+            window: Default::default(),
+            integration_time: Duration::from_secs(60),
+            requested_integration_time: Duration::from_secs(60),
+            dropped_samples: 0,
+            system_temperature: None,
+            interrupted: false,
+            receiver_configuration: None,
+            continuum: Vec::new(),
+            max_tracking_error: None,
+            cycles: 60,
+            polarizations: vec![],
+            flagged: vec![],
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::test_utils::galactic_measurement;
+    use super::*;
+    use crate::database::create_in_memory_database;
+
+    #[tokio::test]
+    async fn test_archive_measurement_assigns_increasing_ids() {
+        let db = create_in_memory_database();
+        let first =
+            archive_measurement(&db, "fake", galactic_measurement(Degrees(30.0), Utc::now()))
+                .await
+                .unwrap();
+        let second =
+            archive_measurement(&db, "fake", galactic_measurement(Degrees(40.0), Utc::now()))
+                .await
+                .unwrap();
+        assert_eq!(first, 1);
+        assert_eq!(second, 2);
+        let ids: Vec<u64> = db
+            .get_data()
+            .await
+            .unwrap()
+            .measurements
+            .iter()
+            .map(|m| m.id)
+            .collect();
+        assert_eq!(ids, vec![1, 2]);
+    }
+
+    #[test]
+    fn test_latest_per_longitude() {
+        let now = Utc::now();
+        let earlier = now - chrono::Duration::hours(1);
+        let archived = |id, l, start| {
+            ArchivedMeasurement::new(id, "fake", galactic_measurement(Degrees(l), start))
+        };
+        let measurements = vec![
+            archived(1, 40.0, now),
+            archived(2, 30.0, earlier),
+            archived(3, 30.2, now),
+            archived(4, 359.9, now),
+        ];
+        let ids: Vec<u64> = latest_per_longitude(&measurements)
+            .iter()
+            .map(|m| m.id)
+            .collect();
+        assert_eq!(ids, vec![4, 3, 1]);
+    }
+}
@@ -0,0 +1,133 @@
+use crate::angles::Radians;
+use crate::archive::ArchivedMeasurement;
+use crate::coords::angular_separation;
+use crate::telescopes::{Measurement, TelescopeTarget};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Spectral statistics of a single archived measurement of a monitored
+/// target.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct MonitoringPoint {
+    pub id: u64,
+    pub telescope_name: String,
+    pub start: DateTime<Utc>,
+    /// Highest channel value above the median baseline.
+    pub peak_amplitude: f64,
+    /// Integral over frequency (in Hz) of the spectrum above the median
+    /// baseline.
+    pub integrated_area: f64,
+}
+
+/// Peak amplitude and integrated area of a spectrum, both measured relative
+/// to the median of the spectrum which is used as a crude baseline.
+pub fn spectrum_statistics(measurement: &Measurement) -> Option<(f64, f64)> {
+    let channels = measurement.amps.len().min(measurement.freqs.len());
+    if channels < 2 {
+        return None;
+    }
+    let mut sorted = measurement.amps[..channels].to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let baseline = sorted[channels / 2];
+
+    let peak_amplitude = sorted[channels - 1] - baseline;
+    let integrated_area = measurement.freqs[..channels]
+        .windows(2)
+        .zip(measurement.amps[..channels].windows(2))
+        .map(|(freqs, amps)| {
+            let width = (freqs[1] - freqs[0]).abs();
+            let height = 0.5 * ((amps[0] - baseline) + (amps[1] - baseline));
+            width * height.max(0.0)
+        })
+        .sum();
+    Some((peak_amplitude, integrated_area))
+}
+
+/// Check if a measurement was made towards `target`, allowing for a pointing
+/// difference of `tolerance`.
+pub fn is_same_target(
+    measured: TelescopeTarget,
+    target: TelescopeTarget,
+    tolerance: Radians,
+) -> bool {
+    match (measured, target) {
+        (
+            TelescopeTarget::Equatorial { ra: ra1, dec: dec1 },
+            TelescopeTarget::Equatorial { ra: ra2, dec: dec2 },
+        ) => angular_separation(ra1, dec1, ra2, dec2) <= tolerance,
+        (
+            TelescopeTarget::Galactic { l: l1, b: b1 },
+            TelescopeTarget::Galactic { l: l2, b: b2 },
+        ) => angular_separation(l1, b1, l2, b2) <= tolerance,
+        _ => false,
+    }
+}
+
+/// Collect the spectral statistics of all measurements of `target`, sorted
+/// by observation time.
+pub fn monitor_target<'a>(
+    measurements: impl IntoIterator<Item = &'a ArchivedMeasurement>,
+    target: TelescopeTarget,
+    tolerance: Radians,
+    telescope_name: Option<&str>,
+) -> Vec<MonitoringPoint> {
+    let mut points: Vec<MonitoringPoint> = measurements
+        .into_iter()
+        .filter(|m| telescope_name.is_none_or(|name| m.telescope_name == name))
+        .filter(|m| is_same_target(m.measurement.target, target, tolerance))
+        .filter_map(|m| {
+            let (peak_amplitude, integrated_area) = spectrum_statistics(&m.measurement)?;
+            Some(MonitoringPoint {
+                id: m.id,
+                telescope_name: m.telescope_name.clone(),
+                start: m.measurement.start,
+                peak_amplitude,
+                integrated_area,
+            })
+        })
+        .collect();
+    points.sort_by_key(|p| p.start);
+    points
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::angles::Degrees;
+    use crate::archive::test_utils::galactic_measurement;
+
+    #[test]
+    fn test_spectrum_statistics() {
+        let mut measurement = galactic_measurement(Degrees(30.0), Utc::now());
+        measurement.freqs = vec![0.0, 1.0, 2.0, 3.0, 4.0];
+        measurement.amps = vec![1.0, 1.0, 3.0, 1.0, 1.0];
+        let (peak, area) = spectrum_statistics(&measurement).unwrap();
+        assert_eq!(peak, 2.0);
+        // Triangle with base 2 Hz and height 2
+        assert_eq!(area, 2.0);
+    }
+
+    #[test]
+    fn test_monitor_target() {
+        let now = Utc::now();
+        let archived = |id, l, start| {
+            ArchivedMeasurement::new(id, "fake", galactic_measurement(Degrees(l), start))
+        };
+        let measurements = vec![
+            archived(1, 30.0, now),
+            archived(2, 30.1, now - chrono::Duration::days(30)),
+            archived(3, 45.0, now),
+        ];
+        let target = TelescopeTarget::Galactic {
+            l: Degrees(30.0).to_radians(),
+            b: Radians(0.0),
+        };
+        let tolerance = Degrees(0.5).to_radians();
+        let ids: Vec<u64> = monitor_target(&measurements, target, tolerance, None)
+            .iter()
+            .map(|p| p.id)
+            .collect();
+        assert_eq!(ids, vec![2, 1]);
+        assert!(monitor_target(&measurements, target, tolerance, Some("brage")).is_empty());
+    }
+}
@@ -0,0 +1,138 @@
+//! Provenance of derived data products.
+//!
+//! Every product derived from archived measurements, such as a column
+//! density or the rotation curve, can be described by the measurements it
+//! was computed from, the processing stages with their parameters and the
+//! revision of the software that did it. That is enough to trace a plot
+//! back to the raw spectra and to compute it again.
+
+use crate::archive::column_density::{ColumnDensity, LINE_FREE_VELOCITY};
+use crate::archive::rotation_curve::{
+    DETECTION_THRESHOLD, MAX_GALACTIC_LATITUDE, SOLAR_GALACTOCENTRIC_DISTANCE, SOLAR_ROTATION_SPEED,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+
+/// Git revision the backend was built from.
+pub const SOFTWARE_VERSION: &str = env!("SALSA_GIT_HASH");
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct ProcessingStage {
+    pub name: String,
+    pub parameters: BTreeMap<String, Value>,
+}
+
+impl ProcessingStage {
+    pub fn new<const N: usize>(name: &str, parameters: [(&str, Value); N]) -> ProcessingStage {
+        ProcessingStage {
+            name: name.to_string(),
+            parameters: parameters
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value))
+                .collect(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Provenance {
+    /// Ids of the archived measurements the product was computed from.
+    pub inputs: Vec<u64>,
+    /// Processing stages, in the order they were applied.
+    pub stages: Vec<ProcessingStage>,
+    pub software_version: String,
+}
+
+impl Provenance {
+    pub fn new(inputs: Vec<u64>, stages: Vec<ProcessingStage>) -> Provenance {
+        Provenance {
+            inputs,
+            stages,
+            software_version: SOFTWARE_VERSION.to_string(),
+        }
+    }
+}
+
+fn lsr_velocity_stage() -> ProcessingStage {
+    ProcessingStage::new("lsr_velocity", [("line", json!("HI"))])
+}
+
+pub fn column_density_provenance(id: u64, column_density: &ColumnDensity) -> Provenance {
+    Provenance::new(
+        vec![id],
+        vec![
+            lsr_velocity_stage(),
+            ProcessingStage::new(
+                "baseline",
+                [
+                    ("fit", json!(column_density.baseline)),
+                    ("line_free_velocity", json!(LINE_FREE_VELOCITY)),
+                ],
+            ),
+            ProcessingStage::new(
+                "brightness_temperature",
+                [
+                    (
+                        "system_temperature",
+                        json!(column_density.system_temperature),
+                    ),
+                    (
+                        "assumed_system_temperature",
+                        json!(column_density.assumed_system_temperature),
+                    ),
+                ],
+            ),
+            ProcessingStage::new(
+                "integrated_intensity",
+                [("velocity_range", json!(LINE_FREE_VELOCITY))],
+            ),
+            ProcessingStage::new("optically_thin_column_density", []),
+        ],
+    )
+}
+
+pub fn rotation_curve_provenance(inputs: Vec<u64>) -> Provenance {
+    Provenance::new(
+        inputs,
+        vec![
+            ProcessingStage::new(
+                "latest_per_longitude",
+                [("max_galactic_latitude", json!(MAX_GALACTIC_LATITUDE.0))],
+            ),
+            lsr_velocity_stage(),
+            ProcessingStage::new(
+                "terminal_velocity",
+                [
+                    ("baseline", json!("median")),
+                    ("detection_threshold", json!(DETECTION_THRESHOLD)),
+                ],
+            ),
+            ProcessingStage::new(
+                "tangent_point",
+                [
+                    (
+                        "solar_galactocentric_distance",
+                        json!(SOLAR_GALACTOCENTRIC_DISTANCE),
+                    ),
+                    ("solar_rotation_speed", json!(SOLAR_ROTATION_SPEED)),
+                ],
+            ),
+        ],
+    )
+}
+
+/// Provenance of a monitoring series, `selection` being the parameters the
+/// measurements were selected with.
+pub fn monitoring_provenance<const N: usize>(
+    inputs: Vec<u64>,
+    selection: [(&str, Value); N],
+) -> Provenance {
+    Provenance::new(
+        inputs,
+        vec![
+            ProcessingStage::new("target_selection", selection),
+            ProcessingStage::new("spectrum_statistics", [("baseline", json!("median"))]),
+        ],
+    )
+}
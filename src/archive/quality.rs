@@ -0,0 +1,215 @@
+//! Automatic assessment of the quality of a measurement, done when it is
+//! archived, so that students know when an observation should be repeated.
+//!
+//! The noise of the spectrum is compared with what the radiometer equation
+//! predicts for the channel width and integration time. Narrow spikes, most
+//! likely interference, are flagged, and the largest tracking error during
+//! the integration is checked if the telescope recorded it.
+
+use crate::angles::{Degrees, Radians};
+use crate::constants::DEFAULT_SYSTEM_TEMPERATURE;
+use crate::telescopes::{Measurement, SwitchingMode};
+use serde::{Deserialize, Serialize};
+
+/// Noise this many times the radiometer expectation is suspicious.
+pub const MAX_NOISE_RATIO: f64 = 3.0;
+/// Channels further than this many times the noise from their neighbours
+/// are flagged.
+pub const SPIKE_THRESHOLD: f64 = 5.0;
+/// Channels on each side in the running median the spikes are found from.
+pub const SPIKE_WINDOW: usize = 3;
+/// More flagged channels than this fraction is suspicious.
+pub const MAX_FLAGGED_FRACTION: f64 = 0.05;
+/// Largest tracking error during an integration that is not suspicious.
+pub const MAX_TRACKING_ERROR: Degrees = Degrees(0.5);
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+pub enum QualityGrade {
+    Good,
+    /// Something looks wrong, see the issues, and the observation should
+    /// probably be repeated.
+    Suspicious,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+pub enum QualityIssue {
+    HighNoise,
+    ManyFlaggedChannels,
+    TrackingError,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct QualityAssessment {
+    /// Noise in the units of the spectrum, from differences between
+    /// neighbouring channels.
+    pub rms_noise: f64,
+    /// Noise predicted by the radiometer equation, in the same units.
+    pub expected_rms_noise: f64,
+    pub flagged_fraction: f64,
+    /// Largest tracking error while integrating, if it was recorded.
+    pub max_tracking_error: Option<Degrees>,
+    pub grade: QualityGrade,
+    pub issues: Vec<QualityIssue>,
+}
+
+fn median(values: &[f64]) -> f64 {
+    let mut sorted = values.to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    sorted[sorted.len() / 2]
+}
+
+/// Noise of a spectrum, insensitive to the baseline, to lines spanning
+/// many channels and to spikes. The median absolute deviation is scaled to
+/// a standard deviation assuming Gaussian noise.
+fn channel_noise(amps: &[f64]) -> f64 {
+    let differences: Vec<f64> = amps.windows(2).map(|w| w[1] - w[0]).collect();
+    let center = median(&differences);
+    let deviations: Vec<f64> = differences.iter().map(|d| (d - center).abs()).collect();
+    // Each difference holds the noise of two channels.
+    1.4826 * median(&deviations) / 2.0f64.sqrt()
+}
+
+/// Channels standing out from the running median of their neighbours by
+/// more than `threshold` times `noise`.
+fn flag_spikes(amps: &[f64], noise: f64, threshold: f64) -> Vec<bool> {
+    (0..amps.len())
+        .map(|i| {
+            let window =
+                &amps[i.saturating_sub(SPIKE_WINDOW)..(i + SPIKE_WINDOW + 1).min(amps.len())];
+            (amps[i] - median(window)).abs() > threshold * noise
+        })
+        .collect()
+}
+
+/// Noise predicted by the radiometer equation for the channel width and
+/// integration time of `measurement`.
+///
+/// Total power spectra are at the level of the system temperature, in
+/// whatever units they have. Switched spectra are differences scaled to K,
+/// with half the time on each side, which doubles the noise. Without a
+/// recorded receiver configuration the units of the spectrum are unknown,
+/// so there is nothing to compare with.
+fn expected_noise(measurement: &Measurement, amps: &[f64]) -> Option<f64> {
+    let channel_width = (measurement.freqs.get(1)? - measurement.freqs.first()?).abs();
+    let integration_time = measurement.integration_time.as_secs_f64();
+    if channel_width <= 0.0 || integration_time <= 0.0 {
+        return None;
+    }
+    let switching = measurement.receiver_configuration?.switching;
+    let (level, factor) = match switching {
+        SwitchingMode::TotalPower => (median(amps), 1.0),
+        SwitchingMode::FrequencySwitched | SwitchingMode::PositionSwitched { .. } => (
+            measurement
+                .system_temperature
+                .unwrap_or(DEFAULT_SYSTEM_TEMPERATURE),
+            2.0,
+        ),
+    };
+    Some(factor * level.abs() / (channel_width * integration_time).sqrt())
+}
+
+/// Assess the quality of a measurement, or None if it has too few channels,
+/// no integration time or no recorded receiver configuration to tell.
+pub fn assess_quality(measurement: &Measurement) -> Option<QualityAssessment> {
+    let channels = measurement.amps.len().min(measurement.freqs.len());
+    if channels < 2 * SPIKE_WINDOW + 1 {
+        return None;
+    }
+    let amps = &measurement.amps[..channels];
+    let rms_noise = channel_noise(amps);
+    let expected_rms_noise = expected_noise(measurement, amps)?;
+    let flags = flag_spikes(amps, rms_noise, SPIKE_THRESHOLD);
+    let flagged_fraction = flags.iter().filter(|&&f| f).count() as f64 / channels as f64;
+    let max_tracking_error = measurement.max_tracking_error.map(Radians::to_degrees);
+
+    let mut issues = Vec::new();
+    if rms_noise > MAX_NOISE_RATIO * expected_rms_noise {
+        issues.push(QualityIssue::HighNoise);
+    }
+    if flagged_fraction > MAX_FLAGGED_FRACTION {
+        issues.push(QualityIssue::ManyFlaggedChannels);
+    }
+    if max_tracking_error.is_some_and(|error| error.0 > MAX_TRACKING_ERROR.0) {
+        issues.push(QualityIssue::TrackingError);
+    }
+    Some(QualityAssessment {
+        rms_noise,
+        expected_rms_noise,
+        flagged_fraction,
+        max_tracking_error,
+        grade: if issues.is_empty() {
+            QualityGrade::Good
+        } else {
+            QualityGrade::Suspicious
+        },
+        issues,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::archive::test_utils::galactic_measurement;
+    use crate::telescopes::ReceiverConfiguration;
+    use chrono::Utc;
+    use rand::SeedableRng;
+    use rand_distr::{Distribution, Normal};
+
+    /// A total power spectrum with 1 MHz channels at a level of 100, with
+    /// the noise of a 100 s integration scaled by `noise_factor`.
+    fn noisy_measurement(noise_factor: f64) -> Measurement {
+        let mut measurement = galactic_measurement(Degrees(30.0), Utc::now());
+        let mut rng = rand::rngs::StdRng::seed_from_u64(1);
+        let noise = Normal::new(0.0, noise_factor * 100.0 / (1e6 * 100.0f64).sqrt()).unwrap();
+        measurement.freqs = (0..256)
+            .map(|channel| 1.4e9 + channel as f64 * 1e6)
+            .collect();
+        measurement.amps = (0..256).map(|_| 100.0 + noise.sample(&mut rng)).collect();
+        measurement.integration_time = std::time::Duration::from_secs(100);
+        measurement.receiver_configuration = Some(ReceiverConfiguration {
+            integrate: true,
+            switching: SwitchingMode::TotalPower,
+            ..ReceiverConfiguration::stopped()
+        });
+        measurement
+    }
+
+    #[test]
+    fn test_assess_quality() {
+        let quality = assess_quality(&noisy_measurement(1.0)).unwrap();
+        assert_eq!(quality.grade, QualityGrade::Good);
+        assert!((quality.rms_noise / quality.expected_rms_noise - 1.0).abs() < 0.35);
+        assert_eq!(quality.max_tracking_error, None);
+
+        let quality = assess_quality(&noisy_measurement(5.0)).unwrap();
+        assert_eq!(quality.issues, vec![QualityIssue::HighNoise]);
+
+        let mut measurement = noisy_measurement(1.0);
+        for channel in (0..256).step_by(16) {
+            measurement.amps[channel] += 10.0;
+        }
+        measurement.max_tracking_error = Some(Degrees(2.0).to_radians());
+        let quality = assess_quality(&measurement).unwrap();
+        assert_eq!(quality.grade, QualityGrade::Suspicious);
+        assert!((quality.flagged_fraction - 16.0 / 256.0).abs() < 1e-9);
+        assert_eq!(
+            quality.issues,
+            vec![
+                QualityIssue::ManyFlaggedChannels,
+                QualityIssue::TrackingError
+            ]
+        );
+    }
+
+    #[test]
+    fn test_assess_quality_without_data() {
+        let mut measurement = galactic_measurement(Degrees(30.0), Utc::now());
+        assert_eq!(assess_quality(&measurement), None);
+        measurement = noisy_measurement(1.0);
+        measurement.integration_time = std::time::Duration::ZERO;
+        assert_eq!(assess_quality(&measurement), None);
+        measurement = noisy_measurement(1.0);
+        measurement.receiver_configuration = None;
+        assert_eq!(assess_quality(&measurement), None);
+    }
+}
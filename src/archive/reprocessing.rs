@@ -0,0 +1,268 @@
+//! Reprocessing of archived measurements.
+//!
+//! When a bug in the analysis is fixed, the derived products of the
+//! affected measurements are computed again from the raw spectra by the
+//! running version of the backend. The products they replace are kept with
+//! the measurement, marked as superseded, so that results published with
+//! them can still be traced.
+
+use crate::archive::column_density::{column_density, ColumnDensity};
+use crate::archive::files::{
+    create_file_store, load_all_spectra, record_measurement_files, write_measurement_file,
+    FileChecksum,
+};
+use crate::archive::provenance::{column_density_provenance, Provenance, SOFTWARE_VERSION};
+use crate::archive::quality::assess_quality;
+use crate::archive::ArchivedMeasurement;
+use crate::database::{DataBase, DataBaseError, Storage};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Derived products of a measurement that were replaced by reprocessing.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct SupersededProducts {
+    pub column_density: Option<ColumnDensity>,
+    pub provenance: Option<Provenance>,
+    pub superseded: DateTime<Utc>,
+    /// Version of the pipeline that replaced them.
+    pub superseded_by: String,
+}
+
+/// Which measurements to reprocess. All given criteria have to match.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Default)]
+pub struct MeasurementSelection {
+    pub measurement_ids: Option<Vec<u64>>,
+    pub telescope_name: Option<String>,
+    /// Only measurements processed by this version of the pipeline, or
+    /// "unknown" for those processed before provenance was recorded.
+    pub processed_by: Option<String>,
+}
+
+impl MeasurementSelection {
+    fn matches(&self, measurement: &ArchivedMeasurement) -> bool {
+        let processed_by = measurement
+            .provenance
+            .as_ref()
+            .map_or("unknown", |p| p.software_version.as_str());
+        self.measurement_ids
+            .as_ref()
+            .is_none_or(|ids| ids.contains(&measurement.id))
+            && self
+                .telescope_name
+                .as_ref()
+                .is_none_or(|name| *name == measurement.telescope_name)
+            && self
+                .processed_by
+                .as_ref()
+                .is_none_or(|version| version == processed_by)
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct ReprocessingRequest {
+    pub selection: MeasurementSelection,
+    /// Version of the pipeline to reprocess with. Only the running version
+    /// can be used, this guards against reprocessing with a backend that
+    /// does not have the fix yet.
+    pub pipeline_version: String,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Default)]
+pub struct ReprocessingReport {
+    /// Measurements whose derived products changed.
+    pub reprocessed: Vec<u64>,
+    /// Measurements that were selected but came out the same.
+    pub unchanged: Vec<u64>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ReprocessingError {
+    /// The requested pipeline version is not the running one.
+    WrongPipelineVersion(String),
+    ServiceUnavailable,
+}
+
+impl From<DataBaseError> for ReprocessingError {
+    fn from(_source: DataBaseError) -> Self {
+        Self::ServiceUnavailable
+    }
+}
+
+/// Compute the derived products of `measurement` again, returning true if
+/// they changed.
+fn reprocess(measurement: &mut ArchivedMeasurement, now: DateTime<Utc>) -> bool {
+    // Earlier quality assessments are not kept among the superseded
+    // products, the latest one simply replaces them.
+    measurement.quality = assess_quality(&measurement.measurement);
+    let new_column_density = column_density(&measurement.measurement);
+    let new_provenance = new_column_density
+        .as_ref()
+        .map(|c| column_density_provenance(measurement.id, c));
+    if new_column_density == measurement.column_density && new_provenance == measurement.provenance
+    {
+        return false;
+    }
+    measurement.superseded.push(SupersededProducts {
+        column_density: std::mem::replace(&mut measurement.column_density, new_column_density),
+        provenance: std::mem::replace(&mut measurement.provenance, new_provenance),
+        superseded: now,
+        superseded_by: SOFTWARE_VERSION.to_string(),
+    });
+    true
+}
+
+/// Reprocess the measurements in `request.selection` and rewrite their data
+/// files.
+pub async fn reprocess_measurements(
+    db: &DataBase<impl Storage>,
+    request: &ReprocessingRequest,
+    now: DateTime<Utc>,
+) -> Result<ReprocessingReport, ReprocessingError> {
+    if request.pipeline_version != SOFTWARE_VERSION {
+        return Err(ReprocessingError::WrongPipelineVersion(
+            SOFTWARE_VERSION.to_string(),
+        ));
+    }
+    let data_model = db.get_data().await?;
+    let selected = data_model
+        .measurements
+        .into_iter()
+        .filter(|m| request.selection.matches(m))
+        .collect();
+    // The spectra of measurements whose files can't be read are not known,
+    // so those are left as they are.
+    let mut processed = load_all_spectra(db, selected).await?;
+    let mut report = ReprocessingReport::default();
+    let mut files = Vec::new();
+    for measurement in processed.iter_mut() {
+        if reprocess(measurement, now) {
+            report.reprocessed.push(measurement.id);
+            files.push(measurement.clone());
+        } else {
+            report.unchanged.push(measurement.id);
+        }
+    }
+    db.update_data(|mut data_model| {
+        for measurement in data_model.measurements.iter_mut() {
+            if let Some(processed) = processed.iter().find(|p| p.id == measurement.id) {
+                measurement.quality = processed.quality.clone();
+                measurement.column_density = processed.column_density.clone();
+                measurement.provenance = processed.provenance.clone();
+                measurement.superseded = processed.superseded.clone();
+            }
+        }
+        data_model
+    })
+    .await?;
+    log::info!(
+        "Reprocessed {} measurements with pipeline {}",
+        report.reprocessed.len(),
+        SOFTWARE_VERSION
+    );
+
+    if let Some(definition) = data_model.file_storage {
+        let store = create_file_store(&definition);
+        let mut checksums: Vec<FileChecksum> = Vec::new();
+        for measurement in &files {
+            match write_measurement_file(&*store, measurement).await {
+                Ok(checksum) => checksums.push(checksum),
+                Err(error) => log::error!(
+                    "Failed to write file of measurement {}: {}",
+                    measurement.id,
+                    error
+                ),
+            }
+        }
+        record_measurement_files(db, checksums).await?;
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::angles::Degrees;
+    use crate::archive::archive_measurement;
+    use crate::archive::column_density::BaselineFit;
+    use crate::archive::test_utils::galactic_measurement;
+    use crate::constants::HI_REST_FREQUENCY;
+    use crate::database::create_in_memory_database;
+
+    #[tokio::test]
+    async fn test_reprocess_measurements() {
+        let db = create_in_memory_database();
+        let mut measurement = galactic_measurement(Degrees(120.0), Utc::now());
+        measurement.freqs = (0..1000)
+            .map(|channel| HI_REST_FREQUENCY - 2.5e6 + channel as f64 * 5e3)
+            .collect();
+        measurement.amps = vec![1.0; 1000];
+        archive_measurement(&db, "fake", measurement.clone())
+            .await
+            .unwrap();
+        archive_measurement(&db, "brage", measurement)
+            .await
+            .unwrap();
+        // Pretend the first was processed by an older, buggy pipeline.
+        db.update_data(|mut data_model| {
+            let first = &mut data_model.measurements[0];
+            first.column_density.as_mut().unwrap().baseline = BaselineFit::Median;
+            first.provenance.as_mut().unwrap().software_version = "old".to_string();
+            data_model
+        })
+        .await
+        .unwrap();
+
+        let request = |selection, pipeline_version: &str| ReprocessingRequest {
+            selection,
+            pipeline_version: pipeline_version.to_string(),
+        };
+        assert_eq!(
+            reprocess_measurements(&db, &request(Default::default(), "old"), Utc::now()).await,
+            Err(ReprocessingError::WrongPipelineVersion(
+                SOFTWARE_VERSION.to_string()
+            ))
+        );
+
+        let selection = MeasurementSelection {
+            processed_by: Some("old".to_string()),
+            ..Default::default()
+        };
+        let report = reprocess_measurements(&db, &request(selection, SOFTWARE_VERSION), Utc::now())
+            .await
+            .unwrap();
+        assert_eq!(report.reprocessed, vec![1]);
+        assert!(report.unchanged.is_empty());
+
+        let measurements = db.get_data().await.unwrap().measurements;
+        let first = &measurements[0];
+        assert_eq!(
+            first.column_density.as_ref().unwrap().baseline,
+            BaselineFit::Linear
+        );
+        assert_eq!(
+            first.provenance.as_ref().unwrap().software_version,
+            SOFTWARE_VERSION
+        );
+        assert_eq!(first.superseded.len(), 1);
+        assert_eq!(
+            first.superseded[0]
+                .provenance
+                .as_ref()
+                .unwrap()
+                .software_version,
+            "old"
+        );
+        assert!(measurements[1].superseded.is_empty());
+
+        // Reprocessing with the same pipeline changes nothing.
+        let report = reprocess_measurements(
+            &db,
+            &request(Default::default(), SOFTWARE_VERSION),
+            Utc::now(),
+        )
+        .await
+        .unwrap();
+        assert!(report.reprocessed.is_empty());
+        assert_eq!(report.unchanged, vec![1, 2]);
+    }
+}
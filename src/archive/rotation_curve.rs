@@ -0,0 +1,183 @@
+//! Rotation curve of the Milky Way from archived spectra of the galactic
+//! plane, using the tangent point method.
+//!
+//! Towards a longitude in the first or fourth quadrant, the gas with the
+//! largest velocity relative to the LSR is found where the line of sight is
+//! tangent to an orbit around the galactic centre. That orbit has radius
+//! R0 |sin l| and its rotation speed is the terminal velocity plus the
+//! projection V0 |sin l| of the rotation of the Sun.
+
+use crate::angles::{Degrees, Radians};
+use crate::archive::{latest_per_longitude, ArchivedMeasurement};
+use crate::constants::HI_REST_FREQUENCY;
+use crate::coords::lsr_velocity_axis;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Distance from the Sun to the galactic centre in kpc.
+pub const SOLAR_GALACTOCENTRIC_DISTANCE: f64 = 8.5;
+/// Rotation speed of the LSR around the galactic centre in km/s.
+pub const SOLAR_ROTATION_SPEED: f64 = 220.0;
+/// Spectra further from the galactic plane than this are not used.
+pub const MAX_GALACTIC_LATITUDE: Degrees = Degrees(2.0);
+/// Emission is detected where the spectrum is this many standard deviations
+/// of the noise above the baseline.
+pub const DETECTION_THRESHOLD: f64 = 3.0;
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct RotationCurvePoint {
+    /// Id of the archived measurement.
+    pub id: u64,
+    /// Galactic longitude in degrees.
+    pub longitude: f64,
+    /// Largest velocity relative to the LSR with detected emission, in km/s.
+    pub terminal_velocity: f64,
+    /// Distance of the tangent point from the galactic centre in kpc.
+    pub radius: f64,
+    /// Rotation speed at the tangent point in km/s.
+    pub rotation_speed: f64,
+}
+
+/// Velocities relative to the LSR, in km/s, of the HI line in each channel.
+pub fn lsr_velocities(freqs: &[f64], l: Radians, b: Radians, when: DateTime<Utc>) -> Vec<f64> {
+    lsr_velocity_axis(freqs, HI_REST_FREQUENCY, l, b, when)
+}
+
+/// The velocity furthest from zero, in the direction allowed by the
+/// rotation of the galaxy, where emission is detected.
+///
+/// Noise is estimated from the median absolute deviation around the median,
+/// which is used as baseline, so a line covering less than half of the
+/// spectrum does not affect it.
+fn terminal_velocity(amps: &[f64], velocities: &[f64], positive: bool) -> Option<f64> {
+    let channels = amps.len().min(velocities.len());
+    if channels < 3 {
+        return None;
+    }
+    let median = |values: &mut Vec<f64>| {
+        values.sort_by(|a, b| a.total_cmp(b));
+        values[values.len() / 2]
+    };
+    let baseline = median(&mut amps[..channels].to_vec());
+    let deviation = median(
+        &mut amps[..channels]
+            .iter()
+            .map(|amp| (amp - baseline).abs())
+            .collect(),
+    );
+    // Scale the median absolute deviation to the standard deviation of
+    // normally distributed noise.
+    let threshold = baseline + DETECTION_THRESHOLD * 1.4826 * deviation;
+    let detected = amps[..channels]
+        .iter()
+        .zip(&velocities[..channels])
+        .filter(|(amp, _)| **amp > threshold)
+        .map(|(_, velocity)| *velocity);
+    if positive {
+        detected.filter(|v| *v > 0.0).max_by(|a, b| a.total_cmp(b))
+    } else {
+        detected.filter(|v| *v < 0.0).min_by(|a, b| a.total_cmp(b))
+    }
+}
+
+/// Rotation curve point from a single measurement, if it was made towards
+/// the galactic plane in the first or fourth quadrant and emission was
+/// detected.
+pub fn rotation_curve_point(measurement: &ArchivedMeasurement) -> Option<RotationCurvePoint> {
+    let (longitude, latitude) = measurement.galactic_position()?;
+    if latitude.0.abs() > MAX_GALACTIC_LATITUDE.0 {
+        return None;
+    }
+    let sin_l = longitude.to_radians().sin();
+    let first_quadrant = longitude.0 < 90.0;
+    let fourth_quadrant = longitude.0 > 270.0;
+    if !(first_quadrant || fourth_quadrant) {
+        return None;
+    }
+    let velocities = lsr_velocities(
+        &measurement.measurement.freqs,
+        longitude.to_radians(),
+        latitude.to_radians(),
+        measurement.measurement.start,
+    );
+    let terminal_velocity =
+        terminal_velocity(&measurement.measurement.amps, &velocities, first_quadrant)?;
+    Some(RotationCurvePoint {
+        id: measurement.id,
+        longitude: longitude.0,
+        terminal_velocity,
+        radius: SOLAR_GALACTOCENTRIC_DISTANCE * sin_l.abs(),
+        rotation_speed: terminal_velocity.abs() + SOLAR_ROTATION_SPEED * sin_l.abs(),
+    })
+}
+
+/// Rotation curve from the most recent measurement per degree of longitude,
+/// sorted by distance from the galactic centre.
+pub fn rotation_curve(measurements: &[ArchivedMeasurement]) -> Vec<RotationCurvePoint> {
+    let mut points: Vec<RotationCurvePoint> = latest_per_longitude(measurements)
+        .into_iter()
+        .filter_map(rotation_curve_point)
+        .collect();
+    points.sort_by(|a, b| a.radius.total_cmp(&b.radius));
+    points
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::archive::test_utils::galactic_measurement;
+
+    /// A measurement towards `l` with a Gaussian line at `line_velocity`
+    /// km/s relative to the LSR.
+    fn measurement_with_line(id: u64, l: f64, line_velocity: f64) -> ArchivedMeasurement {
+        let mut measurement = galactic_measurement(Degrees(l), Utc::now());
+        measurement.freqs = (0..512)
+            .map(|channel| HI_REST_FREQUENCY - 1.25e6 + channel as f64 * 5e3)
+            .collect();
+        let velocities = lsr_velocities(
+            &measurement.freqs,
+            Degrees(l).to_radians(),
+            Radians(0.0),
+            measurement.start,
+        );
+        measurement.amps = velocities
+            .iter()
+            .enumerate()
+            .map(|(channel, v)| {
+                // Deterministic noise, so that the baseline has a spread.
+                let noise = 0.05 * ((channel * 7919) % 13) as f64 / 13.0;
+                1.0 + noise + 10.0 * (-0.5 * ((v - line_velocity) / 5.0).powi(2)).exp()
+            })
+            .collect();
+        ArchivedMeasurement::new(id, "fake", measurement)
+    }
+
+    #[test]
+    fn test_rotation_curve() {
+        let measurements = vec![
+            measurement_with_line(1, 30.0, 100.0),
+            measurement_with_line(2, 330.0, -100.0),
+            // Outside the inner galaxy, where the method does not apply.
+            measurement_with_line(3, 120.0, -50.0),
+        ];
+        let points = rotation_curve(&measurements);
+        assert_eq!(points.len(), 2);
+        for point in &points {
+            // The line extends a few widths beyond its centre.
+            assert!(
+                (100.0..120.0).contains(&point.terminal_velocity.abs()),
+                "{:?}",
+                point
+            );
+            assert!((point.radius - SOLAR_GALACTOCENTRIC_DISTANCE / 2.0).abs() < 1e-6);
+            assert!(
+                (point.rotation_speed - point.terminal_velocity.abs() - SOLAR_ROTATION_SPEED / 2.0)
+                    .abs()
+                    < 1e-6
+            );
+        }
+        assert!(points
+            .iter()
+            .any(|p| p.id == 2 && p.terminal_velocity < 0.0));
+    }
+}
@@ -0,0 +1,140 @@
+use crate::archive::files::{FileChecksum, FileIntegrity};
+use crate::archive::latest_per_longitude;
+use crate::archive::quality::QualityIssue;
+use crate::database::{DataBase, Storage};
+use crate::format::{filters, Locale};
+use crate::template::HtmlTemplate;
+use crate::trash::trash_measurement;
+use askama::Template;
+use axum::{
+    extract::{Path, State},
+    response::IntoResponse,
+    routing::{get, post},
+    Router,
+};
+use chrono::Utc;
+
+pub fn routes(database: DataBase<impl Storage + 'static>) -> Router {
+    Router::new()
+        .route("/", get(get_archive))
+        .route("/integrity", get(get_integrity))
+        .route("/:id/delete", post(delete_measurement))
+        .with_state(database)
+}
+
+struct LongitudeEntry {
+    id: u64,
+    longitude: f64,
+    latitude: f64,
+    telescope_name: String,
+    /// Start time, marked if the clock was out of tolerance.
+    start: String,
+    integration_seconds: u64,
+    /// HI column density, marked if the system temperature was assumed.
+    column_density: Option<String>,
+    /// What looked wrong in the quality assessment, empty if nothing did.
+    quality_issues: Vec<&'static str>,
+}
+
+#[derive(Template)]
+#[template(path = "archive.html")]
+struct ArchiveTemplate {
+    total_measurements: usize,
+    entries: Vec<LongitudeEntry>,
+    locale: Locale,
+}
+
+async fn get_archive<StorageType>(
+    State(db): State<DataBase<StorageType>>,
+    locale: Locale,
+) -> impl IntoResponse
+where
+    StorageType: Storage,
+{
+    let measurements = db
+        .get_data()
+        .await
+        .expect("As long as no one is manually editing the database, this should never fail.")
+        .measurements;
+    let entries = latest_per_longitude(&measurements)
+        .into_iter()
+        .filter_map(|m| {
+            let (longitude, latitude) = m.galactic_position()?;
+            Some(LongitudeEntry {
+                id: m.id,
+                longitude: longitude.0,
+                latitude: latitude.0,
+                telescope_name: m.telescope_name.clone(),
+                start: format!(
+                    "{}{}",
+                    m.measurement.start.format("%Y-%m-%d %H:%M"),
+                    if m.clock_offset.is_some() { "†" } else { "" }
+                ),
+                integration_seconds: m.measurement.duration.as_secs(),
+                column_density: m.column_density.as_ref().map(|c| {
+                    let assumed = if c.assumed_system_temperature {
+                        "*"
+                    } else {
+                        ""
+                    };
+                    format!("{}{}", locale.scientific(c.column_density, 2), assumed)
+                }),
+                quality_issues: m
+                    .quality
+                    .iter()
+                    .flat_map(|q| &q.issues)
+                    .map(|issue| match issue {
+                        QualityIssue::HighNoise => "noisy",
+                        QualityIssue::ManyFlaggedChannels => "interference",
+                        QualityIssue::TrackingError => "tracking error",
+                    })
+                    .collect(),
+            })
+        })
+        .collect();
+    HtmlTemplate(ArchiveTemplate {
+        total_measurements: measurements.len(),
+        entries,
+        locale,
+    })
+}
+
+/// Move a measurement to the trash, from where it can be restored.
+async fn delete_measurement<StorageType>(
+    State(db): State<DataBase<StorageType>>,
+    Path(id): Path<u64>,
+    locale: Locale,
+) -> impl IntoResponse
+where
+    StorageType: Storage,
+{
+    // A measurement that is already gone was probably deleted from another tab.
+    let _ = trash_measurement(&db, id, Utc::now()).await;
+    get_archive(State(db), locale).await
+}
+
+#[derive(Template)]
+#[template(path = "archive_integrity.html")]
+struct IntegrityTemplate {
+    intact: usize,
+    problems: Vec<FileChecksum>,
+}
+
+/// Data files that are corrupted or missing, found on download or by the
+/// daily verification of all files.
+async fn get_integrity<StorageType>(State(db): State<DataBase<StorageType>>) -> impl IntoResponse
+where
+    StorageType: Storage,
+{
+    let (intact, problems) = db
+        .get_data()
+        .await
+        .expect("As long as no one is manually editing the database, this should never fail.")
+        .file_checksums
+        .into_iter()
+        .partition::<Vec<_>, _>(|c| c.integrity == FileIntegrity::Intact);
+    HtmlTemplate(IntegrityTemplate {
+        intact: intact.len(),
+        problems,
+    })
+}
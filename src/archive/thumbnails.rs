@@ -0,0 +1,137 @@
+//! Thumbnails of archived spectra.
+//!
+//! A small PNG of each spectrum is rendered when the measurement is archived
+//! and kept next to its data file in the file store, from where the archive
+//! and the dashboard load it. Thumbnails missing from the store, e.g. of
+//! measurements archived before thumbnails were added, are rendered when
+//! first requested and stored then. Without `file_storage` they are
+//! rendered for every request and left to the browser to cache.
+
+use crate::archive::files::{create_file_store, load_spectra, FileStore, FileStoreError};
+use crate::archive::ArchivedMeasurement;
+use crate::database::{DataBase, Storage};
+use crate::plot::{render_spectrum_thumbnail, PlotError};
+use std::io;
+use thiserror::Error;
+use tokio::io::AsyncReadExt;
+
+#[derive(Debug, Error)]
+pub enum ThumbnailError {
+    #[error(transparent)]
+    Plot(#[from] PlotError),
+    #[error(transparent)]
+    FileStore(#[from] FileStoreError),
+}
+
+pub fn thumbnail_key(id: u64) -> String {
+    format!("thumbnails/{}.png", id)
+}
+
+/// Render the thumbnail of `measurement` and write it to `store`.
+pub async fn write_thumbnail(
+    store: &dyn FileStore,
+    measurement: &ArchivedMeasurement,
+) -> Result<Vec<u8>, ThumbnailError> {
+    let png = render_spectrum_thumbnail(&measurement.spectrum())?;
+    let length = png.len() as u64;
+    store
+        .write(
+            &thumbnail_key(measurement.id),
+            Box::pin(io::Cursor::new(png.clone())),
+            length,
+        )
+        .await?;
+    Ok(png)
+}
+
+async fn read_thumbnail(store: &dyn FileStore, id: u64) -> Result<Vec<u8>, FileStoreError> {
+    let mut png = Vec::new();
+    store
+        .read(&thumbnail_key(id))
+        .await?
+        .read_to_end(&mut png)
+        .await?;
+    Ok(png)
+}
+
+/// The thumbnail of `measurement`, from the file store if there is one.
+/// Problems with the store are only logged, the thumbnail is then rendered.
+/// The spectra are only read when the thumbnail has to be rendered.
+pub async fn fetch_thumbnail(
+    db: &DataBase<impl Storage>,
+    measurement: ArchivedMeasurement,
+) -> Result<Vec<u8>, ThumbnailError> {
+    let file_storage = db
+        .get_data()
+        .await
+        .map_err(FileStoreError::from)?
+        .file_storage;
+    let Some(definition) = file_storage else {
+        let measurement = load_spectra(db, measurement).await?;
+        return Ok(render_spectrum_thumbnail(&measurement.spectrum())?);
+    };
+    let store = create_file_store(&definition);
+    match read_thumbnail(&*store, measurement.id).await {
+        Ok(png) => return Ok(png),
+        Err(FileStoreError::NotFound(_)) => {}
+        Err(error) => log::error!(
+            "Failed to read thumbnail of measurement {}: {}",
+            measurement.id,
+            error
+        ),
+    }
+    let measurement = load_spectra(db, measurement).await?;
+    match write_thumbnail(&*store, &measurement).await {
+        Ok(png) => Ok(png),
+        Err(error) => {
+            log::error!(
+                "Failed to write thumbnail of measurement {}: {}",
+                measurement.id,
+                error
+            );
+            Ok(render_spectrum_thumbnail(&measurement.spectrum())?)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::angles::Degrees;
+    use crate::archive::files::FileStorageDefinition;
+    use crate::archive::test_utils::galactic_measurement;
+    use crate::database::create_in_memory_database;
+
+    #[tokio::test]
+    async fn test_thumbnails_are_stored() {
+        let directory =
+            std::env::temp_dir().join(format!("salsa-thumbnails-{}", std::process::id()));
+        let definition = FileStorageDefinition::Local {
+            directory: directory.to_string_lossy().to_string(),
+        };
+        let measurement = ArchivedMeasurement::new(
+            7,
+            "fake",
+            galactic_measurement(Degrees(30.0), chrono::Utc::now()),
+        );
+        let db = create_in_memory_database();
+        let rendered = fetch_thumbnail(&db, measurement.clone()).await.unwrap();
+        assert!(rendered.starts_with(b"\x89PNG"));
+        assert!(!directory.exists());
+
+        // Rendered on the first request, read from the store after that.
+        db.update_data(|mut data_model| {
+            data_model.file_storage = Some(definition);
+            data_model
+        })
+        .await
+        .unwrap();
+        let png = fetch_thumbnail(&db, measurement.clone()).await.unwrap();
+        assert_eq!(png, rendered);
+        let path = directory.join(thumbnail_key(7));
+        std::fs::write(&path, b"stored").unwrap();
+        let png = fetch_thumbnail(&db, measurement).await.unwrap();
+        assert_eq!(png, b"stored");
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,104 @@
+use crate::assignments::{
+    create_assignment, delete_assignment, group_progress, student_progress, Assignment,
+    AssignmentError, AssignmentProgress, NewAssignment,
+};
+use crate::authentication::Requester;
+use crate::database::{DataBase, Storage};
+use axum::{
+    extract::{Json, Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{delete, get},
+    Router,
+};
+use chrono::Utc;
+
+pub fn routes(database: DataBase<impl Storage + 'static>) -> Router {
+    Router::new()
+        .route("/", get(get_assignments).post(add_assignment))
+        .route("/:id", delete(remove_assignment))
+        .route("/:id/progress", get(get_group_progress))
+        .route("/student/:name", get(get_student_progress))
+        .with_state(database)
+}
+
+impl IntoResponse for AssignmentError {
+    fn into_response(self) -> Response {
+        match self {
+            AssignmentError::NotFound => {
+                (StatusCode::NOT_FOUND, "Assignment not found".to_string()).into_response()
+            }
+            AssignmentError::UserNotFound => {
+                (StatusCode::NOT_FOUND, "User not found".to_string()).into_response()
+            }
+            AssignmentError::NotTeachingGroup => (
+                StatusCode::FORBIDDEN,
+                "Assignments can only be given to groups you teach".to_string(),
+            )
+                .into_response(),
+            AssignmentError::NotAllowed => (
+                StatusCode::FORBIDDEN,
+                "Only the teacher of an assignment and admins may change it".to_string(),
+            )
+                .into_response(),
+            AssignmentError::NoTargets => (
+                StatusCode::BAD_REQUEST,
+                "An assignment needs at least one target".to_string(),
+            )
+                .into_response(),
+            AssignmentError::ServiceUnavailable => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Failed to update assignments".to_string(),
+            )
+                .into_response(),
+        }
+    }
+}
+
+async fn get_assignments(State(db): State<DataBase<impl Storage>>) -> Json<Vec<Assignment>> {
+    Json(
+        db.get_data()
+            .await
+            .expect("As long as no one is manually editing the database, this should never fail.")
+            .assignments,
+    )
+}
+
+async fn add_assignment(
+    State(db): State<DataBase<impl Storage>>,
+    requester: Requester,
+    Json(definition): Json<NewAssignment>,
+) -> Result<(StatusCode, Json<Assignment>), AssignmentError> {
+    let assignment = create_assignment(&db, definition, requester.name(), Utc::now()).await?;
+    Ok((StatusCode::CREATED, Json(assignment)))
+}
+
+async fn remove_assignment(
+    State(db): State<DataBase<impl Storage>>,
+    Path(id): Path<u64>,
+    requester: Requester,
+) -> Result<StatusCode, AssignmentError> {
+    delete_assignment(&db, id, requester.name()).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn get_group_progress(
+    State(db): State<DataBase<impl Storage>>,
+    Path(id): Path<u64>,
+) -> Result<Json<Vec<AssignmentProgress>>, AssignmentError> {
+    let data_model = db.get_data().await?;
+    Ok(Json(group_progress(&data_model, id)?))
+}
+
+async fn get_student_progress(
+    State(db): State<DataBase<impl Storage>>,
+    Path(name): Path<String>,
+) -> Result<Json<Vec<AssignmentProgress>>, AssignmentError> {
+    let data_model = db.get_data().await?;
+    Ok(Json(
+        student_progress(&data_model, &name)?
+            .into_iter()
+            .map(|(_, progress)| progress)
+            .collect(),
+    ))
+}
@@ -0,0 +1,340 @@
+//! Assignments that teachers give to their groups, e.g. to observe a set of
+//! galactic longitudes with at least a minute of integration each.
+//!
+//! Completion is not reported by the students, it is found by matching the
+//! targets of the assignment against the measurements they have archived.
+
+use crate::angles::Degrees;
+use crate::archive::monitoring::is_same_target;
+use crate::archive::ArchivedMeasurement;
+use crate::database::{DataBase, DataBaseError, DataModel, Storage};
+use crate::telescopes::TelescopeTarget;
+use crate::users::is_admin;
+use crate::users::privacy::measurement_ids;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+pub mod api_routes;
+pub mod routes;
+
+/// Default largest pointing difference between a target of an assignment
+/// and a measurement that fulfils it.
+pub const DEFAULT_TARGET_TOLERANCE: Degrees = Degrees(1.0);
+
+fn default_tolerance() -> Degrees {
+    DEFAULT_TARGET_TOLERANCE
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct NewAssignment {
+    pub name: String,
+    /// Group the assignment is given to. The teacher has to teach it.
+    pub group: String,
+    pub teacher: String,
+    pub targets: Vec<TelescopeTarget>,
+    /// Shortest integration accepted for each target.
+    pub min_integration_time: Duration,
+    #[serde(default = "default_tolerance")]
+    pub tolerance: Degrees,
+    #[serde(default)]
+    pub due: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Assignment {
+    pub id: u64,
+    pub created: DateTime<Utc>,
+    #[serde(flatten)]
+    pub definition: NewAssignment,
+}
+
+/// Progress of one student, with the measurement fulfilling each target of
+/// the assignment, if any.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct AssignmentProgress {
+    pub assignment_id: u64,
+    pub student: String,
+    pub targets: Vec<Option<u64>>,
+    pub completed: bool,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum AssignmentError {
+    NotFound,
+    UserNotFound,
+    /// The teacher does not teach the group of the assignment.
+    NotTeachingGroup,
+    /// Only the teacher of an assignment and admins may change it.
+    NotAllowed,
+    NoTargets,
+    ServiceUnavailable,
+}
+
+impl From<DataBaseError> for AssignmentError {
+    fn from(_source: DataBaseError) -> Self {
+        Self::ServiceUnavailable
+    }
+}
+
+impl Assignment {
+    fn fulfils(&self, target: TelescopeTarget, measurement: &ArchivedMeasurement) -> bool {
+        // Measurements archived before the received integration time was
+        // recorded only have the duration.
+        let integration_time = if measurement.measurement.integration_time.is_zero() {
+            measurement.measurement.duration
+        } else {
+            measurement.measurement.integration_time
+        };
+        integration_time >= self.definition.min_integration_time
+            && is_same_target(
+                measurement.measurement.target,
+                target,
+                self.definition.tolerance.to_radians(),
+            )
+    }
+
+    /// Progress of `student` from the measurements in their sessions.
+    pub fn progress(&self, data_model: &DataModel, student: &str) -> AssignmentProgress {
+        let ids = measurement_ids(data_model, student);
+        let measurements: Vec<&ArchivedMeasurement> = data_model
+            .measurements
+            .iter()
+            .filter(|m| ids.contains(&m.id))
+            .collect();
+        let targets: Vec<Option<u64>> = self
+            .definition
+            .targets
+            .iter()
+            .map(|target| {
+                measurements
+                    .iter()
+                    .find(|m| self.fulfils(*target, m))
+                    .map(|m| m.id)
+            })
+            .collect();
+        AssignmentProgress {
+            assignment_id: self.id,
+            student: student.to_string(),
+            completed: targets.iter().all(Option::is_some),
+            targets,
+        }
+    }
+}
+
+/// Progress of every student in the group of an assignment.
+pub fn group_progress(
+    data_model: &DataModel,
+    assignment_id: u64,
+) -> Result<Vec<AssignmentProgress>, AssignmentError> {
+    let assignment = data_model
+        .assignments
+        .iter()
+        .find(|a| a.id == assignment_id)
+        .ok_or(AssignmentError::NotFound)?;
+    Ok(data_model
+        .users
+        .iter()
+        .filter(|u| u.name != assignment.definition.teacher)
+        .filter(|u| u.group.as_ref() == Some(&assignment.definition.group))
+        .map(|u| assignment.progress(data_model, &u.name))
+        .collect())
+}
+
+/// Progress of a student on all assignments given to their group.
+pub fn student_progress(
+    data_model: &DataModel,
+    student: &str,
+) -> Result<Vec<(Assignment, AssignmentProgress)>, AssignmentError> {
+    let user = data_model
+        .users
+        .iter()
+        .find(|u| u.name == student)
+        .ok_or(AssignmentError::UserNotFound)?;
+    Ok(data_model
+        .assignments
+        .iter()
+        .filter(|a| user.group.as_ref() == Some(&a.definition.group))
+        .map(|a| (a.clone(), a.progress(data_model, student)))
+        .collect())
+}
+
+/// Whether `user_name` may change the assignments of `teacher`.
+fn may_change_assignments(data_model: &DataModel, user_name: &str, teacher: &str) -> bool {
+    teacher == user_name || is_admin(data_model, user_name)
+}
+
+/// Create the assignment in `definition` for `user_name`, who has to be its
+/// teacher or an admin.
+pub async fn create_assignment(
+    db: &DataBase<impl Storage>,
+    definition: NewAssignment,
+    user_name: &str,
+    now: DateTime<Utc>,
+) -> Result<Assignment, AssignmentError> {
+    if definition.targets.is_empty() {
+        return Err(AssignmentError::NoTargets);
+    }
+    let data_model = db.get_data().await?;
+    if !may_change_assignments(&data_model, user_name, &definition.teacher) {
+        return Err(AssignmentError::NotAllowed);
+    }
+    let teacher = data_model
+        .users
+        .iter()
+        .find(|u| u.name == definition.teacher)
+        .ok_or(AssignmentError::UserNotFound)?;
+    if !teacher.teaches.contains(&definition.group) {
+        return Err(AssignmentError::NotTeachingGroup);
+    }
+
+    let mut assignment = Assignment {
+        id: 0,
+        created: now,
+        definition,
+    };
+    db.update_data(|mut data_model| {
+        assignment.id = data_model
+            .assignments
+            .iter()
+            .map(|a| a.id + 1)
+            .max()
+            .unwrap_or(1);
+        data_model.assignments.push(assignment.clone());
+        data_model
+    })
+    .await?;
+    log::info!(
+        "{} gave assignment {} to {}",
+        assignment.definition.teacher,
+        assignment.definition.name,
+        assignment.definition.group
+    );
+    Ok(assignment)
+}
+
+/// Delete the assignment `id` for `user_name`, who has to be its teacher or
+/// an admin.
+pub async fn delete_assignment(
+    db: &DataBase<impl Storage>,
+    id: u64,
+    user_name: &str,
+) -> Result<(), AssignmentError> {
+    let mut result = Err(AssignmentError::NotFound);
+    db.update_data(|mut data_model| {
+        if let Some(assignment) = data_model.assignments.iter().find(|a| a.id == id) {
+            if may_change_assignments(&data_model, user_name, &assignment.definition.teacher) {
+                data_model.assignments.retain(|a| a.id != id);
+                result = Ok(());
+            } else {
+                result = Err(AssignmentError::NotAllowed);
+            }
+        }
+        data_model
+    })
+    .await?;
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::angles::Radians;
+    use crate::archive::test_utils::galactic_measurement;
+    use crate::database::create_in_memory_database;
+    use crate::sessions::{RecordedSession, SessionEvent, SessionEventKind};
+    use crate::users::User;
+
+    fn galactic(l: f64) -> TelescopeTarget {
+        TelescopeTarget::Galactic {
+            l: Degrees(l).to_radians(),
+            b: Radians(0.0),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_assignment_progress() {
+        let now = Utc::now();
+        let db = create_in_memory_database();
+        db.update_data(|mut data_model| {
+            let mut teacher = User::new("teacher");
+            teacher.teaches = vec!["3A".to_string()];
+            let mut student = User::new("anna");
+            student.group = Some("3A".to_string());
+            data_model.users = vec![teacher, student];
+            let mut short = galactic_measurement(Degrees(40.0), now);
+            short.integration_time = Duration::from_secs(10);
+            data_model.measurements = vec![
+                ArchivedMeasurement::new(1, "fake", galactic_measurement(Degrees(30.3), now)),
+                ArchivedMeasurement::new(2, "fake", short),
+            ];
+            data_model.sessions = vec![RecordedSession {
+                id: 1,
+                telescope_name: "fake".to_string(),
+                user_name: "anna".to_string(),
+                start_time: now,
+                end_time: now,
+                events: [1, 2]
+                    .into_iter()
+                    .map(|id| SessionEvent {
+                        time: now,
+                        kind: SessionEventKind::MeasurementSaved(id),
+                    })
+                    .collect(),
+            }];
+            data_model
+        })
+        .await
+        .unwrap();
+
+        let definition = NewAssignment {
+            name: "Rotation curve".to_string(),
+            group: "3A".to_string(),
+            teacher: "teacher".to_string(),
+            targets: vec![galactic(30.0), galactic(40.0)],
+            min_integration_time: Duration::from_secs(60),
+            tolerance: DEFAULT_TARGET_TOLERANCE,
+            due: None,
+        };
+        assert_eq!(
+            create_assignment(
+                &db,
+                NewAssignment {
+                    group: "4C".to_string(),
+                    ..definition.clone()
+                },
+                "teacher",
+                now
+            )
+            .await,
+            Err(AssignmentError::NotTeachingGroup)
+        );
+        assert_eq!(
+            create_assignment(&db, definition.clone(), "anna", now).await,
+            Err(AssignmentError::NotAllowed)
+        );
+        let assignment = create_assignment(&db, definition, "teacher", now)
+            .await
+            .unwrap();
+
+        let data_model = db.get_data().await.unwrap();
+        let progress = group_progress(&data_model, assignment.id).unwrap();
+        assert_eq!(progress.len(), 1);
+        // The measurement towards l = 40 is too short.
+        assert_eq!(progress[0].targets, vec![Some(1), None]);
+        assert!(!progress[0].completed);
+        assert_eq!(student_progress(&data_model, "anna").unwrap().len(), 1);
+
+        assert_eq!(
+            delete_assignment(&db, assignment.id, "anna").await,
+            Err(AssignmentError::NotAllowed)
+        );
+        delete_assignment(&db, assignment.id, "teacher")
+            .await
+            .unwrap();
+        assert_eq!(
+            delete_assignment(&db, assignment.id, "teacher").await,
+            Err(AssignmentError::NotFound)
+        );
+    }
+}
@@ -0,0 +1,220 @@
+use crate::angles::{Degrees, Radians};
+use crate::assignments::{
+    create_assignment, group_progress, student_progress, AssignmentError, NewAssignment,
+    DEFAULT_TARGET_TOLERANCE,
+};
+use crate::authentication::Requester;
+use crate::database::{DataBase, Storage};
+use crate::telescopes::TelescopeTarget;
+use crate::template::HtmlTemplate;
+use askama::Template;
+use axum::{
+    extract::{Form, Path, State},
+    response::IntoResponse,
+    routing::get,
+    Router,
+};
+use chrono::Utc;
+use serde::Deserialize;
+use std::time::Duration;
+
+pub fn routes(database: DataBase<impl Storage + 'static>) -> Router {
+    Router::new()
+        .route(
+            "/teacher/:name",
+            get(get_teacher_assignments).post(add_assignment),
+        )
+        .route("/student/:name", get(get_student_assignments))
+        .with_state(database)
+}
+
+struct StudentRow {
+    name: String,
+    done: usize,
+    completed: bool,
+}
+
+struct TeacherAssignment {
+    id: u64,
+    name: String,
+    group: String,
+    targets: Vec<String>,
+    min_integration_seconds: u64,
+    students: Vec<StudentRow>,
+}
+
+#[derive(Template)]
+#[template(path = "assignments_teacher.html")]
+struct TeacherTemplate {
+    name: String,
+    groups: Vec<String>,
+    assignments: Vec<TeacherAssignment>,
+    error: Option<String>,
+}
+
+async fn render_teacher_assignments(
+    db: &DataBase<impl Storage>,
+    name: String,
+    error: Option<String>,
+) -> HtmlTemplate<TeacherTemplate> {
+    let data_model = db
+        .get_data()
+        .await
+        .expect("As long as no one is manually editing the database, this should never fail.");
+    let groups = data_model
+        .users
+        .iter()
+        .find(|u| u.name == name)
+        .map(|u| u.teaches.clone())
+        .unwrap_or_default();
+    let assignments = data_model
+        .assignments
+        .iter()
+        .filter(|a| a.definition.teacher == name)
+        .map(|a| TeacherAssignment {
+            id: a.id,
+            name: a.definition.name.clone(),
+            group: a.definition.group.clone(),
+            targets: a.definition.targets.iter().map(|t| t.to_string()).collect(),
+            min_integration_seconds: a.definition.min_integration_time.as_secs(),
+            students: group_progress(&data_model, a.id)
+                .unwrap_or_default()
+                .into_iter()
+                .map(|progress| StudentRow {
+                    done: progress.targets.iter().flatten().count(),
+                    completed: progress.completed,
+                    name: progress.student,
+                })
+                .collect(),
+        })
+        .collect();
+    HtmlTemplate(TeacherTemplate {
+        name,
+        groups,
+        assignments,
+        error,
+    })
+}
+
+async fn get_teacher_assignments<StorageType>(
+    State(db): State<DataBase<StorageType>>,
+    Path(name): Path<String>,
+) -> impl IntoResponse
+where
+    StorageType: Storage,
+{
+    render_teacher_assignments(&db, name, None).await
+}
+
+#[derive(Deserialize, Debug)]
+struct AssignmentForm {
+    name: String,
+    group: String,
+    /// Galactic longitudes in degrees, separated by commas, to observe in
+    /// the galactic plane.
+    longitudes: String,
+    min_integration_seconds: u64,
+}
+
+fn parse_longitudes(longitudes: &str) -> Result<Vec<TelescopeTarget>, String> {
+    longitudes
+        .split(',')
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .map(|l| {
+            l.parse::<f64>()
+                .map(|l| TelescopeTarget::Galactic {
+                    l: Degrees(l).to_radians(),
+                    b: Radians(0.0),
+                })
+                .map_err(|_| format!("{} is not a longitude", l))
+        })
+        .collect()
+}
+
+async fn add_assignment<StorageType>(
+    State(db): State<DataBase<StorageType>>,
+    Path(name): Path<String>,
+    requester: Requester,
+    Form(form): Form<AssignmentForm>,
+) -> impl IntoResponse
+where
+    StorageType: Storage,
+{
+    let error = match parse_longitudes(&form.longitudes) {
+        Ok(targets) => {
+            let definition = NewAssignment {
+                name: form.name,
+                group: form.group,
+                teacher: name.clone(),
+                targets,
+                min_integration_time: Duration::from_secs(form.min_integration_seconds),
+                tolerance: DEFAULT_TARGET_TOLERANCE,
+                due: None,
+            };
+            match create_assignment(&db, definition, requester.name(), Utc::now()).await {
+                Ok(_) => None,
+                Err(AssignmentError::NoTargets) => Some("Give at least one longitude.".to_string()),
+                Err(AssignmentError::NotTeachingGroup) => {
+                    Some("You do not teach that group.".to_string())
+                }
+                Err(error) => Some(format!("Failed to create the assignment: {:?}", error)),
+            }
+        }
+        Err(error) => Some(error),
+    };
+    render_teacher_assignments(&db, name, error).await
+}
+
+struct StudentTarget {
+    description: String,
+    measurement: Option<u64>,
+}
+
+struct StudentAssignment {
+    name: String,
+    min_integration_seconds: u64,
+    due: Option<String>,
+    completed: bool,
+    targets: Vec<StudentTarget>,
+}
+
+#[derive(Template)]
+#[template(path = "assignments_student.html")]
+struct StudentTemplate {
+    name: String,
+    assignments: Vec<StudentAssignment>,
+}
+
+async fn get_student_assignments<StorageType>(
+    State(db): State<DataBase<StorageType>>,
+    Path(name): Path<String>,
+) -> Result<impl IntoResponse, AssignmentError>
+where
+    StorageType: Storage,
+{
+    let data_model = db.get_data().await?;
+    let assignments = student_progress(&data_model, &name)?
+        .into_iter()
+        .map(|(assignment, progress)| StudentAssignment {
+            name: assignment.definition.name,
+            min_integration_seconds: assignment.definition.min_integration_time.as_secs(),
+            due: assignment
+                .definition
+                .due
+                .map(|due| due.format("%Y-%m-%d").to_string()),
+            completed: progress.completed,
+            targets: assignment
+                .definition
+                .targets
+                .iter()
+                .zip(progress.targets)
+                .map(|(target, measurement)| StudentTarget {
+                    description: target.to_string(),
+                    measurement,
+                })
+                .collect(),
+        })
+        .collect();
+    Ok(HtmlTemplate(StudentTemplate { name, assignments }))
+}
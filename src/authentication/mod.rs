@@ -0,0 +1,315 @@
+//! Who is making a request.
+//!
+//! Users authenticate with an API token handed out by an admin, or by
+//! `--issue-token` on the server for the first admin. Scripts send it as
+//! `Authorization: Bearer <token>`, and the browser keeps it in the
+//! [`API_TOKEN_COOKIE`] cookie after logging in at `/login`. Only a hash of
+//! each token is stored.
+//!
+//! The [`authenticate`] middleware turns the token into the [`Requester`]
+//! handlers see, so a request can not claim to be from a user by naming
+//! them. Admin actions additionally need a confirmation token, see
+//! [`crate::authorization`].
+
+use crate::database::{DataBase, DataBaseError, DataModel, Storage};
+use async_trait::async_trait;
+use axum::{
+    extract::{FromRequestParts, State},
+    http::{header, request::Parts, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::convert::Infallible;
+
+pub mod routes;
+
+pub const API_TOKEN_COOKIE: &str = "salsa-token";
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct ApiToken {
+    pub user_name: String,
+    /// SHA-256 of the token, hex encoded.
+    pub token_hash: String,
+    pub created: DateTime<Utc>,
+}
+
+/// A token as handed out, the only time it is seen in clear text.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct IssuedToken {
+    pub user_name: String,
+    pub token: String,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum AuthenticationError {
+    UnknownUser(String),
+    InvalidToken,
+    ServiceUnavailable,
+}
+
+impl From<DataBaseError> for AuthenticationError {
+    fn from(_source: DataBaseError) -> Self {
+        Self::ServiceUnavailable
+    }
+}
+
+impl IntoResponse for AuthenticationError {
+    fn into_response(self) -> Response {
+        match self {
+            AuthenticationError::UnknownUser(user_name) => (
+                StatusCode::NOT_FOUND,
+                format!("No user called {}", user_name),
+            ),
+            AuthenticationError::InvalidToken => {
+                (StatusCode::UNAUTHORIZED, "Invalid API token".to_string())
+            }
+            AuthenticationError::ServiceUnavailable => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Failed to authenticate".to_string(),
+            ),
+        }
+        .into_response()
+    }
+}
+
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+pub fn new_token() -> String {
+    let bytes: [u8; 32] = rand::thread_rng().gen();
+    hex::encode(bytes)
+}
+
+/// Store a new API token for `user_name` and return it. Users have a
+/// single token, so this revokes the one they had.
+pub fn add_api_token(data_model: &mut DataModel, user_name: &str, now: DateTime<Utc>) -> String {
+    let token = new_token();
+    data_model.api_tokens.retain(|t| t.user_name != user_name);
+    data_model.api_tokens.push(ApiToken {
+        user_name: user_name.to_string(),
+        token_hash: hash_token(&token),
+        created: now,
+    });
+    token
+}
+
+/// Give the existing user `user_name` a new API token.
+pub async fn issue_api_token(
+    db: &DataBase<impl Storage>,
+    user_name: &str,
+    now: DateTime<Utc>,
+) -> Result<IssuedToken, AuthenticationError> {
+    let mut result = Err(AuthenticationError::UnknownUser(user_name.to_string()));
+    db.update_data(|mut data_model| {
+        if data_model.users.iter().any(|u| u.name == user_name) {
+            result = Ok(IssuedToken {
+                user_name: user_name.to_string(),
+                token: add_api_token(&mut data_model, user_name, now),
+            });
+        }
+        data_model
+    })
+    .await?;
+    if result.is_ok() {
+        log::info!("Issued a new API token for {}", user_name);
+    }
+    result
+}
+
+/// The user `token` belongs to, if it is a token of an existing user.
+pub fn token_user(data_model: &DataModel, token: &str) -> Option<String> {
+    let token_hash = hash_token(token);
+    data_model
+        .api_tokens
+        .iter()
+        .find(|t| t.token_hash == token_hash)
+        .filter(|t| data_model.users.iter().any(|u| u.name == t.user_name))
+        .map(|t| t.user_name.clone())
+}
+
+/// The token of the `Authorization: Bearer` header.
+fn bearer_token<B>(request: &Request<B>) -> Option<String> {
+    request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(|token| token.trim().to_string())
+}
+
+/// The token of the [`API_TOKEN_COOKIE`] cookie.
+fn cookie_token<B>(request: &Request<B>) -> Option<String> {
+    request
+        .headers()
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(name, _)| *name == API_TOKEN_COOKIE)
+        .map(|(_, token)| token.to_string())
+}
+
+/// The user a request was authenticated as, added to the request by
+/// [`authenticate`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuthenticatedUser(pub String);
+
+/// Middleware authenticating the requester by their API token. A request
+/// with an invalid bearer token is rejected, while an invalid cookie, e.g.
+/// one left from a revoked token, is ignored so the pages still load for
+/// logging in again. Requests without a valid token are anonymous.
+pub async fn authenticate<StorageType, B>(
+    State(db): State<DataBase<StorageType>>,
+    mut request: Request<B>,
+    next: Next<B>,
+) -> Response
+where
+    StorageType: Storage,
+{
+    let bearer = bearer_token(&request);
+    if let Some(token) = bearer.clone().or_else(|| cookie_token(&request)) {
+        let data_model = db
+            .get_data()
+            .await
+            .expect("As long as no one is manually editing the database, this should never fail.");
+        match token_user(&data_model, &token) {
+            Some(user_name) => {
+                request
+                    .extensions_mut()
+                    .insert(AuthenticatedUser(user_name));
+            }
+            None if bearer.is_some() => return AuthenticationError::InvalidToken.into_response(),
+            None => {}
+        }
+    }
+    next.run(request).await
+}
+
+/// The authenticated user making the request, if any.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Requester(pub Option<String>);
+
+impl Requester {
+    /// The name of the requester, empty for anonymous requests, which is
+    /// never the name of a user.
+    pub fn name(&self) -> &str {
+        self.0.as_deref().unwrap_or_default()
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Requester
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(Requester(
+            parts
+                .extensions
+                .get::<AuthenticatedUser>()
+                .map(|user| user.0.clone()),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::database::create_in_memory_database;
+    use crate::users::User;
+    use axum::{body::Body, middleware, routing::get, Router};
+    use chrono::TimeZone;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_authenticate() {
+        let db = create_in_memory_database();
+        let now = Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap();
+        db.update_data(|mut data_model| {
+            data_model.users.push(User::new("anna"));
+            data_model
+        })
+        .await
+        .unwrap();
+        assert_eq!(
+            issue_api_token(&db, "nobody", now).await,
+            Err(AuthenticationError::UnknownUser("nobody".to_string()))
+        );
+        let revoked = issue_api_token(&db, "anna", now).await.unwrap();
+        let issued = issue_api_token(&db, "anna", now).await.unwrap();
+        assert_eq!(db.get_data().await.unwrap().api_tokens.len(), 1);
+
+        let requester = |header: Option<(header::HeaderName, String)>| {
+            let router = Router::new()
+                .route(
+                    "/",
+                    get(|requester: Requester| async move { requester.name().to_string() }),
+                )
+                .layer(middleware::from_fn_with_state(db.clone(), authenticate));
+            let mut request = Request::builder().uri("/");
+            if let Some((name, value)) = header {
+                request = request.header(name, value);
+            }
+            async move {
+                let response = router
+                    .oneshot(request.body(Body::empty()).unwrap())
+                    .await
+                    .unwrap();
+                let status = response.status();
+                let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+                (status, String::from_utf8(body.to_vec()).unwrap())
+            }
+        };
+
+        assert_eq!(requester(None).await, (StatusCode::OK, String::new()));
+        assert_eq!(
+            requester(Some((
+                header::AUTHORIZATION,
+                format!("Bearer {}", issued.token)
+            )))
+            .await,
+            (StatusCode::OK, "anna".to_string())
+        );
+        assert_eq!(
+            requester(Some((
+                header::COOKIE,
+                format!("theme=dark; {}={}", API_TOKEN_COOKIE, issued.token)
+            )))
+            .await,
+            (StatusCode::OK, "anna".to_string())
+        );
+        assert_eq!(
+            requester(Some((
+                header::AUTHORIZATION,
+                format!("Bearer {}", revoked.token)
+            )))
+            .await
+            .0,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            requester(Some((
+                header::COOKIE,
+                format!("{}={}", API_TOKEN_COOKIE, revoked.token)
+            )))
+            .await,
+            (StatusCode::OK, String::new())
+        );
+        assert_eq!(
+            requester(Some((
+                header::HeaderName::from_static("user-name"),
+                "anna".to_string()
+            )))
+            .await,
+            (StatusCode::OK, String::new())
+        );
+    }
+}
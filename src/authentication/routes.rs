@@ -0,0 +1,89 @@
+use crate::authentication::{token_user, Requester, API_TOKEN_COOKIE};
+use crate::database::{DataBase, Storage};
+use crate::template::HtmlTemplate;
+use askama::Template;
+use axum::{
+    extract::{Form, State},
+    http::header,
+    response::IntoResponse,
+    routing::{get, post},
+    Router,
+};
+use serde::Deserialize;
+
+pub fn routes(database: DataBase<impl Storage + 'static>) -> Router {
+    Router::new()
+        .route("/login", get(get_login).post(post_login))
+        .route("/logout", post(post_logout))
+        .with_state(database)
+}
+
+/// The `Set-Cookie` value logging the browser in with `token`, or logging
+/// it out if the token is empty.
+pub fn login_cookie(token: &str) -> String {
+    let max_age = if token.is_empty() { "; Max-Age=0" } else { "" };
+    format!(
+        "{}={}; Path=/; HttpOnly; SameSite=Strict{}",
+        API_TOKEN_COOKIE, token, max_age
+    )
+}
+
+#[derive(Template)]
+#[template(path = "login.html")]
+struct LoginTemplate {
+    /// The user logged in as, if any.
+    user_name: Option<String>,
+    message: Option<String>,
+}
+
+async fn get_login(requester: Requester) -> impl IntoResponse {
+    HtmlTemplate(LoginTemplate {
+        user_name: requester.0,
+        message: None,
+    })
+}
+
+#[derive(Deserialize, Debug)]
+struct LoginForm {
+    token: String,
+}
+
+/// Log in with an API token, kept in a cookie sent with every later request.
+async fn post_login<StorageType>(
+    State(db): State<DataBase<StorageType>>,
+    Form(form): Form<LoginForm>,
+) -> impl IntoResponse
+where
+    StorageType: Storage,
+{
+    let token = form.token.trim();
+    let data_model = db
+        .get_data()
+        .await
+        .expect("As long as no one is manually editing the database, this should never fail.");
+    match token_user(&data_model, token) {
+        Some(user_name) => (
+            [(header::SET_COOKIE, login_cookie(token))],
+            HtmlTemplate(LoginTemplate {
+                user_name: Some(user_name),
+                message: None,
+            }),
+        )
+            .into_response(),
+        None => HtmlTemplate(LoginTemplate {
+            user_name: None,
+            message: Some("Unknown token".to_string()),
+        })
+        .into_response(),
+    }
+}
+
+async fn post_logout() -> impl IntoResponse {
+    (
+        [(header::SET_COOKIE, login_cookie(""))],
+        HtmlTemplate(LoginTemplate {
+            user_name: None,
+            message: Some("Logged out".to_string()),
+        }),
+    )
+}
@@ -0,0 +1,428 @@
+//! Who may use which route.
+//!
+//! Every route that changes something is listed in [`POLICIES`] together
+//! with the policy for it, and [`authorize`] is applied to the whole router
+//! so no route can forget its check. Routes that only read are public,
+//! while other routes missing from the table are denied, so a new route is
+//! refused until it is given a policy.
+
+use crate::authentication::AuthenticatedUser;
+use crate::confirmation::{check_admin, consume_confirmation_token, CONFIRMATION_TOKEN_HEADER};
+use crate::database::{DataBase, Storage};
+use crate::users::check_control_allowed;
+use axum::{
+    extract::{MatchedPath, State},
+    http::{Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chrono::Utc;
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Policy {
+    Public,
+    /// Checked by the handler, which knows who owns what is changed, e.g.
+    /// only the user who made a booking and admins may delete it.
+    Handler,
+    /// Changes the state of the telescope in the `telescope_id` parameter.
+    /// Real telescopes may only be controlled by admins and by certified
+    /// users during their booking.
+    Control,
+    /// Destructive or changes the configuration of a telescope, needs a
+    /// confirmation token handed out to the admin making the request.
+    Admin,
+    /// Only for admins, without confirmation, for what is not destructive.
+    AdminOnly,
+    /// Only for the user in the `name` parameter and admins, without
+    /// confirmation.
+    SelfOrAdmin,
+    /// Changes something and is not in the table.
+    Denied,
+}
+
+/// Method, matched path and policy of every route that changes something.
+pub const POLICIES: &[(&str, &str, Policy)] = &[
+    ("POST", "/login", Policy::Public),
+    ("POST", "/logout", Policy::Public),
+    ("POST", "/bookings", Policy::Handler),
+    ("POST", "/bookings/delete", Policy::Handler),
+    ("POST", "/bookings/reschedule", Policy::Handler),
+    ("POST", "/citations", Policy::Public),
+    ("POST", "/users/demo", Policy::Public),
+    ("POST", "/users/import", Policy::AdminOnly),
+    ("POST", "/users/:name/deletion", Policy::SelfOrAdmin),
+    ("POST", "/trash/:id/restore", Policy::AdminOnly),
+    ("POST", "/shift_log/:telescope_name", Policy::Public),
+    ("POST", "/faults", Policy::Public),
+    ("POST", "/faults/:id/status", Policy::AdminOnly),
+    ("POST", "/confirmations", Policy::Handler),
+    ("POST", "/analysis", Policy::Public),
+    ("POST", "/analysis/:id/cells", Policy::Public),
+    ("POST", "/analysis/:id/cells/:index/delete", Policy::Public),
+    ("POST", "/assignments/teacher/:name", Policy::Handler),
+    ("POST", "/telescopes/:telescope_id/target", Policy::Control),
+    (
+        "POST",
+        "/telescopes/:telescope_id/receiver",
+        Policy::Control,
+    ),
+    ("POST", "/telescopes/:telescope_id/restart", Policy::Admin),
+    (
+        "POST",
+        "/api/telescopes/:telescope_id/target",
+        Policy::Control,
+    ),
+    (
+        "POST",
+        "/api/telescopes/:telescope_id/target/text",
+        Policy::Control,
+    ),
+    (
+        "POST",
+        "/api/telescopes/:telescope_id/target/satellite",
+        Policy::Control,
+    ),
+    (
+        "POST",
+        "/api/telescopes/:telescope_id/receiver",
+        Policy::Control,
+    ),
+    (
+        "POST",
+        "/api/telescopes/:telescope_id/auxiliary/:device_name",
+        Policy::Control,
+    ),
+    (
+        "POST",
+        "/api/telescopes/:telescope_id/restart",
+        Policy::Admin,
+    ),
+    ("POST", "/api/bookings", Policy::Handler),
+    ("PATCH", "/api/bookings", Policy::Handler),
+    ("DELETE", "/api/bookings", Policy::Handler),
+    ("POST", "/api/bookings/calendar_token", Policy::Handler),
+    ("POST", "/api/citations", Policy::Public),
+    ("POST", "/api/users/demo", Policy::Public),
+    ("POST", "/api/users/import", Policy::AdminOnly),
+    ("POST", "/api/users/:name/certify", Policy::Admin),
+    ("POST", "/api/users/:name/token", Policy::Admin),
+    (
+        "POST",
+        "/api/users/:name/training/:step",
+        Policy::SelfOrAdmin,
+    ),
+    (
+        "POST",
+        "/api/users/:name/tutorial/skip",
+        Policy::SelfOrAdmin,
+    ),
+    ("PUT", "/api/users/:name/teaches", Policy::AdminOnly),
+    ("POST", "/api/users/:name/deletion", Policy::SelfOrAdmin),
+    ("DELETE", "/api/users/:name/deletion", Policy::SelfOrAdmin),
+    ("POST", "/api/users/:name/deletion/confirm", Policy::Admin),
+    ("POST", "/api/trash/:id/restore", Policy::AdminOnly),
+    ("POST", "/api/faults", Policy::Public),
+    ("PUT", "/api/faults/:id/status", Policy::AdminOnly),
+    ("POST", "/api/confirmations", Policy::Handler),
+    ("POST", "/archive/:id/delete", Policy::Admin),
+    ("DELETE", "/api/archive/:id", Policy::Admin),
+    ("POST", "/api/archive/reprocess", Policy::Admin),
+    ("POST", "/api/archive/stack", Policy::Public),
+    ("POST", "/api/calibration/:telescope_name", Policy::Admin),
+    ("PUT", "/api/park_policies/:telescope_name", Policy::Admin),
+    (
+        "PUT",
+        "/api/park_policies/:telescope_name/override",
+        Policy::AdminOnly,
+    ),
+    (
+        "DELETE",
+        "/api/park_policies/:telescope_name/override",
+        Policy::AdminOnly,
+    ),
+    ("PUT", "/api/pointing/:telescope_name/offset", Policy::Admin),
+    ("PUT", "/api/pointing/:telescope_name/model", Policy::Admin),
+    (
+        "POST",
+        "/api/pointing/:telescope_name/observations",
+        Policy::AdminOnly,
+    ),
+    (
+        "DELETE",
+        "/api/pointing/:telescope_name/observations",
+        Policy::Admin,
+    ),
+    ("POST", "/api/pointing/:telescope_name/fit", Policy::Admin),
+    (
+        "POST",
+        "/api/scheduler/:telescope_id/queue",
+        Policy::Control,
+    ),
+    (
+        "DELETE",
+        "/api/scheduler/:telescope_id/queue",
+        Policy::Control,
+    ),
+    ("POST", "/api/scheduler/:telescope_id/plan", Policy::Public),
+    ("POST", "/api/scheduler/jobs", Policy::Public),
+    ("DELETE", "/api/scheduler/jobs/:id", Policy::Handler),
+    ("POST", "/console/:telescope_name", Policy::Admin),
+    ("POST", "/api/console/:telescope_name", Policy::Admin),
+    ("POST", "/api/shift_log", Policy::Public),
+    ("POST", "/api/analysis/run", Policy::Public),
+    ("POST", "/api/analysis/notebooks", Policy::Public),
+    ("POST", "/api/assignments", Policy::Handler),
+    ("DELETE", "/api/assignments/:id", Policy::Handler),
+    ("POST", "/api/spectral_lines/velocity", Policy::Public),
+    ("GET", "/api/confirmations/audit", Policy::AdminOnly),
+    ("GET", "/api/users/:name/export", Policy::SelfOrAdmin),
+];
+
+/// The policy of the route, from the table, or public for routes that only
+/// read and denied for others.
+pub fn policy(method: &str, matched_path: &str) -> Policy {
+    POLICIES
+        .iter()
+        .find(|(m, p, _)| *m == method && *p == matched_path)
+        .map(|(_, _, policy)| *policy)
+        .unwrap_or(match method {
+            "GET" | "HEAD" => Policy::Public,
+            _ => Policy::Denied,
+        })
+}
+
+/// The value of the `name` parameter of `matched_path` in `path`.
+fn path_parameter<'a>(matched_path: &str, path: &'a str, name: &str) -> Option<&'a str> {
+    matched_path
+        .split('/')
+        .zip(path.split('/'))
+        .find(|(segment, _)| segment.strip_prefix(':') == Some(name))
+        .map(|(_, value)| value)
+}
+
+/// Middleware for the whole router, enforcing the policy of each route.
+pub async fn authorize<StorageType, B>(
+    State(db): State<DataBase<StorageType>>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response
+where
+    StorageType: Storage,
+{
+    let Some(matched_path) = request.extensions().get::<MatchedPath>() else {
+        return next.run(request).await;
+    };
+    let method = request.method().to_string();
+    let path = request.uri().path().to_string();
+    let user_name = request
+        .extensions()
+        .get::<AuthenticatedUser>()
+        .map(|user| user.0.clone())
+        .unwrap_or_default();
+    match policy(&method, matched_path.as_str()) {
+        Policy::Public | Policy::Handler => {}
+        Policy::Denied => {
+            log::warn!("Denied {} {}, which has no policy", method, path);
+            return (
+                StatusCode::FORBIDDEN,
+                "No one may use this route".to_string(),
+            )
+                .into_response();
+        }
+        Policy::Control => {
+            if let Some(telescope_name) =
+                path_parameter(matched_path.as_str(), &path, "telescope_id")
+            {
+                let data_model = db.get_data().await.expect(
+                    "As long as no one is manually editing the database, this should never fail.",
+                );
+                if let Err(error) =
+                    check_control_allowed(&data_model, telescope_name, &user_name, Utc::now())
+                {
+                    return error.into_response();
+                }
+            }
+        }
+        Policy::Admin => {
+            let token = request
+                .headers()
+                .get(CONFIRMATION_TOKEN_HEADER)
+                .and_then(|token| token.to_str().ok());
+            if let Err(error) =
+                consume_confirmation_token(&db, &user_name, token, &method, &path, Utc::now()).await
+            {
+                return error.into_response();
+            }
+        }
+        Policy::AdminOnly => {
+            let data_model = db.get_data().await.expect(
+                "As long as no one is manually editing the database, this should never fail.",
+            );
+            if let Err(error) = check_admin(&data_model, &user_name) {
+                return error.into_response();
+            }
+        }
+        Policy::SelfOrAdmin => {
+            let data_model = db.get_data().await.expect(
+                "As long as no one is manually editing the database, this should never fail.",
+            );
+            let is_self = !user_name.is_empty()
+                && path_parameter(matched_path.as_str(), &path, "name") == Some(&user_name);
+            if !is_self {
+                if let Err(error) = check_admin(&data_model, &user_name) {
+                    return error.into_response();
+                }
+            }
+        }
+    }
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::authentication::issue_api_token;
+    use crate::bookings::Booking;
+    use crate::confirmation::{
+        issue_confirmation_secret, issue_confirmation_token, ConfirmationRequest,
+    };
+    use crate::database::create_in_memory_database;
+    use crate::telescopes::test_utils::salsa_telescope;
+    use crate::test_utils::with_token;
+    use crate::users::{Role, User};
+    use axum::{body::Body, http};
+    use chrono::Duration;
+    use tower::ServiceExt;
+
+    async fn send(
+        db: &DataBase<impl Storage + 'static>,
+        api_token: &str,
+        method: &str,
+        uri: &str,
+        token: Option<&str>,
+    ) -> StatusCode {
+        let mut request = with_token(
+            http::Method::from_bytes(method.as_bytes()).unwrap(),
+            uri,
+            Some(api_token),
+        );
+        if let Some(token) = token {
+            request = request.header(CONFIRMATION_TOKEN_HEADER, token);
+        }
+        crate::create_router(Default::default(), db.clone())
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+            .status()
+    }
+
+    #[tokio::test]
+    async fn test_route_policies() {
+        let db = create_in_memory_database();
+        let now = Utc::now();
+        db.update_data(|mut data_model| {
+            data_model.telescopes = vec![salsa_telescope("brage")];
+            data_model.users = vec![
+                User {
+                    role: Role::Admin,
+                    ..User::new("anna")
+                },
+                User::new("student"),
+                User::new("bertil"),
+            ];
+            data_model.bookings = ["brage", "fake"]
+                .into_iter()
+                .map(|telescope_name| Booking {
+                    start_time: now - Duration::hours(1),
+                    end_time: now + Duration::hours(1),
+                    telescope_name: telescope_name.to_string(),
+                    user_name: "student".to_string(),
+                })
+                .collect();
+            data_model
+        })
+        .await
+        .unwrap();
+        let admin = issue_api_token(&db, "anna", now).await.unwrap().token;
+        let student = issue_api_token(&db, "student", now).await.unwrap().token;
+        let secret = issue_confirmation_secret(&db, "anna", now).await.unwrap();
+
+        for (method, matched_path, policy) in POLICIES {
+            let uri = matched_path
+                .split('/')
+                .map(|segment| match segment {
+                    ":telescope_id" => "brage",
+                    ":name" => "bertil",
+                    ":telescope_name" => "brage",
+                    ":id" => "1",
+                    ":device_name" => "fan",
+                    ":index" => "0",
+                    ":step" => "SafetyIntroduction",
+                    segment => segment,
+                })
+                .collect::<Vec<_>>()
+                .join("/");
+            if matches!(policy, Policy::Public | Policy::Handler) {
+                continue;
+            }
+            // Every route in the table exists, and is refused without the
+            // right to use it.
+            assert_eq!(
+                send(&db, &student, method, &uri, None).await,
+                StatusCode::FORBIDDEN,
+                "{} {}",
+                method,
+                uri
+            );
+            let status = match policy {
+                Policy::Admin => {
+                    let token = issue_confirmation_token(
+                        &db,
+                        "anna",
+                        ConfirmationRequest {
+                            method: method.to_string(),
+                            path: uri.clone(),
+                            secret: secret.clone(),
+                        },
+                        Utc::now(),
+                    )
+                    .await
+                    .unwrap();
+                    // Not for anyone but the admin it was handed out to.
+                    assert_eq!(
+                        send(&db, &student, method, &uri, Some(&token.token)).await,
+                        StatusCode::FORBIDDEN
+                    );
+                    send(&db, &admin, method, &uri, Some(&token.token)).await
+                }
+                Policy::AdminOnly | Policy::SelfOrAdmin => {
+                    send(&db, &admin, method, &uri, None).await
+                }
+                _ => continue,
+            };
+            assert_ne!(status, StatusCode::FORBIDDEN, "{} {}", method, uri);
+        }
+
+        // Only real telescopes need a certified user during their booking,
+        // while admins may always control them.
+        assert_ne!(
+            send(&db, &student, "POST", "/api/telescopes/fake/target", None).await,
+            StatusCode::FORBIDDEN
+        );
+        assert_ne!(
+            send(&db, &admin, "POST", "/api/telescopes/brage/target", None).await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            policy("GET", "/api/telescopes/:telescope_id"),
+            Policy::Public
+        );
+        // Routes changing something are denied until they are given a
+        // policy.
+        assert_eq!(policy("POST", "/api/unlisted"), Policy::Denied);
+        assert_eq!(
+            send(&db, &admin, "POST", "/api/users/bertil", None).await,
+            StatusCode::FORBIDDEN
+        );
+    }
+}
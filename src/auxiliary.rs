@@ -0,0 +1,198 @@
+use crate::telescopes::TelescopeError;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::net::{SocketAddr, TcpStream};
+use std::str::FromStr;
+use std::time::Duration;
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+pub enum AuxiliaryDeviceKind {
+    Lna,
+    NoiseDiode,
+    Heater,
+    Other,
+}
+
+/// A device switched on and off by sending commands over TCP, e.g. to a
+/// relay on the controller or a network PDU.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct AuxiliaryDeviceDefinition {
+    pub name: String,
+    pub kind: AuxiliaryDeviceKind,
+    pub address: String,
+    pub on_command: String,
+    pub off_command: String,
+    /// State the device is put in when the telescope starts.
+    #[serde(default)]
+    pub default_on: bool,
+    /// Integrations are refused unless the device is on.
+    #[serde(default)]
+    pub required_for_integration: bool,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct AuxiliaryDeviceState {
+    pub name: String,
+    pub kind: AuxiliaryDeviceKind,
+    /// None until the device has been switched successfully.
+    pub on: Option<bool>,
+}
+
+/// The auxiliary devices of a telescope and their last known state.
+pub struct AuxiliaryDevices {
+    devices: Vec<(AuxiliaryDeviceDefinition, Option<bool>)>,
+    defaults_applied: bool,
+}
+
+impl AuxiliaryDevices {
+    pub fn new(definitions: Vec<AuxiliaryDeviceDefinition>) -> AuxiliaryDevices {
+        AuxiliaryDevices {
+            devices: definitions
+                .into_iter()
+                .map(|definition| (definition, None))
+                .collect(),
+            defaults_applied: false,
+        }
+    }
+
+    pub fn states(&self) -> Vec<AuxiliaryDeviceState> {
+        self.devices
+            .iter()
+            .map(|(definition, on)| AuxiliaryDeviceState {
+                name: definition.name.clone(),
+                kind: definition.kind,
+                on: *on,
+            })
+            .collect()
+    }
+
+    /// Switch the device called `name` using `switcher`, which does the
+    /// actual communication with the device.
+    pub fn switch(
+        &mut self,
+        name: &str,
+        on: bool,
+        switcher: impl FnOnce(&AuxiliaryDeviceDefinition, bool) -> Result<(), TelescopeError>,
+    ) -> Result<AuxiliaryDeviceState, TelescopeError> {
+        let (definition, state) = self
+            .devices
+            .iter_mut()
+            .find(|(definition, _)| definition.name == name)
+            .ok_or_else(|| TelescopeError::UnknownAuxiliaryDevice(name.to_string()))?;
+        switcher(definition, on)?;
+        *state = Some(on);
+        log::info!(
+            "Switched auxiliary device {} {}",
+            name,
+            if on { "on" } else { "off" }
+        );
+        Ok(AuxiliaryDeviceState {
+            name: definition.name.clone(),
+            kind: definition.kind,
+            on: *state,
+        })
+    }
+
+    /// Put all devices in their default state. Only done once, devices that
+    /// fail to switch are left in an unknown state.
+    pub fn apply_defaults(
+        &mut self,
+        mut switcher: impl FnMut(&AuxiliaryDeviceDefinition, bool) -> Result<(), TelescopeError>,
+    ) {
+        if self.defaults_applied {
+            return;
+        }
+        self.defaults_applied = true;
+        for (definition, state) in self.devices.iter_mut() {
+            match switcher(definition, definition.default_on) {
+                Ok(()) => *state = Some(definition.default_on),
+                Err(error) => log::error!(
+                    "Failed to switch auxiliary device {} to its default state: {}",
+                    definition.name,
+                    error
+                ),
+            }
+        }
+    }
+
+    /// Check that all devices required for integration are known to be on.
+    pub fn integration_allowed(&self) -> bool {
+        self.devices
+            .iter()
+            .filter(|(definition, _)| definition.required_for_integration)
+            .all(|(_, on)| *on == Some(true))
+    }
+}
+
+/// Send a single command to a device listening on a TCP socket.
+pub fn send_switch_command(address: &str, command: &str) -> Result<(), TelescopeError> {
+    let timeout = Duration::from_secs(1);
+    let socket_address = SocketAddr::from_str(address).map_err(|error| {
+        TelescopeError::TelescopeIOError(format!("Invalid address {}: {}", address, error))
+    })?;
+    let mut stream = TcpStream::connect_timeout(&socket_address, timeout)?;
+    stream.set_write_timeout(Some(timeout))?;
+    stream.write_all(command.as_bytes())?;
+    Ok(())
+}
+
+pub fn switch_over_tcp(
+    definition: &AuxiliaryDeviceDefinition,
+    on: bool,
+) -> Result<(), TelescopeError> {
+    let command = if on {
+        &definition.on_command
+    } else {
+        &definition.off_command
+    };
+    send_switch_command(&definition.address, command)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn lna() -> AuxiliaryDeviceDefinition {
+        AuxiliaryDeviceDefinition {
+            name: "lna".to_string(),
+            kind: AuxiliaryDeviceKind::Lna,
+            address: "127.0.0.1:3003".to_string(),
+            on_command: "lna on".to_string(),
+            off_command: "lna off".to_string(),
+            default_on: true,
+            required_for_integration: true,
+        }
+    }
+
+    #[test]
+    fn test_integration_interlock() {
+        let mut devices = AuxiliaryDevices::new(vec![lna()]);
+        assert!(!devices.integration_allowed());
+
+        devices.apply_defaults(|_, _| Ok(()));
+        assert!(devices.integration_allowed());
+
+        devices.switch("lna", false, |_, _| Ok(())).unwrap();
+        assert!(!devices.integration_allowed());
+    }
+
+    #[test]
+    fn test_failed_switch_keeps_state() {
+        let mut devices = AuxiliaryDevices::new(vec![lna()]);
+        devices.apply_defaults(|_, _| Ok(()));
+        let result = devices.switch("lna", false, |_, _| {
+            Err(TelescopeError::TelescopeNotConnected)
+        });
+        assert_eq!(result, Err(TelescopeError::TelescopeNotConnected));
+        assert_eq!(devices.states()[0].on, Some(true));
+    }
+
+    #[test]
+    fn test_switch_unknown_device() {
+        let mut devices = AuxiliaryDevices::new(vec![lna()]);
+        assert_eq!(
+            devices.switch("heater", true, |_, _| Ok(())),
+            Err(TelescopeError::UnknownAuxiliaryDevice("heater".to_string()))
+        );
+    }
+}
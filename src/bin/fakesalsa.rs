@@ -1,79 +1,55 @@
-use hex_literal::hex;
-use std::io::prelude::*;
-use std::net::{TcpListener, TcpStream};
+use clap::{Parser, ValueEnum};
+use std::net::TcpListener;
 use std::process;
 
-fn handle(request: &[u8]) -> [u8; 12] {
-    match request {
-        // Direction req
-        hex!("57 00 00 00"
-             "00 00 00 00"
-             "00 00 00 6F") => {
-            println!("Got direction request");
-            hex!("58 00 00 00"
-                 "00 00 00 00"
-                 "00 00 00 20")
-        }
-        // Stop req
-        hex!("57 00 00 00"
-             "00 00 00 00"
-             "00 00 00 0F") => {
-            println!("Got stop request");
-            // ACK
-            hex!("57 00 00 00"
-                 "00 00 00 00"
-                 "00 00 00 20")
-        }
-        _ => {
-            println!("Unknown request. Data: {:02X?}", request);
-            // FIXME: Is this a proper error
-            hex!("57 00 00 00"
-                 "00 00 00 00"
-                 "00 00 00 00")
-        }
-    }
+// Shared with the backend, which only uses parts of them.
+#[allow(dead_code)]
+#[path = "../angles.rs"]
+mod angles;
+#[allow(dead_code)]
+#[path = "../rot2prog/mod.rs"]
+mod rot2prog;
+
+use rot2prog::fake_controller::FakeController;
+use rot2prog::Rot2ProgEncoding;
+
+#[derive(ValueEnum, Copy, Clone, Debug)]
+enum Encoding {
+    /// Digits as ASCII characters, as documented.
+    Ascii,
+    /// Digits as raw bytes, as seen from the real controllers.
+    Raw,
 }
 
-fn controller_connection(mut stream: TcpStream) {
-    loop {
-        let mut command_buffer = [0; 13];
-        match stream.read(&mut command_buffer) {
-            Ok(0) => {
-                println!("Client closed connection.");
-                break;
-            }
-            Ok(13) => {
-                println!("Client sent: {:02X?}", command_buffer);
-                let response = handle(&command_buffer[0..12]);
-                // FIXME: Error handling
-                stream.write(&response).unwrap();
-            }
-            Ok(n) => {
-                println!(
-                    "Client sent {} bytes, expected 13. Data: {:02X?}",
-                    n, command_buffer
-                );
-            }
-            _ => {
-                // FIXME: Handle these errors more gracefully.
-                println!("Something went wrong!");
-            }
-        };
-    }
+#[derive(Parser, Debug)]
+#[command(about = "Fake rot2prog telescope controller")]
+struct Args {
+    #[arg(long, default_value = "127.0.0.1:3001")]
+    address: String,
+
+    /// Encoding of the angles in responses.
+    #[arg(long, value_enum, default_value_t = Encoding::Raw)]
+    encoding: Encoding,
 }
 
 fn main() {
-    let address = "127.0.0.1:3001";
-    let listener = match TcpListener::bind(address) {
+    env_logger::init();
+    let args = Args::parse();
+    let encoding = match args.encoding {
+        Encoding::Ascii => Rot2ProgEncoding::Ascii,
+        Encoding::Raw => Rot2ProgEncoding::Raw,
+    };
+    let listener = match TcpListener::bind(&args.address) {
         Ok(listener) => listener,
         Err(err) => {
-            println!("Failed to bind to address {} ({})", address, err);
+            println!("Failed to bind to address {} ({})", args.address, err);
             process::exit(1);
         }
     };
+    let mut controller = FakeController::new(encoding);
     for stream in listener.incoming() {
         match stream {
-            Ok(stream) => controller_connection(stream),
+            Ok(stream) => controller.serve(stream),
             Err(err) => {
                 println!("Failed to accept connection ({})", err);
             }
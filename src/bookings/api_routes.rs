@@ -1,12 +1,22 @@
-use crate::bookings::{AddBookingError, AddBookingResult, Booking};
+use crate::authentication::Requester;
+use crate::bookings::calendar::{bookings_calendar, calendar_token, CalendarToken};
+use crate::bookings::{
+    reschedule_booking, AddBookingError, AddBookingResult, Booking, BookingFilter,
+    BookingReschedule, ChangeBookingError, NewBooking,
+};
 use crate::database::{DataBase, DataBaseError, Storage};
+use crate::trash::{trash_booking, TrashError, TrashedItem};
+use crate::users::check_booking_allowed;
 use axum::{
-    extract::{Json, State},
-    http::StatusCode,
-    response::IntoResponse,
-    routing::get,
+    extract::{Json, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
     Router,
 };
+use chrono::Utc;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
 
 impl From<DataBaseError> for AddBookingError {
     fn from(_source: DataBaseError) -> Self {
@@ -16,11 +26,26 @@ impl From<DataBaseError> for AddBookingError {
 
 pub fn routes(database: DataBase<impl Storage + 'static>) -> Router {
     Router::new()
-        .route("/", get(get_bookings).post(add_booking_route))
+        .route(
+            "/",
+            get(get_bookings)
+                .post(add_booking_route)
+                .patch(reschedule_booking_route)
+                .delete(delete_booking),
+        )
+        .route("/calendar.ics", get(get_calendar))
+        .route("/calendar_token", post(create_calendar_token))
         .with_state(database)
 }
 
-pub async fn get_bookings<StorageType>(State(db): State<DataBase<StorageType>>) -> impl IntoResponse
+/// The bookings matching the query parameters. The response carries an ETag
+/// of its content, so that a client that already has it gets a 304 when it
+/// asks again with If-None-Match.
+pub async fn get_bookings<StorageType>(
+    State(db): State<DataBase<StorageType>>,
+    Query(filter): Query<BookingFilter>,
+    headers: HeaderMap,
+) -> Response
 where
     StorageType: Storage,
 {
@@ -28,13 +53,33 @@ where
         .get_data()
         .await
         .expect("As long as no one is manually editing the database, this should never fail.");
-    Json(data_model.bookings)
+    let bookings = filter.apply(&data_model.bookings);
+    let body = serde_json::to_vec(&bookings).expect("Bookings can always be serialized");
+    let etag = format!("\"{}\"", hex::encode(Sha256::digest(&body)));
+    let cached = headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|tag| tag.trim() == etag || tag.trim() == "*");
+    if cached {
+        return (StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response();
+    }
+    (
+        [
+            (header::CONTENT_TYPE, "application/json".to_string()),
+            (header::ETAG, etag),
+        ],
+        body,
+    )
+        .into_response()
 }
 
+/// Add the booking of an existing user.
 pub async fn add_booking(db: DataBase<impl Storage>, booking: Booking) -> AddBookingResult {
-    if db
-        .get_data()
-        .await?
+    let data_model = db.get_data().await?;
+    check_booking_allowed(&data_model, &booking)?;
+    if data_model
         .bookings
         .iter()
         .filter(|b| b.telescope_name == booking.telescope_name && b.overlaps(&booking))
@@ -54,25 +99,125 @@ pub async fn add_booking(db: DataBase<impl Storage>, booking: Booking) -> AddBoo
     Ok(db.get_data().await?.bookings.len() as u64)
 }
 
+/// Book a telescope for the requester.
 pub async fn add_booking_route(
     State(db): State<DataBase<impl Storage>>,
-    Json(booking): Json<Booking>,
+    requester: Requester,
+    Json(booking): Json<NewBooking>,
 ) -> (StatusCode, Json<AddBookingResult>) {
-    let payload = add_booking(db, booking).await;
+    let payload = match requester.0 {
+        Some(user_name) => add_booking(db, booking.for_user(&user_name)).await,
+        None => Err(AddBookingError::NotAuthenticated),
+    };
     let status_code = match payload {
         Ok(_) => StatusCode::CREATED,
+        Err(AddBookingError::NotAuthenticated) => StatusCode::UNAUTHORIZED,
         Err(AddBookingError::Conflict) => StatusCode::CONFLICT,
+        Err(AddBookingError::NotCertified)
+        | Err(AddBookingError::DemoLimitExceeded)
+        | Err(AddBookingError::SessionTooLong)
+        | Err(AddBookingError::TooManyBookings)
+        | Err(AddBookingError::TooShortNotice) => StatusCode::FORBIDDEN,
         Err(AddBookingError::ServiceUnavailable) => StatusCode::SERVICE_UNAVAILABLE,
     };
     (status_code, Json(payload))
 }
 
+/// Move a booking to the trash, from where it can be restored. Bookings
+/// have no id, so the whole booking is given. Only the user who made it and
+/// admins may delete it.
+async fn delete_booking(
+    State(db): State<DataBase<impl Storage>>,
+    requester: Requester,
+    Json(booking): Json<Booking>,
+) -> Result<Json<TrashedItem>, TrashError> {
+    Ok(Json(
+        trash_booking(&db, &booking, requester.name(), Utc::now()).await?,
+    ))
+}
+
+/// Move a booking to new times. Only the user who made it and admins may
+/// do so, and the telescope has to be free at the new times.
+async fn reschedule_booking_route(
+    State(db): State<DataBase<impl Storage>>,
+    requester: Requester,
+    Json(reschedule): Json<BookingReschedule>,
+) -> (StatusCode, Json<Result<Booking, ChangeBookingError>>) {
+    let payload = reschedule_booking(&db, &reschedule, requester.name(), Utc::now()).await;
+    let status_code = match payload {
+        Ok(_) => StatusCode::OK,
+        Err(ChangeBookingError::NotFound) => StatusCode::NOT_FOUND,
+        Err(ChangeBookingError::InvalidTime) => StatusCode::BAD_REQUEST,
+        Err(ChangeBookingError::Conflict) => StatusCode::CONFLICT,
+        Err(ChangeBookingError::NotAllowed)
+        | Err(ChangeBookingError::NotCertified)
+        | Err(ChangeBookingError::DemoLimitExceeded)
+        | Err(ChangeBookingError::SessionTooLong)
+        | Err(ChangeBookingError::TooManyBookings)
+        | Err(ChangeBookingError::TooShortNotice) => StatusCode::FORBIDDEN,
+        Err(ChangeBookingError::ServiceUnavailable) => StatusCode::SERVICE_UNAVAILABLE,
+    };
+    (status_code, Json(payload))
+}
+
+#[derive(Deserialize, Debug)]
+struct CalendarQuery {
+    token: Option<String>,
+}
+
+/// The bookings as an iCalendar feed, only those of the user whose token is
+/// given, if one is.
+async fn get_calendar(
+    State(db): State<DataBase<impl Storage>>,
+    Query(query): Query<CalendarQuery>,
+) -> Response {
+    let data_model = db
+        .get_data()
+        .await
+        .expect("As long as no one is manually editing the database, this should never fail.");
+    let mut filter = BookingFilter::default();
+    if let Some(token) = query.token {
+        let Some(calendar_token) = data_model.calendar_tokens.iter().find(|t| t.token == token)
+        else {
+            return (StatusCode::FORBIDDEN, "Unknown calendar token".to_string()).into_response();
+        };
+        filter.user = Some(calendar_token.user_name.clone());
+    }
+    (
+        [
+            (header::CONTENT_TYPE, "text/calendar; charset=utf-8"),
+            (
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"salsa-bookings.ics\"",
+            ),
+        ],
+        bookings_calendar(&filter.apply(&data_model.bookings), Utc::now()),
+    )
+        .into_response()
+}
+
+/// The token for the calendar feed of the requester's own bookings.
+async fn create_calendar_token(
+    State(db): State<DataBase<impl Storage>>,
+    requester: Requester,
+) -> Result<Json<CalendarToken>, StatusCode> {
+    let Some(user_name) = requester.0 else {
+        return Err(StatusCode::UNAUTHORIZED);
+    };
+    calendar_token(&db, &user_name, Utc::now())
+        .await
+        .map(Json)
+        .map_err(|_| StatusCode::SERVICE_UNAVAILABLE)
+}
+
 #[cfg(test)]
 mod test {
     use crate::database::create_in_memory_database;
 
     use super::*;
+    use crate::authentication::AuthenticatedUser;
     use crate::bookings::Booking;
+    use crate::users::ensure_user;
     use axum::{
         body::Body,
         http::{self, Request, StatusCode},
@@ -116,42 +261,213 @@ mod test {
     }
 
     #[tokio::test]
-    async fn test_add_booking() {
+    async fn test_get_bookings_filter_and_etag() {
+        let now = chrono::Utc::now();
+        let booking = |user_name: &str| Booking {
+            telescope_name: "test-telescope".to_string(),
+            user_name: user_name.to_string(),
+            start_time: now,
+            end_time: now + chrono::Duration::hours(1),
+        };
         let db = create_in_memory_database();
-        let app = routes(db.clone());
+        db.update_data(|mut datamodel| {
+            datamodel.bookings.push(booking("anna"));
+            datamodel.bookings.push(booking("bertil"));
+            datamodel
+        })
+        .await
+        .unwrap();
+        let get = |if_none_match: Option<String>| {
+            let mut request = Request::builder()
+                .method(http::Method::GET)
+                .uri("/?user=bertil&limit=10");
+            if let Some(etag) = if_none_match {
+                request = request.header(http::header::IF_NONE_MATCH, etag);
+            }
+            routes(db.clone()).oneshot(request.body(Body::empty()).unwrap())
+        };
+
+        let response = get(None).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let etag = response.headers()[http::header::ETAG]
+            .to_str()
+            .unwrap()
+            .to_string();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let bookings: Vec<Booking> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(bookings, vec![booking("bertil")]);
+
+        let response = get(Some(etag.clone())).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
 
+        db.update_data(|mut datamodel| {
+            datamodel.bookings.push(booking("bertil"));
+            datamodel
+        })
+        .await
+        .unwrap();
+        let response = get(Some(etag)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_reschedule_booking() {
+        let now = chrono::Utc::now();
         let booking = Booking {
             telescope_name: "test-telescope".to_string(),
             user_name: "test-user".to_string(),
-            start_time: chrono::Utc::now(),
-            end_time: chrono::Utc::now(),
+            start_time: now,
+            end_time: now + chrono::Duration::hours(1),
+        };
+        let db = create_in_memory_database();
+        db.update_data(|mut datamodel| {
+            datamodel.bookings.push(booking.clone());
+            datamodel
+        })
+        .await
+        .unwrap();
+        let reschedule = BookingReschedule {
+            booking: booking.clone(),
+            start_time: now + chrono::Duration::hours(2),
+            end_time: now + chrono::Duration::hours(3),
+        };
+        let patch = |user_name: &str| {
+            Request::builder()
+                .method(http::Method::PATCH)
+                .uri("/")
+                .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                .extension(AuthenticatedUser(user_name.to_string()))
+                .body(Body::from(serde_json::to_vec(&reschedule).unwrap()))
+                .unwrap()
         };
 
-        let response = app
+        let response = routes(db.clone()).oneshot(patch("someone")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let response = routes(db.clone())
+            .oneshot(patch("test-user"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            db.get_data().await.unwrap().bookings[0].start_time,
+            reschedule.start_time
+        );
+
+        // The old booking is gone.
+        let response = routes(db.clone())
+            .oneshot(patch("test-user"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_get_calendar() {
+        let now = chrono::Utc::now();
+        let booking = |user_name: &str| Booking {
+            telescope_name: format!("{}-telescope", user_name),
+            user_name: user_name.to_string(),
+            start_time: now,
+            end_time: now + chrono::Duration::hours(1),
+        };
+        let db = create_in_memory_database();
+        db.update_data(|mut datamodel| {
+            datamodel.bookings.push(booking("anna"));
+            datamodel.bookings.push(booking("bertil"));
+            datamodel
+        })
+        .await
+        .unwrap();
+        let get = |uri: String| {
+            routes(db.clone()).oneshot(
+                Request::builder()
+                    .method(http::Method::GET)
+                    .uri(uri)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+        };
+
+        let response = get("/calendar.ics".to_string()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[http::header::CONTENT_TYPE],
+            "text/calendar; charset=utf-8"
+        );
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let calendar = String::from_utf8(body.to_vec()).unwrap();
+        assert_eq!(calendar.matches("BEGIN:VEVENT").count(), 2);
+
+        let response = routes(db.clone())
             .oneshot(
                 Request::builder()
                     .method(http::Method::POST)
-                    .uri("/")
-                    .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
-                    .body(Body::from(serde_json::to_vec(&booking.clone()).unwrap()))
+                    .uri("/calendar_token")
+                    .extension(AuthenticatedUser("anna".to_string()))
+                    .body(Body::empty())
                     .unwrap(),
             )
             .await
             .unwrap();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let token: CalendarToken = serde_json::from_slice(&body).unwrap();
+
+        let response = get(format!("/calendar.ics?token={}", token.token))
+            .await
+            .unwrap();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let calendar = String::from_utf8(body.to_vec()).unwrap();
+        assert_eq!(calendar.matches("BEGIN:VEVENT").count(), 1);
+        assert!(calendar.contains("anna-telescope"));
+
+        let response = get("/calendar.ics?token=wrong".to_string()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
 
+    #[tokio::test]
+    async fn test_add_booking() {
+        let db = create_in_memory_database();
+        ensure_user(&db, "test-user").await.unwrap();
+        let post = |user_name: Option<&str>, body: Vec<u8>| {
+            let mut request = Request::builder()
+                .method(http::Method::POST)
+                .uri("/")
+                .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref());
+            if let Some(user_name) = user_name {
+                request = request.extension(AuthenticatedUser(user_name.to_string()));
+            }
+            routes(db.clone()).oneshot(request.body(Body::from(body)).unwrap())
+        };
+
+        let booking = Booking {
+            telescope_name: "test-telescope".to_string(),
+            user_name: "test-user".to_string(),
+            start_time: chrono::Utc::now(),
+            end_time: chrono::Utc::now(),
+        };
+        // The booker is the requester, not whoever the body names.
+        let body = serde_json::to_vec(&Booking {
+            user_name: "admin".to_string(),
+            ..booking.clone()
+        })
+        .unwrap();
+
+        let response = post(None, body.clone()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert!(db.get_data().await.unwrap().bookings.is_empty());
+
+        let response = post(Some("test-user"), body).await.unwrap();
         assert_eq!(response.status(), StatusCode::CREATED);
         let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
         let res: AddBookingResult = serde_json::from_slice(&body).unwrap();
         assert_eq!(res, Ok(1)); // 1 because the database is empty before the request
 
-        assert_eq!(
-            vec![booking],
-            db.get_data()
-                .await
-                .expect(
-                    "As long as no one is manually editing the database, this should never fail."
-                )
-                .bookings
-        );
+        let data_model = db
+            .get_data()
+            .await
+            .expect("As long as no one is manually editing the database, this should never fail.");
+        assert_eq!(vec![booking], data_model.bookings);
+        assert_eq!(data_model.users.len(), 1);
     }
 }
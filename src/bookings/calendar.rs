@@ -0,0 +1,163 @@
+//! Bookings as an iCalendar feed (RFC 5545), for subscribing to them in a
+//! calendar application.
+//!
+//! Calendar applications can not send an API token, so a feed of the
+//! bookings of one user is instead read with a calendar token of that user
+//! in the query. Without a token the feed holds all bookings.
+
+use crate::bookings::Booking;
+use crate::database::{DataBase, DataBaseError, Storage};
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+/// Longest line in octets, longer lines are folded.
+const MAX_LINE_OCTETS: usize = 75;
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct CalendarToken {
+    pub user_name: String,
+    pub token: String,
+    pub created: DateTime<Utc>,
+}
+
+fn new_token() -> String {
+    let bytes: [u8; 16] = rand::thread_rng().gen();
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// The calendar token of `user_name`, created if the user has none. Users
+/// have a single token, so a feed url stays the same.
+pub async fn calendar_token(
+    db: &DataBase<impl Storage>,
+    user_name: &str,
+    now: DateTime<Utc>,
+) -> Result<CalendarToken, DataBaseError> {
+    let mut token = None;
+    db.update_data(|mut data_model| {
+        let existing = data_model
+            .calendar_tokens
+            .iter()
+            .find(|t| t.user_name == user_name)
+            .cloned();
+        token = Some(existing.unwrap_or_else(|| {
+            let token = CalendarToken {
+                user_name: user_name.to_string(),
+                token: new_token(),
+                created: now,
+            };
+            data_model.calendar_tokens.push(token.clone());
+            token
+        }));
+        data_model
+    })
+    .await?;
+    Ok(token.expect("The token is always set by the update"))
+}
+
+/// Escape text for a property value.
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n")
+}
+
+/// Fold a content line into lines of at most [`MAX_LINE_OCTETS`] octets,
+/// each ended by CRLF, without splitting characters.
+fn fold(line: &str) -> String {
+    let mut folded = String::new();
+    let mut octets = 0;
+    for c in line.chars() {
+        if octets + c.len_utf8() > MAX_LINE_OCTETS {
+            folded.push_str("\r\n ");
+            // The leading space counts towards the continued line.
+            octets = 1;
+        }
+        folded.push(c);
+        octets += c.len_utf8();
+    }
+    folded.push_str("\r\n");
+    folded
+}
+
+fn format_time(time: DateTime<Utc>) -> String {
+    time.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+/// The bookings as an iCalendar file, stamped with `now`.
+pub fn bookings_calendar(bookings: &[Booking], now: DateTime<Utc>) -> String {
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".to_string(),
+        "PRODID:-//SALSA//Bookings//EN".to_string(),
+        "X-WR-CALNAME:SALSA bookings".to_string(),
+    ];
+    for booking in bookings {
+        lines.extend([
+            "BEGIN:VEVENT".to_string(),
+            // Bookings have no id, but a telescope is only booked once at a
+            // time.
+            format!(
+                "UID:{}-{}@salsa",
+                format_time(booking.start_time),
+                escape(&booking.telescope_name)
+            ),
+            format!("DTSTAMP:{}", format_time(now)),
+            format!("DTSTART:{}", format_time(booking.start_time)),
+            format!("DTEND:{}", format_time(booking.end_time)),
+            format!(
+                "SUMMARY:{}",
+                escape(&format!(
+                    "{} booked by {}",
+                    booking.telescope_name, booking.user_name
+                ))
+            ),
+            "END:VEVENT".to_string(),
+        ]);
+    }
+    lines.push("END:VCALENDAR".to_string());
+    lines.iter().map(|line| fold(line)).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::database::create_in_memory_database;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_bookings_calendar() {
+        let start = Utc.with_ymd_and_hms(2024, 3, 1, 13, 0, 0).unwrap();
+        let booking = Booking {
+            start_time: start,
+            end_time: start + chrono::Duration::hours(2),
+            telescope_name: "brage".to_string(),
+            user_name: "anna, class 3b".to_string(),
+        };
+        let calendar = bookings_calendar(&[booking], start);
+        assert!(calendar.starts_with("BEGIN:VCALENDAR\r\nVERSION:2.0\r\n"));
+        assert!(calendar.contains("\r\nUID:20240301T130000Z-brage@salsa\r\n"));
+        assert!(calendar.contains("\r\nDTSTART:20240301T130000Z\r\nDTEND:20240301T150000Z\r\n"));
+        assert!(calendar.contains("\r\nSUMMARY:brage booked by anna\\, class 3b\r\n"));
+        assert!(calendar.ends_with("END:VEVENT\r\nEND:VCALENDAR\r\n"));
+    }
+
+    #[test]
+    fn test_fold() {
+        let line = format!("SUMMARY:{}", "å".repeat(40));
+        let folded = fold(&line);
+        assert!(folded.split("\r\n").all(|l| l.len() <= MAX_LINE_OCTETS));
+        assert_eq!(folded.replace("\r\n ", ""), format!("{}\r\n", line));
+    }
+
+    #[tokio::test]
+    async fn test_calendar_token() {
+        let db = create_in_memory_database();
+        let now = Utc::now();
+        let token = calendar_token(&db, "anna", now).await.unwrap();
+        assert_eq!(calendar_token(&db, "anna", now).await.unwrap(), token);
+        assert_ne!(calendar_token(&db, "bertil", now).await.unwrap(), token);
+        assert_eq!(db.get_data().await.unwrap().calendar_tokens.len(), 2);
+    }
+}
@@ -1,7 +1,10 @@
-use chrono::{DateTime, Utc};
+use crate::database::{DataBase, DataBaseError, DataModel, Storage};
+use crate::users::{check_booking_allowed, may_change_booking};
+use chrono::{DateTime, Duration, Utc};
 use serde::{Deserialize, Serialize};
 
 pub mod api_routes;
+pub mod calendar;
 pub mod routes;
 
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
@@ -12,18 +15,494 @@ pub struct Booking {
     pub user_name: String,
 }
 
+/// A booking as requested, made for the user requesting it.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct NewBooking {
+    pub start_time: DateTime<Utc>,
+    pub end_time: DateTime<Utc>,
+    pub telescope_name: String,
+}
+
+impl NewBooking {
+    pub fn for_user(self, user_name: &str) -> Booking {
+        Booking {
+            start_time: self.start_time,
+            end_time: self.end_time,
+            telescope_name: self.telescope_name,
+            user_name: user_name.to_string(),
+        }
+    }
+}
+
 impl Booking {
     pub fn overlaps(&self, other: &Booking) -> bool {
         self.end_time >= other.start_time && self.start_time <= other.end_time
     }
+
+    pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+        self.start_time <= now && now < self.end_time
+    }
+}
+
+/// The booking of `telescope_name` that is going on at `now`, if any.
+pub fn current_booking<'a>(
+    bookings: &'a [Booking],
+    telescope_name: &str,
+    now: DateTime<Utc>,
+) -> Option<&'a Booking> {
+    bookings
+        .iter()
+        .find(|b| b.telescope_name == telescope_name && b.is_active(now))
+}
+
+/// The first time from `now` on when `telescope_name` is not booked.
+pub fn next_free_slot(
+    bookings: &[Booking],
+    telescope_name: &str,
+    now: DateTime<Utc>,
+) -> DateTime<Utc> {
+    let mut free = now;
+    // Bookings may follow right after each other, so keep going until no
+    // booking covers the candidate time.
+    while let Some(booking) = current_booking(bookings, telescope_name, free) {
+        free = booking.end_time;
+    }
+    free
+}
+
+/// Which bookings to list, given as query parameters. Bookings are kept in
+/// the order they were made, so `offset` and `limit` page through them.
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct BookingFilter {
+    /// Only bookings that end after this time.
+    #[serde(default)]
+    pub from: Option<DateTime<Utc>>,
+    /// Only bookings that start before this time.
+    #[serde(default)]
+    pub to: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub telescope: Option<String>,
+    #[serde(default)]
+    pub user: Option<String>,
+    #[serde(default)]
+    pub offset: usize,
+    #[serde(default)]
+    pub limit: Option<usize>,
+}
+
+impl BookingFilter {
+    pub fn matches(&self, booking: &Booking) -> bool {
+        self.from.is_none_or(|from| booking.end_time > from)
+            && self.to.is_none_or(|to| booking.start_time < to)
+            && self
+                .telescope
+                .as_ref()
+                .is_none_or(|telescope| &booking.telescope_name == telescope)
+            && self
+                .user
+                .as_ref()
+                .is_none_or(|user| &booking.user_name == user)
+    }
+
+    /// The page of matching bookings.
+    pub fn apply(&self, bookings: &[Booking]) -> Vec<Booking> {
+        bookings
+            .iter()
+            .filter(|booking| self.matches(booking))
+            .skip(self.offset)
+            .take(self.limit.unwrap_or(usize::MAX))
+            .cloned()
+            .collect()
+    }
+}
+
+/// Limits on the bookings of users other than admins. Nothing is limited
+/// by default.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Default)]
+pub struct BookingPolicy {
+    /// Longest booking in minutes.
+    #[serde(default)]
+    pub max_session_minutes: Option<i64>,
+    /// Most bookings a user may have that have not ended yet.
+    #[serde(default)]
+    pub max_outstanding_bookings: Option<usize>,
+    /// How many minutes ahead a booking has to be made.
+    #[serde(default)]
+    pub min_lead_minutes: Option<i64>,
+}
+
+impl BookingPolicy {
+    pub fn check(
+        &self,
+        bookings: &[Booking],
+        booking: &Booking,
+        now: DateTime<Utc>,
+    ) -> Result<(), AddBookingError> {
+        if let Some(max_session_minutes) = self.max_session_minutes {
+            if booking.end_time - booking.start_time > Duration::minutes(max_session_minutes) {
+                return Err(AddBookingError::SessionTooLong);
+            }
+        }
+        if let Some(min_lead_minutes) = self.min_lead_minutes {
+            if booking.start_time < now + Duration::minutes(min_lead_minutes) {
+                return Err(AddBookingError::TooShortNotice);
+            }
+        }
+        if let Some(max_outstanding_bookings) = self.max_outstanding_bookings {
+            let outstanding = bookings
+                .iter()
+                .filter(|b| b.user_name == booking.user_name && b.end_time > now)
+                .count();
+            if outstanding >= max_outstanding_bookings {
+                return Err(AddBookingError::TooManyBookings);
+            }
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
 pub enum AddBookingError {
     ServiceUnavailable,
+    /// Only users who are logged in may book.
+    NotAuthenticated,
     Conflict,
+    NotCertified,
+    DemoLimitExceeded,
+    /// The booking is longer than the booking policy allows.
+    SessionTooLong,
+    /// The user already has as many upcoming bookings as allowed.
+    TooManyBookings,
+    /// The booking starts too soon to be made now.
+    TooShortNotice,
     // NotFuture - booking is entirely(?) in the past
     // NonPositiveDuration - booking ends before it starts
 }
 
+impl AddBookingError {
+    /// Why the booking was refused, to show to the user.
+    pub fn explanation(&self) -> &'static str {
+        match self {
+            AddBookingError::ServiceUnavailable => {
+                "The booking could not be saved, try again later"
+            }
+            AddBookingError::NotAuthenticated => "Log in to book a telescope",
+            AddBookingError::Conflict => "The telescope is already booked at that time",
+            AddBookingError::NotCertified => "Only certified users may book this telescope",
+            AddBookingError::DemoLimitExceeded => "Demo accounts may only make one short booking",
+            AddBookingError::SessionTooLong => "The booking is longer than allowed",
+            AddBookingError::TooManyBookings => {
+                "You already have as many upcoming bookings as allowed"
+            }
+            AddBookingError::TooShortNotice => "The booking has to be made further in advance",
+        }
+    }
+}
+
 pub type AddBookingResult = Result<u64, AddBookingError>;
+
+/// New times of a booking, which is identified by its old value since
+/// bookings have no id.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct BookingReschedule {
+    pub booking: Booking,
+    pub start_time: DateTime<Utc>,
+    pub end_time: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub enum BookingChangeKind {
+    Rescheduled {
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+    },
+    Cancelled,
+}
+
+/// A change of a booking after it was made, kept in the audit trail of
+/// bookings.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct BookingChange {
+    pub time: DateTime<Utc>,
+    pub changed_by: String,
+    /// The booking before the change.
+    pub booking: Booking,
+    pub change: BookingChangeKind,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub enum ChangeBookingError {
+    NotFound,
+    /// Only the user who made a booking, or an admin, may change it.
+    NotAllowed,
+    /// The booking ends before it starts.
+    InvalidTime,
+    Conflict,
+    NotCertified,
+    DemoLimitExceeded,
+    SessionTooLong,
+    TooManyBookings,
+    TooShortNotice,
+    ServiceUnavailable,
+}
+
+impl From<DataBaseError> for ChangeBookingError {
+    fn from(_source: DataBaseError) -> Self {
+        Self::ServiceUnavailable
+    }
+}
+
+impl From<AddBookingError> for ChangeBookingError {
+    fn from(source: AddBookingError) -> Self {
+        match source {
+            AddBookingError::ServiceUnavailable => Self::ServiceUnavailable,
+            AddBookingError::NotAuthenticated => Self::NotAllowed,
+            AddBookingError::Conflict => Self::Conflict,
+            AddBookingError::NotCertified => Self::NotCertified,
+            AddBookingError::DemoLimitExceeded => Self::DemoLimitExceeded,
+            AddBookingError::SessionTooLong => Self::SessionTooLong,
+            AddBookingError::TooManyBookings => Self::TooManyBookings,
+            AddBookingError::TooShortNotice => Self::TooShortNotice,
+        }
+    }
+}
+
+pub fn record_booking_change(
+    data_model: &mut DataModel,
+    changed_by: &str,
+    booking: &Booking,
+    change: BookingChangeKind,
+    now: DateTime<Utc>,
+) {
+    data_model.booking_changes.push(BookingChange {
+        time: now,
+        changed_by: changed_by.to_string(),
+        booking: booking.clone(),
+        change,
+    });
+}
+
+fn reschedule(
+    data_model: &mut DataModel,
+    reschedule: &BookingReschedule,
+    requested_by: &str,
+    now: DateTime<Utc>,
+) -> Result<Booking, ChangeBookingError> {
+    if reschedule.end_time <= reschedule.start_time {
+        return Err(ChangeBookingError::InvalidTime);
+    }
+    let index = data_model
+        .bookings
+        .iter()
+        .position(|b| b == &reschedule.booking)
+        .ok_or(ChangeBookingError::NotFound)?;
+    if !may_change_booking(data_model, requested_by, &reschedule.booking) {
+        return Err(ChangeBookingError::NotAllowed);
+    }
+    let rescheduled = Booking {
+        start_time: reschedule.start_time,
+        end_time: reschedule.end_time,
+        ..reschedule.booking.clone()
+    };
+    // Check the new times as if the old booking was not there, so that it
+    // neither conflicts with nor counts against itself.
+    let mut others = data_model.clone();
+    others.bookings.remove(index);
+    check_booking_allowed(&others, &rescheduled)?;
+    if others
+        .bookings
+        .iter()
+        .any(|b| b.telescope_name == rescheduled.telescope_name && b.overlaps(&rescheduled))
+    {
+        return Err(ChangeBookingError::Conflict);
+    }
+    data_model.bookings[index] = rescheduled.clone();
+    record_booking_change(
+        data_model,
+        requested_by,
+        &reschedule.booking,
+        BookingChangeKind::Rescheduled {
+            start_time: rescheduled.start_time,
+            end_time: rescheduled.end_time,
+        },
+        now,
+    );
+    Ok(rescheduled)
+}
+
+/// Move a booking to new times, if the telescope is free then.
+pub async fn reschedule_booking(
+    db: &DataBase<impl Storage>,
+    booking_reschedule: &BookingReschedule,
+    requested_by: &str,
+    now: DateTime<Utc>,
+) -> Result<Booking, ChangeBookingError> {
+    let mut result = Err(ChangeBookingError::NotFound);
+    db.update_data(|mut data_model| {
+        result = reschedule(&mut data_model, booking_reschedule, requested_by, now);
+        data_model
+    })
+    .await?;
+    if let Ok(booking) = &result {
+        log::info!(
+            "Rescheduled booking of {} by {} to {}",
+            booking.telescope_name,
+            booking.user_name,
+            booking.start_time
+        );
+    }
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::Duration;
+
+    #[test]
+    fn test_next_free_slot() {
+        let now = Utc::now();
+        let booking = |start: i64, end: i64, telescope_name: &str| Booking {
+            start_time: now + Duration::hours(start),
+            end_time: now + Duration::hours(end),
+            telescope_name: telescope_name.to_string(),
+            user_name: "anna".to_string(),
+        };
+        let bookings = vec![
+            booking(-1, 1, "brage"),
+            booking(1, 2, "brage"),
+            booking(3, 4, "brage"),
+            booking(-1, 5, "vale"),
+        ];
+        assert_eq!(current_booking(&bookings, "brage", now), Some(&bookings[0]));
+        assert_eq!(
+            next_free_slot(&bookings, "brage", now),
+            now + Duration::hours(2)
+        );
+        assert_eq!(next_free_slot(&bookings, "torre", now), now);
+        assert_eq!(current_booking(&bookings, "torre", now), None);
+    }
+
+    #[test]
+    fn test_booking_policy() {
+        let now = Utc::now();
+        let booking = |start: i64, end: i64| Booking {
+            start_time: now + Duration::hours(start),
+            end_time: now + Duration::hours(end),
+            telescope_name: "brage".to_string(),
+            user_name: "anna".to_string(),
+        };
+        let bookings = vec![booking(-2, -1), booking(1, 2)];
+        assert_eq!(
+            BookingPolicy::default().check(&bookings, &booking(0, 10), now),
+            Ok(())
+        );
+        let policy = BookingPolicy {
+            max_session_minutes: Some(120),
+            max_outstanding_bookings: Some(2),
+            min_lead_minutes: Some(30),
+        };
+        assert_eq!(policy.check(&bookings, &booking(3, 5), now), Ok(()));
+        assert_eq!(
+            policy.check(&bookings, &booking(3, 6), now),
+            Err(AddBookingError::SessionTooLong)
+        );
+        assert_eq!(
+            policy.check(&bookings, &booking(0, 1), now),
+            Err(AddBookingError::TooShortNotice)
+        );
+        // The past booking does not count.
+        let bookings = vec![booking(-2, -1), booking(1, 2), booking(3, 4)];
+        assert_eq!(
+            policy.check(&bookings, &booking(5, 6), now),
+            Err(AddBookingError::TooManyBookings)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reschedule_booking() {
+        let now = Utc::now();
+        let booking = |start: i64, end: i64, user_name: &str| Booking {
+            start_time: now + Duration::hours(start),
+            end_time: now + Duration::hours(end),
+            telescope_name: "brage".to_string(),
+            user_name: user_name.to_string(),
+        };
+        let db = crate::database::create_in_memory_database();
+        db.update_data(|mut data_model| {
+            data_model.bookings.push(booking(1, 2, "anna"));
+            data_model.bookings.push(booking(3, 4, "bertil"));
+            data_model
+        })
+        .await
+        .unwrap();
+        let change = |start: i64, end: i64| BookingReschedule {
+            booking: booking(1, 2, "anna"),
+            start_time: now + Duration::hours(start),
+            end_time: now + Duration::hours(end),
+        };
+
+        assert_eq!(
+            reschedule_booking(&db, &change(2, 4), "anna", now).await,
+            Err(ChangeBookingError::Conflict)
+        );
+        assert_eq!(
+            reschedule_booking(&db, &change(2, 1), "anna", now).await,
+            Err(ChangeBookingError::InvalidTime)
+        );
+        assert_eq!(
+            reschedule_booking(&db, &change(0, 2), "bertil", now).await,
+            Err(ChangeBookingError::NotAllowed)
+        );
+        // Overlapping the old times of the booking itself is fine.
+        assert_eq!(
+            reschedule_booking(&db, &change(0, 2), "anna", now).await,
+            Ok(booking(0, 2, "anna"))
+        );
+
+        let data_model = db.get_data().await.unwrap();
+        assert_eq!(
+            data_model.bookings,
+            vec![booking(0, 2, "anna"), booking(3, 4, "bertil")]
+        );
+        assert_eq!(data_model.booking_changes.len(), 1);
+        assert_eq!(data_model.booking_changes[0].booking, booking(1, 2, "anna"));
+        assert_eq!(data_model.booking_changes[0].changed_by, "anna");
+    }
+
+    #[test]
+    fn test_booking_filter() {
+        let now = Utc::now();
+        let booking = |start: i64, end: i64, telescope_name: &str, user_name: &str| Booking {
+            start_time: now + Duration::hours(start),
+            end_time: now + Duration::hours(end),
+            telescope_name: telescope_name.to_string(),
+            user_name: user_name.to_string(),
+        };
+        let bookings = vec![
+            booking(-3, -2, "brage", "anna"),
+            booking(-1, 1, "brage", "bertil"),
+            booking(1, 2, "vale", "anna"),
+            booking(3, 4, "brage", "anna"),
+        ];
+        assert_eq!(BookingFilter::default().apply(&bookings), bookings);
+        let upcoming = BookingFilter {
+            from: Some(now),
+            ..Default::default()
+        };
+        assert_eq!(upcoming.apply(&bookings), bookings[1..]);
+        let before = BookingFilter {
+            to: Some(now + Duration::hours(1)),
+            telescope: Some("brage".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(before.apply(&bookings), bookings[..2]);
+        let page = BookingFilter {
+            user: Some("anna".to_string()),
+            offset: 1,
+            limit: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(page.apply(&bookings), vec![bookings[2].clone()]);
+    }
+}
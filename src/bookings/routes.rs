@@ -1,15 +1,28 @@
-use crate::bookings::Booking;
+use crate::authentication::Requester;
+use crate::bookings::{
+    reschedule_booking, AddBookingError, Booking, BookingFilter, BookingReschedule,
+};
 use crate::database::{DataBase, Storage};
+use crate::idempotency::new_idempotency_key;
 use crate::template::HtmlTemplate;
+use crate::trash::trash_booking;
+use crate::users::check_booking_allowed;
 use askama::Template;
 use axum::Form;
-use axum::{extract::State, response::IntoResponse, routing::get, Router};
+use axum::{
+    extract::{Query, State},
+    response::IntoResponse,
+    routing::{get, post},
+    Router,
+};
 use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc};
 use serde::Deserialize;
 
 pub fn routes(database: DataBase<impl Storage + 'static>) -> Router {
     Router::new()
         .route("/", get(get_bookings).post(create_booking))
+        .route("/delete", post(delete_booking))
+        .route("/reschedule", post(reschedule))
         .with_state(database)
 }
 
@@ -18,9 +31,18 @@ pub fn routes(database: DataBase<impl Storage + 'static>) -> Router {
 struct BookingsTemplate {
     bookings: Vec<Booking>,
     telescope_names: Vec<String>,
+    /// Sent with the booking form, so that submitting it twice only books once.
+    idempotency_key: String,
+    /// Why the booking just submitted was refused.
+    refusal: Option<String>,
 }
 
-async fn get_bookings<StorageType>(State(db): State<DataBase<StorageType>>) -> impl IntoResponse
+/// Bookings matching the query parameters, by default those that have not
+/// ended yet, so that the page does not grow with the history.
+async fn get_bookings<StorageType>(
+    State(db): State<DataBase<StorageType>>,
+    Query(mut filter): Query<BookingFilter>,
+) -> impl IntoResponse
 where
     StorageType: Storage,
 {
@@ -28,7 +50,8 @@ where
         .get_data()
         .await
         .expect("As long as no one is manually editing the database, this should never fail.");
-    let bookings = data_model.bookings;
+    filter.from = filter.from.or(Some(Utc::now()));
+    let bookings = filter.apply(&data_model.bookings);
     let telescope_names: Vec<String> = data_model
         .telescopes
         .iter()
@@ -38,20 +61,23 @@ where
     HtmlTemplate(BookingsTemplate {
         bookings,
         telescope_names,
+        idempotency_key: new_idempotency_key(),
+        refusal: None,
     })
 }
 
 #[derive(Deserialize, Debug)]
 struct BookingForm {
-    name: String,
     start_date: NaiveDate,
     start_time: NaiveTime,
     telescope: String,
     duration: i64,
 }
 
+/// Book a telescope for the requester.
 async fn create_booking<StorageType>(
     State(db): State<DataBase<StorageType>>,
+    requester: Requester,
     Form(booking_form): Form<BookingForm>,
 ) -> impl IntoResponse
 where
@@ -66,15 +92,17 @@ where
     let booking = Booking {
         start_time,
         end_time,
-        user_name: booking_form.name,
+        user_name: requester.name().to_string(),
         telescope_name: booking_form.telescope,
     };
-    let mut skip = false;
-    if db
+    let data_model = db
         .get_data()
         // Error handling!
         .await
-        .expect("Failed to get data")
+        .expect("Failed to get data");
+    // Uncertified users may only book the fake telescopes.
+    let mut refusal = check_booking_allowed(&data_model, &booking).err();
+    if data_model
         .bookings
         .iter()
         .filter(|b| b.telescope_name == booking.telescope_name && b.overlaps(&booking))
@@ -82,10 +110,13 @@ where
     {
         // There is already a booking of the selected telescope overlapping
         // with the new booking. The new booking must be rejected.
-        skip = true;
+        refusal = Some(AddBookingError::Conflict);
+    }
+    if requester.0.is_none() {
+        refusal = Some(AddBookingError::NotAuthenticated);
     }
 
-    if !skip {
+    if refusal.is_none() {
         db.update_data(|mut data_model| {
             data_model.bookings.push(booking);
             data_model
@@ -98,7 +129,11 @@ where
         .get_data()
         .await
         .expect("As long as no one is manually editing the database, this should never fail.");
-    let bookings = data_model.bookings;
+    let filter = BookingFilter {
+        from: Some(Utc::now()),
+        ..Default::default()
+    };
+    let bookings = filter.apply(&data_model.bookings);
     let telescope_names: Vec<String> = data_model
         .telescopes
         .iter()
@@ -108,9 +143,82 @@ where
     HtmlTemplate(BookingsTemplate {
         bookings,
         telescope_names,
+        idempotency_key: new_idempotency_key(),
+        refusal: refusal.map(|error| error.explanation().to_string()),
     })
 }
 
+#[derive(Deserialize, Debug)]
+struct DeleteBookingForm {
+    start_time: DateTime<Utc>,
+    end_time: DateTime<Utc>,
+    telescope_name: String,
+    user_name: String,
+}
+
+/// Move a booking to the trash. The form holds the whole booking, since
+/// bookings have no id. Only its user and admins may delete it.
+async fn delete_booking<StorageType>(
+    State(db): State<DataBase<StorageType>>,
+    requester: Requester,
+    Form(form): Form<DeleteBookingForm>,
+) -> impl IntoResponse
+where
+    StorageType: Storage,
+{
+    let booking = Booking {
+        start_time: form.start_time,
+        end_time: form.end_time,
+        telescope_name: form.telescope_name,
+        user_name: form.user_name,
+    };
+    // A booking that is already gone was probably deleted from another tab,
+    // and one of someone else stays in the list.
+    let _ = trash_booking(&db, &booking, requester.name(), Utc::now()).await;
+    get_bookings(State(db), Query(BookingFilter::default())).await
+}
+
+#[derive(Deserialize, Debug)]
+struct RescheduleBookingForm {
+    start_time: DateTime<Utc>,
+    end_time: DateTime<Utc>,
+    telescope_name: String,
+    user_name: String,
+    new_start_date: NaiveDate,
+    new_start_time: NaiveTime,
+    /// New length of the booking in hours.
+    duration: i64,
+}
+
+/// Move a booking to new times. Like deleting, the form holds the whole
+/// booking.
+async fn reschedule<StorageType>(
+    State(db): State<DataBase<StorageType>>,
+    requester: Requester,
+    Form(form): Form<RescheduleBookingForm>,
+) -> impl IntoResponse
+where
+    StorageType: Storage,
+{
+    let start_time = Utc.from_utc_datetime(&NaiveDateTime::new(
+        form.new_start_date,
+        form.new_start_time,
+    ));
+    let booking_reschedule = BookingReschedule {
+        booking: Booking {
+            start_time: form.start_time,
+            end_time: form.end_time,
+            telescope_name: form.telescope_name,
+            user_name: form.user_name,
+        },
+        start_time,
+        end_time: start_time + Duration::hours(form.duration),
+    };
+    // Like for new bookings, a refused change leaves the list as it was.
+    let _ = reschedule_booking(&db, &booking_reschedule, requester.name(), Utc::now()).await;
+    get_bookings(State(db), Query(BookingFilter::default())).await
+}
+
 // pub async fn add_booking_route(
 //     State(db): State<DataBase<impl Storage>>,
 //     Json(booking): Json<Booking>,
@@ -0,0 +1,85 @@
+use crate::calibration::{calibrate_telescope, Calibration, CalibrationError, CalibrationRequest};
+use crate::database::{DataBase, Storage};
+use crate::telescope::TelescopeCollection;
+use axum::{
+    extract::{Json, Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use chrono::Utc;
+
+/// State of the calibration routes, which hand new calibrations to the
+/// telescopes.
+#[derive(Clone)]
+pub struct CalibrationState<StorageType: Storage> {
+    pub database: DataBase<StorageType>,
+    pub telescopes: TelescopeCollection,
+}
+
+pub fn routes(
+    telescopes: TelescopeCollection,
+    database: DataBase<impl Storage + 'static>,
+) -> Router {
+    Router::new()
+        .route(
+            "/:telescope_name",
+            get(get_calibrations).post(post_calibration),
+        )
+        .with_state(CalibrationState {
+            database,
+            telescopes,
+        })
+}
+
+impl IntoResponse for CalibrationError {
+    fn into_response(self) -> Response {
+        let status = match self {
+            CalibrationError::UnknownTelescope | CalibrationError::NotFound(_) => {
+                StatusCode::NOT_FOUND
+            }
+            CalibrationError::Unreadable(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            CalibrationError::DataBase => StatusCode::SERVICE_UNAVAILABLE,
+            _ => StatusCode::UNPROCESSABLE_ENTITY,
+        };
+        (status, self.to_string()).into_response()
+    }
+}
+
+/// Calibrations of the telescope, the most recent first.
+async fn get_calibrations(
+    State(state): State<CalibrationState<impl Storage>>,
+    Path(telescope_name): Path<String>,
+) -> Json<Vec<Calibration>> {
+    let mut calibrations: Vec<Calibration> = state
+        .database
+        .get_data()
+        .await
+        .expect("As long as no one is manually editing the database, this should never fail.")
+        .calibrations
+        .into_iter()
+        .filter(|c| c.telescope_name == telescope_name)
+        .collect();
+    calibrations.sort_by_key(|c| std::cmp::Reverse(c.time));
+    Json(calibrations)
+}
+
+/// Calibrate from a hot and a cold total power measurement already in the
+/// archive. The new calibration is used from the next integration.
+async fn post_calibration(
+    State(state): State<CalibrationState<impl Storage>>,
+    Path(telescope_name): Path<String>,
+    Json(request): Json<CalibrationRequest>,
+) -> Result<Json<Calibration>, CalibrationError> {
+    let calibration =
+        calibrate_telescope(&state.database, &telescope_name, &request, Utc::now()).await?;
+    if let Some(container) = state.telescopes.read().await.get(&telescope_name) {
+        container
+            .telescope
+            .lock()
+            .await
+            .set_calibration(calibration.clone());
+    }
+    Ok(Json(calibration))
+}
@@ -0,0 +1,305 @@
+//! System temperature and gain of the receivers.
+//!
+//! Telescopes with a noise diode measure the system temperature before each
+//! observation. Any telescope can also be calibrated by an operator with
+//! the Y-factor method: a total power measurement with an absorber at
+//! ambient temperature in front of the feed (hot) and one of cold sky
+//! (cold). The resulting gain of each channel and system temperature are
+//! stored per telescope, and the latest is used to scale spectra.
+
+pub mod api_routes;
+
+use crate::archive::files::load_spectra;
+use crate::archive::ArchivedMeasurement;
+use crate::auxiliary::send_switch_command;
+use crate::database::{DataBase, DataBaseError, Storage};
+use crate::telescopes::{NoiseDiodeDefinition, SwitchingMode, TelescopeError};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// Physical temperature in K of the absorber, if the operator gives none.
+pub const DEFAULT_HOT_LOAD_TEMPERATURE: f64 = 290.0;
+/// Brightness temperature in K of cold sky at 21 cm away from the galactic
+/// plane, including the CMB and the atmosphere.
+pub const DEFAULT_COLD_SKY_TEMPERATURE: f64 = 10.0;
+/// Calibrations kept per telescope, the oldest are dropped.
+const MAX_CALIBRATIONS: usize = 100;
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Calibration {
+    pub telescope_name: String,
+    pub time: DateTime<Utc>,
+    pub hot_measurement_id: u64,
+    pub cold_measurement_id: u64,
+    pub hot_temperature: f64,
+    pub cold_temperature: f64,
+    /// Noise temperature of the receiver in K, the median over channels.
+    pub receiver_temperature: f64,
+    /// System temperature in K when looking at cold sky.
+    pub system_temperature: f64,
+    pub frequencies: Vec<f64>,
+    /// Receiver units per K of each channel.
+    pub gain: Vec<f64>,
+}
+
+impl Calibration {
+    /// Convert a total power spectrum in receiver units to K. Returns None
+    /// if the spectrum has other channels than the calibration.
+    pub fn to_temperature(&self, spectrum: &[f64]) -> Option<Vec<f64>> {
+        if spectrum.len() != self.gain.len() {
+            return None;
+        }
+        Some(
+            spectrum
+                .iter()
+                .zip(&self.gain)
+                .map(|(p, g)| p / g)
+                .collect(),
+        )
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct CalibrationRequest {
+    pub hot_measurement_id: u64,
+    pub cold_measurement_id: u64,
+    #[serde(default)]
+    pub hot_temperature: Option<f64>,
+    #[serde(default)]
+    pub cold_temperature: Option<f64>,
+}
+
+#[derive(Debug, Error, PartialEq)]
+pub enum CalibrationError {
+    #[error("telescope not found")]
+    UnknownTelescope,
+    #[error("measurement {0} not found")]
+    NotFound(u64),
+    #[error("measurement {0} was not taken by this telescope")]
+    WrongTelescope(u64),
+    #[error("measurement {0} was not measured in total power mode")]
+    NotTotalPower(u64),
+    #[error("the hot and cold measurements have different channels")]
+    ChannelMismatch,
+    #[error("the hot load must be warmer than the cold sky")]
+    InvalidTemperatures,
+    #[error("the hot load gave no more power than the cold sky, was it in place?")]
+    NoSignal,
+    #[error("the data file of measurement {0} could not be read")]
+    Unreadable(u64),
+    #[error("failed to access the database")]
+    DataBase,
+}
+
+impl From<DataBaseError> for CalibrationError {
+    fn from(_: DataBaseError) -> Self {
+        CalibrationError::DataBase
+    }
+}
+
+pub fn switch_noise_diode(
+    definition: &NoiseDiodeDefinition,
+    on: bool,
+) -> Result<(), TelescopeError> {
+    let command = if on {
+        &definition.on_command
+    } else {
+        &definition.off_command
+    };
+    send_switch_command(&definition.address, command)
+}
+
+/// Estimate the system temperature from spectra measured with the noise
+/// diode on and off.
+///
+/// The diode adds a known temperature, so the difference between the
+/// spectra gives the gain of each channel. The median over channels is
+/// returned to suppress channels with interference. Returns None if the
+/// diode made no difference, e.g. because it failed to switch.
+pub fn system_temperature(on: &[f64], off: &[f64], diode_temperature: f64) -> Option<f64> {
+    let mut temperatures: Vec<f64> = on
+        .iter()
+        .zip(off)
+        .filter(|(on, off)| on > off)
+        .map(|(on, off)| diode_temperature * off / (on - off))
+        .collect();
+    if temperatures.is_empty() {
+        return None;
+    }
+    temperatures.sort_by(|a, b| a.total_cmp(b));
+    Some(temperatures[temperatures.len() / 2])
+}
+
+/// Receiver temperature and gain of each channel from total power spectra
+/// of loads at `hot_temperature` and `cold_temperature`.
+///
+/// With Y = P_hot / P_cold the receiver temperature is
+/// (T_hot - Y T_cold) / (Y - 1). Channels where the hot load gave no more
+/// power get the median gain. Returns None if no channel did.
+pub fn y_factor(
+    hot: &[f64],
+    cold: &[f64],
+    hot_temperature: f64,
+    cold_temperature: f64,
+) -> Option<(f64, Vec<f64>)> {
+    let mut receiver_temperatures: Vec<f64> = hot
+        .iter()
+        .zip(cold)
+        .filter(|(hot, cold)| hot > cold && **cold > 0.0)
+        .map(|(hot, cold)| {
+            let y = hot / cold;
+            (hot_temperature - y * cold_temperature) / (y - 1.0)
+        })
+        .collect();
+    if receiver_temperatures.is_empty() {
+        return None;
+    }
+    receiver_temperatures.sort_by(|a, b| a.total_cmp(b));
+    let receiver_temperature = receiver_temperatures[receiver_temperatures.len() / 2];
+
+    let gain: Vec<f64> = hot
+        .iter()
+        .zip(cold)
+        .map(|(hot, cold)| (hot - cold) / (hot_temperature - cold_temperature))
+        .collect();
+    let mut valid: Vec<f64> = gain.iter().copied().filter(|g| *g > 0.0).collect();
+    valid.sort_by(|a, b| a.total_cmp(b));
+    let median_gain = valid[valid.len() / 2];
+    let gain = gain
+        .into_iter()
+        .map(|g| if g > 0.0 { g } else { median_gain })
+        .collect();
+    Some((receiver_temperature, gain))
+}
+
+/// The most recent of `calibrations` of `telescope_name`.
+pub fn latest_calibration<'a>(
+    calibrations: &'a [Calibration],
+    telescope_name: &str,
+) -> Option<&'a Calibration> {
+    calibrations
+        .iter()
+        .filter(|c| c.telescope_name == telescope_name)
+        .max_by_key(|c| c.time)
+}
+
+/// Calibrate `telescope_name` from two archived total power measurements
+/// and store the result.
+pub async fn calibrate_telescope(
+    db: &DataBase<impl Storage>,
+    telescope_name: &str,
+    request: &CalibrationRequest,
+    now: DateTime<Utc>,
+) -> Result<Calibration, CalibrationError> {
+    let data_model = db.get_data().await?;
+    if !data_model
+        .telescopes
+        .iter()
+        .any(|t| t.name == telescope_name)
+    {
+        return Err(CalibrationError::UnknownTelescope);
+    }
+    let total_power = |id: u64| {
+        let archived = data_model
+            .measurements
+            .iter()
+            .find(|m| m.id == id)
+            .ok_or(CalibrationError::NotFound(id))?;
+        if archived.telescope_name != telescope_name {
+            return Err(CalibrationError::WrongTelescope(id));
+        }
+        let switching = archived
+            .measurement
+            .receiver_configuration
+            .map(|configuration| configuration.switching);
+        if switching != Some(SwitchingMode::TotalPower) {
+            return Err(CalibrationError::NotTotalPower(id));
+        }
+        Ok(archived.clone())
+    };
+    let hot = total_power(request.hot_measurement_id)?;
+    let cold = total_power(request.cold_measurement_id)?;
+    let with_spectra = |archived: ArchivedMeasurement| async move {
+        let id = archived.id;
+        load_spectra(db, archived).await.map_err(|error| {
+            log::error!("Failed to read spectra of measurement {}: {}", id, error);
+            CalibrationError::Unreadable(id)
+        })
+    };
+    let hot = with_spectra(hot).await?.measurement;
+    let cold = with_spectra(cold).await?.measurement;
+    if hot.freqs != cold.freqs {
+        return Err(CalibrationError::ChannelMismatch);
+    }
+    let hot_temperature = request
+        .hot_temperature
+        .unwrap_or(DEFAULT_HOT_LOAD_TEMPERATURE);
+    let cold_temperature = request
+        .cold_temperature
+        .unwrap_or(DEFAULT_COLD_SKY_TEMPERATURE);
+    if hot_temperature <= cold_temperature {
+        return Err(CalibrationError::InvalidTemperatures);
+    }
+    let (receiver_temperature, gain) =
+        y_factor(&hot.amps, &cold.amps, hot_temperature, cold_temperature)
+            .ok_or(CalibrationError::NoSignal)?;
+    let calibration = Calibration {
+        telescope_name: telescope_name.to_string(),
+        time: now,
+        hot_measurement_id: request.hot_measurement_id,
+        cold_measurement_id: request.cold_measurement_id,
+        hot_temperature,
+        cold_temperature,
+        receiver_temperature,
+        system_temperature: receiver_temperature + cold_temperature,
+        frequencies: hot.freqs.clone(),
+        gain,
+    };
+    let stored = calibration.clone();
+    db.update_data(|mut data_model| {
+        data_model.calibrations.push(stored);
+        let count = data_model
+            .calibrations
+            .iter()
+            .filter(|c| c.telescope_name == telescope_name)
+            .count();
+        let mut excess = count.saturating_sub(MAX_CALIBRATIONS);
+        data_model.calibrations.retain(|c| {
+            let drop = excess > 0 && c.telescope_name == telescope_name;
+            if drop {
+                excess -= 1;
+            }
+            !drop
+        });
+        data_model
+    })
+    .await?;
+    Ok(calibration)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_system_temperature() {
+        // Gain 2 per K, 100 K system and a 10 K diode
+        let off = vec![200.0, 200.0, 200.0];
+        let on = vec![220.0, 220.0, 500.0];
+        assert_eq!(system_temperature(&on, &off, 10.0), Some(100.0));
+        assert_eq!(system_temperature(&off, &off, 10.0), None);
+    }
+
+    #[test]
+    fn test_y_factor() {
+        // Gain 2 per K and a 90 K receiver, hot load at 290 K and cold sky
+        // at 10 K. The last channel did not see the load.
+        let hot = vec![760.0, 760.0, 200.0];
+        let cold = vec![200.0, 200.0, 200.0];
+        let (receiver_temperature, gain) = y_factor(&hot, &cold, 290.0, 10.0).unwrap();
+        assert!((receiver_temperature - 90.0).abs() < 1e-9);
+        assert_eq!(gain, vec![2.0, 2.0, 2.0]);
+        assert_eq!(y_factor(&cold, &cold, 290.0, 10.0), None);
+    }
+}
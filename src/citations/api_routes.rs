@@ -0,0 +1,81 @@
+use crate::citations::{
+    cite_measurement, creators, datacite_metadata, fetch_citation, Citation, CitationError,
+    DataCiteMetadata,
+};
+use crate::database::{DataBase, Storage};
+use axum::{
+    extract::{Json, Path, State, TypedHeader},
+    headers::Host,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Router,
+};
+use chrono::Utc;
+use serde::Deserialize;
+
+pub fn routes(database: DataBase<impl Storage + 'static>) -> Router {
+    Router::new()
+        .route("/", post(post_citation))
+        .route("/:identifier", get(get_citation))
+        .route("/:identifier/datacite", get(get_datacite_metadata))
+        .with_state(database)
+}
+
+impl IntoResponse for CitationError {
+    fn into_response(self) -> Response {
+        match self {
+            CitationError::MeasurementNotFound => {
+                (StatusCode::NOT_FOUND, "Measurement not found".to_string()).into_response()
+            }
+            CitationError::CitationNotFound => {
+                (StatusCode::NOT_FOUND, "Citation not found".to_string()).into_response()
+            }
+            CitationError::ServiceUnavailable => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Failed to look up citation".to_string(),
+            )
+                .into_response(),
+        }
+    }
+}
+
+/// Absolute url of the landing page of a citation.
+pub fn permalink(host: &Host, identifier: &str) -> String {
+    format!("https://{}/citations/{}", host, identifier)
+}
+
+#[derive(Deserialize, Debug)]
+struct CitationRequest {
+    measurement_id: u64,
+}
+
+async fn post_citation(
+    State(db): State<DataBase<impl Storage>>,
+    Json(request): Json<CitationRequest>,
+) -> Result<Json<Citation>, CitationError> {
+    Ok(Json(
+        cite_measurement(&db, request.measurement_id, Utc::now()).await?,
+    ))
+}
+
+async fn get_citation(
+    State(db): State<DataBase<impl Storage>>,
+    Path(identifier): Path<String>,
+) -> Result<Json<Citation>, CitationError> {
+    Ok(Json(fetch_citation(&db, &identifier).await?.0))
+}
+
+async fn get_datacite_metadata(
+    State(db): State<DataBase<impl Storage>>,
+    Path(identifier): Path<String>,
+    TypedHeader(host): TypedHeader<Host>,
+) -> Result<Json<DataCiteMetadata>, CitationError> {
+    let (citation, _) = fetch_citation(&db, &identifier).await?;
+    let creators = creators(&db.get_data().await?, &citation);
+    Ok(Json(datacite_metadata(
+        &citation,
+        creators,
+        &permalink(&host, &identifier),
+    )))
+}
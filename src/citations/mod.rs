@@ -0,0 +1,331 @@
+//! Permalinks for citing archived observations in reports.
+//!
+//! Measurement ids can be reused once a measurement has been purged from
+//! the trash, so a citation gets its own identifier that is never reused.
+//! The metadata of the observation is kept with the citation, so the
+//! landing page still describes it if the measurement is deleted later.
+
+use crate::archive::ArchivedMeasurement;
+use crate::database::{DataBase, DataBaseError, DataModel, Storage};
+use crate::sessions::{RecordedSession, SessionEventKind};
+use crate::telescopes::TelescopeTarget;
+use chrono::{DateTime, Datelike, Utc};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+pub mod api_routes;
+pub mod routes;
+
+pub const PUBLISHER: &str = "SALSA, Onsala Space Observatory";
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Citation {
+    /// Identifier in the permalink, e.g. `salsa-42-3f9a1c07`.
+    pub identifier: String,
+    pub measurement_id: u64,
+    pub created: DateTime<Utc>,
+    pub telescope_name: String,
+    pub target: TelescopeTarget,
+    pub start: DateTime<Utc>,
+    pub duration: Duration,
+}
+
+impl Citation {
+    pub fn title(&self) -> String {
+        format!(
+            "{} spectrum of {} observed {}",
+            self.telescope_name,
+            self.target,
+            self.start.format("%Y-%m-%d %H:%M UTC")
+        )
+    }
+
+    /// Whether `measurement` is the cited one, and not a later one that
+    /// was given the id of a purged measurement.
+    pub fn cites(&self, measurement: &ArchivedMeasurement) -> bool {
+        measurement.id == self.measurement_id && measurement.measurement.start == self.start
+    }
+
+    /// Whether the cited measurement has been deleted from the archive.
+    pub fn withdrawn(&self, data_model: &DataModel) -> bool {
+        !data_model.measurements.iter().any(|m| self.cites(m))
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum CitationError {
+    MeasurementNotFound,
+    CitationNotFound,
+    ServiceUnavailable,
+}
+
+impl From<DataBaseError> for CitationError {
+    fn from(_source: DataBaseError) -> Self {
+        Self::ServiceUnavailable
+    }
+}
+
+fn new_identifier(measurement_id: u64) -> String {
+    let suffix: u32 = rand::thread_rng().gen();
+    format!("salsa-{}-{:08x}", measurement_id, suffix)
+}
+
+/// The citation of an archived measurement, created the first time it is
+/// asked for.
+pub async fn cite_measurement(
+    db: &DataBase<impl Storage>,
+    measurement_id: u64,
+    now: DateTime<Utc>,
+) -> Result<Citation, CitationError> {
+    let mut result = Err(CitationError::MeasurementNotFound);
+    db.update_data(|mut data_model| {
+        let measurement = data_model
+            .measurements
+            .iter()
+            .find(|m| m.id == measurement_id);
+        // A purged id may have been given to a new measurement, whose
+        // citation then has to be a new one.
+        let existing = data_model
+            .citations
+            .iter()
+            .find(|c| measurement.is_some_and(|m| c.cites(m)));
+        if let Some(existing) = existing {
+            result = Ok(existing.clone());
+        } else if let Some(ArchivedMeasurement {
+            telescope_name,
+            measurement,
+            ..
+        }) = measurement
+        {
+            let citation = Citation {
+                identifier: new_identifier(measurement_id),
+                measurement_id,
+                created: now,
+                telescope_name: telescope_name.clone(),
+                target: measurement.target,
+                start: measurement.start,
+                duration: measurement.duration,
+            };
+            data_model.citations.push(citation.clone());
+            result = Ok(citation);
+        }
+        data_model
+    })
+    .await?;
+    result
+}
+
+pub async fn fetch_citation(
+    db: &DataBase<impl Storage>,
+    identifier: &str,
+) -> Result<(Citation, bool), CitationError> {
+    let data_model = db.get_data().await?;
+    let citation = data_model
+        .citations
+        .iter()
+        .find(|c| c.identifier == identifier)
+        .ok_or(CitationError::CitationNotFound)?;
+    Ok((citation.clone(), citation.withdrawn(&data_model)))
+}
+
+/// Names of the users who saved the measurement in their sessions, so that
+/// deleted users are no longer named.
+pub fn creators(data_model: &DataModel, citation: &Citation) -> Vec<String> {
+    // Only sessions during which the cited measurement was started count,
+    // a later session may have saved a measurement given the same id.
+    let saved_it = |session: &RecordedSession| {
+        session.start_time <= citation.start
+            && citation.start <= session.end_time
+            && session
+                .events
+                .iter()
+                .any(|e| e.kind == SessionEventKind::MeasurementSaved(citation.measurement_id))
+    };
+    let mut creators: Vec<String> = data_model
+        .users
+        .iter()
+        .filter(|u| {
+            data_model
+                .sessions
+                .iter()
+                .any(|s| s.user_name == u.name && saved_it(s))
+        })
+        .map(|u| u.name.clone())
+        .collect();
+    if creators.is_empty() {
+        creators.push(format!("SALSA {}", citation.telescope_name));
+    }
+    creators
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct DataCiteIdentifier {
+    pub identifier: String,
+    #[serde(rename = "identifierType")]
+    pub identifier_type: String,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct DataCiteName {
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct DataCiteTitle {
+    pub title: String,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct DataCiteTypes {
+    #[serde(rename = "resourceTypeGeneral")]
+    pub resource_type_general: String,
+    #[serde(rename = "resourceType")]
+    pub resource_type: String,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct DataCiteDate {
+    pub date: String,
+    #[serde(rename = "dateType")]
+    pub date_type: String,
+}
+
+/// Metadata of a citation in the shape of the attributes of a DataCite
+/// record, for reference managers and repositories that import them.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct DataCiteMetadata {
+    pub identifiers: Vec<DataCiteIdentifier>,
+    pub creators: Vec<DataCiteName>,
+    pub titles: Vec<DataCiteTitle>,
+    pub publisher: String,
+    #[serde(rename = "publicationYear")]
+    pub publication_year: i32,
+    pub types: DataCiteTypes,
+    pub dates: Vec<DataCiteDate>,
+    pub formats: Vec<String>,
+}
+
+/// `url` is the permalink of the citation.
+pub fn datacite_metadata(
+    citation: &Citation,
+    creators: Vec<String>,
+    url: &str,
+) -> DataCiteMetadata {
+    DataCiteMetadata {
+        identifiers: vec![DataCiteIdentifier {
+            identifier: url.to_string(),
+            identifier_type: "URL".to_string(),
+        }],
+        creators: creators
+            .into_iter()
+            .map(|name| DataCiteName { name })
+            .collect(),
+        titles: vec![DataCiteTitle {
+            title: citation.title(),
+        }],
+        publisher: PUBLISHER.to_string(),
+        publication_year: citation.created.year(),
+        types: DataCiteTypes {
+            resource_type_general: "Dataset".to_string(),
+            resource_type: "Radio spectrum".to_string(),
+        },
+        dates: vec![
+            DataCiteDate {
+                date: citation.start.to_rfc3339(),
+                date_type: "Collected".to_string(),
+            },
+            DataCiteDate {
+                date: citation.created.to_rfc3339(),
+                date_type: "Issued".to_string(),
+            },
+        ],
+        formats: vec!["application/json".to_string(), "image/png".to_string()],
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::angles::Degrees;
+    use crate::archive::archive_measurement;
+    use crate::archive::test_utils::galactic_measurement;
+    use crate::database::create_in_memory_database;
+    use crate::sessions::SessionEvent;
+    use crate::users::User;
+
+    #[tokio::test]
+    async fn test_cite_measurement() {
+        let db = create_in_memory_database();
+        let now = Utc::now();
+        let id = archive_measurement(&db, "fake", galactic_measurement(Degrees(30.0), now))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            cite_measurement(&db, id + 1, now).await,
+            Err(CitationError::MeasurementNotFound)
+        );
+        let citation = cite_measurement(&db, id, now).await.unwrap();
+        assert!(citation.identifier.starts_with(&format!("salsa-{}-", id)));
+        // Citing again gives the same permalink.
+        assert_eq!(cite_measurement(&db, id, now).await, Ok(citation.clone()));
+        assert_eq!(
+            fetch_citation(&db, &citation.identifier).await,
+            Ok((citation.clone(), false))
+        );
+
+        // The citation outlives the measurement.
+        db.update_data(|mut data_model| {
+            data_model.measurements.clear();
+            data_model
+        })
+        .await
+        .unwrap();
+        assert_eq!(
+            fetch_citation(&db, &citation.identifier).await,
+            Ok((citation.clone(), true))
+        );
+
+        let metadata = datacite_metadata(
+            &citation,
+            creators(&db.get_data().await.unwrap(), &citation),
+            "https://salsa.example/citations/x",
+        );
+        assert_eq!(metadata.creators[0].name, "SALSA fake");
+        assert_eq!(metadata.types.resource_type_general, "Dataset");
+
+        // A later measurement given the purged id is not the cited one, and
+        // neither is the observer who saved it.
+        let later = now + chrono::Duration::hours(2);
+        let reused = archive_measurement(&db, "fake", galactic_measurement(Degrees(30.0), later))
+            .await
+            .unwrap();
+        assert_eq!(reused, id);
+        let session = |id, user_name: &str, start: DateTime<Utc>| RecordedSession {
+            id,
+            telescope_name: "fake".to_string(),
+            user_name: user_name.to_string(),
+            start_time: start - chrono::Duration::minutes(30),
+            end_time: start + chrono::Duration::minutes(30),
+            events: vec![SessionEvent {
+                time: start + chrono::Duration::minutes(5),
+                kind: SessionEventKind::MeasurementSaved(reused),
+            }],
+        };
+        db.update_data(|mut data_model| {
+            data_model.users = vec![User::new("anna"), User::new("bertil")];
+            data_model.sessions = vec![session(1, "anna", now), session(2, "bertil", later)];
+            data_model
+        })
+        .await
+        .unwrap();
+        let data_model = db.get_data().await.unwrap();
+        assert!(citation.withdrawn(&data_model));
+        assert_eq!(creators(&data_model, &citation), vec!["anna".to_string()]);
+        let recited = cite_measurement(&db, id, later).await.unwrap();
+        assert_ne!(recited.identifier, citation.identifier);
+        assert!(!recited.withdrawn(&data_model));
+        assert_eq!(creators(&data_model, &recited), vec!["bertil".to_string()]);
+    }
+}
@@ -0,0 +1,78 @@
+use crate::citations::api_routes::permalink;
+use crate::citations::{cite_measurement, creators, fetch_citation, Citation, CitationError};
+use crate::database::{DataBase, Storage};
+use crate::template::HtmlTemplate;
+use askama::Template;
+use axum::{
+    extract::{Form, Path, State, TypedHeader},
+    headers::Host,
+    response::IntoResponse,
+    routing::{get, post},
+    Router,
+};
+use chrono::Utc;
+use serde::Deserialize;
+
+pub fn routes(database: DataBase<impl Storage + 'static>) -> Router {
+    Router::new()
+        .route("/", post(post_citation))
+        .route("/:identifier", get(get_citation))
+        .with_state(database)
+}
+
+#[derive(Template)]
+#[template(path = "citation_link.html")]
+struct CitationLinkTemplate {
+    identifier: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct CitationForm {
+    measurement_id: u64,
+}
+
+/// Link to the permalink of a measurement, swapped in where the cite button
+/// was.
+async fn post_citation<StorageType>(
+    State(db): State<DataBase<StorageType>>,
+    Form(form): Form<CitationForm>,
+) -> Result<impl IntoResponse, CitationError>
+where
+    StorageType: Storage,
+{
+    let citation = cite_measurement(&db, form.measurement_id, Utc::now()).await?;
+    Ok(HtmlTemplate(CitationLinkTemplate {
+        identifier: citation.identifier,
+    }))
+}
+
+#[derive(Template)]
+#[template(path = "citation.html")]
+struct CitationTemplate {
+    citation: Citation,
+    title: String,
+    creators: String,
+    permalink: String,
+    withdrawn: bool,
+}
+
+/// Landing page of a permalink, which is opened directly rather than
+/// through the rest of the site.
+async fn get_citation<StorageType>(
+    State(db): State<DataBase<StorageType>>,
+    Path(identifier): Path<String>,
+    TypedHeader(host): TypedHeader<Host>,
+) -> Result<impl IntoResponse, CitationError>
+where
+    StorageType: Storage,
+{
+    let (citation, withdrawn) = fetch_citation(&db, &identifier).await?;
+    let creators = creators(&db.get_data().await?, &citation).join(", ");
+    Ok(HtmlTemplate(CitationTemplate {
+        title: citation.title(),
+        creators,
+        permalink: permalink(&host, &identifier),
+        withdrawn,
+        citation,
+    }))
+}
@@ -0,0 +1,46 @@
+use crate::clock::{latest_clock_check, out_of_tolerance, ClockCheck};
+use crate::database::{DataBase, Storage};
+use axum::{
+    extract::{Json, State},
+    routing::get,
+    Router,
+};
+use serde::Serialize;
+
+pub fn routes(database: DataBase<impl Storage + 'static>) -> Router {
+    Router::new()
+        .route("/", get(get_clock_status))
+        .route("/checks", get(get_clock_checks))
+        .with_state(database)
+}
+
+#[derive(Serialize, Debug)]
+struct ClockStatus {
+    configured: bool,
+    tolerance_seconds: Option<f64>,
+    latest: Option<ClockCheck>,
+    out_of_tolerance: bool,
+}
+
+async fn get_clock_status(State(db): State<DataBase<impl Storage>>) -> Json<ClockStatus> {
+    let data_model = db
+        .get_data()
+        .await
+        .expect("As long as no one is manually editing the database, this should never fail.");
+    let latest = latest_clock_check(&data_model);
+    Json(ClockStatus {
+        configured: data_model.clock_check.is_some(),
+        tolerance_seconds: data_model.clock_check.as_ref().map(|c| c.tolerance_seconds),
+        latest: latest.cloned(),
+        out_of_tolerance: latest.is_some_and(|c| out_of_tolerance(&data_model, c)),
+    })
+}
+
+async fn get_clock_checks(State(db): State<DataBase<impl Storage>>) -> Json<Vec<ClockCheck>> {
+    Json(
+        db.get_data()
+            .await
+            .expect("As long as no one is manually editing the database, this should never fail.")
+            .clock_checks,
+    )
+}
@@ -0,0 +1,282 @@
+//! Check of the system clock against an NTP server.
+//!
+//! The telescopes point by converting sky coordinates with the system time,
+//! so a clock that is off moves the beam off the target, by 15 arcseconds
+//! per second of error at the equator. The offset to an NTP server is
+//! measured at startup and then periodically with a single SNTP request,
+//! and measurements taken while it exceeded the tolerance are marked in
+//! the archive.
+
+use crate::database::{DataBase, DataBaseError, DataModel, Storage};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use thiserror::Error;
+use tokio::net::UdpSocket;
+
+pub mod api_routes;
+pub mod routes;
+
+const NTP_TIMEOUT: Duration = Duration::from_secs(5);
+const NTP_PACKET_SIZE: usize = 48;
+/// Seconds from the NTP epoch, 1900, to the Unix epoch.
+const NTP_UNIX_OFFSET: f64 = 2_208_988_800.0;
+/// Checks kept in the database, older ones are removed.
+pub const MAX_CLOCK_CHECKS: usize = 1000;
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct ClockCheckDefinition {
+    /// NTP server, e.g. `pool.ntp.org:123`.
+    pub server: String,
+    /// Seconds between checks.
+    pub interval_seconds: u64,
+    /// Largest offset in seconds before the clock is considered wrong.
+    pub tolerance_seconds: f64,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct ClockCheck {
+    pub time: DateTime<Utc>,
+    /// Seconds to add to the system clock to get the time of the server.
+    pub offset: Option<f64>,
+    /// Why the offset could not be measured.
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Error)]
+pub enum ClockError {
+    #[error("failed to query NTP server: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("NTP server did not respond in time")]
+    Timeout,
+    #[error("unexpected response from NTP server: {0}")]
+    Protocol(String),
+}
+
+fn unix_seconds(time: DateTime<Utc>) -> f64 {
+    time.timestamp() as f64 + time.timestamp_subsec_nanos() as f64 * 1e-9
+}
+
+fn to_ntp_timestamp(unix_seconds: f64) -> [u8; 8] {
+    let ntp = unix_seconds + NTP_UNIX_OFFSET;
+    let seconds = ntp.floor();
+    let fraction = ((ntp - seconds) * 2f64.powi(32)) as u32;
+    let mut bytes = [0; 8];
+    bytes[..4].copy_from_slice(&(seconds as u32).to_be_bytes());
+    bytes[4..].copy_from_slice(&fraction.to_be_bytes());
+    bytes
+}
+
+fn from_ntp_timestamp(bytes: &[u8]) -> f64 {
+    let seconds = u32::from_be_bytes(bytes[..4].try_into().unwrap()) as f64;
+    let fraction = u32::from_be_bytes(bytes[4..8].try_into().unwrap()) as f64;
+    seconds + fraction / 2f64.powi(32) - NTP_UNIX_OFFSET
+}
+
+/// SNTP client request sent at `sent`, in Unix seconds.
+pub fn ntp_request(sent: f64) -> [u8; NTP_PACKET_SIZE] {
+    let mut packet = [0; NTP_PACKET_SIZE];
+    // Leap indicator 0, version 4, mode 3 (client).
+    packet[0] = 0b00_100_011;
+    packet[40..48].copy_from_slice(&to_ntp_timestamp(sent));
+    packet
+}
+
+/// Clock offset from a server `response` to a request sent at `sent` and
+/// received back at `received`, in Unix seconds of the system clock.
+pub fn parse_ntp_response(response: &[u8], sent: f64, received: f64) -> Result<f64, ClockError> {
+    if response.len() < NTP_PACKET_SIZE {
+        return Err(ClockError::Protocol(format!(
+            "{} bytes in response",
+            response.len()
+        )));
+    }
+    let mode = response[0] & 0b111;
+    if mode != 4 {
+        return Err(ClockError::Protocol(format!("mode {}", mode)));
+    }
+    // Stratum 0 is a kiss-o'-death, e.g. asking to query less often.
+    let stratum = response[1];
+    if stratum == 0 {
+        let code = String::from_utf8_lossy(&response[12..16]).to_string();
+        return Err(ClockError::Protocol(format!("kiss code {}", code)));
+    }
+    // The request echoed back, so that a stray packet is not taken as the
+    // response.
+    if response[24..32] != to_ntp_timestamp(sent) {
+        return Err(ClockError::Protocol(
+            "response to another request".to_string(),
+        ));
+    }
+    let server_received = from_ntp_timestamp(&response[32..40]);
+    let server_sent = from_ntp_timestamp(&response[40..48]);
+    Ok(((server_received - sent) + (server_sent - received)) / 2.0)
+}
+
+pub async fn query_ntp_offset(server: &str) -> Result<f64, ClockError> {
+    let exchange = async {
+        let socket = UdpSocket::bind("0.0.0.0:0").await?;
+        socket.connect(server).await?;
+        let sent = unix_seconds(Utc::now());
+        socket.send(&ntp_request(sent)).await?;
+        let mut response = [0; NTP_PACKET_SIZE];
+        let length = socket.recv(&mut response).await?;
+        let received = unix_seconds(Utc::now());
+        parse_ntp_response(&response[..length], sent, received)
+    };
+    tokio::time::timeout(NTP_TIMEOUT, exchange)
+        .await
+        .map_err(|_| ClockError::Timeout)?
+}
+
+/// The most recent check of the clock, if any.
+pub fn latest_clock_check(data_model: &DataModel) -> Option<&ClockCheck> {
+    data_model.clock_checks.last()
+}
+
+/// Whether `check` found the clock off by more than the tolerance.
+pub fn out_of_tolerance(data_model: &DataModel, check: &ClockCheck) -> bool {
+    match (&data_model.clock_check, check.offset) {
+        (Some(definition), Some(offset)) => offset.abs() > definition.tolerance_seconds,
+        _ => false,
+    }
+}
+
+/// The largest clock offset out of tolerance between `start` and `end`,
+/// including the last check before `start`, which is in effect then.
+pub fn clock_offset_during(
+    data_model: &DataModel,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> Option<f64> {
+    let before = data_model
+        .clock_checks
+        .iter()
+        .rev()
+        .find(|c| c.time < start);
+    data_model
+        .clock_checks
+        .iter()
+        .filter(|c| start <= c.time && c.time <= end)
+        .chain(before)
+        .filter(|c| out_of_tolerance(data_model, c))
+        .filter_map(|c| c.offset)
+        .max_by(|a, b| a.abs().total_cmp(&b.abs()))
+}
+
+pub async fn record_clock_check(
+    database: &DataBase<impl Storage>,
+    check: ClockCheck,
+) -> Result<(), DataBaseError> {
+    database
+        .update_data(|mut data_model| {
+            data_model.clock_checks.push(check);
+            let excess = data_model
+                .clock_checks
+                .len()
+                .saturating_sub(MAX_CLOCK_CHECKS);
+            data_model.clock_checks.drain(..excess);
+            data_model
+        })
+        .await
+}
+
+pub fn start_clock_check_service<T>(
+    database: DataBase<T>,
+    definition: ClockCheckDefinition,
+) -> tokio::task::JoinHandle<()>
+where
+    T: Storage + 'static,
+{
+    tokio::spawn(async move {
+        loop {
+            let check = match query_ntp_offset(&definition.server).await {
+                Ok(offset) => {
+                    if offset.abs() > definition.tolerance_seconds {
+                        log::warn!(
+                            "System clock is off by {:.3} s from {}, pointing will be wrong",
+                            offset,
+                            definition.server
+                        );
+                    }
+                    ClockCheck {
+                        time: Utc::now(),
+                        offset: Some(offset),
+                        error: None,
+                    }
+                }
+                Err(error) => {
+                    log::error!("{}", error);
+                    ClockCheck {
+                        time: Utc::now(),
+                        offset: None,
+                        error: Some(error.to_string()),
+                    }
+                }
+            };
+            if let Err(error) = record_clock_check(&database, check).await {
+                log::error!("Failed to record clock check: {}", error);
+            }
+            tokio::time::sleep(Duration::from_secs(definition.interval_seconds)).await;
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_parse_ntp_response() {
+        let sent = 1_700_000_000.25;
+        let request = ntp_request(sent);
+        assert_eq!(request[0] & 0b111, 3);
+        assert!((from_ntp_timestamp(&request[40..48]) - sent).abs() < 1e-6);
+
+        // A server 2 s ahead, 0.1 s away each way, taking 0.05 s to reply.
+        let mut response = [0; NTP_PACKET_SIZE];
+        response[0] = 0b00_100_100;
+        response[1] = 2;
+        response[24..32].copy_from_slice(&request[40..48]);
+        response[32..40].copy_from_slice(&to_ntp_timestamp(sent + 2.1));
+        response[40..48].copy_from_slice(&to_ntp_timestamp(sent + 2.15));
+        let offset = parse_ntp_response(&response, sent, sent + 0.25).unwrap();
+        assert!((offset - 2.0).abs() < 1e-6, "{}", offset);
+
+        assert!(parse_ntp_response(&response, sent + 1.0, sent + 1.25).is_err());
+        response[1] = 0;
+        assert!(parse_ntp_response(&response, sent, sent + 0.25).is_err());
+        assert!(parse_ntp_response(&response[..10], sent, sent + 0.25).is_err());
+    }
+
+    #[test]
+    fn test_clock_offset_during() {
+        let at = |minute| Utc.with_ymd_and_hms(2024, 3, 1, 20, minute, 0).unwrap();
+        let check = |minute, offset| ClockCheck {
+            time: at(minute),
+            offset: Some(offset),
+            error: None,
+        };
+        let data_model = DataModel {
+            clock_check: Some(ClockCheckDefinition {
+                server: "pool.ntp.org:123".to_string(),
+                interval_seconds: 600,
+                tolerance_seconds: 1.0,
+            }),
+            clock_checks: vec![
+                check(0, 3.0),
+                check(10, 0.1),
+                check(20, -1.5),
+                check(30, 0.2),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(clock_offset_during(&data_model, at(12), at(18)), None);
+        assert_eq!(clock_offset_during(&data_model, at(15), at(25)), Some(-1.5));
+        // The check before the start is in effect at the start.
+        assert_eq!(clock_offset_during(&data_model, at(25), at(28)), Some(-1.5));
+        assert_eq!(clock_offset_during(&data_model, at(5), at(40)), Some(3.0));
+        assert_eq!(clock_offset_during(&data_model, at(1), at(2)), Some(3.0));
+    }
+}
@@ -0,0 +1,43 @@
+use crate::clock::{latest_clock_check, out_of_tolerance};
+use crate::database::{DataBase, Storage};
+use crate::format::{filters, Locale};
+use crate::template::HtmlTemplate;
+use askama::Template;
+use axum::{extract::State, response::IntoResponse, routing::get, Router};
+
+pub fn routes(database: DataBase<impl Storage + 'static>) -> Router {
+    Router::new()
+        .route("/", get(get_clock_status))
+        .with_state(database)
+}
+
+#[derive(Template)]
+#[template(path = "clock_status.html")]
+struct ClockStatusTemplate {
+    out_of_tolerance: bool,
+    offset: Option<f64>,
+    error: Option<String>,
+    checked: Option<String>,
+    locale: Locale,
+}
+
+async fn get_clock_status<StorageType>(
+    State(db): State<DataBase<StorageType>>,
+    locale: Locale,
+) -> impl IntoResponse
+where
+    StorageType: Storage,
+{
+    let data_model = db
+        .get_data()
+        .await
+        .expect("As long as no one is manually editing the database, this should never fail.");
+    let latest = latest_clock_check(&data_model);
+    HtmlTemplate(ClockStatusTemplate {
+        out_of_tolerance: latest.is_some_and(|c| out_of_tolerance(&data_model, c)),
+        offset: latest.and_then(|c| c.offset),
+        error: latest.and_then(|c| c.error.clone()),
+        checked: latest.map(|c| c.time.format("%Y-%m-%d %H:%M").to_string()),
+        locale,
+    })
+}
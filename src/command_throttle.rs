@@ -0,0 +1,125 @@
+//! Protection of the rotator from storms of motion commands.
+//!
+//! Near the tracking tolerance, or with noisy encoder readings, the tracker
+//! can decide on a new command every tick, alternating between moving and
+//! stopping. Each command makes the rotator brake or start, so commands
+//! are coalesced and rate limited before they reach the controller:
+//!
+//! - a command repeating the last one sent is dropped, unless the last one
+//!   is old enough that the controller may have missed it,
+//! - a new direction is held back until some time has passed since the
+//!   previous motion command. The tracker asks again on a later tick.
+//!
+//! Stopping is never delayed, as it may be needed to avoid a collision.
+
+use crate::coords::Direction;
+use crate::telescope_controller::TelescopeCommand;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Shortest time between a motion command and a following new direction.
+pub const MIN_COMMAND_INTERVAL: Duration = Duration::from_secs(1);
+/// A repeated command is sent again after this long.
+pub const REPEAT_INTERVAL: Duration = Duration::from_secs(5);
+/// Directions closer than this in degrees on both axes are the same.
+const SAME_DIRECTION_TOLERANCE: f64 = 0.01;
+
+/// Motion commands handled since the tracker started.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy, Default)]
+pub struct CommandCounters {
+    pub sent: u64,
+    /// Dropped as repeats of the last command.
+    pub coalesced: u64,
+    /// Held back as too soon after the last command.
+    pub throttled: u64,
+}
+
+#[derive(Default)]
+pub struct CommandThrottle {
+    last: Option<(Instant, TelescopeCommand)>,
+    counters: CommandCounters,
+}
+
+fn same_command(a: TelescopeCommand, b: TelescopeCommand) -> bool {
+    let same_direction = |a: Direction, b: Direction| {
+        (a.azimuth.0 - b.azimuth.0).abs().to_degrees() < SAME_DIRECTION_TOLERANCE
+            && (a.altitude.0 - b.altitude.0).abs().to_degrees() < SAME_DIRECTION_TOLERANCE
+    };
+    match (a, b) {
+        (TelescopeCommand::SetDirection(a), TelescopeCommand::SetDirection(b)) => {
+            same_direction(a, b)
+        }
+        (a, b) => a == b,
+    }
+}
+
+impl CommandThrottle {
+    /// Whether the motion `command` should be sent to the controller at
+    /// `now`. Commands that are admitted are taken to be sent.
+    pub fn admit(&mut self, command: TelescopeCommand, now: Instant) -> bool {
+        if let Some((time, last)) = self.last {
+            let elapsed = now.duration_since(time);
+            if same_command(command, last) && elapsed < REPEAT_INTERVAL {
+                self.counters.coalesced += 1;
+                return false;
+            }
+            let is_stop = command == TelescopeCommand::Stop;
+            if !is_stop && elapsed < MIN_COMMAND_INTERVAL {
+                self.counters.throttled += 1;
+                return false;
+            }
+        }
+        self.last = Some((now, command));
+        self.counters.sent += 1;
+        true
+    }
+
+    /// Forget the last command, e.g. after reconnecting to a controller
+    /// that may have been restarted.
+    pub fn reset(&mut self) {
+        self.last = None;
+    }
+
+    pub fn counters(&self) -> CommandCounters {
+        self.counters
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::angles::Degrees;
+
+    #[test]
+    fn test_coalesce_and_throttle() {
+        let direction = |azimuth| {
+            TelescopeCommand::SetDirection(Direction {
+                azimuth: Degrees(azimuth).to_radians(),
+                altitude: Degrees(30.0).to_radians(),
+            })
+        };
+        let start = Instant::now();
+        let at = |millis| start + Duration::from_millis(millis);
+        let mut throttle = CommandThrottle::default();
+
+        assert!(throttle.admit(direction(10.0), at(0)));
+        // The same direction again is coalesced until it is due for a repeat.
+        assert!(!throttle.admit(direction(10.001), at(100)));
+        assert!(throttle.admit(direction(10.0), at(5000)));
+        // Stopping is immediate, a new direction has to wait.
+        assert!(throttle.admit(TelescopeCommand::Stop, at(5100)));
+        assert!(!throttle.admit(TelescopeCommand::Stop, at(5200)));
+        assert!(!throttle.admit(direction(11.0), at(5300)));
+        assert!(throttle.admit(direction(11.0), at(6100)));
+
+        assert_eq!(
+            throttle.counters(),
+            CommandCounters {
+                sent: 4,
+                coalesced: 2,
+                throttled: 1,
+            }
+        );
+    }
+}
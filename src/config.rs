@@ -0,0 +1,419 @@
+//! Validation of the configuration in the database file.
+//!
+//! Telescopes, hooks and spectral lines are configured by hand in the
+//! database file, so mistakes are reported with the file, the path to the
+//! offending field and, for syntax errors, the line and column.
+//!
+//! Defaulting rules: a field that is missing from the file gets a default
+//! value if, and only if, it is marked `#[serde(default)]`. Those are
+//!
+//! - all top level lists except `bookings` and `telescopes`, which start
+//!   out empty,
+//! - `ups`, the power is not monitored,
+//! - `file_storage`, no data files are written for archived measurements,
+//! - `dut1`, sidereal time is computed from UTC as if it were UT1,
+//! - `tracker_telemetry`, the decisions of the trackers are not recorded,
+//! - `clock_check`, the system clock is not compared with an NTP server,
+//! - `rfi_scan` of a telescope, no RFI scans are made,
+//! - `auxiliary_devices` of a telescope, which starts out empty,
+//! - `park_policies` of a telescope, it is never parked automatically,
+//! - `pointing_model` of a telescope, all terms zero,
+//! - `noise_diode` of a SALSA telescope, the system temperature of the
+//!   latest calibration, or else the fixed `DEFAULT_SYSTEM_TEMPERATURE`, is
+//!   used instead,
+//! - `response_encoding` of a SALSA telescope, it is detected when
+//!   connecting to the controller,
+//! - `polarization`, `polarization_receivers` and `record_polarizations`
+//!   of a SALSA telescope, a single receiver of polarization X,
+//! - `rfi_mask` of a SALSA telescope, no frequencies are masked,
+//! - `default_on` and `required_for_integration` of an auxiliary device,
+//!   both false.
+//!
+//! Every other field has to be given explicitly.
+
+use crate::angles::{Radians, RIGHT_ANGLE};
+use crate::archive::files::FileStorageDefinition;
+use crate::database::DataModel;
+use crate::orbit::parse_tle;
+use crate::park_policies::ParkPolicyError;
+use crate::telescopes::{TelescopeDefinition, TelescopeType};
+use crate::ups::UpsDefinition;
+use std::collections::HashSet;
+use std::fmt::{Display, Formatter};
+use thiserror::Error;
+
+/// Leap seconds keep UT1 - UTC within 0.9 s.
+const MAX_DUT1: f64 = 0.9;
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("could not read {file}: {source}")]
+    Io {
+        file: String,
+        source: std::io::Error,
+    },
+    #[error("{file}:{line}:{column}: {path}: {message}")]
+    Syntax {
+        file: String,
+        path: String,
+        line: usize,
+        column: usize,
+        message: String,
+    },
+    #[error("{file}: {}", problems.iter().map(ToString::to_string).collect::<Vec<_>>().join(", "))]
+    Invalid {
+        file: String,
+        problems: Vec<ConfigProblem>,
+    },
+}
+
+/// A value that parses but does not make sense.
+#[derive(Debug, PartialEq)]
+pub struct ConfigProblem {
+    /// Path to the field, e.g. `telescopes[0].min_altitude`.
+    pub path: String,
+    pub message: String,
+}
+
+impl Display for ConfigProblem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)
+    }
+}
+
+/// Read and validate the database file at `file_path`.
+pub async fn load_configuration(file_path: &str) -> Result<DataModel, ConfigError> {
+    let data = tokio::fs::read(file_path)
+        .await
+        .map_err(|source| ConfigError::Io {
+            file: file_path.to_string(),
+            source,
+        })?;
+    parse_configuration(file_path, &data)
+}
+
+pub fn parse_configuration(file: &str, data: &[u8]) -> Result<DataModel, ConfigError> {
+    let deserializer = &mut serde_json::Deserializer::from_slice(data);
+    let data_model: DataModel =
+        serde_path_to_error::deserialize(deserializer).map_err(|error| {
+            let path = error.path().to_string();
+            let error = error.into_inner();
+            ConfigError::Syntax {
+                file: file.to_string(),
+                path,
+                line: error.line(),
+                column: error.column(),
+                message: error.to_string(),
+            }
+        })?;
+
+    let problems = validate(&data_model);
+    if !problems.is_empty() {
+        return Err(ConfigError::Invalid {
+            file: file.to_string(),
+            problems,
+        });
+    }
+    Ok(data_model)
+}
+
+pub fn validate(data_model: &DataModel) -> Vec<ConfigProblem> {
+    let mut problems = Vec::new();
+    let mut names = HashSet::new();
+    for (index, telescope) in data_model.telescopes.iter().enumerate() {
+        let path = format!("telescopes[{}]", index);
+        if !names.insert(telescope.name.as_str()) {
+            problems.push(ConfigProblem {
+                path: format!("{}.name", path),
+                message: format!("telescope name {} is used more than once", telescope.name),
+            });
+        }
+        validate_telescope(&path, telescope, &mut problems);
+    }
+    for (index, zone) in data_model.collision_zones.iter().enumerate() {
+        let path = format!("collision_zones[{}]", index);
+        for (field, name) in [
+            ("first_telescope", &zone.first_telescope),
+            ("second_telescope", &zone.second_telescope),
+        ] {
+            if !names.contains(name.as_str()) {
+                problems.push(ConfigProblem {
+                    path: format!("{}.{}", path, field),
+                    message: format!("unknown telescope {}", name),
+                });
+            }
+        }
+        if zone.first_telescope == zone.second_telescope {
+            problems.push(ConfigProblem {
+                path: format!("{}.second_telescope", path),
+                message: "must differ from first_telescope".to_string(),
+            });
+        }
+    }
+    if data_model.dut1.abs() > MAX_DUT1 {
+        problems.push(ConfigProblem {
+            path: "dut1".to_string(),
+            message: format!("must be between -{0} and {0} (seconds)", MAX_DUT1),
+        });
+    }
+    match &data_model.ups {
+        Some(UpsDefinition::Nut { address, .. }) if address.is_empty() => {
+            problems.push(ConfigProblem {
+                path: "ups.Nut.address".to_string(),
+                message: "must not be empty".to_string(),
+            })
+        }
+        Some(UpsDefinition::Gpio { value_path, .. }) if value_path.is_empty() => {
+            problems.push(ConfigProblem {
+                path: "ups.Gpio.value_path".to_string(),
+                message: "must not be empty".to_string(),
+            })
+        }
+        _ => {}
+    }
+    for (index, satellite) in data_model.satellites.iter().enumerate() {
+        if let Err(error) = parse_tle(&satellite.line1, &satellite.line2) {
+            problems.push(ConfigProblem {
+                path: format!("satellites[{}]", index),
+                message: error.to_string(),
+            })
+        }
+    }
+    if let Some(clock_check) = &data_model.clock_check {
+        if clock_check.server.is_empty() {
+            problems.push(ConfigProblem {
+                path: "clock_check.server".to_string(),
+                message: "must not be empty".to_string(),
+            })
+        }
+        if clock_check.interval_seconds == 0 {
+            problems.push(ConfigProblem {
+                path: "clock_check.interval_seconds".to_string(),
+                message: "must be at least 1".to_string(),
+            })
+        }
+        if clock_check.tolerance_seconds <= 0.0 {
+            problems.push(ConfigProblem {
+                path: "clock_check.tolerance_seconds".to_string(),
+                message: "must be positive".to_string(),
+            })
+        }
+    }
+    if let Some(telemetry) = &data_model.tracker_telemetry {
+        if telemetry.directory.is_empty() {
+            problems.push(ConfigProblem {
+                path: "tracker_telemetry.directory".to_string(),
+                message: "must not be empty".to_string(),
+            })
+        }
+        if telemetry.max_records == 0 {
+            problems.push(ConfigProblem {
+                path: "tracker_telemetry.max_records".to_string(),
+                message: "must be at least 1".to_string(),
+            })
+        }
+    }
+    match &data_model.file_storage {
+        Some(FileStorageDefinition::Local { directory }) if directory.is_empty() => {
+            problems.push(ConfigProblem {
+                path: "file_storage.Local.directory".to_string(),
+                message: "must not be empty".to_string(),
+            })
+        }
+        Some(FileStorageDefinition::S3 {
+            endpoint, bucket, ..
+        }) => {
+            if endpoint
+                .parse::<hyper::Uri>()
+                .map_or(true, |uri| uri.host().is_none())
+            {
+                problems.push(ConfigProblem {
+                    path: "file_storage.S3.endpoint".to_string(),
+                    message: "must be a url, e.g. https://s3.eu-north-1.amazonaws.com".to_string(),
+                })
+            }
+            if bucket.is_empty() {
+                problems.push(ConfigProblem {
+                    path: "file_storage.S3.bucket".to_string(),
+                    message: "must not be empty".to_string(),
+                })
+            }
+        }
+        _ => {}
+    }
+    problems
+}
+
+fn validate_telescope(
+    path: &str,
+    telescope: &TelescopeDefinition,
+    problems: &mut Vec<ConfigProblem>,
+) {
+    let mut problem = |field: &str, message: &str| {
+        problems.push(ConfigProblem {
+            path: format!("{}.{}", path, field),
+            message: message.to_string(),
+        })
+    };
+
+    if telescope.name.is_empty() {
+        problem("name", "must not be empty");
+    }
+    if !(-RIGHT_ANGLE..=RIGHT_ANGLE).contains(&telescope.location.latitude) {
+        problem(
+            "location.latitude",
+            "must be between -π/2 and π/2 (radians)",
+        );
+    }
+    if !(Radians(0.0)..RIGHT_ANGLE).contains(&telescope.min_altitude) {
+        problem("min_altitude", "must be between 0 and π/2 (radians)");
+    }
+
+    match &telescope.telescope_type {
+        TelescopeType::Salsa { definition } => {
+            let path = "telescope_type.Salsa.definition";
+            if definition.controller_address.is_empty() {
+                problem(&format!("{}.controller_address", path), "must not be empty");
+            }
+            if definition.receiver_address.is_empty() {
+                problem(&format!("{}.receiver_address", path), "must not be empty");
+            }
+            let mut polarizations = HashSet::from([definition.polarization]);
+            for (index, receiver) in definition.polarization_receivers.iter().enumerate() {
+                let path = format!("{}.polarization_receivers[{}]", path, index);
+                if receiver.address.is_empty() {
+                    problem(&format!("{}.address", path), "must not be empty");
+                }
+                if !polarizations.insert(receiver.polarization) {
+                    problem(
+                        &format!("{}.polarization", path),
+                        "is already received by another receiver",
+                    );
+                }
+            }
+            for (index, range) in definition.rfi_mask.iter().enumerate() {
+                if range.min > range.max {
+                    problem(
+                        &format!("{}.rfi_mask[{}]", path, index),
+                        "min must not be above max",
+                    );
+                }
+            }
+            if let Some(noise_diode) = &definition.noise_diode {
+                if noise_diode.address.is_empty() {
+                    problem(
+                        &format!("{}.noise_diode.address", path),
+                        "must not be empty",
+                    );
+                }
+                if noise_diode.temperature <= 0.0 {
+                    problem(
+                        &format!("{}.noise_diode.temperature", path),
+                        "must be positive (K)",
+                    );
+                }
+            }
+        }
+        TelescopeType::Fake { definition } => {
+            if definition.slewing_speed <= 0.0 {
+                problem(
+                    "telescope_type.Fake.definition.slewing_speed",
+                    "must be positive (radians per second)",
+                );
+            }
+        }
+    }
+
+    if let Some(rfi_scan) = &telescope.rfi_scan {
+        if rfi_scan.start_frequency >= rfi_scan.stop_frequency {
+            problem("rfi_scan.stop_frequency", "must be above start_frequency");
+        }
+        if rfi_scan.interval_minutes == 0 {
+            problem("rfi_scan.interval_minutes", "must be at least 1");
+        }
+    }
+
+    let mut device_names = HashSet::new();
+    for (index, device) in telescope.auxiliary_devices.iter().enumerate() {
+        if !device_names.insert(device.name.as_str()) {
+            problem(
+                &format!("auxiliary_devices[{}].name", index),
+                &format!("device name {} is used more than once", device.name),
+            );
+        }
+        if device.address.is_empty() {
+            problem(
+                &format!("auxiliary_devices[{}].address", index),
+                "must not be empty",
+            );
+        }
+    }
+
+    for (index, policy) in telescope.park_policies.iter().enumerate() {
+        if let Err(ParkPolicyError::InvalidPolicy(message)) = policy.validate() {
+            problem(&format!("park_policies[{}]", index), &message);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const TELESCOPE: &str = r#"{
+        "name": "fake",
+        "enabled": true,
+        "location": {"longitude": 0.2, "latitude": 1.0},
+        "min_altitude": 0.087,
+        "telescope_type": {"Fake": {"definition": {"slewing_speed": 0.314}}}
+    }"#;
+
+    #[test]
+    fn test_parse_configuration() {
+        let data = format!(r#"{{"bookings": [], "telescopes": [{}]}}"#, TELESCOPE);
+        let data_model = parse_configuration("database.json", data.as_bytes()).unwrap();
+        assert_eq!(data_model.telescopes[0].name, "fake");
+        assert!(data_model.telescopes[0].rfi_scan.is_none());
+
+        let data = format!(
+            r#"{{"bookings": [], "telescopes": [{}]}}"#,
+            TELESCOPE.replace("Fake", "Fak")
+        );
+        match parse_configuration("database.json", data.as_bytes()) {
+            Err(ConfigError::Syntax {
+                path,
+                line,
+                message,
+                ..
+            }) => {
+                assert_eq!(path, "telescopes[0].telescope_type");
+                assert_eq!(line, 6);
+                assert!(message.contains("expected `Salsa` or `Fake`"));
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        let data = format!(
+            r#"{{"bookings": [], "dut1": 1.2,
+                "tracker_telemetry": {{"directory": "telemetry", "max_records": 0}},
+                "telescopes": [{}, {}]}}"#,
+            TELESCOPE,
+            TELESCOPE.replace("0.087", "-0.1")
+        );
+        match parse_configuration("database.json", data.as_bytes()) {
+            Err(ConfigError::Invalid { problems, .. }) => {
+                let paths: Vec<_> = problems.iter().map(|p| p.path.as_str()).collect();
+                assert_eq!(
+                    paths,
+                    vec![
+                        "telescopes[1].name",
+                        "telescopes[1].min_altitude",
+                        "dut1",
+                        "tracker_telemetry.max_records"
+                    ]
+                );
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
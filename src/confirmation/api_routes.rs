@@ -0,0 +1,74 @@
+use crate::authentication::Requester;
+use crate::confirmation::{
+    issue_confirmation_token, AuditEntry, ConfirmationError, ConfirmationRequest, ConfirmationToken,
+};
+use crate::database::{DataBase, Storage};
+use axum::{
+    extract::{Json, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Router,
+};
+use chrono::Utc;
+
+pub fn routes(database: DataBase<impl Storage + 'static>) -> Router {
+    Router::new()
+        .route("/", post(post_confirmation))
+        .route("/audit", get(get_audit_log))
+        .with_state(database)
+}
+
+impl IntoResponse for ConfirmationError {
+    fn into_response(self) -> Response {
+        match self {
+            ConfirmationError::NotAuthenticated => (
+                StatusCode::UNAUTHORIZED,
+                "Log in as an admin to do this".to_string(),
+            )
+                .into_response(),
+            ConfirmationError::NotAdmin(user_name) => (
+                StatusCode::FORBIDDEN,
+                format!("Only admins may do this, {} is not one", user_name),
+            )
+                .into_response(),
+            ConfirmationError::InvalidSecret => (
+                StatusCode::FORBIDDEN,
+                "Wrong confirmation secret".to_string(),
+            )
+                .into_response(),
+            ConfirmationError::MissingToken => (
+                StatusCode::FORBIDDEN,
+                "This action has to be confirmed with a confirmation token".to_string(),
+            )
+                .into_response(),
+            ConfirmationError::InvalidToken => (
+                StatusCode::FORBIDDEN,
+                "The confirmation token is not valid for this action".to_string(),
+            )
+                .into_response(),
+            ConfirmationError::ServiceUnavailable => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Failed to check confirmation".to_string(),
+            )
+                .into_response(),
+        }
+    }
+}
+
+async fn post_confirmation(
+    State(db): State<DataBase<impl Storage>>,
+    requester: Requester,
+    Json(request): Json<ConfirmationRequest>,
+) -> Result<Json<ConfirmationToken>, ConfirmationError> {
+    Ok(Json(
+        issue_confirmation_token(&db, requester.name(), request, Utc::now()).await?,
+    ))
+}
+
+/// The audit trail, only for admins, see [`crate::authorization::POLICIES`].
+async fn get_audit_log(
+    State(db): State<DataBase<impl Storage>>,
+) -> Result<Json<Vec<AuditEntry>>, ConfirmationError> {
+    Ok(Json(db.get_data().await?.audit_log))
+}
@@ -0,0 +1,357 @@
+//! Confirmation of destructive admin actions, such as restarting a
+//! telescope or deleting a user.
+//!
+//! Before performing such an action an admin asks for a confirmation token
+//! for it, naming the method and path of the request. The token is only
+//! given to an authenticated admin who also gives their confirmation
+//! secret, which is handed out with `--issue-confirmation-secret` on the
+//! server, so a stolen API token is not enough to destroy anything. It is
+//! valid for one request by the same admin to that path for a few minutes,
+//! and has to be sent in the `Confirmation-Token` header. Every token
+//! handed out or refused, and every confirmed or rejected request, is kept
+//! in the audit trail, which only admins may read.
+
+use crate::authentication::{hash_token, new_token};
+use crate::database::{DataBase, DataBaseError, DataModel, Storage};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+pub mod api_routes;
+pub mod routes;
+
+pub const CONFIRMATION_TOKEN_HEADER: &str = "confirmation-token";
+/// How long a token can be used after it was handed out.
+pub const CONFIRMATION_TOKEN_MINUTES: i64 = 5;
+
+/// The action an admin wants to confirm, and their confirmation secret.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct ConfirmationRequest {
+    pub method: String,
+    pub path: String,
+    pub secret: String,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct ConfirmationToken {
+    pub token: String,
+    /// The admin the token was handed out to, the only one who may use it.
+    pub user_name: String,
+    pub method: String,
+    pub path: String,
+    pub expires: DateTime<Utc>,
+}
+
+/// The secret an admin confirms actions with, only stored as a hash.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct ConfirmationSecret {
+    pub user_name: String,
+    /// SHA-256 of the secret, hex encoded.
+    pub secret_hash: String,
+    pub created: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub enum AuditOutcome {
+    TokenIssued,
+    TokenRefused,
+    Confirmed,
+    Rejected(String),
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct AuditEntry {
+    pub time: DateTime<Utc>,
+    /// None for anonymous requests.
+    pub user_name: Option<String>,
+    pub method: String,
+    pub path: String,
+    pub outcome: AuditOutcome,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ConfirmationError {
+    NotAuthenticated,
+    NotAdmin(String),
+    InvalidSecret,
+    MissingToken,
+    InvalidToken,
+    ServiceUnavailable,
+}
+
+impl From<DataBaseError> for ConfirmationError {
+    fn from(_source: DataBaseError) -> Self {
+        Self::ServiceUnavailable
+    }
+}
+
+/// Check that the authenticated `user_name`, empty for anonymous requests,
+/// is an admin.
+pub fn check_admin(data_model: &DataModel, user_name: &str) -> Result<(), ConfirmationError> {
+    if user_name.is_empty() {
+        Err(ConfirmationError::NotAuthenticated)
+    } else if data_model
+        .users
+        .iter()
+        .any(|u| u.name == user_name && u.is_admin())
+    {
+        Ok(())
+    } else {
+        Err(ConfirmationError::NotAdmin(user_name.to_string()))
+    }
+}
+
+/// Give the admin `user_name` a new confirmation secret, replacing the one
+/// they had.
+pub async fn issue_confirmation_secret(
+    db: &DataBase<impl Storage>,
+    user_name: &str,
+    now: DateTime<Utc>,
+) -> Result<String, ConfirmationError> {
+    let secret = new_token();
+    let mut result = Err(ConfirmationError::NotAdmin(user_name.to_string()));
+    db.update_data(|mut data_model| {
+        if check_admin(&data_model, user_name).is_ok() {
+            data_model
+                .confirmation_secrets
+                .retain(|s| s.user_name != user_name);
+            data_model.confirmation_secrets.push(ConfirmationSecret {
+                user_name: user_name.to_string(),
+                secret_hash: hash_token(&secret),
+                created: now,
+            });
+            result = Ok(secret.clone());
+        }
+        data_model
+    })
+    .await?;
+    result
+}
+
+/// Hand out a token for the action in `request` to the authenticated
+/// `user_name`, if they are an admin and gave their confirmation secret.
+pub async fn issue_confirmation_token(
+    db: &DataBase<impl Storage>,
+    user_name: &str,
+    request: ConfirmationRequest,
+    now: DateTime<Utc>,
+) -> Result<ConfirmationToken, ConfirmationError> {
+    let mut result = Err(ConfirmationError::NotAuthenticated);
+    db.update_data(|mut data_model| {
+        let secret_hash = hash_token(&request.secret);
+        result = check_admin(&data_model, user_name).and_then(|_| {
+            if data_model
+                .confirmation_secrets
+                .iter()
+                .any(|s| s.user_name == user_name && s.secret_hash == secret_hash)
+            {
+                Ok(ConfirmationToken {
+                    token: new_token(),
+                    user_name: user_name.to_string(),
+                    method: request.method.clone(),
+                    path: request.path.clone(),
+                    expires: now + Duration::minutes(CONFIRMATION_TOKEN_MINUTES),
+                })
+            } else {
+                Err(ConfirmationError::InvalidSecret)
+            }
+        });
+        let outcome = match &result {
+            Ok(token) => {
+                data_model.confirmation_tokens.retain(|t| t.expires > now);
+                data_model.confirmation_tokens.push(token.clone());
+                AuditOutcome::TokenIssued
+            }
+            Err(_) => AuditOutcome::TokenRefused,
+        };
+        data_model.audit_log.push(AuditEntry {
+            time: now,
+            user_name: Some(user_name.to_string()).filter(|name| !name.is_empty()),
+            method: request.method.clone(),
+            path: request.path.clone(),
+            outcome,
+        });
+        data_model
+    })
+    .await?;
+    if let Err(error) = &result {
+        log::warn!(
+            "Refused to confirm {} {} for {:?}: {:?}",
+            request.method,
+            request.path,
+            user_name,
+            error
+        );
+    }
+    result
+}
+
+/// Use up `token` for a request by the authenticated `user_name` with
+/// `method` to `path`, returning the name of the admin it was handed out
+/// to, who has to be the one making the request.
+pub async fn consume_confirmation_token(
+    db: &DataBase<impl Storage>,
+    user_name: &str,
+    token: Option<&str>,
+    method: &str,
+    path: &str,
+    now: DateTime<Utc>,
+) -> Result<String, ConfirmationError> {
+    let mut result = Err(ConfirmationError::MissingToken);
+    db.update_data(|mut data_model| {
+        data_model.confirmation_tokens.retain(|t| t.expires > now);
+        if let Some(token) = token {
+            result = Err(ConfirmationError::InvalidToken);
+            if let Some(index) = data_model.confirmation_tokens.iter().position(|t| {
+                t.token == token && t.user_name == user_name && t.method == method && t.path == path
+            }) {
+                let confirmation = data_model.confirmation_tokens.remove(index);
+                result = Ok(confirmation.user_name);
+            }
+        }
+        data_model.audit_log.push(AuditEntry {
+            time: now,
+            user_name: Some(user_name.to_string()).filter(|name| !name.is_empty()),
+            method: method.to_string(),
+            path: path.to_string(),
+            outcome: match &result {
+                Ok(_) => AuditOutcome::Confirmed,
+                Err(error) => AuditOutcome::Rejected(format!("{:?}", error)),
+            },
+        });
+        data_model
+    })
+    .await?;
+    match &result {
+        Ok(user_name) => log::warn!("{} confirmed {} {}", user_name, method, path),
+        Err(error) => log::warn!("Rejected {} {}: {:?}", method, path, error),
+    }
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::database::create_in_memory_database;
+    use crate::users::{Role, User};
+
+    #[tokio::test]
+    async fn test_confirmation_token() {
+        let db = create_in_memory_database();
+        let now = Utc::now();
+        db.update_data(|mut data_model| {
+            data_model.users = vec![
+                User {
+                    role: Role::Admin,
+                    ..User::new("anna")
+                },
+                User {
+                    role: Role::Admin,
+                    ..User::new("cecilia")
+                },
+                User::new("bertil"),
+            ];
+            data_model
+        })
+        .await
+        .unwrap();
+        assert_eq!(
+            issue_confirmation_secret(&db, "bertil", now).await,
+            Err(ConfirmationError::NotAdmin("bertil".to_string()))
+        );
+        let secret = issue_confirmation_secret(&db, "anna", now).await.unwrap();
+        let request = ConfirmationRequest {
+            method: "POST".to_string(),
+            path: "/api/telescopes/fake/restart".to_string(),
+            secret,
+        };
+
+        assert_eq!(
+            issue_confirmation_token(&db, "", request.clone(), now).await,
+            Err(ConfirmationError::NotAuthenticated)
+        );
+        assert_eq!(
+            issue_confirmation_token(&db, "bertil", request.clone(), now).await,
+            Err(ConfirmationError::NotAdmin("bertil".to_string()))
+        );
+        // The secret of one admin does not confirm for another.
+        assert_eq!(
+            issue_confirmation_token(&db, "cecilia", request.clone(), now).await,
+            Err(ConfirmationError::InvalidSecret)
+        );
+        assert_eq!(
+            issue_confirmation_token(
+                &db,
+                "anna",
+                ConfirmationRequest {
+                    secret: "guess".to_string(),
+                    ..request.clone()
+                },
+                now
+            )
+            .await,
+            Err(ConfirmationError::InvalidSecret)
+        );
+        let token = issue_confirmation_token(&db, "anna", request.clone(), now)
+            .await
+            .unwrap();
+
+        let consume = |user_name, token, method, path, now| {
+            let db = db.clone();
+            async move { consume_confirmation_token(&db, user_name, token, method, path, now).await }
+        };
+        assert_eq!(
+            consume("anna", None, "POST", &request.path, now).await,
+            Err(ConfirmationError::MissingToken)
+        );
+        // Only for the action it was handed out for.
+        assert_eq!(
+            consume(
+                "anna",
+                Some(&token.token),
+                "POST",
+                "/api/users/bertil/deletion/confirm",
+                now
+            )
+            .await,
+            Err(ConfirmationError::InvalidToken)
+        );
+        // Only by the admin it was handed out to.
+        assert_eq!(
+            consume("bertil", Some(&token.token), "POST", &request.path, now).await,
+            Err(ConfirmationError::InvalidToken)
+        );
+        // Not after it has expired.
+        assert_eq!(
+            consume(
+                "anna",
+                Some(&token.token),
+                "POST",
+                &request.path,
+                now + Duration::minutes(CONFIRMATION_TOKEN_MINUTES + 1)
+            )
+            .await,
+            Err(ConfirmationError::InvalidToken)
+        );
+
+        let token = issue_confirmation_token(&db, "anna", request.clone(), now)
+            .await
+            .unwrap();
+        assert_eq!(
+            consume("anna", Some(&token.token), "POST", &request.path, now).await,
+            Ok("anna".to_string())
+        );
+        // Only once.
+        assert_eq!(
+            consume("anna", Some(&token.token), "POST", &request.path, now).await,
+            Err(ConfirmationError::InvalidToken)
+        );
+
+        let audit_log = db.get_data().await.unwrap().audit_log;
+        assert_eq!(audit_log.len(), 12);
+        assert_eq!(audit_log[0].outcome, AuditOutcome::TokenRefused);
+        assert_eq!(audit_log[0].user_name, None);
+        assert_eq!(audit_log[4].outcome, AuditOutcome::TokenIssued);
+        assert_eq!(audit_log[7].user_name, Some("bertil".to_string()));
+        assert_eq!(audit_log[10].outcome, AuditOutcome::Confirmed);
+    }
+}
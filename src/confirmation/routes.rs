@@ -0,0 +1,93 @@
+use crate::authentication::Requester;
+use crate::confirmation::{issue_confirmation_token, ConfirmationError, ConfirmationRequest};
+use crate::database::{DataBase, Storage};
+use crate::template::HtmlTemplate;
+use askama::Template;
+use axum::{
+    extract::{Form, Query, State},
+    response::IntoResponse,
+    routing::get,
+    Router,
+};
+use chrono::Utc;
+use serde::Deserialize;
+
+pub fn routes(database: DataBase<impl Storage + 'static>) -> Router {
+    Router::new()
+        .route("/", get(get_confirmation).post(post_confirmation))
+        .with_state(database)
+}
+
+/// The action to confirm, and where htmx should put its response.
+#[derive(Deserialize, Debug, Clone)]
+struct Action {
+    method: String,
+    path: String,
+    description: String,
+    target: String,
+    swap: String,
+    /// Selector of inputs to send with the action, if it has any.
+    #[serde(default)]
+    include: String,
+}
+
+#[derive(Template)]
+#[template(path = "confirmation.html")]
+struct ConfirmationTemplate {
+    action: Action,
+    /// Set once the admin has been given a token for the action.
+    token: Option<String>,
+    message: Option<String>,
+}
+
+/// Dialog asking an admin to confirm a destructive action, swapped in
+/// where the button for the action was.
+async fn get_confirmation(Query(action): Query<Action>) -> impl IntoResponse {
+    HtmlTemplate(ConfirmationTemplate {
+        action,
+        token: None,
+        message: None,
+    })
+}
+
+#[derive(Deserialize, Debug)]
+struct ConfirmationForm {
+    secret: String,
+    #[serde(flatten)]
+    action: Action,
+}
+
+async fn post_confirmation<StorageType>(
+    State(db): State<DataBase<StorageType>>,
+    requester: Requester,
+    Form(form): Form<ConfirmationForm>,
+) -> impl IntoResponse
+where
+    StorageType: Storage,
+{
+    let request = ConfirmationRequest {
+        method: form.action.method.to_uppercase(),
+        path: form.action.path.clone(),
+        secret: form.secret,
+    };
+    let (token, message) =
+        match issue_confirmation_token(&db, requester.name(), request, Utc::now()).await {
+            Ok(token) => (Some(token.token), None),
+            Err(ConfirmationError::NotAuthenticated) => {
+                (None, Some("Log in as an admin to do this.".to_string()))
+            }
+            Err(ConfirmationError::NotAdmin(user_name)) => (
+                None,
+                Some(format!("{} is not allowed to do this.", user_name)),
+            ),
+            Err(ConfirmationError::InvalidSecret) => {
+                (None, Some("Wrong confirmation secret.".to_string()))
+            }
+            Err(error) => (None, Some(format!("Failed to confirm: {:?}", error))),
+        };
+    HtmlTemplate(ConfirmationTemplate {
+        action: form.action,
+        token,
+        message,
+    })
+}
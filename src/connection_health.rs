@@ -0,0 +1,107 @@
+//! Health of the connection to the hardware of a telescope.
+//!
+//! A telescope that does not respond is otherwise only explained by the
+//! logs, so the tracker counts failed connections and commands to the
+//! controller and remembers when a command last went through.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Default)]
+pub struct ConnectionHealth {
+    /// Whether the latest attempt to reach the controller succeeded.
+    pub controller_connected: bool,
+    pub last_successful_command: Option<DateTime<Utc>>,
+    pub since_last_successful_command: Option<Duration>,
+    /// Attempts to connect to the controller that failed.
+    pub connection_failures: u64,
+    /// Commands that failed after connecting.
+    pub io_errors: u64,
+    /// Times the controller was reached again after a failure.
+    pub reconnects: u64,
+    /// Error of the latest measurement or RFI scan if it failed, most likely
+    /// because the receiver could not be reached.
+    pub receiver_error: Option<String>,
+}
+
+#[derive(Default)]
+pub struct ConnectionMonitor {
+    connected: bool,
+    failing: bool,
+    last_success: Option<DateTime<Utc>>,
+    connection_failures: u64,
+    io_errors: u64,
+    reconnects: u64,
+}
+
+impl ConnectionMonitor {
+    pub fn record_connection_failure(&mut self) {
+        self.connected = false;
+        self.failing = true;
+        self.connection_failures += 1;
+    }
+
+    /// Record whether a command sent at `now` got through.
+    pub fn record_command(&mut self, succeeded: bool, now: DateTime<Utc>) {
+        self.connected = succeeded;
+        if !succeeded {
+            self.failing = true;
+            self.io_errors += 1;
+            return;
+        }
+        if self.failing {
+            self.failing = false;
+            self.reconnects += 1;
+        }
+        self.last_success = Some(now);
+    }
+
+    pub fn health(&self, now: DateTime<Utc>) -> ConnectionHealth {
+        ConnectionHealth {
+            controller_connected: self.connected,
+            last_successful_command: self.last_success,
+            since_last_successful_command: self
+                .last_success
+                .and_then(|last| (now - last).to_std().ok()),
+            connection_failures: self.connection_failures,
+            io_errors: self.io_errors,
+            reconnects: self.reconnects,
+            receiver_error: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_connection_monitor() {
+        let at = |second| Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, second).unwrap();
+        let mut monitor = ConnectionMonitor::default();
+        assert_eq!(monitor.health(at(0)), ConnectionHealth::default());
+
+        monitor.record_command(true, at(1));
+        monitor.record_connection_failure();
+        monitor.record_connection_failure();
+        monitor.record_command(false, at(4));
+        let health = monitor.health(at(5));
+        assert!(!health.controller_connected);
+        assert_eq!(health.last_successful_command, Some(at(1)));
+        assert_eq!(
+            health.since_last_successful_command,
+            Some(Duration::from_secs(4))
+        );
+        assert_eq!(health.connection_failures, 2);
+        assert_eq!(health.io_errors, 1);
+        assert_eq!(health.reconnects, 0);
+
+        monitor.record_command(true, at(6));
+        monitor.record_command(true, at(7));
+        let health = monitor.health(at(7));
+        assert!(health.controller_connected);
+        assert_eq!(health.reconnects, 1);
+    }
+}
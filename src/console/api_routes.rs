@@ -0,0 +1,79 @@
+use crate::authentication::Requester;
+use crate::console::{
+    send_raw_command, ConsoleEntry, ConsoleError, ConsoleState, RawCommandRequest,
+};
+use crate::database::{DataBase, Storage};
+use crate::telescope::TelescopeCollection;
+use axum::{
+    extract::{Json, Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Router,
+};
+use chrono::Utc;
+
+pub fn routes(
+    telescopes: TelescopeCollection,
+    database: DataBase<impl Storage + 'static>,
+) -> Router {
+    Router::new()
+        .route("/", get(get_console_log))
+        .route("/:telescope_name", post(post_raw_command))
+        .with_state(ConsoleState {
+            database,
+            telescopes,
+        })
+}
+
+impl IntoResponse for ConsoleError {
+    fn into_response(self) -> Response {
+        match self {
+            ConsoleError::UnknownTelescope => {
+                (StatusCode::NOT_FOUND, "Telescope not found".to_string()).into_response()
+            }
+            ConsoleError::NotAdmin(user_name) => (
+                StatusCode::FORBIDDEN,
+                format!("{} is not allowed to send raw commands", user_name),
+            )
+                .into_response(),
+            ConsoleError::NotConfirmed => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "Confirm the command by repeating the name of the telescope".to_string(),
+            )
+                .into_response(),
+            ConsoleError::InvalidCommand(message) => {
+                (StatusCode::UNPROCESSABLE_ENTITY, message).into_response()
+            }
+            ConsoleError::ServiceUnavailable => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Failed to record raw command".to_string(),
+            )
+                .into_response(),
+        }
+    }
+}
+
+async fn get_console_log<StorageType: Storage>(
+    State(state): State<ConsoleState<StorageType>>,
+) -> Result<Json<Vec<ConsoleEntry>>, ConsoleError> {
+    Ok(Json(state.database.get_data().await?.console_log))
+}
+
+async fn post_raw_command<StorageType: Storage>(
+    State(state): State<ConsoleState<StorageType>>,
+    Path(telescope_name): Path<String>,
+    requester: Requester,
+    Json(request): Json<RawCommandRequest>,
+) -> Result<Json<ConsoleEntry>, ConsoleError> {
+    let entry = send_raw_command(
+        &state.database,
+        &state.telescopes,
+        &telescope_name,
+        requester.name(),
+        request,
+        Utc::now(),
+    )
+    .await?;
+    Ok(Json(entry))
+}
@@ -0,0 +1,233 @@
+//! Console for sending raw commands to the rot2prog controller of a
+//! telescope, for admins debugging a misbehaving rotator on site.
+//!
+//! Raw commands bypass the tracker and the interlocks, so sending one is an
+//! admin action that needs a confirmation token, see
+//! [`crate::authorization`]. Each command also has to be confirmed by
+//! repeating the name of the telescope, and every command sent is kept in an
+//! audit log together with the response.
+
+use crate::database::{DataBase, DataBaseError, Storage};
+use crate::telescope::TelescopeCollection;
+use crate::telescopes::TelescopeError;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+pub mod api_routes;
+pub mod routes;
+
+/// State of the console routes, which talk to the telescopes.
+#[derive(Clone)]
+pub struct ConsoleState<StorageType: Storage> {
+    pub database: DataBase<StorageType>,
+    pub telescopes: TelescopeCollection,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct RawCommandRequest {
+    /// Bytes to send as hex, e.g. `57 00 00 00 00 00 00 00 00 00 00 6F 20`.
+    pub command: String,
+    /// Has to be the name of the telescope, to confirm that the admin knows
+    /// which telescope they are about to move.
+    pub confirmation: String,
+}
+
+/// A raw command sent to a controller.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct ConsoleEntry {
+    pub time: DateTime<Utc>,
+    pub telescope_name: String,
+    pub user_name: String,
+    pub command: String,
+    /// The response as hex, or why there was none.
+    pub response: Result<String, String>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ConsoleError {
+    UnknownTelescope,
+    NotAdmin(String),
+    NotConfirmed,
+    InvalidCommand(String),
+    ServiceUnavailable,
+}
+
+impl From<DataBaseError> for ConsoleError {
+    fn from(_source: DataBaseError) -> Self {
+        Self::ServiceUnavailable
+    }
+}
+
+/// Parse bytes written as hex, optionally separated by whitespace.
+pub fn parse_hex(input: &str) -> Result<Vec<u8>, ConsoleError> {
+    let digits: String = input.split_whitespace().collect();
+    if digits.is_empty() || !digits.len().is_multiple_of(2) {
+        return Err(ConsoleError::InvalidCommand(
+            "The command has to be a whole number of bytes written as hex".to_string(),
+        ));
+    }
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| {
+            digits
+                .get(i..i + 2)
+                .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+                .ok_or_else(|| {
+                    ConsoleError::InvalidCommand(format!("Invalid hex in command: {}", input))
+                })
+        })
+        .collect()
+}
+
+pub fn format_hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Send the command in `request` from the authenticated `user_name` to the
+/// controller of `telescope_name` and record it in the audit log, whether
+/// the controller responded or not.
+pub async fn send_raw_command(
+    db: &DataBase<impl Storage>,
+    telescopes: &TelescopeCollection,
+    telescope_name: &str,
+    user_name: &str,
+    request: RawCommandRequest,
+    now: DateTime<Utc>,
+) -> Result<ConsoleEntry, ConsoleError> {
+    let is_admin = db
+        .get_data()
+        .await?
+        .users
+        .iter()
+        .any(|u| u.name == user_name && u.is_admin());
+    if !is_admin {
+        log::warn!(
+            "{:?} tried to send a raw command to {} without being an admin",
+            user_name,
+            telescope_name
+        );
+        return Err(ConsoleError::NotAdmin(user_name.to_string()));
+    }
+    if request.confirmation != telescope_name {
+        return Err(ConsoleError::NotConfirmed);
+    }
+    let bytes = parse_hex(&request.command)?;
+    let telescope = telescopes
+        .read()
+        .await
+        .get(telescope_name)
+        .ok_or(ConsoleError::UnknownTelescope)?
+        .telescope
+        .clone();
+
+    let response = telescope.lock().await.send_raw_command(bytes.clone()).await;
+    let entry = ConsoleEntry {
+        time: now,
+        telescope_name: telescope_name.to_string(),
+        user_name: user_name.to_string(),
+        command: format_hex(&bytes),
+        response: response
+            .as_deref()
+            .map(format_hex)
+            .map_err(TelescopeError::to_string),
+    };
+    log::warn!(
+        "{} sent raw command {} to {}, response: {:?}",
+        entry.user_name,
+        entry.command,
+        entry.telescope_name,
+        entry.response
+    );
+    db.update_data(|mut data_model| {
+        data_model.console_log.push(entry.clone());
+        data_model
+    })
+    .await?;
+    Ok(entry)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::database::create_in_memory_database;
+    use crate::interlock::CollisionInterlock;
+    use crate::supervisor::TelescopeSupervisor;
+    use crate::telescope::TelescopeContainer;
+    use crate::users::{Role, User};
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use tokio::sync::{Mutex, RwLock};
+
+    #[tokio::test]
+    async fn test_send_raw_command() {
+        let db = create_in_memory_database();
+        let now = Utc::now();
+        let supervisor = TelescopeSupervisor::new("fake");
+        let telescopes: TelescopeCollection = Arc::new(RwLock::new(HashMap::from([(
+            "fake".to_string(),
+            TelescopeContainer {
+                telescope: Arc::new(Mutex::new(crate::fake_telescope::create(
+                    "fake".to_string(),
+                    None,
+                    vec![],
+                    supervisor.clone(),
+                    CollisionInterlock::default(),
+                ))),
+                supervisor,
+                updates: Default::default(),
+            },
+        )])));
+        db.update_data(|mut data_model| {
+            data_model.users = vec![
+                User {
+                    role: Role::Admin,
+                    ..User::new("anna")
+                },
+                User::new("bertil"),
+            ];
+            data_model
+        })
+        .await
+        .unwrap();
+        let request = RawCommandRequest {
+            command: "57 00 00 00 00 00 00 00 00 00 00 0f20".to_string(),
+            confirmation: "fake".to_string(),
+        };
+
+        assert_eq!(
+            parse_hex(&request.command).map(|b| format_hex(&b)),
+            Ok("57 00 00 00 00 00 00 00 00 00 00 0F 20".to_string())
+        );
+        assert!(parse_hex("57 0").is_err());
+        assert!(parse_hex("5G").is_err());
+
+        let send = |user_name, request| {
+            send_raw_command(&db, &telescopes, "fake", user_name, request, now)
+        };
+        assert_eq!(
+            send("bertil", request.clone()).await,
+            Err(ConsoleError::NotAdmin("bertil".to_string()))
+        );
+        assert_eq!(
+            send(
+                "anna",
+                RawCommandRequest {
+                    confirmation: "salsa".to_string(),
+                    ..request.clone()
+                }
+            )
+            .await,
+            Err(ConsoleError::NotConfirmed)
+        );
+        assert_eq!(db.get_data().await.unwrap().console_log, vec![]);
+
+        // The fake telescope has no controller, but the attempt is logged.
+        let entry = send("anna", request).await.unwrap();
+        assert!(entry.response.is_err());
+        assert_eq!(db.get_data().await.unwrap().console_log, vec![entry]);
+    }
+}
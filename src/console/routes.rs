@@ -0,0 +1,115 @@
+use crate::authentication::Requester;
+use crate::console::{send_raw_command, ConsoleError, ConsoleState, RawCommandRequest};
+use crate::database::{DataBase, Storage};
+use crate::telescope::TelescopeCollection;
+use crate::template::HtmlTemplate;
+use askama::Template;
+use axum::{
+    extract::{Form, Path, State},
+    response::IntoResponse,
+    routing::{get, post},
+    Router,
+};
+use chrono::Utc;
+
+pub fn routes(
+    telescopes: TelescopeCollection,
+    database: DataBase<impl Storage + 'static>,
+) -> Router {
+    Router::new()
+        .route("/", get(get_console))
+        .route("/:telescope_name", post(post_console))
+        .with_state(ConsoleState {
+            database,
+            telescopes,
+        })
+}
+
+struct ConsoleLogEntry {
+    time: String,
+    telescope_name: String,
+    user_name: String,
+    command: String,
+    response: String,
+}
+
+#[derive(Template)]
+#[template(path = "console.html")]
+struct ConsoleTemplate {
+    telescope_names: Vec<String>,
+    entries: Vec<ConsoleLogEntry>,
+    message: Option<String>,
+}
+
+async fn render_console(
+    db: &DataBase<impl Storage>,
+    message: Option<String>,
+) -> HtmlTemplate<ConsoleTemplate> {
+    let data_model = db
+        .get_data()
+        .await
+        .expect("As long as no one is manually editing the database, this should never fail.");
+    let entries = data_model
+        .console_log
+        .into_iter()
+        .rev()
+        .map(|entry| ConsoleLogEntry {
+            time: entry.time.format("%Y-%m-%d %H:%M:%S").to_string(),
+            telescope_name: entry.telescope_name,
+            user_name: entry.user_name,
+            command: entry.command,
+            response: entry.response.unwrap_or_else(|error| error),
+        })
+        .collect();
+    HtmlTemplate(ConsoleTemplate {
+        telescope_names: data_model.telescopes.into_iter().map(|t| t.name).collect(),
+        entries,
+        message,
+    })
+}
+
+async fn get_console<StorageType>(
+    State(state): State<ConsoleState<StorageType>>,
+) -> impl IntoResponse
+where
+    StorageType: Storage,
+{
+    render_console(&state.database, None).await
+}
+
+/// Send a raw command, once confirmed by an admin in the confirmation
+/// dialog.
+async fn post_console<StorageType>(
+    State(state): State<ConsoleState<StorageType>>,
+    Path(telescope_name): Path<String>,
+    requester: Requester,
+    Form(request): Form<RawCommandRequest>,
+) -> impl IntoResponse
+where
+    StorageType: Storage,
+{
+    let message = match send_raw_command(
+        &state.database,
+        &state.telescopes,
+        &telescope_name,
+        requester.name(),
+        request,
+        Utc::now(),
+    )
+    .await
+    {
+        Ok(entry) => match entry.response {
+            Ok(response) => format!("Response: {}", response),
+            Err(error) => format!("No response: {}", error),
+        },
+        Err(ConsoleError::NotAdmin(user_name)) => {
+            format!("{} is not allowed to send raw commands.", user_name)
+        }
+        Err(ConsoleError::NotConfirmed) => {
+            "Type the name of the telescope to confirm the command.".to_string()
+        }
+        Err(ConsoleError::InvalidCommand(message)) => message,
+        Err(error) => format!("Failed to send the command: {:?}", error),
+    };
+    render_console(&state.database, Some(message)).await
+}
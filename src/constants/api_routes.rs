@@ -0,0 +1,58 @@
+use crate::constants::{
+    fetch_spectral_lines, lsr_velocities, SpectralLine, VelocityAxis, VelocityError,
+};
+use crate::database::{DataBase, Storage};
+use crate::telescopes::TelescopeTarget;
+use axum::{
+    extract::{Json, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Router,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+pub fn routes(database: DataBase<impl Storage + 'static>) -> Router {
+    Router::new()
+        .route("/", get(get_spectral_lines))
+        .route("/velocity", post(post_velocity))
+        .with_state(database)
+}
+
+impl IntoResponse for VelocityError {
+    fn into_response(self) -> Response {
+        (StatusCode::BAD_REQUEST, self.to_string()).into_response()
+    }
+}
+
+async fn get_spectral_lines(State(db): State<DataBase<impl Storage>>) -> Json<Vec<SpectralLine>> {
+    Json(fetch_spectral_lines(&db).await)
+}
+
+/// A frequency axis to convert to velocities relative to the LSR.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct VelocityRequest {
+    /// Name of the spectral line, e.g. "HI".
+    pub line: String,
+    /// Channel frequencies in Hz.
+    pub frequencies: Vec<f64>,
+    pub target: TelescopeTarget,
+    /// When the spectrum was observed, now if not given.
+    #[serde(default)]
+    pub time: Option<DateTime<Utc>>,
+}
+
+async fn post_velocity(
+    State(db): State<DataBase<impl Storage>>,
+    Json(request): Json<VelocityRequest>,
+) -> Result<Json<VelocityAxis>, VelocityError> {
+    let lines = fetch_spectral_lines(&db).await;
+    Ok(Json(lsr_velocities(
+        &lines,
+        &request.line,
+        &request.frequencies,
+        request.target,
+        request.time.unwrap_or_else(Utc::now),
+    )?))
+}
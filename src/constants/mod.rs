@@ -0,0 +1,165 @@
+use crate::coords::velocity_axis;
+use crate::database::{DataBase, DataModel, Storage};
+use crate::telescopes::TelescopeTarget;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+pub mod api_routes;
+
+/// Rest frequency of the 21 cm line of neutral hydrogen in Hz.
+pub const HI_REST_FREQUENCY: f64 = 1.420405751768e9;
+
+/// System temperature in K used for telescopes without a noise diode.
+pub const DEFAULT_SYSTEM_TEMPERATURE: f64 = 285.0;
+
+/// A spectral line that can be marked in spectra.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct SpectralLine {
+    pub name: String,
+    /// Rest frequency in Hz.
+    pub rest_frequency: f64,
+}
+
+impl SpectralLine {
+    fn new(name: &str, rest_frequency: f64) -> SpectralLine {
+        SpectralLine {
+            name: name.to_string(),
+            rest_frequency,
+        }
+    }
+}
+
+/// The lines that are within reach of the receivers, by default.
+pub fn builtin_spectral_lines() -> Vec<SpectralLine> {
+    vec![
+        SpectralLine::new("HI", HI_REST_FREQUENCY),
+        SpectralLine::new("OH 1612", 1.612231e9),
+        SpectralLine::new("OH 1665", 1.665402e9),
+        SpectralLine::new("OH 1667", 1.667359e9),
+        SpectralLine::new("OH 1720", 1.720530e9),
+    ]
+}
+
+/// The built in lines together with the lines configured in the database.
+/// A configured line replaces a built in line with the same name.
+pub fn spectral_lines(data_model: &DataModel) -> Vec<SpectralLine> {
+    let mut lines: Vec<SpectralLine> = builtin_spectral_lines()
+        .into_iter()
+        .filter(|line| {
+            !data_model
+                .spectral_lines
+                .iter()
+                .any(|l| l.name == line.name)
+        })
+        .collect();
+    lines.extend(data_model.spectral_lines.iter().cloned());
+    lines.sort_by(|a, b| a.rest_frequency.total_cmp(&b.rest_frequency));
+    lines
+}
+
+pub async fn fetch_spectral_lines(db: &DataBase<impl Storage>) -> Vec<SpectralLine> {
+    let data_model = db
+        .get_data()
+        .await
+        .expect("As long as no one is manually editing the database, this should never fail.");
+    spectral_lines(&data_model)
+}
+
+#[derive(Debug, Error, PartialEq)]
+pub enum VelocityError {
+    #[error("there is no spectral line named {0}")]
+    UnknownLine(String),
+    #[error("the target is not a fixed position on the sky, so it has no LSR velocity")]
+    NoSkyPosition,
+}
+
+/// A frequency axis converted to velocities relative to the LSR.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct VelocityAxis {
+    pub line: SpectralLine,
+    /// Correction in km/s from the topocentric frame to the LSR, included
+    /// in the velocities.
+    pub correction: f64,
+    /// Velocity of each channel in km/s, in the radio convention.
+    pub velocities: Vec<f64>,
+}
+
+/// Velocities relative to the LSR of the line `line_name` among `lines`
+/// for a spectrum with channels at `freqs`, observed towards `target` at
+/// `when`.
+pub fn lsr_velocities(
+    lines: &[SpectralLine],
+    line_name: &str,
+    freqs: &[f64],
+    target: TelescopeTarget,
+    when: DateTime<Utc>,
+) -> Result<VelocityAxis, VelocityError> {
+    let line = lines
+        .iter()
+        .find(|line| line.name == line_name)
+        .ok_or_else(|| VelocityError::UnknownLine(line_name.to_string()))?;
+    let correction = target
+        .lsr_correction(when)
+        .ok_or(VelocityError::NoSkyPosition)?;
+    Ok(VelocityAxis {
+        line: line.clone(),
+        correction: correction / 1e3,
+        velocities: velocity_axis(freqs, line.rest_frequency, correction),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_configured_lines_replace_builtin() {
+        let data_model = DataModel {
+            spectral_lines: vec![
+                SpectralLine::new("HI", 1.4204e9),
+                SpectralLine::new("CH", 3.335481e9),
+            ],
+            ..Default::default()
+        };
+        let lines = spectral_lines(&data_model);
+        assert_eq!(lines.len(), builtin_spectral_lines().len() + 1);
+        assert_eq!(lines[0], SpectralLine::new("HI", 1.4204e9));
+        assert_eq!(lines.last().unwrap().name, "CH");
+    }
+
+    #[test]
+    fn test_lsr_velocities() {
+        use crate::angles::Degrees;
+        use crate::coords::lsr_velocity_axis;
+        use chrono::TimeZone;
+
+        let when = Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap();
+        let (l, b) = (Degrees(30.0).to_radians(), Degrees(0.0).to_radians());
+        let freqs = [1.4200e9, HI_REST_FREQUENCY, 1.4208e9];
+        let lines = builtin_spectral_lines();
+        let axis = lsr_velocities(
+            &lines,
+            "HI",
+            &freqs,
+            TelescopeTarget::Galactic { l, b },
+            when,
+        )
+        .unwrap();
+        assert_eq!(
+            axis.velocities,
+            lsr_velocity_axis(&freqs, HI_REST_FREQUENCY, l, b, when)
+        );
+        assert!((axis.velocities[1] - axis.correction).abs() < 1e-9);
+        // Lower frequencies are receding.
+        assert!(axis.velocities[0] > axis.velocities[2]);
+        assert_eq!(
+            lsr_velocities(&lines, "CO", &freqs, TelescopeTarget::Parked, when),
+            Err(VelocityError::UnknownLine("CO".to_string()))
+        );
+        assert_eq!(
+            lsr_velocities(&lines, "HI", &freqs, TelescopeTarget::Parked, when),
+            Err(VelocityError::NoSkyPosition)
+        );
+    }
+}
@@ -1,44 +1,51 @@
+use crate::angles::{Degrees, Radians};
 use chrono::{DateTime, TimeZone, Utc};
 use serde::{Deserialize, Serialize};
 use std::f64::consts::PI;
+use std::sync::atomic::{AtomicU64, Ordering};
 
 // Obliquity of the ecliptic, accurate to 1 arcmin per century from J2000
-const EC: f64 = 0.40909260052;
+const EC: Radians = Radians(0.40909260052);
 // The generally accepted motion of the Sun in the LSR is that it is moving at a speed of about
 // 20 km/s towards an “apex” that is rather close to the direction of Vega, with coordinates
 // R.A. = 18 hr = 270 deg, and Dec. = 30 deg. Define this apex as ARA, ADE:
-const ARA: f64 = 4.71238898038;
-const ADE: f64 = 0.52359877559;
+const ARA: Radians = Radians(4.71238898038);
+const ADE: Radians = Radians(0.52359877559);
 const R_EARTH: f64 = 6378.135; // Earth radius in km
-const FULL_CIRCLE: f64 = 2.0 * PI;
+
+/// Speed of light in vacuum in m/s.
+pub const SPEED_OF_LIGHT: f64 = 299_792_458.0;
 
 #[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
 pub struct Location {
-    pub longitude: f64,
-    pub latitude: f64,
+    pub longitude: Radians,
+    pub latitude: Radians,
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
 pub struct Direction {
-    pub azimuth: f64,
-    pub altitude: f64,
+    pub azimuth: Radians,
+    /// Angle above the horizon, also accepted as `elevation` from clients
+    /// using that name.
+    #[serde(alias = "elevation")]
+    pub altitude: Radians,
 }
 
 // Function to calculate satellite position for observe at given time
 // from https://celestrak.org/columns/v02n02/
 // satellite ECI xs,ys,zs in km
-// observer lat,long in radians, alt in km
+// observer lat,long, alt in km
 pub fn horizontal_from_sat_eci(
     xs: f64,
     ys: f64,
     zs: f64,
-    lat: f64,
-    lon: f64,
+    lat: Radians,
+    lon: Radians,
     alt: f64,
     when: DateTime<Utc>,
-) -> (f64, f64) {
+) -> Direction {
     // Calculate ECI coordinates of observer position
-    let theta = (gmst(when) + lon) % FULL_CIRCLE;
+    let theta = (gmst(when) + lon).normalized();
     let r = (R_EARTH + alt) * lat.cos();
     let xo = r * theta.cos();
     let yo = r * theta.sin();
@@ -53,12 +60,14 @@ pub fn horizontal_from_sat_eci(
     let top_z = lat.cos() * theta.cos() * rx + lat.cos() * theta.sin() * ry + lat.sin() * rz;
     // Calculate az/el from topocentric vector
     // Use atan2 instead of celestrack tan logic:
-    let az = (top_e).atan2(-top_s);
-    // Ensure positive az
-    let az = ((az % FULL_CIRCLE) + FULL_CIRCLE) % FULL_CIRCLE;
+    let az = Radians::atan2(top_e, -top_s);
     let rg = (rx * rx + ry * ry + rz * rz).sqrt();
-    let el = (top_z / rg).asin();
-    (az, el)
+    let el = Radians::asin(top_z / rg);
+    Direction {
+        // Ensure positive az
+        azimuth: az.normalized(),
+        altitude: el,
+    }
 }
 
 fn julian_day(when: DateTime<Utc>) -> f64 {
@@ -73,9 +82,34 @@ fn julian_day(when: DateTime<Utc>) -> f64 {
     2451545.0 + (diff.num_milliseconds() as f64 / (24.0 * 60.0 * 60.0 * 1000.0))
 }
 
-fn gmst(when: DateTime<Utc>) -> f64 {
+/// UT1 - UTC in seconds, as the bits of an f64.
+static DUT1: AtomicU64 = AtomicU64::new(0);
+
+/// Set UT1 - UTC in seconds, as published in IERS Bulletin A. Leap seconds
+/// keep it within 0.9 s, which is at most 0.004 degrees of hour angle.
+pub fn set_dut1(seconds: f64) {
+    DUT1.store(seconds.to_bits(), Ordering::Relaxed);
+}
+
+pub fn dut1() -> f64 {
+    f64::from_bits(DUT1.load(Ordering::Relaxed))
+}
+
+/// Greenwich mean sidereal time at `when`, corrected to UT1 with the DUT1
+/// set by [`set_dut1`].
+///
+/// Times are counted without leap seconds, so `when` is UTC as a count of
+/// SI days since J2000 only between leap seconds. Correcting with DUT1
+/// takes care of that as well, since it jumps by a second with each leap
+/// second.
+fn gmst(when: DateTime<Utc>) -> Radians {
+    gmst_with_dut1(when, dut1())
+}
+
+fn gmst_with_dut1(when: DateTime<Utc>, dut1: f64) -> Radians {
     // Algoritm from https://aa.usno.navy.mil/faq/GAST
-    let jd = julian_day(when);
+    let ut1 = when + chrono::Duration::microseconds((dut1 * 1e6).round() as i64);
+    let jd = julian_day(ut1);
     let jd0 = jd.floor() + 0.5;
     let h = (jd - jd0) * 24.0;
     let dtt = jd - 2451545.0;
@@ -83,75 +117,86 @@ fn gmst(when: DateTime<Utc>) -> f64 {
     let t = dtt / 36525.0;
     let gmst = (6.697375 + 0.065709824279 * dut + 1.0027379 * h + 0.0000258 * t * t) % 24.0;
     // return GMST in radians
-    gmst * PI / 12.0
+    Radians(gmst * PI / 12.0)
 }
 
 /// Convert equatorial coordinates to horizontal coordinates
 /// # Arguments
 /// * `location` - Location struct with latitude and longitude
-/// * `ra` - Right ascension in radians
-/// * `dec` - Declination in radians
+/// * `ra` - Right ascension
+/// * `dec` - Declination
 /// # Returns
 /// * `Direction` struct with azimuth and altitude
 pub fn horizontal_from_equatorial(
     location: Location,
     when: DateTime<Utc>,
-    ra: f64,
-    dec: f64,
+    ra: Radians,
+    dec: Radians,
 ) -> Direction {
-    // Assume input in radians
-
     // Get antenna position
     let lon = location.longitude;
     let lat = location.latitude;
 
     // Equatorial to Horizontal conversion from https://aa.usno.navy.mil/faq/alt_az
-    let lha = (gmst(when) - ra).to_radians() * (15.0 * 12.0 / PI) + lon;
-    let alt = (lha.cos() * dec.cos() * lat.cos() + dec.sin() * lat.sin()).asin();
-    let az = (-lha.sin()).atan2(dec.tan() * lat.cos() - lat.sin() * lha.cos());
-
-    // Ensure positive az
-    let az = ((az % FULL_CIRCLE) + FULL_CIRCLE) % FULL_CIRCLE;
+    let lha = gmst(when) - ra + lon;
+    let alt = Radians::asin(lha.cos() * dec.cos() * lat.cos() + dec.sin() * lat.sin());
+    let az = Radians::atan2(-lha.sin(), dec.tan() * lat.cos() - lat.sin() * lha.cos());
 
     Direction {
-        azimuth: az,
+        // Ensure positive az
+        azimuth: az.normalized(),
         altitude: alt,
     }
 }
 
-fn equatorial_from_galactic(l: f64, b: f64) -> (f64, f64) {
-    // Assume input in radians
-
+pub fn equatorial_from_galactic(l: Radians, b: Radians) -> (Radians, Radians) {
     // Calculation from https://physics.stackexchange.com/questions/88663/converting-between-galactic-and-ecliptic-coordinates
-    let ra_ngp: f64 = 192.85948_f64.to_radians(); // R.A. North Galactic Pole
-    let dec_ngp: f64 = 27.12825_f64.to_radians(); // Declination North Galactic Pole
-    let l_ncp: f64 = 122.93192_f64.to_radians(); // Galactic Longitude North Celestial Pole
+    let ra_ngp = Degrees(192.85948).to_radians(); // R.A. North Galactic Pole
+    let dec_ngp = Degrees(27.12825).to_radians(); // Declination North Galactic Pole
+    let l_ncp = Degrees(122.93192).to_radians(); // Galactic Longitude North Celestial Pole
                                                  // Declination is straight forward from link above
-    let dec = (dec_ngp.sin() * b.sin() + dec_ngp.cos() * b.cos() * (l_ncp - l).cos()).asin();
+    let dec = Radians::asin(dec_ngp.sin() * b.sin() + dec_ngp.cos() * b.cos() * (l_ncp - l).cos());
     // To get one equation for R.A., divide the two equations on link above we get
     // tan(ra-ra_ngp) on left side and long thing on right sida. Use atan2 to recover
     // ra-ra_ngp, and then get ra.
-    let ra = (b.cos() * (l_ncp - l).sin())
-        .atan2(dec_ngp.cos() * b.sin() - dec_ngp.sin() * b.cos() * (l_ncp - l).cos())
-        + ra_ngp;
+    let ra = Radians::atan2(
+        b.cos() * (l_ncp - l).sin(),
+        dec_ngp.cos() * b.sin() - dec_ngp.sin() * b.cos() * (l_ncp - l).cos(),
+    ) + ra_ngp;
     (ra, dec)
 }
 
 pub fn horizontal_from_galactic(
     location: Location,
     when: DateTime<Utc>,
-    l: f64,
-    b: f64,
+    l: Radians,
+    b: Radians,
 ) -> Direction {
     // First convert galactic to equatorial, then to horizontal
     let (ra, dec) = equatorial_from_galactic(l, b);
     horizontal_from_equatorial(location, when, ra, dec)
 }
 
-fn ecliptic_from_equatorial(ra: f64, dec: f64) -> (f64, f64) {
+/// Angular distance between two directions on the sphere.
+/// # Arguments
+/// * `lon1`, `lat1` - Longitude and latitude of the first direction
+/// * `lon2`, `lat2` - Longitude and latitude of the second direction
+/// # Returns
+/// * Separation between the directions
+pub fn angular_separation(lon1: Radians, lat1: Radians, lon2: Radians, lat2: Radians) -> Radians {
+    // Haversine formula, well conditioned also for small separations
+    let dlat = lat2 - lat1;
+    let dlon = lon2 - lon1;
+    let a = (dlat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (dlon / 2.0).sin().powi(2);
+    Radians::asin(a.sqrt().min(1.0)) * 2.0
+}
+
+fn ecliptic_from_equatorial(ra: Radians, dec: Radians) -> (Radians, Radians) {
     // From javascript code behind calculations at https://frostydrew.org/utilities.dc/convert/tool-eq_coordinates/
-    let l = (ra.tan() * EC.cos() + dec.tan() * EC.sin() / ra.cos()).atan();
-    let b = (dec.sin() * EC.cos() - dec.cos() * EC.sin() * ra.sin()).asin();
+    // atan2 rather than atan of the ratio, which would put half the sky in
+    // the opposite direction.
+    let l = Radians::atan2(ra.sin() * EC.cos() + dec.tan() * EC.sin(), ra.cos());
+    let b = Radians::asin(dec.sin() * EC.cos() - dec.cos() * EC.sin() * ra.sin());
     (l, b)
 }
 
@@ -161,7 +206,7 @@ fn ecliptic_from_equatorial(ra: f64, dec: f64) -> (f64, f64) {
 //    (l, b)
 //}
 
-fn ecliptic_from_sun(when: DateTime<Utc>) -> (f64, f64) {
+fn ecliptic_from_sun(when: DateTime<Utc>) -> (Radians, Radians) {
     // Algorithm from https://aa.usno.navy.mil/faq/sun_approx
     // for computing the Sun's angular coordinates to an accuracy of about 1 arcminute within two centuries of 2000
     let d = julian_day(when) - 2451545.0;
@@ -174,20 +219,19 @@ fn ecliptic_from_sun(when: DateTime<Utc>) -> (f64, f64) {
     // where all the constants (therefore g, q, and L) are in degrees.
     // It may be necessary or desirable to reduce g, q, and L to the range 0° to 360°.
     //The Sun's ecliptic latitude, b, can be approximated by b=0.
-    let b = 0.0;
-    // return in radians
-    (l.to_radians(), b)
+    let b = Radians(0.0);
+    (Degrees(l).to_radians(), b)
 }
 
-fn equatorial_from_sun(when: DateTime<Utc>) -> (f64, f64) {
+fn equatorial_from_sun(when: DateTime<Utc>) -> (Radians, Radians) {
     // Algorithm from https://aa.usno.navy.mil/faq/sun_approx
     // for computing the Sun's angular coordinates to an accuracy of about 1 arcminute within two centuries of 2000
     let (l, _b) = ecliptic_from_sun(when);
     let d = julian_day(when) - 2451545.0;
     //First compute the mean obliquity of the ecliptic:
-    let e = (23.439 - 0.00000036 * d).to_radians();
-    let ra = (e.cos() * l.sin()).atan2(l.cos());
-    let dec = (e.sin() * l.sin()).asin();
+    let e = Degrees(23.439 - 0.00000036 * d).to_radians();
+    let ra = Radians::atan2(e.cos() * l.sin(), l.cos());
+    let dec = Radians::asin(e.sin() * l.sin());
     (ra, dec)
 }
 
@@ -196,11 +240,13 @@ pub fn horizontal_from_sun(location: Location, when: DateTime<Utc>) -> Direction
     horizontal_from_equatorial(location, when, ra, dec)
 }
 
-pub fn vlsrcorr_from_galactic(l: f64, b: f64, when: DateTime<Utc>) -> f64 {
-    // From http://web.mit.edu/8.13/www/srt_software/vlsr.pdf
-
-    // Target direction in equatorial coordinates
+pub fn vlsrcorr_from_galactic(l: Radians, b: Radians, when: DateTime<Utc>) -> f64 {
     let (ra, dec) = equatorial_from_galactic(l, b);
+    vlsrcorr_from_equatorial(ra, dec, when)
+}
+
+pub fn vlsrcorr_from_equatorial(ra: Radians, dec: Radians, when: DateTime<Utc>) -> f64 {
+    // From http://web.mit.edu/8.13/www/srt_software/vlsr.pdf
 
     // Movement of Sun with respect to LSR: dot product of target & apex vectors, in km/s
     let vsun = 20.0
@@ -221,11 +267,37 @@ pub fn vlsrcorr_from_galactic(l: f64, b: f64, when: DateTime<Utc>) -> f64 {
     1e3 * (vsun + vorb)
 }
 
+/// Velocities relative to the LSR, in km/s, of a line at `rest_frequency`
+/// observed at `freqs` towards galactic `l`, `b`, in the radio convention.
+pub fn lsr_velocity_axis(
+    freqs: &[f64],
+    rest_frequency: f64,
+    l: Radians,
+    b: Radians,
+    when: DateTime<Utc>,
+) -> Vec<f64> {
+    velocity_axis(freqs, rest_frequency, vlsrcorr_from_galactic(l, b, when))
+}
+
+/// Velocities in km/s, in the radio convention, of a line at
+/// `rest_frequency` observed at `freqs`, with `correction` in m/s added to
+/// go from the topocentric frame to the LSR.
+pub fn velocity_axis(freqs: &[f64], rest_frequency: f64, correction: f64) -> Vec<f64> {
+    freqs
+        .iter()
+        .map(|freq| {
+            let radio_velocity = -SPEED_OF_LIGHT * (freq - rest_frequency) / rest_frequency;
+            (radio_velocity + correction) / 1e3
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod test {
     use chrono::Duration;
 
     use super::*;
+    use crate::angles::RIGHT_ANGLE;
 
     macro_rules! assert_similar {
         ($left:expr, $right:expr, $precision: expr) => {
@@ -238,6 +310,19 @@ mod test {
         };
     }
 
+    #[test]
+    fn test_direction_serialization() {
+        let direction = Direction {
+            azimuth: Radians(1.0),
+            altitude: Radians(0.5),
+        };
+        let json = serde_json::to_string(&direction).unwrap();
+        assert_eq!(json, r#"{"azimuth":1.0,"altitude":0.5}"#);
+        let elevation: Direction =
+            serde_json::from_str(r#"{"azimuth":1.0,"elevation":0.5}"#).unwrap();
+        assert_eq!(elevation, direction);
+    }
+
     #[test]
     fn test_julian_day() {
         // Test that we get the correct julian day for a given date
@@ -263,15 +348,15 @@ mod test {
         let jdref = Utc.with_ymd_and_hms(2023, 4, 4, 12, 0, 0).unwrap();
         // Use SALSA Onsala location
         let locref = Location {
-            longitude: 0.20802143022,
-            latitude: 1.00170457462,
+            longitude: Radians(0.20802143022),
+            latitude: Radians(1.00170457462),
         };
         let dir = horizontal_from_sun(locref, jdref);
         // Expected horizontal coordinates in radians
         let expected_az = 3.386904823113701;
         let expected_alt = 0.6557470215389855;
-        assert_similar!(dir.azimuth, expected_az, 1e-6);
-        assert_similar!(dir.altitude, expected_alt, 1e-6);
+        assert_similar!(dir.azimuth.0, expected_az, 1e-6);
+        assert_similar!(dir.altitude.0, expected_alt, 1e-6);
     }
 
     #[test]
@@ -279,26 +364,299 @@ mod test {
         // Test that we get the correct VLSR-correction for
         // a given Galactic coordinate and time
         let jdref = Utc.with_ymd_and_hms(2023, 4, 4, 15, 0, 0).unwrap();
-        let glon = 140.0_f64.to_radians();
-        let vlsrcorr = vlsrcorr_from_galactic(glon, 0.0, jdref);
+        let glon = Degrees(140.0).to_radians();
+        let vlsrcorr = vlsrcorr_from_galactic(glon, Radians(0.0), jdref);
         // Expected VLSR correction in m/s
         let expected_vlsrcorr = -15443.385967834394;
         assert_similar!(vlsrcorr, expected_vlsrcorr, 1e-6);
     }
 
+    #[test]
+    fn test_angular_separation() {
+        let zero = Radians(0.0);
+        let degrees = |d| Degrees(d).to_radians();
+        assert_similar!(angular_separation(zero, zero, zero, zero).0, 0.0, 1e-12);
+        assert_similar!(
+            angular_separation(zero, zero, degrees(90.0), zero).0,
+            degrees(90.0).0,
+            1e-12
+        );
+        // Wrapping around longitude zero
+        assert_similar!(
+            angular_separation(degrees(359.0), zero, degrees(1.0), zero).0,
+            degrees(2.0).0,
+            1e-12
+        );
+        // Meridians converge towards the pole
+        assert_similar!(
+            angular_separation(zero, RIGHT_ANGLE, Radians(PI), RIGHT_ANGLE).0,
+            0.0,
+            1e-12
+        );
+    }
+
     #[test]
     fn test_horizontal_from_sat_eci() {
         //fn horizontal_from_sat_eci(xs: f64, ys: f64, zs: f64, lat: f64, lon: f64, alt: f64, when: DateTime<Utc>) -> (f64, f64) {
         // Test that we get the correct horizontal position for a satellite
         // with given ECI position at given time and location
         let jdref = Utc.with_ymd_and_hms(2023, 4, 5, 14, 0, 0).unwrap();
-        let lon: f64 = 0.20802143022; // Obs lon
-        let lat: f64 = 1.00170457462; // Obs lat
+        let lon = Radians(0.20802143022); // Obs lon
+        let lat = Radians(1.00170457462); // Obs lat
         let alt: f64 = 0.0; // Obs alt in km
         let eci = (-22923.01754858807, 6273.375502631187, 17649.65857168936);
         let expected_hor = (54.385157764497684, 8.823870387817111);
         let hor = horizontal_from_sat_eci(eci.0, eci.1, eci.2, lat, lon, alt, jdref);
-        assert_similar!(hor.0.to_degrees(), expected_hor.0, 1e-6);
-        assert_similar!(hor.1.to_degrees(), expected_hor.1, 1e-6);
+        assert_similar!(hor.azimuth.to_degrees().0, expected_hor.0, 1e-6);
+        assert_similar!(hor.altitude.to_degrees().0, expected_hor.1, 1e-6);
+    }
+
+    // The tests above pin the current output. Those below check it against
+    // published values, with budgets from the documented accuracy of each
+    // algorithm rather than from what it happens to achieve.
+
+    /// SALSA at Onsala, longitude and latitude in degrees.
+    const ONSALA: (f64, f64) = (11.917778, 57.393056);
+
+    fn onsala() -> Location {
+        Location {
+            longitude: Degrees(ONSALA.0).to_radians(),
+            latitude: Degrees(ONSALA.1).to_radians(),
+        }
+    }
+
+    fn utc(y: i32, m: u32, d: u32, h: u32, min: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(y, m, d, h, min, 0).unwrap()
+    }
+
+    /// Difference between two angles in degrees, in -180..180.
+    fn angle_difference(a: f64, b: f64) -> f64 {
+        (a - b + 540.0).rem_euclid(360.0) - 180.0
+    }
+
+    /// Obliquity of the ecliptic in 2024, in degrees.
+    const OBLIQUITY_2024: f64 = 23.436;
+
+    /// Equinoxes and solstices of 2024 from the USNO "Earth's Seasons"
+    /// table, to the minute, with the right ascension and declination of
+    /// the Sun in degrees at those instants.
+    fn seasons() -> [(DateTime<Utc>, f64, f64); 4] {
+        [
+            (utc(2024, 3, 20, 3, 6), 0.0, 0.0),
+            (utc(2024, 6, 20, 20, 51), 90.0, OBLIQUITY_2024),
+            (utc(2024, 9, 22, 12, 44), 180.0, 0.0),
+            (utc(2024, 12, 21, 9, 21), 270.0, -OBLIQUITY_2024),
+        ]
+    }
+
+    #[test]
+    fn test_sun_position_at_seasons() {
+        // The USNO approximation is good to 1 arcminute within two
+        // centuries of 2000.
+        let budget = 1.0 / 60.0;
+        for (when, ra, dec) in seasons() {
+            let (sun_ra, sun_dec) = equatorial_from_sun(when);
+            assert_similar!(angle_difference(sun_ra.to_degrees().0, ra), 0.0, budget);
+            assert_similar!(sun_dec.to_degrees().0, dec, budget);
+        }
+    }
+
+    #[test]
+    fn test_sun_altitude_at_noon() {
+        // The highest altitude of the day is 90 - latitude + declination.
+        // The declination changes by at most 0.4 degrees in a day, less
+        // than 0.01 degrees around the solstices.
+        for (when, _, dec) in seasons() {
+            let budget = if dec == 0.0 { 0.25 } else { 0.05 };
+            let day = when.date_naive().and_hms_opt(0, 0, 0).unwrap().and_utc();
+            let (highest, azimuth) = (0..24 * 60)
+                .map(|minute| horizontal_from_sun(onsala(), day + Duration::minutes(minute)))
+                .map(|d| (d.altitude.to_degrees().0, d.azimuth.to_degrees().0))
+                .fold(
+                    (f64::NEG_INFINITY, 0.0),
+                    |a, b| if b.0 > a.0 { b } else { a },
+                );
+            assert_similar!(highest, 90.0 - ONSALA.1 + dec, budget);
+            // Due south, within the motion of the Sun in a minute.
+            assert_similar!(azimuth, 180.0, 0.5);
+        }
+    }
+
+    #[test]
+    fn test_sidereal_time() {
+        // IAU 1982: GMST at J2000.0, 2000-01-01 12:00 UT1, is 280.46061837
+        // degrees. UT1 and UTC differ by less than 0.9 s, which is 0.004
+        // degrees.
+        let j2000 = gmst_with_dut1(utc(2000, 1, 1, 12, 0), 0.0).to_degrees().0;
+        assert_similar!(angle_difference(j2000, 280.46061837), 0.0, 0.004);
+        // With DUT1 the remaining error is that of the USNO approximation,
+        // 0.1 s of time or 0.0004 degrees. DUT1 was about +0.355 s at the
+        // start of 2000, so 12:00 UTC was 12:00:00.355 UT1, when GMST was
+        // 280.46061837 degrees plus 0.355 s of sidereal time.
+        let j2000 = gmst_with_dut1(utc(2000, 1, 1, 12, 0), 0.355).to_degrees().0;
+        let expected = 280.46061837 + 0.355 * 1.00273791 * 15.0 / 3600.0;
+        assert_similar!(angle_difference(j2000, expected), 0.0, 0.0004);
+        // A sidereal day is 23 h 56 min 4.0905 s.
+        let later = utc(2000, 1, 1, 12, 0) + Duration::milliseconds(86_164_090);
+        assert_similar!(
+            angle_difference(gmst_with_dut1(later, 0.0).to_degrees().0, 280.46061837),
+            0.0,
+            0.004
+        );
+    }
+
+    #[test]
+    fn test_galactic_reference_directions() {
+        // (l, b) and (RA, Dec) in degrees, J2000, of the galactic centre,
+        // the anticentre and the poles of both systems.
+        let directions = [
+            ((0.0, 0.0), (266.40500, -28.93617)),
+            ((180.0, 0.0), (86.40500, 28.93617)),
+            ((0.0, 90.0), (192.85948, 27.12825)),
+            ((122.93192, 27.12825), (0.0, 90.0)),
+        ];
+        for ((l, b), (ra, dec)) in directions {
+            let (converted_ra, converted_dec) =
+                equatorial_from_galactic(Degrees(l).to_radians(), Degrees(b).to_radians());
+            assert_similar!(converted_dec.to_degrees().0, dec, 0.001);
+            if dec.abs() < 89.0 {
+                assert_similar!(
+                    angle_difference(converted_ra.to_degrees().0, ra),
+                    0.0,
+                    0.001
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_horizontal_at_meridian() {
+        // At upper culmination a source is at altitude 90 - |latitude - dec|,
+        // to the south if it passes south of the zenith. At lower
+        // culmination below the north celestial pole it is at
+        // dec + latitude - 90, to the north. This covers the extreme
+        // declinations, from a source that never rises to one next to the
+        // celestial pole.
+        let when = utc(2024, 6, 20, 20, 51);
+        let lst = (gmst(when) + onsala().longitude).to_degrees().0;
+        let declinations = [-89.9, -40.0, -30.0, 0.0, 30.0, 80.0, 89.9];
+        for dec in declinations {
+            let upper = horizontal_from_equatorial(
+                onsala(),
+                when,
+                Degrees(lst).to_radians(),
+                Degrees(dec).to_radians(),
+            );
+            assert_similar!(
+                upper.altitude.to_degrees().0,
+                90.0 - (ONSALA.1 - dec).abs(),
+                1e-6
+            );
+            let expected_azimuth = if dec < ONSALA.1 { 180.0 } else { 0.0 };
+            assert_similar!(
+                angle_difference(upper.azimuth.to_degrees().0, expected_azimuth),
+                0.0,
+                1e-6
+            );
+
+            // Sources south of -latitude pass below the south celestial
+            // pole instead.
+            if dec + ONSALA.1 < 0.0 {
+                continue;
+            }
+            let lower = horizontal_from_equatorial(
+                onsala(),
+                when,
+                Degrees(lst + 180.0).to_radians(),
+                Degrees(dec).to_radians(),
+            );
+            assert_similar!(lower.altitude.to_degrees().0, dec + ONSALA.1 - 90.0, 1e-6);
+            assert_similar!(
+                angle_difference(lower.azimuth.to_degrees().0, 0.0),
+                0.0,
+                1e-6
+            );
+        }
+
+        // A source on the celestial equator sets due west, six sidereal
+        // hours after it culminates.
+        let setting = horizontal_from_equatorial(
+            onsala(),
+            when,
+            Degrees(lst - 90.0).to_radians(),
+            Radians(0.0),
+        );
+        assert_similar!(setting.altitude.to_degrees().0, 0.0, 1e-6);
+        assert_similar!(setting.azimuth.to_degrees().0, 270.0, 1e-6);
+    }
+
+    #[test]
+    fn test_vlsr_correction_through_the_year() {
+        // The correction models the solar motion as 20 km/s towards RA 18 h,
+        // Dec 30 degrees and the Earth on a circular orbit at 30 km/s, in the
+        // ecliptic of J2000. The real orbital speed varies between 29.3 and
+        // 30.3 km/s and the ecliptic precesses by 0.34 degrees from 2000 to
+        // 2024, so the model is good to 1 km/s. The rotation of the Earth
+        // adds up to 0.25 km/s at Onsala, which the model leaves out, as do
+        // the reference values.
+        let budget = 1e3;
+
+        // Corrections in km/s at the 2024 equinoxes and solstices towards
+        // galactic (l, b) in degrees. Computed from the longitude and
+        // distance of the Sun given by the low precision formulae of the
+        // Astronomical Almanac, which include the eccentricity of the orbit,
+        // with the directions precessed to the equinox of date and the same
+        // standard solar motion.
+        let references = [
+            ((0.0, 0.0), [40.04, 8.97, -19.19, 11.88]),
+            ((90.0, 0.0), [18.17, 29.69, 11.73, 0.21]),
+            ((180.0, 0.0), [-40.04, -8.97, 19.19, -11.88]),
+            ((270.0, 0.0), [-18.17, -29.69, -11.73, -0.21]),
+            ((30.0, 60.0), [29.31, -3.79, 1.77, 34.87]),
+            ((300.0, -60.0), [-5.28, 4.49, -17.02, -26.79]),
+        ];
+        for ((l, b), expected) in references {
+            for ((when, _, _), expected) in seasons().into_iter().zip(expected) {
+                let correction =
+                    vlsrcorr_from_galactic(Degrees(l).to_radians(), Degrees(b).to_radians(), when);
+                assert_similar!(correction, expected * 1e3, budget);
+            }
+        }
+
+        // Towards the north ecliptic pole, at l = 96.384, b = 29.811 and
+        // Dec 66.561, the orbital motion does not contribute and only the
+        // solar motion remains, 20 cos(66.561 - 30) km/s.
+        let days = |year| (0..365).map(move |day| utc(year, 1, 1, 0, 0) + Duration::days(day));
+        let expected = 20e3 * Degrees(66.561 - 30.0).to_radians().cos();
+        for when in days(2024) {
+            let correction = vlsrcorr_from_galactic(
+                Degrees(96.384).to_radians(),
+                Degrees(29.811).to_radians(),
+                when,
+            );
+            assert_similar!(correction, expected, 10.0);
+        }
+
+        // Towards the galactic centre, 5.6 degrees south of the ecliptic,
+        // the orbital motion sweeps 30 cos(5.6) km/s each way over a year.
+        let corrections: Vec<f64> = days(2024)
+            .map(|when| vlsrcorr_from_galactic(Radians(0.0), Radians(0.0), when))
+            .collect();
+        let highest = corrections
+            .iter()
+            .copied()
+            .fold(f64::NEG_INFINITY, f64::max);
+        let lowest = corrections.iter().copied().fold(f64::INFINITY, f64::min);
+        assert_similar!(
+            highest - lowest,
+            2.0 * 30e3 * Degrees(5.6).to_radians().cos(),
+            100.0
+        );
+        // The Earth moves towards the galactic centre, at ecliptic longitude
+        // 266.8, when the Sun is at 356.8, three days before the March
+        // equinox on day 79. The line then appears blueshifted the most, so
+        // the correction is the highest.
+        let day_of_highest = corrections.iter().position(|&c| c == highest).unwrap();
+        assert!((75..=77).contains(&day_of_highest), "{}", day_of_highest);
     }
 }
@@ -25,6 +25,10 @@ pub enum DataBaseError {
 pub trait Storage: Sized + Clone + Send + Sync {
     async fn read(&self) -> Result<Option<Vec<u8>>, DataBaseError>;
     async fn write(&mut self, data: &[u8]) -> Result<(), DataBaseError>;
+    /// Make sure that everything written is stored, e.g. before exiting.
+    async fn flush(&self) -> Result<(), DataBaseError> {
+        Ok(())
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -66,6 +70,11 @@ impl Storage for FileStorage {
         file.write_all(data).await?;
         Ok(())
     }
+
+    async fn flush(&self) -> Result<(), DataBaseError> {
+        fs::File::open(&self.file_path).await?.sync_all().await?;
+        Ok(())
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -102,13 +111,168 @@ pub async fn create_database_from_directory(
     })
 }
 
-use crate::bookings::Booking;
+use crate::analysis::Notebook;
+use crate::archive::checkpoints::MeasurementCheckpoint;
+use crate::archive::files::{FileChecksum, FileStorageDefinition};
+use crate::archive::ArchivedMeasurement;
+use crate::assignments::Assignment;
+use crate::authentication::ApiToken;
+use crate::bookings::calendar::CalendarToken;
+use crate::bookings::{Booking, BookingChange, BookingPolicy};
+use crate::calibration::Calibration;
+use crate::citations::Citation;
+use crate::clock::{ClockCheck, ClockCheckDefinition};
+use crate::confirmation::{AuditEntry, ConfirmationSecret, ConfirmationToken};
+use crate::console::ConsoleEntry;
+use crate::constants::SpectralLine;
+use crate::faults::FaultReport;
+use crate::hooks::PostObservationHook;
+use crate::idempotency::IdempotencyRecord;
+use crate::interlock::CollisionZone;
+use crate::observation_log::ObservationLogEntry;
+use crate::orbit::SatelliteDefinition;
+use crate::park_policies::ParkOverride;
+use crate::pointing::PointingObservation;
+use crate::rfi::RfiScan;
+use crate::scheduler::jobs::ScheduledObservation;
+use crate::sessions::RecordedSession;
+use crate::shift_log::ShiftLogNote;
+use crate::telemetry::TelemetryDefinition;
 use crate::telescopes::TelescopeDefinition;
+use crate::trash::TrashedItem;
+use crate::ups::{PowerEvent, UpsDefinition};
+use crate::users::User;
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
 pub struct DataModel {
     pub bookings: Vec<Booking>,
+    /// Audit trail of bookings rescheduled or cancelled after they were made.
+    #[serde(default)]
+    pub booking_changes: Vec<BookingChange>,
+    /// Limits on the bookings users may make.
+    #[serde(default)]
+    pub booking_policy: BookingPolicy,
+    /// Tokens for reading the calendar feed of a user's bookings.
+    #[serde(default)]
+    pub calendar_tokens: Vec<CalendarToken>,
     pub telescopes: Vec<TelescopeDefinition>,
+    #[serde(default)]
+    pub measurements: Vec<ArchivedMeasurement>,
+    #[serde(default)]
+    pub rfi_scans: Vec<RfiScan>,
+    #[serde(default)]
+    pub post_observation_hooks: Vec<PostObservationHook>,
+    #[serde(default)]
+    pub sessions: Vec<RecordedSession>,
+    #[serde(default)]
+    pub users: Vec<User>,
+    #[serde(default)]
+    pub spectral_lines: Vec<SpectralLine>,
+    #[serde(default)]
+    pub measurement_checkpoints: Vec<MeasurementCheckpoint>,
+    #[serde(default)]
+    pub trash: Vec<TrashedItem>,
+    #[serde(default)]
+    pub assignments: Vec<Assignment>,
+    #[serde(default)]
+    pub idempotency_keys: Vec<IdempotencyRecord>,
+    #[serde(default)]
+    pub shift_log: Vec<ShiftLogNote>,
+    #[serde(default)]
+    pub fault_reports: Vec<FaultReport>,
+    #[serde(default)]
+    pub park_overrides: Vec<ParkOverride>,
+    #[serde(default)]
+    pub ups: Option<UpsDefinition>,
+    #[serde(default)]
+    pub power_events: Vec<PowerEvent>,
+    #[serde(default)]
+    pub collision_zones: Vec<CollisionZone>,
+    #[serde(default)]
+    pub pointing_observations: Vec<PointingObservation>,
+    #[serde(default)]
+    pub console_log: Vec<ConsoleEntry>,
+    /// Hashes of the tokens users authenticate with.
+    #[serde(default)]
+    pub api_tokens: Vec<ApiToken>,
+    /// Hashes of the secrets admins confirm destructive actions with.
+    #[serde(default)]
+    pub confirmation_secrets: Vec<ConfirmationSecret>,
+    #[serde(default)]
+    pub confirmation_tokens: Vec<ConfirmationToken>,
+    #[serde(default)]
+    pub audit_log: Vec<AuditEntry>,
+    /// Commands sent to the telescopes and who sent them.
+    #[serde(default)]
+    pub observation_log: Vec<ObservationLogEntry>,
+    /// Permalinks of archived measurements, which are never removed.
+    #[serde(default)]
+    pub citations: Vec<Citation>,
+    #[serde(default)]
+    pub file_storage: Option<FileStorageDefinition>,
+    #[serde(default)]
+    pub file_checksums: Vec<FileChecksum>,
+    /// UT1 - UTC in seconds from IERS Bulletin A, applied to sidereal time.
+    #[serde(default)]
+    pub dut1: f64,
+    /// Saved analysis notebooks, shared by their id.
+    #[serde(default)]
+    pub notebooks: Vec<Notebook>,
+    /// Record the decisions of the trackers, for debugging pointing.
+    #[serde(default)]
+    pub tracker_telemetry: Option<TelemetryDefinition>,
+    /// Observations to run at a booked time without the user present.
+    #[serde(default)]
+    pub scheduled_observations: Vec<ScheduledObservation>,
+    /// Compare the system clock with an NTP server.
+    #[serde(default)]
+    pub clock_check: Option<ClockCheckDefinition>,
+    #[serde(default)]
+    pub clock_checks: Vec<ClockCheck>,
+    /// Satellites that can be targeted by catalog number.
+    #[serde(default)]
+    pub satellites: Vec<SatelliteDefinition>,
+    /// Hot and cold load calibrations of the receivers.
+    #[serde(default)]
+    pub calibrations: Vec<Calibration>,
+    /// Format of the stored data, see [`migrate`].
+    #[serde(default)]
+    pub schema_version: u32,
+}
+
+/// Format written by this version of the backend.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Bring stored data up to [`SCHEMA_VERSION`].
+///
+/// New fields with a default need no migration. A step is only needed when
+/// the meaning of stored data changes, and it is run on the raw JSON so that
+/// the old fields are still there to read.
+fn migrate(mut value: serde_json::Value) -> serde_json::Value {
+    let version = value
+        .get("schema_version")
+        .and_then(|version| version.as_u64())
+        .unwrap_or(0);
+    if version < 1 {
+        // The admin flag of users was replaced by a role.
+        if let Some(users) = value.get_mut("users").and_then(|u| u.as_array_mut()) {
+            for user in users.iter_mut().filter_map(|u| u.as_object_mut()) {
+                if user.remove("admin").and_then(|admin| admin.as_bool()) == Some(true) {
+                    user.insert("role".to_string(), "Admin".into());
+                }
+            }
+        }
+    }
+    if let Some(object) = value.as_object_mut() {
+        object.insert("schema_version".to_string(), SCHEMA_VERSION.into());
+    }
+    value
+}
+
+fn decode(data: &[u8]) -> Result<DataModel, DataBaseError> {
+    Ok(serde_json::from_value(migrate(serde_json::from_slice(
+        data,
+    )?))?)
 }
 
 impl<StorageType> DataBase<StorageType>
@@ -131,7 +295,7 @@ where
     pub async fn get_data(&self) -> Result<DataModel, DataBaseError> {
         let storage = self.storage.read().await;
         match storage.read().await? {
-            Some(data) => Ok(serde_json::from_slice(&data)?),
+            Some(data) => decode(&data),
             None => Ok(DataModel::default()),
         }
     }
@@ -160,7 +324,7 @@ where
         let mut storage_handle = self.storage.write().await;
 
         let value = match storage_handle.read().await? {
-            Some(data) => serde_json::from_slice(&data)?,
+            Some(data) => decode(&data)?,
             None => DataModel::default(),
         };
 
@@ -170,6 +334,12 @@ where
 
         Ok(())
     }
+
+    /// Wait for any update in progress and make sure that the data is
+    /// stored, e.g. before exiting.
+    pub async fn flush(&self) -> Result<(), DataBaseError> {
+        self.storage.write().await.flush().await
+    }
 }
 
 #[cfg(test)]
@@ -177,6 +347,7 @@ mod test {
     use chrono::{Duration, Utc};
 
     use super::*;
+    use crate::users::Role;
 
     #[tokio::test]
     async fn given_no_previous_write_then_get_data_returns_default() {
@@ -234,4 +405,24 @@ mod test {
         let data = db.get_data().await.expect("should be able to get db data");
         assert_eq!(data.bookings, vec![booking1, booking2]);
     }
+
+    #[tokio::test]
+    async fn test_migrate_admin_flag_to_role() {
+        let db = create_in_memory_database();
+        db.storage
+            .write()
+            .await
+            .write(
+                br#"{"bookings": [], "telescopes": [], "users": [
+                    {"name": "anna", "created": "2023-01-01T00:00:00Z", "admin": true},
+                    {"name": "student", "created": "2023-01-01T00:00:00Z", "admin": false}
+                ]}"#,
+            )
+            .await
+            .unwrap();
+        let data = db.get_data().await.expect("should be able to get db data");
+        assert_eq!(data.schema_version, SCHEMA_VERSION);
+        assert_eq!(data.users[0].role, Role::Admin);
+        assert_eq!(data.users[1].role, Role::Observer);
+    }
 }
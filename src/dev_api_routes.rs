@@ -0,0 +1,183 @@
+//! Control over simulated telescopes, only served when the backend is
+//! started with `--dev`.
+//!
+//! This lets the frontend and UI tests put the fake telescope in any state,
+//! e.g. an error or strong wind, without changing the backend.
+
+use crate::fake_telescope::SimulatedConditions;
+use crate::telescope::TelescopeCollection;
+use crate::telescope_api_routes::{extract_telescope, TelescopeNotFound};
+use axum::{
+    extract::{Json, Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Router,
+};
+use serde::{Deserialize, Serialize};
+
+pub fn routes(telescopes: TelescopeCollection) -> Router {
+    Router::new()
+        .route(
+            "/telescopes/:telescope_id/conditions",
+            get(get_conditions).put(set_conditions),
+        )
+        .route("/telescopes/:telescope_id/jump_time", post(jump_time))
+        .with_state(telescopes)
+}
+
+#[derive(Debug)]
+enum DevError {
+    TelescopeNotFound,
+    NotSimulated,
+}
+
+impl From<TelescopeNotFound> for DevError {
+    fn from(_: TelescopeNotFound) -> Self {
+        DevError::TelescopeNotFound
+    }
+}
+
+impl IntoResponse for DevError {
+    fn into_response(self) -> Response {
+        match self {
+            DevError::TelescopeNotFound => TelescopeNotFound.into_response(),
+            DevError::NotSimulated => (
+                StatusCode::BAD_REQUEST,
+                "Telescope is not simulated".to_string(),
+            )
+                .into_response(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct TimeJump {
+    seconds: i64,
+}
+
+async fn update_conditions(
+    telescopes: TelescopeCollection,
+    telescope_id: String,
+    update: impl FnOnce(&mut SimulatedConditions),
+) -> Result<Json<SimulatedConditions>, DevError> {
+    let mut telescope = extract_telescope(telescopes, telescope_id).await?;
+    let conditions = telescope
+        .simulated_conditions()
+        .ok_or(DevError::NotSimulated)?;
+    update(conditions);
+    Ok(Json(conditions.clone()))
+}
+
+async fn get_conditions(
+    State(telescopes): State<TelescopeCollection>,
+    Path(telescope_id): Path<String>,
+) -> Result<Json<SimulatedConditions>, DevError> {
+    update_conditions(telescopes, telescope_id, |_| {}).await
+}
+
+async fn set_conditions(
+    State(telescopes): State<TelescopeCollection>,
+    Path(telescope_id): Path<String>,
+    Json(new_conditions): Json<SimulatedConditions>,
+) -> Result<Json<SimulatedConditions>, DevError> {
+    log::info!(
+        "Setting simulated conditions of {} to {:?}",
+        telescope_id,
+        new_conditions
+    );
+    update_conditions(telescopes, telescope_id, |conditions| {
+        *conditions = new_conditions
+    })
+    .await
+}
+
+async fn jump_time(
+    State(telescopes): State<TelescopeCollection>,
+    Path(telescope_id): Path<String>,
+    Json(jump): Json<TimeJump>,
+) -> Result<Json<SimulatedConditions>, DevError> {
+    update_conditions(telescopes, telescope_id, |conditions| {
+        conditions.time_offset += jump.seconds
+    })
+    .await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::interlock::CollisionInterlock;
+    use crate::supervisor::TelescopeSupervisor;
+    use crate::telescope::{Telescope, TelescopeContainer};
+    use crate::telescopes::TelescopeError;
+    use crate::test_utils::send;
+    use axum::http;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use tokio::sync::{Mutex, RwLock};
+
+    #[tokio::test]
+    async fn test_set_conditions() {
+        let supervisor = TelescopeSupervisor::new("fake");
+        let telescope = Arc::new(Mutex::new(crate::fake_telescope::create(
+            "fake".to_string(),
+            None,
+            vec![],
+            supervisor.clone(),
+            CollisionInterlock::default(),
+        )));
+        let telescopes: TelescopeCollection = Arc::new(RwLock::new(HashMap::from([(
+            "fake".to_string(),
+            TelescopeContainer {
+                telescope: telescope.clone(),
+                supervisor,
+                updates: Default::default(),
+            },
+        )])));
+
+        let conditions = SimulatedConditions {
+            forced_error: Some(TelescopeError::TelescopeNotConnected),
+            wind_speed: 20.0,
+            ..Default::default()
+        };
+        let response = send(
+            routes(telescopes.clone()),
+            http::Method::PUT,
+            "/telescopes/fake/conditions",
+            serde_json::to_string(&conditions).unwrap(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = send(
+            routes(telescopes.clone()),
+            http::Method::POST,
+            "/telescopes/fake/jump_time",
+            r#"{"seconds": 3600}"#.to_string(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let telescope = telescope.lock().await;
+        assert_eq!(
+            telescope.conditions,
+            SimulatedConditions {
+                time_offset: 3600,
+                ..conditions
+            }
+        );
+        let info = telescope.get_info().await.unwrap();
+        assert_eq!(
+            info.most_recent_error,
+            Some(TelescopeError::TelescopeNotConnected)
+        );
+
+        let response = send(
+            routes(telescopes),
+            http::Method::GET,
+            "/telescopes/salsa/conditions",
+            String::new(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
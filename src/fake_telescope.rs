@@ -1,29 +1,134 @@
-use crate::coords::{horizontal_from_equatorial, horizontal_from_galactic};
+use crate::angles::{Degrees, Radians, RIGHT_ANGLE};
+use crate::auxiliary::{AuxiliaryDeviceDefinition, AuxiliaryDeviceState, AuxiliaryDevices};
+use crate::constants::HI_REST_FREQUENCY;
+use crate::coords::{
+    angular_separation, horizontal_from_equatorial, horizontal_from_galactic, lsr_velocity_axis,
+};
 use crate::coords::{Direction, Location};
+use crate::interlock::{CollisionInterlock, Sweep};
+use crate::pointing::PointingCorrection;
+use crate::rfi::{RfiScan, RfiScanDefinition};
+use crate::supervisor::TelescopeSupervisor;
 use crate::telescope::Telescope;
+use crate::telescope_tracker::{check_target_direction, preview_target};
 use crate::telescopes::{
-    ObservedSpectra, ReceiverConfiguration, ReceiverError, TelescopeError, TelescopeInfo,
-    TelescopeStatus, TelescopeTarget,
+    ContinuumSample, FrequencyRange, Measurement, ObservationMode, ObservedSpectra,
+    ReceiverConfiguration, ReceiverError, ReceiverTransition, TargetPreview, TelescopeCapabilities,
+    TelescopeError, TelescopeInfo, TelescopeStatus, TelescopeTarget, WindowFunction,
 };
 use async_trait::async_trait;
 use chrono::{DateTime, Utc};
 use rand::Rng;
 use rand_distr::StandardNormal;
+use serde::{Deserialize, Serialize};
 use std::f64::consts::PI;
 use std::time::Duration;
 
 const FAKE_TELESCOPE_PARKING_HORIZONTAL: Direction = Direction {
-    azimuth: 0.0,
-    altitude: PI / 2.0,
+    azimuth: Radians(0.0),
+    altitude: RIGHT_ANGLE,
 };
-pub const LOWEST_ALLOWED_ALTITUDE: f64 = 5.0 / 180. * PI;
+pub const LOWEST_ALLOWED_ALTITUDE: Radians = Radians(5.0 / 180. * PI);
 
-pub const FAKE_TELESCOPE_SLEWING_SPEED: f64 = PI / 10.0;
+// Per second
+pub const FAKE_TELESCOPE_SLEWING_SPEED: Radians = Radians(PI / 10.0);
 pub const FAKE_TELESCOPE_CHANNELS: usize = 400;
 pub const FAKE_TELESCOPE_CHANNEL_WIDTH: f64 = 2e6f64 / FAKE_TELESCOPE_CHANNELS as f64;
 pub const FAKE_TELESCOPE_FIRST_CHANNEL: f64 =
-    1.420e9f64 - FAKE_TELESCOPE_CHANNEL_WIDTH * FAKE_TELESCOPE_CHANNELS as f64 / 2f64;
+    HI_REST_FREQUENCY - FAKE_TELESCOPE_CHANNEL_WIDTH * FAKE_TELESCOPE_CHANNELS as f64 / 2f64;
 pub const FAKE_TELESCOPE_NOISE: f64 = 2f64;
+// Level of the spectra without any lines or interference, in K.
+pub const FAKE_TELESCOPE_SYSTEM_TEMPERATURE: f64 = 5f64;
+// Full width at half maximum of the beam.
+pub const FAKE_TELESCOPE_BEAM_WIDTH: Degrees = Degrees(7.0);
+// Frequencies of the fake interference seen in RFI scans
+pub const FAKE_TELESCOPE_RFI_CARRIERS: [f64; 2] = [1.4105e9, 1.4275e9];
+// The telescope is not moved in stronger wind than this, in m/s.
+pub const FAKE_TELESCOPE_MAX_WIND_SPEED: f64 = 15.0;
+
+/// Conditions the fake telescope is observing in, controlled through the
+/// dev API to exercise states that are hard to reach otherwise.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Default)]
+pub struct SimulatedConditions {
+    /// Reported as the most recent error while set.
+    #[serde(default)]
+    pub forced_error: Option<TelescopeError>,
+    /// Wind speed in m/s. Slewing slows down in wind and stops completely
+    /// above `FAKE_TELESCOPE_MAX_WIND_SPEED`.
+    #[serde(default)]
+    pub wind_speed: f64,
+    /// Frequencies of interference, in Hz, in addition to the usual
+    /// carriers. Shows up both in spectra and in RFI scans.
+    #[serde(default)]
+    pub rfi_carriers: Vec<f64>,
+    /// Seconds added to the clock of the telescope.
+    #[serde(default)]
+    pub time_offset: i64,
+    /// Replaces the usual fake spectra with radiometer noise and this
+    /// line, to check what the analysis recovers from an observation.
+    #[serde(default)]
+    pub hi_source: Option<SyntheticHiSource>,
+}
+
+/// HI emission with a Gaussian profile, strongest in the galactic plane.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct SyntheticHiSource {
+    /// Peak brightness temperature in K at galactic latitude 0.
+    pub peak_temperature: f64,
+    /// Standard deviation in degrees of the Gaussian the emission falls
+    /// off with away from the galactic plane.
+    pub latitude_width: f64,
+    /// Velocity of the line relative to the LSR in km/s.
+    pub velocity: f64,
+    /// Full width at half maximum of the line in km/s.
+    pub width: f64,
+}
+
+impl SyntheticHiSource {
+    /// Peak brightness temperature towards galactic latitude `b`.
+    pub fn peak_temperature_at(&self, b: Radians) -> f64 {
+        let offset = b.to_degrees().0 / self.latitude_width;
+        self.peak_temperature * (-0.5 * offset.powi(2)).exp()
+    }
+
+    /// Spectrum measured during `integration_time` when tracking galactic
+    /// `l`, `b` with the beam `pointing_offset` away from it. Noise follows
+    /// the radiometer equation for the width of the channels.
+    fn observe(
+        &self,
+        l: Radians,
+        b: Radians,
+        pointing_offset: Radians,
+        when: DateTime<Utc>,
+        integration_time: Duration,
+    ) -> ObservedSpectra {
+        let mut rng = rand::thread_rng();
+        let frequencies = fake_frequencies();
+        let velocities = lsr_velocity_axis(&frequencies, HI_REST_FREQUENCY, l, b, when);
+        let beam_response = (-4.0
+            * 2f64.ln()
+            * (pointing_offset.0 / FAKE_TELESCOPE_BEAM_WIDTH.to_radians().0).powi(2))
+        .exp();
+        let peak = beam_response * self.peak_temperature_at(b);
+        let sigma = self.width / (2.0 * (2.0 * 2f64.ln()).sqrt());
+        let samples = FAKE_TELESCOPE_CHANNEL_WIDTH * integration_time.as_secs_f64();
+        let spectra = velocities
+            .iter()
+            .map(|velocity| {
+                let line = peak * (-0.5 * ((velocity - self.velocity) / sigma).powi(2)).exp();
+                let temperature = FAKE_TELESCOPE_SYSTEM_TEMPERATURE + line;
+                let noise = temperature / samples.max(1.0).sqrt();
+                temperature + noise * rng.sample::<f64, StandardNormal>(StandardNormal)
+            })
+            .collect();
+        ObservedSpectra {
+            frequencies,
+            spectra,
+            observation_time: integration_time,
+            cycles: 1,
+        }
+    }
+}
 
 pub struct FakeTelescope {
     pub target: TelescopeTarget,
@@ -32,21 +137,149 @@ pub struct FakeTelescope {
     pub most_recent_error: Option<TelescopeError>,
     pub receiver_configuration: ReceiverConfiguration,
     pub current_spectra: Vec<ObservedSpectra>,
+    pub integration_start: Option<DateTime<Utc>>,
+    /// Largest tracking error during the current integration.
+    pub max_tracking_error: Option<Radians>,
+    pub completed_measurements: Vec<Measurement>,
+    pub rfi_scan: Option<RfiScanDefinition>,
+    pub last_rfi_scan: Option<DateTime<Utc>>,
+    pub completed_rfi_scans: Vec<RfiScan>,
+    pub auxiliary_devices: AuxiliaryDevices,
     pub name: String,
+    // The fake telescope has no tasks of its own, but reports the ones run
+    // on its behalf.
+    pub supervisor: TelescopeSupervisor,
+    pub conditions: SimulatedConditions,
+    pub interlock: CollisionInterlock,
+    pub pointing: PointingCorrection,
 }
 
-pub fn create(name: String) -> FakeTelescope {
+pub fn create(
+    name: String,
+    rfi_scan: Option<RfiScanDefinition>,
+    auxiliary_devices: Vec<AuxiliaryDeviceDefinition>,
+    supervisor: TelescopeSupervisor,
+    interlock: CollisionInterlock,
+) -> FakeTelescope {
+    // There is nothing to switch for the fake telescope, devices just keep
+    // track of their state.
+    let mut auxiliary_devices = AuxiliaryDevices::new(auxiliary_devices);
+    auxiliary_devices.apply_defaults(|_, _| Ok(()));
     FakeTelescope {
         target: TelescopeTarget::Parked,
         horizontal: FAKE_TELESCOPE_PARKING_HORIZONTAL,
         location: Location {
-            longitude: 0.20802143022, //(11.0+55.0/60.0+7.5/3600.0) * PI / 180.0. Sign positive, handled in gmst calc
-            latitude: 1.00170457462,  //(57.0+23.0/60.0+36.4/3600.0) * PI / 180.0
+            longitude: Radians(0.20802143022), //(11.0+55.0/60.0+7.5/3600.0) * PI / 180.0. Sign positive, handled in gmst calc
+            latitude: Radians(1.00170457462),  //(57.0+23.0/60.0+36.4/3600.0) * PI / 180.0
         },
         most_recent_error: None,
-        receiver_configuration: ReceiverConfiguration { integrate: false },
+        receiver_configuration: ReceiverConfiguration::stopped(),
         current_spectra: vec![],
+        integration_start: None,
+        max_tracking_error: None,
+        completed_measurements: vec![],
+        rfi_scan,
+        last_rfi_scan: None,
+        completed_rfi_scans: vec![],
+        auxiliary_devices,
         name,
+        supervisor,
+        conditions: SimulatedConditions::default(),
+        interlock,
+        pointing: PointingCorrection::default(),
+    }
+}
+
+impl FakeTelescope {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now() + chrono::Duration::seconds(self.conditions.time_offset)
+    }
+
+    fn rfi_carriers(&self) -> Vec<f64> {
+        FAKE_TELESCOPE_RFI_CARRIERS
+            .iter()
+            .chain(self.conditions.rfi_carriers.iter())
+            .copied()
+            .collect()
+    }
+
+    fn average_spectra(&self) -> Option<ObservedSpectra> {
+        if self.current_spectra.is_empty() {
+            return None;
+        }
+        let mut latest_observation = ObservedSpectra {
+            frequencies: vec![0f64; FAKE_TELESCOPE_CHANNELS],
+            spectra: vec![0f64; FAKE_TELESCOPE_CHANNELS],
+            observation_time: Duration::from_secs(0),
+            cycles: 0,
+        };
+        for integration in &self.current_spectra {
+            latest_observation.spectra = latest_observation
+                .spectra
+                .into_iter()
+                .zip(integration.spectra.iter())
+                .map(|(a, b)| a + b)
+                .collect();
+            latest_observation.observation_time += integration.observation_time;
+            latest_observation.cycles += integration.cycles;
+        }
+        latest_observation.frequencies = self.current_spectra[0].frequencies.clone();
+        latest_observation.spectra = latest_observation
+            .spectra
+            .into_iter()
+            .map(|value| value / self.current_spectra.len() as f64)
+            .collect();
+        Some(latest_observation)
+    }
+
+    /// One sample per update, the mean of the spectrum observed in it.
+    fn continuum(&self, start: DateTime<Utc>) -> Vec<ContinuumSample> {
+        let mut time = start;
+        self.current_spectra
+            .iter()
+            .map(|integration| {
+                let sample = ContinuumSample {
+                    time,
+                    power: integration.spectra.iter().sum::<f64>()
+                        / integration.spectra.len().max(1) as f64,
+                };
+                time += chrono::Duration::from_std(integration.observation_time)
+                    .unwrap_or_else(|_| chrono::Duration::zero());
+                sample
+            })
+            .collect()
+    }
+
+    fn current_measurement(&self) -> Option<Measurement> {
+        let start = self.integration_start?;
+        let spectra = self.average_spectra()?;
+        Some(Measurement {
+            amps: spectra.spectra,
+            freqs: spectra.frequencies,
+            target: self.target,
+            start,
+            duration: spectra.observation_time,
+            window: self.receiver_configuration.window,
+            integration_time: spectra.observation_time,
+            requested_integration_time: spectra.observation_time,
+            dropped_samples: 0,
+            system_temperature: None,
+            interrupted: false,
+            receiver_configuration: Some(self.receiver_configuration),
+            continuum: self.continuum(start),
+            max_tracking_error: self.max_tracking_error,
+            cycles: spectra.cycles,
+            polarizations: vec![],
+            flagged: vec![],
+        })
+    }
+
+    fn stop_integration(&mut self) {
+        self.receiver_configuration.integrate = false;
+        if let Some(measurement) = self.current_measurement() {
+            self.completed_measurements.push(measurement);
+        }
+        self.integration_start = None;
     }
 }
 
@@ -64,56 +297,168 @@ impl Telescope for FakeTelescope {
         &mut self,
         target: TelescopeTarget,
     ) -> Result<TelescopeTarget, TelescopeError> {
+        if let Some(error) = &self.conditions.forced_error {
+            return Err(error.clone());
+        }
         self.most_recent_error = None;
-        self.receiver_configuration.integrate = false;
+        self.stop_integration();
         self.current_spectra.clear();
 
-        let target_horizontal =
-            calculate_target_horizontal(self.location, Utc::now(), target, self.horizontal);
-        if target_horizontal.altitude < LOWEST_ALLOWED_ALTITUDE {
-            log::info!(
-                "Refusing to set target for telescope {} to {:?}. Target is below horizon",
-                &self.name,
-                &target
-            );
-            self.target = TelescopeTarget::Stopped;
-            Err(TelescopeError::TargetBelowHorizon)
-        } else {
-            log::info!(
-                "Setting target for telescope {} to {:?}",
-                &self.name,
-                &target
-            );
-            self.target = target;
-            Ok(target)
+        let target_horizontal = calculate_target_horizontal(
+            self.location,
+            self.now(),
+            target,
+            self.horizontal,
+            &self.pointing,
+        );
+        match check_target_direction(
+            &self.name,
+            self.horizontal,
+            target_horizontal,
+            &self.interlock,
+        ) {
+            Ok(()) => {
+                log::info!(
+                    "Setting target for telescope {} to {:?}",
+                    &self.name,
+                    &target
+                );
+                self.target = target;
+                Ok(target)
+            }
+            Err(TelescopeError::TargetBelowHorizon) => {
+                log::info!(
+                    "Refusing to set target for telescope {} to {:?}. Target is below horizon",
+                    &self.name,
+                    &target
+                );
+                self.target = TelescopeTarget::Stopped;
+                Err(TelescopeError::TargetBelowHorizon)
+            }
+            Err(error) => {
+                log::info!(
+                    "Refusing to set target for telescope {} to {:?}: {}",
+                    &self.name,
+                    &target,
+                    error
+                );
+                Err(error)
+            }
         }
     }
 
+    async fn preview_target(&self, target: TelescopeTarget) -> TargetPreview {
+        let commanded = calculate_target_horizontal(
+            self.location,
+            self.now(),
+            target,
+            self.horizontal,
+            &self.pointing,
+        );
+        let mut preview = preview_target(
+            &self.name,
+            target,
+            Some(commanded),
+            Some(self.horizontal),
+            FAKE_TELESCOPE_SLEWING_SPEED,
+            &self.interlock,
+        );
+        if let Some(error) = &self.conditions.forced_error {
+            preview.error = Some(error.clone());
+        }
+        preview
+    }
+
     async fn set_receiver_configuration(
         &mut self,
         receiver_configuration: ReceiverConfiguration,
     ) -> Result<ReceiverConfiguration, ReceiverError> {
-        if receiver_configuration.integrate && !self.receiver_configuration.integrate {
+        let transition = self
+            .receiver_configuration
+            .transition(&receiver_configuration)?;
+        if matches!(
+            transition,
+            ReceiverTransition::Stop | ReceiverTransition::Restart
+        ) {
+            log::info!("Stopping integration");
+            self.stop_integration();
+        }
+        // The fake receiver does not do frequency or position switching, but
+        // remember the reference frequency and switching mode so that they
+        // are reported back like for a real one.
+        self.receiver_configuration = ReceiverConfiguration {
+            integrate: transition == ReceiverTransition::Continue,
+            ..receiver_configuration
+        };
+        if matches!(
+            transition,
+            ReceiverTransition::Start | ReceiverTransition::Restart
+        ) {
+            if !self.auxiliary_devices.integration_allowed() {
+                return Err(ReceiverError::AuxiliaryDeviceOff);
+            }
             log::info!("Starting integration");
             self.receiver_configuration.integrate = true;
-        } else if !receiver_configuration.integrate && self.receiver_configuration.integrate {
-            log::info!("Stopping integration");
-            self.receiver_configuration.integrate = false;
+            self.current_spectra.clear();
+            self.integration_start = Some(self.now());
+            self.max_tracking_error = None;
         }
         Ok(self.receiver_configuration)
     }
 
+    fn simulated_conditions(&mut self) -> Option<&mut SimulatedConditions> {
+        Some(&mut self.conditions)
+    }
+
+    fn capabilities(&self) -> TelescopeCapabilities {
+        let mut observation_modes = vec![ObservationMode::TotalPower];
+        if self.rfi_scan.is_some() {
+            observation_modes.push(ObservationMode::RfiScan);
+        }
+        TelescopeCapabilities {
+            observed_band: FrequencyRange {
+                min: FAKE_TELESCOPE_FIRST_CHANNEL,
+                max: FAKE_TELESCOPE_FIRST_CHANNEL
+                    + FAKE_TELESCOPE_CHANNEL_WIDTH * FAKE_TELESCOPE_CHANNELS as f64,
+            },
+            reference_band: None,
+            bandwidths: vec![FAKE_TELESCOPE_CHANNEL_WIDTH * FAKE_TELESCOPE_CHANNELS as f64],
+            sample_rates: vec![FAKE_TELESCOPE_CHANNEL_WIDTH * FAKE_TELESCOPE_CHANNELS as f64],
+            channels: FAKE_TELESCOPE_CHANNELS,
+            window_functions: vec![WindowFunction::default()],
+            observation_modes,
+            slewing_speed: FAKE_TELESCOPE_SLEWING_SPEED,
+            min_altitude: LOWEST_ALLOWED_ALTITUDE,
+        }
+    }
+
+    fn pointing_correction(&self) -> PointingCorrection {
+        self.pointing
+    }
+
+    fn set_pointing_correction(&mut self, correction: PointingCorrection) {
+        self.pointing = correction;
+    }
+
     async fn get_info(&self) -> Result<TelescopeInfo, TelescopeError> {
-        let target_horizontal =
-            calculate_target_horizontal(self.location, Utc::now(), self.target, self.horizontal);
+        let target_horizontal = calculate_target_horizontal(
+            self.location,
+            self.now(),
+            self.target,
+            self.horizontal,
+            &self.pointing,
+        );
 
         let horizontal_offset_squared = (target_horizontal.azimuth - self.horizontal.azimuth)
+            .0
             .powi(2)
-            + (target_horizontal.altitude - self.horizontal.altitude).powi(2);
+            + (target_horizontal.altitude - self.horizontal.altitude)
+                .0
+                .powi(2);
         let status = {
             if self.target == TelescopeTarget::Stopped {
                 TelescopeStatus::Idle
-            } else if horizontal_offset_squared > 0.2f64.to_radians().powi(2) {
+            } else if horizontal_offset_squared > Degrees(0.2).to_radians().0.powi(2) {
                 TelescopeStatus::Slewing
             } else if self.target == TelescopeTarget::Parked {
                 TelescopeStatus::Idle
@@ -122,49 +467,39 @@ impl Telescope for FakeTelescope {
             }
         };
 
-        let latest_observation = if self.current_spectra.is_empty() {
-            None
-        } else {
-            let mut latest_observation = ObservedSpectra {
-                frequencies: vec![0f64; FAKE_TELESCOPE_CHANNELS],
-                spectra: vec![0f64; FAKE_TELESCOPE_CHANNELS],
-                observation_time: Duration::from_secs(0),
-            };
-            for integration in &self.current_spectra {
-                latest_observation.spectra = latest_observation
-                    .spectra
-                    .into_iter()
-                    .zip(integration.spectra.iter())
-                    .map(|(a, b)| a + b)
-                    .collect();
-                latest_observation.observation_time += integration.observation_time;
-            }
-            latest_observation.frequencies = self.current_spectra[0].frequencies.clone();
-            latest_observation.spectra = latest_observation
-                .spectra
-                .into_iter()
-                .map(|value| value / self.current_spectra.len() as f64)
-                .collect();
-            Some(latest_observation)
-        };
+        let latest_observation = self.average_spectra();
         Ok(TelescopeInfo {
             id: self.name.clone(),
             status,
             current_horizontal: self.horizontal,
             commanded_horizontal: Some(target_horizontal),
             current_target: self.target,
-            most_recent_error: self.most_recent_error.clone(),
+            most_recent_error: self
+                .conditions
+                .forced_error
+                .clone()
+                .or_else(|| self.most_recent_error.clone()),
             measurement_in_progress: self.receiver_configuration.integrate,
             latest_observation,
+            auxiliary_devices: self.auxiliary_devices.states(),
+            tasks: self.supervisor.health(),
+            controller_latency: None,
+            controller_commands: None,
         })
     }
 
     async fn update(&mut self, delta_time: Duration) -> Result<(), TelescopeError> {
-        let now = Utc::now();
+        let now = self.now();
         let current_horizontal = self.horizontal;
-        let target_horizontal =
-            calculate_target_horizontal(self.location, now, self.target, current_horizontal);
+        let target_horizontal = calculate_target_horizontal(
+            self.location,
+            now,
+            self.target,
+            current_horizontal,
+            &self.pointing,
+        );
 
+        let mut commanded_horizontal = None;
         if target_horizontal.altitude < LOWEST_ALLOWED_ALTITUDE {
             self.target = TelescopeTarget::Stopped;
             log::info!(
@@ -172,17 +507,89 @@ impl Telescope for FakeTelescope {
                 &self.target
             );
             self.most_recent_error = Some(TelescopeError::TargetBelowHorizon);
+        } else if let Err(error) = self.interlock.reserve(
+            &self.name,
+            Sweep::between(current_horizontal, target_horizontal),
+        ) {
+            // Hold position until the other telescope has left the zone.
+            self.most_recent_error = Some(error);
+        } else if self.conditions.wind_speed > FAKE_TELESCOPE_MAX_WIND_SPEED {
+            commanded_horizontal = Some(target_horizontal);
+            self.most_recent_error = Some(TelescopeError::TelescopeIOError(format!(
+                "Wind speed {} m/s is too high to move the telescope",
+                self.conditions.wind_speed
+            )));
         } else {
-            let max_delta_angle = FAKE_TELESCOPE_SLEWING_SPEED * delta_time.as_secs_f64();
+            commanded_horizontal = Some(target_horizontal);
+            if matches!(
+                self.most_recent_error,
+                Some(TelescopeError::CollisionRisk(_))
+            ) {
+                self.most_recent_error = None;
+            }
+            let wind_factor = 1.0 - self.conditions.wind_speed / FAKE_TELESCOPE_MAX_WIND_SPEED;
+            let max_delta_angle =
+                FAKE_TELESCOPE_SLEWING_SPEED * delta_time.as_secs_f64() * wind_factor.max(0.0);
             self.horizontal.azimuth += (target_horizontal.azimuth - current_horizontal.azimuth)
                 .clamp(-max_delta_angle, max_delta_angle);
             self.horizontal.altitude += (target_horizontal.altitude - current_horizontal.altitude)
                 .clamp(-max_delta_angle, max_delta_angle);
         }
+        self.interlock
+            .update(&self.name, self.horizontal, commanded_horizontal);
 
         if self.receiver_configuration.integrate {
             log::info!("Pushing spectum...");
-            self.current_spectra.push(create_fake_spectra(delta_time))
+            let tracking_error = angular_separation(
+                target_horizontal.azimuth,
+                target_horizontal.altitude,
+                self.horizontal.azimuth,
+                self.horizontal.altitude,
+            );
+            self.max_tracking_error = Some(match self.max_tracking_error {
+                Some(max) if max.0 >= tracking_error.0 => max,
+                _ => tracking_error,
+            });
+            let spectra = match (&self.conditions.hi_source, self.target) {
+                (Some(source), TelescopeTarget::Galactic { l, b }) => {
+                    let pointing_offset = Radians(
+                        (((target_horizontal.azimuth - self.horizontal.azimuth).0
+                            * self.horizontal.altitude.cos())
+                        .powi(2)
+                            + (target_horizontal.altitude - self.horizontal.altitude)
+                                .0
+                                .powi(2))
+                        .sqrt(),
+                    );
+                    source.observe(l, b, pointing_offset, now, delta_time)
+                }
+                _ => create_fake_spectra(delta_time, &self.rfi_carriers()),
+            };
+            self.current_spectra.push(spectra);
+            let integrated = self
+                .integration_start
+                .and_then(|start| (now - start).to_std().ok())
+                .unwrap_or_default();
+            if self.receiver_configuration.mode.is_complete(integrated) {
+                log::info!("Integration finished after {:?}", integrated);
+                self.stop_integration();
+            }
+        } else if matches!(
+            self.target,
+            TelescopeTarget::Parked | TelescopeTarget::Stopped
+        ) {
+            if let Some(rfi_scan) = &self.rfi_scan {
+                if rfi_scan.is_due(self.last_rfi_scan, now) {
+                    log::info!("Running RFI scan for telescope {}", &self.name);
+                    self.completed_rfi_scans.push(create_fake_rfi_scan(
+                        self.name.clone(),
+                        now,
+                        rfi_scan,
+                        &self.rfi_carriers(),
+                    ));
+                    self.last_rfi_scan = Some(now);
+                }
+            }
         }
 
         Ok(())
@@ -190,21 +597,53 @@ impl Telescope for FakeTelescope {
 
     async fn restart(&mut self) -> Result<(), TelescopeError> {
         self.most_recent_error = None;
-        self.receiver_configuration.integrate = false;
+        self.stop_integration();
         self.current_spectra.clear();
         Ok(())
     }
+
+    async fn set_auxiliary_device(
+        &mut self,
+        name: &str,
+        on: bool,
+    ) -> Result<AuxiliaryDeviceState, TelescopeError> {
+        self.auxiliary_devices.switch(name, on, |_, _| Ok(()))
+    }
+
+    async fn measurement_in_progress(&self) -> Option<Measurement> {
+        self.current_measurement()
+    }
+
+    async fn take_completed_measurements(&mut self) -> Vec<Measurement> {
+        std::mem::take(&mut self.completed_measurements)
+    }
+
+    async fn take_completed_rfi_scans(&mut self) -> Vec<RfiScan> {
+        std::mem::take(&mut self.completed_rfi_scans)
+    }
 }
 
-fn create_fake_spectra(integration_time: Duration) -> ObservedSpectra {
+fn fake_frequencies() -> Vec<f64> {
+    (0..FAKE_TELESCOPE_CHANNELS)
+        .map(|channel| channel as f64 * FAKE_TELESCOPE_CHANNEL_WIDTH + FAKE_TELESCOPE_FIRST_CHANNEL)
+        .collect()
+}
+
+fn create_fake_spectra(integration_time: Duration, rfi_carriers: &[f64]) -> ObservedSpectra {
     let mut rng = rand::thread_rng();
 
-    let frequencies: Vec<f64> = (0..FAKE_TELESCOPE_CHANNELS)
-        .map(|channel| channel as f64 * FAKE_TELESCOPE_CHANNEL_WIDTH + FAKE_TELESCOPE_FIRST_CHANNEL)
-        .collect();
-    let spectra: Vec<f64> = vec![5f64; FAKE_TELESCOPE_CHANNELS]
-        .into_iter()
-        .map(|value| {
+    let frequencies = fake_frequencies();
+    let spectra: Vec<f64> = frequencies
+        .iter()
+        .map(|frequency| {
+            let carrier = rfi_carriers
+                .iter()
+                .any(|carrier| (frequency - carrier).abs() < FAKE_TELESCOPE_CHANNEL_WIDTH);
+            let value = if carrier {
+                50f64
+            } else {
+                FAKE_TELESCOPE_SYSTEM_TEMPERATURE
+            };
             value + FAKE_TELESCOPE_NOISE * rng.sample::<f64, StandardNormal>(StandardNormal)
         })
         .collect();
@@ -213,21 +652,154 @@ fn create_fake_spectra(integration_time: Duration) -> ObservedSpectra {
         frequencies,
         spectra,
         observation_time: integration_time,
+        cycles: 1,
     }
 }
 
+fn create_fake_rfi_scan(
+    telescope_name: String,
+    start: DateTime<Utc>,
+    definition: &RfiScanDefinition,
+    rfi_carriers: &[f64],
+) -> RfiScan {
+    let mut rng = rand::thread_rng();
+    let channels = ((definition.stop_frequency - definition.start_frequency)
+        / FAKE_TELESCOPE_CHANNEL_WIDTH) as usize;
+    let frequencies: Vec<f64> = (0..channels)
+        .map(|channel| definition.start_frequency + channel as f64 * FAKE_TELESCOPE_CHANNEL_WIDTH)
+        .collect();
+    let power: Vec<f64> = frequencies
+        .iter()
+        .map(|frequency| {
+            let carrier = rfi_carriers
+                .iter()
+                .any(|carrier| (frequency - carrier).abs() < 0.5e6);
+            let noise = 0.1 * rng.sample::<f64, StandardNormal>(StandardNormal);
+            if carrier {
+                10.0 + noise
+            } else {
+                1.0 + noise
+            }
+        })
+        .collect();
+    RfiScan::from_spectra(telescope_name, start, &[(frequencies, power)])
+}
+
 fn calculate_target_horizontal(
     location: Location,
     when: DateTime<Utc>,
     target: TelescopeTarget,
     current_horizontal: Direction,
+    pointing: &PointingCorrection,
 ) -> Direction {
     match target {
         TelescopeTarget::Equatorial { ra, dec } => {
-            horizontal_from_equatorial(location, when, ra, dec)
+            pointing.apply(horizontal_from_equatorial(location, when, ra, dec))
+        }
+        TelescopeTarget::Galactic { l, b } => {
+            pointing.apply(horizontal_from_galactic(location, when, l, b))
+        }
+        TelescopeTarget::Horizontal { azimuth, elevation } => pointing.apply(Direction {
+            azimuth,
+            altitude: elevation,
+        }),
+        TelescopeTarget::Satellite { elements } => {
+            pointing.apply(elements.horizontal(location, when))
         }
-        TelescopeTarget::Galactic { l, b } => horizontal_from_galactic(location, when, l, b),
         TelescopeTarget::Stopped => current_horizontal,
         TelescopeTarget::Parked => FAKE_TELESCOPE_PARKING_HORIZONTAL,
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::analysis::{run_cells, AnalysisCache, Operation};
+    use crate::archive::archive_measurement;
+    use crate::database::create_in_memory_database;
+
+    #[tokio::test]
+    async fn test_synthetic_hi_source_is_recovered() {
+        let source = SyntheticHiSource {
+            peak_temperature: 50.0,
+            latitude_width: 5.0,
+            velocity: -50.0,
+            width: 20.0,
+        };
+        let mut telescope = create(
+            "fake".to_string(),
+            None,
+            vec![],
+            TelescopeSupervisor::new("fake"),
+            CollisionInterlock::default(),
+        );
+        telescope.conditions.hi_source = Some(source.clone());
+        // Circumpolar from Onsala, so always above the horizon.
+        let b = Degrees(2.0).to_radians();
+        telescope
+            .set_target(TelescopeTarget::Galactic {
+                l: Degrees(120.0).to_radians(),
+                b,
+            })
+            .await
+            .unwrap();
+        for _ in 0..30 {
+            telescope.update(Duration::from_secs(1)).await.unwrap();
+        }
+        assert_eq!(
+            telescope.get_info().await.unwrap().status,
+            TelescopeStatus::Tracking
+        );
+
+        let idle = telescope.receiver_configuration;
+        telescope
+            .set_receiver_configuration(ReceiverConfiguration {
+                integrate: true,
+                ..idle
+            })
+            .await
+            .unwrap();
+        // Long enough for the noise to hardly bias the fitted moments.
+        for _ in 0..200 {
+            telescope.update(Duration::from_secs(10)).await.unwrap();
+        }
+        // The average so far is shown while integrating.
+        let preview = telescope
+            .get_info()
+            .await
+            .unwrap()
+            .latest_observation
+            .unwrap();
+        assert_eq!(preview.cycles, 200);
+        assert_eq!(preview.observation_time, Duration::from_secs(2000));
+        telescope.set_receiver_configuration(idle).await.unwrap();
+        let measurements = telescope.take_completed_measurements().await;
+        assert_eq!(measurements.len(), 1);
+
+        let db = create_in_memory_database();
+        let id = archive_measurement(&db, "fake", measurements[0].clone())
+            .await
+            .unwrap();
+        let cells = [
+            Operation::Select { measurement_id: id },
+            Operation::Baseline {
+                order: 1,
+                exclude_from: -120.0,
+                exclude_to: 20.0,
+            },
+            Operation::Fit,
+        ];
+        let outputs = run_cells(&db, &AnalysisCache::default(), &cells)
+            .await
+            .unwrap();
+        let fit = outputs[2].result.as_ref().unwrap().fit.as_ref().unwrap();
+        let peak = source.peak_temperature_at(b);
+        assert!((fit.amplitude - peak).abs() < 0.05 * peak, "{:?}", fit);
+        assert!((fit.center - source.velocity).abs() < 1.0, "{:?}", fit);
+        assert!(
+            (fit.width - source.width).abs() < 0.1 * source.width,
+            "{:?}",
+            fit
+        );
+    }
+}
@@ -0,0 +1,80 @@
+use crate::database::{DataBase, Storage};
+use crate::faults::{
+    file_fault_report, set_fault_status, telescope_snapshot, FaultError, FaultReport, FaultStatus,
+    FaultsState, NewFaultReport,
+};
+use crate::telescope::TelescopeCollection;
+use axum::{
+    extract::{Json, Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{get, put},
+    Router,
+};
+use chrono::Utc;
+
+pub fn routes(
+    telescopes: TelescopeCollection,
+    database: DataBase<impl Storage + 'static>,
+) -> Router {
+    Router::new()
+        .route("/", get(get_fault_reports).post(post_fault_report))
+        .route("/:id/status", put(put_fault_status))
+        .with_state(FaultsState {
+            database,
+            telescopes,
+        })
+}
+
+impl IntoResponse for FaultError {
+    fn into_response(self) -> Response {
+        match self {
+            FaultError::NotFound => {
+                (StatusCode::NOT_FOUND, "Fault report not found".to_string()).into_response()
+            }
+            FaultError::UnknownTelescope => {
+                (StatusCode::NOT_FOUND, "Telescope not found".to_string()).into_response()
+            }
+            FaultError::EmptyDescription => {
+                (StatusCode::BAD_REQUEST, "Describe the problem".to_string()).into_response()
+            }
+            FaultError::ServiceUnavailable => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Failed to update fault reports".to_string(),
+            )
+                .into_response(),
+        }
+    }
+}
+
+async fn get_fault_reports(
+    State(state): State<FaultsState<impl Storage>>,
+) -> Json<Vec<FaultReport>> {
+    Json(
+        state
+            .database
+            .get_data()
+            .await
+            .expect("As long as no one is manually editing the database, this should never fail.")
+            .fault_reports,
+    )
+}
+
+async fn post_fault_report(
+    State(state): State<FaultsState<impl Storage>>,
+    Json(report): Json<NewFaultReport>,
+) -> Result<(StatusCode, Json<FaultReport>), FaultError> {
+    let snapshot = telescope_snapshot(&state.telescopes, &report.telescope_name).await;
+    let fault = file_fault_report(&state.database, report, snapshot, Utc::now()).await?;
+    Ok((StatusCode::CREATED, Json(fault)))
+}
+
+async fn put_fault_status(
+    State(state): State<FaultsState<impl Storage>>,
+    Path(id): Path<u64>,
+    Json(status): Json<FaultStatus>,
+) -> Result<Json<FaultReport>, FaultError> {
+    Ok(Json(
+        set_fault_status(&state.database, id, status, Utc::now()).await?,
+    ))
+}
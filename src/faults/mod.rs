@@ -0,0 +1,268 @@
+//! Fault reports filed by observers when something is wrong with a
+//! telescope.
+//!
+//! Each report keeps a snapshot of the telescope info at the time it was
+//! filed, including the most recent error and the health of its tasks, so
+//! that the fault can be investigated after the telescope has moved on.
+
+use crate::database::{DataBase, DataBaseError, Storage};
+use crate::telescope::TelescopeCollection;
+use crate::telescopes::TelescopeInfo;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+
+pub mod api_routes;
+pub mod routes;
+
+/// State of the fault routes, which need the telescopes to take snapshots.
+#[derive(Clone)]
+pub struct FaultsState<StorageType: Storage> {
+    pub database: DataBase<StorageType>,
+    pub telescopes: TelescopeCollection,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+pub enum FaultCategory {
+    Mechanical,
+    Receiver,
+    Software,
+    Weather,
+    Other,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone, Default)]
+pub enum FaultStatus {
+    #[default]
+    Open,
+    /// Someone is looking into the fault.
+    Acknowledged,
+    Resolved,
+}
+
+impl Display for FaultCategory {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl Display for FaultStatus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct NewFaultReport {
+    pub telescope_name: String,
+    pub reporter: String,
+    pub category: FaultCategory,
+    pub description: String,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct FaultReport {
+    pub id: u64,
+    pub created: DateTime<Utc>,
+    #[serde(flatten)]
+    pub report: NewFaultReport,
+    /// State of the telescope when the report was filed, None if the
+    /// telescope did not respond.
+    pub snapshot: Option<TelescopeInfo>,
+    pub status: FaultStatus,
+    pub status_changed: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum FaultError {
+    NotFound,
+    UnknownTelescope,
+    EmptyDescription,
+    ServiceUnavailable,
+}
+
+impl From<DataBaseError> for FaultError {
+    fn from(_source: DataBaseError) -> Self {
+        Self::ServiceUnavailable
+    }
+}
+
+/// Current info of a telescope to store with a fault report. The latest
+/// spectrum is left out, it is large and already in the session recording.
+pub async fn telescope_snapshot(
+    telescopes: &TelescopeCollection,
+    telescope_name: &str,
+) -> Option<TelescopeInfo> {
+    let telescope = telescopes
+        .read()
+        .await
+        .get(telescope_name)?
+        .telescope
+        .clone();
+    let info = telescope.lock().await.get_info().await.ok()?;
+    Some(TelescopeInfo {
+        latest_observation: None,
+        ..info
+    })
+}
+
+pub async fn file_fault_report(
+    db: &DataBase<impl Storage>,
+    report: NewFaultReport,
+    snapshot: Option<TelescopeInfo>,
+    now: DateTime<Utc>,
+) -> Result<FaultReport, FaultError> {
+    if report.description.trim().is_empty() {
+        return Err(FaultError::EmptyDescription);
+    }
+    if !db
+        .get_data()
+        .await?
+        .telescopes
+        .iter()
+        .any(|t| t.name == report.telescope_name)
+    {
+        return Err(FaultError::UnknownTelescope);
+    }
+
+    let mut fault = FaultReport {
+        id: 0,
+        created: now,
+        report,
+        snapshot,
+        status: FaultStatus::Open,
+        status_changed: None,
+    };
+    db.update_data(|mut data_model| {
+        fault.id = data_model
+            .fault_reports
+            .iter()
+            .map(|f| f.id + 1)
+            .max()
+            .unwrap_or(1);
+        data_model.fault_reports.push(fault.clone());
+        data_model
+    })
+    .await?;
+    // Admins watch the log for warnings, which is how they learn about
+    // new faults.
+    log::warn!(
+        "{} reported a {:?} fault on {}: {}",
+        fault.report.reporter,
+        fault.report.category,
+        fault.report.telescope_name,
+        fault.report.description
+    );
+    Ok(fault)
+}
+
+pub async fn set_fault_status(
+    db: &DataBase<impl Storage>,
+    id: u64,
+    status: FaultStatus,
+    now: DateTime<Utc>,
+) -> Result<FaultReport, FaultError> {
+    let mut result = Err(FaultError::NotFound);
+    db.update_data(|mut data_model| {
+        if let Some(fault) = data_model.fault_reports.iter_mut().find(|f| f.id == id) {
+            if fault.status != status {
+                fault.status = status;
+                fault.status_changed = Some(now);
+            }
+            result = Ok(fault.clone());
+        }
+        data_model
+    })
+    .await?;
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::database::create_in_memory_database;
+    use crate::interlock::CollisionInterlock;
+    use crate::supervisor::TelescopeSupervisor;
+    use crate::telescope::TelescopeContainer;
+    use crate::telescopes::test_utils::fake_telescope;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use tokio::sync::{Mutex, RwLock};
+
+    #[tokio::test]
+    async fn test_fault_report() {
+        let db = create_in_memory_database();
+        let now = Utc::now();
+        let supervisor = TelescopeSupervisor::new("fake");
+        let telescopes: TelescopeCollection = Arc::new(RwLock::new(HashMap::from([(
+            "fake".to_string(),
+            TelescopeContainer {
+                telescope: Arc::new(Mutex::new(crate::fake_telescope::create(
+                    "fake".to_string(),
+                    None,
+                    vec![],
+                    supervisor.clone(),
+                    CollisionInterlock::default(),
+                ))),
+                supervisor,
+                updates: Default::default(),
+            },
+        )])));
+        db.update_data(|mut data_model| {
+            data_model.telescopes = vec![fake_telescope("fake")];
+            data_model
+        })
+        .await
+        .unwrap();
+        let report = NewFaultReport {
+            telescope_name: "fake".to_string(),
+            reporter: "anna".to_string(),
+            category: FaultCategory::Mechanical,
+            description: "Grinding noise when slewing".to_string(),
+        };
+
+        let snapshot = telescope_snapshot(&telescopes, "fake").await;
+        assert!(snapshot.is_some());
+        assert_eq!(telescope_snapshot(&telescopes, "salsa").await, None);
+        let fault = file_fault_report(&db, report.clone(), snapshot, now)
+            .await
+            .unwrap();
+        assert_eq!(fault.status, FaultStatus::Open);
+        assert_eq!(fault.snapshot.unwrap().id, "fake");
+        assert_eq!(
+            file_fault_report(
+                &db,
+                NewFaultReport {
+                    description: " ".to_string(),
+                    ..report.clone()
+                },
+                None,
+                now
+            )
+            .await,
+            Err(FaultError::EmptyDescription)
+        );
+        assert_eq!(
+            file_fault_report(
+                &db,
+                NewFaultReport {
+                    telescope_name: "salsa".to_string(),
+                    ..report
+                },
+                None,
+                now
+            )
+            .await,
+            Err(FaultError::UnknownTelescope)
+        );
+
+        let acknowledged = set_fault_status(&db, fault.id, FaultStatus::Acknowledged, now)
+            .await
+            .unwrap();
+        assert_eq!(acknowledged.status_changed, Some(now));
+        assert_eq!(
+            set_fault_status(&db, 7, FaultStatus::Resolved, now).await,
+            Err(FaultError::NotFound)
+        );
+    }
+}
@@ -0,0 +1,159 @@
+use crate::database::{DataBase, Storage};
+use crate::faults::{
+    file_fault_report, set_fault_status, telescope_snapshot, FaultCategory, FaultError,
+    FaultStatus, FaultsState, NewFaultReport,
+};
+use crate::telescope::TelescopeCollection;
+use crate::template::HtmlTemplate;
+use askama::Template;
+use axum::{
+    extract::{Form, Path, State},
+    response::IntoResponse,
+    routing::{get, post},
+    Router,
+};
+use chrono::Utc;
+use serde::Deserialize;
+
+pub fn routes(
+    telescopes: TelescopeCollection,
+    database: DataBase<impl Storage + 'static>,
+) -> Router {
+    Router::new()
+        .route("/", get(get_faults).post(post_fault))
+        .route("/new", get(get_fault_form))
+        .route("/:id/status", post(post_fault_status))
+        .with_state(FaultsState {
+            database,
+            telescopes,
+        })
+}
+
+struct FaultEntry {
+    id: u64,
+    created: String,
+    telescope_name: String,
+    reporter: String,
+    category: FaultCategory,
+    description: String,
+    status: FaultStatus,
+    /// Most recent error of the telescope when the fault was reported.
+    telescope_error: String,
+}
+
+#[derive(Template)]
+#[template(path = "faults.html")]
+struct FaultsTemplate {
+    faults: Vec<FaultEntry>,
+    message: Option<String>,
+}
+
+async fn render_faults(
+    db: &DataBase<impl Storage>,
+    message: Option<String>,
+) -> HtmlTemplate<FaultsTemplate> {
+    let faults = db
+        .get_data()
+        .await
+        .expect("As long as no one is manually editing the database, this should never fail.")
+        .fault_reports
+        .into_iter()
+        .rev()
+        .map(|fault| FaultEntry {
+            id: fault.id,
+            created: fault.created.format("%Y-%m-%d %H:%M").to_string(),
+            telescope_error: match &fault.snapshot {
+                Some(info) => info
+                    .most_recent_error
+                    .as_ref()
+                    .map(|e| e.to_string())
+                    .unwrap_or_default(),
+                None => "Telescope did not respond".to_string(),
+            },
+            telescope_name: fault.report.telescope_name,
+            reporter: fault.report.reporter,
+            category: fault.report.category,
+            description: fault.report.description,
+            status: fault.status,
+        })
+        .collect();
+    HtmlTemplate(FaultsTemplate { faults, message })
+}
+
+async fn get_faults<StorageType>(State(state): State<FaultsState<StorageType>>) -> impl IntoResponse
+where
+    StorageType: Storage,
+{
+    render_faults(&state.database, None).await
+}
+
+#[derive(Template)]
+#[template(path = "fault_form.html")]
+struct FaultFormTemplate {
+    telescope_names: Vec<String>,
+    categories: Vec<FaultCategory>,
+}
+
+async fn get_fault_form<StorageType>(
+    State(state): State<FaultsState<StorageType>>,
+) -> impl IntoResponse
+where
+    StorageType: Storage,
+{
+    let telescope_names = state
+        .database
+        .get_data()
+        .await
+        .expect("As long as no one is manually editing the database, this should never fail.")
+        .telescopes
+        .iter()
+        .map(|t| t.name.clone())
+        .collect();
+    HtmlTemplate(FaultFormTemplate {
+        telescope_names,
+        categories: vec![
+            FaultCategory::Mechanical,
+            FaultCategory::Receiver,
+            FaultCategory::Software,
+            FaultCategory::Weather,
+            FaultCategory::Other,
+        ],
+    })
+}
+
+async fn post_fault<StorageType>(
+    State(state): State<FaultsState<StorageType>>,
+    Form(report): Form<NewFaultReport>,
+) -> impl IntoResponse
+where
+    StorageType: Storage,
+{
+    let snapshot = telescope_snapshot(&state.telescopes, &report.telescope_name).await;
+    let message = match file_fault_report(&state.database, report, snapshot, Utc::now()).await {
+        Ok(fault) => format!("Thank you, the problem was reported as fault {}.", fault.id),
+        Err(FaultError::EmptyDescription) => "Describe the problem to report it.".to_string(),
+        Err(FaultError::UnknownTelescope) => "Select a telescope to report it.".to_string(),
+        Err(error) => format!("Failed to report the problem: {:?}", error),
+    };
+    render_faults(&state.database, Some(message)).await
+}
+
+#[derive(Deserialize, Debug)]
+struct StatusForm {
+    status: FaultStatus,
+}
+
+async fn post_fault_status<StorageType>(
+    State(state): State<FaultsState<StorageType>>,
+    Path(id): Path<u64>,
+    Form(form): Form<StatusForm>,
+) -> impl IntoResponse
+where
+    StorageType: Storage,
+{
+    let message = set_fault_status(&state.database, id, form.status, Utc::now())
+        .await
+        .err()
+        .map(|error| format!("Failed to update fault {}: {:?}", id, error));
+    render_faults(&state.database, message).await
+}
@@ -0,0 +1,170 @@
+//! Number formatting for pages and exports.
+//!
+//! Pages use the decimal separator of the visitor's preferred language, taken
+//! from the `Accept-Language` header. Exports that may be read by another
+//! program (CSV, FITS, the PDF report and PNG plots) always use
+//! [`Locale::MACHINE`] so that numbers parse the same everywhere.
+use async_trait::async_trait;
+use axum::extract::FromRequestParts;
+use axum::http::{header, request::Parts};
+use std::convert::Infallible;
+
+/// Primary language subtags that write decimals with a comma.
+const DECIMAL_COMMA_LANGUAGES: &[&str] = &[
+    "sv", "da", "nb", "nn", "no", "fi", "de", "nl", "fr", "es", "it", "pt", "pl", "cs", "ru",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Locale {
+    decimal_separator: char,
+}
+
+impl Default for Locale {
+    fn default() -> Self {
+        Locale::MACHINE
+    }
+}
+
+impl Locale {
+    /// Formatting for machine readable output, always with a '.' decimal point.
+    pub const MACHINE: Locale = Locale {
+        decimal_separator: '.',
+    };
+
+    /// Picks the locale from an `Accept-Language` header value. Only the most
+    /// preferred language is considered.
+    pub fn from_accept_language(accept_language: &str) -> Locale {
+        let language = accept_language
+            .split([',', ';'])
+            .next()
+            .unwrap_or_default()
+            .trim();
+        let primary = language
+            .split(['-', '_'])
+            .next()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        if DECIMAL_COMMA_LANGUAGES.contains(&primary.as_str()) {
+            Locale {
+                decimal_separator: ',',
+            }
+        } else {
+            Locale::MACHINE
+        }
+    }
+
+    /// Formats a value with a fixed number of decimals.
+    pub fn number(&self, value: f64, precision: usize) -> String {
+        self.localize(format!("{:.*}", precision, value))
+    }
+
+    /// Formats a value with a fixed number of decimals followed by its unit.
+    pub fn quantity(&self, value: f64, precision: usize, unit: &str) -> String {
+        format!("{} {}", self.number(value, precision), unit)
+    }
+
+    /// Formats a value in scientific notation, e.g. `1.23e20`.
+    pub fn scientific(&self, value: f64, precision: usize) -> String {
+        self.localize(format!("{:.*e}", precision, value))
+    }
+
+    fn localize(&self, formatted: String) -> String {
+        if self.decimal_separator == '.' {
+            formatted
+        } else {
+            formatted.replace('.', &self.decimal_separator.to_string())
+        }
+    }
+}
+
+/// Number of decimals needed to tell apart axis ticks spread over `span`.
+pub fn precision_for_span(span: f64) -> usize {
+    if !span.is_finite() || span <= 0.0 {
+        return 0;
+    }
+    (2.0 - span.log10().floor()).max(0.0) as usize
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Locale
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(parts
+            .headers
+            .get(header::ACCEPT_LANGUAGE)
+            .and_then(|value| value.to_str().ok())
+            .map(Locale::from_accept_language)
+            .unwrap_or_default())
+    }
+}
+
+/// Values that the template filters can format.
+pub trait Number {
+    fn to_f64(&self) -> f64;
+}
+
+impl Number for f64 {
+    fn to_f64(&self) -> f64 {
+        *self
+    }
+}
+
+impl Number for f32 {
+    fn to_f64(&self) -> f64 {
+        *self as f64
+    }
+}
+
+impl<T: Number + ?Sized> Number for &T {
+    fn to_f64(&self) -> f64 {
+        (**self).to_f64()
+    }
+}
+
+/// Askama filters, brought into scope with `use crate::format::filters;` next
+/// to the template struct. Used as `{{ value|number(1, locale) }}`.
+pub mod filters {
+    use super::{Locale, Number};
+
+    pub fn number<T: Number>(
+        value: &T,
+        precision: usize,
+        locale: &Locale,
+    ) -> askama::Result<String> {
+        Ok(locale.number(value.to_f64(), precision))
+    }
+
+    pub fn quantity<T: Number>(
+        value: &T,
+        precision: usize,
+        unit: &str,
+        locale: &Locale,
+    ) -> askama::Result<String> {
+        Ok(locale.quantity(value.to_f64(), precision, unit))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_locale_decimal_separator() {
+        let swedish = Locale::from_accept_language("sv-SE,sv;q=0.9,en-US;q=0.8");
+        assert_eq!(swedish.number(1420.4058, 2), "1420,41");
+        assert_eq!(swedish.quantity(-3.26, 1, "K"), "-3,3 K");
+        assert_eq!(swedish.scientific(1.234e20, 2), "1,23e20");
+
+        let english = Locale::from_accept_language("en-GB,sv;q=0.5");
+        assert_eq!(english.number(1420.4058, 2), "1420.41");
+        assert_eq!(Locale::from_accept_language(""), Locale::MACHINE);
+
+        assert_eq!(precision_for_span(2.5), 2);
+        assert_eq!(precision_for_span(2.5e6), 0);
+        assert_eq!(precision_for_span(0.04), 4);
+    }
+}
@@ -0,0 +1,167 @@
+use crate::archive::ArchivedMeasurement;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::Write;
+use std::time::Duration;
+use tokio::process::Command;
+
+fn default_timeout_seconds() -> u64 {
+    60
+}
+
+/// An external command run after each completed measurement, e.g. to copy
+/// data to a network share.
+///
+/// The arguments may contain the placeholders `{id}`, `{telescope}`,
+/// `{start}`, `{duration}` and `{file}`, where `{file}` is the path of a
+/// temporary JSON file with the archived measurement.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct PostObservationHook {
+    pub name: String,
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default = "default_timeout_seconds")]
+    pub timeout_seconds: u64,
+    /// Only run for measurements from these telescopes. Empty means all.
+    #[serde(default)]
+    pub telescopes: Vec<String>,
+}
+
+impl PostObservationHook {
+    fn applies_to(&self, telescope_name: &str) -> bool {
+        self.telescopes.is_empty() || self.telescopes.iter().any(|t| t == telescope_name)
+    }
+}
+
+/// Replace the placeholders in `argument` with their values.
+pub fn expand_argument(argument: &str, values: &HashMap<&str, String>) -> String {
+    values
+        .iter()
+        .fold(argument.to_string(), |argument, (key, value)| {
+            argument.replace(&format!("{{{}}}", key), value)
+        })
+}
+
+async fn run_hook(
+    hook: &PostObservationHook,
+    values: &HashMap<&str, String>,
+) -> Result<(), String> {
+    let args: Vec<String> = hook
+        .args
+        .iter()
+        .map(|arg| expand_argument(arg, values))
+        .collect();
+    let output = Command::new(&hook.command)
+        .args(&args)
+        .kill_on_drop(true)
+        .output();
+    match tokio::time::timeout(Duration::from_secs(hook.timeout_seconds), output).await {
+        Err(_) => Err(format!("timed out after {} s", hook.timeout_seconds)),
+        Ok(Err(error)) => Err(format!("failed to start: {}", error)),
+        Ok(Ok(output)) if !output.status.success() => Err(format!(
+            "exited with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )),
+        Ok(Ok(_)) => Ok(()),
+    }
+}
+
+/// Run all hooks applying to the measurement, one after the other.
+/// Failures are logged but do not stop the remaining hooks.
+pub async fn run_post_observation_hooks(
+    hooks: Vec<PostObservationHook>,
+    measurement: ArchivedMeasurement,
+) {
+    let hooks: Vec<_> = hooks
+        .into_iter()
+        .filter(|hook| hook.applies_to(&measurement.telescope_name))
+        .collect();
+    if hooks.is_empty() {
+        return;
+    }
+
+    // A fresh file with a random name, only readable by us, so that other
+    // users can neither read the measurement nor plant a file in its place.
+    let contents =
+        serde_json::to_vec(&measurement).expect("Serializing a measurement should never fail.");
+    let file = match tempfile::Builder::new()
+        .prefix(&format!("salsa-measurement-{}-", measurement.id))
+        .suffix(".json")
+        .tempfile()
+        .and_then(|mut file| file.write_all(&contents).map(|_| file))
+    {
+        Ok(file) => file,
+        Err(error) => {
+            log::error!(
+                "Failed to write measurement {} for post observation hooks: {}",
+                measurement.id,
+                error
+            );
+            return;
+        }
+    };
+
+    let values = HashMap::from([
+        ("id", measurement.id.to_string()),
+        ("telescope", measurement.telescope_name.clone()),
+        ("start", measurement.measurement.start.to_rfc3339()),
+        (
+            "duration",
+            measurement.measurement.duration.as_secs_f64().to_string(),
+        ),
+        ("file", file.path().display().to_string()),
+    ]);
+    for hook in hooks {
+        match run_hook(&hook, &values).await {
+            Ok(()) => log::info!(
+                "Post observation hook {} finished for measurement {}",
+                hook.name,
+                measurement.id
+            ),
+            Err(error) => log::error!(
+                "Post observation hook {} for measurement {} {}",
+                hook.name,
+                measurement.id,
+                error
+            ),
+        }
+    }
+
+    if let Err(error) = file.close() {
+        log::warn!("Failed to remove measurement file: {}", error);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_expand_argument() {
+        let values = HashMap::from([("id", "42".to_string()), ("telescope", "brage".to_string())]);
+        assert_eq!(
+            expand_argument("/share/{telescope}/{id}.json", &values),
+            "/share/brage/42.json"
+        );
+        assert_eq!(expand_argument("{unknown}", &values), "{unknown}");
+    }
+
+    #[tokio::test]
+    async fn test_run_hook_reports_failure() {
+        let hook = |command: &str| PostObservationHook {
+            name: "test".to_string(),
+            command: command.to_string(),
+            args: vec![],
+            timeout_seconds: 5,
+            telescopes: vec![],
+        };
+        let values = HashMap::new();
+        assert!(run_hook(&hook("true"), &values).await.is_ok());
+        assert!(run_hook(&hook("false"), &values).await.is_err());
+        assert!(run_hook(&hook("/nonexistent/command"), &values)
+            .await
+            .is_err());
+    }
+}
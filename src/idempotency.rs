@@ -0,0 +1,421 @@
+//! Idempotency keys for requests that change state.
+//!
+//! A client that may send the same request twice, e.g. when htmx retries or
+//! a button is double-clicked, sets the `Idempotency-Key` header to a value
+//! unique to the action. The first request with a key is performed and its
+//! response stored for a while, later requests with the same key get the
+//! stored response instead of performing the action again.
+//!
+//! Keys belong to the requester, so the layer runs after the request has
+//! been authenticated and authorized, and a key only replays responses to
+//! the same requester. Reusing a key for a different request is refused.
+
+use crate::authentication::AuthenticatedUser;
+use crate::database::{DataBase, Storage};
+use axum::{
+    body::{Body, Bytes, Full},
+    extract::State,
+    http::{header, HeaderName, HeaderValue, Method, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Duration, Utc};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+/// Header set on responses replayed from an earlier request.
+pub const IDEMPOTENT_REPLAYED_HEADER: &str = "idempotent-replayed";
+/// How long responses are kept for replay.
+pub const IDEMPOTENCY_KEY_RETENTION_HOURS: i64 = 24;
+/// Responses with larger bodies are not stored, so requests with their key
+/// are performed again.
+pub const MAX_STORED_RESPONSE_BYTES: usize = 64 * 1024;
+/// The oldest keys of a requester are forgotten beyond this many, anonymous
+/// requesters sharing the same keys.
+pub const MAX_IDEMPOTENCY_KEYS_PER_REQUESTER: usize = 100;
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct StoredResponse {
+    pub status: u16,
+    /// Name and value of each header, except those describing the body
+    /// that are set again when it is replayed.
+    #[serde(default)]
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct IdempotencyRecord {
+    pub key: String,
+    /// Who sent the request, none for anonymous requests.
+    #[serde(default)]
+    pub user_name: Option<String>,
+    pub method: String,
+    pub path: String,
+    /// SHA-256 of the request body, hex encoded.
+    #[serde(default)]
+    pub body_hash: String,
+    pub created: DateTime<Utc>,
+    /// None while the first request is being performed.
+    pub response: Option<StoredResponse>,
+}
+
+/// A new random key, for pages to put in the `hx-headers` of their forms.
+pub fn new_idempotency_key() -> String {
+    let bytes: [u8; 16] = rand::thread_rng().gen();
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+enum Reservation {
+    Reserved,
+    Replay(StoredResponse),
+    InProgress,
+    /// The key was used for a request with another method, path or body.
+    Mismatch,
+}
+
+/// What identifies a request, for telling whether a reused key is for the
+/// same one.
+struct RequestIdentity {
+    user_name: Option<String>,
+    method: String,
+    path: String,
+    body_hash: String,
+}
+
+async fn reserve(
+    db: &DataBase<impl Storage>,
+    key: &str,
+    request: &RequestIdentity,
+    now: DateTime<Utc>,
+) -> Reservation {
+    let mut reservation = Reservation::Reserved;
+    let result = db
+        .update_data(|mut data_model| {
+            let oldest = now - Duration::hours(IDEMPOTENCY_KEY_RETENTION_HOURS);
+            data_model.idempotency_keys.retain(|r| r.created > oldest);
+            match data_model
+                .idempotency_keys
+                .iter()
+                .find(|r| r.key == key && r.user_name == request.user_name)
+            {
+                Some(record)
+                    if record.method != request.method
+                        || record.path != request.path
+                        || record.body_hash != request.body_hash =>
+                {
+                    reservation = Reservation::Mismatch;
+                }
+                Some(record) => {
+                    reservation = match &record.response {
+                        Some(response) => Reservation::Replay(response.clone()),
+                        None => Reservation::InProgress,
+                    };
+                }
+                None => {
+                    data_model.idempotency_keys.push(IdempotencyRecord {
+                        key: key.to_string(),
+                        user_name: request.user_name.clone(),
+                        method: request.method.clone(),
+                        path: request.path.clone(),
+                        body_hash: request.body_hash.clone(),
+                        created: now,
+                        response: None,
+                    });
+                    let stored = data_model
+                        .idempotency_keys
+                        .iter()
+                        .filter(|r| r.user_name == request.user_name)
+                        .count();
+                    let mut to_remove = stored.saturating_sub(MAX_IDEMPOTENCY_KEYS_PER_REQUESTER);
+                    data_model.idempotency_keys.retain(|r| {
+                        if to_remove > 0 && r.user_name == request.user_name {
+                            to_remove -= 1;
+                            false
+                        } else {
+                            true
+                        }
+                    });
+                }
+            }
+            data_model
+        })
+        .await;
+    if let Err(error) = result {
+        // Better to risk performing the action twice than not at all.
+        log::error!("Failed to store idempotency key: {}", error);
+    }
+    reservation
+}
+
+async fn store(
+    db: &DataBase<impl Storage>,
+    key: &str,
+    user_name: &Option<String>,
+    response: Option<StoredResponse>,
+) {
+    let result = db
+        .update_data(|mut data_model| {
+            let is_record = |r: &IdempotencyRecord| r.key == key && &r.user_name == user_name;
+            match response {
+                Some(response) => {
+                    if let Some(record) = data_model
+                        .idempotency_keys
+                        .iter_mut()
+                        .find(|r| is_record(r))
+                    {
+                        record.response = Some(response);
+                    }
+                }
+                None => data_model.idempotency_keys.retain(|r| !is_record(r)),
+            }
+            data_model
+        })
+        .await;
+    if let Err(error) = result {
+        log::error!("Failed to store response for idempotency key: {}", error);
+    }
+}
+
+/// Headers describing the body, which are set for the replayed body itself.
+fn is_body_header(name: &HeaderName) -> bool {
+    name == header::CONTENT_LENGTH || name == header::TRANSFER_ENCODING
+}
+
+fn replay(stored: StoredResponse) -> Response {
+    let status = StatusCode::from_u16(stored.status).unwrap_or(StatusCode::OK);
+    let mut response = (status, stored.body).into_response();
+    let headers = response.headers_mut();
+    headers.remove(header::CONTENT_TYPE);
+    for (name, value) in stored.headers {
+        if let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(name.as_bytes()),
+            HeaderValue::from_str(&value),
+        ) {
+            if !is_body_header(&name) {
+                headers.append(name, value);
+            }
+        }
+    }
+    headers.insert(IDEMPOTENT_REPLAYED_HEADER, HeaderValue::from_static("true"));
+    response
+}
+
+/// Middleware performing requests that change state at most once per
+/// idempotency key and requester. Requests without a key are passed through.
+pub async fn idempotent<StorageType>(
+    State(db): State<DataBase<StorageType>>,
+    request: Request<Body>,
+    next: Next<Body>,
+) -> Response
+where
+    StorageType: Storage,
+{
+    if request.method() == Method::GET || request.method() == Method::HEAD {
+        return next.run(request).await;
+    }
+    let Some(key) = request
+        .headers()
+        .get(IDEMPOTENCY_KEY_HEADER)
+        .and_then(|key| key.to_str().ok())
+        .map(str::to_string)
+    else {
+        return next.run(request).await;
+    };
+    let user_name = request
+        .extensions()
+        .get::<AuthenticatedUser>()
+        .map(|user| user.0.clone());
+    let (parts, body) = request.into_parts();
+    let body = match hyper::body::to_bytes(body).await {
+        Ok(body) => body,
+        Err(error) => {
+            log::warn!("Failed to read request body: {}", error);
+            return (StatusCode::BAD_REQUEST, "Failed to read the request body").into_response();
+        }
+    };
+    let identity = RequestIdentity {
+        user_name,
+        method: parts.method.to_string(),
+        path: parts.uri.path().to_string(),
+        body_hash: hex::encode(Sha256::digest(&body)),
+    };
+    let request = Request::from_parts(parts, Body::from(body));
+
+    match reserve(&db, &key, &identity, Utc::now()).await {
+        Reservation::Reserved => {}
+        Reservation::Replay(stored) => return replay(stored),
+        Reservation::InProgress => {
+            return (
+                StatusCode::CONFLICT,
+                "A request with this idempotency key is still being processed",
+            )
+                .into_response()
+        }
+        Reservation::Mismatch => {
+            return (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "The idempotency key was already used for a different request",
+            )
+                .into_response()
+        }
+    }
+
+    let response = next.run(request).await;
+    // Server errors may be temporary, so let the client retry those.
+    if response.status().is_server_error() {
+        store(&db, &key, &identity.user_name, None).await;
+        return response;
+    }
+    let (parts, body) = response.into_parts();
+    let body = match hyper::body::to_bytes(body).await {
+        Ok(body) => body,
+        Err(error) => {
+            log::error!("Failed to read response body: {}", error);
+            store(&db, &key, &identity.user_name, None).await;
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    let stored = (body.len() <= MAX_STORED_RESPONSE_BYTES).then(|| StoredResponse {
+        status: parts.status.as_u16(),
+        headers: parts
+            .headers
+            .iter()
+            .filter(|(name, _)| !is_body_header(name))
+            .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+            .collect(),
+        body: body.to_vec(),
+    });
+    store(&db, &key, &identity.user_name, stored).await;
+    Response::from_parts(parts, axum::body::boxed(Full::<Bytes>::new(body)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::database::create_in_memory_database;
+    use axum::{middleware, routing::post, Router};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tower::ServiceExt;
+
+    async fn send(
+        app: Router,
+        uri: &str,
+        key: Option<&str>,
+        user_name: Option<&str>,
+        body: &str,
+    ) -> Response {
+        let mut request = Request::builder().method(Method::POST).uri(uri);
+        if let Some(key) = key {
+            request = request.header(IDEMPOTENCY_KEY_HEADER, key);
+        }
+        if let Some(user_name) = user_name {
+            request = request.extension(AuthenticatedUser(user_name.to_string()));
+        }
+        app.oneshot(request.body(Body::from(body.to_string())).unwrap())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_idempotent() {
+        let db = create_in_memory_database();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let app = {
+            let calls = calls.clone();
+            let count = move || {
+                let calls = calls.clone();
+                async move {
+                    (
+                        [(header::SET_COOKIE, "seen=1")],
+                        format!("call {}", calls.fetch_add(1, Ordering::SeqCst) + 1),
+                    )
+                }
+            };
+            Router::new()
+                .route("/count", post(count.clone()))
+                .route("/other", post(count))
+                .layer(middleware::from_fn_with_state(db.clone(), idempotent))
+        };
+
+        let body = |response: Response| async {
+            let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+            String::from_utf8(body.to_vec()).unwrap()
+        };
+        let first = send(app.clone(), "/count", Some("abc"), Some("anna"), "").await;
+        assert_eq!(body(first).await, "call 1");
+        let second = send(app.clone(), "/count", Some("abc"), Some("anna"), "").await;
+        assert_eq!(second.status(), StatusCode::OK);
+        assert!(second.headers().contains_key(IDEMPOTENT_REPLAYED_HEADER));
+        assert_eq!(second.headers()[header::SET_COOKIE], "seen=1");
+        assert_eq!(body(second).await, "call 1");
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // Without a key, with a new one, or from someone else, the action is
+        // performed.
+        assert_eq!(
+            body(send(app.clone(), "/count", None, Some("anna"), "").await).await,
+            "call 2"
+        );
+        assert_eq!(
+            body(send(app.clone(), "/count", Some("def"), Some("anna"), "").await).await,
+            "call 3"
+        );
+        assert_eq!(
+            body(send(app.clone(), "/count", Some("abc"), None, "").await).await,
+            "call 4"
+        );
+        // A key is for one request.
+        assert_eq!(
+            send(app.clone(), "/other", Some("abc"), Some("anna"), "")
+                .await
+                .status(),
+            StatusCode::UNPROCESSABLE_ENTITY
+        );
+        assert_eq!(
+            send(app.clone(), "/count", Some("abc"), Some("anna"), "{}")
+                .await
+                .status(),
+            StatusCode::UNPROCESSABLE_ENTITY
+        );
+
+        // Expired keys are forgotten.
+        let request = RequestIdentity {
+            user_name: None,
+            method: "POST".to_string(),
+            path: "/count".to_string(),
+            body_hash: hex::encode(Sha256::digest(b"")),
+        };
+        reserve(
+            &db,
+            "ghi",
+            &request,
+            Utc::now() + Duration::hours(IDEMPOTENCY_KEY_RETENTION_HOURS + 1),
+        )
+        .await;
+        let keys: Vec<String> = db
+            .get_data()
+            .await
+            .unwrap()
+            .idempotency_keys
+            .into_iter()
+            .map(|r| r.key)
+            .collect();
+        assert_eq!(keys, vec!["ghi"]);
+        assert_eq!(
+            send(app, "/count", Some("ghi"), None, "").await.status(),
+            StatusCode::CONFLICT
+        );
+
+        // The oldest keys of a requester are forgotten beyond the limit.
+        for key in 0..MAX_IDEMPOTENCY_KEYS_PER_REQUESTER + 1 {
+            reserve(&db, &key.to_string(), &request, Utc::now()).await;
+        }
+        let keys = db.get_data().await.unwrap().idempotency_keys;
+        assert_eq!(keys.len(), MAX_IDEMPOTENCY_KEYS_PER_REQUESTER);
+        assert_eq!(keys[0].key, "1");
+    }
+}
@@ -1,11 +1,95 @@
+use crate::bookings::{current_booking, next_free_slot};
+use crate::database::{DataBase, Storage};
+use crate::telescope::TelescopeCollection;
 use crate::template::HtmlTemplate;
 use askama::Template;
-use axum::response::IntoResponse;
+use axum::{extract::State, response::IntoResponse, routing::get, Router};
+use chrono::Utc;
+
+/// State of the index routes, which show the bookings and the telescopes.
+#[derive(Clone)]
+pub struct IndexState<StorageType: Storage> {
+    pub database: DataBase<StorageType>,
+    pub telescopes: TelescopeCollection,
+}
+
+pub fn routes(
+    telescopes: TelescopeCollection,
+    database: DataBase<impl Storage + 'static>,
+) -> Router {
+    Router::new()
+        .route("/", get(get_index))
+        .route("/telescope_status", get(get_telescope_status))
+        .with_state(IndexState {
+            database,
+            telescopes,
+        })
+}
 
 #[derive(Template)]
 #[template(path = "index.html")]
 struct IndexTemplate {}
 
-pub async fn get_index() -> impl IntoResponse {
+async fn get_index() -> impl IntoResponse {
     HtmlTemplate(IndexTemplate {})
 }
+
+struct TelescopeAvailability {
+    name: String,
+    /// None if the telescope could not be reached.
+    status: Option<String>,
+    /// Name of the user observing now, if it is booked.
+    observer: Option<String>,
+    /// None if the telescope is free now.
+    next_free: Option<String>,
+}
+
+#[derive(Template)]
+#[template(path = "telescope_status.html")]
+struct TelescopeStatusTemplate {
+    telescopes: Vec<TelescopeAvailability>,
+}
+
+/// Status of every telescope together with who is observing and when it is
+/// free, for the welcome page.
+async fn get_telescope_status<StorageType>(
+    State(state): State<IndexState<StorageType>>,
+) -> impl IntoResponse
+where
+    StorageType: Storage,
+{
+    let data_model = state
+        .database
+        .get_data()
+        .await
+        .expect("As long as no one is manually editing the database, this should never fail.");
+    let now = Utc::now();
+    let mut telescopes = Vec::new();
+    for definition in data_model.telescopes.iter().filter(|t| t.enabled) {
+        let telescope = state
+            .telescopes
+            .read()
+            .await
+            .get(&definition.name)
+            .map(|t| t.telescope.clone());
+        let status = match telescope {
+            Some(telescope) => telescope
+                .lock()
+                .await
+                .get_info()
+                .await
+                .ok()
+                .map(|info| format!("{:?}", info.status)),
+            None => None,
+        };
+        let next_free = next_free_slot(&data_model.bookings, &definition.name, now);
+        telescopes.push(TelescopeAvailability {
+            name: definition.name.clone(),
+            status,
+            observer: current_booking(&data_model.bookings, &definition.name, now)
+                .map(|b| b.user_name.clone()),
+            next_free: (next_free > now).then(|| next_free.format("%Y-%m-%d %H:%M").to_string()),
+        });
+    }
+    HtmlTemplate(TelescopeStatusTemplate { telescopes })
+}
@@ -0,0 +1,266 @@
+//! Interlock keeping co-located dishes from clashing.
+//!
+//! A collision zone names two telescopes and, for each of them, a region
+//! of horizontal directions. The dishes may clash if both point into their
+//! regions at the same time, so a telescope is not allowed to move into its
+//! region while the other one is in, or heading into, its own. A dish
+//! passes through every direction between where it points and where it is
+//! heading, so the whole sweep of a slew is checked, and a telescope
+//! reserves its sweep in the same step as it is checked, so two telescopes
+//! can not both be let into their regions at once. Targets that would clash
+//! are rejected, and a telescope already tracking holds its position until
+//! the other one has left the zone.
+
+use crate::angles::Radians;
+use crate::coords::Direction;
+use crate::telescopes::TelescopeError;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::sync::{Arc, Mutex};
+
+/// Directions with azimuth and altitude within the given ranges, both
+/// inclusive. The azimuth range wraps past north if `min_azimuth` is larger
+/// than `max_azimuth`.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct HorizontalRegion {
+    pub min_azimuth: Radians,
+    pub max_azimuth: Radians,
+    pub min_altitude: Radians,
+    pub max_altitude: Radians,
+}
+
+impl HorizontalRegion {
+    /// Whether any direction of `sweep` is in the region.
+    pub fn intersects(&self, sweep: &Sweep) -> bool {
+        let min_azimuth = self.min_azimuth.normalized();
+        let max_azimuth = self.max_azimuth.normalized();
+        let overlaps =
+            |min: Radians, max: Radians| min <= sweep.max_azimuth && sweep.min_azimuth <= max;
+        let azimuth_inside = if min_azimuth <= max_azimuth {
+            overlaps(min_azimuth, max_azimuth)
+        } else {
+            overlaps(min_azimuth, Radians(2.0 * PI)) || overlaps(Radians(0.0), max_azimuth)
+        };
+        azimuth_inside
+            && self.min_altitude <= sweep.max_altitude
+            && sweep.min_altitude <= self.max_altitude
+    }
+}
+
+/// The directions a dish passes through when slewing between two
+/// directions. Both axes may move at the same time, so this is every
+/// direction within the azimuth and altitude ranges between them. The
+/// azimuth axis does not wrap around, so a slew never passes north.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct Sweep {
+    min_azimuth: Radians,
+    max_azimuth: Radians,
+    min_altitude: Radians,
+    max_altitude: Radians,
+}
+
+impl Sweep {
+    pub fn between(from: Direction, to: Direction) -> Sweep {
+        let (from_azimuth, to_azimuth) = (from.azimuth.normalized(), to.azimuth.normalized());
+        Sweep {
+            min_azimuth: Radians(from_azimuth.0.min(to_azimuth.0)),
+            max_azimuth: Radians(from_azimuth.0.max(to_azimuth.0)),
+            min_altitude: Radians(from.altitude.0.min(to.altitude.0)),
+            max_altitude: Radians(from.altitude.0.max(to.altitude.0)),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct CollisionZone {
+    pub first_telescope: String,
+    pub first_region: HorizontalRegion,
+    pub second_telescope: String,
+    pub second_region: HorizontalRegion,
+}
+
+impl CollisionZone {
+    /// The region of `telescope` and the other telescope with its region,
+    /// if `telescope` is part of the zone.
+    fn regions(&self, telescope: &str) -> Option<(&HorizontalRegion, &str, &HorizontalRegion)> {
+        if self.first_telescope == telescope {
+            Some((
+                &self.first_region,
+                &self.second_telescope,
+                &self.second_region,
+            ))
+        } else if self.second_telescope == telescope {
+            Some((
+                &self.second_region,
+                &self.first_telescope,
+                &self.first_region,
+            ))
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Default)]
+struct InterlockState {
+    zones: Vec<CollisionZone>,
+    /// Directions each telescope points at or passes through on its way to
+    /// where it is heading.
+    occupied: HashMap<String, Sweep>,
+}
+
+impl InterlockState {
+    fn check(&self, telescope: &str, sweep: &Sweep) -> Result<(), TelescopeError> {
+        for zone in &self.zones {
+            let Some((region, other, other_region)) = zone.regions(telescope) else {
+                continue;
+            };
+            if !region.intersects(sweep) {
+                continue;
+            }
+            let other_inside = self
+                .occupied
+                .get(other)
+                .is_some_and(|other_sweep| other_region.intersects(other_sweep));
+            if other_inside {
+                return Err(TelescopeError::CollisionRisk(other.to_string()));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Shared by the trackers of all telescopes, which report where they are and
+/// ask before moving.
+#[derive(Clone, Default)]
+pub struct CollisionInterlock {
+    state: Arc<Mutex<InterlockState>>,
+}
+
+impl CollisionInterlock {
+    pub fn new(zones: Vec<CollisionZone>) -> CollisionInterlock {
+        CollisionInterlock {
+            state: Arc::new(Mutex::new(InterlockState {
+                zones,
+                occupied: HashMap::new(),
+            })),
+        }
+    }
+
+    /// Record the current direction of `telescope` and the direction it has
+    /// been commanded to, if any.
+    pub fn update(&self, telescope: &str, current: Direction, commanded: Option<Direction>) {
+        let sweep = Sweep::between(current, commanded.unwrap_or(current));
+        self.state
+            .lock()
+            .unwrap()
+            .occupied
+            .insert(telescope.to_string(), sweep);
+    }
+
+    /// Check that `telescope` may make `sweep`, without reserving it.
+    pub fn check(&self, telescope: &str, sweep: Sweep) -> Result<(), TelescopeError> {
+        self.state.lock().unwrap().check(telescope, &sweep)
+    }
+
+    /// Check that `telescope` may make `sweep` and, if so, reserve it before
+    /// any other telescope is checked, so they can't both move into the
+    /// zone. The telescope has to be commanded to make the sweep afterwards.
+    pub fn reserve(&self, telescope: &str, sweep: Sweep) -> Result<(), TelescopeError> {
+        let mut state = self.state.lock().unwrap();
+        state.check(telescope, &sweep)?;
+        state.occupied.insert(telescope.to_string(), sweep);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::angles::Degrees;
+
+    fn direction(azimuth: f64, altitude: f64) -> Direction {
+        Direction {
+            azimuth: Degrees(azimuth).to_radians(),
+            altitude: Degrees(altitude).to_radians(),
+        }
+    }
+
+    fn at(azimuth: f64, altitude: f64) -> Sweep {
+        Sweep::between(direction(azimuth, altitude), direction(azimuth, altitude))
+    }
+
+    fn region(min_azimuth: f64, max_azimuth: f64, max_altitude: f64) -> HorizontalRegion {
+        HorizontalRegion {
+            min_azimuth: Degrees(min_azimuth).to_radians(),
+            max_azimuth: Degrees(max_azimuth).to_radians(),
+            min_altitude: Radians(0.0),
+            max_altitude: Degrees(max_altitude).to_radians(),
+        }
+    }
+
+    #[test]
+    fn test_collision_interlock() {
+        let interlock = CollisionInterlock::new(vec![CollisionZone {
+            first_telescope: "brage".to_string(),
+            first_region: region(80.0, 100.0, 30.0),
+            second_telescope: "vale".to_string(),
+            // Wraps past north.
+            second_region: region(260.0, 10.0, 30.0),
+        }]);
+        assert!(region(260.0, 10.0, 30.0).intersects(&at(5.0, 20.0)));
+        assert!(!region(260.0, 10.0, 30.0).intersects(&at(100.0, 20.0)));
+
+        let collision = Err(TelescopeError::CollisionRisk("vale".to_string()));
+
+        // Nothing known about vale yet.
+        assert_eq!(interlock.check("brage", at(90.0, 20.0)), Ok(()));
+
+        interlock.update("vale", direction(180.0, 45.0), Some(direction(270.0, 20.0)));
+        assert_eq!(interlock.check("brage", at(90.0, 20.0)), collision);
+        // Above the zone, or outside it, brage may move.
+        assert_eq!(interlock.check("brage", at(90.0, 40.0)), Ok(()));
+        assert_eq!(interlock.check("brage", at(120.0, 20.0)), Ok(()));
+        // Telescopes outside the zone are not affected.
+        assert_eq!(interlock.check("fake", at(90.0, 20.0)), Ok(()));
+
+        interlock.update("vale", direction(180.0, 45.0), None);
+        assert_eq!(interlock.check("brage", at(90.0, 20.0)), Ok(()));
+
+        // Slewing between two directions outside the region still passes
+        // through it.
+        interlock.update("vale", direction(300.0, 20.0), None);
+        let through_zone = Sweep::between(direction(60.0, 20.0), direction(120.0, 20.0));
+        assert_eq!(interlock.check("brage", through_zone), collision);
+        // A slew of vale through its region counts as being in it.
+        interlock.update("vale", direction(200.0, 20.0), Some(direction(350.0, 40.0)));
+        assert_eq!(interlock.check("brage", at(90.0, 20.0)), collision);
+        // The azimuth axis doesn't wrap, so this slew goes the long way round
+        // through south, outside the region wrapping past north.
+        interlock.update("vale", direction(250.0, 20.0), Some(direction(20.0, 20.0)));
+        assert_eq!(interlock.check("brage", at(90.0, 20.0)), Ok(()));
+    }
+
+    #[test]
+    fn test_reserve() {
+        let interlock = CollisionInterlock::new(vec![CollisionZone {
+            first_telescope: "brage".to_string(),
+            first_region: region(80.0, 100.0, 30.0),
+            second_telescope: "vale".to_string(),
+            second_region: region(260.0, 280.0, 30.0),
+        }]);
+        interlock.update("brage", direction(180.0, 45.0), None);
+        interlock.update("vale", direction(180.0, 45.0), None);
+        // Both are outside the zone, but only the first to reserve its way
+        // in may go.
+        let brage_in = Sweep::between(direction(180.0, 45.0), direction(90.0, 20.0));
+        let vale_in = Sweep::between(direction(180.0, 45.0), direction(270.0, 20.0));
+        assert_eq!(interlock.check("vale", vale_in), Ok(()));
+        assert_eq!(interlock.reserve("brage", brage_in), Ok(()));
+        assert_eq!(
+            interlock.reserve("vale", vale_in),
+            Err(TelescopeError::CollisionRisk("brage".to_string()))
+        );
+    }
+}
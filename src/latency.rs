@@ -0,0 +1,135 @@
+//! Round trip latency of the link to a telescope controller.
+//!
+//! Slow serial to ethernet bridges make tracking jerky without any error
+//! showing up, so the time each direction request takes is recorded and
+//! summarized over the last hour. A warning is logged when the link gets
+//! slow, which is how admins learn about it.
+
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Latencies older than this are forgotten.
+pub const LATENCY_WINDOW: Duration = Duration::from_secs(3600);
+/// At most one latency is kept per interval, the tracker asks for the
+/// direction much more often than that.
+pub const LATENCY_SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+/// The link is considered degraded when the 95th percentile is above this.
+pub const DEGRADED_LATENCY: Duration = Duration::from_millis(200);
+/// Percentiles of fewer latencies than this say little.
+const MIN_SAMPLES: usize = 30;
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct LatencyStatistics {
+    pub samples: usize,
+    pub p50: Duration,
+    pub p95: Duration,
+    /// Standard deviation of the latencies.
+    pub jitter: Duration,
+    pub degraded: bool,
+}
+
+#[derive(Default)]
+pub struct LatencyMonitor {
+    samples: VecDeque<(Instant, Duration)>,
+    degraded: bool,
+}
+
+impl LatencyMonitor {
+    /// Record a round trip that took `latency` and finished at `now`. Returns
+    /// true if this made the link degraded.
+    pub fn record(&mut self, now: Instant, latency: Duration) -> bool {
+        if let Some((last, _)) = self.samples.back() {
+            if now.duration_since(*last) < LATENCY_SAMPLE_INTERVAL {
+                return false;
+            }
+        }
+        self.samples.push_back((now, latency));
+        while let Some((time, _)) = self.samples.front() {
+            if now.duration_since(*time) <= LATENCY_WINDOW {
+                break;
+            }
+            self.samples.pop_front();
+        }
+
+        let Some(statistics) = self.statistics() else {
+            return false;
+        };
+        let was_degraded = self.degraded;
+        // Only recover well below the limit to avoid flapping.
+        if statistics.p95 > DEGRADED_LATENCY {
+            self.degraded = true;
+        } else if statistics.p95 < DEGRADED_LATENCY / 2 {
+            self.degraded = false;
+        }
+        self.degraded && !was_degraded
+    }
+
+    pub fn statistics(&self) -> Option<LatencyStatistics> {
+        if self.samples.len() < MIN_SAMPLES {
+            return None;
+        }
+        let mut latencies: Vec<Duration> = self.samples.iter().map(|(_, l)| *l).collect();
+        latencies.sort();
+        let percentile = |p: f64| latencies[((latencies.len() - 1) as f64 * p).round() as usize];
+        let seconds: Vec<f64> = latencies.iter().map(Duration::as_secs_f64).collect();
+        let mean = seconds.iter().sum::<f64>() / seconds.len() as f64;
+        let variance =
+            seconds.iter().map(|s| (s - mean).powi(2)).sum::<f64>() / seconds.len() as f64;
+        Some(LatencyStatistics {
+            samples: latencies.len(),
+            p50: percentile(0.5),
+            p95: percentile(0.95),
+            jitter: Duration::from_secs_f64(variance.sqrt()),
+            degraded: self.degraded,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_latency_monitor() {
+        let mut monitor = LatencyMonitor::default();
+        let start = Instant::now();
+        let at = |seconds: u64| start + Duration::from_secs(seconds);
+        let ms = Duration::from_millis;
+
+        for i in 0..MIN_SAMPLES as u64 - 1 {
+            assert!(!monitor.record(at(i), ms(20)));
+        }
+        assert_eq!(monitor.statistics(), None);
+        // Too soon after the previous one to be kept.
+        monitor.record(at(MIN_SAMPLES as u64 - 2) + ms(100), ms(20));
+        assert_eq!(monitor.statistics(), None);
+        monitor.record(at(MIN_SAMPLES as u64 - 1), ms(20));
+        let statistics = monitor.statistics().unwrap();
+        assert_eq!(statistics.samples, MIN_SAMPLES);
+        assert_eq!(statistics.p50, ms(20));
+        assert_eq!(statistics.jitter, Duration::ZERO);
+        assert!(!statistics.degraded);
+
+        // A slow stretch degrades the link, which is only reported once.
+        let mut alerts = 0;
+        for i in 100..130 {
+            if monitor.record(at(i), ms(500)) {
+                alerts += 1;
+            }
+        }
+        assert_eq!(alerts, 1);
+        let statistics = monitor.statistics().unwrap();
+        assert!(statistics.degraded);
+        assert_eq!(statistics.p95, ms(500));
+
+        // An hour later the slow stretch is forgotten.
+        for i in 3800..3800 + MIN_SAMPLES as u64 {
+            monitor.record(at(i), ms(20));
+        }
+        let statistics = monitor.statistics().unwrap();
+        assert_eq!(statistics.samples, MIN_SAMPLES);
+        assert!(!statistics.degraded);
+    }
+}
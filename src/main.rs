@@ -1,26 +1,75 @@
-use axum::{routing::get, Router};
+use axum::{middleware, routing::get, Router};
 use axum_server::tls_rustls::RustlsConfig;
 use clap::Parser;
-use database::create_database_from_directory;
+use database::{create_database_from_directory, DataBase, Storage};
 use std::net::SocketAddr;
-use telescope::create_telescope_collection;
+use std::time::Duration;
+use telescope::{create_telescope_collection, shutdown_telescopes, TelescopeCollection};
+use tokio_util::sync::CancellationToken;
 use tower_http::services::ServeDir;
 
+mod analysis;
+mod angles;
+mod archive;
+mod assignments;
+mod authentication;
+mod authorization;
+mod auxiliary;
 mod bookings;
+mod calibration;
+mod citations;
+mod clock;
+mod command_throttle;
+mod config;
+mod confirmation;
+mod connection_health;
+mod console;
+mod constants;
 mod coords;
 mod database;
+mod dev_api_routes;
 mod fake_telescope;
+mod faults;
+mod format;
+mod hooks;
+mod idempotency;
 mod index;
+mod interlock;
+mod latency;
+mod observation_log;
+mod orbit;
+mod park_policies;
+mod plot;
+mod pointing;
+mod rfi;
+mod rot2prog;
 mod salsa_telescope;
+mod scheduler;
+mod search;
+mod sessions;
+mod shift_log;
+mod statistics;
+mod supervisor;
+mod telemetry;
 mod telescope;
 mod telescope_api_routes;
 mod telescope_controller;
 mod telescope_routes;
 mod telescope_tracker;
+mod telescope_updates;
 mod telescopes;
 mod template;
+#[cfg(test)]
+mod test_utils;
+mod trash;
+mod ups;
+mod users;
 mod weather;
 
+const DATABASE_FILE: &str = "database.json";
+/// Time given to requests in progress when shutting down.
+const SERVER_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
@@ -30,6 +79,25 @@ struct Args {
     #[arg(short, long, env = "CERT_FILE_PATH")]
     cert_file_path: Option<String>,
     s: Option<String>,
+
+    /// Serve /api/dev for controlling the simulated conditions of fake
+    /// telescopes. Never use this in production.
+    #[arg(long)]
+    dev: bool,
+
+    /// Validate the configuration in the database file and exit.
+    #[arg(long)]
+    check_config: bool,
+
+    /// Print a new API token for the given user and exit, e.g. for the
+    /// first admin, who has no one to hand them a token.
+    #[arg(long, value_name = "USER")]
+    issue_token: Option<String>,
+
+    /// Print a new confirmation secret for the given admin and exit. Admins
+    /// give it to confirm destructive actions.
+    #[arg(long, value_name = "ADMIN")]
+    issue_confirmation_secret: Option<String>,
 }
 
 #[tokio::main]
@@ -38,26 +106,121 @@ async fn main() {
 
     let args = Args::parse();
 
-    let database = create_database_from_directory("database.json")
+    let configuration = match config::load_configuration(DATABASE_FILE).await {
+        Ok(configuration) => configuration,
+        Err(error) => {
+            eprintln!("Invalid configuration: {}", error);
+            std::process::exit(1);
+        }
+    };
+    if args.check_config {
+        println!("{} is valid", DATABASE_FILE);
+        return;
+    }
+
+    coords::set_dut1(configuration.dut1);
+
+    let database = create_database_from_directory(DATABASE_FILE)
         .await
         .expect("failed to create database");
 
+    if let Some(user_name) = args.issue_token {
+        match authentication::issue_api_token(&database, &user_name, chrono::Utc::now()).await {
+            Ok(issued) => println!("{}", issued.token),
+            Err(error) => {
+                eprintln!("Failed to issue token for {}: {:?}", user_name, error);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    if let Some(user_name) = args.issue_confirmation_secret {
+        match confirmation::issue_confirmation_secret(&database, &user_name, chrono::Utc::now())
+            .await
+        {
+            Ok(secret) => println!("{}", secret),
+            Err(error) => {
+                eprintln!(
+                    "Failed to issue confirmation secret for {}: {:?}",
+                    user_name, error
+                );
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    match archive::checkpoints::recover_interrupted_measurements(&database).await {
+        Ok(ids) if !ids.is_empty() => log::warn!("Recovered interrupted measurements {:?}", ids),
+        Ok(_) => {}
+        Err(error) => log::error!("Failed to recover interrupted measurements: {}", error),
+    }
+
+    let interrupted_sessions = sessions::interrupted_sessions(
+        &database.get_data().await.expect("failed to read database"),
+        chrono::Utc::now(),
+    );
+
     let telescopes = create_telescope_collection(&database)
         .await
         .expect("failed to create telescopes");
+    sessions::resume_interrupted_sessions(&database, &telescopes, interrupted_sessions).await;
+
+    users::start_account_cleanup_service(database.clone());
+    trash::start_trash_purge_service(database.clone());
+    archive::files::start_file_maintenance_service(database.clone());
+    park_policies::start_park_policy_service(database.clone(), telescopes.clone());
+    sessions::expiry::start_session_expiry_service(database.clone(), telescopes.clone());
+    scheduler::jobs::start_scheduled_observation_service(database.clone(), telescopes.clone());
+    if let Some(ups) = database
+        .get_data()
+        .await
+        .expect("failed to read database")
+        .ups
+    {
+        ups::start_ups_monitor_service(database.clone(), telescopes.clone(), ups);
+    }
+    if let Some(clock_check) = database
+        .get_data()
+        .await
+        .expect("failed to read database")
+        .clock_check
+    {
+        clock::start_clock_check_service(database.clone(), clock_check);
+    }
+
+    // Cancelled on SIGINT. The server stops taking requests, and the
+    // telescopes are shut down once it has stopped.
+    let shutdown = CancellationToken::new();
+    {
+        let shutdown = shutdown.clone();
+        tokio::spawn(async move {
+            if let Err(error) = tokio::signal::ctrl_c().await {
+                log::error!("Failed to listen for shutdown signal: {}", error);
+                return;
+            }
+            log::info!("Shutting down");
+            shutdown.cancel();
+        });
+    }
+    let server_handle = axum_server::Handle::new();
+    {
+        let server_handle = server_handle.clone();
+        let shutdown = shutdown.clone();
+        tokio::spawn(async move {
+            shutdown.cancelled().await;
+            server_handle.graceful_shutdown(Some(SERVER_SHUTDOWN_TIMEOUT));
+        });
+    }
 
     let addr = SocketAddr::from(([0, 0, 0, 0], 3000));
 
-    let mut app = Router::new()
-        .route("/", get(index::get_index))
-        .route("/weather", get(weather::get_weather_info))
-        .nest("/bookings", bookings::routes::routes(database.clone()))
-        .nest("/telescopes", telescope_routes::routes(telescopes.clone()))
-        .nest("/api/telescopes", telescope_api_routes::routes(telescopes))
-        .nest(
-            "/api/bookings",
-            bookings::api_routes::routes(database.clone()),
-        );
+    let mut app = create_router(telescopes.clone(), database.clone());
+
+    if args.dev {
+        log::warn!("Running in dev mode, simulated conditions can be changed through /api/dev");
+        app = app.nest("/api/dev", dev_api_routes::routes(telescopes.clone()));
+    }
 
     let assets_path = "assets";
     log::info!("serving asserts from {}", assets_path);
@@ -76,13 +239,168 @@ async fn main() {
             .await
             .unwrap();
         axum_server::bind_rustls(addr, tls)
+            .handle(server_handle)
             .serve(app.into_make_service())
             .await
             .unwrap();
     } else {
         axum_server::bind(addr)
+            .handle(server_handle)
             .serve(app.into_make_service())
             .await
             .unwrap();
     }
+
+    shutdown_telescopes(&telescopes, &database).await;
+    if let Err(error) = database.flush().await {
+        log::error!("Failed to flush database: {}", error);
+    }
+    log::info!("Shut down");
+}
+
+/// All routes of the backend, each guarded by its authorization policy for
+/// the user authenticated by their API token. Idempotency keys are only
+/// looked at for requests that are allowed.
+fn create_router<StorageType>(
+    telescopes: TelescopeCollection,
+    database: DataBase<StorageType>,
+) -> Router
+where
+    StorageType: Storage + 'static,
+{
+    let analysis_cache = analysis::AnalysisCache::default();
+    Router::new()
+        .merge(index::routes(telescopes.clone(), database.clone()))
+        .merge(authentication::routes::routes(database.clone()))
+        .route("/weather", get(weather::get_weather_info))
+        .nest("/bookings", bookings::routes::routes(database.clone()))
+        .nest("/archive", archive::routes::routes(database.clone()))
+        .nest("/citations", citations::routes::routes(database.clone()))
+        .nest("/rfi", rfi::routes::routes(database.clone()))
+        .nest("/sessions", sessions::routes::routes(database.clone()))
+        .nest("/users", users::routes::routes(database.clone()))
+        .nest("/trash", trash::routes::routes(database.clone()))
+        .nest("/shift_log", shift_log::routes::routes(database.clone()))
+        .nest(
+            "/observation_log",
+            observation_log::routes::routes(database.clone()),
+        )
+        .nest(
+            "/faults",
+            faults::routes::routes(telescopes.clone(), database.clone()),
+        )
+        .nest(
+            "/confirmations",
+            confirmation::routes::routes(database.clone()),
+        )
+        .nest(
+            "/console",
+            console::routes::routes(telescopes.clone(), database.clone()),
+        )
+        .nest("/search", search::routes::routes(database.clone()))
+        .nest("/statistics", statistics::routes::routes(database.clone()))
+        .nest(
+            "/analysis",
+            analysis::routes::routes(database.clone(), analysis_cache.clone()),
+        )
+        .nest("/ups", ups::routes::routes(database.clone()))
+        .nest("/clock", clock::routes::routes(database.clone()))
+        .nest(
+            "/assignments",
+            assignments::routes::routes(database.clone()),
+        )
+        .nest(
+            "/telescopes",
+            telescope_routes::routes(telescopes.clone(), database.clone()),
+        )
+        .nest(
+            "/api/telescopes",
+            telescope_api_routes::routes(telescopes.clone(), database.clone()),
+        )
+        .nest(
+            "/api/bookings",
+            bookings::api_routes::routes(database.clone()),
+        )
+        .nest(
+            "/api/archive",
+            archive::api_routes::routes(database.clone()),
+        )
+        .nest(
+            "/api/citations",
+            citations::api_routes::routes(database.clone()),
+        )
+        .nest("/api/rfi", rfi::api_routes::routes(database.clone()))
+        .nest(
+            "/api/sessions",
+            sessions::api_routes::routes(database.clone()),
+        )
+        .nest("/api/users", users::api_routes::routes(database.clone()))
+        .nest("/api/trash", trash::api_routes::routes(database.clone()))
+        .nest(
+            "/api/faults",
+            faults::api_routes::routes(telescopes.clone(), database.clone()),
+        )
+        .nest(
+            "/api/confirmations",
+            confirmation::api_routes::routes(database.clone()),
+        )
+        .nest(
+            "/api/calibration",
+            calibration::api_routes::routes(telescopes.clone(), database.clone()),
+        )
+        .nest(
+            "/api/console",
+            console::api_routes::routes(telescopes.clone(), database.clone()),
+        )
+        .nest(
+            "/api/park_policies",
+            park_policies::api_routes::routes(database.clone()),
+        )
+        .nest(
+            "/api/pointing",
+            pointing::api_routes::routes(telescopes.clone(), database.clone()),
+        )
+        .nest(
+            "/api/scheduler",
+            scheduler::api_routes::routes(telescopes.clone(), database.clone()),
+        )
+        .nest(
+            "/api/shift_log",
+            shift_log::api_routes::routes(database.clone()),
+        )
+        .nest(
+            "/api/observation_log",
+            observation_log::api_routes::routes(database.clone()),
+        )
+        .nest("/api/search", search::api_routes::routes(database.clone()))
+        .nest(
+            "/api/statistics",
+            statistics::api_routes::routes(database.clone()),
+        )
+        .nest(
+            "/api/analysis",
+            analysis::api_routes::routes(database.clone(), analysis_cache),
+        )
+        .nest("/api/ups", ups::api_routes::routes(database.clone()))
+        .nest("/api/clock", clock::api_routes::routes(database.clone()))
+        .nest(
+            "/api/assignments",
+            assignments::api_routes::routes(database.clone()),
+        )
+        .nest(
+            "/api/spectral_lines",
+            constants::api_routes::routes(database.clone()),
+        )
+        .route_layer(middleware::from_fn_with_state(
+            database.clone(),
+            idempotency::idempotent,
+        ))
+        .route_layer(middleware::from_fn_with_state(
+            database.clone(),
+            authorization::authorize,
+        ))
+        .route_layer(middleware::from_fn_with_state(
+            database,
+            authentication::authenticate,
+        ))
 }
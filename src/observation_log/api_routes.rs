@@ -0,0 +1,41 @@
+use crate::database::{DataBase, Storage};
+use crate::observation_log::{telescope_log, ObservationLogEntry};
+use axum::{
+    extract::{Json, Path, State},
+    routing::get,
+    Router,
+};
+
+pub fn routes(database: DataBase<impl Storage + 'static>) -> Router {
+    Router::new()
+        .route("/", get(get_observation_log))
+        .route("/:telescope_name", get(get_telescope_log))
+        .with_state(database)
+}
+
+async fn get_observation_log(
+    State(db): State<DataBase<impl Storage>>,
+) -> Json<Vec<ObservationLogEntry>> {
+    Json(
+        db.get_data()
+            .await
+            .expect("As long as no one is manually editing the database, this should never fail.")
+            .observation_log,
+    )
+}
+
+async fn get_telescope_log(
+    State(db): State<DataBase<impl Storage>>,
+    Path(telescope_name): Path<String>,
+) -> Json<Vec<ObservationLogEntry>> {
+    let data_model = db
+        .get_data()
+        .await
+        .expect("As long as no one is manually editing the database, this should never fail.");
+    Json(
+        telescope_log(&data_model, &telescope_name)
+            .into_iter()
+            .cloned()
+            .collect(),
+    )
+}
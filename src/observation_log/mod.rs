@@ -0,0 +1,200 @@
+//! Observation log of the commands sent to each telescope.
+//!
+//! Every change of target, start and stop of an integration and restart of
+//! a telescope is recorded with the user who sent it and the error if it
+//! failed. When the hardware misbehaves, the log shows what the telescope
+//! was told to do leading up to it, and it shows who was using the
+//! instrument if it was misused.
+
+use crate::database::{DataBase, DataModel, Storage};
+use crate::telescopes::{ReceiverConfiguration, TelescopeTarget};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+
+pub mod api_routes;
+pub mod routes;
+
+/// The oldest entries of a telescope are dropped beyond this many.
+pub const MAX_OBSERVATION_LOG_ENTRIES_PER_TELESCOPE: usize = 10000;
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub enum ObservationCommand {
+    SetTarget(TelescopeTarget),
+    StartReceiver,
+    StopReceiver,
+    Restart,
+}
+
+impl ObservationCommand {
+    /// The command sent by setting `configuration` on a receiver that is
+    /// `integrating` or not, if it starts or stops an integration.
+    pub fn receiver(integrating: bool, configuration: &ReceiverConfiguration) -> Option<Self> {
+        match (integrating, configuration.integrate) {
+            (false, true) => Some(ObservationCommand::StartReceiver),
+            (true, false) => Some(ObservationCommand::StopReceiver),
+            _ => None,
+        }
+    }
+
+    pub fn description(&self) -> String {
+        match self {
+            ObservationCommand::SetTarget(target) => format!("Set target to {}", target),
+            ObservationCommand::StartReceiver => "Start integration".to_string(),
+            ObservationCommand::StopReceiver => "Stop integration".to_string(),
+            ObservationCommand::Restart => "Restart".to_string(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct ObservationLogEntry {
+    pub time: DateTime<Utc>,
+    pub telescope_name: String,
+    /// Who sent the command, none for commands sent by the backend itself,
+    /// e.g. parking the telescope when a booking ends.
+    pub user_name: Option<String>,
+    pub command: ObservationCommand,
+    /// Why the command failed, if it did.
+    pub error: Option<String>,
+}
+
+/// Entries of `telescope_name`, newest first.
+pub fn telescope_log<'a>(
+    data_model: &'a DataModel,
+    telescope_name: &str,
+) -> Vec<&'a ObservationLogEntry> {
+    data_model
+        .observation_log
+        .iter()
+        .rev()
+        .filter(|e| e.telescope_name == telescope_name)
+        .collect()
+}
+
+/// Record that `command` was sent to `telescope_name` by `user_name` and
+/// had `result`. A command that went through is not undone if it can't be
+/// recorded, so failing to store the entry is only logged.
+pub async fn record_command<T, E: Display>(
+    db: &DataBase<impl Storage>,
+    telescope_name: &str,
+    user_name: Option<String>,
+    command: ObservationCommand,
+    result: &Result<T, E>,
+    now: DateTime<Utc>,
+) {
+    let entry = ObservationLogEntry {
+        time: now,
+        telescope_name: telescope_name.to_string(),
+        user_name,
+        command,
+        error: result.as_ref().err().map(|error| error.to_string()),
+    };
+    let stored = db
+        .update_data(|mut data_model| {
+            let stored = data_model
+                .observation_log
+                .iter()
+                .filter(|e| e.telescope_name == entry.telescope_name)
+                .count();
+            let mut to_remove =
+                (stored + 1).saturating_sub(MAX_OBSERVATION_LOG_ENTRIES_PER_TELESCOPE);
+            data_model.observation_log.retain(|e| {
+                if to_remove > 0 && e.telescope_name == entry.telescope_name {
+                    to_remove -= 1;
+                    false
+                } else {
+                    true
+                }
+            });
+            data_model.observation_log.push(entry.clone());
+            data_model
+        })
+        .await;
+    if let Err(error) = stored {
+        log::error!(
+            "Failed to record {:?} in the observation log: {}",
+            entry,
+            error
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::angles::Radians;
+    use crate::database::create_in_memory_database;
+    use crate::telescopes::TelescopeError;
+    use chrono::TimeZone;
+
+    #[tokio::test]
+    async fn test_record_command() {
+        let db = create_in_memory_database();
+        let at = |second| Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, second).unwrap();
+        let target = TelescopeTarget::Galactic {
+            l: Radians(1.0),
+            b: Radians(0.0),
+        };
+        record_command(
+            &db,
+            "fake",
+            Some("anna".to_string()),
+            ObservationCommand::SetTarget(target),
+            &Ok::<_, TelescopeError>(target),
+            at(0),
+        )
+        .await;
+        record_command(
+            &db,
+            "other",
+            None,
+            ObservationCommand::Restart,
+            &Ok::<_, TelescopeError>(()),
+            at(1),
+        )
+        .await;
+        record_command(
+            &db,
+            "fake",
+            Some("bertil".to_string()),
+            ObservationCommand::Restart,
+            &Err::<(), _>(TelescopeError::TelescopeNotConnected),
+            at(2),
+        )
+        .await;
+
+        let data_model = db.get_data().await.unwrap();
+        let log = telescope_log(&data_model, "fake");
+        assert_eq!(log.len(), 2);
+        assert_eq!(log[0].time, at(2));
+        assert_eq!(log[0].user_name, Some("bertil".to_string()));
+        assert_eq!(
+            log[0].error,
+            Some(TelescopeError::TelescopeNotConnected.to_string())
+        );
+        assert_eq!(log[1].command, ObservationCommand::SetTarget(target));
+        assert_eq!(log[1].error, None);
+        assert_eq!(telescope_log(&data_model, "other")[0].user_name, None);
+    }
+
+    #[test]
+    fn test_receiver_command() {
+        let configuration = |integrate| ReceiverConfiguration {
+            integrate,
+            ..ReceiverConfiguration::stopped()
+        };
+        assert_eq!(
+            ObservationCommand::receiver(false, &configuration(true)),
+            Some(ObservationCommand::StartReceiver)
+        );
+        assert_eq!(
+            ObservationCommand::receiver(true, &configuration(false)),
+            Some(ObservationCommand::StopReceiver)
+        );
+        assert_eq!(
+            ObservationCommand::receiver(true, &configuration(true)),
+            None
+        );
+    }
+}
@@ -0,0 +1,83 @@
+use crate::database::{DataBase, Storage};
+use crate::observation_log::telescope_log;
+use crate::template::HtmlTemplate;
+use askama::Template;
+use axum::{
+    extract::{Path, State},
+    response::IntoResponse,
+    routing::get,
+    Router,
+};
+
+pub fn routes(database: DataBase<impl Storage + 'static>) -> Router {
+    Router::new()
+        .route("/", get(get_observation_log_overview))
+        .route("/:telescope_name", get(get_observation_log))
+        .with_state(database)
+}
+
+#[derive(Template)]
+#[template(path = "observation_log_overview.html")]
+struct ObservationLogOverviewTemplate {
+    telescope_names: Vec<String>,
+}
+
+async fn get_observation_log_overview<StorageType>(
+    State(db): State<DataBase<StorageType>>,
+) -> impl IntoResponse
+where
+    StorageType: Storage,
+{
+    let telescope_names = db
+        .get_data()
+        .await
+        .expect("As long as no one is manually editing the database, this should never fail.")
+        .telescopes
+        .iter()
+        .map(|t| t.name.clone())
+        .collect();
+    HtmlTemplate(ObservationLogOverviewTemplate { telescope_names })
+}
+
+struct LogEntry {
+    time: String,
+    user_name: String,
+    command: String,
+    error: String,
+}
+
+#[derive(Template)]
+#[template(path = "observation_log.html")]
+struct ObservationLogTemplate {
+    telescope_name: String,
+    entries: Vec<LogEntry>,
+}
+
+async fn get_observation_log<StorageType>(
+    State(db): State<DataBase<StorageType>>,
+    Path(telescope_name): Path<String>,
+) -> impl IntoResponse
+where
+    StorageType: Storage,
+{
+    let data_model = db
+        .get_data()
+        .await
+        .expect("As long as no one is manually editing the database, this should never fail.");
+    let entries = telescope_log(&data_model, &telescope_name)
+        .into_iter()
+        .map(|entry| LogEntry {
+            time: entry.time.format("%Y-%m-%d %H:%M:%S").to_string(),
+            user_name: entry
+                .user_name
+                .clone()
+                .unwrap_or_else(|| "(automatic)".to_string()),
+            command: entry.command.description(),
+            error: entry.error.clone().unwrap_or_default(),
+        })
+        .collect();
+    HtmlTemplate(ObservationLogTemplate {
+        telescope_name,
+        entries,
+    })
+}
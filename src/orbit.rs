@@ -0,0 +1,240 @@
+//! Orbits of satellites, from two-line element sets (TLE).
+//!
+//! Satellites are propagated from the mean elements of their TLE with the
+//! secular effects of the Earth's oblateness (J2) and of drag, which are
+//! the dominant terms of SGP4. The short periodic terms and the lunar and
+//! solar perturbations of deep space orbits are left out. For GNSS and low
+//! orbit satellites within a few days of the epoch of their elements, the
+//! error is a small fraction of the beam of the telescopes.
+
+use crate::angles::{Degrees, Radians};
+use crate::coords::{horizontal_from_sat_eci, Direction, Location};
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use std::f64::consts::PI;
+use thiserror::Error;
+
+/// Gravitational parameter of the Earth, km³/s².
+const MU: f64 = 398600.4418;
+/// Second zonal harmonic of the Earth's gravity field.
+const J2: f64 = 1.08262668e-3;
+const EARTH_RADIUS: f64 = 6378.135;
+const SECONDS_PER_DAY: f64 = 86_400.0;
+const TLE_LINE_LENGTH: usize = 69;
+
+/// Mean orbital elements of a satellite at `epoch`.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+pub struct OrbitalElements {
+    pub catalog_number: u32,
+    pub epoch: DateTime<Utc>,
+    pub inclination: Radians,
+    pub right_ascension_of_ascending_node: Radians,
+    pub eccentricity: f64,
+    pub argument_of_perigee: Radians,
+    pub mean_anomaly: Radians,
+    /// Revolutions per day.
+    pub mean_motion: f64,
+    /// Half the rate of change of the mean motion, revolutions per day².
+    pub mean_motion_change: f64,
+}
+
+/// A satellite that can be looked up by its catalog number, configured in
+/// the database file.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct SatelliteDefinition {
+    pub name: String,
+    pub line1: String,
+    pub line2: String,
+}
+
+#[derive(Debug, Error, PartialEq)]
+pub enum TleError {
+    #[error("line {0} is not {TLE_LINE_LENGTH} characters long")]
+    Length(u8),
+    #[error("line {0} does not start with its line number")]
+    LineNumber(u8),
+    #[error("line {0} does not match its checksum")]
+    Checksum(u8),
+    #[error("the lines are of different satellites")]
+    CatalogNumber,
+    #[error("invalid {0}")]
+    Field(&'static str),
+}
+
+fn checksum(line: &str) -> u32 {
+    line.chars()
+        .take(TLE_LINE_LENGTH - 1)
+        .map(|c| match c {
+            '-' => 1,
+            c => c.to_digit(10).unwrap_or(0),
+        })
+        .sum::<u32>()
+        % 10
+}
+
+fn check_line(line: &str, number: u8) -> Result<&str, TleError> {
+    let line = line.trim_end();
+    if line.len() != TLE_LINE_LENGTH || !line.is_ascii() {
+        return Err(TleError::Length(number));
+    }
+    if !line.starts_with(&format!("{} ", number)) {
+        return Err(TleError::LineNumber(number));
+    }
+    if line[68..].parse::<u32>() != Ok(checksum(line)) {
+        return Err(TleError::Checksum(number));
+    }
+    Ok(line)
+}
+
+fn field<T: std::str::FromStr>(text: &str, name: &'static str) -> Result<T, TleError> {
+    text.trim().parse().map_err(|_| TleError::Field(name))
+}
+
+fn angle(text: &str, name: &'static str) -> Result<Radians, TleError> {
+    Ok(Degrees(field(text, name)?).to_radians())
+}
+
+/// Parse the two lines of a TLE, given without the optional name line.
+pub fn parse_tle(line1: &str, line2: &str) -> Result<OrbitalElements, TleError> {
+    let line1 = check_line(line1, 1)?;
+    let line2 = check_line(line2, 2)?;
+    let catalog_number = field(&line1[2..7], "catalog number")?;
+    if field::<u32>(&line2[2..7], "catalog number")? != catalog_number {
+        return Err(TleError::CatalogNumber);
+    }
+    // Two digit years, 57 to 99 are in the 1900s.
+    let year: i32 = field(&line1[18..20], "epoch year")?;
+    let year = if year < 57 { 2000 + year } else { 1900 + year };
+    let day: f64 = field(&line1[20..32], "epoch day")?;
+    let epoch = Utc
+        .with_ymd_and_hms(year, 1, 1, 0, 0, 0)
+        .single()
+        .ok_or(TleError::Field("epoch year"))?
+        + Duration::microseconds(((day - 1.0) * SECONDS_PER_DAY * 1e6).round() as i64);
+    Ok(OrbitalElements {
+        catalog_number,
+        epoch,
+        inclination: angle(&line2[8..16], "inclination")?,
+        right_ascension_of_ascending_node: angle(&line2[17..25], "right ascension")?,
+        eccentricity: field::<f64>(&format!("0.{}", line2[26..33].trim()), "eccentricity")?,
+        argument_of_perigee: angle(&line2[34..42], "argument of perigee")?,
+        mean_anomaly: angle(&line2[43..51], "mean anomaly")?,
+        mean_motion: field(&line2[52..63], "mean motion")?,
+        mean_motion_change: field(&line1[33..43], "mean motion change")?,
+    })
+}
+
+impl OrbitalElements {
+    /// Position in km at `when` in the Earth centered inertial frame of
+    /// date.
+    pub fn position(&self, when: DateTime<Utc>) -> [f64; 3] {
+        let t = (when - self.epoch).num_milliseconds() as f64 / 1000.0;
+        let n = self.mean_motion * 2.0 * PI / SECONDS_PER_DAY;
+        let a = (MU / (n * n)).cbrt();
+        let e = self.eccentricity;
+        let (sin_i, cos_i) = self.inclination.0.sin_cos();
+        // Secular drift of the orbit due to the oblateness of the Earth.
+        let p = a * (1.0 - e * e);
+        let k = 1.5 * J2 * (EARTH_RADIUS / p).powi(2) * n;
+        let node = self.right_ascension_of_ascending_node.0 - k * cos_i * t;
+        let perigee = self.argument_of_perigee.0 + k * (2.0 - 2.5 * sin_i * sin_i) * t;
+        let days = t / SECONDS_PER_DAY;
+        let mean_anomaly = self.mean_anomaly.0
+            + (n + k * (1.0 - e * e).sqrt() * (1.0 - 1.5 * sin_i * sin_i)) * t
+            + 2.0 * PI * self.mean_motion_change * days * days;
+
+        // Kepler's equation, by Newton's method.
+        let mean_anomaly = mean_anomaly.rem_euclid(2.0 * PI);
+        let mut eccentric_anomaly = mean_anomaly;
+        for _ in 0..10 {
+            eccentric_anomaly -= (eccentric_anomaly - e * eccentric_anomaly.sin() - mean_anomaly)
+                / (1.0 - e * eccentric_anomaly.cos());
+        }
+        let true_anomaly = 2.0
+            * ((1.0 + e).sqrt() * (0.5 * eccentric_anomaly).sin())
+                .atan2((1.0 - e).sqrt() * (0.5 * eccentric_anomaly).cos());
+        let r = a * (1.0 - e * eccentric_anomaly.cos());
+
+        let (sin_u, cos_u) = (perigee + true_anomaly).sin_cos();
+        let (sin_node, cos_node) = node.sin_cos();
+        [
+            r * (cos_node * cos_u - sin_node * sin_u * cos_i),
+            r * (sin_node * cos_u + cos_node * sin_u * cos_i),
+            r * sin_u * sin_i,
+        ]
+    }
+
+    /// Where the satellite is seen from `location` at `when`.
+    pub fn horizontal(&self, location: Location, when: DateTime<Utc>) -> Direction {
+        let [x, y, z] = self.position(when);
+        horizontal_from_sat_eci(x, y, z, location.latitude, location.longitude, 0.0, when)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const ISS_LINE1: &str = "1 25544U 98067A   08264.51782528 -.00002182  00000-0 -11606-4 0  2927";
+    const ISS_LINE2: &str = "2 25544  51.6416 247.4627 0006703 130.5360 325.0288 15.72125391563537";
+
+    #[test]
+    fn test_parse_tle() {
+        let elements = parse_tle(ISS_LINE1, ISS_LINE2).unwrap();
+        assert_eq!(elements.catalog_number, 25544);
+        assert_eq!(
+            elements.epoch.format("%Y-%m-%d %H:%M").to_string(),
+            "2008-09-20 12:25"
+        );
+        assert!((elements.inclination.to_degrees().0 - 51.6416).abs() < 1e-9);
+        assert!((elements.eccentricity - 0.0006703).abs() < 1e-12);
+        assert!((elements.mean_motion - 15.72125391).abs() < 1e-9);
+
+        let corrupted = ISS_LINE2.replace("51.6416", "51.6417");
+        assert_eq!(parse_tle(ISS_LINE1, &corrupted), Err(TleError::Checksum(2)));
+        assert_eq!(
+            parse_tle(ISS_LINE2, ISS_LINE1),
+            Err(TleError::LineNumber(1))
+        );
+        assert_eq!(
+            parse_tle(&ISS_LINE1[..60], ISS_LINE2),
+            Err(TleError::Length(1))
+        );
+    }
+
+    #[test]
+    fn test_propagation() {
+        let iss = parse_tle(ISS_LINE1, ISS_LINE2).unwrap();
+        let radius = |p: [f64; 3]| (p[0] * p[0] + p[1] * p[1] + p[2] * p[2]).sqrt();
+        let altitude = radius(iss.position(iss.epoch)) - EARTH_RADIUS;
+        assert!((300.0..400.0).contains(&altitude), "{}", altitude);
+        // Back in about the same place after a revolution, the orbit only
+        // turns slowly.
+        let period = Duration::milliseconds((SECONDS_PER_DAY / iss.mean_motion * 1e3) as i64);
+        let start = iss.position(iss.epoch);
+        let end = iss.position(iss.epoch + period);
+        let distance = radius([end[0] - start[0], end[1] - start[1], end[2] - start[2]]);
+        assert!(distance < 100.0, "{}", distance);
+
+        // A geostationary satellite stays in the same direction.
+        let geostationary = OrbitalElements {
+            catalog_number: 1,
+            epoch: iss.epoch,
+            inclination: Radians(0.0),
+            right_ascension_of_ascending_node: Radians(0.0),
+            eccentricity: 0.0,
+            argument_of_perigee: Radians(0.0),
+            mean_anomaly: Radians(0.0),
+            mean_motion: 1.0027379,
+            mean_motion_change: 0.0,
+        };
+        let location = Location {
+            longitude: Degrees(11.9).to_radians(),
+            latitude: Degrees(57.4).to_radians(),
+        };
+        let before = geostationary.horizontal(location, iss.epoch);
+        let after = geostationary.horizontal(location, iss.epoch + Duration::hours(6));
+        assert!((before.azimuth - after.azimuth).abs().to_degrees().0 < 0.1);
+        assert!((before.altitude - after.altitude).abs().to_degrees().0 < 0.1);
+    }
+}
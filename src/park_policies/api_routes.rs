@@ -0,0 +1,105 @@
+use crate::database::{DataBase, Storage};
+use crate::park_policies::{
+    active_override, park_reason, set_park_override, set_park_policies, ParkPolicy,
+    ParkPolicyError, ParkReason,
+};
+use axum::{
+    extract::{Json, Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{get, put},
+    Router,
+};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+pub fn routes(database: DataBase<impl Storage + 'static>) -> Router {
+    Router::new()
+        .route("/", get(get_park_policies))
+        .route("/:telescope_name", put(put_park_policies))
+        .route(
+            "/:telescope_name/override",
+            put(put_park_override).delete(delete_park_override),
+        )
+        .with_state(database)
+}
+
+impl IntoResponse for ParkPolicyError {
+    fn into_response(self) -> Response {
+        match self {
+            ParkPolicyError::UnknownTelescope => {
+                (StatusCode::NOT_FOUND, "Telescope not found".to_string()).into_response()
+            }
+            ParkPolicyError::InvalidPolicy(message) => {
+                (StatusCode::BAD_REQUEST, message).into_response()
+            }
+            ParkPolicyError::QuietHours => (
+                StatusCode::LOCKED,
+                "The telescope is parked for its quiet hours".to_string(),
+            )
+                .into_response(),
+            ParkPolicyError::ServiceUnavailable => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Failed to update park policies".to_string(),
+            )
+                .into_response(),
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct ParkPolicyStatus {
+    telescope_name: String,
+    policies: Vec<ParkPolicy>,
+    override_until: Option<DateTime<Utc>>,
+    /// Why the telescope should be parked right now, if it should.
+    park_reason: Option<ParkReason>,
+}
+
+async fn get_park_policies(
+    State(db): State<DataBase<impl Storage>>,
+) -> Json<Vec<ParkPolicyStatus>> {
+    let data_model = db
+        .get_data()
+        .await
+        .expect("As long as no one is manually editing the database, this should never fail.");
+    let now = Utc::now();
+    Json(
+        data_model
+            .telescopes
+            .iter()
+            .map(|t| ParkPolicyStatus {
+                telescope_name: t.name.clone(),
+                policies: t.park_policies.clone(),
+                override_until: active_override(&data_model, &t.name, now).map(|o| o.until),
+                park_reason: park_reason(&data_model, &t.name, now),
+            })
+            .collect(),
+    )
+}
+
+async fn put_park_policies(
+    State(db): State<DataBase<impl Storage>>,
+    Path(telescope_name): Path<String>,
+    Json(policies): Json<Vec<ParkPolicy>>,
+) -> Result<StatusCode, ParkPolicyError> {
+    set_park_policies(&db, &telescope_name, policies).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn put_park_override(
+    State(db): State<DataBase<impl Storage>>,
+    Path(telescope_name): Path<String>,
+    Json(until): Json<DateTime<Utc>>,
+) -> Result<StatusCode, ParkPolicyError> {
+    set_park_override(&db, &telescope_name, Some(until)).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn delete_park_override(
+    State(db): State<DataBase<impl Storage>>,
+    Path(telescope_name): Path<String>,
+) -> Result<StatusCode, ParkPolicyError> {
+    set_park_override(&db, &telescope_name, None).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
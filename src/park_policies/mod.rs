@@ -0,0 +1,363 @@
+//! Scheduled park policies of the telescopes, e.g. parking every night
+//! between 02:00 and 06:00 or whenever nobody has booked the telescope.
+//!
+//! The policies are configured per telescope. A background service parks
+//! telescopes when one of their policies applies, and during quiet hours
+//! requests that would move the telescope are refused. An admin can lift
+//! the policies of a telescope until a given time with an override.
+
+use crate::bookings::Booking;
+use crate::database::{DataBase, DataBaseError, DataModel, Storage};
+use crate::observation_log::{record_command, ObservationCommand};
+use crate::telescope::TelescopeCollection;
+use crate::telescopes::TelescopeTarget;
+use axum::{
+    extract::{Path, State},
+    http::{Method, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Duration, NaiveTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+
+pub mod api_routes;
+
+pub const PARK_POLICY_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub enum ParkPolicy {
+    /// Park and refuse motion between `start` and `end` UTC. The quiet
+    /// hours wrap past midnight if `end` is before `start`.
+    QuietHours { start: NaiveTime, end: NaiveTime },
+    /// Park when no booking is active or starts within `minutes`.
+    ParkWhenIdle { minutes: u64 },
+}
+
+/// Policies of a telescope lifted until `until`.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct ParkOverride {
+    pub telescope_name: String,
+    pub until: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+pub enum ParkReason {
+    QuietHours,
+    Idle,
+}
+
+impl Display for ParkReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParkReason::QuietHours => write!(f, "quiet hours"),
+            ParkReason::Idle => write!(f, "no upcoming booking"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ParkPolicyError {
+    UnknownTelescope,
+    InvalidPolicy(String),
+    /// Motion was requested during the quiet hours of the telescope.
+    QuietHours,
+    ServiceUnavailable,
+}
+
+impl From<DataBaseError> for ParkPolicyError {
+    fn from(_source: DataBaseError) -> Self {
+        Self::ServiceUnavailable
+    }
+}
+
+impl ParkPolicy {
+    pub fn validate(&self) -> Result<(), ParkPolicyError> {
+        match self {
+            ParkPolicy::QuietHours { start, end } if start == end => {
+                Err(ParkPolicyError::InvalidPolicy(
+                    "Quiet hours must not start and end at the same time".to_string(),
+                ))
+            }
+            ParkPolicy::ParkWhenIdle { minutes: 0 } => Err(ParkPolicyError::InvalidPolicy(
+                "Idle time must be at least one minute".to_string(),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    fn reason(
+        &self,
+        telescope_name: &str,
+        bookings: &[Booking],
+        now: DateTime<Utc>,
+    ) -> Option<ParkReason> {
+        match self {
+            ParkPolicy::QuietHours { start, end } => {
+                let time = now.time();
+                let quiet = if start <= end {
+                    *start <= time && time < *end
+                } else {
+                    *start <= time || time < *end
+                };
+                quiet.then_some(ParkReason::QuietHours)
+            }
+            ParkPolicy::ParkWhenIdle { minutes } => {
+                let horizon = now + Duration::minutes(*minutes as i64);
+                let booked = bookings.iter().any(|b| {
+                    b.telescope_name == telescope_name && b.start_time < horizon && now < b.end_time
+                });
+                (!booked).then_some(ParkReason::Idle)
+            }
+        }
+    }
+}
+
+pub fn active_override<'a>(
+    data_model: &'a DataModel,
+    telescope_name: &str,
+    now: DateTime<Utc>,
+) -> Option<&'a ParkOverride> {
+    data_model
+        .park_overrides
+        .iter()
+        .find(|o| o.telescope_name == telescope_name && now < o.until)
+}
+
+/// Why `telescope_name` should be parked at `now`, if it should. Quiet
+/// hours take precedence over other policies.
+pub fn park_reason(
+    data_model: &DataModel,
+    telescope_name: &str,
+    now: DateTime<Utc>,
+) -> Option<ParkReason> {
+    if active_override(data_model, telescope_name, now).is_some() {
+        return None;
+    }
+    let telescope = data_model
+        .telescopes
+        .iter()
+        .find(|t| t.name == telescope_name)?;
+    let reasons: Vec<ParkReason> = telescope
+        .park_policies
+        .iter()
+        .filter_map(|p| p.reason(telescope_name, &data_model.bookings, now))
+        .collect();
+    if reasons.contains(&ParkReason::QuietHours) {
+        Some(ParkReason::QuietHours)
+    } else {
+        reasons.first().copied()
+    }
+}
+
+pub async fn set_park_policies(
+    db: &DataBase<impl Storage>,
+    telescope_name: &str,
+    policies: Vec<ParkPolicy>,
+) -> Result<(), ParkPolicyError> {
+    for policy in &policies {
+        policy.validate()?;
+    }
+    let mut result = Err(ParkPolicyError::UnknownTelescope);
+    db.update_data(|mut data_model| {
+        if let Some(telescope) = data_model
+            .telescopes
+            .iter_mut()
+            .find(|t| t.name == telescope_name)
+        {
+            telescope.park_policies = policies;
+            result = Ok(());
+        }
+        data_model
+    })
+    .await?;
+    result
+}
+
+/// Lift the policies of `telescope_name` until `until`, or restore them
+/// now if `until` is None.
+pub async fn set_park_override(
+    db: &DataBase<impl Storage>,
+    telescope_name: &str,
+    until: Option<DateTime<Utc>>,
+) -> Result<(), ParkPolicyError> {
+    let mut result = Err(ParkPolicyError::UnknownTelescope);
+    db.update_data(|mut data_model| {
+        if data_model
+            .telescopes
+            .iter()
+            .any(|t| t.name == telescope_name)
+        {
+            data_model
+                .park_overrides
+                .retain(|o| o.telescope_name != telescope_name);
+            if let Some(until) = until {
+                data_model.park_overrides.push(ParkOverride {
+                    telescope_name: telescope_name.to_string(),
+                    until,
+                });
+            }
+            result = Ok(());
+        }
+        data_model
+    })
+    .await?;
+    if result.is_ok() {
+        match until {
+            Some(until) => log::info!("Park policies of {} lifted until {}", telescope_name, until),
+            None => log::info!("Park policies of {} restored", telescope_name),
+        }
+    }
+    result
+}
+
+/// Park every telescope that one of its policies says should be parked and
+/// is not already.
+pub async fn apply_park_policies(
+    database: &DataBase<impl Storage>,
+    telescopes: &TelescopeCollection,
+    now: DateTime<Utc>,
+) -> Result<(), DataBaseError> {
+    let data_model = database.get_data().await?;
+    let telescopes = telescopes.read().await;
+    for (name, container) in telescopes.iter() {
+        let Some(reason) = park_reason(&data_model, name, now) else {
+            continue;
+        };
+        let mut telescope = container.telescope.lock().await;
+        match telescope.get_info().await {
+            Ok(info) if info.current_target == TelescopeTarget::Parked => continue,
+            Ok(_) => {}
+            Err(error) => {
+                log::warn!("Failed to check whether {} is parked: {}", name, error);
+                continue;
+            }
+        }
+        let result = telescope.set_target(TelescopeTarget::Parked).await;
+        match &result {
+            Ok(_) => log::info!("Parking {} because of {}", name, reason),
+            Err(error) => log::error!("Failed to park {}: {}", name, error),
+        }
+        record_command(
+            database,
+            name,
+            None,
+            ObservationCommand::SetTarget(TelescopeTarget::Parked),
+            &result,
+            now,
+        )
+        .await;
+    }
+    Ok(())
+}
+
+pub fn start_park_policy_service<T>(
+    database: DataBase<T>,
+    telescopes: TelescopeCollection,
+) -> tokio::task::JoinHandle<()>
+where
+    T: Storage + 'static,
+{
+    tokio::spawn(async move {
+        loop {
+            if let Err(error) = apply_park_policies(&database, &telescopes, Utc::now()).await {
+                log::error!("Failed to apply park policies: {}", error);
+            }
+            tokio::time::sleep(PARK_POLICY_INTERVAL).await;
+        }
+    })
+}
+
+/// Middleware for the telescope routes, rejecting every request that changes
+/// the state of a telescope during its quiet hours.
+pub async fn enforce_quiet_hours<StorageType, B>(
+    State(db): State<DataBase<StorageType>>,
+    Path(params): Path<HashMap<String, String>>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response
+where
+    StorageType: Storage,
+{
+    if request.method() != Method::GET {
+        if let Some(telescope_name) = params.get("telescope_id") {
+            let data_model = db.get_data().await.expect(
+                "As long as no one is manually editing the database, this should never fail.",
+            );
+            if park_reason(&data_model, telescope_name, Utc::now()) == Some(ParkReason::QuietHours)
+            {
+                return ParkPolicyError::QuietHours.into_response();
+            }
+        }
+    }
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::database::create_in_memory_database;
+    use crate::telescopes::test_utils::fake_telescope;
+    use chrono::TimeZone;
+
+    #[tokio::test]
+    async fn test_park_reason() {
+        let db = create_in_memory_database();
+        let at = |hour, minute| Utc.with_ymd_and_hms(2024, 3, 1, hour, minute, 0).unwrap();
+        db.update_data(|mut data_model| {
+            data_model.telescopes = vec![fake_telescope("fake")];
+            data_model.bookings = vec![Booking {
+                start_time: at(12, 30),
+                end_time: at(13, 30),
+                telescope_name: "fake".to_string(),
+                user_name: "anna".to_string(),
+            }];
+            data_model
+        })
+        .await
+        .unwrap();
+
+        let quiet_hours = ParkPolicy::QuietHours {
+            start: NaiveTime::from_hms_opt(22, 0, 0).unwrap(),
+            end: NaiveTime::from_hms_opt(6, 0, 0).unwrap(),
+        };
+        assert_eq!(
+            set_park_policies(&db, "salsa", vec![]).await,
+            Err(ParkPolicyError::UnknownTelescope)
+        );
+        assert!(matches!(
+            set_park_policies(&db, "fake", vec![ParkPolicy::ParkWhenIdle { minutes: 0 }]).await,
+            Err(ParkPolicyError::InvalidPolicy(_))
+        ));
+        set_park_policies(
+            &db,
+            "fake",
+            vec![ParkPolicy::ParkWhenIdle { minutes: 60 }, quiet_hours],
+        )
+        .await
+        .unwrap();
+
+        let reason = |now| {
+            let db = db.clone();
+            async move {
+                let data_model = db.get_data().await.unwrap();
+                park_reason(&data_model, "fake", now)
+            }
+        };
+        assert_eq!(reason(at(23, 0)).await, Some(ParkReason::QuietHours));
+        assert_eq!(reason(at(2, 0)).await, Some(ParkReason::QuietHours));
+        assert_eq!(reason(at(10, 0)).await, Some(ParkReason::Idle));
+        // The booking starts within the hour, or is active.
+        assert_eq!(reason(at(11, 45)).await, None);
+        assert_eq!(reason(at(13, 0)).await, None);
+
+        set_park_override(&db, "fake", Some(at(4, 0)))
+            .await
+            .unwrap();
+        assert_eq!(reason(at(2, 0)).await, None);
+        assert_eq!(reason(at(5, 0)).await, Some(ParkReason::QuietHours));
+        set_park_override(&db, "fake", None).await.unwrap();
+        assert_eq!(reason(at(2, 0)).await, Some(ParkReason::QuietHours));
+    }
+}
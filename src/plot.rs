@@ -0,0 +1,679 @@
+use crate::constants::SpectralLine;
+use crate::format::{precision_for_span, Locale};
+use crate::telescopes::{ContinuumSample, ObservedSpectra};
+use chrono::{DateTime, Utc};
+use image::{ImageOutputFormat, RgbImage};
+use plotters::prelude::*;
+use printpdf::{Image, ImageTransform, Mm, PdfDocument};
+use serde::Deserialize;
+use std::io::Cursor;
+use thiserror::Error;
+
+pub const DEFAULT_PLOT_WIDTH: u32 = 1600;
+pub const DEFAULT_PLOT_HEIGHT: u32 = 1000;
+/// Size of the spectrum thumbnails shown in listings.
+pub const THUMBNAIL_WIDTH: u32 = 160;
+pub const THUMBNAIL_HEIGHT: u32 = 100;
+// Limit the size of rendered figures so a single request can not allocate
+// unbounded amounts of memory.
+pub const MAX_PLOT_SIDE: u32 = 4096;
+/// Resolution the bitmap of a PDF figure is printed at, which makes the
+/// default size about 17 cm wide.
+pub const PDF_DPI: f32 = 240.0;
+
+#[derive(Debug, Error)]
+pub enum PlotError {
+    #[error("there is no spectrum to plot")]
+    EmptySpectrum,
+    #[error("requested plot size {0}x{1} is not supported")]
+    InvalidSize(u32, u32),
+    #[error("failed to render plot: {0}")]
+    DrawingError(String),
+    #[error("failed to encode plot: {0}")]
+    EncodingError(String),
+}
+
+#[derive(Deserialize, Debug, Copy, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PlotFormat {
+    Svg,
+    Png,
+    Pdf,
+}
+
+impl PlotFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            PlotFormat::Svg => "image/svg+xml",
+            PlotFormat::Png => "image/png",
+            PlotFormat::Pdf => "application/pdf",
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Copy, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum FrequencyUnit {
+    Hz,
+    Khz,
+    Mhz,
+    Ghz,
+}
+
+impl FrequencyUnit {
+    fn scale(&self) -> f64 {
+        match self {
+            FrequencyUnit::Hz => 1.0,
+            FrequencyUnit::Khz => 1e3,
+            FrequencyUnit::Mhz => 1e6,
+            FrequencyUnit::Ghz => 1e9,
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            FrequencyUnit::Hz => "Hz",
+            FrequencyUnit::Khz => "kHz",
+            FrequencyUnit::Mhz => "MHz",
+            FrequencyUnit::Ghz => "GHz",
+        }
+    }
+}
+
+/// Options controlling how a spectrum is rendered.
+///
+/// All fields are optional so that the struct can be deserialized directly
+/// from the query string of a request.
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct PlotOptions {
+    pub format: Option<PlotFormat>,
+    pub title: Option<String>,
+    pub x_label: Option<String>,
+    pub y_label: Option<String>,
+    pub frequency_unit: Option<FrequencyUnit>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// Name of a spectral line to plot the velocity relative to the LSR of
+    /// instead of the frequency.
+    pub line: Option<String>,
+    /// Lines marked in the plot when inside the plotted frequency range.
+    #[serde(skip)]
+    pub spectral_lines: Vec<SpectralLine>,
+    /// Velocity of each channel in km/s, plotted instead of the frequency
+    /// if given. Filled in from `line` by the handlers.
+    #[serde(skip)]
+    pub velocities: Option<Vec<f64>>,
+}
+
+impl PlotOptions {
+    pub fn format(&self) -> PlotFormat {
+        self.format.unwrap_or(PlotFormat::Svg)
+    }
+
+    fn size(&self) -> Result<(u32, u32), PlotError> {
+        let width = self.width.unwrap_or(DEFAULT_PLOT_WIDTH);
+        let height = self.height.unwrap_or(DEFAULT_PLOT_HEIGHT);
+        if width == 0 || height == 0 || width > MAX_PLOT_SIDE || height > MAX_PLOT_SIDE {
+            return Err(PlotError::InvalidSize(width, height));
+        }
+        Ok((width, height))
+    }
+}
+
+/// Render a spectrum as an SVG document.
+pub fn render_spectrum_svg(
+    spectrum: &ObservedSpectra,
+    options: &PlotOptions,
+) -> Result<String, PlotError> {
+    let size = options.size()?;
+    let mut svg = String::new();
+    {
+        let backend = SVGBackend::with_string(&mut svg, size);
+        draw_spectrum(backend.into_drawing_area(), spectrum, options, size)?;
+    }
+    Ok(svg)
+}
+
+/// Render a spectrum as a PNG image.
+pub fn render_spectrum_png(
+    spectrum: &ObservedSpectra,
+    options: &PlotOptions,
+) -> Result<Vec<u8>, PlotError> {
+    let (width, height) = options.size()?;
+    let mut buffer = vec![0u8; width as usize * height as usize * 3];
+    {
+        let backend = BitMapBackend::with_buffer(&mut buffer, (width, height));
+        draw_spectrum(
+            backend.into_drawing_area(),
+            spectrum,
+            options,
+            (width, height),
+        )?;
+    }
+    encode_png(buffer, width, height)
+}
+
+/// Render a spectrum as a single page PDF, sized to fit the figure.
+pub fn render_spectrum_pdf(
+    spectrum: &ObservedSpectra,
+    options: &PlotOptions,
+) -> Result<Vec<u8>, PlotError> {
+    let png = render_spectrum_png(spectrum, options)?;
+    let image =
+        image::load_from_memory(&png).map_err(|err| PlotError::EncodingError(err.to_string()))?;
+    let to_mm = |pixels: u32| Mm(pixels as f32 / PDF_DPI * 25.4);
+    let title = options.title.as_deref().unwrap_or("Spectrum");
+    let (document, page, layer) =
+        PdfDocument::new(title, to_mm(image.width()), to_mm(image.height()), "Plot");
+    Image::from_dynamic_image(&image).add_to_layer(
+        document.get_page(page).get_layer(layer),
+        ImageTransform {
+            dpi: Some(PDF_DPI),
+            ..Default::default()
+        },
+    );
+    document
+        .save_to_bytes()
+        .map_err(|err| PlotError::EncodingError(err.to_string()))
+}
+
+/// Render a spectrum in the format asked for in `options`.
+pub fn render_spectrum(
+    spectrum: &ObservedSpectra,
+    options: &PlotOptions,
+) -> Result<Vec<u8>, PlotError> {
+    match options.format() {
+        PlotFormat::Svg => render_spectrum_svg(spectrum, options).map(String::into_bytes),
+        PlotFormat::Png => render_spectrum_png(spectrum, options),
+        PlotFormat::Pdf => render_spectrum_pdf(spectrum, options),
+    }
+}
+
+/// Render a spectrum as a small PNG without axes or labels, to recognize
+/// it at a glance in a listing.
+pub fn render_spectrum_thumbnail(spectrum: &ObservedSpectra) -> Result<Vec<u8>, PlotError> {
+    if spectrum.frequencies.is_empty() || spectrum.spectra.is_empty() {
+        return Err(PlotError::EmptySpectrum);
+    }
+    let (width, height) = (THUMBNAIL_WIDTH, THUMBNAIL_HEIGHT);
+    let mut buffer = vec![0u8; width as usize * height as usize * 3];
+    {
+        let root = BitMapBackend::with_buffer(&mut buffer, (width, height)).into_drawing_area();
+        let points: Vec<(f64, f64)> = spectrum
+            .frequencies
+            .iter()
+            .copied()
+            .zip(spectrum.spectra.iter().copied())
+            .collect();
+        let (x_min, x_max) = value_range(points.iter().map(|p| p.0));
+        let (y_min, y_max) = value_range(points.iter().map(|p| p.1));
+        let y_margin = 0.05 * (y_max - y_min);
+        root.fill(&WHITE).map_err(drawing_error)?;
+        let mut chart = ChartBuilder::on(&root)
+            .margin(4)
+            .build_cartesian_2d(x_min..x_max, (y_min - y_margin)..(y_max + y_margin))
+            .map_err(drawing_error)?;
+        chart
+            .draw_series(LineSeries::new(points, &BLACK))
+            .map_err(drawing_error)?;
+        root.present().map_err(drawing_error)?;
+    }
+    encode_png(buffer, width, height)
+}
+
+fn encode_png(buffer: Vec<u8>, width: u32, height: u32) -> Result<Vec<u8>, PlotError> {
+    let image = RgbImage::from_raw(width, height, buffer)
+        .ok_or_else(|| PlotError::EncodingError("buffer does not match image size".to_string()))?;
+    let mut png = Cursor::new(Vec::new());
+    image
+        .write_to(&mut png, ImageOutputFormat::Png)
+        .map_err(|err| PlotError::EncodingError(err.to_string()))?;
+    Ok(png.into_inner())
+}
+
+fn draw_spectrum<DB>(
+    root: DrawingArea<DB, plotters::coord::Shift>,
+    spectrum: &ObservedSpectra,
+    options: &PlotOptions,
+    (width, _height): (u32, u32),
+) -> Result<(), PlotError>
+where
+    DB: DrawingBackend,
+{
+    if spectrum.frequencies.is_empty() || spectrum.spectra.is_empty() {
+        return Err(PlotError::EmptySpectrum);
+    }
+
+    let unit = options.frequency_unit.unwrap_or(FrequencyUnit::Mhz);
+    let xs: Vec<f64> = match &options.velocities {
+        Some(velocities) => velocities.clone(),
+        None => spectrum
+            .frequencies
+            .iter()
+            .map(|frequency| frequency / unit.scale())
+            .collect(),
+    };
+    let points: Vec<(f64, f64)> = xs
+        .into_iter()
+        .zip(spectrum.spectra.iter().copied())
+        .collect();
+    if points.is_empty() {
+        return Err(PlotError::EmptySpectrum);
+    }
+    let (x_min, x_max) = value_range(points.iter().map(|p| p.0));
+    let (y_min, y_max) = value_range(points.iter().map(|p| p.1));
+    let y_margin = 0.05 * (y_max - y_min);
+
+    // Scale fonts and line widths with the figure so that large figures meant
+    // for print still have legible labels.
+    let scale = width as f64 / DEFAULT_PLOT_WIDTH as f64;
+    let font_size = |size: f64| (size * scale).max(8.0);
+
+    root.fill(&WHITE).map_err(drawing_error)?;
+    let title = options.title.clone().unwrap_or_else(|| {
+        format!(
+            "Spectrum ({} integration)",
+            Locale::MACHINE.quantity(spectrum.observation_time.as_secs_f64(), 0, "s")
+        )
+    });
+    let x_label = options
+        .x_label
+        .clone()
+        .unwrap_or_else(|| match &options.velocities {
+            Some(_) => "Velocity (LSR) [km/s]".to_string(),
+            None => format!("Frequency [{}]", unit.label()),
+        });
+    let y_label = options
+        .y_label
+        .clone()
+        .unwrap_or_else(|| "Intensity".to_string());
+
+    let mut chart = ChartBuilder::on(&root)
+        .caption(title, ("sans-serif", font_size(48.0)))
+        .margin((20.0 * scale) as u32)
+        .x_label_area_size((100.0 * scale) as u32)
+        .y_label_area_size((140.0 * scale) as u32)
+        .build_cartesian_2d(x_min..x_max, (y_min - y_margin)..(y_max + y_margin))
+        .map_err(drawing_error)?;
+
+    // Exported figures are often read into other tools, so tick labels use
+    // the machine format with only as many decimals as the ticks need.
+    let x_precision = precision_for_span(x_max - x_min);
+    let y_precision = precision_for_span(y_max - y_min);
+    chart
+        .configure_mesh()
+        .x_desc(x_label)
+        .y_desc(y_label)
+        .x_label_formatter(&|x| Locale::MACHINE.number(*x, x_precision))
+        .y_label_formatter(&|y| Locale::MACHINE.number(*y, y_precision))
+        .axis_desc_style(("sans-serif", font_size(36.0)))
+        .label_style(("sans-serif", font_size(28.0)))
+        .draw()
+        .map_err(drawing_error)?;
+
+    chart
+        .draw_series(LineSeries::new(
+            points,
+            BLACK.stroke_width((2.0 * scale).max(1.0) as u32),
+        ))
+        .map_err(drawing_error)?;
+
+    let (y_low, y_high) = (y_min - y_margin, y_max + y_margin);
+    // The lines are at their rest frequencies, which are not on a velocity
+    // axis.
+    let marked_lines: &[SpectralLine] = if options.velocities.is_some() {
+        &[]
+    } else {
+        &options.spectral_lines
+    };
+    for line in marked_lines {
+        let x = line.rest_frequency / unit.scale();
+        if x < x_min || x > x_max {
+            continue;
+        }
+        chart
+            .draw_series(LineSeries::new(
+                [(x, y_low), (x, y_high)],
+                RED.stroke_width(scale.max(1.0) as u32),
+            ))
+            .map_err(drawing_error)?;
+        chart
+            .draw_series(std::iter::once(Text::new(
+                line.name.clone(),
+                (x, y_high),
+                ("sans-serif", font_size(28.0)).into_font().color(&RED),
+            )))
+            .map_err(drawing_error)?;
+    }
+
+    root.present().map_err(drawing_error)?;
+    Ok(())
+}
+
+/// Render the galactic positions (l, b) in degrees as a coverage map.
+pub fn render_coverage_svg(positions: &[(f64, f64)]) -> Result<String, PlotError> {
+    let mut svg = String::new();
+    {
+        let root = SVGBackend::with_string(&mut svg, (DEFAULT_PLOT_WIDTH, DEFAULT_PLOT_HEIGHT / 2))
+            .into_drawing_area();
+        root.fill(&WHITE).map_err(drawing_error)?;
+        let mut chart = ChartBuilder::on(&root)
+            .caption("Galactic coverage", ("sans-serif", 40.0))
+            .margin(20)
+            .x_label_area_size(80)
+            .y_label_area_size(100)
+            .build_cartesian_2d(0.0..360.0, -90.0..90.0)
+            .map_err(drawing_error)?;
+        chart
+            .configure_mesh()
+            .x_desc("Galactic longitude [deg]")
+            .y_desc("Galactic latitude [deg]")
+            .axis_desc_style(("sans-serif", 28.0))
+            .label_style(("sans-serif", 22.0))
+            .draw()
+            .map_err(drawing_error)?;
+        chart
+            .draw_series(
+                positions
+                    .iter()
+                    .map(|&(l, b)| Circle::new((l, b), 4, BLUE.filled())),
+            )
+            .map_err(drawing_error)?;
+        root.present().map_err(drawing_error)?;
+    }
+    Ok(svg)
+}
+
+/// Render rotation speeds in km/s against distance from the galactic centre
+/// in kpc.
+pub fn render_rotation_curve_svg(points: &[(f64, f64)]) -> Result<String, PlotError> {
+    if points.is_empty() {
+        return Err(PlotError::EmptySpectrum);
+    }
+    let (_, r_max) = value_range(points.iter().map(|p| p.0));
+    let (_, v_max) = value_range(points.iter().map(|p| p.1));
+
+    let mut svg = String::new();
+    {
+        let root = SVGBackend::with_string(&mut svg, (DEFAULT_PLOT_WIDTH, DEFAULT_PLOT_HEIGHT))
+            .into_drawing_area();
+        root.fill(&WHITE).map_err(drawing_error)?;
+        let mut chart = ChartBuilder::on(&root)
+            .caption("Rotation curve of the Milky Way", ("sans-serif", 48.0))
+            .margin(20)
+            .x_label_area_size(100)
+            .y_label_area_size(140)
+            .build_cartesian_2d(0.0..(1.1 * r_max), 0.0..(1.1 * v_max))
+            .map_err(drawing_error)?;
+        chart
+            .configure_mesh()
+            .x_desc("Distance from the galactic centre [kpc]")
+            .y_desc("Rotation speed [km/s]")
+            .axis_desc_style(("sans-serif", 36.0))
+            .label_style(("sans-serif", 28.0))
+            .draw()
+            .map_err(drawing_error)?;
+        chart
+            .draw_series(
+                points
+                    .iter()
+                    .map(|&point| Circle::new(point, 5, BLUE.filled())),
+            )
+            .map_err(drawing_error)?;
+        root.present().map_err(drawing_error)?;
+    }
+    Ok(svg)
+}
+
+/// Render values over time as an SVG scatter plot with connecting lines.
+///
+/// Time is shown as days since the first point.
+pub fn render_time_series_svg(
+    points: &[(DateTime<Utc>, f64)],
+    title: &str,
+    y_label: &str,
+) -> Result<String, PlotError> {
+    let first = points
+        .iter()
+        .map(|p| p.0)
+        .min()
+        .ok_or(PlotError::EmptySpectrum)?;
+    let days: Vec<(f64, f64)> = points
+        .iter()
+        .map(|&(when, value)| {
+            let elapsed = when.signed_duration_since(first);
+            (elapsed.num_seconds() as f64 / 86400.0, value)
+        })
+        .collect();
+    let (x_min, x_max) = value_range(days.iter().map(|p| p.0));
+    let (y_min, y_max) = value_range(days.iter().map(|p| p.1));
+    let y_margin = 0.05 * (y_max - y_min);
+
+    let mut svg = String::new();
+    {
+        let root = SVGBackend::with_string(&mut svg, (DEFAULT_PLOT_WIDTH, DEFAULT_PLOT_HEIGHT))
+            .into_drawing_area();
+        root.fill(&WHITE).map_err(drawing_error)?;
+        let mut chart = ChartBuilder::on(&root)
+            .caption(title, ("sans-serif", 48.0))
+            .margin(20)
+            .x_label_area_size(100)
+            .y_label_area_size(140)
+            .build_cartesian_2d(x_min..x_max, (y_min - y_margin)..(y_max + y_margin))
+            .map_err(drawing_error)?;
+        chart
+            .configure_mesh()
+            .x_desc(format!("Days since {}", first.format("%Y-%m-%d %H:%M UTC")))
+            .y_desc(y_label)
+            .axis_desc_style(("sans-serif", 36.0))
+            .label_style(("sans-serif", 28.0))
+            .draw()
+            .map_err(drawing_error)?;
+        chart
+            .draw_series(LineSeries::new(days.iter().copied(), BLACK.stroke_width(1)))
+            .map_err(drawing_error)?;
+        chart
+            .draw_series(
+                days.iter()
+                    .map(|&point| Circle::new(point, 5, BLUE.filled())),
+            )
+            .map_err(drawing_error)?;
+        root.present().map_err(drawing_error)?;
+    }
+    Ok(svg)
+}
+
+/// Render the total power recorded during a measurement against seconds
+/// since its start.
+pub fn render_continuum_svg(samples: &[ContinuumSample]) -> Result<String, PlotError> {
+    let first = samples.first().ok_or(PlotError::EmptySpectrum)?.time;
+    let points: Vec<(f64, f64)> = samples
+        .iter()
+        .map(|sample| {
+            let elapsed = sample.time.signed_duration_since(first);
+            (elapsed.num_milliseconds() as f64 / 1e3, sample.power)
+        })
+        .collect();
+    let (x_min, x_max) = value_range(points.iter().map(|p| p.0));
+    let (y_min, y_max) = value_range(points.iter().map(|p| p.1));
+    let y_margin = 0.05 * (y_max - y_min);
+
+    let mut svg = String::new();
+    {
+        let root = SVGBackend::with_string(&mut svg, (DEFAULT_PLOT_WIDTH, DEFAULT_PLOT_HEIGHT))
+            .into_drawing_area();
+        root.fill(&WHITE).map_err(drawing_error)?;
+        let mut chart = ChartBuilder::on(&root)
+            .caption("Total power", ("sans-serif", 48.0))
+            .margin(20)
+            .x_label_area_size(100)
+            .y_label_area_size(140)
+            .build_cartesian_2d(x_min..x_max, (y_min - y_margin)..(y_max + y_margin))
+            .map_err(drawing_error)?;
+        chart
+            .configure_mesh()
+            .x_desc(format!(
+                "Seconds since {}",
+                first.format("%Y-%m-%d %H:%M:%S UTC")
+            ))
+            .y_desc("Power [receiver units]")
+            .axis_desc_style(("sans-serif", 36.0))
+            .label_style(("sans-serif", 28.0))
+            .draw()
+            .map_err(drawing_error)?;
+        chart
+            .draw_series(LineSeries::new(points, BLACK.stroke_width(1)))
+            .map_err(drawing_error)?;
+        root.present().map_err(drawing_error)?;
+    }
+    Ok(svg)
+}
+
+/// Render rows of per-frequency values in [0, 1] as an SVG heatmap.
+///
+/// Each row is a pair of bin center frequencies in Hz and bin values. Rows
+/// are drawn from the bottom up in the order given.
+pub fn render_heatmap_svg(title: &str, rows: &[(Vec<f64>, Vec<f64>)]) -> Result<String, PlotError> {
+    let (x_min, x_max) = value_range(rows.iter().flat_map(|row| row.0.iter().map(|f| f / 1e6)));
+    if rows.is_empty() || !x_min.is_finite() {
+        return Err(PlotError::EmptySpectrum);
+    }
+
+    let mut svg = String::new();
+    {
+        let root = SVGBackend::with_string(&mut svg, (DEFAULT_PLOT_WIDTH, DEFAULT_PLOT_HEIGHT))
+            .into_drawing_area();
+        root.fill(&WHITE).map_err(drawing_error)?;
+        let mut chart = ChartBuilder::on(&root)
+            .caption(title, ("sans-serif", 48.0))
+            .margin(20)
+            .x_label_area_size(100)
+            .y_label_area_size(140)
+            .build_cartesian_2d(x_min..x_max, 0.0..rows.len() as f64)
+            .map_err(drawing_error)?;
+        chart
+            .configure_mesh()
+            .disable_mesh()
+            .x_desc("Frequency [MHz]")
+            .y_desc("Scan (oldest at bottom)")
+            .axis_desc_style(("sans-serif", 36.0))
+            .label_style(("sans-serif", 28.0))
+            .draw()
+            .map_err(drawing_error)?;
+        for (row_index, (frequencies, values)) in rows.iter().enumerate() {
+            let bin_width = match frequencies.as_slice() {
+                [first, second, ..] => (second - first) / 1e6,
+                _ => 1.0,
+            };
+            chart
+                .draw_series(frequencies.iter().zip(values).map(|(frequency, &value)| {
+                    let x = frequency / 1e6;
+                    let y = row_index as f64;
+                    // White for clean bins going to red for fully occupied ones.
+                    let shade = (255.0 * (1.0 - value.clamp(0.0, 1.0))) as u8;
+                    Rectangle::new(
+                        [(x - bin_width / 2.0, y), (x + bin_width / 2.0, y + 1.0)],
+                        RGBColor(255, shade, shade).filled(),
+                    )
+                }))
+                .map_err(drawing_error)?;
+        }
+        root.present().map_err(drawing_error)?;
+    }
+    Ok(svg)
+}
+
+fn drawing_error<E>(err: DrawingAreaErrorKind<E>) -> PlotError
+where
+    E: std::error::Error + Send + Sync,
+{
+    PlotError::DrawingError(err.to_string())
+}
+
+fn value_range(values: impl Iterator<Item = f64>) -> (f64, f64) {
+    let (min, max) = values.fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), value| {
+        (min.min(value), max.max(value))
+    });
+    if min < max {
+        (min, max)
+    } else {
+        // A flat spectrum still needs a non-empty range to be drawn.
+        (min - 0.5, max + 0.5)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    fn test_spectrum() -> ObservedSpectra {
+        ObservedSpectra {
+            frequencies: (0..100).map(|i| 1.42e9 + i as f64 * 1e4).collect(),
+            spectra: (0..100).map(|i| (i as f64 / 10.0).sin()).collect(),
+            observation_time: Duration::from_secs(10),
+            cycles: 10,
+        }
+    }
+
+    #[test]
+    fn test_render_spectrum_svg() {
+        let options = PlotOptions {
+            title: Some("Galactic plane l=30".to_string()),
+            ..Default::default()
+        };
+        let svg = render_spectrum_svg(&test_spectrum(), &options).unwrap();
+        assert!(svg.starts_with("<svg"));
+        assert!(svg.contains("Galactic plane l=30"));
+        assert!(svg.contains("Frequency [MHz]"));
+    }
+
+    #[test]
+    fn test_render_spectrum_velocities() {
+        let spectrum = test_spectrum();
+        let options = PlotOptions {
+            velocities: Some((0..spectrum.frequencies.len()).map(|i| i as f64).collect()),
+            ..Default::default()
+        };
+        let svg = render_spectrum_svg(&spectrum, &options).unwrap();
+        assert!(svg.contains("Velocity (LSR) [km/s]"));
+    }
+
+    #[test]
+    fn test_render_spectrum_pdf() {
+        let options = PlotOptions {
+            format: Some(PlotFormat::Pdf),
+            width: Some(400),
+            height: Some(250),
+            ..Default::default()
+        };
+        let pdf = render_spectrum(&test_spectrum(), &options).unwrap();
+        assert!(pdf.starts_with(b"%PDF"));
+    }
+
+    #[test]
+    fn test_render_spectrum_rejects_empty_spectrum() {
+        let spectrum = ObservedSpectra {
+            frequencies: vec![],
+            spectra: vec![],
+            observation_time: Duration::from_secs(0),
+            cycles: 0,
+        };
+        assert!(matches!(
+            render_spectrum_svg(&spectrum, &PlotOptions::default()),
+            Err(PlotError::EmptySpectrum)
+        ));
+    }
+
+    #[test]
+    fn test_render_spectrum_rejects_huge_plots() {
+        let options = PlotOptions {
+            width: Some(MAX_PLOT_SIDE + 1),
+            ..Default::default()
+        };
+        assert!(matches!(
+            render_spectrum_png(&test_spectrum(), &options),
+            Err(PlotError::InvalidSize(_, _))
+        ));
+    }
+}
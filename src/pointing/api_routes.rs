@@ -0,0 +1,180 @@
+use crate::database::{DataBase, Storage};
+use crate::pointing::{
+    apply_fitted_model, record_pointing_observation, set_pointing_model, PointingCorrection,
+    PointingError, PointingFit, PointingModel, PointingObservation, PointingOffset,
+};
+use crate::telescope::TelescopeCollection;
+use axum::{
+    extract::{Json, Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{get, post, put},
+    Router,
+};
+use chrono::Utc;
+use serde::Serialize;
+
+/// State of the pointing routes, which apply corrections to the telescopes.
+#[derive(Clone)]
+pub struct PointingState<StorageType: Storage> {
+    pub database: DataBase<StorageType>,
+    pub telescopes: TelescopeCollection,
+}
+
+pub fn routes(
+    telescopes: TelescopeCollection,
+    database: DataBase<impl Storage + 'static>,
+) -> Router {
+    Router::new()
+        .route("/:telescope_name", get(get_pointing))
+        .route("/:telescope_name/offset", put(put_pointing_offset))
+        .route("/:telescope_name/model", put(put_pointing_model))
+        .route(
+            "/:telescope_name/observations",
+            post(post_pointing_observation).delete(delete_pointing_observations),
+        )
+        .route("/:telescope_name/fit", post(post_pointing_fit))
+        .with_state(PointingState {
+            database,
+            telescopes,
+        })
+}
+
+impl IntoResponse for PointingError {
+    fn into_response(self) -> Response {
+        match self {
+            PointingError::UnknownTelescope => {
+                (StatusCode::NOT_FOUND, "Telescope not found".to_string()).into_response()
+            }
+            PointingError::NotTracking => (
+                StatusCode::CONFLICT,
+                "Point the telescope at a source first".to_string(),
+            )
+                .into_response(),
+            PointingError::TooFewObservations(count) => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                format!("Only {} calibration observations recorded", count),
+            )
+                .into_response(),
+            PointingError::Degenerate => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "Calibration observations have to cover a range of azimuths and altitudes"
+                    .to_string(),
+            )
+                .into_response(),
+            PointingError::ServiceUnavailable => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Failed to update pointing calibration".to_string(),
+            )
+                .into_response(),
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct PointingStatus {
+    correction: PointingCorrection,
+    observations: Vec<PointingObservation>,
+}
+
+async fn correction(
+    telescopes: &TelescopeCollection,
+    telescope_name: &str,
+) -> Result<PointingCorrection, PointingError> {
+    let telescopes = telescopes.read().await;
+    let container = telescopes
+        .get(telescope_name)
+        .ok_or(PointingError::UnknownTelescope)?;
+    let correction = container.telescope.lock().await.pointing_correction();
+    Ok(correction)
+}
+
+async fn get_pointing(
+    State(state): State<PointingState<impl Storage>>,
+    Path(telescope_name): Path<String>,
+) -> Result<Json<PointingStatus>, PointingError> {
+    let correction = correction(&state.telescopes, &telescope_name).await?;
+    let observations = state
+        .database
+        .get_data()
+        .await
+        .expect("As long as no one is manually editing the database, this should never fail.")
+        .pointing_observations
+        .into_iter()
+        .filter(|o| o.telescope_name == telescope_name)
+        .collect();
+    Ok(Json(PointingStatus {
+        correction,
+        observations,
+    }))
+}
+
+async fn put_pointing_offset(
+    State(state): State<PointingState<impl Storage>>,
+    Path(telescope_name): Path<String>,
+    Json(offset): Json<PointingOffset>,
+) -> Result<Json<PointingCorrection>, PointingError> {
+    let telescopes = state.telescopes.read().await;
+    let container = telescopes
+        .get(&telescope_name)
+        .ok_or(PointingError::UnknownTelescope)?;
+    let mut telescope = container.telescope.lock().await;
+    let correction = PointingCorrection {
+        offset,
+        ..telescope.pointing_correction()
+    };
+    telescope.set_pointing_correction(correction);
+    Ok(Json(correction))
+}
+
+async fn put_pointing_model(
+    State(state): State<PointingState<impl Storage>>,
+    Path(telescope_name): Path<String>,
+    Json(model): Json<PointingModel>,
+) -> Result<Json<PointingCorrection>, PointingError> {
+    set_pointing_model(&state.database, &state.telescopes, &telescope_name, model).await?;
+    Ok(Json(PointingCorrection {
+        model,
+        offset: PointingOffset::default(),
+    }))
+}
+
+async fn post_pointing_observation(
+    State(state): State<PointingState<impl Storage>>,
+    Path(telescope_name): Path<String>,
+) -> Result<Json<PointingObservation>, PointingError> {
+    Ok(Json(
+        record_pointing_observation(
+            &state.database,
+            &state.telescopes,
+            &telescope_name,
+            Utc::now(),
+        )
+        .await?,
+    ))
+}
+
+async fn delete_pointing_observations(
+    State(state): State<PointingState<impl Storage>>,
+    Path(telescope_name): Path<String>,
+) -> Result<StatusCode, PointingError> {
+    state
+        .database
+        .update_data(|mut data_model| {
+            data_model
+                .pointing_observations
+                .retain(|o| o.telescope_name != telescope_name);
+            data_model
+        })
+        .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn post_pointing_fit(
+    State(state): State<PointingState<impl Storage>>,
+    Path(telescope_name): Path<String>,
+) -> Result<Json<PointingFit>, PointingError> {
+    Ok(Json(
+        apply_fitted_model(&state.database, &state.telescopes, &telescope_name).await?,
+    ))
+}
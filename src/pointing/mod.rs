@@ -0,0 +1,463 @@
+//! Pointing model of a telescope, correcting for the imperfections of its
+//! mount.
+//!
+//! The model is the classical one for an alt-azimuth mount. With azimuth A
+//! and altitude E of the source, the telescope is commanded to A + ΔA and
+//! E + ΔE where
+//!
+//! ```text
+//! ΔA = IA + CA / cos E + NPAE tan E + AN sin A tan E - AW cos A tan E
+//! ΔE = IE + AN cos A + AW sin A + TF cos E
+//! ```
+//!
+//! IA and IE are encoder offsets, CA the collimation error, NPAE the
+//! non-perpendicularity of the axes, AN and AW the tilt of the azimuth axis
+//! towards north and west and TF the gravitational sag of the tube.
+//!
+//! The terms are fitted to calibration observations. To collect one, an
+//! admin points the telescope at a strong source, adjusts a manual offset
+//! until the signal peaks and records the point. The offset that was needed
+//! on top of the current model is stored with the direction of the source.
+//! An admin can also set the terms directly, e.g. only IA and IE for a
+//! telescope that is off by constant offsets.
+
+use crate::angles::Radians;
+use crate::coords::Direction;
+use crate::database::{DataBase, DataBaseError, Storage};
+use crate::telescope::TelescopeCollection;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+pub mod api_routes;
+
+/// Fewest calibration observations a model is fitted to. Each observation
+/// gives two equations for the seven terms.
+pub const MIN_POINTING_OBSERVATIONS: usize = 6;
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone, Default)]
+pub struct PointingModel {
+    pub ia: Radians,
+    pub ie: Radians,
+    pub ca: Radians,
+    pub npae: Radians,
+    pub an: Radians,
+    pub aw: Radians,
+    pub tf: Radians,
+}
+
+/// Offset added to a direction, in the same sense as the model.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone, Default)]
+pub struct PointingOffset {
+    pub azimuth: Radians,
+    pub altitude: Radians,
+}
+
+/// What a tracker applies to the direction of its target: the pointing
+/// model and the manual offset used while calibrating.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone, Default)]
+pub struct PointingCorrection {
+    pub model: PointingModel,
+    pub offset: PointingOffset,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct PointingObservation {
+    pub telescope_name: String,
+    pub time: DateTime<Utc>,
+    /// Direction of the source.
+    pub direction: Direction,
+    /// Offset from the direction of the source to where the signal peaked.
+    pub offset: PointingOffset,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct PointingFit {
+    pub model: PointingModel,
+    /// Root mean square of the residuals on the sky.
+    pub rms: Radians,
+    pub observations: usize,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum PointingError {
+    UnknownTelescope,
+    /// The telescope has to track a source to record an observation.
+    NotTracking,
+    TooFewObservations(usize),
+    /// The observations do not constrain every term, e.g. because they were
+    /// all made at the same altitude.
+    Degenerate,
+    ServiceUnavailable,
+}
+
+impl From<DataBaseError> for PointingError {
+    fn from(_source: DataBaseError) -> Self {
+        Self::ServiceUnavailable
+    }
+}
+
+/// Coefficients of the terms, in the order of `PointingModel::terms`, in
+/// the equations for ΔA cos E and ΔE.
+fn design_rows(direction: Direction) -> ([f64; 7], [f64; 7]) {
+    let (sin_a, cos_a) = (direction.azimuth.sin(), direction.azimuth.cos());
+    let (sin_e, cos_e) = (direction.altitude.sin(), direction.altitude.cos());
+    (
+        [cos_e, 0.0, 1.0, sin_e, sin_a * sin_e, -cos_a * sin_e, 0.0],
+        [0.0, 1.0, 0.0, 0.0, cos_a, sin_a, cos_e],
+    )
+}
+
+fn dot(a: &[f64; 7], b: &[f64; 7]) -> f64 {
+    a.iter().zip(b).map(|(a, b)| a * b).sum()
+}
+
+impl PointingModel {
+    fn terms(&self) -> [f64; 7] {
+        [
+            self.ia.0,
+            self.ie.0,
+            self.ca.0,
+            self.npae.0,
+            self.an.0,
+            self.aw.0,
+            self.tf.0,
+        ]
+    }
+
+    fn from_terms(terms: [f64; 7]) -> PointingModel {
+        let [ia, ie, ca, npae, an, aw, tf] = terms.map(Radians);
+        PointingModel {
+            ia,
+            ie,
+            ca,
+            npae,
+            an,
+            aw,
+            tf,
+        }
+    }
+
+    /// Offset predicted by the model towards `direction`.
+    pub fn offset(&self, direction: Direction) -> PointingOffset {
+        let (azimuth_row, altitude_row) = design_rows(direction);
+        let terms = self.terms();
+        PointingOffset {
+            azimuth: Radians(dot(&azimuth_row, &terms) / direction.altitude.cos()),
+            altitude: Radians(dot(&altitude_row, &terms)),
+        }
+    }
+}
+
+impl PointingCorrection {
+    /// Direction to command for a source in `direction`.
+    pub fn apply(&self, direction: Direction) -> Direction {
+        let model = self.model.offset(direction);
+        Direction {
+            azimuth: direction.azimuth + model.azimuth + self.offset.azimuth,
+            altitude: direction.altitude + model.altitude + self.offset.altitude,
+        }
+    }
+
+    /// Total offset towards `direction`, as recorded for calibration.
+    pub fn total_offset(&self, direction: Direction) -> PointingOffset {
+        let model = self.model.offset(direction);
+        PointingOffset {
+            azimuth: model.azimuth + self.offset.azimuth,
+            altitude: model.altitude + self.offset.altitude,
+        }
+    }
+}
+
+/// Solve `matrix x = vector` by Gaussian elimination, None if the matrix is
+/// singular.
+fn solve(mut matrix: [[f64; 7]; 7], mut vector: [f64; 7]) -> Option<[f64; 7]> {
+    let scale = matrix
+        .iter()
+        .flatten()
+        .fold(0.0f64, |max, value| max.max(value.abs()));
+    for column in 0..7 {
+        let pivot = (column..7).max_by(|a, b| {
+            matrix[*a][column]
+                .abs()
+                .total_cmp(&matrix[*b][column].abs())
+        })?;
+        if matrix[pivot][column].abs() <= 1e-12 * scale {
+            return None;
+        }
+        matrix.swap(column, pivot);
+        vector.swap(column, pivot);
+        let pivot_row = matrix[column];
+        for row in column + 1..7 {
+            let factor = matrix[row][column] / pivot_row[column];
+            for (value, pivot_value) in matrix[row][column..].iter_mut().zip(&pivot_row[column..]) {
+                *value -= factor * pivot_value;
+            }
+            vector[row] -= factor * vector[column];
+        }
+    }
+    let mut solution = [0.0; 7];
+    for row in (0..7).rev() {
+        let sum: f64 = (row + 1..7).map(|k| matrix[row][k] * solution[k]).sum();
+        solution[row] = (vector[row] - sum) / matrix[row][row];
+    }
+    Some(solution)
+}
+
+/// Least squares fit of a pointing model to `observations`.
+pub fn fit_pointing_model(
+    observations: &[PointingObservation],
+) -> Result<PointingFit, PointingError> {
+    if observations.len() < MIN_POINTING_OBSERVATIONS {
+        return Err(PointingError::TooFewObservations(observations.len()));
+    }
+    let equations: Vec<([f64; 7], f64)> = observations
+        .iter()
+        .flat_map(|observation| {
+            let (azimuth_row, altitude_row) = design_rows(observation.direction);
+            [
+                (
+                    azimuth_row,
+                    observation.offset.azimuth.0 * observation.direction.altitude.cos(),
+                ),
+                (altitude_row, observation.offset.altitude.0),
+            ]
+        })
+        .collect();
+
+    let mut normal_matrix = [[0.0; 7]; 7];
+    let mut normal_vector = [0.0; 7];
+    for (row, value) in &equations {
+        for i in 0..7 {
+            normal_vector[i] += row[i] * value;
+            for j in 0..7 {
+                normal_matrix[i][j] += row[i] * row[j];
+            }
+        }
+    }
+    let terms = solve(normal_matrix, normal_vector).ok_or(PointingError::Degenerate)?;
+    let squared_residuals: f64 = equations
+        .iter()
+        .map(|(row, value)| (value - dot(row, &terms)).powi(2))
+        .sum();
+    Ok(PointingFit {
+        model: PointingModel::from_terms(terms),
+        rms: Radians((squared_residuals / observations.len() as f64).sqrt()),
+        observations: observations.len(),
+    })
+}
+
+/// Record where the signal of the source tracked by `telescope_name` peaks,
+/// given the pointing correction currently applied.
+pub async fn record_pointing_observation(
+    db: &DataBase<impl Storage>,
+    telescopes: &TelescopeCollection,
+    telescope_name: &str,
+    now: DateTime<Utc>,
+) -> Result<PointingObservation, PointingError> {
+    let location = db
+        .get_data()
+        .await?
+        .telescopes
+        .into_iter()
+        .find(|t| t.name == telescope_name)
+        .ok_or(PointingError::UnknownTelescope)?
+        .location;
+    let telescope = telescopes
+        .read()
+        .await
+        .get(telescope_name)
+        .ok_or(PointingError::UnknownTelescope)?
+        .telescope
+        .clone();
+    let (target, correction) = {
+        let telescope = telescope.lock().await;
+        let target = telescope
+            .get_target()
+            .await
+            .map_err(|_| PointingError::NotTracking)?;
+        (target, telescope.pointing_correction())
+    };
+    let direction = target
+        .horizontal(location, now)
+        .ok_or(PointingError::NotTracking)?;
+
+    let observation = PointingObservation {
+        telescope_name: telescope_name.to_string(),
+        time: now,
+        direction,
+        offset: correction.total_offset(direction),
+    };
+    db.update_data(|mut data_model| {
+        data_model.pointing_observations.push(observation.clone());
+        data_model
+    })
+    .await?;
+    Ok(observation)
+}
+
+/// Store `model` with `telescope_name` and start using it, without any
+/// manual offset. An admin can set e.g. only IA and IE to correct the
+/// telescope with constant offsets, without any calibration observations.
+pub async fn set_pointing_model(
+    db: &DataBase<impl Storage>,
+    telescopes: &TelescopeCollection,
+    telescope_name: &str,
+    model: PointingModel,
+) -> Result<(), PointingError> {
+    let mut result = Err(PointingError::UnknownTelescope);
+    db.update_data(|mut data_model| {
+        if let Some(telescope) = data_model
+            .telescopes
+            .iter_mut()
+            .find(|t| t.name == telescope_name)
+        {
+            telescope.pointing_model = model;
+            result = Ok(());
+        }
+        data_model
+    })
+    .await?;
+    result?;
+    if let Some(container) = telescopes.read().await.get(telescope_name) {
+        container
+            .telescope
+            .lock()
+            .await
+            .set_pointing_correction(PointingCorrection {
+                model,
+                offset: PointingOffset::default(),
+            });
+    }
+    Ok(())
+}
+
+/// Fit a model to the observations of `telescope_name`, store it with the
+/// telescope and start using it, without any manual offset.
+pub async fn apply_fitted_model(
+    db: &DataBase<impl Storage>,
+    telescopes: &TelescopeCollection,
+    telescope_name: &str,
+) -> Result<PointingFit, PointingError> {
+    let data_model = db.get_data().await?;
+    if !data_model
+        .telescopes
+        .iter()
+        .any(|t| t.name == telescope_name)
+    {
+        return Err(PointingError::UnknownTelescope);
+    }
+    let observations: Vec<PointingObservation> = data_model
+        .pointing_observations
+        .into_iter()
+        .filter(|o| o.telescope_name == telescope_name)
+        .collect();
+    let fit = fit_pointing_model(&observations)?;
+    set_pointing_model(db, telescopes, telescope_name, fit.model).await?;
+    log::info!(
+        "Fitted pointing model of {} to {} observations, rms {:.4}°",
+        telescope_name,
+        fit.observations,
+        fit.rms.to_degrees().0
+    );
+    Ok(fit)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::angles::Degrees;
+    use crate::database::create_in_memory_database;
+    use crate::telescopes::test_utils::fake_telescope;
+
+    #[test]
+    fn test_fit_pointing_model() {
+        let arcmin = |value: f64| Degrees(value / 60.0).to_radians();
+        let model = PointingModel {
+            ia: arcmin(12.0),
+            ie: arcmin(-5.0),
+            ca: arcmin(3.0),
+            npae: arcmin(-2.0),
+            an: arcmin(1.5),
+            aw: arcmin(-0.8),
+            tf: arcmin(4.0),
+        };
+        let correction = PointingCorrection {
+            model,
+            offset: PointingOffset::default(),
+        };
+        let observations: Vec<PointingObservation> = (0..12)
+            .map(|i| {
+                let direction = Direction {
+                    azimuth: Degrees(30.0 * i as f64).to_radians(),
+                    altitude: Degrees(15.0 + 5.0 * (i % 7) as f64).to_radians(),
+                };
+                PointingObservation {
+                    telescope_name: "fake".to_string(),
+                    time: Utc::now(),
+                    direction,
+                    offset: correction.total_offset(direction),
+                }
+            })
+            .collect();
+
+        let fit = fit_pointing_model(&observations).unwrap();
+        for (fitted, expected) in fit.model.terms().iter().zip(model.terms()) {
+            assert!((fitted - expected).abs() < 1e-9);
+        }
+        assert!(fit.rms.0 < 1e-9);
+
+        assert_eq!(
+            fit_pointing_model(&observations[..3]),
+            Err(PointingError::TooFewObservations(3))
+        );
+        // Without different altitudes the terms can not be told apart.
+        let same_altitude: Vec<PointingObservation> = observations
+            .iter()
+            .cloned()
+            .map(|mut o| {
+                o.direction.altitude = Degrees(30.0).to_radians();
+                o
+            })
+            .collect();
+        assert_eq!(
+            fit_pointing_model(&same_altitude),
+            Err(PointingError::Degenerate)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_set_pointing_model() {
+        let db = create_in_memory_database();
+        let telescopes = TelescopeCollection::default();
+        db.update_data(|mut data_model| {
+            data_model.telescopes = vec![fake_telescope("fake")];
+            data_model
+        })
+        .await
+        .unwrap();
+
+        let model = PointingModel {
+            ia: Degrees(0.5).to_radians(),
+            ie: Degrees(-0.2).to_radians(),
+            ..Default::default()
+        };
+        assert_eq!(
+            set_pointing_model(&db, &telescopes, "salsa", model).await,
+            Err(PointingError::UnknownTelescope)
+        );
+        set_pointing_model(&db, &telescopes, "fake", model)
+            .await
+            .unwrap();
+        assert_eq!(
+            db.get_data().await.unwrap().telescopes[0].pointing_model,
+            model
+        );
+        // Constant terms offset every direction the same.
+        let offset = model.offset(Direction {
+            azimuth: Radians(1.0),
+            altitude: Radians(0.5),
+        });
+        assert!((offset.azimuth.0 - model.ia.0).abs() < 1e-12);
+        assert!((offset.altitude.0 - model.ie.0).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,96 @@
+use crate::database::{DataBase, Storage};
+use crate::plot::render_heatmap_svg;
+use crate::rfi::RfiScan;
+use axum::{
+    extract::{Json, Path, State},
+    http::header,
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+
+pub fn routes(database: DataBase<impl Storage + 'static>) -> Router {
+    Router::new()
+        .route("/:telescope_id", get(get_rfi_scans))
+        .route("/:telescope_id/heatmap", get(get_rfi_heatmap))
+        .with_state(database)
+}
+
+pub async fn fetch_rfi_scans(db: &DataBase<impl Storage>, telescope_id: &str) -> Vec<RfiScan> {
+    db.get_data()
+        .await
+        .expect("As long as no one is manually editing the database, this should never fail.")
+        .rfi_scans
+        .into_iter()
+        .filter(|scan| scan.telescope_name == telescope_id)
+        .collect()
+}
+
+async fn get_rfi_scans(
+    State(db): State<DataBase<impl Storage>>,
+    Path(telescope_id): Path<String>,
+) -> Json<Vec<RfiScan>> {
+    Json(fetch_rfi_scans(&db, &telescope_id).await)
+}
+
+async fn get_rfi_heatmap(
+    State(db): State<DataBase<impl Storage>>,
+    Path(telescope_id): Path<String>,
+) -> Response {
+    let rows: Vec<(Vec<f64>, Vec<f64>)> = fetch_rfi_scans(&db, &telescope_id)
+        .await
+        .into_iter()
+        .map(|scan| (scan.frequencies, scan.occupancy))
+        .collect();
+    let title = format!("RFI occupancy for {}", telescope_id);
+    match render_heatmap_svg(&title, &rows) {
+        Ok(svg) => ([(header::CONTENT_TYPE, "image/svg+xml")], svg).into_response(),
+        Err(error) => error.into_response(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::database::create_in_memory_database;
+    use crate::rfi::store_rfi_scan;
+    use axum::{
+        body::Body,
+        http::{self, Request, StatusCode},
+    };
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_get_rfi_scans() {
+        let db = create_in_memory_database();
+        for telescope_name in ["fake", "brage"] {
+            store_rfi_scan(
+                &db,
+                RfiScan {
+                    telescope_name: telescope_name.to_string(),
+                    start: chrono::Utc::now(),
+                    frequencies: vec![1.4205e9],
+                    occupancy: vec![0.25],
+                },
+            )
+            .await
+            .unwrap();
+        }
+
+        let response = routes(db)
+            .oneshot(
+                Request::builder()
+                    .method(http::Method::GET)
+                    .uri("/fake")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let scans: Vec<RfiScan> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(scans.len(), 1);
+        assert_eq!(scans[0].telescope_name, "fake");
+    }
+}
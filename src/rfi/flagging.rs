@@ -0,0 +1,229 @@
+//! Flagging of interference in the spectra measured by a receiver.
+//!
+//! Each power spectrum is flagged at full FFT resolution, before it is
+//! averaged down to the channels of the measurement, and the flagged points
+//! are replaced by the median of the unflagged points around them. That
+//! keeps a narrow spike from leaking into a whole channel. The algorithm is
+//! chosen in the receiver configuration, while frequencies that are always
+//! occupied at a site can be masked in the configuration of the telescope.
+
+use crate::telescopes::FrequencyRange;
+use serde::{Deserialize, Serialize};
+
+/// Block size of the median filter, and of the neighbourhood flagged
+/// points are replaced from.
+pub const DEFAULT_MEDIAN_KERNEL: usize = 32;
+/// Relative deviation from the median of its block flagging a point.
+pub const DEFAULT_MEDIAN_THRESHOLD: f64 = 0.1;
+/// Sigma clipping stops after this many rounds even if it still flags
+/// points.
+pub const MAX_SIGMA_CLIP_ITERATIONS: usize = 10;
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+pub enum RfiFlagging {
+    /// Only the frequency mask of the telescope is flagged.
+    Off,
+    /// Points deviating from the median of their block of `kernel` points
+    /// by more than `threshold` times the median.
+    MedianFilter { kernel: usize, threshold: f64 },
+    /// Points further than `sigma` standard deviations from the mean of the
+    /// unflagged points, repeated until nothing more is flagged or for at
+    /// most `iterations` rounds.
+    SigmaClip { sigma: f64, iterations: usize },
+}
+
+impl Default for RfiFlagging {
+    fn default() -> Self {
+        RfiFlagging::MedianFilter {
+            kernel: DEFAULT_MEDIAN_KERNEL,
+            threshold: DEFAULT_MEDIAN_THRESHOLD,
+        }
+    }
+}
+
+impl RfiFlagging {
+    pub fn is_valid(&self) -> bool {
+        match *self {
+            RfiFlagging::Off => true,
+            RfiFlagging::MedianFilter { kernel, threshold } => kernel > 0 && threshold > 0.0,
+            RfiFlagging::SigmaClip { sigma, iterations } => {
+                sigma > 0.0 && (1..=MAX_SIGMA_CLIP_ITERATIONS).contains(&iterations)
+            }
+        }
+    }
+}
+
+fn median(values: &[f64]) -> Option<f64> {
+    let mut sorted = values.to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let n = sorted.len();
+    match n {
+        0 => None,
+        _ if n.is_multiple_of(2) => Some(0.5 * (sorted[n / 2 - 1] + sorted[n / 2])),
+        _ => Some(sorted[n / 2]),
+    }
+}
+
+fn median_filter(spectrum: &[f64], kernel: usize, threshold: f64, flags: &mut [bool]) {
+    // A last block shorter than the kernel is left as it is.
+    for (block, block_flags) in spectrum.chunks_exact(kernel).zip(flags.chunks_mut(kernel)) {
+        let Some(m) = median(block) else { continue };
+        for (value, flag) in block.iter().zip(block_flags) {
+            if (value - m).abs() > threshold * m {
+                *flag = true;
+            }
+        }
+    }
+}
+
+fn sigma_clip(spectrum: &[f64], sigma: f64, iterations: usize, flags: &mut [bool]) {
+    for _ in 0..iterations {
+        let kept: Vec<f64> = spectrum
+            .iter()
+            .zip(flags.iter())
+            .filter(|(_, &flag)| !flag)
+            .map(|(&value, _)| value)
+            .collect();
+        if kept.len() < 2 {
+            return;
+        }
+        let mean = kept.iter().sum::<f64>() / kept.len() as f64;
+        let deviation = (kept.iter().map(|value| (value - mean).powi(2)).sum::<f64>()
+            / (kept.len() - 1) as f64)
+            .sqrt();
+        let mut flagged_any = false;
+        for (value, flag) in spectrum.iter().zip(flags.iter_mut()) {
+            if !*flag && (value - mean).abs() > sigma * deviation {
+                *flag = true;
+                flagged_any = true;
+            }
+        }
+        if !flagged_any {
+            return;
+        }
+    }
+}
+
+/// Flag the points of `spectrum` at `frequencies` with `flagging`, and those
+/// inside `mask`, and replace them. Returns the flags.
+pub fn flag_interference(
+    spectrum: &mut [f64],
+    frequencies: &[f64],
+    flagging: RfiFlagging,
+    mask: &[FrequencyRange],
+) -> Vec<bool> {
+    let mut flags: Vec<bool> = frequencies
+        .iter()
+        .map(|f| mask.iter().any(|range| range.min <= *f && *f <= range.max))
+        .collect();
+    flags.resize(spectrum.len(), false);
+    match flagging {
+        RfiFlagging::Off => {}
+        RfiFlagging::MedianFilter { kernel, threshold } => {
+            median_filter(spectrum, kernel, threshold, &mut flags)
+        }
+        RfiFlagging::SigmaClip { sigma, iterations } => {
+            sigma_clip(spectrum, sigma, iterations, &mut flags)
+        }
+    }
+    replace_flagged(spectrum, &flags);
+    flags
+}
+
+/// Replace flagged points with the median of the unflagged points in their
+/// block, or of the whole block if all of it is flagged.
+fn replace_flagged(spectrum: &mut [f64], flags: &[bool]) {
+    for (block, block_flags) in spectrum
+        .chunks_mut(DEFAULT_MEDIAN_KERNEL)
+        .zip(flags.chunks(DEFAULT_MEDIAN_KERNEL))
+    {
+        if !block_flags.contains(&true) {
+            continue;
+        }
+        let kept: Vec<f64> = block
+            .iter()
+            .zip(block_flags)
+            .filter(|(_, &flag)| !flag)
+            .map(|(&value, _)| value)
+            .collect();
+        let Some(replacement) = median(&kept).or_else(|| median(block)) else {
+            continue;
+        };
+        for (value, &flag) in block.iter_mut().zip(block_flags) {
+            if flag {
+                *value = replacement;
+            }
+        }
+    }
+}
+
+/// Fraction of the points averaged into each of `channels` channels that
+/// were flagged.
+pub fn flagged_fractions(flags: &[bool], channels: usize) -> Vec<f64> {
+    let points = flags.len() / channels.max(1);
+    if points == 0 {
+        return vec![0.0; channels];
+    }
+    flags
+        .chunks_exact(points)
+        .take(channels)
+        .map(|chunk| chunk.iter().filter(|&&flag| flag).count() as f64 / points as f64)
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn spiky_spectrum() -> (Vec<f64>, Vec<f64>) {
+        let frequencies: Vec<f64> = (0..128).map(|i| 1.42e9 + i as f64 * 1e3).collect();
+        let mut spectrum: Vec<f64> = (0..128).map(|i| 100.0 + (i % 3) as f64).collect();
+        spectrum[10] = 500.0;
+        spectrum[70] = 20.0;
+        (frequencies, spectrum)
+    }
+
+    #[test]
+    fn test_median_filter() {
+        let (frequencies, mut spectrum) = spiky_spectrum();
+        let flags = flag_interference(&mut spectrum, &frequencies, RfiFlagging::default(), &[]);
+        let flagged: Vec<usize> = (0..128).filter(|&i| flags[i]).collect();
+        assert_eq!(flagged, vec![10, 70]);
+        assert_eq!(spectrum[10], 101.0);
+        assert_eq!(spectrum[70], 101.0);
+    }
+
+    #[test]
+    fn test_sigma_clip_and_mask() {
+        let (frequencies, mut spectrum) = spiky_spectrum();
+        let mask = [FrequencyRange {
+            min: 1.42e9 + 99.5e3,
+            max: 1.42e9 + 101.5e3,
+        }];
+        let flagging = RfiFlagging::SigmaClip {
+            sigma: 5.0,
+            iterations: 3,
+        };
+        let flags = flag_interference(&mut spectrum, &frequencies, flagging, &mask);
+        let flagged: Vec<usize> = (0..128).filter(|&i| flags[i]).collect();
+        assert_eq!(flagged, vec![10, 70, 100, 101]);
+        assert!(spectrum.iter().all(|value| (100.0..=102.0).contains(value)));
+        assert_eq!(
+            flagged_fractions(&flags, 4),
+            vec![1.0 / 32.0, 0.0, 1.0 / 32.0, 2.0 / 32.0]
+        );
+    }
+
+    #[test]
+    fn test_off_only_masks() {
+        let (frequencies, mut spectrum) = spiky_spectrum();
+        let flags = flag_interference(&mut spectrum, &frequencies, RfiFlagging::Off, &[]);
+        assert!(!flags.contains(&true));
+        assert_eq!(spectrum[10], 500.0);
+        assert!(!RfiFlagging::SigmaClip {
+            sigma: 3.0,
+            iterations: 0
+        }
+        .is_valid());
+    }
+}
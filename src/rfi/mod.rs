@@ -0,0 +1,283 @@
+use crate::database::{DataBase, DataBaseError, Storage};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+pub mod api_routes;
+pub mod flagging;
+pub mod routes;
+
+// A channel is considered occupied by interference when its power exceeds the
+// median of the spectrum by this fraction.
+pub const RFI_DETECTION_THRESHOLD: f64 = 0.5;
+pub const RFI_OCCUPANCY_BIN_WIDTH: f64 = 1e6;
+// Oldest scans are dropped to keep the database from growing without bound.
+pub const MAX_STORED_RFI_SCANS_PER_TELESCOPE: usize = 500;
+
+/// How and when a telescope should scan its tuning range for interference.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct RfiScanDefinition {
+    pub start_frequency: f64,
+    pub stop_frequency: f64,
+    pub interval_minutes: u64,
+}
+
+impl RfiScanDefinition {
+    /// A new scan is due if no scan has been made or if the last one is
+    /// older than the configured interval.
+    pub fn is_due(&self, last_scan: Option<DateTime<Utc>>, now: DateTime<Utc>) -> bool {
+        match last_scan {
+            None => true,
+            Some(last_scan) => {
+                now.signed_duration_since(last_scan)
+                    >= chrono::Duration::minutes(self.interval_minutes as i64)
+            }
+        }
+    }
+}
+
+/// Fraction of channels occupied by interference in frequency bins of
+/// `RFI_OCCUPANCY_BIN_WIDTH`.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct RfiScan {
+    pub telescope_name: String,
+    pub start: DateTime<Utc>,
+    /// Center frequency of each bin in Hz.
+    pub frequencies: Vec<f64>,
+    pub occupancy: Vec<f64>,
+}
+
+impl RfiScan {
+    /// Build a scan from power spectra taken at different tunings.
+    ///
+    /// Each step is a pair of channel frequencies and channel powers.
+    pub fn from_spectra(
+        telescope_name: String,
+        start: DateTime<Utc>,
+        steps: &[(Vec<f64>, Vec<f64>)],
+    ) -> RfiScan {
+        let mut frequencies = Vec::new();
+        let mut flags = Vec::new();
+        for (step_frequencies, power) in steps {
+            frequencies.extend_from_slice(step_frequencies);
+            flags.extend(detect_rfi(power, RFI_DETECTION_THRESHOLD));
+        }
+        let (frequencies, occupancy) = occupancy(&frequencies, &flags, RFI_OCCUPANCY_BIN_WIDTH);
+        RfiScan {
+            telescope_name,
+            start,
+            frequencies,
+            occupancy,
+        }
+    }
+}
+
+/// Flag channels whose power exceeds the median power by more than
+/// `threshold` (relative).
+pub fn detect_rfi(power: &[f64], threshold: f64) -> Vec<bool> {
+    if power.is_empty() {
+        return vec![];
+    }
+    let mut sorted = power.to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let median = sorted[sorted.len() / 2];
+    power
+        .iter()
+        .map(|&value| value > median * (1.0 + threshold))
+        .collect()
+}
+
+/// Group flagged channels into frequency bins of `bin_width` Hz and compute
+/// the fraction of flagged channels in each bin.
+///
+/// Returns the bin center frequencies and their occupancy, sorted by
+/// frequency.
+pub fn occupancy(frequencies: &[f64], flags: &[bool], bin_width: f64) -> (Vec<f64>, Vec<f64>) {
+    let mut bins = std::collections::BTreeMap::<i64, (usize, usize)>::new();
+    for (frequency, &flagged) in frequencies.iter().zip(flags) {
+        let bin = bins
+            .entry((frequency / bin_width).floor() as i64)
+            .or_insert((0, 0));
+        bin.0 += flagged as usize;
+        bin.1 += 1;
+    }
+    bins.into_iter()
+        .map(|(bin, (flagged, total))| {
+            (
+                (bin as f64 + 0.5) * bin_width,
+                flagged as f64 / total as f64,
+            )
+        })
+        .unzip()
+}
+
+/// Average occupancy per frequency bin over several scans, sorted by
+/// frequency.
+pub fn mean_occupancy<'a>(scans: impl IntoIterator<Item = &'a RfiScan>) -> Vec<(f64, f64)> {
+    let mut bins = std::collections::BTreeMap::<i64, (f64, usize)>::new();
+    for scan in scans {
+        for (frequency, occupancy) in scan.frequencies.iter().zip(&scan.occupancy) {
+            // Key on whole Hz so that bins from different scans line up.
+            let bin = bins.entry(frequency.round() as i64).or_insert((0.0, 0));
+            bin.0 += occupancy;
+            bin.1 += 1;
+        }
+    }
+    bins.into_iter()
+        .map(|(frequency, (sum, count))| (frequency as f64, sum / count as f64))
+        .collect()
+}
+
+/// Pick a reference frequency for frequency switching with as little
+/// interference as possible.
+///
+/// Candidates are the bin centers of `occupancy` (as returned by
+/// `mean_occupancy`) at least `bandwidth` and at most `max_offset` Hz away
+/// from `signal_frequency`, so that the reference band does not overlap the
+/// signal band. A candidate is scored by the mean occupancy of all bins
+/// within its band, and ties are broken by picking the one closest to the
+/// signal frequency. Returns None if there are no candidates.
+pub fn select_reference_frequency(
+    occupancy: &[(f64, f64)],
+    signal_frequency: f64,
+    bandwidth: f64,
+    max_offset: f64,
+) -> Option<f64> {
+    let band_occupancy = |center: f64| {
+        let in_band: Vec<f64> = occupancy
+            .iter()
+            .filter(|(frequency, _)| (frequency - center).abs() <= 0.5 * bandwidth)
+            .map(|(_, occupancy)| *occupancy)
+            .collect();
+        in_band.iter().sum::<f64>() / in_band.len() as f64
+    };
+    occupancy
+        .iter()
+        .map(|(frequency, _)| *frequency)
+        .filter(|frequency| {
+            let offset = (frequency - signal_frequency).abs();
+            offset >= bandwidth && offset <= max_offset
+        })
+        .map(|frequency| (band_occupancy(frequency), frequency))
+        .min_by(|(a, frequency_a), (b, frequency_b)| {
+            a.total_cmp(b).then(
+                (frequency_a - signal_frequency)
+                    .abs()
+                    .total_cmp(&(frequency_b - signal_frequency).abs()),
+            )
+        })
+        .map(|(_, frequency)| frequency)
+}
+
+/// Store a completed scan, dropping the oldest scans of the same telescope
+/// if there are too many.
+pub async fn store_rfi_scan(
+    db: &DataBase<impl Storage>,
+    scan: RfiScan,
+) -> Result<(), DataBaseError> {
+    db.update_data(|mut data_model| {
+        let stored = data_model
+            .rfi_scans
+            .iter()
+            .filter(|s| s.telescope_name == scan.telescope_name)
+            .count();
+        let mut to_remove = (stored + 1).saturating_sub(MAX_STORED_RFI_SCANS_PER_TELESCOPE);
+        data_model.rfi_scans.retain(|s| {
+            if to_remove > 0 && s.telescope_name == scan.telescope_name {
+                to_remove -= 1;
+                false
+            } else {
+                true
+            }
+        });
+        data_model.rfi_scans.push(scan);
+        data_model
+    })
+    .await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::database::create_in_memory_database;
+
+    #[test]
+    fn test_detect_rfi() {
+        let power = vec![1.0, 1.1, 0.9, 5.0, 1.0];
+        assert_eq!(
+            detect_rfi(&power, RFI_DETECTION_THRESHOLD),
+            vec![false, false, false, true, false]
+        );
+    }
+
+    #[test]
+    fn test_occupancy() {
+        let frequencies = vec![0.1e6, 0.6e6, 1.1e6, 1.6e6];
+        let flags = vec![true, false, true, true];
+        let (centers, occupancy) = occupancy(&frequencies, &flags, 1e6);
+        assert_eq!(centers, vec![0.5e6, 1.5e6]);
+        assert_eq!(occupancy, vec![0.5, 1.0]);
+    }
+
+    #[test]
+    fn test_mean_occupancy() {
+        let scan = |occupancy: Vec<f64>| RfiScan {
+            telescope_name: "fake".to_string(),
+            start: Utc::now(),
+            frequencies: vec![0.5e6, 1.5e6],
+            occupancy,
+        };
+        let scans = vec![scan(vec![0.0, 1.0]), scan(vec![0.5, 0.0])];
+        assert_eq!(mean_occupancy(&scans), vec![(0.5e6, 0.25), (1.5e6, 0.5)]);
+    }
+
+    #[test]
+    fn test_select_reference_frequency() {
+        let occupancy: Vec<(f64, f64)> = (1400..1440)
+            .map(|mhz| {
+                let frequency = (mhz as f64 + 0.5) * 1e6;
+                // Interference just below the signal band
+                let occupied = (1416..1419).contains(&mhz);
+                (frequency, if occupied { 0.8 } else { 0.0 })
+            })
+            .collect();
+        assert_eq!(
+            select_reference_frequency(&occupancy, 1.4204e9, 2.5e6, 5e6),
+            Some(1.4235e9)
+        );
+        assert_eq!(
+            select_reference_frequency(&occupancy, 1.4204e9, 2.5e6, 1e6),
+            None
+        );
+    }
+
+    #[test]
+    fn test_is_due() {
+        let definition = RfiScanDefinition {
+            start_frequency: 1.4e9,
+            stop_frequency: 1.44e9,
+            interval_minutes: 60,
+        };
+        let now = Utc::now();
+        assert!(definition.is_due(None, now));
+        assert!(!definition.is_due(Some(now - chrono::Duration::minutes(30)), now));
+        assert!(definition.is_due(Some(now - chrono::Duration::minutes(60)), now));
+    }
+
+    #[tokio::test]
+    async fn test_store_rfi_scan_drops_oldest() {
+        let db = create_in_memory_database();
+        let scan = |name: &str, minutes| RfiScan {
+            telescope_name: name.to_string(),
+            start: Utc::now() + chrono::Duration::minutes(minutes),
+            frequencies: vec![],
+            occupancy: vec![],
+        };
+        store_rfi_scan(&db, scan("other", 0)).await.unwrap();
+        for minutes in 0..MAX_STORED_RFI_SCANS_PER_TELESCOPE as i64 + 2 {
+            store_rfi_scan(&db, scan("fake", minutes)).await.unwrap();
+        }
+        let scans = db.get_data().await.unwrap().rfi_scans;
+        assert_eq!(scans.len(), MAX_STORED_RFI_SCANS_PER_TELESCOPE + 1);
+        assert_eq!(scans[0].telescope_name, "other");
+    }
+}
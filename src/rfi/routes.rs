@@ -0,0 +1,85 @@
+use crate::database::{DataBase, Storage};
+use crate::format::{filters, Locale};
+use crate::rfi::api_routes::fetch_rfi_scans;
+use crate::rfi::mean_occupancy;
+use crate::template::HtmlTemplate;
+use askama::Template;
+use axum::{
+    extract::{Path, State},
+    response::IntoResponse,
+    routing::get,
+    Router,
+};
+
+// Number of frequency bins listed as candidates on the report page.
+const CLEANEST_BINS_LISTED: usize = 10;
+
+pub fn routes(database: DataBase<impl Storage + 'static>) -> Router {
+    Router::new()
+        .route("/", get(get_rfi_overview))
+        .route("/:telescope_id", get(get_rfi_report))
+        .with_state(database)
+}
+
+#[derive(Template)]
+#[template(path = "rfi_overview.html")]
+struct RfiOverviewTemplate {
+    telescope_names: Vec<String>,
+}
+
+async fn get_rfi_overview<StorageType>(State(db): State<DataBase<StorageType>>) -> impl IntoResponse
+where
+    StorageType: Storage,
+{
+    let telescope_names = db
+        .get_data()
+        .await
+        .expect("As long as no one is manually editing the database, this should never fail.")
+        .telescopes
+        .iter()
+        .filter(|t| t.rfi_scan.is_some())
+        .map(|t| t.name.clone())
+        .collect();
+    HtmlTemplate(RfiOverviewTemplate { telescope_names })
+}
+
+struct OccupancyBin {
+    frequency_mhz: f64,
+    occupancy_percent: f64,
+}
+
+#[derive(Template)]
+#[template(path = "rfi_report.html")]
+struct RfiReportTemplate {
+    telescope_name: String,
+    number_of_scans: usize,
+    cleanest_bins: Vec<OccupancyBin>,
+    locale: Locale,
+}
+
+async fn get_rfi_report<StorageType>(
+    State(db): State<DataBase<StorageType>>,
+    Path(telescope_id): Path<String>,
+    locale: Locale,
+) -> impl IntoResponse
+where
+    StorageType: Storage,
+{
+    let scans = fetch_rfi_scans(&db, &telescope_id).await;
+    let mut bins = mean_occupancy(&scans);
+    bins.sort_by(|a, b| a.1.total_cmp(&b.1));
+    let cleanest_bins = bins
+        .into_iter()
+        .take(CLEANEST_BINS_LISTED)
+        .map(|(frequency, occupancy)| OccupancyBin {
+            frequency_mhz: frequency / 1e6,
+            occupancy_percent: 100.0 * occupancy,
+        })
+        .collect();
+    HtmlTemplate(RfiReportTemplate {
+        telescope_name: telescope_id,
+        number_of_scans: scans.len(),
+        cleanest_bins,
+        locale,
+    })
+}
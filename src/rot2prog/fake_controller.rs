@@ -0,0 +1,111 @@
+//! A stand-in for a rot2prog controller, used by fakesalsa and in tests.
+//!
+//! The fake controller moves to any commanded direction immediately and
+//! answers in the configured encoding.
+
+use super::{decode_angle, encode_angle, Rot2ProgEncoding};
+use crate::angles::Degrees;
+use hex_literal::hex;
+use std::io::prelude::*;
+use std::net::TcpStream;
+
+const ACK: [u8; 12] = hex!("57 00 00 00 00 00 00 00 00 00 00 20");
+
+pub struct FakeController {
+    encoding: Rot2ProgEncoding,
+    azimuth: Degrees,
+    altitude: Degrees,
+}
+
+impl FakeController {
+    pub fn new(encoding: Rot2ProgEncoding) -> FakeController {
+        FakeController {
+            encoding,
+            azimuth: Degrees(0.0),
+            altitude: Degrees(90.0),
+        }
+    }
+
+    fn direction_response(&self) -> [u8; 12] {
+        let mut response = [0; 12];
+        response[0] = 0x58;
+        response[1..=5].copy_from_slice(&encode_angle(self.azimuth, self.encoding));
+        response[6..=10].copy_from_slice(&encode_angle(self.altitude, self.encoding));
+        response[11] = 0x20;
+        response
+    }
+
+    /// Handle a request, without the trailing 0x20.
+    pub fn handle(&mut self, request: &[u8]) -> [u8; 12] {
+        match request {
+            hex!("57 00 00 00 00 00 00 00 00 00 00 6F") => {
+                log::debug!("Got direction request");
+                self.direction_response()
+            }
+            hex!("57 00 00 00 00 00 00 00 00 00 00 0F") => {
+                log::info!("Got stop request");
+                ACK
+            }
+            hex!("57 EF BE AD DE 00 00 00 00 00 00 EE") => {
+                log::info!("Got restart request");
+                ACK
+            }
+            [0x57, direction @ .., 0x5F] => {
+                // Commands are always ASCII encoded.
+                match (
+                    decode_angle(&direction[0..5], Rot2ProgEncoding::Ascii),
+                    decode_angle(&direction[5..10], Rot2ProgEncoding::Ascii),
+                ) {
+                    (Some(azimuth), Some(altitude)) => {
+                        log::info!("Got set direction request ({}, {})", azimuth, altitude);
+                        self.azimuth = azimuth;
+                        self.altitude = altitude;
+                        self.direction_response()
+                    }
+                    _ => {
+                        log::warn!("Invalid set direction request. Data: {:02X?}", request);
+                        // FIXME: Is this a proper error
+                        hex!("57 00 00 00 00 00 00 00 00 00 00 00")
+                    }
+                }
+            }
+            _ => {
+                log::warn!("Unknown request. Data: {:02X?}", request);
+                // FIXME: Is this a proper error
+                hex!("57 00 00 00 00 00 00 00 00 00 00 00")
+            }
+        }
+    }
+
+    /// Answer requests on `stream` until the client closes the connection.
+    pub fn serve(&mut self, mut stream: TcpStream) {
+        loop {
+            let mut command_buffer = [0; 13];
+            match stream.read(&mut command_buffer) {
+                Ok(0) => {
+                    log::info!("Client closed connection.");
+                    break;
+                }
+                Ok(13) => {
+                    log::debug!("Client sent: {:02X?}", command_buffer);
+                    let response = self.handle(&command_buffer[0..12]);
+                    if let Err(err) = stream.write_all(&response) {
+                        log::error!("Failed to respond ({})", err);
+                        break;
+                    }
+                }
+                Ok(n) => {
+                    log::warn!(
+                        "Client sent {} bytes, expected 13. Data: {:02X?}",
+                        n,
+                        command_buffer
+                    );
+                }
+                Err(err) => {
+                    log::error!("Failed to read from client ({})", err);
+                    break;
+                }
+            };
+        }
+    }
+}
@@ -0,0 +1,147 @@
+//! Encoding of angles in the rot2prog protocol used by the telescope
+//! controllers.
+//!
+//! Angles are sent as five decimal digits of hundredths of degrees, offset
+//! by 360. According to the documentation the digits are ASCII encoded
+//! both ways, but the controllers seem to answer with the digits as raw
+//! bytes, e.g. 0x03 instead of 0x33 for '3'. Commands are always ASCII
+//! encoded, the encoding of responses is either configured or detected
+//! when connecting.
+
+use crate::angles::Degrees;
+use serde::{Deserialize, Serialize};
+
+// Only used by fakesalsa and in tests.
+#[allow(dead_code)]
+pub mod fake_controller;
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+pub enum Rot2ProgEncoding {
+    /// Digits as ASCII characters, as documented.
+    Ascii,
+    /// Digits as raw bytes, as seen from the controllers.
+    Raw,
+}
+
+pub const ENCODINGS: [Rot2ProgEncoding; 2] = [Rot2ProgEncoding::Raw, Rot2ProgEncoding::Ascii];
+
+// The range of directions a controller can possibly report. Anything
+// outside of this is a sign that the response was decoded the wrong way.
+const PLAUSIBLE_AZIMUTH: std::ops::RangeInclusive<f64> = -180.0..=540.0;
+const PLAUSIBLE_ALTITUDE: std::ops::RangeInclusive<f64> = -90.0..=180.0;
+
+impl Rot2ProgEncoding {
+    fn offset(self) -> u8 {
+        match self {
+            Rot2ProgEncoding::Ascii => 0x30,
+            Rot2ProgEncoding::Raw => 0,
+        }
+    }
+}
+
+pub fn encode_angle(angle: Degrees, encoding: Rot2ProgEncoding) -> [u8; 5] {
+    let mut bytes = [0; 5];
+    let mut value = ((angle.0 + 360.0) * 100.0).round() as u32;
+    for byte in bytes.iter_mut().rev() {
+        *byte = (value % 10) as u8 + encoding.offset();
+        value /= 10;
+    }
+    bytes
+}
+
+/// Decode an angle, or None if `bytes` are not digits in `encoding`.
+pub fn decode_angle(bytes: &[u8], encoding: Rot2ProgEncoding) -> Option<Degrees> {
+    let mut value = 0;
+    for &byte in bytes {
+        let digit = byte
+            .checked_sub(encoding.offset())
+            .filter(|digit| *digit < 10)?;
+        value = value * 10 + digit as u32;
+    }
+    Some(Degrees(value as f64 / 100.0 - 360.0))
+}
+
+/// Decode an azimuth and altitude, or None if they are not plausible in
+/// `encoding`.
+pub fn decode_direction(
+    azimuth: &[u8],
+    altitude: &[u8],
+    encoding: Rot2ProgEncoding,
+) -> Option<(Degrees, Degrees)> {
+    let azimuth = decode_angle(azimuth, encoding)?;
+    let altitude = decode_angle(altitude, encoding)?;
+    if PLAUSIBLE_AZIMUTH.contains(&azimuth.0) && PLAUSIBLE_ALTITUDE.contains(&altitude.0) {
+        Some((azimuth, altitude))
+    } else {
+        None
+    }
+}
+
+/// Find the encoding that gives a plausible direction, if exactly one does.
+pub fn detect_encoding(azimuth: &[u8], altitude: &[u8]) -> Option<Rot2ProgEncoding> {
+    let mut plausible = ENCODINGS
+        .into_iter()
+        .filter(|encoding| decode_direction(azimuth, altitude, *encoding).is_some());
+    match (plausible.next(), plausible.next()) {
+        (Some(encoding), None) => Some(encoding),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use hex_literal::hex;
+
+    #[test]
+    fn test_encode_angle() {
+        assert_eq!(
+            encode_angle(Degrees(0.0), Rot2ProgEncoding::Ascii),
+            hex!("3336303030"),
+            "0.0 should be 0x3336303030 (telescope expects angle + 360)"
+        );
+        assert_eq!(
+            encode_angle(Degrees(5.54), Rot2ProgEncoding::Ascii),
+            hex!("3336353534"),
+            "5.54 should be 0x3336353534 (example from documentation)"
+        );
+        assert_eq!(
+            encode_angle(Degrees(5.54), Rot2ProgEncoding::Raw),
+            hex!("0306050504")
+        );
+    }
+
+    #[test]
+    fn test_decode_angle() {
+        let precision = 0.01;
+        let angle = decode_angle(&hex!("0306000000"), Rot2ProgEncoding::Raw).unwrap();
+        assert!(angle.0.abs() < precision);
+        let angle = decode_angle(&hex!("3336303030"), Rot2ProgEncoding::Ascii).unwrap();
+        assert!(angle.0.abs() < precision);
+        // Example from documentation
+        let angle = decode_angle(&hex!("3338323333"), Rot2ProgEncoding::Ascii).unwrap();
+        assert!((angle.0 - 22.33).abs() < precision);
+        assert_eq!(
+            decode_angle(&hex!("3336303030"), Rot2ProgEncoding::Raw),
+            None
+        );
+        assert_eq!(
+            decode_angle(&hex!("0306000000"), Rot2ProgEncoding::Ascii),
+            None
+        );
+    }
+
+    #[test]
+    fn test_detect_encoding() {
+        for encoding in ENCODINGS {
+            let azimuth = encode_angle(Degrees(180.0), encoding);
+            let altitude = encode_angle(Degrees(45.0), encoding);
+            assert_eq!(detect_encoding(&azimuth, &altitude), Some(encoding));
+        }
+        // Valid digits, but pointing below the ground.
+        assert_eq!(
+            detect_encoding(&hex!("0000000000"), &hex!("0000000000")),
+            None
+        );
+    }
+}
@@ -1,116 +1,475 @@
-use crate::coords::Direction;
+use crate::angles::Radians;
+use crate::auxiliary::{
+    switch_over_tcp, AuxiliaryDeviceDefinition, AuxiliaryDeviceState, AuxiliaryDevices,
+};
+use crate::calibration::{switch_noise_diode, system_temperature, Calibration};
+use crate::connection_health::ConnectionHealth;
+use crate::constants::{DEFAULT_SYSTEM_TEMPERATURE, HI_REST_FREQUENCY};
+use crate::coords::{angular_separation, Direction};
+use crate::interlock::CollisionInterlock;
+use crate::pointing::PointingCorrection;
+use crate::rfi::flagging::{flag_interference, flagged_fractions, RfiFlagging};
+use crate::rfi::{mean_occupancy, select_reference_frequency, RfiScan, RfiScanDefinition};
+use crate::supervisor::TelescopeSupervisor;
+use crate::telemetry::{TelemetryDefinition, TrackerTelemetry};
 use crate::telescope::Telescope;
-use crate::telescope_tracker::TelescopeTracker;
+use crate::telescope_tracker::{TelescopeTracker, TelescopeTrackerInfo, LOWEST_ALLOWED_ALTITUDE};
 use crate::telescopes::{
-    Measurement, ObservedSpectra, ReceiverConfiguration, ReceiverError, TelescopeError,
-    TelescopeInfo, TelescopeTarget,
+    ContinuumSample, FrequencyRange, Measurement, NoiseDiodeDefinition, ObservationMode,
+    ObservedSpectra, Polarization, PolarizationReceiver, PolarizationSpectrum,
+    ReceiverConfiguration, ReceiverError, ReceiverParameter, ReceiverTransition,
+    SalsaTelescopeDefinition, SwitchingMode, TargetPreview, TelescopeCapabilities, TelescopeError,
+    TelescopeInfo, TelescopeStatus, TelescopeTarget, WindowFunction, ZoomConfiguration,
 };
 use async_trait::async_trait;
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use std::sync::Arc;
 use tokio::sync::Mutex;
 use tokio_util::sync::CancellationToken;
 
+use std::f64::consts::PI;
 use std::time::Duration;
 
 use rustfft::{num_complex::Complex, FftPlanner};
 use uhd::{self, StreamCommand, StreamCommandType, StreamTime, TuneRequest, Usrp};
 
+const SIGNAL_FREQUENCY: f64 = HI_REST_FREQUENCY;
+const SAMPLE_RATE: f64 = 2.5e6;
+// Sample rates the receiver can be set to, whole fractions of the 100 MHz
+// clock of the USRP that the host keeps up with.
+const SAMPLE_RATES: [f64; 4] = [1e6, 2.5e6, 5e6, 10e6];
+// Frequencies in Hz the receiver can be tuned to.
+const TUNING_RANGE: FrequencyRange = FrequencyRange {
+    min: 50e6,
+    max: 2.2e9,
+};
+// Keep the reference close to the signal so that the bandpass is similar.
+const MAX_REFERENCE_OFFSET: f64 = 10e6;
+// Keep the reference position of position switching within a few beams of
+// the target, and far enough to be outside the 7 degree beam.
+const MAX_POSITION_OFFSET: f64 = 30.0;
+// Seconds spent on each position when position switching, long enough to
+// make the time spent moving between them worth it.
+const POSITION_SWITCHED_INTEGRATION_TIME: f64 = 10.0;
+// Give up position switching if the telescope does not reach the position.
+const POSITION_SWITCH_TIMEOUT: Duration = Duration::from_secs(120);
+// Number of recent RFI scans used when picking a reference frequency.
+// Seconds of samples averaged into each point of the continuum time series.
+const CONTINUUM_INTERVAL: f64 = 1.0;
+
+const REFERENCE_RFI_SCAN_HISTORY: usize = 24;
+// Channels in the spectra of measurements.
+const CHANNELS: usize = 512;
+const FFT_SIZE: usize = 8192;
+const MAX_FFT_SIZE: usize = 65536;
+const GAIN: f64 = 38.0;
+// Gains in dB accepted by the receiver, the default being the highest.
+const MAX_GAIN: f64 = 38.0;
+// Zoomed bandwidths are the sample rate divided by these factors.
+const ZOOM_DECIMATIONS: [usize; 5] = [1, 2, 4, 8, 16];
+/// Approximate slewing speed of the rot2prog rotators, one degree per
+/// second.
+pub const SALSA_SLEWING_SPEED: Radians = Radians(PI / 180.0);
+// The tracker sends queued raw commands within a few updates.
+const RAW_COMMAND_TIMEOUT: Duration = Duration::from_secs(5);
+
 pub struct ActiveIntegration {
     cancellation_token: CancellationToken,
-    measurement_task: tokio::task::JoinHandle<()>,
+    measurement_task: tokio::task::JoinHandle<Option<()>>,
+}
+
+pub struct ActiveRfiScan {
+    cancellation_token: CancellationToken,
+    scan_task: tokio::task::JoinHandle<Option<Option<RfiScan>>>,
 }
 
 pub struct SalsaTelescope {
     name: String,
     receiver_address: String,
+    polarization: Polarization,
+    polarization_receivers: Vec<PolarizationReceiver>,
+    record_polarizations: bool,
+    rfi_mask: Vec<FrequencyRange>,
+    noise_diode: Option<NoiseDiodeDefinition>,
+    calibration: Option<Calibration>,
     controller: TelescopeTracker,
+    telemetry: Option<TrackerTelemetry>,
     receiver_configuration: ReceiverConfiguration,
     measurements: Arc<Mutex<Vec<Measurement>>>,
     active_integration: Option<ActiveIntegration>,
+    completed_measurements: Vec<Measurement>,
+    rfi_scan: Option<RfiScanDefinition>,
+    active_rfi_scan: Option<ActiveRfiScan>,
+    last_rfi_scan: Option<DateTime<Utc>>,
+    completed_rfi_scans: Vec<RfiScan>,
+    recent_rfi_scans: Vec<RfiScan>,
+    auxiliary_devices: AuxiliaryDevices,
+    supervisor: TelescopeSupervisor,
 }
 
 pub fn create(
     name: String,
-    controller_address: String,
-    receiver_address: String,
+    definition: SalsaTelescopeDefinition,
+    rfi_scan: Option<RfiScanDefinition>,
+    mut recent_rfi_scans: Vec<RfiScan>,
+    auxiliary_devices: Vec<AuxiliaryDeviceDefinition>,
+    supervisor: TelescopeSupervisor,
+    interlock: CollisionInterlock,
+    telemetry: Option<TelemetryDefinition>,
 ) -> SalsaTelescope {
+    let last_rfi_scan = recent_rfi_scans.iter().map(|scan| scan.start).max();
+    recent_rfi_scans.sort_by_key(|scan| scan.start);
+    let skip = recent_rfi_scans
+        .len()
+        .saturating_sub(REFERENCE_RFI_SCAN_HISTORY);
+    recent_rfi_scans.drain(..skip);
+    let telemetry =
+        telemetry.map(|telemetry| TrackerTelemetry::start(&name, &telemetry, &supervisor));
+    let controller = TelescopeTracker::new(
+        name.clone(),
+        definition.controller_address,
+        definition.response_encoding,
+        interlock,
+        telemetry.clone(),
+        &supervisor,
+    );
     SalsaTelescope {
         name,
-        receiver_address,
-        controller: TelescopeTracker::new(controller_address),
-        receiver_configuration: ReceiverConfiguration { integrate: false },
+        receiver_address: definition.receiver_address,
+        polarization: definition.polarization,
+        polarization_receivers: definition.polarization_receivers,
+        record_polarizations: definition.record_polarizations,
+        rfi_mask: definition.rfi_mask,
+        noise_diode: definition.noise_diode,
+        calibration: None,
+        controller,
+        telemetry,
+        receiver_configuration: ReceiverConfiguration::stopped(),
         measurements: Arc::new(Mutex::new(Vec::new())),
         active_integration: None,
+        completed_measurements: Vec::new(),
+        rfi_scan,
+        active_rfi_scan: None,
+        last_rfi_scan,
+        completed_rfi_scans: Vec::new(),
+        recent_rfi_scans,
+        auxiliary_devices: AuxiliaryDevices::new(auxiliary_devices),
+        supervisor,
     }
 }
 
-// Reading the documentation of the telescope, this should be the correct way to interpret the bytes
-// This would match how rot2prog_angle_to_bytes works.
-fn rot2prog_bytes_to_int_documented(bytes: &[u8]) -> u32 {
-    bytes
-        .iter()
-        .rev()
-        .enumerate()
-        .map(|(pos, &digit)| (digit as u32 - 0x30) * 10_u32.pow(pos as u32))
-        .sum()
+/// Receiver settings of a configuration, with the defaults filled in.
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct ReceiverSettings {
+    sample_rate: f64,
+    center_frequency: f64,
+    fft_size: usize,
+    channels: usize,
+    gain: f64,
+}
+
+impl ReceiverSettings {
+    fn new(configuration: &ReceiverConfiguration) -> ReceiverSettings {
+        ReceiverSettings {
+            sample_rate: configuration.sample_rate.unwrap_or(SAMPLE_RATE),
+            center_frequency: configuration.center_frequency.unwrap_or(SIGNAL_FREQUENCY),
+            fft_size: configuration.fft_size.unwrap_or(FFT_SIZE),
+            channels: configuration.channels.unwrap_or(CHANNELS),
+            gain: configuration.gain.unwrap_or(GAIN),
+        }
+    }
+
+    /// Check that the USRP can be set up like this, and that the reference
+    /// frequency and zoom of `configuration` fit with it.
+    fn validate(&self, configuration: &ReceiverConfiguration) -> Result<(), ReceiverError> {
+        let unsupported = ReceiverError::UnsupportedSetting;
+        if !SAMPLE_RATES.contains(&self.sample_rate) {
+            return Err(unsupported(ReceiverParameter::SampleRate));
+        }
+        let band_inside_tuning_range = |center: f64| {
+            center - 0.5 * self.sample_rate >= TUNING_RANGE.min
+                && center + 0.5 * self.sample_rate <= TUNING_RANGE.max
+        };
+        if !band_inside_tuning_range(self.center_frequency) {
+            return Err(unsupported(ReceiverParameter::CenterFrequency));
+        }
+        if let Some(reference_frequency) = configuration.reference_frequency {
+            if !band_inside_tuning_range(reference_frequency)
+                || (reference_frequency - self.center_frequency).abs() > MAX_REFERENCE_OFFSET
+            {
+                return Err(unsupported(ReceiverParameter::ReferenceFrequency));
+            }
+        }
+        if !self.fft_size.is_power_of_two() || self.fft_size > MAX_FFT_SIZE {
+            return Err(unsupported(ReceiverParameter::FftSize));
+        }
+        // The channels average an equal number of FFT points each.
+        if self.channels == 0 || !self.fft_size.is_multiple_of(self.channels) {
+            return Err(unsupported(ReceiverParameter::Channels));
+        }
+        if !(0.0..=MAX_GAIN).contains(&self.gain) {
+            return Err(unsupported(ReceiverParameter::Gain));
+        }
+        if let SwitchingMode::PositionSwitched { azimuth_offset } = configuration.switching {
+            if azimuth_offset.0 == 0.0 || azimuth_offset.0.abs() > MAX_POSITION_OFFSET {
+                return Err(unsupported(ReceiverParameter::Switching));
+            }
+        }
+        if !configuration.rfi_flagging.is_valid() {
+            return Err(unsupported(ReceiverParameter::RfiFlagging));
+        }
+        if let Some(zoom) = configuration.zoom {
+            // The zoomed band has to fit within the sampled band.
+            if zoom.bandwidth <= 0.0
+                || zoom.offset.abs() + 0.5 * zoom.bandwidth > 0.5 * self.sample_rate
+            {
+                return Err(ReceiverError::InvalidZoom);
+            }
+        }
+        Ok(())
+    }
 }
 
-#[allow(dead_code)]
-fn rot2prog_bytes_to_angle_documented(bytes: &[u8]) -> f64 {
-    (rot2prog_bytes_to_int_documented(bytes) as f64 / 100.0 - 360.0).to_radians()
+// Settings for turning received samples into a spectrum.
+struct Spectrometer {
+    srate: f64,
+    avg_pts: usize,
+    window: Vec<f64>,
+    zoom: Option<ZoomConfiguration>,
+    flagging: RfiFlagging,
+    rfi_mask: Vec<FrequencyRange>,
+}
+
+impl Spectrometer {
+    fn decimation(&self) -> usize {
+        match self.zoom {
+            Some(zoom) => decimation_factor(self.srate, zoom.bandwidth),
+            None => 1,
+        }
+    }
+
+    // Bandwidth of the spectrum, which is narrower than the sampled
+    // bandwidth in zoom mode.
+    fn bandwidth(&self) -> f64 {
+        self.srate / self.decimation() as f64
+    }
+
+    fn offset(&self) -> f64 {
+        self.zoom.map_or(0.0, |zoom| zoom.offset)
+    }
+
+    // Frequencies of the averaged channels when tuned to cfreq.
+    fn frequencies(&self, cfreq: f64) -> Vec<f64> {
+        channel_frequencies(cfreq + self.offset(), self.bandwidth(), self.avg_pts)
+    }
+}
+
+// Bookkeeping of the samples received in one or more streams, based on the
+// time stamps the USRP attaches to each packet.
+#[derive(Debug, Default, Clone, PartialEq)]
+struct ReceiveStatistics {
+    // Device time in seconds of the first received sample.
+    start_time: Option<f64>,
+    // Device time in seconds just after the last received sample.
+    end_time: Option<f64>,
+    received: usize,
+    dropped: usize,
+    // Total power of the received samples, one point per CONTINUUM_INTERVAL.
+    continuum: Vec<ContinuumSample>,
+    // Fraction of each channel flagged as interference.
+    flagged: Vec<f64>,
+}
+
+impl ReceiveStatistics {
+    // Record a packet of samples starting at device time `time`. Samples
+    // are considered dropped if the packet starts later than expected from
+    // the samples received so far.
+    fn record(&mut self, time: Option<f64>, samples: usize, srate: f64) {
+        if let (Some(start_time), Some(time)) = (self.start_time, time) {
+            let expected = start_time + (self.received + self.dropped) as f64 / srate;
+            let gap = ((time - expected) * srate).round();
+            if gap > 0.0 {
+                self.dropped += gap as usize;
+            }
+        }
+        if self.start_time.is_none() {
+            self.start_time = time;
+        }
+        if let Some(time) = time.or(self.end_time) {
+            self.end_time = Some(time + samples as f64 / srate);
+        }
+        self.received += samples;
+    }
+
+    // Combine statistics of a stream with those of another receiver reading
+    // the same time. The time and continuum are those of the first receiver.
+    fn alongside(self, other: ReceiveStatistics) -> ReceiveStatistics {
+        ReceiveStatistics {
+            dropped: self.dropped + other.dropped,
+            flagged: mean_flagged(&self.flagged, &other.flagged),
+            ..self
+        }
+    }
+
+    // Combine statistics of a stream with those of a later one.
+    fn followed_by(self, later: ReceiveStatistics) -> ReceiveStatistics {
+        ReceiveStatistics {
+            start_time: self.start_time.or(later.start_time),
+            end_time: later.end_time.or(self.end_time),
+            received: self.received + later.received,
+            dropped: self.dropped + later.dropped,
+            continuum: [self.continuum, later.continuum].concat(),
+            flagged: mean_flagged(&self.flagged, &later.flagged),
+        }
+    }
+}
+
+// Channel by channel mean of the flagged fractions of two spectra.
+fn mean_flagged(a: &[f64], b: &[f64]) -> Vec<f64> {
+    match (a.is_empty(), b.is_empty()) {
+        (true, _) => b.to_vec(),
+        (_, true) => a.to_vec(),
+        _ => a.iter().zip(b).map(|(a, b)| 0.5 * (a + b)).collect(),
+    }
 }
 
 fn measure_switched(
     usrp: &mut Usrp,
     sfreq: f64,
     rfreq: f64,
-    fft_pts: usize,
     tint: f64,
-    avg_pts: usize,
-    srate: f64,
+    spectrometer: &Spectrometer,
+    tsys: f64,
     spec: &mut Vec<f64>,
-) {
+) -> ReceiveStatistics {
     let mut spec_sig: Vec<f64> = vec![];
-    measure_single(
-        usrp,
-        sfreq,
-        fft_pts,
-        0.5 * tint,
-        avg_pts,
-        srate,
-        &mut spec_sig,
-    );
+    let sig_statistics = measure_single(usrp, sfreq, 0.5 * tint, spectrometer, &mut spec_sig);
     let mut spec_ref: Vec<f64> = vec![];
-    measure_single(
-        usrp,
-        rfreq,
-        fft_pts,
-        0.5 * tint,
-        avg_pts,
-        srate,
-        &mut spec_ref,
-    );
+    let ref_statistics = measure_single(usrp, rfreq, 0.5 * tint, spectrometer, &mut spec_ref);
     // Form sig-ref difference and scale with Tsys
-    // Hard coded Tsys for now
-    let tsys = 285.0;
-    for i in 0..avg_pts {
-        spec[i] = tsys * (spec_sig[i] - spec_ref[i]) / spec_ref[i];
+    *spec = (0..spectrometer.avg_pts)
+        .map(|i| tsys * (spec_sig[i] - spec_ref[i]) / spec_ref[i])
+        .collect();
+    sig_statistics.followed_by(ref_statistics)
+}
+
+// Measure with each receiver in turn, giving a spectrum per receiver. The
+// N210 has a single channel, so the receivers of a dual polarization feed
+// are separate devices.
+fn measure_each(
+    usrps: &mut [Usrp],
+    mut measure: impl FnMut(&mut Usrp, &mut Vec<f64>) -> ReceiveStatistics,
+) -> (Vec<Vec<f64>>, ReceiveStatistics) {
+    let mut specs = Vec::with_capacity(usrps.len());
+    let mut statistics: Option<ReceiveStatistics> = None;
+    for usrp in usrps.iter_mut() {
+        let mut spec = vec![];
+        let received = measure(usrp, &mut spec);
+        specs.push(spec);
+        statistics = Some(match statistics {
+            Some(statistics) => statistics.alongside(received),
+            None => received,
+        });
     }
+    (specs, statistics.unwrap_or_default())
+}
+
+// Average of the spectra of the receivers, channel by channel.
+fn average_spectra(specs: &[Vec<f64>], avg_pts: usize) -> Vec<f64> {
+    (0..avg_pts)
+        .map(|i| specs.iter().map(|spec| spec[i]).sum::<f64>() / specs.len() as f64)
+        .collect()
+}
+
+// Measure tint seconds on the reference position, off the target by
+// `offset` in azimuth, and then on the target. Returns the on and off
+// spectra of each receiver, or None if the telescope did not get to either
+// position or the integration was stopped on the way.
+async fn measure_on_off(
+    usrps: &mut [Usrp],
+    tracker: &TelescopeTracker,
+    offset: Radians,
+    cfreq: f64,
+    tint: f64,
+    spectrometer: &Spectrometer,
+    cancellation_token: &CancellationToken,
+) -> Option<(Vec<Vec<f64>>, Vec<Vec<f64>>, ReceiveStatistics)> {
+    tracker.set_azimuth_offset(offset);
+    if !wait_until_tracking(tracker, cancellation_token).await {
+        return None;
+    }
+    let (specs_off, off_statistics) = measure_each(usrps, |usrp, spec| {
+        measure_single(usrp, cfreq, tint, spectrometer, spec)
+    });
+    tracker.set_azimuth_offset(Radians(0.0));
+    if !wait_until_tracking(tracker, cancellation_token).await {
+        return None;
+    }
+    let (specs_on, on_statistics) = measure_each(usrps, |usrp, spec| {
+        measure_single(usrp, cfreq, tint, spectrometer, spec)
+    });
+    Some((
+        specs_on,
+        specs_off,
+        off_statistics.followed_by(on_statistics),
+    ))
+}
+
+// Wait for the tracker to reach the commanded direction.
+async fn wait_until_tracking(
+    tracker: &TelescopeTracker,
+    cancellation_token: &CancellationToken,
+) -> bool {
+    let deadline = tokio::time::Instant::now() + POSITION_SWITCH_TIMEOUT;
+    // Let the tracker pick up the new direction before checking it.
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    while !cancellation_token.is_cancelled() {
+        if matches!(tracker.info(), Ok(info) if info.status == TelescopeStatus::Tracking) {
+            return true;
+        }
+        if tokio::time::Instant::now() > deadline {
+            log::error!("Telescope did not reach the position to measure, stopping integration");
+            return false;
+        }
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    }
+    false
 }
 
 fn measure_single(
     usrp: &mut Usrp,
     cfreq: f64,
-    fft_pts: usize,
     tint: f64,
-    avg_pts: usize,
-    srate: f64,
+    spectrometer: &Spectrometer,
     fft_avg: &mut Vec<f64>,
-) {
-    let nsamp: f64 = tint * srate; // total number of samples to request
-    let nstack: usize = (nsamp as usize) / fft_pts;
-    let navg: usize = fft_pts / avg_pts;
+) -> ReceiveStatistics {
+    let (mut fft_abs, mut statistics) = receive_power_spectrum(usrp, cfreq, tint, spectrometer);
+    let frequencies = channel_frequencies(
+        cfreq + spectrometer.offset(),
+        spectrometer.bandwidth(),
+        fft_abs.len(),
+    );
+    let flags = flag_interference(
+        &mut fft_abs,
+        &frequencies,
+        spectrometer.flagging,
+        &spectrometer.rfi_mask,
+    );
+    statistics.flagged = flagged_fractions(&flags, spectrometer.avg_pts);
+    fft_avg.extend(average_channels(&fft_abs, spectrometer.avg_pts));
+    statistics
+}
+
+// Receive tint seconds of samples at cfreq and return the stacked power
+// spectrum, lowest frequency first. In zoom mode the samples are shifted
+// and decimated to the zoomed band before the FFT.
+fn receive_power_spectrum(
+    usrp: &mut Usrp,
+    cfreq: f64,
+    tint: f64,
+    spectrometer: &Spectrometer,
+) -> (Vec<f64>, ReceiveStatistics) {
+    let nsamp: f64 = tint * spectrometer.srate; // total number of samples to request
 
     usrp.set_rx_frequency(&TuneRequest::with_frequency(cfreq), 0)
         .unwrap(); // The N210 only has one input channel 0.
@@ -127,7 +486,80 @@ fn measure_single(
             time: StreamTime::Now,
         })
         .unwrap();
-    receiver.receive_simple(buffer.as_mut()).unwrap();
+
+    // A single call may not fill the whole buffer, so keep receiving until
+    // all requested samples have arrived or the stream stops delivering.
+    let received_at = Utc::now();
+    let mut statistics = ReceiveStatistics::default();
+    while statistics.received < buffer.len() {
+        let metadata = receiver
+            .receive_simple(&mut buffer[statistics.received..])
+            .unwrap();
+        if let Some(error) = metadata.last_error() {
+            log::warn!("Error while receiving samples: {:?}", error);
+        }
+        if metadata.out_of_sequence() {
+            log::warn!("Received samples out of sequence");
+        }
+        if metadata.samples() == 0 {
+            break;
+        }
+        let time = metadata
+            .time_spec()
+            .map(|time_spec| time_spec.seconds as f64 + time_spec.fraction);
+        statistics.record(time, metadata.samples(), spectrometer.srate);
+    }
+    if statistics.dropped > 0 {
+        log::warn!(
+            "Dropped {} samples while receiving at {} Hz",
+            statistics.dropped,
+            cfreq
+        );
+    }
+
+    let samples: Vec<Complex<f64>> = buffer[..statistics.received]
+        .iter()
+        .map(|x| Complex::<f64>::new(x.re as f64, x.im as f64))
+        .collect();
+    statistics.continuum = continuum_power(&samples, spectrometer.srate, received_at);
+    let samples = match spectrometer.zoom {
+        Some(_) => downconvert_and_decimate(
+            &samples,
+            spectrometer.offset(),
+            spectrometer.srate,
+            spectrometer.decimation(),
+        ),
+        None => samples,
+    };
+    (power_spectrum(&samples, &spectrometer.window), statistics)
+}
+
+// Mean power of each CONTINUUM_INTERVAL of samples, the first received at
+// `start`. A last interval less than half full is left out.
+fn continuum_power(
+    samples: &[Complex<f64>],
+    srate: f64,
+    start: DateTime<Utc>,
+) -> Vec<ContinuumSample> {
+    let interval = ((CONTINUUM_INTERVAL * srate) as usize).max(1);
+    samples
+        .chunks(interval)
+        .filter(|chunk| 2 * chunk.len() >= interval)
+        .enumerate()
+        .map(|(i, chunk)| ContinuumSample {
+            time: start
+                + chrono::Duration::milliseconds((i as f64 * CONTINUUM_INTERVAL * 1e3) as i64),
+            power: chunk.iter().map(|x| x.norm_sqr()).sum::<f64>() / chunk.len() as f64,
+        })
+        .collect()
+}
+
+// Stack the power spectra of consecutive blocks of samples, lowest
+// frequency first. The number of channels is given by the length of the
+// window applied before each FFT.
+fn power_spectrum(samples: &[Complex<f64>], window: &[f64]) -> Vec<f64> {
+    let fft_pts = window.len();
+    let nstack: usize = samples.len() / fft_pts;
 
     // array to store power spectrum (abs of FFT result)
     let mut fft_abs: Vec<f64> = Vec::with_capacity(fft_pts);
@@ -137,10 +569,10 @@ fn measure_single(
     let fft = planner.plan_fft_forward(fft_pts);
     // Loop through the samples, taking fft_pts each time
     for n in 0..nstack {
-        let mut fft_buffer: Vec<Complex<f64>> = buffer[n * fft_pts..(n + 1) * fft_pts]
+        let mut fft_buffer: Vec<Complex<f64>> = samples[n * fft_pts..(n + 1) * fft_pts]
             .iter()
-            .copied()
-            .map(|x| Complex::<f64>::new(x.re as f64, x.im as f64))
+            .zip(window)
+            .map(|(x, w)| x * w)
             .collect();
         // Do the FFT
         fft.process(&mut fft_buffer);
@@ -152,28 +584,91 @@ fn measure_single(
             fft_abs[i] = fft_abs[i] + fft_buffer[i + fft_pts / 2].norm();
         }
     }
-    // Normalise spectrum by number of stackings,
+    // Normalise spectrum by number of stackings and by the power of the
+    // window, so that the noise level does not depend on the window,
     // do **2 to get power spectrum
+    let window_power = window_power(window);
     for i in 0..fft_pts {
-        fft_abs[i] = fft_abs[i] * fft_abs[i] / (nstack as f64);
-    }
-
-    // median window filter data
-    let mwkernel = 32; //median window filter size, power of 2
-    let threshold = 0.1; // thershold where to cut data and replace with median
-    let nchunks = fft_pts / mwkernel;
-    for i in 0..nchunks {
-        let chunk = &mut fft_abs[i * mwkernel..(i + 1) * mwkernel];
-        let m = median(chunk.to_vec());
-        for n in 0..mwkernel {
-            let diff = (chunk[n] - m).abs();
-            if diff > threshold * m {
-                chunk[n] = m;
-            }
-        }
+        fft_abs[i] = fft_abs[i] * fft_abs[i] / (nstack as f64) / window_power;
+    }
+    fft_abs
+}
+
+// Center frequencies of channels evenly dividing a band, lowest first.
+// Matches the channel order of power_spectrum.
+fn channel_frequencies(center: f64, bandwidth: f64, channels: usize) -> Vec<f64> {
+    (0..channels)
+        .map(|i| center - 0.5 * bandwidth + bandwidth * (i as f64 / channels as f64))
+        .collect()
+}
+
+// Largest integer decimation giving at least the requested bandwidth.
+fn decimation_factor(srate: f64, bandwidth: f64) -> usize {
+    ((srate / bandwidth).floor() as usize).max(1)
+}
+
+// Windowed sinc low pass filter with cutoff at the Nyquist frequency after
+// decimating by factor, normalised to unit gain at zero frequency.
+fn lowpass_taps(factor: usize) -> Vec<f64> {
+    if factor == 1 {
+        return vec![1.0];
+    }
+    let ntaps = 8 * factor + 1;
+    let middle = (ntaps / 2) as f64;
+    let cutoff = 0.5 / factor as f64;
+    let taps: Vec<f64> = (0..ntaps)
+        .map(|n| {
+            let x = n as f64 - middle;
+            let sinc = if x == 0.0 {
+                1.0
+            } else {
+                (2.0 * PI * cutoff * x).sin() / (2.0 * PI * cutoff * x)
+            };
+            let hamming = 0.54 - 0.46 * (2.0 * PI * n as f64 / (ntaps - 1) as f64).cos();
+            sinc * hamming
+        })
+        .collect();
+    let sum: f64 = taps.iter().sum();
+    taps.into_iter().map(|tap| tap / sum).collect()
+}
+
+// Shift the band centered at offset Hz down to zero frequency, low pass
+// filter it and keep every factor:th sample.
+fn downconvert_and_decimate(
+    samples: &[Complex<f64>],
+    offset: f64,
+    srate: f64,
+    factor: usize,
+) -> Vec<Complex<f64>> {
+    let shifted: Vec<Complex<f64>> = samples
+        .iter()
+        .enumerate()
+        .map(|(n, x)| x * Complex::from_polar(1.0, -2.0 * PI * offset * n as f64 / srate))
+        .collect();
+    let taps = lowpass_taps(factor);
+    if shifted.len() < taps.len() {
+        return vec![];
     }
+    (0..=(shifted.len() - taps.len()) / factor)
+        .map(|k| {
+            shifted[k * factor..k * factor + taps.len()]
+                .iter()
+                .zip(&taps)
+                .map(|(x, tap)| x * tap)
+                .sum()
+        })
+        .collect()
+}
+
+// Mean squared window coefficient, 1 for the rectangular window.
+fn window_power(window: &[f64]) -> f64 {
+    window.iter().map(|w| w * w).sum::<f64>() / window.len() as f64
+}
 
+fn average_channels(fft_abs: &[f64], avg_pts: usize) -> Vec<f64> {
     // Average spectrum to save data
+    let navg: usize = fft_abs.len() / avg_pts;
+    let mut fft_avg = Vec::with_capacity(avg_pts);
     for i in 0..avg_pts {
         let mut avg = 0.0;
         for j in navg * i..navg * (i + 1) {
@@ -181,77 +676,440 @@ fn measure_single(
         }
         fft_avg.push(avg / (navg as f64));
     }
+    fft_avg
 }
 
-fn median(mut xs: Vec<f64>) -> f64 {
-    // sort in ascending order, panic on f64::NaN
-    xs.sort_by(|x, y| x.partial_cmp(y).unwrap());
-    let n = xs.len();
-    if n % 2 == 0 {
-        (xs[n / 2] + xs[n / 2 - 1]) / 2.0
-    } else {
-        xs[n / 2]
+fn open_receiver(address: &str, gain: f64, srate: f64) -> Usrp {
+    // Setup usrp for taking data
+    let args = format!("addr={}", address);
+    let mut usrp = Usrp::open(&args).unwrap(); // Brage
+
+    // The N210 only has one input channel 0.
+    usrp.set_rx_gain(gain, 0, "").unwrap(); // empty string to set all gains
+    usrp.set_rx_antenna("TX/RX", 0).unwrap();
+    usrp.set_rx_dc_offset_enabled(true, 0).unwrap();
+
+    usrp.set_rx_sample_rate(srate, 0).unwrap();
+    usrp
+}
+
+// Step across the scan range one bandwidth at a time, recording raw power
+// spectra. Unlike measure_single no interference is flagged since the spikes are
+// exactly what we are looking for.
+async fn rfi_scan(
+    address: String,
+    telescope_name: String,
+    definition: RfiScanDefinition,
+    cancellation_token: CancellationToken,
+) -> Option<RfiScan> {
+    let tint: f64 = 0.1; // integration time per tuning, seconds
+    let srate: f64 = SAMPLE_RATE; // sample rate, Hz
+    let avg_pts: usize = 512;
+    let fft_pts: usize = 8192;
+    let gain: f64 = 38.0;
+
+    let spectrometer = Spectrometer {
+        srate,
+        avg_pts,
+        // Strong carriers should not leak into neighbouring bins and make
+        // them look occupied, so use a window with low sidelobes.
+        window: WindowFunction::BlackmanHarris.coefficients(fft_pts),
+        zoom: None,
+        flagging: RfiFlagging::Off,
+        rfi_mask: vec![],
+    };
+
+    let start = Utc::now();
+    let mut usrp = open_receiver(&address, gain, srate);
+
+    let mut steps = Vec::new();
+    let mut cfreq = definition.start_frequency + 0.5 * srate;
+    while cfreq - 0.5 * srate < definition.stop_frequency {
+        if cancellation_token.is_cancelled() {
+            return None;
+        }
+        let (fft_abs, _) = receive_power_spectrum(&mut usrp, cfreq, tint, &spectrometer);
+        steps.push((
+            spectrometer.frequencies(cfreq),
+            average_channels(&fft_abs, avg_pts),
+        ));
+        cfreq += srate;
     }
+    Some(RfiScan::from_spectra(telescope_name, start, &steps))
+}
+
+impl SalsaTelescope {
+    // Scans are only made when the telescope is not observing anything.
+    fn should_start_rfi_scan(&self) -> bool {
+        let idle = self.active_integration.is_none()
+            && self.active_rfi_scan.is_none()
+            && matches!(
+                self.controller.target(),
+                Ok(TelescopeTarget::Parked | TelescopeTarget::Stopped)
+            );
+        match &self.rfi_scan {
+            Some(definition) => idle && definition.is_due(self.last_rfi_scan, Utc::now()),
+            None => false,
+        }
+    }
+
+    // The receivers of all polarizations, the one at receiver_address first.
+    fn receivers(&self) -> Vec<PolarizationReceiver> {
+        let primary = PolarizationReceiver {
+            address: self.receiver_address.clone(),
+            polarization: self.polarization,
+        };
+        std::iter::once(primary)
+            .chain(self.polarization_receivers.iter().cloned())
+            .collect()
+    }
+
+    fn reference_frequency(&self) -> f64 {
+        if let Some(reference_frequency) = self.receiver_configuration.reference_frequency {
+            return reference_frequency;
+        }
+        let settings = ReceiverSettings::new(&self.receiver_configuration);
+        select_reference_frequency(
+            &mean_occupancy(&self.recent_rfi_scans),
+            settings.center_frequency,
+            settings.sample_rate,
+            MAX_REFERENCE_OFFSET,
+        )
+        // Used when there are no RFI scans to pick a reference frequency from.
+        .unwrap_or(settings.center_frequency - settings.sample_rate)
+    }
+
+    async fn stop_rfi_scan(&mut self) {
+        if let Some(active_rfi_scan) = self.active_rfi_scan.take() {
+            log::info!("Aborting RFI scan for telescope {}", self.name);
+            active_rfi_scan.cancellation_token.cancel();
+            if let Err(error) = active_rfi_scan.scan_task.await {
+                log::error!("Error while waiting for RFI scan task: {}", error);
+            }
+        }
+    }
+
+    async fn start_integration(&mut self) -> Result<(), ReceiverError> {
+        if self.active_integration.is_some() {
+            return Err(ReceiverError::IntegrationAlreadyRunning);
+        }
+        if !self.auxiliary_devices.integration_allowed() {
+            return Err(ReceiverError::AuxiliaryDeviceOff);
+        }
+
+        // The receiver can only be used by one task at a time.
+        self.stop_rfi_scan().await;
+
+        let reference_frequency = self.reference_frequency();
+        let scaling = Scaling {
+            noise_diode: self.noise_diode.clone(),
+            calibration: self.calibration.clone(),
+        };
+        log::info!(
+            "Starting integration with reference frequency {} Hz",
+            reference_frequency
+        );
+        self.receiver_configuration.integrate = true;
+        let configuration = ReceiverConfiguration {
+            reference_frequency: Some(reference_frequency),
+            ..self.receiver_configuration
+        };
+        let cancellation_token = CancellationToken::new();
+        let measurement_task = {
+            let receivers = Receivers {
+                receivers: self.receivers(),
+                record_polarizations: self.record_polarizations,
+                rfi_mask: self.rfi_mask.clone(),
+            };
+            let target = self.controller.target().unwrap_or(TelescopeTarget::Stopped);
+            let tracker = self.controller.clone();
+            let measurements = self.measurements.clone();
+            let cancellation_token = cancellation_token.clone();
+            self.supervisor.spawn_once("measurement", async move {
+                measure(
+                    receivers,
+                    target,
+                    tracker,
+                    configuration,
+                    scaling,
+                    measurements,
+                    cancellation_token,
+                )
+                .await;
+            })
+        };
+        self.active_integration = Some(ActiveIntegration {
+            cancellation_token,
+            measurement_task,
+        });
+        Ok(())
+    }
+
+    /// Stop the running integration and wait for its measurement, so that a
+    /// new integration does not average into the same one.
+    async fn finish_integration(&mut self) {
+        if let Some(active_integration) = self.active_integration.take() {
+            active_integration.cancellation_token.cancel();
+            if let Err(error) = active_integration.measurement_task.await {
+                log::error!("Error while waiting for measurement task: {}", error);
+            } else if let Some(measurement) = self.measurements.lock().await.last() {
+                self.completed_measurements.push(measurement.clone());
+            }
+        }
+        self.receiver_configuration.integrate = false;
+    }
+}
+
+// Measure the system temperature at cfreq by switching the noise diode on
+// and off. Failures are logged and result in None, so that the observation
+// can continue uncalibrated.
+fn calibrate(
+    usrp: &mut Usrp,
+    cfreq: f64,
+    tint: f64,
+    spectrometer: &Spectrometer,
+    noise_diode: &NoiseDiodeDefinition,
+) -> Option<f64> {
+    if let Err(error) = switch_noise_diode(noise_diode, true) {
+        log::error!("Failed to switch on noise diode: {}", error);
+        return None;
+    }
+    let mut spec_on: Vec<f64> = vec![];
+    measure_single(usrp, cfreq, 0.5 * tint, spectrometer, &mut spec_on);
+    if let Err(error) = switch_noise_diode(noise_diode, false) {
+        log::error!("Failed to switch off noise diode: {}", error);
+        return None;
+    }
+    let mut spec_off: Vec<f64> = vec![];
+    measure_single(usrp, cfreq, 0.5 * tint, spectrometer, &mut spec_off);
+    let tsys = system_temperature(&spec_on, &spec_off, noise_diode.temperature);
+    if tsys.is_none() {
+        log::warn!("Noise diode made no difference, is it connected?");
+    }
+    tsys
+}
+
+// How the spectra of an integration are scaled to temperatures.
+struct Scaling {
+    noise_diode: Option<NoiseDiodeDefinition>,
+    calibration: Option<Calibration>,
+}
+
+// Receivers of the separate polarizations, the first being the one at
+// `receiver_address`, which is also used to calibrate.
+struct Receivers {
+    receivers: Vec<PolarizationReceiver>,
+    record_polarizations: bool,
+    // Frequencies flagged as interference in every receiver.
+    rfi_mask: Vec<FrequencyRange>,
 }
 
 async fn measure(
-    address: String,
+    receivers: Receivers,
+    target: TelescopeTarget,
+    tracker: TelescopeTracker,
+    receiver_configuration: ReceiverConfiguration,
+    scaling: Scaling,
     measurements: Arc<Mutex<Vec<Measurement>>>,
     cancellation_token: CancellationToken,
 ) -> () {
+    let Scaling {
+        noise_diode,
+        calibration,
+    } = scaling;
+    let Receivers {
+        receivers,
+        record_polarizations,
+        rfi_mask,
+    } = receivers;
+    let record_polarizations = record_polarizations && receivers.len() > 1;
     // Switched HI example
-    let tint: f64 = 1.0; // integration time per cycle, seconds
-    let srate: f64 = 2.5e6; // sample rate, Hz
-    let sfreq: f64 = 1.4204e9;
-    let rfreq: f64 = 1.4179e9;
-    let avg_pts: usize = 512; // ^2 Number of points after average, setting spectral resolution
-    let fft_pts: usize = 8192; // ^2 Number of points in FFT, setting spectral resolution
-    let gain: f64 = 38.0;
-
-    // Setup usrp for taking data
-    let args = format!("addr={}", address);
-    let mut usrp = Usrp::open(&args).unwrap(); // Brage
+    let settings = ReceiverSettings::new(&receiver_configuration);
+    let tint: f64 = match receiver_configuration.switching {
+        SwitchingMode::PositionSwitched { .. } => POSITION_SWITCHED_INTEGRATION_TIME,
+        SwitchingMode::TotalPower | SwitchingMode::FrequencySwitched => 1.0,
+    }; // integration time per cycle, seconds
+    let srate: f64 = settings.sample_rate; // sample rate, Hz
+    let sfreq: f64 = settings.center_frequency;
+    let rfreq: f64 = receiver_configuration
+        .reference_frequency
+        .unwrap_or(sfreq - srate);
+    let window_function = receiver_configuration.window;
+    let avg_pts: usize = settings.channels; // Number of points after average, setting spectral resolution
+    let fft_pts: usize = settings.fft_size; // ^2 Number of points in FFT, setting spectral resolution
+    let gain: f64 = settings.gain;
 
-    // The N210 only has one input channel 0.
-    usrp.set_rx_gain(gain, 0, "").unwrap(); // empty string to set all gains
-    usrp.set_rx_antenna("TX/RX", 0).unwrap();
-    usrp.set_rx_dc_offset_enabled(true, 0).unwrap();
+    let spectrometer = Spectrometer {
+        srate,
+        avg_pts,
+        window: window_function.coefficients(fft_pts),
+        zoom: receiver_configuration.zoom,
+        flagging: receiver_configuration.rfi_flagging,
+        rfi_mask,
+    };
+    let mut usrps: Vec<Usrp> = receivers
+        .iter()
+        .map(|receiver| open_receiver(&receiver.address, gain, srate))
+        .collect();
 
-    usrp.set_rx_sample_rate(srate as f64, 0).unwrap();
+    let measured_tsys = noise_diode
+        .as_ref()
+        .and_then(|noise_diode| calibrate(&mut usrps[0], sfreq, tint, &spectrometer, noise_diode));
+    // Without a noise diode the latest hot and cold load calibration is the
+    // best estimate.
+    let known_tsys = measured_tsys.or(calibration.as_ref().map(|c| c.system_temperature));
+    let tsys = known_tsys.unwrap_or(DEFAULT_SYSTEM_TEMPERATURE);
 
     {
         let mut measurements = measurements.clone().lock_owned().await;
-        let mut measurement = Measurement {
+        let measurement = Measurement {
             amps: vec![0.0; avg_pts],
-            freqs: vec![0.0; avg_pts],
+            freqs: spectrometer.frequencies(sfreq),
+            target,
             start: Utc::now(),
             duration: Duration::from_secs(0),
+            window: window_function,
+            integration_time: Duration::from_secs(0),
+            requested_integration_time: Duration::from_secs(0),
+            dropped_samples: 0,
+            system_temperature: known_tsys,
+            interrupted: false,
+            receiver_configuration: Some(receiver_configuration),
+            continuum: Vec::new(),
+            max_tracking_error: None,
+            cycles: 0,
+            polarizations: if record_polarizations {
+                receivers
+                    .iter()
+                    .map(|receiver| PolarizationSpectrum {
+                        polarization: receiver.polarization,
+                        amps: vec![0.0; avg_pts],
+                    })
+                    .collect()
+            } else {
+                vec![]
+            },
+            flagged: vec![0.0; avg_pts],
         };
-        for i in 0..avg_pts {
-            measurement.freqs[i] = sfreq - 0.5 * srate + srate * (i as f64 / avg_pts as f64);
-        }
         measurements.push(measurement);
     }
 
     // start taking data until integrate is false
     let mut n = 0.0;
+    let mut statistics: Option<ReceiveStatistics> = None;
     while !cancellation_token.is_cancelled() {
-        let mut spec = vec![0.0; avg_pts];
-        measure_switched(
-            &mut usrp, sfreq, rfreq, fft_pts, tint, avg_pts, srate, &mut spec,
-        );
+        let (specs, mut cycle) = match receiver_configuration.switching {
+            SwitchingMode::TotalPower => {
+                let (specs_total, cycle) = measure_each(&mut usrps, |usrp, spec| {
+                    measure_single(usrp, sfreq, tint, &spectrometer, spec)
+                });
+                // In K if the receiver has been calibrated with these channels.
+                let specs = specs_total
+                    .into_iter()
+                    .map(|spec_total| {
+                        calibration
+                            .as_ref()
+                            .and_then(|c| c.to_temperature(&spec_total))
+                            .unwrap_or(spec_total)
+                    })
+                    .collect();
+                (specs, cycle)
+            }
+            SwitchingMode::FrequencySwitched => measure_each(&mut usrps, |usrp, spec| {
+                measure_switched(usrp, sfreq, rfreq, tint, &spectrometer, tsys, spec)
+            }),
+            SwitchingMode::PositionSwitched { azimuth_offset } => {
+                let Some((specs_on, specs_off, cycle)) = measure_on_off(
+                    &mut usrps,
+                    &tracker,
+                    azimuth_offset.to_radians(),
+                    sfreq,
+                    tint,
+                    &spectrometer,
+                    &cancellation_token,
+                )
+                .await
+                else {
+                    break;
+                };
+                // Form on-off difference and scale with Tsys
+                let specs = specs_on
+                    .iter()
+                    .zip(&specs_off)
+                    .map(|(spec_on, spec_off)| {
+                        (0..avg_pts)
+                            .map(|i| tsys * (spec_on[i] - spec_off[i]) / spec_off[i])
+                            .collect()
+                    })
+                    .collect();
+                (specs, cycle)
+            }
+        };
+        let spec = average_spectra(&specs, avg_pts);
         n = n + 1.0;
+        // The continuum goes straight into the measurement rather than
+        // piling up in the running totals.
+        let continuum = std::mem::take(&mut cycle.continuum);
+        let flagged = std::mem::take(&mut cycle.flagged);
+        let total = match statistics.take() {
+            Some(statistics) => statistics.followed_by(cycle),
+            None => cycle,
+        };
 
         let mut measurements = measurements.lock().await;
         let measurement = measurements.last_mut().unwrap();
         for i in 0..avg_pts {
             measurement.amps[i] = (measurement.amps[i] * (n - 1.0) + spec[i]) / n;
         }
-        measurement.duration = Utc::now()
-            .signed_duration_since(measurement.start)
-            .to_std()
-            .unwrap();
+        for (total, flagged) in measurement.flagged.iter_mut().zip(&flagged) {
+            *total = (*total * (n - 1.0) + flagged) / n;
+        }
+        for (polarization, polarization_spec) in measurement.polarizations.iter_mut().zip(&specs) {
+            for (total, amp) in polarization.amps.iter_mut().zip(polarization_spec) {
+                *total = (*total * (n - 1.0) + amp) / n;
+            }
+        }
+        // Prefer the device clock, which is not affected by the time spent
+        // processing on the host, and fall back to wall clock time.
+        measurement.duration = match (total.start_time, total.end_time) {
+            (Some(start_time), Some(end_time)) => Duration::from_secs_f64(end_time - start_time),
+            _ => Utc::now()
+                .signed_duration_since(measurement.start)
+                .to_std()
+                .unwrap(),
+        };
+        measurement.integration_time = Duration::from_secs_f64(total.received as f64 / srate);
+        measurement.requested_integration_time = Duration::from_secs_f64(n * tint);
+        measurement.cycles = n as u64;
+        measurement.dropped_samples = total.dropped as u64;
+        measurement.continuum.extend(continuum);
+        if let Ok(TelescopeTrackerInfo {
+            commanded_horizontal: Some(commanded),
+            current_horizontal: current,
+            ..
+        }) = tracker.info()
+        {
+            let tracking_error = angular_separation(
+                commanded.azimuth,
+                commanded.altitude,
+                current.azimuth,
+                current.altitude,
+            );
+            measurement.max_tracking_error = Some(match measurement.max_tracking_error {
+                Some(max) if max.0 >= tracking_error.0 => max,
+                _ => tracking_error,
+            });
+        }
+        statistics = Some(total);
+        if receiver_configuration
+            .mode
+            .is_complete(measurement.requested_integration_time)
+        {
+            break;
+        }
     }
+    // Back on the target if the integration was stopped while switched.
+    tracker.set_azimuth_offset(Radians(0.0));
 }
 
 #[async_trait]
@@ -264,6 +1122,50 @@ impl Telescope for SalsaTelescope {
         self.controller.target()
     }
 
+    fn capabilities(&self) -> TelescopeCapabilities {
+        let mut observation_modes = vec![
+            ObservationMode::TotalPower,
+            ObservationMode::FrequencySwitched,
+            ObservationMode::PositionSwitched,
+            ObservationMode::Zoom,
+        ];
+        if self.rfi_scan.is_some() {
+            observation_modes.push(ObservationMode::RfiScan);
+        }
+        TelescopeCapabilities {
+            observed_band: FrequencyRange {
+                min: SIGNAL_FREQUENCY - 0.5 * SAMPLE_RATE,
+                max: SIGNAL_FREQUENCY + 0.5 * SAMPLE_RATE,
+            },
+            reference_band: Some(FrequencyRange {
+                min: SIGNAL_FREQUENCY - MAX_REFERENCE_OFFSET,
+                max: SIGNAL_FREQUENCY + MAX_REFERENCE_OFFSET,
+            }),
+            bandwidths: ZOOM_DECIMATIONS
+                .iter()
+                .map(|factor| SAMPLE_RATE / *factor as f64)
+                .collect(),
+            sample_rates: SAMPLE_RATES.to_vec(),
+            channels: CHANNELS,
+            window_functions: WindowFunction::ALL.to_vec(),
+            observation_modes,
+            slewing_speed: SALSA_SLEWING_SPEED,
+            min_altitude: LOWEST_ALLOWED_ALTITUDE,
+        }
+    }
+
+    fn pointing_correction(&self) -> PointingCorrection {
+        self.controller.pointing_correction()
+    }
+
+    fn set_pointing_correction(&mut self, correction: PointingCorrection) {
+        self.controller.set_pointing_correction(correction);
+    }
+
+    fn set_calibration(&mut self, calibration: Calibration) {
+        self.calibration = Some(calibration);
+    }
+
     async fn set_target(
         &mut self,
         target: TelescopeTarget,
@@ -271,36 +1173,78 @@ impl Telescope for SalsaTelescope {
         self.controller.set_target(target)
     }
 
+    async fn preview_target(&self, target: TelescopeTarget) -> TargetPreview {
+        self.controller.preview_target(target, SALSA_SLEWING_SPEED)
+    }
+
+    fn tracker_telemetry(&self) -> Option<TrackerTelemetry> {
+        self.telemetry.clone()
+    }
+
+    fn connection_health(&self) -> Option<ConnectionHealth> {
+        // The receiver is only opened by measurements and RFI scans.
+        let receiver_error = self
+            .supervisor
+            .health()
+            .into_iter()
+            .filter(|task| task.name == "measurement" || task.name == "rfi_scan")
+            .find_map(|task| task.last_error);
+        Some(ConnectionHealth {
+            receiver_error,
+            ..self.controller.connection_health()
+        })
+    }
+
+    async fn send_raw_command(&mut self, bytes: Vec<u8>) -> Result<Vec<u8>, TelescopeError> {
+        let response = self.controller.send_raw_command(bytes);
+        match tokio::time::timeout(RAW_COMMAND_TIMEOUT, response).await {
+            Ok(Ok(result)) => result,
+            _ => Err(TelescopeError::TelescopeNotConnected),
+        }
+    }
+
+    async fn stop_motion(&mut self) -> Result<(), TelescopeError> {
+        let response = self.controller.stop();
+        match tokio::time::timeout(RAW_COMMAND_TIMEOUT, response).await {
+            Ok(Ok(result)) => result,
+            _ => Err(TelescopeError::TelescopeNotConnected),
+        }
+    }
+
     async fn set_receiver_configuration(
         &mut self,
         receiver_configuration: ReceiverConfiguration,
     ) -> Result<ReceiverConfiguration, ReceiverError> {
-        if receiver_configuration.integrate && !self.receiver_configuration.integrate {
-            if self.active_integration.is_some() {
-                return Err(ReceiverError::IntegrationAlreadyRunning);
+        ReceiverSettings::new(&receiver_configuration).validate(&receiver_configuration)?;
+        let transition = self
+            .receiver_configuration
+            .transition(&receiver_configuration)?;
+        match transition {
+            ReceiverTransition::Stop => {
+                log::info!("Stopping integration");
+                if let Some(active_integration) = &mut self.active_integration {
+                    active_integration.cancellation_token.cancel();
+                }
             }
-
-            log::info!("Starting integration");
-            self.receiver_configuration.integrate = true;
-            let cancellation_token = CancellationToken::new();
-            let measurement_task = {
-                let address = self.receiver_address.clone();
-                let measurements = self.measurements.clone();
-                let cancellation_token = cancellation_token.clone();
-                tokio::spawn(async move {
-                    measure(address, measurements, cancellation_token).await;
-                })
-            };
-            self.active_integration = Some(ActiveIntegration {
-                cancellation_token,
-                measurement_task,
-            });
-        } else if !receiver_configuration.integrate && self.receiver_configuration.integrate {
-            log::info!("Stopping integration");
-            if let Some(active_integration) = &mut self.active_integration {
-                active_integration.cancellation_token.cancel();
+            ReceiverTransition::Restart => {
+                log::info!("Restarting integration with new receiver configuration");
+                self.finish_integration().await;
             }
-            self.receiver_configuration.integrate = false;
+            ReceiverTransition::Configure
+            | ReceiverTransition::Start
+            | ReceiverTransition::Continue => {}
+        }
+        self.receiver_configuration = ReceiverConfiguration {
+            integrate: false,
+            ..receiver_configuration
+        };
+        if matches!(
+            transition,
+            ReceiverTransition::Start | ReceiverTransition::Restart
+        ) {
+            self.start_integration().await?;
+        } else {
+            self.receiver_configuration.integrate = transition == ReceiverTransition::Continue;
         }
         Ok(self.receiver_configuration)
     }
@@ -318,6 +1262,7 @@ impl Telescope for SalsaTelescope {
                         frequencies: measurement.freqs,
                         spectra: measurement.amps,
                         observation_time: measurement.duration,
+                        cycles: measurement.cycles,
                     };
                     Some(latest_observation)
                 }
@@ -333,19 +1278,70 @@ impl Telescope for SalsaTelescope {
             most_recent_error: controller_info.most_recent_error,
             measurement_in_progress: self.active_integration.is_some(),
             latest_observation,
+            auxiliary_devices: self.auxiliary_devices.states(),
+            tasks: self.supervisor.health(),
+            controller_latency: controller_info.controller_latency,
+            controller_commands: Some(controller_info.controller_commands),
         })
     }
 
     async fn update(&mut self, _delta_time: Duration) -> Result<(), TelescopeError> {
+        self.auxiliary_devices.apply_defaults(switch_over_tcp);
+
         if let Some(active_integration) = self.active_integration.take() {
             if active_integration.measurement_task.is_finished() {
                 if let Err(error) = active_integration.measurement_task.await {
                     log::error!("Error while waiting for measurement task: {}", error);
+                } else if let Some(measurement) = self.measurements.lock().await.last() {
+                    self.completed_measurements.push(measurement.clone());
                 }
+                // The integration may have ended by itself, e.g. after a
+                // fixed time.
+                self.receiver_configuration.integrate = false;
             } else {
                 self.active_integration = Some(active_integration);
             }
         }
+
+        if let Some(active_rfi_scan) = self.active_rfi_scan.take() {
+            if active_rfi_scan.scan_task.is_finished() {
+                // The outer option is empty if the scan was stopped by a shutdown.
+                match active_rfi_scan.scan_task.await.map(Option::flatten) {
+                    Ok(Some(scan)) => {
+                        if self.recent_rfi_scans.len() >= REFERENCE_RFI_SCAN_HISTORY {
+                            self.recent_rfi_scans.remove(0);
+                        }
+                        self.recent_rfi_scans.push(scan.clone());
+                        self.completed_rfi_scans.push(scan);
+                    }
+                    Ok(None) => {}
+                    Err(error) => log::error!("Error while waiting for RFI scan task: {}", error),
+                }
+            } else {
+                self.active_rfi_scan = Some(active_rfi_scan);
+            }
+        }
+
+        if self.should_start_rfi_scan() {
+            if let Some(definition) = self.rfi_scan.clone() {
+                log::info!("Starting RFI scan for telescope {}", self.name);
+                self.last_rfi_scan = Some(Utc::now());
+                let cancellation_token = CancellationToken::new();
+                let scan_task = self.supervisor.spawn_once(
+                    "rfi_scan",
+                    rfi_scan(
+                        self.receiver_address.clone(),
+                        self.name.clone(),
+                        definition,
+                        cancellation_token.clone(),
+                    ),
+                );
+                self.active_rfi_scan = Some(ActiveRfiScan {
+                    cancellation_token,
+                    scan_task,
+                });
+            }
+        }
         Ok(())
     }
 
@@ -353,24 +1349,224 @@ impl Telescope for SalsaTelescope {
         self.controller.restart();
         Ok(())
     }
+
+    async fn set_auxiliary_device(
+        &mut self,
+        name: &str,
+        on: bool,
+    ) -> Result<AuxiliaryDeviceState, TelescopeError> {
+        self.auxiliary_devices.switch(name, on, switch_over_tcp)
+    }
+
+    async fn measurement_in_progress(&self) -> Option<Measurement> {
+        self.active_integration.as_ref()?;
+        self.measurements.lock().await.last().cloned()
+    }
+
+    async fn take_completed_measurements(&mut self) -> Vec<Measurement> {
+        std::mem::take(&mut self.completed_measurements)
+    }
+
+    async fn take_completed_rfi_scans(&mut self) -> Vec<RfiScan> {
+        std::mem::take(&mut self.completed_rfi_scans)
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use hex_literal::hex;
 
     use super::*;
+    use crate::angles::Degrees;
 
     #[test]
-    fn test_rot2prog_bytes_to_angle_documented() {
-        // This behavior is what I expect reading the documentation, but the telescope seems to work with returned bytes
-        // directly instead of ascii encoded numbers. E.g. 0x03 instead of 0x33 which is '3' in ascii.
-        assert!((rot2prog_bytes_to_angle_documented(&hex!("3336303030")) - 0.0).abs() < 0.01,);
-        // Example from documentation
-        assert!(
-            (rot2prog_bytes_to_angle_documented(&hex!("3338323333")) - 22.33_f64.to_radians())
-                .abs()
-                < 0.01,
+    fn test_window_power() {
+        let fft_pts = 8192;
+        assert_eq!(
+            window_power(&WindowFunction::Rectangular.coefficients(fft_pts)),
+            1.0
+        );
+        // Known values for the mean squared coefficient of the windows
+        for (window, expected) in [
+            (WindowFunction::Hann, 0.375),
+            (WindowFunction::Hamming, 0.3974),
+            (WindowFunction::BlackmanHarris, 0.2580),
+        ] {
+            assert!((window_power(&window.coefficients(fft_pts)) - expected).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn test_channel_frequencies() {
+        assert_eq!(
+            channel_frequencies(1.4204e9, 250e3, 4),
+            vec![1.4202750e9, 1.4203375e9, 1.4204e9, 1.4204625e9]
+        );
+    }
+
+    #[test]
+    fn test_zoom_frequency_axis() {
+        let spectrometer = Spectrometer {
+            srate: 2.5e6,
+            avg_pts: 512,
+            window: WindowFunction::Rectangular.coefficients(8192),
+            zoom: Some(ZoomConfiguration {
+                bandwidth: 250e3,
+                offset: 100e3,
+            }),
+            flagging: RfiFlagging::default(),
+            rfi_mask: vec![],
+        };
+        assert_eq!(spectrometer.decimation(), 10);
+        let frequencies = spectrometer.frequencies(1.4204e9);
+        assert_eq!(frequencies.len(), 512);
+        assert_eq!(frequencies[0], 1.4204e9 + 100e3 - 125e3);
+        assert_eq!(frequencies[256], 1.4204e9 + 100e3);
+    }
+
+    #[test]
+    fn test_downconvert_and_decimate() {
+        // A tone 10 kHz above the zoomed band center should end up 10 kHz
+        // above the center of the decimated spectrum.
+        let srate = 1e6;
+        let offset = 200e3;
+        let tone = offset + 10e3;
+        let samples: Vec<Complex<f64>> = (0..100_000)
+            .map(|n| Complex::from_polar(1.0, 2.0 * PI * tone * n as f64 / srate))
+            .collect();
+        let decimated = downconvert_and_decimate(&samples, offset, srate, 10);
+        assert_eq!(decimated.len(), (100_000 - 81) / 10 + 1);
+
+        let fft_pts = 100;
+        let spectrum = power_spectrum(
+            &decimated,
+            &WindowFunction::Rectangular.coefficients(fft_pts),
+        );
+        let peak = (0..fft_pts)
+            .max_by(|&a, &b| spectrum[a].total_cmp(&spectrum[b]))
+            .unwrap();
+        let frequencies = channel_frequencies(offset, srate / 10.0, fft_pts);
+        assert_eq!(frequencies[peak], tone);
+    }
+
+    #[test]
+    fn test_receive_statistics_detects_dropped_samples() {
+        let srate = 1000.0;
+        let mut statistics = ReceiveStatistics::default();
+        statistics.record(Some(10.0), 100, srate);
+        statistics.record(Some(10.1), 100, srate);
+        assert_eq!(statistics.dropped, 0);
+        // 50 samples missing between the packets
+        statistics.record(Some(10.25), 100, srate);
+        assert_eq!(statistics.dropped, 50);
+        assert_eq!(statistics.received, 300);
+        assert_eq!(statistics.start_time, Some(10.0));
+        assert_eq!(statistics.end_time, Some(10.35));
+
+        let mut later = ReceiveStatistics::default();
+        later.record(Some(11.0), 100, srate);
+        let total = statistics.followed_by(later);
+        assert_eq!(total.start_time, Some(10.0));
+        assert_eq!(total.end_time, Some(11.1));
+        assert_eq!(total.received, 400);
+        assert_eq!(total.dropped, 50);
+    }
+
+    #[test]
+    fn test_continuum_power() {
+        let start = Utc::now();
+        let srate = 10.0;
+        // 2.5 s of samples, the last half interval is long enough to be kept.
+        let mut samples = vec![Complex::new(2.0, 0.0); 25];
+        samples[0] = Complex::new(0.0, 6.0);
+        let continuum = continuum_power(&samples, srate, start);
+        assert_eq!(continuum.len(), 3);
+        assert_eq!(continuum[0].power, (36.0 + 9.0 * 4.0) / 10.0);
+        assert_eq!(continuum[2].power, 4.0);
+        assert_eq!(continuum[2].time, start + chrono::Duration::seconds(2));
+
+        // Less than half an interval left over is dropped.
+        assert_eq!(continuum_power(&samples[..14], srate, start).len(), 1);
+    }
+
+    #[test]
+    fn test_receiver_settings_validation() {
+        let configuration = ReceiverConfiguration::stopped();
+        let validate = |configuration: ReceiverConfiguration| {
+            ReceiverSettings::new(&configuration).validate(&configuration)
+        };
+        assert_eq!(validate(configuration), Ok(()));
+        let wide = ReceiverConfiguration {
+            sample_rate: Some(5e6),
+            zoom: Some(ZoomConfiguration {
+                bandwidth: 2.5e6,
+                offset: 1e6,
+            }),
+            ..configuration
+        };
+        assert_eq!(validate(wide), Ok(()));
+        // The zoom is outside the default band.
+        assert_eq!(
+            validate(ReceiverConfiguration {
+                sample_rate: None,
+                ..wide
+            }),
+            Err(ReceiverError::InvalidZoom)
+        );
+
+        let unsupported = |parameter| Err(ReceiverError::UnsupportedSetting(parameter));
+        assert_eq!(
+            validate(ReceiverConfiguration {
+                sample_rate: Some(3e6),
+                ..configuration
+            }),
+            unsupported(ReceiverParameter::SampleRate)
+        );
+        assert_eq!(
+            validate(ReceiverConfiguration {
+                center_frequency: Some(10e9),
+                ..configuration
+            }),
+            unsupported(ReceiverParameter::CenterFrequency)
+        );
+        assert_eq!(
+            validate(ReceiverConfiguration {
+                reference_frequency: Some(SIGNAL_FREQUENCY + 2.0 * MAX_REFERENCE_OFFSET),
+                ..configuration
+            }),
+            unsupported(ReceiverParameter::ReferenceFrequency)
+        );
+        assert_eq!(
+            validate(ReceiverConfiguration {
+                fft_size: Some(1000),
+                ..configuration
+            }),
+            unsupported(ReceiverParameter::FftSize)
+        );
+        assert_eq!(
+            validate(ReceiverConfiguration {
+                fft_size: Some(256),
+                channels: Some(512),
+                ..configuration
+            }),
+            unsupported(ReceiverParameter::Channels)
+        );
+        let position_switched = |degrees| ReceiverConfiguration {
+            switching: SwitchingMode::PositionSwitched {
+                azimuth_offset: Degrees(degrees),
+            },
+            ..configuration
+        };
+        assert_eq!(validate(position_switched(-10.0)), Ok(()));
+        assert_eq!(
+            validate(position_switched(90.0)),
+            unsupported(ReceiverParameter::Switching)
+        );
+        assert_eq!(
+            validate(ReceiverConfiguration {
+                gain: Some(60.0),
+                ..configuration
+            }),
+            unsupported(ReceiverParameter::Gain)
         );
     }
 }
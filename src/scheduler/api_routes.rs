@@ -0,0 +1,229 @@
+use crate::authentication::Requester;
+use crate::database::{DataBase, Storage};
+use crate::park_policies::enforce_quiet_hours;
+use crate::scheduler::jobs::{
+    cancel_scheduled_observation, schedule_observation, NewScheduledObservation, ScheduleError,
+    ScheduledObservation,
+};
+use crate::scheduler::{
+    cancel_queue, plan_queue, running_queue, start_queue, ObservationPlan, ObservationQueues,
+    QueuedObservation, SchedulerError,
+};
+use crate::telescope::TelescopeCollection;
+use crate::ups::refuse_on_battery;
+use axum::{
+    extract::{Json, Path, State},
+    http::StatusCode,
+    middleware,
+    response::{IntoResponse, Response},
+    routing::{delete, get, post},
+    Router,
+};
+use chrono::Utc;
+
+/// State of the scheduler routes, which run queues on the telescopes.
+#[derive(Clone)]
+pub struct SchedulerState<StorageType: Storage> {
+    pub database: DataBase<StorageType>,
+    pub telescopes: TelescopeCollection,
+    pub queues: ObservationQueues,
+}
+
+pub fn routes(
+    telescopes: TelescopeCollection,
+    database: DataBase<impl Storage + 'static>,
+) -> Router {
+    // Running a queue controls the telescope, so it is guarded like the
+    // telescope routes. Planning only reports what would happen.
+    Router::new()
+        .route(
+            "/:telescope_id/queue",
+            get(get_queue).post(post_queue).delete(delete_queue),
+        )
+        .route_layer(middleware::from_fn_with_state(
+            database.clone(),
+            enforce_quiet_hours,
+        ))
+        .route_layer(middleware::from_fn_with_state(
+            database.clone(),
+            refuse_on_battery,
+        ))
+        .route("/:telescope_id/plan", post(post_plan))
+        // Scheduled observations run later, when these checks are made.
+        .route(
+            "/jobs",
+            get(get_scheduled_observations).post(post_scheduled_observation),
+        )
+        .route("/jobs/:id", delete(delete_scheduled_observation))
+        .with_state(SchedulerState {
+            database,
+            telescopes,
+            queues: Default::default(),
+        })
+}
+
+impl IntoResponse for SchedulerError {
+    fn into_response(self) -> Response {
+        match self {
+            SchedulerError::UnknownTelescope => {
+                (StatusCode::NOT_FOUND, "Telescope not found".to_string()).into_response()
+            }
+            SchedulerError::EmptyQueue => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "The queue has no observations".to_string(),
+            )
+                .into_response(),
+            SchedulerError::InvalidTarget(error) => {
+                (StatusCode::UNPROCESSABLE_ENTITY, error.to_string()).into_response()
+            }
+            SchedulerError::Telescope(error) => {
+                (StatusCode::SERVICE_UNAVAILABLE, error.to_string()).into_response()
+            }
+            SchedulerError::ServiceUnavailable => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Failed to run observation queue".to_string(),
+            )
+                .into_response(),
+        }
+    }
+}
+
+impl IntoResponse for ScheduleError {
+    fn into_response(self) -> Response {
+        match self {
+            ScheduleError::UnknownTelescope => {
+                (StatusCode::NOT_FOUND, "Telescope not found".to_string()).into_response()
+            }
+            ScheduleError::InvalidTarget(error) => {
+                (StatusCode::UNPROCESSABLE_ENTITY, error.to_string()).into_response()
+            }
+            ScheduleError::NoIntegrationTime => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "The integration time must be positive".to_string(),
+            )
+                .into_response(),
+            ScheduleError::InPast => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "The start time has already passed".to_string(),
+            )
+                .into_response(),
+            ScheduleError::NotBooked => (
+                StatusCode::FORBIDDEN,
+                "The observation must be within your booking of the telescope".to_string(),
+            )
+                .into_response(),
+            ScheduleError::Conflict => (
+                StatusCode::CONFLICT,
+                "Another observation is scheduled at the same time".to_string(),
+            )
+                .into_response(),
+            ScheduleError::NotFound => (
+                StatusCode::NOT_FOUND,
+                "Scheduled observation not found".to_string(),
+            )
+                .into_response(),
+            ScheduleError::NotPending => (
+                StatusCode::CONFLICT,
+                "The observation has already started".to_string(),
+            )
+                .into_response(),
+            ScheduleError::NotAllowed => (
+                StatusCode::FORBIDDEN,
+                "Only the user who scheduled the observation and admins may cancel it".to_string(),
+            )
+                .into_response(),
+            ScheduleError::ServiceUnavailable => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Failed to schedule observation".to_string(),
+            )
+                .into_response(),
+        }
+    }
+}
+
+async fn get_scheduled_observations<StorageType: Storage>(
+    State(state): State<SchedulerState<StorageType>>,
+) -> Json<Vec<ScheduledObservation>> {
+    Json(
+        state
+            .database
+            .get_data()
+            .await
+            .expect("As long as no one is manually editing the database, this should never fail.")
+            .scheduled_observations,
+    )
+}
+
+async fn post_scheduled_observation<StorageType: Storage>(
+    State(state): State<SchedulerState<StorageType>>,
+    Json(observation): Json<NewScheduledObservation>,
+) -> Result<(StatusCode, Json<ScheduledObservation>), ScheduleError> {
+    let scheduled = schedule_observation(&state.database, observation, Utc::now()).await?;
+    Ok((StatusCode::CREATED, Json(scheduled)))
+}
+
+/// Cancel an observation that has not started yet.
+async fn delete_scheduled_observation<StorageType: Storage>(
+    State(state): State<SchedulerState<StorageType>>,
+    Path(id): Path<u64>,
+    requester: Requester,
+) -> Result<Json<ScheduledObservation>, ScheduleError> {
+    Ok(Json(
+        cancel_scheduled_observation(&state.database, id, requester.name()).await?,
+    ))
+}
+
+async fn post_plan<StorageType: Storage>(
+    State(state): State<SchedulerState<StorageType>>,
+    Path(telescope_id): Path<String>,
+    Json(queue): Json<Vec<QueuedObservation>>,
+) -> Result<Json<ObservationPlan>, SchedulerError> {
+    let plan = plan_queue(
+        &state.database,
+        &state.telescopes,
+        &telescope_id,
+        queue,
+        Utc::now(),
+    )
+    .await?;
+    Ok(Json(plan))
+}
+
+async fn get_queue<StorageType: Storage>(
+    State(state): State<SchedulerState<StorageType>>,
+    Path(telescope_id): Path<String>,
+) -> Json<Option<ObservationPlan>> {
+    Json(running_queue(&state.queues, &telescope_id).await)
+}
+
+/// Plan the queue and start executing it, returning the plan.
+async fn post_queue<StorageType: Storage>(
+    State(state): State<SchedulerState<StorageType>>,
+    Path(telescope_id): Path<String>,
+    Json(queue): Json<Vec<QueuedObservation>>,
+) -> Result<Json<ObservationPlan>, SchedulerError> {
+    let plan = plan_queue(
+        &state.database,
+        &state.telescopes,
+        &telescope_id,
+        queue,
+        Utc::now(),
+    )
+    .await?;
+    start_queue(&state.queues, &state.telescopes, plan.clone()).await?;
+    log::info!(
+        "Started queue of {} observations on {}, with {} s of slewing",
+        plan.observations.len(),
+        telescope_id,
+        plan.total_slew_time().num_seconds()
+    );
+    Ok(Json(plan))
+}
+
+async fn delete_queue<StorageType: Storage>(
+    State(state): State<SchedulerState<StorageType>>,
+    Path(telescope_id): Path<String>,
+) -> Result<StatusCode, SchedulerError> {
+    cancel_queue(&state.queues, &state.telescopes, &telescope_id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
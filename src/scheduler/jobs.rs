@@ -0,0 +1,559 @@
+//! Observations scheduled to run at a booked time, without the user
+//! present.
+//!
+//! A user with a booking queues an observation to run within it. The
+//! scheduled observation service starts each one when its time comes:
+//! the telescope is pointed at the target, given some time to get there,
+//! and then integrates for the requested time. The telescope service
+//! archives the measurement like any other. Observations are not run while
+//! the telescope has to be parked for quiet hours or is on battery power.
+
+use crate::bookings::Booking;
+use crate::database::{DataBase, DataBaseError, DataModel, Storage};
+use crate::park_policies::{park_reason, ParkReason};
+use crate::scheduler::receiver_configuration;
+use crate::telescope::{Telescope, TelescopeCollection};
+use crate::telescopes::{InvalidTarget, ReceiverConfiguration, TelescopeStatus, TelescopeTarget};
+use crate::ups::power_status;
+use crate::users::is_admin;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+/// How often the service looks for observations that are due.
+const SCHEDULE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10);
+/// Time the telescope is given to reach the target before integrating.
+pub const SLEW_ALLOWANCE: Duration = Duration::minutes(2);
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct NewScheduledObservation {
+    pub telescope_name: String,
+    pub user_name: String,
+    pub start_time: DateTime<Utc>,
+    pub target: TelescopeTarget,
+    pub integration_seconds: u64,
+    /// Receiver settings to integrate with, the telescope's defaults if not
+    /// given. Whether it integrates is controlled by the schedule.
+    #[serde(default)]
+    pub receiver_configuration: Option<ReceiverConfiguration>,
+}
+
+impl NewScheduledObservation {
+    /// The end of the integration, if the telescope needs all of the slew
+    /// allowance to reach the target.
+    pub fn end_time(&self) -> DateTime<Utc> {
+        self.start_time + SLEW_ALLOWANCE + Duration::seconds(self.integration_seconds as i64)
+    }
+
+    fn within(&self, booking: &Booking) -> bool {
+        booking.telescope_name == self.telescope_name
+            && booking.user_name == self.user_name
+            && booking.start_time <= self.start_time
+            && self.end_time() <= booking.end_time
+    }
+
+    fn overlaps(&self, other: &NewScheduledObservation) -> bool {
+        self.telescope_name == other.telescope_name
+            && self.start_time < other.end_time()
+            && other.start_time < self.end_time()
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub enum ScheduledObservationStatus {
+    Pending,
+    Running,
+    Completed { finished: DateTime<Utc> },
+    Failed { reason: String },
+    Cancelled,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct ScheduledObservation {
+    pub id: u64,
+    pub created: DateTime<Utc>,
+    pub status: ScheduledObservationStatus,
+    #[serde(flatten)]
+    pub observation: NewScheduledObservation,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ScheduleError {
+    UnknownTelescope,
+    InvalidTarget(InvalidTarget),
+    NoIntegrationTime,
+    InPast,
+    /// The observation is not within a booking of the telescope by the user.
+    NotBooked,
+    /// Another observation is scheduled on the telescope at the same time.
+    Conflict,
+    NotFound,
+    /// Only pending observations can be cancelled.
+    NotPending,
+    /// Only the user who scheduled the observation and admins may cancel it.
+    NotAllowed,
+    ServiceUnavailable,
+}
+
+impl From<DataBaseError> for ScheduleError {
+    fn from(_source: DataBaseError) -> Self {
+        Self::ServiceUnavailable
+    }
+}
+
+fn check_observation(
+    data_model: &DataModel,
+    observation: &NewScheduledObservation,
+    now: DateTime<Utc>,
+) -> Result<(), ScheduleError> {
+    if !data_model
+        .telescopes
+        .iter()
+        .any(|t| t.name == observation.telescope_name)
+    {
+        return Err(ScheduleError::UnknownTelescope);
+    }
+    if observation.integration_seconds == 0 {
+        return Err(ScheduleError::NoIntegrationTime);
+    }
+    if observation.start_time < now {
+        return Err(ScheduleError::InPast);
+    }
+    if !data_model.bookings.iter().any(|b| observation.within(b)) {
+        return Err(ScheduleError::NotBooked);
+    }
+    if data_model
+        .scheduled_observations
+        .iter()
+        .filter(|s| {
+            matches!(
+                s.status,
+                ScheduledObservationStatus::Pending | ScheduledObservationStatus::Running
+            )
+        })
+        .any(|s| s.observation.overlaps(observation))
+    {
+        return Err(ScheduleError::Conflict);
+    }
+    Ok(())
+}
+
+pub async fn schedule_observation(
+    db: &DataBase<impl Storage>,
+    observation: NewScheduledObservation,
+    now: DateTime<Utc>,
+) -> Result<ScheduledObservation, ScheduleError> {
+    let observation = NewScheduledObservation {
+        target: observation
+            .target
+            .validated()
+            .map_err(ScheduleError::InvalidTarget)?,
+        ..observation
+    };
+    let mut result = Err(ScheduleError::ServiceUnavailable);
+    db.update_data(|mut data_model| {
+        result = check_observation(&data_model, &observation, now).map(|()| {
+            let id = data_model
+                .scheduled_observations
+                .iter()
+                .map(|s| s.id + 1)
+                .max()
+                .unwrap_or(1);
+            ScheduledObservation {
+                id,
+                created: now,
+                status: ScheduledObservationStatus::Pending,
+                observation: observation.clone(),
+            }
+        });
+        if let Ok(scheduled) = &result {
+            data_model.scheduled_observations.push(scheduled.clone());
+        }
+        data_model
+    })
+    .await?;
+    if let Ok(scheduled) = &result {
+        log::info!(
+            "{} scheduled observation {} on {} at {}",
+            observation.user_name,
+            scheduled.id,
+            observation.telescope_name,
+            observation.start_time
+        );
+    }
+    result
+}
+
+/// Cancel the observation `id` for `user_name`, who has to be the user who
+/// scheduled it or an admin.
+pub async fn cancel_scheduled_observation(
+    db: &DataBase<impl Storage>,
+    id: u64,
+    user_name: &str,
+) -> Result<ScheduledObservation, ScheduleError> {
+    let mut result = Err(ScheduleError::NotFound);
+    db.update_data(|mut data_model| {
+        let admin = is_admin(&data_model, user_name);
+        if let Some(scheduled) = data_model
+            .scheduled_observations
+            .iter_mut()
+            .find(|s| s.id == id)
+        {
+            result = if scheduled.observation.user_name != user_name && !admin {
+                Err(ScheduleError::NotAllowed)
+            } else if scheduled.status == ScheduledObservationStatus::Pending {
+                scheduled.status = ScheduledObservationStatus::Cancelled;
+                Ok(scheduled.clone())
+            } else {
+                Err(ScheduleError::NotPending)
+            };
+        }
+        data_model
+    })
+    .await?;
+    result
+}
+
+async fn set_status(
+    db: &DataBase<impl Storage>,
+    id: u64,
+    status: ScheduledObservationStatus,
+) -> Result<(), DataBaseError> {
+    db.update_data(|mut data_model| {
+        if let Some(scheduled) = data_model
+            .scheduled_observations
+            .iter_mut()
+            .find(|s| s.id == id)
+        {
+            scheduled.status = status;
+        }
+        data_model
+    })
+    .await
+}
+
+/// Why `observation` can't start at `now`, if it can't.
+fn refusal(
+    data_model: &DataModel,
+    observation: &NewScheduledObservation,
+    now: DateTime<Utc>,
+) -> Option<String> {
+    if !data_model.bookings.iter().any(|b| observation.within(b)) {
+        return Some("The booking was cancelled".to_string());
+    }
+    if now > observation.start_time + SLEW_ALLOWANCE {
+        return Some("The scheduled time was missed".to_string());
+    }
+    if park_reason(data_model, &observation.telescope_name, now) == Some(ParkReason::QuietHours) {
+        return Some(format!("Parked for {}", ParkReason::QuietHours));
+    }
+    if power_status(data_model).on_battery() {
+        return Some("The telescope is on battery power".to_string());
+    }
+    None
+}
+
+/// Point at the target, wait for the telescope to get there and integrate
+/// for the requested time.
+pub async fn run_scheduled_observation(
+    telescope: Arc<Mutex<dyn Telescope>>,
+    observation: &NewScheduledObservation,
+) -> Result<(), String> {
+    let poll_interval = std::time::Duration::from_secs(1);
+    telescope
+        .lock()
+        .await
+        .set_target(observation.target)
+        .await
+        .map_err(|error| format!("Failed to point at the target: {}", error))?;
+    let arrive_before = tokio::time::Instant::now() + SLEW_ALLOWANCE.to_std().unwrap_or_default();
+    loop {
+        let info = telescope.lock().await.get_info().await;
+        if matches!(info, Ok(info) if info.status == TelescopeStatus::Tracking) {
+            break;
+        }
+        if tokio::time::Instant::now() >= arrive_before {
+            return Err("The telescope did not reach the target in time".to_string());
+        }
+        tokio::time::sleep(poll_interval).await;
+    }
+
+    let settings = observation
+        .receiver_configuration
+        .unwrap_or_else(|| receiver_configuration(false));
+    telescope
+        .lock()
+        .await
+        .set_receiver_configuration(ReceiverConfiguration {
+            integrate: true,
+            ..settings
+        })
+        .await
+        .map_err(|error| format!("Failed to start integration: {:?}", error))?;
+    tokio::time::sleep(std::time::Duration::from_secs(
+        observation.integration_seconds,
+    ))
+    .await;
+    telescope
+        .lock()
+        .await
+        .set_receiver_configuration(ReceiverConfiguration {
+            integrate: false,
+            ..settings
+        })
+        .await
+        .map_err(|error| format!("Failed to stop integration: {:?}", error))?;
+    Ok(())
+}
+
+/// Start the observations that are due at `now`, returning how many were
+/// started. Observations that can't start are marked as failed.
+pub async fn start_due_observations<T>(
+    db: &DataBase<T>,
+    telescopes: &TelescopeCollection,
+    now: DateTime<Utc>,
+) -> Result<usize, DataBaseError>
+where
+    T: Storage + 'static,
+{
+    let data_model = db.get_data().await?;
+    let due: Vec<&ScheduledObservation> = data_model
+        .scheduled_observations
+        .iter()
+        .filter(|s| s.status == ScheduledObservationStatus::Pending)
+        .filter(|s| s.observation.start_time <= now)
+        .collect();
+    let mut started = 0;
+    for scheduled in due {
+        let observation = scheduled.observation.clone();
+        let telescope = telescopes
+            .read()
+            .await
+            .get(&observation.telescope_name)
+            .map(|container| container.telescope.clone());
+        let refusal = match telescope {
+            None => Some("The telescope is not available".to_string()),
+            Some(_) => refusal(&data_model, &observation, now),
+        };
+        if let Some(reason) = refusal {
+            log::warn!("Scheduled observation {} failed: {}", scheduled.id, reason);
+            set_status(
+                db,
+                scheduled.id,
+                ScheduledObservationStatus::Failed { reason },
+            )
+            .await?;
+            continue;
+        }
+        let Some(telescope) = telescope else {
+            continue;
+        };
+        set_status(db, scheduled.id, ScheduledObservationStatus::Running).await?;
+        log::info!(
+            "Starting scheduled observation {} on {}",
+            scheduled.id,
+            observation.telescope_name
+        );
+        let db = db.clone();
+        let id = scheduled.id;
+        tokio::spawn(async move {
+            let status = match run_scheduled_observation(telescope, &observation).await {
+                Ok(()) => ScheduledObservationStatus::Completed {
+                    finished: Utc::now(),
+                },
+                Err(reason) => {
+                    log::warn!("Scheduled observation {} failed: {}", id, reason);
+                    ScheduledObservationStatus::Failed { reason }
+                }
+            };
+            if let Err(error) = set_status(&db, id, status).await {
+                log::error!("Failed to update scheduled observation {}: {}", id, error);
+            }
+        });
+        started += 1;
+    }
+    Ok(started)
+}
+
+pub fn start_scheduled_observation_service<T>(
+    database: DataBase<T>,
+    telescopes: TelescopeCollection,
+) -> tokio::task::JoinHandle<()>
+where
+    T: Storage + 'static,
+{
+    tokio::spawn(async move {
+        // Observations running when the backend stopped never finished.
+        let interrupted = database
+            .update_data(|mut data_model| {
+                for scheduled in &mut data_model.scheduled_observations {
+                    if scheduled.status == ScheduledObservationStatus::Running {
+                        scheduled.status = ScheduledObservationStatus::Failed {
+                            reason: "Interrupted by a restart".to_string(),
+                        };
+                    }
+                }
+                data_model
+            })
+            .await;
+        if let Err(error) = interrupted {
+            log::error!(
+                "Failed to update interrupted scheduled observations: {}",
+                error
+            );
+        }
+        loop {
+            if let Err(error) = start_due_observations(&database, &telescopes, Utc::now()).await {
+                log::error!("Failed to start scheduled observations: {}", error);
+            }
+            tokio::time::sleep(SCHEDULE_INTERVAL).await;
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::angles::{Degrees, Radians};
+    use crate::database::create_in_memory_database;
+    use crate::interlock::CollisionInterlock;
+    use crate::supervisor::TelescopeSupervisor;
+    use crate::telescope::TelescopeContainer;
+    use crate::telescopes::test_utils::fake_telescope;
+    use std::collections::HashMap;
+    use tokio::sync::RwLock;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_scheduled_observation() {
+        let db = create_in_memory_database();
+        let now = Utc::now();
+        db.update_data(|mut data_model| {
+            data_model.telescopes = vec![fake_telescope("fake")];
+            data_model.bookings = vec![Booking {
+                start_time: now,
+                end_time: now + Duration::hours(1),
+                telescope_name: "fake".to_string(),
+                user_name: "anna".to_string(),
+            }];
+            data_model
+        })
+        .await
+        .unwrap();
+        let observation = |user_name: &str, start_minutes: i64| NewScheduledObservation {
+            telescope_name: "fake".to_string(),
+            user_name: user_name.to_string(),
+            start_time: now + Duration::minutes(start_minutes),
+            // Circumpolar from Onsala, where the fake telescope is.
+            target: TelescopeTarget::Equatorial {
+                ra: Radians(0.0),
+                dec: Degrees(80.0).to_radians(),
+            },
+            integration_seconds: 60,
+            receiver_configuration: None,
+        };
+
+        assert_eq!(
+            schedule_observation(&db, observation("bertil", 10), now).await,
+            Err(ScheduleError::NotBooked)
+        );
+        assert_eq!(
+            schedule_observation(&db, observation("anna", 58), now).await,
+            Err(ScheduleError::NotBooked)
+        );
+        let first = schedule_observation(&db, observation("anna", 10), now)
+            .await
+            .unwrap();
+        assert_eq!(first.status, ScheduledObservationStatus::Pending);
+        assert_eq!(
+            schedule_observation(&db, observation("anna", 11), now).await,
+            Err(ScheduleError::Conflict)
+        );
+        let second = schedule_observation(&db, observation("anna", 30), now)
+            .await
+            .unwrap();
+        assert_eq!(
+            cancel_scheduled_observation(&db, second.id, "bertil").await,
+            Err(ScheduleError::NotAllowed)
+        );
+        assert_eq!(
+            cancel_scheduled_observation(&db, second.id, "anna")
+                .await
+                .unwrap()
+                .status,
+            ScheduledObservationStatus::Cancelled
+        );
+
+        let telescope = Arc::new(Mutex::new(crate::fake_telescope::create(
+            "fake".to_string(),
+            None,
+            vec![],
+            TelescopeSupervisor::new("fake"),
+            CollisionInterlock::default(),
+        )));
+        let telescopes: TelescopeCollection = Arc::new(RwLock::new(HashMap::from([(
+            "fake".to_string(),
+            TelescopeContainer {
+                telescope: telescope.clone(),
+                supervisor: TelescopeSupervisor::new("fake"),
+                updates: Default::default(),
+            },
+        )])));
+        // Stand in for the telescope service, on the paused clock.
+        let service = tokio::spawn({
+            let telescope = telescope.clone();
+            async move {
+                loop {
+                    let _ = telescope
+                        .lock()
+                        .await
+                        .update(std::time::Duration::from_secs(1))
+                        .await;
+                    tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+                }
+            }
+        });
+
+        assert_eq!(
+            start_due_observations(&db, &telescopes, now).await.unwrap(),
+            0
+        );
+        let start = first.observation.start_time;
+        assert_eq!(
+            start_due_observations(&db, &telescopes, start)
+                .await
+                .unwrap(),
+            1
+        );
+        let status = || async {
+            db.get_data().await.unwrap().scheduled_observations[0]
+                .status
+                .clone()
+        };
+        assert_eq!(status().await, ScheduledObservationStatus::Running);
+        while status().await == ScheduledObservationStatus::Running {
+            tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+        }
+        service.abort();
+        assert!(matches!(
+            status().await,
+            ScheduledObservationStatus::Completed { .. }
+        ));
+        assert_eq!(
+            telescope
+                .lock()
+                .await
+                .take_completed_measurements()
+                .await
+                .len(),
+            1
+        );
+        // Nothing else is due, the other observation was cancelled.
+        assert_eq!(
+            start_due_observations(&db, &telescopes, start)
+                .await
+                .unwrap(),
+            0
+        );
+    }
+}
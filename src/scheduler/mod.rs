@@ -0,0 +1,442 @@
+//! Queues of observations executed one after another on a telescope.
+//!
+//! Before a queue is executed the targets are ordered to keep the total
+//! slewing time down. From the current direction of the telescope the
+//! nearest target that stays above the horizon for the whole integration
+//! is picked next, which is simple and good enough for the handful of
+//! targets in a typical queue. The resulting order and the estimated
+//! timeline are reported before the telescope starts moving.
+
+use crate::angles::Radians;
+use crate::coords::{Direction, Location};
+use crate::database::{DataBase, DataBaseError, Storage};
+use crate::telescope::{Telescope, TelescopeCollection};
+use crate::telescopes::{InvalidTarget, ReceiverConfiguration, TelescopeError, TelescopeTarget};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+
+pub mod api_routes;
+pub mod jobs;
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct QueuedObservation {
+    pub target: TelescopeTarget,
+    pub integration_seconds: u64,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct PlannedObservation {
+    #[serde(flatten)]
+    pub observation: QueuedObservation,
+    pub slew_start: DateTime<Utc>,
+    pub integration_start: DateTime<Utc>,
+    pub integration_end: DateTime<Utc>,
+}
+
+impl PlannedObservation {
+    pub fn slew_time(&self) -> Duration {
+        self.integration_start - self.slew_start
+    }
+
+    pub fn integration_time(&self) -> Duration {
+        self.integration_end - self.integration_start
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct ObservationPlan {
+    pub telescope_name: String,
+    pub observations: Vec<PlannedObservation>,
+    /// Observations left out since their targets are below the horizon
+    /// whenever they could be observed.
+    pub unobservable: Vec<QueuedObservation>,
+}
+
+impl ObservationPlan {
+    pub fn total_slew_time(&self) -> Duration {
+        self.observations
+            .iter()
+            .map(PlannedObservation::slew_time)
+            .fold(Duration::zero(), |total, slew| total + slew)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum SchedulerError {
+    UnknownTelescope,
+    EmptyQueue,
+    InvalidTarget(InvalidTarget),
+    Telescope(TelescopeError),
+    ServiceUnavailable,
+}
+
+impl From<DataBaseError> for SchedulerError {
+    fn from(_source: DataBaseError) -> Self {
+        Self::ServiceUnavailable
+    }
+}
+
+/// The queues currently executing, by telescope name.
+pub type ObservationQueues = Arc<Mutex<HashMap<String, RunningQueue>>>;
+
+pub struct RunningQueue {
+    pub plan: ObservationPlan,
+    handle: JoinHandle<()>,
+}
+
+/// Estimated time to slew between two directions, with both axes moving
+/// at the same time.
+fn slew_time(from: Direction, to: Direction, speed: Radians) -> Duration {
+    let azimuth = (to.azimuth - from.azimuth).abs();
+    let altitude = (to.altitude - from.altitude).abs();
+    let angle = if azimuth > altitude {
+        azimuth
+    } else {
+        altitude
+    };
+    Duration::milliseconds((angle.0 / speed.0 * 1000.0).ceil() as i64)
+}
+
+/// Order `queue` to keep the slewing time down and estimate when each
+/// observation starts and ends, starting from `direction` at `start`.
+pub fn plan_observations(
+    telescope_name: &str,
+    location: Location,
+    min_altitude: Radians,
+    speed: Radians,
+    direction: Direction,
+    start: DateTime<Utc>,
+    queue: Vec<QueuedObservation>,
+) -> ObservationPlan {
+    let visible = |target: TelescopeTarget, when| {
+        target
+            .horizontal(location, when)
+            .filter(|d| d.altitude >= min_altitude)
+    };
+
+    let mut remaining = queue;
+    let mut observations = Vec::new();
+    let mut direction = direction;
+    let mut now = start;
+    loop {
+        let next = remaining
+            .iter()
+            .enumerate()
+            .filter_map(|(index, observation)| {
+                let target = visible(observation.target, now)?;
+                let integration_start = now + slew_time(direction, target, speed);
+                let integration_end =
+                    integration_start + Duration::seconds(observation.integration_seconds as i64);
+                visible(observation.target, integration_start)?;
+                let end_direction = visible(observation.target, integration_end)?;
+                Some((index, integration_start, integration_end, end_direction))
+            })
+            .min_by_key(|(_, integration_start, _, _)| *integration_start);
+        let Some((index, integration_start, integration_end, end_direction)) = next else {
+            break;
+        };
+        observations.push(PlannedObservation {
+            observation: remaining.remove(index),
+            slew_start: now,
+            integration_start,
+            integration_end,
+        });
+        direction = end_direction;
+        now = integration_end;
+    }
+
+    ObservationPlan {
+        telescope_name: telescope_name.to_string(),
+        observations,
+        unobservable: remaining,
+    }
+}
+
+/// Plan `queue` for `telescope_name` from where the telescope points now.
+pub async fn plan_queue(
+    db: &DataBase<impl Storage>,
+    telescopes: &TelescopeCollection,
+    telescope_name: &str,
+    queue: Vec<QueuedObservation>,
+    now: DateTime<Utc>,
+) -> Result<ObservationPlan, SchedulerError> {
+    if queue.is_empty() {
+        return Err(SchedulerError::EmptyQueue);
+    }
+    let queue = queue
+        .into_iter()
+        .map(|observation| {
+            Ok(QueuedObservation {
+                target: observation
+                    .target
+                    .validated()
+                    .map_err(SchedulerError::InvalidTarget)?,
+                ..observation
+            })
+        })
+        .collect::<Result<Vec<_>, SchedulerError>>()?;
+    let definition = db
+        .get_data()
+        .await?
+        .telescopes
+        .into_iter()
+        .find(|t| t.name == telescope_name)
+        .ok_or(SchedulerError::UnknownTelescope)?;
+    let telescope = telescopes
+        .read()
+        .await
+        .get(telescope_name)
+        .ok_or(SchedulerError::UnknownTelescope)?
+        .telescope
+        .clone();
+    let (direction, capabilities) = {
+        let telescope = telescope.lock().await;
+        let direction = telescope
+            .get_direction()
+            .await
+            .map_err(SchedulerError::Telescope)?;
+        (direction, telescope.capabilities())
+    };
+    let min_altitude = if definition.min_altitude > capabilities.min_altitude {
+        definition.min_altitude
+    } else {
+        capabilities.min_altitude
+    };
+    Ok(plan_observations(
+        telescope_name,
+        definition.location,
+        min_altitude,
+        capabilities.slewing_speed,
+        direction,
+        now,
+        queue,
+    ))
+}
+
+pub(crate) fn receiver_configuration(integrate: bool) -> ReceiverConfiguration {
+    ReceiverConfiguration {
+        integrate,
+        ..ReceiverConfiguration::stopped()
+    }
+}
+
+/// Point at each target in turn and integrate for the planned time. The
+/// telescope is given the estimated slewing time to reach the target
+/// before the integration starts.
+pub async fn execute_plan(telescope: Arc<Mutex<dyn Telescope>>, plan: ObservationPlan) {
+    for planned in &plan.observations {
+        let target = planned.observation.target;
+        if let Err(error) = telescope.lock().await.set_target(target).await {
+            log::error!(
+                "Aborting observation queue on {}, failed to point at {:?}: {}",
+                plan.telescope_name,
+                target,
+                error
+            );
+            return;
+        }
+        tokio::time::sleep(planned.slew_time().to_std().unwrap_or_default()).await;
+
+        if let Err(error) = telescope
+            .lock()
+            .await
+            .set_receiver_configuration(receiver_configuration(true))
+            .await
+        {
+            log::error!(
+                "Aborting observation queue on {}, failed to start integration: {:?}",
+                plan.telescope_name,
+                error
+            );
+            return;
+        }
+        tokio::time::sleep(planned.integration_time().to_std().unwrap_or_default()).await;
+        if let Err(error) = telescope
+            .lock()
+            .await
+            .set_receiver_configuration(receiver_configuration(false))
+            .await
+        {
+            log::error!(
+                "Failed to stop integration on {}: {:?}",
+                plan.telescope_name,
+                error
+            );
+        }
+    }
+    log::info!("Observation queue on {} finished", plan.telescope_name);
+}
+
+/// Start executing `plan`, replacing any queue already running on the
+/// telescope.
+pub async fn start_queue(
+    queues: &ObservationQueues,
+    telescopes: &TelescopeCollection,
+    plan: ObservationPlan,
+) -> Result<(), SchedulerError> {
+    let telescope = telescopes
+        .read()
+        .await
+        .get(&plan.telescope_name)
+        .ok_or(SchedulerError::UnknownTelescope)?
+        .telescope
+        .clone();
+    let handle = tokio::spawn(execute_plan(telescope, plan.clone()));
+    if let Some(previous) = queues
+        .lock()
+        .await
+        .insert(plan.telescope_name.clone(), RunningQueue { plan, handle })
+    {
+        previous.handle.abort();
+    }
+    Ok(())
+}
+
+/// The plan of the queue running on `telescope_name`, if any.
+pub async fn running_queue(
+    queues: &ObservationQueues,
+    telescope_name: &str,
+) -> Option<ObservationPlan> {
+    let mut queues = queues.lock().await;
+    queues.retain(|_, queue| !queue.handle.is_finished());
+    queues.get(telescope_name).map(|queue| queue.plan.clone())
+}
+
+/// Stop the queue running on `telescope_name`. The telescope is left where
+/// it is, but an integration in progress is stopped.
+pub async fn cancel_queue(
+    queues: &ObservationQueues,
+    telescopes: &TelescopeCollection,
+    telescope_name: &str,
+) -> Result<(), SchedulerError> {
+    let Some(queue) = queues.lock().await.remove(telescope_name) else {
+        return Ok(());
+    };
+    queue.handle.abort();
+    if let Some(container) = telescopes.read().await.get(telescope_name) {
+        container
+            .telescope
+            .lock()
+            .await
+            .set_receiver_configuration(receiver_configuration(false))
+            .await
+            .map_err(|error| {
+                log::error!(
+                    "Failed to stop integration on {}: {:?}",
+                    telescope_name,
+                    error
+                );
+                SchedulerError::ServiceUnavailable
+            })?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::angles::Degrees;
+    use crate::fake_telescope::{FAKE_TELESCOPE_SLEWING_SPEED, LOWEST_ALLOWED_ALTITUDE};
+    use crate::interlock::CollisionInterlock;
+    use crate::supervisor::TelescopeSupervisor;
+    use chrono::TimeZone;
+
+    fn observation(ra: f64, dec: f64, integration_seconds: u64) -> QueuedObservation {
+        QueuedObservation {
+            target: TelescopeTarget::Equatorial {
+                ra: Degrees(ra).to_radians(),
+                dec: Degrees(dec).to_radians(),
+            },
+            integration_seconds,
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_observation_queue() {
+        let location = Location {
+            longitude: Degrees(11.9).to_radians(),
+            latitude: Degrees(57.4).to_radians(),
+        };
+        // Circumpolar targets, which the fake telescope accepts whenever the
+        // test runs.
+        let queue = vec![
+            observation(0.0, 80.0, 10),
+            observation(180.0, 70.0, 10),
+            observation(10.0, 75.0, 10),
+            // Never rises.
+            observation(0.0, -60.0, 10),
+        ];
+        let start = Utc.with_ymd_and_hms(2024, 3, 1, 22, 0, 0).unwrap();
+        let direction = TelescopeTarget::Equatorial {
+            ra: Radians(0.0),
+            dec: Degrees(85.0).to_radians(),
+        }
+        .horizontal(location, start)
+        .unwrap();
+        let plan = plan_observations(
+            "fake",
+            location,
+            LOWEST_ALLOWED_ALTITUDE,
+            FAKE_TELESCOPE_SLEWING_SPEED,
+            direction,
+            start,
+            queue.clone(),
+        );
+
+        // The two targets close to the start come first.
+        let order: Vec<QueuedObservation> = plan
+            .observations
+            .iter()
+            .map(|p| p.observation.clone())
+            .collect();
+        assert_eq!(
+            order,
+            vec![queue[0].clone(), queue[2].clone(), queue[1].clone()]
+        );
+        assert_eq!(plan.unobservable, vec![queue[3].clone()]);
+        assert_eq!(plan.observations[0].slew_start, start);
+        for pair in plan.observations.windows(2) {
+            assert_eq!(pair[0].integration_end, pair[1].slew_start);
+        }
+        assert!(plan
+            .observations
+            .iter()
+            .all(|p| p.integration_time() == Duration::seconds(10)));
+        assert!(plan.total_slew_time() > Duration::zero());
+
+        let supervisor = TelescopeSupervisor::new("fake");
+        let telescope = Arc::new(Mutex::new(crate::fake_telescope::create(
+            "fake".to_string(),
+            None,
+            vec![],
+            supervisor,
+            CollisionInterlock::default(),
+        )));
+        // Stand in for the telescope service, on the paused clock.
+        let service = tokio::spawn({
+            let telescope = telescope.clone();
+            async move {
+                loop {
+                    let _ = telescope
+                        .lock()
+                        .await
+                        .update(std::time::Duration::from_secs(1))
+                        .await;
+                    tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+                }
+            }
+        });
+        execute_plan(telescope.clone(), plan.clone()).await;
+        service.abort();
+        let mut telescope = telescope.lock().await;
+        assert_eq!(
+            telescope.get_target().await,
+            Ok(plan.observations[2].observation.target)
+        );
+        assert_eq!(telescope.take_completed_measurements().await.len(), 3);
+    }
+}
@@ -0,0 +1,67 @@
+use crate::database::{DataBase, Storage};
+use crate::search::{search, SearchResult};
+use axum::{
+    extract::{Json, Query, State},
+    routing::get,
+    Router,
+};
+use serde::Deserialize;
+
+pub fn routes(database: DataBase<impl Storage + 'static>) -> Router {
+    Router::new()
+        .route("/", get(get_search))
+        .with_state(database)
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SearchQuery {
+    #[serde(default)]
+    pub q: String,
+}
+
+async fn get_search(
+    State(db): State<DataBase<impl Storage>>,
+    Query(query): Query<SearchQuery>,
+) -> Json<Vec<SearchResult>> {
+    let data_model = db
+        .get_data()
+        .await
+        .expect("As long as no one is manually editing the database, this should never fail.");
+    Json(search(&data_model, &query.q))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::database::create_in_memory_database;
+    use crate::search::SearchResultKind;
+    use crate::users::ensure_user;
+    use axum::{
+        body::Body,
+        http::{self, Request, StatusCode},
+    };
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_get_search() {
+        let db = create_in_memory_database();
+        ensure_user(&db, "anna").await.unwrap();
+
+        let response = routes(db)
+            .oneshot(
+                Request::builder()
+                    .method(http::Method::GET)
+                    .uri("/?q=ann")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let results: Vec<SearchResult> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].kind, SearchResultKind::User);
+        assert_eq!(results[0].link, "/users/anna/training");
+    }
+}
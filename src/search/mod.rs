@@ -0,0 +1,169 @@
+//! Search across users, sessions, archived measurements and the shift log.
+//!
+//! Everything is kept in memory anyway, so entries are matched by scanning
+//! a text describing each of them. An entry matches if every word of the
+//! query is found in its text, ignoring case.
+
+use crate::archive::ArchivedMeasurement;
+use crate::database::DataModel;
+use serde::{Deserialize, Serialize};
+
+pub mod api_routes;
+pub mod routes;
+
+/// Most results returned for a query.
+pub const SEARCH_RESULT_LIMIT: usize = 100;
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+pub enum SearchResultKind {
+    User,
+    Session,
+    Measurement,
+    Note,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct SearchResult {
+    pub kind: SearchResultKind,
+    pub title: String,
+    pub detail: String,
+    pub link: String,
+}
+
+fn measurement_result(measurement: &ArchivedMeasurement) -> SearchResult {
+    SearchResult {
+        kind: SearchResultKind::Measurement,
+        title: format!("Measurement {}", measurement.id),
+        detail: format!(
+            "{}, {}, {}",
+            measurement.telescope_name,
+            measurement.measurement.target,
+            measurement.measurement.start.format("%Y-%m-%d %H:%M")
+        ),
+        link: format!("/api/archive/{}/plot?format=svg", measurement.id),
+    }
+}
+
+/// Find the entries matching `query`, users first and the most recent
+/// sessions, measurements and notes before older ones.
+pub fn search(data_model: &DataModel, query: &str) -> Vec<SearchResult> {
+    let words: Vec<String> = query.split_whitespace().map(str::to_lowercase).collect();
+    if words.is_empty() {
+        return vec![];
+    }
+    let matches = |result: &SearchResult| {
+        let text = format!("{} {}", result.title, result.detail).to_lowercase();
+        words.iter().all(|word| text.contains(word.as_str()))
+    };
+
+    let users = data_model.users.iter().map(|user| {
+        let details: Vec<&str> = [&user.email, &user.external_id, &user.group]
+            .into_iter()
+            .flatten()
+            .map(String::as_str)
+            .collect();
+        SearchResult {
+            kind: SearchResultKind::User,
+            title: user.name.clone(),
+            detail: details.join(", "),
+            link: format!("/users/{}/training", user.name),
+        }
+    });
+    let sessions = data_model
+        .sessions
+        .iter()
+        .rev()
+        .map(|session| SearchResult {
+            kind: SearchResultKind::Session,
+            title: format!("Session {}", session.id),
+            detail: format!(
+                "{} on {}, {}",
+                session.user_name,
+                session.telescope_name,
+                session.start_time.format("%Y-%m-%d %H:%M")
+            ),
+            link: format!("/sessions/{}/replay/0", session.id),
+        });
+    let measurements = data_model.measurements.iter().rev().map(measurement_result);
+    let notes = data_model.shift_log.iter().rev().map(|note| SearchResult {
+        kind: SearchResultKind::Note,
+        title: format!("Note on {}", note.note.telescope_name),
+        detail: format!(
+            "{}, {}: {}",
+            note.note.author,
+            note.time.format("%Y-%m-%d %H:%M"),
+            note.note.text
+        ),
+        link: format!("/shift_log/{}", note.note.telescope_name),
+    });
+
+    users
+        .chain(sessions)
+        .chain(measurements)
+        .chain(notes)
+        .filter(matches)
+        .take(SEARCH_RESULT_LIMIT)
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::angles::Degrees;
+    use crate::archive::test_utils::galactic_measurement;
+    use crate::sessions::RecordedSession;
+    use crate::shift_log::{NewShiftLogNote, ShiftLogNote};
+    use crate::users::User;
+    use chrono::Utc;
+
+    #[test]
+    fn test_search() {
+        let mut anna = User::new("anna");
+        anna.group = Some("Class 3A".to_string());
+        let data_model = DataModel {
+            users: vec![anna, User::new("bertil")],
+            sessions: vec![RecordedSession {
+                id: 1,
+                telescope_name: "brage".to_string(),
+                user_name: "anna".to_string(),
+                start_time: Utc::now(),
+                end_time: Utc::now(),
+                events: vec![],
+            }],
+            measurements: [30.0, 120.0]
+                .into_iter()
+                .enumerate()
+                .map(|(index, l)| {
+                    ArchivedMeasurement::new(
+                        index as u64 + 1,
+                        "vale",
+                        galactic_measurement(Degrees(l), Utc::now()),
+                    )
+                })
+                .collect(),
+            shift_log: vec![ShiftLogNote {
+                id: 1,
+                time: Utc::now(),
+                note: NewShiftLogNote {
+                    telescope_name: "vale".to_string(),
+                    author: "operator".to_string(),
+                    text: "Re-greased the azimuth drive".to_string(),
+                },
+            }],
+            ..Default::default()
+        };
+
+        let titles = |query: &str| -> Vec<String> {
+            search(&data_model, query)
+                .into_iter()
+                .map(|result| result.title)
+                .collect()
+        };
+        assert_eq!(titles("ANNA"), vec!["anna", "Session 1"]);
+        assert_eq!(titles("class 3a"), vec!["anna"]);
+        assert_eq!(titles("vale l=120"), vec!["Measurement 2"]);
+        assert_eq!(titles("galactic"), vec!["Measurement 2", "Measurement 1"]);
+        assert_eq!(titles("azimuth"), vec!["Note on vale"]);
+        assert!(titles("  ").is_empty());
+    }
+}
@@ -0,0 +1,70 @@
+use crate::database::{DataBase, Storage};
+use crate::search::api_routes::SearchQuery;
+use crate::search::{search, SearchResult, SearchResultKind, SEARCH_RESULT_LIMIT};
+use crate::template::HtmlTemplate;
+use askama::Template;
+use axum::{
+    extract::{Query, State},
+    response::IntoResponse,
+    routing::get,
+    Router,
+};
+
+pub fn routes(database: DataBase<impl Storage + 'static>) -> Router {
+    Router::new()
+        .route("/", get(get_search))
+        .with_state(database)
+}
+
+struct SearchEntry {
+    kind: &'static str,
+    title: String,
+    detail: String,
+    link: String,
+    /// Links to pages are loaded into the page, other links are followed.
+    page: bool,
+}
+
+#[derive(Template)]
+#[template(path = "search.html")]
+struct SearchTemplate {
+    query: String,
+    entries: Vec<SearchEntry>,
+    limit: usize,
+}
+
+async fn get_search<StorageType>(
+    State(db): State<DataBase<StorageType>>,
+    Query(query): Query<SearchQuery>,
+) -> impl IntoResponse
+where
+    StorageType: Storage,
+{
+    let data_model = db
+        .get_data()
+        .await
+        .expect("As long as no one is manually editing the database, this should never fail.");
+    let entries = search(&data_model, &query.q)
+        .into_iter()
+        .map(|result: SearchResult| {
+            let (kind, page) = match result.kind {
+                SearchResultKind::User => ("User", true),
+                SearchResultKind::Session => ("Session", true),
+                SearchResultKind::Measurement => ("Measurement", false),
+                SearchResultKind::Note => ("Note", true),
+            };
+            SearchEntry {
+                kind,
+                title: result.title,
+                detail: result.detail,
+                link: result.link,
+                page,
+            }
+        })
+        .collect();
+    HtmlTemplate(SearchTemplate {
+        query: query.q,
+        entries,
+        limit: SEARCH_RESULT_LIMIT,
+    })
+}
@@ -0,0 +1,204 @@
+use crate::archive::files::load_all_spectra;
+use crate::constants::fetch_spectral_lines;
+use crate::database::{DataBase, Storage};
+use crate::plot::{render_spectrum_svg, PlotError, PlotOptions};
+use crate::sessions::report::{render_session_report, ReportError};
+use crate::sessions::{RecordedSession, SessionEventKind};
+use axum::{
+    extract::{Json, Path, Query, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+pub fn routes(database: DataBase<impl Storage + 'static>) -> Router {
+    Router::new()
+        .route("/", get(get_sessions))
+        .route("/:id", get(get_session))
+        .route("/:id/events/:index/plot", get(get_event_plot))
+        .route("/:id/report", get(get_report))
+        .with_state(database)
+}
+
+#[derive(Debug)]
+pub struct SessionNotFound;
+
+impl IntoResponse for SessionNotFound {
+    fn into_response(self) -> Response {
+        (StatusCode::NOT_FOUND, "Session not found".to_string()).into_response()
+    }
+}
+
+/// Everything about a recorded session except the events.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct SessionSummary {
+    pub id: u64,
+    pub telescope_name: String,
+    pub user_name: String,
+    pub start_time: DateTime<Utc>,
+    pub end_time: DateTime<Utc>,
+    pub number_of_events: usize,
+}
+
+impl From<&RecordedSession> for SessionSummary {
+    fn from(session: &RecordedSession) -> Self {
+        SessionSummary {
+            id: session.id,
+            telescope_name: session.telescope_name.clone(),
+            user_name: session.user_name.clone(),
+            start_time: session.start_time,
+            end_time: session.end_time,
+            number_of_events: session.events.len(),
+        }
+    }
+}
+
+pub async fn fetch_sessions(db: &DataBase<impl Storage>) -> Vec<RecordedSession> {
+    db.get_data()
+        .await
+        .expect("As long as no one is manually editing the database, this should never fail.")
+        .sessions
+}
+
+pub async fn fetch_session(
+    db: &DataBase<impl Storage>,
+    id: u64,
+) -> Result<RecordedSession, SessionNotFound> {
+    fetch_sessions(db)
+        .await
+        .into_iter()
+        .find(|s| s.id == id)
+        .ok_or(SessionNotFound)
+}
+
+async fn get_sessions(State(db): State<DataBase<impl Storage>>) -> Json<Vec<SessionSummary>> {
+    Json(fetch_sessions(&db).await.iter().map(Into::into).collect())
+}
+
+async fn get_session(
+    State(db): State<DataBase<impl Storage>>,
+    Path(id): Path<u64>,
+) -> Result<Json<RecordedSession>, SessionNotFound> {
+    Ok(Json(fetch_session(&db, id).await?))
+}
+
+async fn get_event_plot(
+    State(db): State<DataBase<impl Storage>>,
+    Path((id, index)): Path<(u64, usize)>,
+    Query(mut options): Query<PlotOptions>,
+) -> Result<Response, SessionNotFound> {
+    let state = fetch_session(&db, id).await?.state_at(index);
+    options.spectral_lines = fetch_spectral_lines(&db).await;
+    let response = match state.spectrum {
+        Some(spectrum) => match render_spectrum_svg(&spectrum, &options) {
+            Ok(svg) => ([(header::CONTENT_TYPE, "image/svg+xml")], svg).into_response(),
+            Err(error) => error.into_response(),
+        },
+        None => PlotError::EmptySpectrum.into_response(),
+    };
+    Ok(response)
+}
+
+impl IntoResponse for ReportError {
+    fn into_response(self) -> Response {
+        match self {
+            ReportError::Plot(error) => error.into_response(),
+            ReportError::Pdf(_) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, self.to_string()).into_response()
+            }
+        }
+    }
+}
+
+async fn get_report(
+    State(db): State<DataBase<impl Storage>>,
+    Path(id): Path<u64>,
+) -> Result<Response, SessionNotFound> {
+    let session = fetch_session(&db, id).await?;
+    let saved: Vec<u64> = session
+        .events
+        .iter()
+        .filter_map(|event| match event.kind {
+            SessionEventKind::MeasurementSaved(id) => Some(id),
+            _ => None,
+        })
+        .collect();
+    let measurements = db
+        .get_data()
+        .await
+        .expect("As long as no one is manually editing the database, this should never fail.")
+        .measurements
+        .into_iter()
+        .filter(|m| saved.contains(&m.id))
+        .collect();
+    let measurements = load_all_spectra(&db, measurements)
+        .await
+        .expect("As long as no one is manually editing the database, this should never fail.");
+    let spectral_lines = fetch_spectral_lines(&db).await;
+    let response = match render_session_report(&session, &measurements, &spectral_lines) {
+        Ok(pdf) => (
+            [
+                (header::CONTENT_TYPE, "application/pdf".to_string()),
+                (
+                    header::CONTENT_DISPOSITION,
+                    format!("attachment; filename=\"salsa-session-{}.pdf\"", id),
+                ),
+            ],
+            pdf,
+        )
+            .into_response(),
+        Err(error) => error.into_response(),
+    };
+    Ok(response)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bookings::Booking;
+    use crate::database::create_in_memory_database;
+    use crate::sessions::{record_session_events, SessionEvent, SessionEventKind};
+    use crate::test_utils::get;
+
+    #[tokio::test]
+    async fn test_get_sessions() {
+        let db = create_in_memory_database();
+        let now = Utc::now();
+        let booking = Booking {
+            start_time: now,
+            end_time: now + chrono::Duration::hours(1),
+            telescope_name: "fake".to_string(),
+            user_name: "student".to_string(),
+        };
+        let event = SessionEvent {
+            time: now,
+            kind: SessionEventKind::IntegrationStarted,
+        };
+        record_session_events(&db, &booking, vec![event])
+            .await
+            .unwrap();
+
+        let response = get(routes(db.clone()), "/").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let sessions: Vec<SessionSummary> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].user_name, "student");
+        assert_eq!(sessions[0].number_of_events, 1);
+
+        // No spectrum has been recorded in the session
+        let response = get(routes(db.clone()), "/1/events/0/plot").await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let response = get(routes(db.clone()), "/1/report").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers().get(header::CONTENT_TYPE).unwrap(),
+            "application/pdf"
+        );
+        let response = get(routes(db), "/2").await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,335 @@
+//! Ending observing sessions when their booking ends.
+//!
+//! A background service remembers the booking going on at each telescope.
+//! When it ends, the integration is stopped and the telescope is parked, so
+//! it does not keep tracking for a user who has left. Only admins and the
+//! user whose booking is going on may command a telescope, and a user whose
+//! booking ended is told so until they book it again.
+
+use crate::authentication::Requester;
+use crate::bookings::{current_booking, Booking};
+use crate::database::{DataBase, DataBaseError, DataModel, Storage};
+use crate::observation_log::{record_command, ObservationCommand};
+use crate::telescope::TelescopeCollection;
+use crate::telescopes::{ReceiverConfiguration, TelescopeTarget};
+use crate::users::is_admin;
+use axum::{
+    extract::{Path, State},
+    http::{Method, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Duration, Utc};
+use std::collections::HashMap;
+
+pub const SESSION_EXPIRY_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10);
+/// Commands are refused for this long after the booking of the user ended.
+pub const EXPIRED_SESSION_HOURS: i64 = 12;
+
+#[derive(Debug, PartialEq)]
+pub enum SessionExpiryError {
+    BookingEnded {
+        telescope_name: String,
+        end_time: DateTime<Utc>,
+    },
+    /// The requester has no booking of the telescope going on.
+    NotBooked { telescope_name: String },
+}
+
+impl IntoResponse for SessionExpiryError {
+    fn into_response(self) -> Response {
+        match self {
+            SessionExpiryError::BookingEnded {
+                telescope_name,
+                end_time,
+            } => (
+                StatusCode::FORBIDDEN,
+                format!(
+                    "Your booking of {} ended at {}, book it again to continue observing",
+                    telescope_name,
+                    end_time.format("%H:%M UTC")
+                ),
+            )
+                .into_response(),
+            SessionExpiryError::NotBooked { telescope_name } => (
+                StatusCode::FORBIDDEN,
+                format!("Book {} to observe with it", telescope_name),
+            )
+                .into_response(),
+        }
+    }
+}
+
+/// The booking going on at each telescope, as last seen by the service.
+#[derive(Debug, Default)]
+pub struct SessionManager {
+    sessions: HashMap<String, Booking>,
+}
+
+impl SessionManager {
+    /// Update the current bookings to `now` and return the bookings that
+    /// ended since the last update.
+    pub fn update<'a>(
+        &mut self,
+        telescope_names: impl IntoIterator<Item = &'a String>,
+        bookings: &[Booking],
+        now: DateTime<Utc>,
+    ) -> Vec<Booking> {
+        let mut ended = Vec::new();
+        for telescope_name in telescope_names {
+            let current = current_booking(bookings, telescope_name, now).cloned();
+            let previous = match current {
+                Some(current) => self
+                    .sessions
+                    .insert(telescope_name.clone(), current.clone()),
+                None => self.sessions.remove(telescope_name),
+            };
+            if let Some(previous) = previous {
+                if current_booking(bookings, telescope_name, now) != Some(&previous) {
+                    ended.push(previous);
+                }
+            }
+        }
+        ended
+    }
+}
+
+/// Stop the integration on the telescope of `booking` and park it.
+async fn end_session(
+    database: &DataBase<impl Storage>,
+    telescopes: &TelescopeCollection,
+    booking: &Booking,
+) {
+    let telescopes = telescopes.read().await;
+    let Some(container) = telescopes.get(&booking.telescope_name) else {
+        return;
+    };
+    let mut telescope = container.telescope.lock().await;
+    if telescope.measurement_in_progress().await.is_some() {
+        let stop = ReceiverConfiguration::stopped();
+        let result = telescope
+            .set_receiver_configuration(stop)
+            .await
+            .map_err(|error| format!("{:?}", error));
+        if let Err(error) = &result {
+            log::error!(
+                "Failed to stop integration on {}: {}",
+                booking.telescope_name,
+                error
+            );
+        }
+        record_command(
+            database,
+            &booking.telescope_name,
+            None,
+            ObservationCommand::StopReceiver,
+            &result,
+            Utc::now(),
+        )
+        .await;
+    }
+    let result = telescope.set_target(TelescopeTarget::Parked).await;
+    match &result {
+        Ok(_) => log::info!(
+            "Parking {} because the booking of {} ended",
+            booking.telescope_name,
+            booking.user_name
+        ),
+        Err(error) => log::error!("Failed to park {}: {}", booking.telescope_name, error),
+    }
+    record_command(
+        database,
+        &booking.telescope_name,
+        None,
+        ObservationCommand::SetTarget(TelescopeTarget::Parked),
+        &result,
+        Utc::now(),
+    )
+    .await;
+}
+
+/// End the sessions of the bookings that ended since the last call.
+pub async fn end_expired_sessions(
+    manager: &mut SessionManager,
+    database: &DataBase<impl Storage>,
+    telescopes: &TelescopeCollection,
+    now: DateTime<Utc>,
+) -> Result<(), DataBaseError> {
+    let bookings = database.get_data().await?.bookings;
+    let telescope_names: Vec<String> = telescopes.read().await.keys().cloned().collect();
+    for booking in manager.update(&telescope_names, &bookings, now) {
+        end_session(database, telescopes, &booking).await;
+    }
+    Ok(())
+}
+
+pub fn start_session_expiry_service<T>(
+    database: DataBase<T>,
+    telescopes: TelescopeCollection,
+) -> tokio::task::JoinHandle<()>
+where
+    T: Storage + 'static,
+{
+    tokio::spawn(async move {
+        let mut manager = SessionManager::default();
+        loop {
+            if let Err(error) =
+                end_expired_sessions(&mut manager, &database, &telescopes, Utc::now()).await
+            {
+                log::error!("Failed to end expired sessions: {}", error);
+            }
+            tokio::time::sleep(SESSION_EXPIRY_INTERVAL).await;
+        }
+    })
+}
+
+/// Refuse commands from `user_name` to `telescope_name` unless they have a
+/// booking of it going on or are an admin. Users whose booking ended
+/// recently are told so.
+pub fn check_session_active(
+    data_model: &DataModel,
+    telescope_name: &str,
+    user_name: &str,
+    now: DateTime<Utc>,
+) -> Result<(), SessionExpiryError> {
+    if is_admin(data_model, user_name) {
+        return Ok(());
+    }
+    let bookings = data_model
+        .bookings
+        .iter()
+        .filter(|b| b.telescope_name == telescope_name && b.user_name == user_name);
+    if bookings.clone().any(|b| b.is_active(now)) {
+        return Ok(());
+    }
+    let since = now - Duration::hours(EXPIRED_SESSION_HOURS);
+    match bookings
+        .filter(|b| since < b.end_time && b.end_time <= now)
+        .max_by_key(|b| b.end_time)
+    {
+        Some(booking) => Err(SessionExpiryError::BookingEnded {
+            telescope_name: telescope_name.to_string(),
+            end_time: booking.end_time,
+        }),
+        None => Err(SessionExpiryError::NotBooked {
+            telescope_name: telescope_name.to_string(),
+        }),
+    }
+}
+
+/// Middleware for the telescope routes, rejecting every request that changes
+/// the state of a telescope from a requester without a booking of it going
+/// on, including anonymous ones.
+pub async fn enforce_booking_end<StorageType, B>(
+    State(db): State<DataBase<StorageType>>,
+    Path(params): Path<HashMap<String, String>>,
+    requester: Requester,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response
+where
+    StorageType: Storage,
+{
+    if request.method() != Method::GET {
+        if let Some(telescope_name) = params.get("telescope_id") {
+            let data_model = db.get_data().await.expect(
+                "As long as no one is manually editing the database, this should never fail.",
+            );
+            if let Err(error) =
+                check_session_active(&data_model, telescope_name, requester.name(), Utc::now())
+            {
+                return error.into_response();
+            }
+        }
+    }
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn booking(user_name: &str, start_hour: u32, end_hour: u32) -> Booking {
+        Booking {
+            start_time: Utc.with_ymd_and_hms(2024, 3, 1, start_hour, 0, 0).unwrap(),
+            end_time: Utc.with_ymd_and_hms(2024, 3, 1, end_hour, 0, 0).unwrap(),
+            telescope_name: "fake".to_string(),
+            user_name: user_name.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_session_manager() {
+        let at = |hour, minute| Utc.with_ymd_and_hms(2024, 3, 1, hour, minute, 0).unwrap();
+        let bookings = vec![
+            booking("anna", 10, 11),
+            booking("bertil", 11, 12),
+            booking("anna", 13, 14),
+        ];
+        let telescopes = vec!["fake".to_string()];
+        let mut manager = SessionManager::default();
+        assert_eq!(manager.update(&telescopes, &bookings, at(9, 0)), vec![]);
+        assert_eq!(manager.update(&telescopes, &bookings, at(10, 30)), vec![]);
+        // Back to back bookings still end the first session.
+        assert_eq!(
+            manager.update(&telescopes, &bookings, at(11, 0)),
+            vec![bookings[0].clone()]
+        );
+        assert_eq!(manager.update(&telescopes, &bookings, at(11, 30)), vec![]);
+        assert_eq!(
+            manager.update(&telescopes, &bookings, at(12, 30)),
+            vec![bookings[1].clone()]
+        );
+        assert_eq!(manager.update(&telescopes, &bookings, at(12, 40)), vec![]);
+    }
+
+    #[test]
+    fn test_check_session_active() {
+        let at = |hour, minute| Utc.with_ymd_and_hms(2024, 3, 1, hour, minute, 0).unwrap();
+        let data_model = DataModel {
+            bookings: vec![booking("anna", 10, 11), booking("anna", 13, 14)],
+            users: vec![crate::users::User {
+                role: crate::users::Role::Admin,
+                ..crate::users::User::new("cecilia")
+            }],
+            ..Default::default()
+        };
+        let not_booked = |telescope_name: &str| {
+            Err(SessionExpiryError::NotBooked {
+                telescope_name: telescope_name.to_string(),
+            })
+        };
+        assert_eq!(
+            check_session_active(&data_model, "fake", "anna", at(10, 30)),
+            Ok(())
+        );
+        assert_eq!(
+            check_session_active(&data_model, "fake", "anna", at(12, 0)),
+            Err(SessionExpiryError::BookingEnded {
+                telescope_name: "fake".to_string(),
+                end_time: at(11, 0),
+            })
+        );
+        assert_eq!(
+            check_session_active(&data_model, "fake", "anna", at(13, 30)),
+            Ok(())
+        );
+        assert_eq!(
+            check_session_active(&data_model, "fake", "bertil", at(12, 0)),
+            not_booked("fake")
+        );
+        assert_eq!(
+            check_session_active(&data_model, "fake", "", at(10, 30)),
+            not_booked("fake")
+        );
+        assert_eq!(
+            check_session_active(&data_model, "other", "anna", at(12, 0)),
+            not_booked("other")
+        );
+        assert_eq!(
+            check_session_active(&data_model, "fake", "cecilia", at(12, 0)),
+            Ok(())
+        );
+    }
+}
@@ -0,0 +1,484 @@
+use crate::bookings::Booking;
+use crate::database::{DataBase, DataBaseError, DataModel, Storage};
+use crate::telescope::TelescopeCollection;
+use crate::telescopes::{ObservedSpectra, TelescopeInfo, TelescopeStatus, TelescopeTarget};
+use crate::users::tutorial::advance_tutorial;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+pub mod api_routes;
+pub mod expiry;
+pub mod report;
+pub mod routes;
+
+// Spectra are large, so only store a snapshot this often while integrating.
+pub const SESSION_SPECTRUM_INTERVAL_SECONDS: i64 = 30;
+// How often the recorder looks for new bookings.
+pub const SESSION_BOOKING_REFRESH_SECONDS: i64 = 60;
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub enum SessionEventKind {
+    TargetChanged(TelescopeTarget),
+    StatusChanged(TelescopeStatus),
+    IntegrationStarted,
+    IntegrationStopped,
+    Spectrum(ObservedSpectra),
+    /// The measurement with this archive id was saved.
+    MeasurementSaved(u64),
+    /// The backend was restarted during the session and pointed the
+    /// telescope at the target it had before.
+    Resumed(TelescopeTarget),
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct SessionEvent {
+    pub time: DateTime<Utc>,
+    pub kind: SessionEventKind,
+}
+
+/// Everything that happened on a telescope during a booking.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct RecordedSession {
+    pub id: u64,
+    pub telescope_name: String,
+    pub user_name: String,
+    pub start_time: DateTime<Utc>,
+    pub end_time: DateTime<Utc>,
+    pub events: Vec<SessionEvent>,
+}
+
+/// State of the telescope as seen by the user at some point of a session.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Default)]
+pub struct SessionState {
+    pub time: Option<DateTime<Utc>>,
+    pub target: Option<TelescopeTarget>,
+    pub status: Option<TelescopeStatus>,
+    pub integrating: bool,
+    pub spectrum: Option<ObservedSpectra>,
+    pub saved_measurement: Option<u64>,
+}
+
+impl RecordedSession {
+    /// Replay the events up to and including the event at `index`.
+    pub fn state_at(&self, index: usize) -> SessionState {
+        let mut state = SessionState::default();
+        for event in self.events.iter().take(index + 1) {
+            state.time = Some(event.time);
+            match &event.kind {
+                SessionEventKind::TargetChanged(target) | SessionEventKind::Resumed(target) => {
+                    state.target = Some(*target)
+                }
+                SessionEventKind::StatusChanged(status) => state.status = Some(*status),
+                SessionEventKind::IntegrationStarted => state.integrating = true,
+                SessionEventKind::IntegrationStopped => state.integrating = false,
+                SessionEventKind::Spectrum(spectrum) => state.spectrum = Some(spectrum.clone()),
+                SessionEventKind::MeasurementSaved(id) => state.saved_measurement = Some(*id),
+            }
+        }
+        state
+    }
+
+    /// The last target of the session, unless the telescope was stopped or
+    /// parked.
+    pub fn last_target(&self) -> Option<TelescopeTarget> {
+        let target = self
+            .events
+            .iter()
+            .rev()
+            .find_map(|event| match event.kind {
+                SessionEventKind::TargetChanged(target) | SessionEventKind::Resumed(target) => {
+                    Some(target)
+                }
+                _ => None,
+            })?;
+        match target {
+            TelescopeTarget::Parked | TelescopeTarget::Stopped => None,
+            target => Some(target),
+        }
+    }
+}
+
+/// Turns the stream of telescope infos into session events, keeping only
+/// changes.
+#[derive(Default)]
+pub struct SessionRecorder {
+    last_target: Option<TelescopeTarget>,
+    last_status: Option<TelescopeStatus>,
+    last_integrating: bool,
+    last_spectrum: Option<DateTime<Utc>>,
+    bookings: Vec<Booking>,
+    bookings_fetched: Option<DateTime<Utc>>,
+}
+
+impl SessionRecorder {
+    /// Forget what has been seen, so that the next session starts with the
+    /// full telescope state.
+    pub fn reset(&mut self) {
+        self.last_target = None;
+        self.last_status = None;
+        self.last_integrating = false;
+        self.last_spectrum = None;
+    }
+
+    pub fn changes(&mut self, info: &TelescopeInfo, now: DateTime<Utc>) -> Vec<SessionEvent> {
+        let mut events = Vec::new();
+        let mut push = |kind| events.push(SessionEvent { time: now, kind });
+        if self.last_target != Some(info.current_target) {
+            self.last_target = Some(info.current_target);
+            push(SessionEventKind::TargetChanged(info.current_target));
+        }
+        if self.last_status != Some(info.status) {
+            self.last_status = Some(info.status);
+            push(SessionEventKind::StatusChanged(info.status));
+        }
+        if info.measurement_in_progress != self.last_integrating {
+            self.last_integrating = info.measurement_in_progress;
+            if info.measurement_in_progress {
+                self.last_spectrum = None;
+                push(SessionEventKind::IntegrationStarted);
+            } else {
+                // Keep the final spectrum of the integration.
+                if let Some(spectrum) = &info.latest_observation {
+                    push(SessionEventKind::Spectrum(spectrum.clone()));
+                }
+                push(SessionEventKind::IntegrationStopped);
+            }
+        }
+        if info.measurement_in_progress {
+            let due = self.last_spectrum.is_none_or(|last| {
+                now - last >= Duration::seconds(SESSION_SPECTRUM_INTERVAL_SECONDS)
+            });
+            if let (true, Some(spectrum)) = (due, &info.latest_observation) {
+                self.last_spectrum = Some(now);
+                push(SessionEventKind::Spectrum(spectrum.clone()));
+            }
+        }
+        events
+    }
+
+    fn current_booking(&self, telescope_name: &str, now: DateTime<Utc>) -> Option<Booking> {
+        self.bookings
+            .iter()
+            .find(|b| b.telescope_name == telescope_name && b.start_time <= now && now < b.end_time)
+            .cloned()
+    }
+
+    async fn refresh_bookings(
+        &mut self,
+        database: &DataBase<impl Storage>,
+        now: DateTime<Utc>,
+    ) -> Result<(), DataBaseError> {
+        let refresh = self.bookings_fetched.is_none_or(|fetched| {
+            now - fetched >= Duration::seconds(SESSION_BOOKING_REFRESH_SECONDS)
+        });
+        if refresh {
+            self.bookings = database.get_data().await?.bookings;
+            self.bookings_fetched = Some(now);
+        }
+        Ok(())
+    }
+
+    /// Record changes of the telescope if it is currently booked.
+    pub async fn record(
+        &mut self,
+        database: &DataBase<impl Storage>,
+        telescope_name: &str,
+        info: &TelescopeInfo,
+    ) -> Result<(), DataBaseError> {
+        let now = Utc::now();
+        self.refresh_bookings(database, now).await?;
+        let Some(booking) = self.current_booking(telescope_name, now) else {
+            self.reset();
+            return Ok(());
+        };
+        let events = self.changes(info, now);
+        if events.is_empty() {
+            return Ok(());
+        }
+        record_session_events(database, &booking, events).await
+    }
+
+    /// Record that a measurement was archived if the telescope is booked.
+    pub async fn record_measurement_saved(
+        &mut self,
+        database: &DataBase<impl Storage>,
+        telescope_name: &str,
+        id: u64,
+    ) -> Result<(), DataBaseError> {
+        let now = Utc::now();
+        self.refresh_bookings(database, now).await?;
+        let Some(booking) = self.current_booking(telescope_name, now) else {
+            return Ok(());
+        };
+        let event = SessionEvent {
+            time: now,
+            kind: SessionEventKind::MeasurementSaved(id),
+        };
+        record_session_events(database, &booking, vec![event]).await
+    }
+}
+
+/// Append events to the session of `booking`, creating it if needed.
+pub async fn record_session_events(
+    database: &DataBase<impl Storage>,
+    booking: &Booking,
+    events: Vec<SessionEvent>,
+) -> Result<(), DataBaseError> {
+    database
+        .update_data(|mut data_model| {
+            let existing = data_model.sessions.iter().position(|s| {
+                s.telescope_name == booking.telescope_name && s.start_time == booking.start_time
+            });
+            let index = match existing {
+                Some(index) => index,
+                None => {
+                    let id = data_model
+                        .sessions
+                        .iter()
+                        .map(|s| s.id + 1)
+                        .max()
+                        .unwrap_or(1);
+                    data_model.sessions.push(RecordedSession {
+                        id,
+                        telescope_name: booking.telescope_name.clone(),
+                        user_name: booking.user_name.clone(),
+                        start_time: booking.start_time,
+                        end_time: booking.end_time,
+                        events: vec![],
+                    });
+                    data_model.sessions.len() - 1
+                }
+            };
+            advance_tutorial(&mut data_model.users, &booking.user_name, &events);
+            data_model.sessions[index].events.extend(events);
+            data_model
+        })
+        .await
+}
+
+/// Bookings with a session in progress and the target to resume tracking,
+/// collected before the telescopes start up and park.
+pub fn interrupted_sessions(
+    data_model: &DataModel,
+    now: DateTime<Utc>,
+) -> Vec<(Booking, TelescopeTarget)> {
+    data_model
+        .bookings
+        .iter()
+        .filter(|b| b.start_time <= now && now < b.end_time)
+        .filter_map(|booking| {
+            let session = data_model.sessions.iter().find(|s| {
+                s.telescope_name == booking.telescope_name && s.start_time == booking.start_time
+            })?;
+            Some((booking.clone(), session.last_target()?))
+        })
+        .collect()
+}
+
+/// Point the telescopes back at the targets of interrupted sessions, and
+/// tell the users through their sessions.
+pub async fn resume_interrupted_sessions(
+    database: &DataBase<impl Storage>,
+    telescopes: &TelescopeCollection,
+    interrupted: Vec<(Booking, TelescopeTarget)>,
+) {
+    for (booking, target) in interrupted {
+        let telescopes = telescopes.read().await;
+        let Some(container) = telescopes.get(&booking.telescope_name) else {
+            continue;
+        };
+        let result = container.telescope.lock().await.set_target(target).await;
+        match result {
+            Ok(target) => {
+                log::info!(
+                    "Resumed session of {} on {}",
+                    booking.user_name,
+                    booking.telescope_name
+                );
+                let event = SessionEvent {
+                    time: Utc::now(),
+                    kind: SessionEventKind::Resumed(target),
+                };
+                if let Err(error) = record_session_events(database, &booking, vec![event]).await {
+                    log::error!("Failed to record resumed session: {}", error);
+                }
+            }
+            Err(error) => log::error!(
+                "Failed to resume session of {} on {}: {}",
+                booking.user_name,
+                booking.telescope_name,
+                error
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod test_utils {
+    use super::*;
+    use crate::angles::Radians;
+    use crate::coords::Direction;
+
+    pub fn telescope_info(target: TelescopeTarget, integrating: bool) -> TelescopeInfo {
+        TelescopeInfo {
+            id: "fake".to_string(),
+            status: TelescopeStatus::Tracking,
+            commanded_horizontal: None,
+            current_horizontal: Direction {
+                azimuth: Radians(0.0),
+                altitude: Radians(0.0),
+            },
+            current_target: target,
+            most_recent_error: None,
+            measurement_in_progress: integrating,
+            latest_observation: Some(ObservedSpectra {
+                frequencies: vec![1.42e9],
+                spectra: vec![1.0],
+                observation_time: std::time::Duration::from_secs(1),
+                cycles: 1,
+            }),
+            auxiliary_devices: vec![],
+            tasks: vec![],
+            controller_latency: None,
+            controller_commands: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::test_utils::telescope_info;
+    use super::*;
+    use crate::angles::Radians;
+    use crate::database::create_in_memory_database;
+
+    #[test]
+    fn test_recorder_only_records_changes() {
+        let mut recorder = SessionRecorder::default();
+        let now = Utc::now();
+        let target = TelescopeTarget::Galactic {
+            l: Radians(0.5),
+            b: Radians(0.0),
+        };
+
+        let events = recorder.changes(&telescope_info(target, false), now);
+        assert_eq!(events.len(), 2);
+        assert!(recorder
+            .changes(&telescope_info(target, false), now)
+            .is_empty());
+
+        let events = recorder.changes(&telescope_info(target, true), now);
+        let kinds: Vec<_> = events.into_iter().map(|e| e.kind).collect();
+        assert_eq!(kinds[0], SessionEventKind::IntegrationStarted);
+        assert!(matches!(kinds[1], SessionEventKind::Spectrum(_)));
+
+        // No new spectrum until the interval has passed
+        assert!(recorder
+            .changes(&telescope_info(target, true), now + Duration::seconds(1))
+            .is_empty());
+        assert_eq!(
+            recorder
+                .changes(
+                    &telescope_info(target, true),
+                    now + Duration::seconds(SESSION_SPECTRUM_INTERVAL_SECONDS)
+                )
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_state_at() {
+        let mut recorder = SessionRecorder::default();
+        let now = Utc::now();
+        let target = TelescopeTarget::Galactic {
+            l: Radians(0.5),
+            b: Radians(0.0),
+        };
+        let mut events = recorder.changes(&telescope_info(TelescopeTarget::Parked, false), now);
+        events.extend(recorder.changes(&telescope_info(target, true), now));
+        let session = RecordedSession {
+            id: 1,
+            telescope_name: "fake".to_string(),
+            user_name: "student".to_string(),
+            start_time: now,
+            end_time: now,
+            events,
+        };
+        assert_eq!(session.state_at(0).target, Some(TelescopeTarget::Parked));
+        assert!(!session.state_at(1).integrating);
+        let last = session.state_at(session.events.len() - 1);
+        assert_eq!(last.target, Some(target));
+        assert!(last.integrating);
+        assert!(last.spectrum.is_some());
+    }
+
+    #[test]
+    fn test_interrupted_sessions() {
+        let now = Utc::now();
+        let target = TelescopeTarget::Galactic {
+            l: Radians(0.5),
+            b: Radians(0.0),
+        };
+        let booking = Booking {
+            start_time: now - Duration::hours(1),
+            end_time: now + Duration::hours(1),
+            telescope_name: "fake".to_string(),
+            user_name: "student".to_string(),
+        };
+        let event = |kind| SessionEvent { time: now, kind };
+        let mut data_model = DataModel {
+            bookings: vec![booking.clone()],
+            sessions: vec![RecordedSession {
+                id: 1,
+                telescope_name: "fake".to_string(),
+                user_name: "student".to_string(),
+                start_time: booking.start_time,
+                end_time: booking.end_time,
+                events: vec![
+                    event(SessionEventKind::TargetChanged(target)),
+                    event(SessionEventKind::IntegrationStarted),
+                ],
+            }],
+            ..Default::default()
+        };
+        assert_eq!(
+            interrupted_sessions(&data_model, now),
+            vec![(booking, target)]
+        );
+        assert!(interrupted_sessions(&data_model, now + Duration::hours(2)).is_empty());
+
+        data_model.sessions[0]
+            .events
+            .push(event(SessionEventKind::TargetChanged(
+                TelescopeTarget::Parked,
+            )));
+        assert!(interrupted_sessions(&data_model, now).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_record_session_events_groups_by_booking() {
+        let db = create_in_memory_database();
+        let now = Utc::now();
+        let booking = Booking {
+            start_time: now,
+            end_time: now + Duration::hours(1),
+            telescope_name: "fake".to_string(),
+            user_name: "student".to_string(),
+        };
+        let event = SessionEvent {
+            time: now,
+            kind: SessionEventKind::IntegrationStarted,
+        };
+        record_session_events(&db, &booking, vec![event.clone()])
+            .await
+            .unwrap();
+        record_session_events(&db, &booking, vec![event])
+            .await
+            .unwrap();
+        let sessions = db.get_data().await.unwrap().sessions;
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].id, 1);
+        assert_eq!(sessions[0].events.len(), 2);
+    }
+}
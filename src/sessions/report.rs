@@ -0,0 +1,275 @@
+//! Observation log of a session as a PDF, meant to be attached to a lab
+//! report.
+//!
+//! The log lists the booking, every recorded event and, for each saved
+//! measurement, its settings, a plot of the spectrum and the column density
+//! estimated from it.
+
+use crate::archive::column_density::BaselineFit;
+use crate::archive::ArchivedMeasurement;
+use crate::constants::SpectralLine;
+use crate::format::Locale;
+use crate::plot::{render_spectrum_png, PlotError, PlotOptions};
+use crate::sessions::{RecordedSession, SessionEventKind};
+use printpdf::{
+    BuiltinFont, Image, ImageTransform, IndirectFontRef, Mm, PdfDocument, PdfDocumentReference,
+    PdfLayerReference,
+};
+use thiserror::Error;
+
+const PAGE_WIDTH: Mm = Mm(210.0);
+const PAGE_HEIGHT: Mm = Mm(297.0);
+const MARGIN: f32 = 20.0;
+const FONT_SIZE: f32 = 10.0;
+const HEADING_SIZE: f32 = 14.0;
+const LINE_HEIGHT: f32 = 5.0;
+/// Size in pixels of the spectrum plots, which are printed at
+/// `PLOT_DPI` to fill the width of the page inside the margins.
+const PLOT_SIZE: (u32, u32) = (1600, 1000);
+const PLOT_DPI: f32 = 240.0;
+
+#[derive(Debug, Error)]
+pub enum ReportError {
+    #[error(transparent)]
+    Plot(#[from] PlotError),
+    #[error("failed to write report: {0}")]
+    Pdf(String),
+}
+
+/// Writes lines from the top of the page down, starting a new page when
+/// the current one is full.
+struct ReportWriter {
+    document: PdfDocumentReference,
+    layer: PdfLayerReference,
+    font: IndirectFontRef,
+    bold: IndirectFontRef,
+    y: f32,
+    pages: usize,
+}
+
+impl ReportWriter {
+    fn new(title: &str) -> Result<Self, ReportError> {
+        let (document, page, layer) = PdfDocument::new(title, PAGE_WIDTH, PAGE_HEIGHT, "Log");
+        let font = document
+            .add_builtin_font(BuiltinFont::Helvetica)
+            .map_err(pdf_error)?;
+        let bold = document
+            .add_builtin_font(BuiltinFont::HelveticaBold)
+            .map_err(pdf_error)?;
+        let layer = document.get_page(page).get_layer(layer);
+        Ok(ReportWriter {
+            document,
+            layer,
+            font,
+            bold,
+            y: PAGE_HEIGHT.0 - MARGIN,
+            pages: 1,
+        })
+    }
+
+    /// Make room for `height` mm on the current page.
+    fn reserve(&mut self, height: f32) {
+        if self.y - height >= MARGIN {
+            return;
+        }
+        self.pages += 1;
+        let (page, layer) =
+            self.document
+                .add_page(PAGE_WIDTH, PAGE_HEIGHT, format!("Page {}", self.pages));
+        self.layer = self.document.get_page(page).get_layer(layer);
+        self.y = PAGE_HEIGHT.0 - MARGIN;
+    }
+
+    fn heading(&mut self, text: &str) {
+        self.reserve(3.0 * LINE_HEIGHT);
+        self.y -= LINE_HEIGHT;
+        self.layer
+            .use_text(text, HEADING_SIZE, Mm(MARGIN), Mm(self.y), &self.bold);
+        self.y -= 2.0 * LINE_HEIGHT;
+    }
+
+    fn line(&mut self, text: &str) {
+        self.reserve(LINE_HEIGHT);
+        self.layer
+            .use_text(text, FONT_SIZE, Mm(MARGIN), Mm(self.y), &self.font);
+        self.y -= LINE_HEIGHT;
+    }
+
+    fn png(&mut self, png: &[u8]) -> Result<(), ReportError> {
+        let image = image::load_from_memory(png).map_err(pdf_error)?;
+        let height = image.height() as f32 / PLOT_DPI * 25.4;
+        self.reserve(height + LINE_HEIGHT);
+        self.y -= height;
+        Image::from_dynamic_image(&image).add_to_layer(
+            self.layer.clone(),
+            ImageTransform {
+                translate_x: Some(Mm(MARGIN)),
+                translate_y: Some(Mm(self.y)),
+                dpi: Some(PLOT_DPI),
+                ..Default::default()
+            },
+        );
+        self.y -= LINE_HEIGHT;
+        Ok(())
+    }
+
+    fn finish(self) -> Result<Vec<u8>, ReportError> {
+        self.document.save_to_bytes().map_err(pdf_error)
+    }
+}
+
+fn pdf_error(error: impl std::fmt::Display) -> ReportError {
+    ReportError::Pdf(error.to_string())
+}
+
+fn describe_event(kind: &SessionEventKind) -> String {
+    match kind {
+        SessionEventKind::TargetChanged(target) => format!("Target changed to {}", target),
+        SessionEventKind::StatusChanged(status) => format!("Telescope {:?}", status),
+        SessionEventKind::IntegrationStarted => "Integration started".to_string(),
+        SessionEventKind::IntegrationStopped => "Integration stopped".to_string(),
+        SessionEventKind::Spectrum(spectrum) => format!(
+            "Spectrum after {} of integration",
+            Locale::MACHINE.quantity(spectrum.observation_time.as_secs_f64(), 0, "s")
+        ),
+        SessionEventKind::MeasurementSaved(id) => format!("Measurement {} saved", id),
+        SessionEventKind::Resumed(target) => {
+            format!("Backend restarted, resumed observing {}", target)
+        }
+    }
+}
+
+fn write_measurement(
+    writer: &mut ReportWriter,
+    measurement: &ArchivedMeasurement,
+    spectral_lines: &[SpectralLine],
+) -> Result<(), ReportError> {
+    let data = &measurement.measurement;
+    let number = Locale::MACHINE;
+    writer.heading(&format!("Measurement {}", measurement.id));
+    writer.line(&format!("Target: {}", data.target));
+    writer.line(&format!(
+        "Started: {} UTC",
+        data.start.format("%Y-%m-%d %H:%M:%S")
+    ));
+    writer.line(&format!(
+        "Integration: {} of {} requested, {} samples dropped",
+        number.quantity(data.integration_time.as_secs_f64(), 0, "s"),
+        number.quantity(data.requested_integration_time.as_secs_f64(), 0, "s"),
+        data.dropped_samples
+    ));
+    writer.line(&format!("Window function: {:?}", data.window));
+    if let Some(tsys) = data.system_temperature {
+        writer.line(&format!(
+            "System temperature: {}",
+            number.quantity(tsys, 1, "K")
+        ));
+    }
+    if data.interrupted {
+        writer.line("Recovered from a checkpoint after the backend stopped.");
+    }
+    if let Some(result) = &measurement.column_density {
+        let baseline = match result.baseline {
+            BaselineFit::Linear => "linear baseline",
+            BaselineFit::Median => "median baseline",
+        };
+        writer.line(&format!(
+            "HI column density: {} cm^-2 ({}, {}, Tsys {}{})",
+            number.scientific(result.column_density, 3),
+            number.quantity(result.integrated_intensity, 1, "K km/s"),
+            baseline,
+            number.quantity(result.system_temperature, 0, "K"),
+            if result.assumed_system_temperature {
+                " assumed"
+            } else {
+                ""
+            }
+        ));
+    }
+    let spectrum = measurement.spectrum();
+    if spectrum.frequencies.is_empty() {
+        return Ok(());
+    }
+    let options = PlotOptions {
+        width: Some(PLOT_SIZE.0),
+        height: Some(PLOT_SIZE.1),
+        spectral_lines: spectral_lines.to_vec(),
+        ..Default::default()
+    };
+    writer.png(&render_spectrum_png(&spectrum, &options)?)
+}
+
+/// Render the observation log of `session` with the saved measurements
+/// found in `measurements`. Measurements deleted from the archive are only
+/// mentioned in the event log.
+pub fn render_session_report(
+    session: &RecordedSession,
+    measurements: &[ArchivedMeasurement],
+    spectral_lines: &[SpectralLine],
+) -> Result<Vec<u8>, ReportError> {
+    let title = format!("SALSA session {}", session.id);
+    let mut writer = ReportWriter::new(&title)?;
+    writer.heading(&title);
+    writer.line(&format!("Telescope: {}", session.telescope_name));
+    writer.line(&format!("Observer: {}", session.user_name));
+    writer.line(&format!(
+        "Booked: {} - {} UTC",
+        session.start_time.format("%Y-%m-%d %H:%M"),
+        session.end_time.format("%Y-%m-%d %H:%M")
+    ));
+
+    writer.heading("Observation log");
+    for event in &session.events {
+        writer.line(&format!(
+            "{}  {}",
+            event.time.format("%H:%M:%S"),
+            describe_event(&event.kind)
+        ));
+    }
+
+    for event in &session.events {
+        let SessionEventKind::MeasurementSaved(id) = event.kind else {
+            continue;
+        };
+        if let Some(measurement) = measurements.iter().find(|m| m.id == id) {
+            write_measurement(&mut writer, measurement, spectral_lines)?;
+        }
+    }
+    writer.finish()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::angles::Degrees;
+    use crate::archive::test_utils::galactic_measurement;
+    use crate::sessions::SessionEvent;
+    use chrono::Utc;
+
+    #[test]
+    fn test_render_session_report() {
+        let now = Utc::now();
+        let measurement = galactic_measurement(Degrees(30.0), now);
+        let session = RecordedSession {
+            id: 1,
+            telescope_name: "fake".to_string(),
+            user_name: "anna".to_string(),
+            start_time: now,
+            end_time: now,
+            events: vec![
+                SessionEvent {
+                    time: now,
+                    kind: SessionEventKind::TargetChanged(measurement.target),
+                },
+                SessionEvent {
+                    time: now,
+                    kind: SessionEventKind::MeasurementSaved(1),
+                },
+            ],
+        };
+        let measurements = vec![ArchivedMeasurement::new(1, "fake", measurement)];
+
+        let pdf = render_session_report(&session, &measurements, &[]).unwrap();
+        assert!(pdf.starts_with(b"%PDF"));
+    }
+}
@@ -0,0 +1,121 @@
+use crate::database::{DataBase, Storage};
+use crate::sessions::api_routes::{fetch_session, fetch_sessions, SessionNotFound};
+use crate::shift_log::notes_between;
+use crate::template::HtmlTemplate;
+use askama::Template;
+use axum::{
+    extract::{Path, State},
+    response::IntoResponse,
+    routing::get,
+    Router,
+};
+
+pub fn routes(database: DataBase<impl Storage + 'static>) -> Router {
+    Router::new()
+        .route("/", get(get_sessions))
+        .route("/:id/replay/:index", get(get_replay_step))
+        .with_state(database)
+}
+
+struct SessionEntry {
+    id: u64,
+    telescope_name: String,
+    user_name: String,
+    start: String,
+    number_of_events: usize,
+}
+
+#[derive(Template)]
+#[template(path = "sessions.html")]
+struct SessionsTemplate {
+    sessions: Vec<SessionEntry>,
+}
+
+async fn get_sessions<StorageType>(State(db): State<DataBase<StorageType>>) -> impl IntoResponse
+where
+    StorageType: Storage,
+{
+    let sessions = fetch_sessions(&db)
+        .await
+        .iter()
+        .rev()
+        .map(|s| SessionEntry {
+            id: s.id,
+            telescope_name: s.telescope_name.clone(),
+            user_name: s.user_name.clone(),
+            start: s.start_time.format("%Y-%m-%d %H:%M").to_string(),
+            number_of_events: s.events.len(),
+        })
+        .collect();
+    HtmlTemplate(SessionsTemplate { sessions })
+}
+
+/// A shift log note posted during the session.
+struct ReplayNote {
+    time: String,
+    author: String,
+    html: String,
+}
+
+#[derive(Template)]
+#[template(path = "session_replay.html")]
+struct ReplayTemplate {
+    id: u64,
+    telescope_name: String,
+    user_name: String,
+    index: usize,
+    number_of_events: usize,
+    time: String,
+    target: String,
+    status: String,
+    integrating: bool,
+    has_spectrum: bool,
+    saved_measurement: Option<u64>,
+    notes: Vec<ReplayNote>,
+}
+
+async fn get_replay_step<StorageType>(
+    State(db): State<DataBase<StorageType>>,
+    Path((id, index)): Path<(u64, usize)>,
+) -> Result<impl IntoResponse, SessionNotFound>
+where
+    StorageType: Storage,
+{
+    let session = fetch_session(&db, id).await?;
+    let index = index.min(session.events.len().saturating_sub(1));
+    let state = session.state_at(index);
+    let data_model = db
+        .get_data()
+        .await
+        .expect("As long as no one is manually editing the database, this should never fail.");
+    let notes = notes_between(
+        &data_model,
+        &session.telescope_name,
+        session.start_time,
+        session.end_time,
+    )
+    .into_iter()
+    .map(|note| ReplayNote {
+        time: note.time.format("%H:%M").to_string(),
+        author: note.note.author.clone(),
+        html: note.html(),
+    })
+    .collect();
+    Ok(HtmlTemplate(ReplayTemplate {
+        id,
+        telescope_name: session.telescope_name.clone(),
+        user_name: session.user_name.clone(),
+        index,
+        number_of_events: session.events.len(),
+        time: state
+            .time
+            .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+            .unwrap_or_default(),
+        target: state.target.map(|t| format!("{:?}", t)).unwrap_or_default(),
+        status: state.status.map(|s| format!("{:?}", s)).unwrap_or_default(),
+        integrating: state.integrating,
+        has_spectrum: state.spectrum.is_some(),
+        saved_measurement: state.saved_measurement,
+        notes,
+    }))
+}
@@ -0,0 +1,73 @@
+use crate::database::{DataBase, Storage};
+use crate::shift_log::{add_note, telescope_notes, NewShiftLogNote, ShiftLogError, ShiftLogNote};
+use axum::{
+    extract::{Json, Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use chrono::Utc;
+
+pub fn routes(database: DataBase<impl Storage + 'static>) -> Router {
+    Router::new()
+        .route("/", get(get_notes).post(post_note))
+        .route("/:telescope_name", get(get_telescope_notes))
+        .with_state(database)
+}
+
+impl IntoResponse for ShiftLogError {
+    fn into_response(self) -> Response {
+        match self {
+            ShiftLogError::UnknownTelescope => {
+                (StatusCode::NOT_FOUND, "Telescope not found".to_string()).into_response()
+            }
+            ShiftLogError::NotCertified => (
+                StatusCode::FORBIDDEN,
+                "Only certified users may post to the shift log".to_string(),
+            )
+                .into_response(),
+            ShiftLogError::EmptyNote => {
+                (StatusCode::BAD_REQUEST, "The note is empty".to_string()).into_response()
+            }
+            ShiftLogError::ServiceUnavailable => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Failed to update the shift log".to_string(),
+            )
+                .into_response(),
+        }
+    }
+}
+
+async fn get_notes(State(db): State<DataBase<impl Storage>>) -> Json<Vec<ShiftLogNote>> {
+    Json(
+        db.get_data()
+            .await
+            .expect("As long as no one is manually editing the database, this should never fail.")
+            .shift_log,
+    )
+}
+
+async fn get_telescope_notes(
+    State(db): State<DataBase<impl Storage>>,
+    Path(telescope_name): Path<String>,
+) -> Json<Vec<ShiftLogNote>> {
+    let data_model = db
+        .get_data()
+        .await
+        .expect("As long as no one is manually editing the database, this should never fail.");
+    Json(
+        telescope_notes(&data_model, &telescope_name)
+            .into_iter()
+            .cloned()
+            .collect(),
+    )
+}
+
+async fn post_note(
+    State(db): State<DataBase<impl Storage>>,
+    Json(note): Json<NewShiftLogNote>,
+) -> Result<(StatusCode, Json<ShiftLogNote>), ShiftLogError> {
+    let note = add_note(&db, note, Utc::now()).await?;
+    Ok((StatusCode::CREATED, Json(note)))
+}
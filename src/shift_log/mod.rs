@@ -0,0 +1,212 @@
+//! Shift log of notes posted by operators about a telescope, e.g. that the
+//! azimuth drive was re-greased, so that whoever looks into odd data later
+//! knows what was done to the telescope and when.
+//!
+//! Notes are written in markdown. Raw HTML in a note is shown as text.
+
+use crate::database::{DataBase, DataBaseError, DataModel, Storage};
+use chrono::{DateTime, Utc};
+use pulldown_cmark::{html, Event, Parser};
+use serde::{Deserialize, Serialize};
+
+pub mod api_routes;
+pub mod routes;
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct NewShiftLogNote {
+    pub telescope_name: String,
+    pub author: String,
+    /// The note in markdown.
+    pub text: String,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct ShiftLogNote {
+    pub id: u64,
+    pub time: DateTime<Utc>,
+    #[serde(flatten)]
+    pub note: NewShiftLogNote,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ShiftLogError {
+    UnknownTelescope,
+    /// Only certified users operate the telescopes, so only they may post.
+    NotCertified,
+    EmptyNote,
+    ServiceUnavailable,
+}
+
+impl From<DataBaseError> for ShiftLogError {
+    fn from(_source: DataBaseError) -> Self {
+        Self::ServiceUnavailable
+    }
+}
+
+impl ShiftLogNote {
+    /// The note rendered from markdown to HTML.
+    pub fn html(&self) -> String {
+        render_markdown(&self.note.text)
+    }
+}
+
+/// Render markdown to HTML, escaping any raw HTML in it.
+pub fn render_markdown(text: &str) -> String {
+    let events = Parser::new(text).map(|event| match event {
+        Event::Html(html) => Event::Text(html),
+        event => event,
+    });
+    let mut output = String::new();
+    html::push_html(&mut output, events);
+    output
+}
+
+/// Notes about `telescope_name`, newest first.
+pub fn telescope_notes<'a>(
+    data_model: &'a DataModel,
+    telescope_name: &str,
+) -> Vec<&'a ShiftLogNote> {
+    data_model
+        .shift_log
+        .iter()
+        .rev()
+        .filter(|n| n.note.telescope_name == telescope_name)
+        .collect()
+}
+
+/// Notes about `telescope_name` posted between `start` and `end`, oldest
+/// first, e.g. to show with the events of a session.
+pub fn notes_between<'a>(
+    data_model: &'a DataModel,
+    telescope_name: &str,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> Vec<&'a ShiftLogNote> {
+    data_model
+        .shift_log
+        .iter()
+        .filter(|n| n.note.telescope_name == telescope_name)
+        .filter(|n| start <= n.time && n.time <= end)
+        .collect()
+}
+
+pub async fn add_note(
+    db: &DataBase<impl Storage>,
+    note: NewShiftLogNote,
+    now: DateTime<Utc>,
+) -> Result<ShiftLogNote, ShiftLogError> {
+    if note.text.trim().is_empty() {
+        return Err(ShiftLogError::EmptyNote);
+    }
+    let data_model = db.get_data().await?;
+    if !data_model
+        .telescopes
+        .iter()
+        .any(|t| t.name == note.telescope_name)
+    {
+        return Err(ShiftLogError::UnknownTelescope);
+    }
+    if !data_model
+        .users
+        .iter()
+        .any(|u| u.name == note.author && u.certified)
+    {
+        return Err(ShiftLogError::NotCertified);
+    }
+
+    let mut note = ShiftLogNote {
+        id: 0,
+        time: now,
+        note,
+    };
+    db.update_data(|mut data_model| {
+        note.id = data_model
+            .shift_log
+            .iter()
+            .map(|n| n.id + 1)
+            .max()
+            .unwrap_or(1);
+        data_model.shift_log.push(note.clone());
+        data_model
+    })
+    .await?;
+    log::info!(
+        "{} added a note to the shift log of {}",
+        note.note.author,
+        note.note.telescope_name
+    );
+    Ok(note)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::database::create_in_memory_database;
+    use crate::telescopes::test_utils::fake_telescope;
+    use crate::users::User;
+    use chrono::Duration;
+
+    #[tokio::test]
+    async fn test_shift_log() {
+        let db = create_in_memory_database();
+        let now = Utc::now();
+        db.update_data(|mut data_model| {
+            let mut operator = User::new("operator");
+            operator.certified = true;
+            data_model.users = vec![operator, User::new("student")];
+            data_model.telescopes = vec![fake_telescope("fake")];
+            data_model
+        })
+        .await
+        .unwrap();
+        let telescope_name = "fake".to_string();
+        let note = |author: &str, text: &str| NewShiftLogNote {
+            telescope_name: telescope_name.clone(),
+            author: author.to_string(),
+            text: text.to_string(),
+        };
+
+        assert_eq!(
+            add_note(&db, note("student", "Looks fine"), now).await,
+            Err(ShiftLogError::NotCertified)
+        );
+        assert_eq!(
+            add_note(&db, note("operator", "  "), now).await,
+            Err(ShiftLogError::EmptyNote)
+        );
+        assert_eq!(
+            add_note(
+                &db,
+                NewShiftLogNote {
+                    telescope_name: "nowhere".to_string(),
+                    ..note("operator", "Looks fine")
+                },
+                now
+            )
+            .await,
+            Err(ShiftLogError::UnknownTelescope)
+        );
+        add_note(&db, note("operator", "Re-greased **azimuth** drive"), now)
+            .await
+            .unwrap();
+        add_note(
+            &db,
+            note("operator", "<script>alert(1)</script>"),
+            now + Duration::hours(2),
+        )
+        .await
+        .unwrap();
+
+        let data_model = db.get_data().await.unwrap();
+        let notes = telescope_notes(&data_model, &telescope_name);
+        assert_eq!(notes.len(), 2);
+        assert_eq!(
+            notes[1].html(),
+            "<p>Re-greased <strong>azimuth</strong> drive</p>\n"
+        );
+        assert!(!notes[0].html().contains("<script>"));
+        let during = notes_between(&data_model, &telescope_name, now, now + Duration::hours(1));
+        assert_eq!(during.len(), 1);
+        assert_eq!(during[0].id, 1);
+    }
+}
@@ -0,0 +1,126 @@
+use crate::database::{DataBase, Storage};
+use crate::shift_log::{add_note, telescope_notes, NewShiftLogNote, ShiftLogError};
+use crate::template::HtmlTemplate;
+use askama::Template;
+use axum::{
+    extract::{Form, Path, State},
+    response::IntoResponse,
+    routing::get,
+    Router,
+};
+use chrono::Utc;
+use serde::Deserialize;
+
+pub fn routes(database: DataBase<impl Storage + 'static>) -> Router {
+    Router::new()
+        .route("/", get(get_shift_log_overview))
+        .route(
+            "/:telescope_name",
+            get(get_shift_log).post(post_shift_log_note),
+        )
+        .with_state(database)
+}
+
+#[derive(Template)]
+#[template(path = "shift_log_overview.html")]
+struct ShiftLogOverviewTemplate {
+    telescope_names: Vec<String>,
+}
+
+async fn get_shift_log_overview<StorageType>(
+    State(db): State<DataBase<StorageType>>,
+) -> impl IntoResponse
+where
+    StorageType: Storage,
+{
+    let telescope_names = db
+        .get_data()
+        .await
+        .expect("As long as no one is manually editing the database, this should never fail.")
+        .telescopes
+        .iter()
+        .map(|t| t.name.clone())
+        .collect();
+    HtmlTemplate(ShiftLogOverviewTemplate { telescope_names })
+}
+
+struct NoteEntry {
+    time: String,
+    author: String,
+    html: String,
+}
+
+#[derive(Template)]
+#[template(path = "shift_log.html")]
+struct ShiftLogTemplate {
+    telescope_name: String,
+    notes: Vec<NoteEntry>,
+    message: Option<String>,
+}
+
+async fn render_shift_log(
+    db: &DataBase<impl Storage>,
+    telescope_name: String,
+    message: Option<String>,
+) -> HtmlTemplate<ShiftLogTemplate> {
+    let data_model = db
+        .get_data()
+        .await
+        .expect("As long as no one is manually editing the database, this should never fail.");
+    let notes = telescope_notes(&data_model, &telescope_name)
+        .into_iter()
+        .map(|note| NoteEntry {
+            time: note.time.format("%Y-%m-%d %H:%M").to_string(),
+            author: note.note.author.clone(),
+            html: note.html(),
+        })
+        .collect();
+    HtmlTemplate(ShiftLogTemplate {
+        telescope_name,
+        notes,
+        message,
+    })
+}
+
+async fn get_shift_log<StorageType>(
+    State(db): State<DataBase<StorageType>>,
+    Path(telescope_name): Path<String>,
+) -> impl IntoResponse
+where
+    StorageType: Storage,
+{
+    render_shift_log(&db, telescope_name, None).await
+}
+
+#[derive(Deserialize, Debug)]
+struct NoteForm {
+    author: String,
+    text: String,
+}
+
+async fn post_shift_log_note<StorageType>(
+    State(db): State<DataBase<StorageType>>,
+    Path(telescope_name): Path<String>,
+    Form(form): Form<NoteForm>,
+) -> impl IntoResponse
+where
+    StorageType: Storage,
+{
+    let note = NewShiftLogNote {
+        telescope_name: telescope_name.clone(),
+        author: form.author,
+        text: form.text,
+    };
+    let message = match add_note(&db, note, Utc::now()).await {
+        Ok(_) => None,
+        Err(ShiftLogError::NotCertified) => {
+            Some("Only certified users may post to the shift log.".to_string())
+        }
+        Err(ShiftLogError::EmptyNote) => Some("Write something in the note.".to_string()),
+        Err(ShiftLogError::UnknownTelescope) => Some("There is no such telescope.".to_string()),
+        Err(ShiftLogError::ServiceUnavailable) => {
+            Some("Failed to save the note, try again later.".to_string())
+        }
+    };
+    render_shift_log(&db, telescope_name, message).await
+}
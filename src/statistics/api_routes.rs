@@ -0,0 +1,22 @@
+use crate::database::{DataBase, Storage};
+use crate::statistics::{statistics, Statistics};
+use axum::{
+    extract::{Json, State},
+    routing::get,
+    Router,
+};
+use chrono::Utc;
+
+pub fn routes(database: DataBase<impl Storage + 'static>) -> Router {
+    Router::new()
+        .route("/", get(get_statistics))
+        .with_state(database)
+}
+
+async fn get_statistics(State(db): State<DataBase<impl Storage>>) -> Json<Statistics> {
+    let data_model = db
+        .get_data()
+        .await
+        .expect("As long as no one is manually editing the database, this should never fail.");
+    Json(statistics(&data_model, Utc::now()))
+}
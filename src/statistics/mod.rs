@@ -0,0 +1,78 @@
+//! Aggregate numbers about the use of the telescopes, shown publicly for
+//! outreach.
+
+use crate::archive::latest_per_longitude;
+use crate::database::DataModel;
+use chrono::{DateTime, Datelike, Utc};
+use serde::{Deserialize, Serialize};
+
+pub mod api_routes;
+pub mod routes;
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Statistics {
+    pub total_observations: usize,
+    pub observing_hours_this_month: f64,
+    /// Users with a regular account, demo accounts are not counted.
+    pub registered_users: usize,
+    /// Whole degrees of galactic longitude observed at least once.
+    pub longitudes_covered: usize,
+}
+
+pub fn statistics(data_model: &DataModel, now: DateTime<Utc>) -> Statistics {
+    let this_month = |time: DateTime<Utc>| time.year() == now.year() && time.month() == now.month();
+    let observing_seconds: f64 = data_model
+        .measurements
+        .iter()
+        .filter(|m| this_month(m.measurement.start))
+        .map(|m| m.measurement.duration.as_secs_f64())
+        .sum();
+    Statistics {
+        total_observations: data_model.measurements.len(),
+        observing_hours_this_month: observing_seconds / 3600.0,
+        registered_users: data_model.users.iter().filter(|u| !u.is_demo()).count(),
+        longitudes_covered: latest_per_longitude(&data_model.measurements).len(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::angles::Degrees;
+    use crate::archive::test_utils::galactic_measurement;
+    use crate::archive::ArchivedMeasurement;
+    use crate::users::{User, UserKind};
+    use chrono::{Duration, TimeZone};
+
+    #[test]
+    fn test_statistics() {
+        let now = Utc.with_ymd_and_hms(2024, 3, 15, 12, 0, 0).unwrap();
+        let archived = |id, l, start| {
+            ArchivedMeasurement::new(id, "fake", galactic_measurement(Degrees(l), start))
+        };
+        let data_model = DataModel {
+            measurements: vec![
+                archived(1, 30.0, now - Duration::days(30)),
+                archived(2, 30.2, now),
+                archived(3, 40.0, now - Duration::days(1)),
+            ],
+            users: vec![
+                User::new("anna"),
+                User {
+                    kind: UserKind::Demo { expires: now },
+                    ..User::new("demo")
+                },
+            ],
+            ..Default::default()
+        };
+        assert_eq!(
+            statistics(&data_model, now),
+            Statistics {
+                total_observations: 3,
+                observing_hours_this_month: 2.0 / 60.0,
+                registered_users: 1,
+                longitudes_covered: 2,
+            }
+        );
+    }
+}
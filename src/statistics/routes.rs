@@ -0,0 +1,63 @@
+use crate::database::{DataBase, Storage};
+use crate::format::{filters, Locale};
+use crate::statistics::{statistics, Statistics};
+use crate::template::HtmlTemplate;
+use askama::Template;
+use axum::{extract::State, response::IntoResponse, routing::get, Router};
+use chrono::Utc;
+
+pub fn routes(database: DataBase<impl Storage + 'static>) -> Router {
+    Router::new()
+        .route("/", get(get_statistics))
+        .route("/counters", get(get_counters))
+        .with_state(database)
+}
+
+#[derive(Template)]
+#[template(path = "statistics.html")]
+struct StatisticsTemplate {
+    statistics: Statistics,
+    locale: Locale,
+}
+
+#[derive(Template)]
+#[template(path = "statistics_counters.html")]
+struct CountersTemplate {
+    statistics: Statistics,
+    locale: Locale,
+}
+
+async fn fetch_statistics(db: &DataBase<impl Storage>) -> Statistics {
+    let data_model = db
+        .get_data()
+        .await
+        .expect("As long as no one is manually editing the database, this should never fail.");
+    statistics(&data_model, Utc::now())
+}
+
+async fn get_statistics<StorageType>(
+    State(db): State<DataBase<StorageType>>,
+    locale: Locale,
+) -> impl IntoResponse
+where
+    StorageType: Storage,
+{
+    HtmlTemplate(StatisticsTemplate {
+        statistics: fetch_statistics(&db).await,
+        locale,
+    })
+}
+
+/// Only the counters, which the page polls to keep them live.
+async fn get_counters<StorageType>(
+    State(db): State<DataBase<StorageType>>,
+    locale: Locale,
+) -> impl IntoResponse
+where
+    StorageType: Storage,
+{
+    HtmlTemplate(CountersTemplate {
+        statistics: fetch_statistics(&db).await,
+        locale,
+    })
+}
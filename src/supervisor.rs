@@ -0,0 +1,236 @@
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+// Restarts are delayed by this much, doubled for every consecutive crash.
+pub const MIN_RESTART_DELAY: Duration = Duration::from_secs(1);
+pub const MAX_RESTART_DELAY: Duration = Duration::from_secs(60);
+// A task that has run this long without crashing is considered healthy again.
+pub const HEALTHY_RUN_TIME: Duration = Duration::from_secs(300);
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+pub enum TaskState {
+    Running,
+    /// Crashed and waiting to be restarted.
+    Restarting,
+    /// A one-off task that has run to completion.
+    Finished,
+    Stopped,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct TaskHealth {
+    pub name: String,
+    pub state: TaskState,
+    pub restarts: u32,
+    pub last_error: Option<String>,
+}
+
+/// Owns all tasks of one telescope.
+///
+/// Long running tasks are restarted with backoff if they crash or return,
+/// and everything is stopped together by [`TelescopeSupervisor::shutdown`].
+/// Cloning gives another handle to the same set of tasks, so a telescope can
+/// spawn its own tasks and report their health.
+#[derive(Clone)]
+pub struct TelescopeSupervisor {
+    telescope_name: String,
+    token: CancellationToken,
+    // Tasks are identified by a number, as names of one-off tasks are reused.
+    health: Arc<Mutex<Vec<(u64, TaskHealth)>>>,
+    next_id: Arc<AtomicU64>,
+    handles: Arc<Mutex<Vec<JoinHandle<()>>>>,
+}
+
+impl TelescopeSupervisor {
+    pub fn new(telescope_name: &str) -> TelescopeSupervisor {
+        TelescopeSupervisor {
+            telescope_name: telescope_name.to_string(),
+            token: CancellationToken::new(),
+            health: Arc::new(Mutex::new(Vec::new())),
+            next_id: Arc::new(AtomicU64::new(0)),
+            handles: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    pub fn health(&self) -> Vec<TaskHealth> {
+        self.health
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(_, health)| health.clone())
+            .collect()
+    }
+
+    fn update_health(&self, id: u64, update: impl FnOnce(&mut TaskHealth)) {
+        if let Some((_, health)) = self.health.lock().unwrap().iter_mut().find(|t| t.0 == id) {
+            update(health);
+        }
+    }
+
+    fn register(&self, name: &str) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let mut health = self.health.lock().unwrap();
+        // Names are reused by one-off tasks, such as one measurement after
+        // another, so only the latest one is kept.
+        health.retain(|(_, task)| task.name != name || task.state == TaskState::Running);
+        health.push((
+            id,
+            TaskHealth {
+                name: name.to_string(),
+                state: TaskState::Running,
+                restarts: 0,
+                last_error: None,
+            },
+        ));
+        id
+    }
+
+    /// Run a long running task, created anew by `create_task` every time it
+    /// has to be restarted.
+    pub fn spawn<F, Fut>(&self, name: &str, create_task: F)
+    where
+        F: Fn() -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let id = self.register(name);
+        let supervisor = self.clone();
+        let name = name.to_string();
+        let handle = tokio::spawn(async move {
+            let mut delay = MIN_RESTART_DELAY;
+            loop {
+                let started = tokio::time::Instant::now();
+                let mut task = tokio::spawn(create_task());
+                let error = tokio::select! {
+                    _ = supervisor.token.cancelled() => {
+                        task.abort();
+                        break;
+                    }
+                    result = &mut task => match result {
+                        Ok(()) => "task returned".to_string(),
+                        Err(error) => error.to_string(),
+                    },
+                };
+                log::error!(
+                    "Task {} of telescope {} stopped unexpectedly: {}",
+                    name,
+                    supervisor.telescope_name,
+                    error
+                );
+                if started.elapsed() >= HEALTHY_RUN_TIME {
+                    delay = MIN_RESTART_DELAY;
+                }
+                supervisor.update_health(id, |health| {
+                    health.state = TaskState::Restarting;
+                    health.restarts += 1;
+                    health.last_error = Some(error);
+                });
+                tokio::select! {
+                    _ = supervisor.token.cancelled() => break,
+                    _ = tokio::time::sleep(delay) => {}
+                }
+                delay = (delay * 2).min(MAX_RESTART_DELAY);
+                supervisor.update_health(id, |health| health.state = TaskState::Running);
+            }
+            supervisor.update_health(id, |health| health.state = TaskState::Stopped);
+        });
+        self.handles.lock().unwrap().push(handle);
+    }
+
+    /// Run a task once, such as a single measurement. It is not restarted,
+    /// but is aborted when the telescope shuts down.
+    ///
+    /// The returned handle gives the output of the task, or `None` if it was
+    /// aborted by a shutdown. Panics are passed on.
+    pub fn spawn_once<Fut>(&self, name: &str, task: Fut) -> JoinHandle<Option<Fut::Output>>
+    where
+        Fut: Future + Send + 'static,
+        Fut::Output: Send + 'static,
+    {
+        let id = self.register(name);
+        let supervisor = self.clone();
+        tokio::spawn(async move {
+            let mut task = tokio::spawn(task);
+            let result = tokio::select! {
+                _ = supervisor.token.cancelled() => {
+                    task.abort();
+                    supervisor.update_health(id, |health| health.state = TaskState::Stopped);
+                    return None;
+                }
+                result = &mut task => result,
+            };
+            supervisor.update_health(id, |health| {
+                health.state = TaskState::Finished;
+                health.last_error = result.as_ref().err().map(ToString::to_string);
+            });
+            match result {
+                Ok(output) => Some(output),
+                Err(error) => std::panic::resume_unwind(error.into_panic()),
+            }
+        })
+    }
+
+    /// Stop all tasks and wait for the long running ones to finish. One-off
+    /// tasks are aborted.
+    pub async fn shutdown(&self) {
+        log::info!("Stopping tasks of telescope {}", self.telescope_name);
+        self.token.cancel();
+        let handles = std::mem::take(&mut *self.handles.lock().unwrap());
+        for handle in handles {
+            if let Err(error) = handle.await {
+                log::error!(
+                    "Task of telescope {} failed during shutdown: {}",
+                    self.telescope_name,
+                    error
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::AtomicU32;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_restart_crashed_task() {
+        let supervisor = TelescopeSupervisor::new("fake");
+        let runs = Arc::new(AtomicU32::new(0));
+        let counter = runs.clone();
+        supervisor.spawn("crashing", move || {
+            let counter = counter.clone();
+            async move {
+                counter.fetch_add(1, Ordering::SeqCst);
+                panic!("crash");
+            }
+        });
+        tokio::time::sleep(MIN_RESTART_DELAY * 4).await;
+        // Restarted after 1 s and 2 s, the next restart is after another 4 s.
+        assert_eq!(runs.load(Ordering::SeqCst), 3);
+        let health = supervisor.health();
+        assert_eq!(health[0].name, "crashing");
+        assert_eq!(health[0].restarts, 3);
+
+        supervisor.shutdown().await;
+        assert_eq!(supervisor.health()[0].state, TaskState::Stopped);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_shutdown_aborts_one_off_tasks() {
+        let supervisor = TelescopeSupervisor::new("fake");
+        let handle = supervisor.spawn_once("measurement", async {
+            tokio::time::sleep(Duration::from_secs(3600)).await;
+        });
+        tokio::task::yield_now().await;
+        assert_eq!(supervisor.health()[0].state, TaskState::Running);
+        supervisor.shutdown().await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(handle.is_finished());
+        assert_eq!(supervisor.health()[0].state, TaskState::Stopped);
+    }
+}
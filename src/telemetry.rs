@@ -0,0 +1,267 @@
+//! Debug telemetry of what the tracker decides on each tick.
+//!
+//! When enabled in the configuration, every tick of the tracker of a SALSA
+//! telescope is recorded with the direction it aimed for, the direction the
+//! controller reported and what was sent to the controller. Records are
+//! streamed to websocket clients and appended to a file per telescope, so
+//! that pointing problems can be analysed afterwards without a redeploy.
+//!
+//! The file works as a ring buffer: once it holds `max_records` records it
+//! is moved to `<file>.1`, replacing the previous one, and a new file is
+//! started.
+
+use crate::coords::Direction;
+use crate::supervisor::TelescopeSupervisor;
+use crate::telescopes::{TelescopeError, TelescopeTarget};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tokio::fs::{File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::broadcast;
+
+/// Records buffered for each websocket client and the file writer. A
+/// client that falls further behind misses records.
+const CHANNEL_CAPACITY: usize = 1024;
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct TelemetryDefinition {
+    /// Directory of the files, named `<telescope>-tracker.jsonl`.
+    pub directory: String,
+    /// Records in each file before it is rotated.
+    pub max_records: usize,
+}
+
+/// What the tracker sent to the controller on a tick.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+pub enum TrackerAction {
+    /// Nothing was sent, the telescope was already where it should be, the
+    /// target could not be tracked or the command was throttled.
+    Nothing,
+    SetDirection(Direction),
+    Stop,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct TrackerDecision {
+    pub time: DateTime<Utc>,
+    pub telescope: String,
+    pub target: TelescopeTarget,
+    /// Where the target is, with the pointing correction applied.
+    pub target_horizontal: Option<Direction>,
+    /// Where the controller reported the telescope to be.
+    pub measured_horizontal: Option<Direction>,
+    /// Target minus measured direction, on each axis.
+    pub pointing_error: Option<Direction>,
+    pub action: TrackerAction,
+    pub error: Option<TelescopeError>,
+}
+
+impl TrackerDecision {
+    pub fn new(
+        telescope: &str,
+        time: DateTime<Utc>,
+        target: TelescopeTarget,
+        target_horizontal: Option<Direction>,
+        measured_horizontal: Option<Direction>,
+        action: TrackerAction,
+        error: Option<TelescopeError>,
+    ) -> TrackerDecision {
+        let pointing_error =
+            target_horizontal
+                .zip(measured_horizontal)
+                .map(|(target, measured)| Direction {
+                    azimuth: target.azimuth - measured.azimuth,
+                    altitude: target.altitude - measured.altitude,
+                });
+        TrackerDecision {
+            time,
+            telescope: telescope.to_string(),
+            target,
+            target_horizontal,
+            measured_horizontal,
+            pointing_error,
+            action,
+            error,
+        }
+    }
+}
+
+/// Handle for recording the decisions of one tracker.
+#[derive(Clone)]
+pub struct TrackerTelemetry {
+    sender: broadcast::Sender<TrackerDecision>,
+}
+
+impl TrackerTelemetry {
+    /// Start writing the decisions of telescope `name` to the file given
+    /// by `definition`.
+    pub fn start(
+        name: &str,
+        definition: &TelemetryDefinition,
+        supervisor: &TelescopeSupervisor,
+    ) -> TrackerTelemetry {
+        let (sender, _) = broadcast::channel(CHANNEL_CAPACITY);
+        let telemetry = TrackerTelemetry { sender };
+        let path = Path::new(&definition.directory).join(format!("{}-tracker.jsonl", name));
+        let max_records = definition.max_records;
+        let task_telemetry = telemetry.clone();
+        supervisor.spawn("telemetry", move || {
+            write_telemetry(path.clone(), max_records, task_telemetry.subscribe())
+        });
+        telemetry
+    }
+
+    pub fn record(&self, decision: TrackerDecision) {
+        // Nobody listening is not an error.
+        let _ = self.sender.send(decision);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<TrackerDecision> {
+        self.sender.subscribe()
+    }
+}
+
+fn rotated_path(path: &Path) -> PathBuf {
+    let mut rotated = path.as_os_str().to_owned();
+    rotated.push(".1");
+    PathBuf::from(rotated)
+}
+
+async fn open_telemetry_file(path: &Path) -> std::io::Result<File> {
+    if let Some(directory) = path.parent() {
+        tokio::fs::create_dir_all(directory).await?;
+    }
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await
+}
+
+/// Append the records from `decisions` to `path`, one JSON object per line,
+/// moving the file to `<path>.1` when it has `max_records` records.
+async fn write_telemetry(
+    path: PathBuf,
+    max_records: usize,
+    mut decisions: broadcast::Receiver<TrackerDecision>,
+) {
+    let mut records = match tokio::fs::read_to_string(&path).await {
+        Ok(contents) => contents.lines().count(),
+        Err(_) => 0,
+    };
+    let mut file = match open_telemetry_file(&path).await {
+        Ok(file) => file,
+        Err(error) => {
+            log::error!("Failed to open telemetry file {:?}: {}", path, error);
+            return;
+        }
+    };
+    loop {
+        let decision = match decisions.recv().await {
+            Ok(decision) => decision,
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                log::warn!("Telemetry file {:?} missed {} records", path, skipped);
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => return,
+        };
+        if records >= max_records {
+            if let Err(error) = tokio::fs::rename(&path, rotated_path(&path)).await {
+                log::error!("Failed to rotate telemetry file {:?}: {}", path, error);
+                return;
+            }
+            file = match open_telemetry_file(&path).await {
+                Ok(file) => file,
+                Err(error) => {
+                    log::error!("Failed to open telemetry file {:?}: {}", path, error);
+                    return;
+                }
+            };
+            records = 0;
+        }
+        let mut line = serde_json::to_vec(&decision).expect("Decisions can always be serialized");
+        line.push(b'\n');
+        if let Err(error) = file.write_all(&line).await.and(file.flush().await) {
+            log::error!("Failed to write telemetry file {:?}: {}", path, error);
+            return;
+        }
+        records += 1;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::angles::Radians;
+
+    fn decision(index: usize) -> TrackerDecision {
+        let direction = Direction {
+            azimuth: Radians(index as f64 * 0.01),
+            altitude: Radians(0.5),
+        };
+        TrackerDecision::new(
+            "brage",
+            Utc::now(),
+            TelescopeTarget::Stopped,
+            Some(direction),
+            Some(direction),
+            TrackerAction::Nothing,
+            None,
+        )
+    }
+
+    #[tokio::test]
+    async fn test_telemetry_file_rotation() {
+        let directory =
+            std::env::temp_dir().join(format!("salsa-telemetry-test-{}", std::process::id()));
+        let _ = tokio::fs::remove_dir_all(&directory).await;
+        let supervisor = TelescopeSupervisor::new("brage");
+        let telemetry = TrackerTelemetry::start(
+            "brage",
+            &TelemetryDefinition {
+                directory: directory.to_string_lossy().to_string(),
+                max_records: 3,
+            },
+            &supervisor,
+        );
+        // Let the writer subscribe before recording.
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        let mut client = telemetry.subscribe();
+        for index in 0..5 {
+            telemetry.record(decision(index));
+        }
+        assert_eq!(
+            client.recv().await.unwrap().target_horizontal,
+            decision(0).target_horizontal
+        );
+
+        let path = directory.join("brage-tracker.jsonl");
+        let read_records = |path: PathBuf| async move {
+            tokio::fs::read_to_string(path)
+                .await
+                .unwrap_or_default()
+                .lines()
+                .map(|line| serde_json::from_str::<TrackerDecision>(line).unwrap())
+                .collect::<Vec<_>>()
+        };
+        for _ in 0..100 {
+            if read_records(path.clone()).await.len() == 2 {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        let current = read_records(path.clone()).await;
+        let rotated = read_records(rotated_path(&path)).await;
+        assert_eq!(rotated.len(), 3);
+        assert_eq!(current.len(), 2);
+        assert_eq!(current[1].target_horizontal, decision(4).target_horizontal);
+        assert_eq!(
+            rotated[0].pointing_error.map(|e| e.azimuth),
+            Some(Radians(0.0))
+        );
+
+        supervisor.shutdown().await;
+        let _ = tokio::fs::remove_dir_all(&directory).await;
+    }
+}
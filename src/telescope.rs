@@ -1,7 +1,22 @@
+use crate::archive::checkpoints::{clear_checkpoint, save_checkpoint, CHECKPOINT_INTERVAL};
+use crate::archive::{archive_measurement, ArchivedMeasurement};
+use crate::auxiliary::AuxiliaryDeviceState;
+use crate::calibration::{latest_calibration, Calibration};
+use crate::connection_health::ConnectionHealth;
 use crate::coords::Direction;
+use crate::fake_telescope::SimulatedConditions;
+use crate::hooks::run_post_observation_hooks;
+use crate::interlock::CollisionInterlock;
+use crate::pointing::{PointingCorrection, PointingOffset};
+use crate::rfi::{store_rfi_scan, RfiScan};
+use crate::sessions::SessionRecorder;
+use crate::supervisor::TelescopeSupervisor;
+use crate::telemetry::{TelemetryDefinition, TrackerTelemetry};
+use crate::telescope_updates::TelescopeUpdates;
 use crate::telescopes::{
-    ReceiverConfiguration, ReceiverError, TelescopeDefinition, TelescopeError, TelescopeInfo,
-    TelescopeTarget, TelescopeType,
+    Measurement, ReceiverConfiguration, ReceiverError, TargetPreview, TelescopeCapabilities,
+    TelescopeDefinition, TelescopeError, TelescopeInfo, TelescopeStatus, TelescopeTarget,
+    TelescopeType,
 };
 use async_trait::async_trait;
 use std::collections::HashMap;
@@ -12,6 +27,8 @@ use tokio::sync::{Mutex, RwLock};
 use crate::database::{DataBase, DataBaseError, Storage};
 
 pub const TELESCOPE_UPDATE_INTERVAL: Duration = Duration::from_secs(1);
+/// Longest time to wait for the telescopes to park when shutting down.
+pub const SHUTDOWN_PARK_TIMEOUT: Duration = Duration::from_secs(60);
 
 #[async_trait]
 pub trait Telescope: Send + Sync {
@@ -21,6 +38,8 @@ pub trait Telescope: Send + Sync {
         &mut self,
         target: TelescopeTarget,
     ) -> Result<TelescopeTarget, TelescopeError>;
+    /// What `set_target` would do right now, without moving the telescope.
+    async fn preview_target(&self, target: TelescopeTarget) -> TargetPreview;
     async fn set_receiver_configuration(
         &mut self,
         receiver_configuration: ReceiverConfiguration,
@@ -28,70 +47,404 @@ pub trait Telescope: Send + Sync {
     async fn get_info(&self) -> Result<TelescopeInfo, TelescopeError>;
     async fn update(&mut self, delta_time: Duration) -> Result<(), TelescopeError>;
     async fn restart(&mut self) -> Result<(), TelescopeError>;
+    async fn set_auxiliary_device(
+        &mut self,
+        name: &str,
+        on: bool,
+    ) -> Result<AuxiliaryDeviceState, TelescopeError>;
+    /// Decisions of the tracker, if telemetry is enabled.
+    fn tracker_telemetry(&self) -> Option<TrackerTelemetry> {
+        None
+    }
+    /// Health of the connection to the hardware, for telescopes with any.
+    fn connection_health(&self) -> Option<ConnectionHealth> {
+        None
+    }
+    /// Conditions of a simulated telescope, which can be changed in dev mode.
+    fn simulated_conditions(&mut self) -> Option<&mut SimulatedConditions> {
+        None
+    }
+    fn capabilities(&self) -> TelescopeCapabilities;
+    fn pointing_correction(&self) -> PointingCorrection;
+    /// Replace the pointing model and offset applied to the target.
+    fn set_pointing_correction(&mut self, correction: PointingCorrection);
+    /// Use `calibration` to scale spectra from the next integration.
+    /// Telescopes without a real receiver ignore it.
+    fn set_calibration(&mut self, _calibration: Calibration) {}
+    /// Send `bytes` as they are to the controller of the telescope and
+    /// return its response, for debugging a misbehaving controller.
+    async fn send_raw_command(&mut self, _bytes: Vec<u8>) -> Result<Vec<u8>, TelescopeError> {
+        Err(TelescopeError::TelescopeIOError(
+            "Telescope has no controller taking raw commands".to_string(),
+        ))
+    }
+    /// Stop the mount right away, e.g. before shutting down. Telescopes
+    /// without a controller have nothing to stop.
+    async fn stop_motion(&mut self) -> Result<(), TelescopeError> {
+        Ok(())
+    }
+    /// Snapshot of the measurement currently being integrated, if any.
+    async fn measurement_in_progress(&self) -> Option<Measurement>;
+    /// Hand over measurements that have finished since the last call.
+    ///
+    /// Each measurement is only returned once, so the caller is responsible
+    /// for storing it.
+    async fn take_completed_measurements(&mut self) -> Vec<Measurement>;
+    /// Hand over RFI scans that have finished since the last call.
+    async fn take_completed_rfi_scans(&mut self) -> Vec<RfiScan>;
 }
 
 pub struct TelescopeContainer {
     pub telescope: Arc<Mutex<dyn Telescope>>,
-    pub service: Option<tokio::task::JoinHandle<()>>,
+    pub supervisor: TelescopeSupervisor,
+    /// State published after each update, for websocket clients.
+    pub updates: TelescopeUpdates,
 }
 
 pub type TelescopeCollection = Arc<RwLock<HashMap<String, TelescopeContainer>>>;
 
-fn start_telescope_service(telescope: Arc<Mutex<dyn Telescope>>) -> tokio::task::JoinHandle<()> {
-    tokio::spawn(async move {
-        loop {
+async fn run_telescope_service<T>(
+    telescope_name: String,
+    telescope: Arc<Mutex<dyn Telescope>>,
+    database: DataBase<T>,
+    supervisor: TelescopeSupervisor,
+    updates: TelescopeUpdates,
+) where
+    T: Storage + 'static,
+{
+    let mut session_recorder = SessionRecorder::default();
+    let mut published_spectrum = None;
+    let mut last_checkpoint: Option<tokio::time::Instant> = None;
+    loop {
+        let (info, measurement_in_progress, completed_measurements, completed_rfi_scans) = {
+            let mut telescope = telescope.clone().lock_owned().await;
+            if let Err(error) = telescope.update(TELESCOPE_UPDATE_INTERVAL).await {
+                log::error!("Failed to update telescope: {}", error);
+            }
+            (
+                telescope.get_info().await,
+                telescope.measurement_in_progress().await,
+                telescope.take_completed_measurements().await,
+                telescope.take_completed_rfi_scans().await,
+            )
+        };
+        if let Ok(info) = info {
+            updates.publish(
+                &info,
+                measurement_in_progress.as_ref(),
+                &mut published_spectrum,
+            );
+            if let Err(error) = session_recorder
+                .record(&database, &telescope_name, &info)
+                .await
             {
-                let mut telescope = telescope.clone().lock_owned().await;
-                if let Err(error) = telescope.update(TELESCOPE_UPDATE_INTERVAL).await {
-                    log::error!("Failed to update telescope: {}", error);
+                log::error!("Failed to record session on {}: {}", telescope_name, error);
+            }
+        }
+        let finished = measurement_in_progress.is_none();
+        if let Some(measurement) = measurement_in_progress {
+            let due = last_checkpoint.is_none_or(|last| last.elapsed() >= CHECKPOINT_INTERVAL);
+            if due {
+                if let Err(error) = save_checkpoint(&database, &telescope_name, measurement).await {
+                    log::error!(
+                        "Failed to checkpoint measurement on {}: {}",
+                        telescope_name,
+                        error
+                    );
                 }
+                last_checkpoint = Some(tokio::time::Instant::now());
             }
-            tokio::time::sleep(TELESCOPE_UPDATE_INTERVAL).await;
         }
-    })
+        for measurement in completed_measurements {
+            match archive_measurement(&database, &telescope_name, measurement.clone()).await {
+                Ok(id) => {
+                    if let Err(error) = session_recorder
+                        .record_measurement_saved(&database, &telescope_name, id)
+                        .await
+                    {
+                        log::error!("Failed to record session on {}: {}", telescope_name, error);
+                    }
+                    let hooks = match database.get_data().await {
+                        Ok(data_model) => data_model.post_observation_hooks,
+                        Err(_) => vec![],
+                    };
+                    // Hooks may be slow, don't hold up the telescope.
+                    supervisor.spawn_once(
+                        "post_observation_hooks",
+                        run_post_observation_hooks(
+                            hooks,
+                            ArchivedMeasurement::new(id, &telescope_name, measurement),
+                        ),
+                    );
+                }
+                Err(error) => log::error!(
+                    "Failed to archive measurement from {}: {}",
+                    telescope_name,
+                    error
+                ),
+            }
+        }
+        // Only drop the checkpoint once the finished measurement is safely
+        // in the archive.
+        if finished && last_checkpoint.take().is_some() {
+            if let Err(error) = clear_checkpoint(&database, &telescope_name).await {
+                log::error!(
+                    "Failed to clear measurement checkpoint on {}: {}",
+                    telescope_name,
+                    error
+                );
+            }
+        }
+        for rfi_scan in completed_rfi_scans {
+            if let Err(error) = store_rfi_scan(&database, rfi_scan).await {
+                log::error!(
+                    "Failed to store RFI scan from {}: {}",
+                    telescope_name,
+                    error
+                );
+            }
+        }
+        tokio::time::sleep(TELESCOPE_UPDATE_INTERVAL).await;
+    }
 }
 
-fn create_telescope(telescope_definition: TelescopeDefinition) -> TelescopeContainer {
+fn create_telescope<T>(
+    telescope_definition: TelescopeDefinition,
+    rfi_scans: Vec<RfiScan>,
+    interlock: CollisionInterlock,
+    telemetry: Option<TelemetryDefinition>,
+    calibration: Option<Calibration>,
+    database: DataBase<T>,
+) -> TelescopeContainer
+where
+    T: Storage + 'static,
+{
     log::info!("Creating telescope {}", telescope_definition.name);
+    let supervisor = TelescopeSupervisor::new(&telescope_definition.name);
+    let pointing = PointingCorrection {
+        model: telescope_definition.pointing_model,
+        offset: PointingOffset::default(),
+    };
     let telescope: Arc<Mutex<dyn Telescope>> = match telescope_definition.telescope_type {
         TelescopeType::Salsa { definition } => {
-            Arc::new(Mutex::new(crate::salsa_telescope::create(
+            let mut telescope = crate::salsa_telescope::create(
+                telescope_definition.name.clone(),
+                definition,
+                telescope_definition.rfi_scan.clone(),
+                rfi_scans,
+                telescope_definition.auxiliary_devices.clone(),
+                supervisor.clone(),
+                interlock,
+                telemetry,
+            );
+            telescope.set_pointing_correction(pointing);
+            if let Some(calibration) = calibration {
+                telescope.set_calibration(calibration);
+            }
+            Arc::new(Mutex::new(telescope))
+        }
+        TelescopeType::Fake { .. } => {
+            let mut telescope = crate::fake_telescope::create(
                 telescope_definition.name.clone(),
-                definition.controller_address.clone(),
-                definition.receiver_address.clone(),
-            )))
+                telescope_definition.rfi_scan.clone(),
+                telescope_definition.auxiliary_devices.clone(),
+                supervisor.clone(),
+                interlock,
+            );
+            telescope.set_pointing_correction(pointing);
+            Arc::new(Mutex::new(telescope))
         }
-        TelescopeType::Fake { .. } => Arc::new(Mutex::new(crate::fake_telescope::create(
-            telescope_definition.name.clone(),
-        ))),
     };
 
-    let service: Option<_> = if telescope_definition.enabled {
-        Some(start_telescope_service(telescope.clone()))
-    } else {
-        None
-    };
+    let updates = TelescopeUpdates::default();
+    if telescope_definition.enabled {
+        let telescope_name = telescope_definition.name.clone();
+        let telescope = telescope.clone();
+        let service_supervisor = supervisor.clone();
+        let updates = updates.clone();
+        supervisor.spawn("service", move || {
+            run_telescope_service(
+                telescope_name.clone(),
+                telescope.clone(),
+                database.clone(),
+                service_supervisor.clone(),
+                updates.clone(),
+            )
+        });
+    }
+
+    TelescopeContainer {
+        telescope,
+        supervisor,
+        updates,
+    }
+}
+
+/// Stop the integration of a telescope that is shutting down and archive
+/// what it measured. The checkpoint is only dropped once that is done.
+async fn archive_before_shutdown(
+    telescope_name: &str,
+    telescope: &mut dyn Telescope,
+    database: &DataBase<impl Storage>,
+) {
+    if telescope.measurement_in_progress().await.is_some() {
+        let stop = ReceiverConfiguration::stopped();
+        if let Err(error) = telescope.set_receiver_configuration(stop).await {
+            log::error!(
+                "Failed to stop integration on {}: {:?}",
+                telescope_name,
+                error
+            );
+            return;
+        }
+    }
+    for measurement in telescope.take_completed_measurements().await {
+        match archive_measurement(database, telescope_name, measurement).await {
+            Ok(id) => log::info!("Archived measurement {} from {}", id, telescope_name),
+            Err(error) => {
+                log::error!(
+                    "Failed to archive measurement from {}: {}",
+                    telescope_name,
+                    error
+                );
+                return;
+            }
+        }
+    }
+    if let Err(error) = clear_checkpoint(database, telescope_name).await {
+        log::error!(
+            "Failed to clear measurement checkpoint on {}: {}",
+            telescope_name,
+            error
+        );
+    }
+}
 
-    TelescopeContainer { telescope, service }
+/// Remove all telescopes and stop their tasks, e.g. when the application
+/// is shutting down.
+///
+/// Integrations are stopped and archived, and the telescopes are parked.
+/// Once they are parked, or after [`SHUTDOWN_PARK_TIMEOUT`], the controllers
+/// are stopped so that nothing moves unattended.
+pub async fn shutdown_telescopes(
+    telescopes: &TelescopeCollection,
+    database: &DataBase<impl Storage>,
+) {
+    let removed: Vec<_> = telescopes.write().await.drain().collect();
+    for (name, container) in &removed {
+        let mut telescope = container.telescope.lock().await;
+        archive_before_shutdown(name, &mut *telescope, database).await;
+        match telescope.set_target(TelescopeTarget::Parked).await {
+            Ok(_) => log::info!("Parking {} before shutting down", name),
+            Err(error) => log::error!("Failed to park {}: {}", name, error),
+        }
+    }
+    // The telescopes are still updated by their tasks while parking.
+    let deadline = tokio::time::Instant::now() + SHUTDOWN_PARK_TIMEOUT;
+    for (name, container) in &removed {
+        while tokio::time::Instant::now() < deadline {
+            let info = container.telescope.lock().await.get_info().await;
+            if !matches!(info, Ok(info) if info.status == TelescopeStatus::Slewing) {
+                break;
+            }
+            tokio::time::sleep(TELESCOPE_UPDATE_INTERVAL).await;
+        }
+        if let Err(error) = container.telescope.lock().await.stop_motion().await {
+            log::error!("Failed to stop {}: {}", name, error);
+        }
+    }
+    for (_, container) in removed {
+        container.supervisor.shutdown().await;
+    }
 }
 
 pub async fn create_telescope_collection<T>(
     database: &DataBase<T>,
 ) -> Result<TelescopeCollection, DataBaseError>
 where
-    T: Storage,
+    T: Storage + 'static,
 {
-    let telescope_definitions = database.get_data().await?.telescopes;
+    let data_model = database.get_data().await?;
+    let interlock = CollisionInterlock::new(data_model.collision_zones);
 
-    let telescopes: HashMap<_, _> = telescope_definitions
+    let telescopes: HashMap<_, _> = data_model
+        .telescopes
         .into_iter()
         .map(|telescope_definition| {
+            let calibration =
+                latest_calibration(&data_model.calibrations, &telescope_definition.name).cloned();
+            let rfi_scans = data_model
+                .rfi_scans
+                .iter()
+                .filter(|scan| scan.telescope_name == telescope_definition.name)
+                .cloned()
+                .collect();
             (
                 telescope_definition.name.clone(),
-                create_telescope(telescope_definition),
+                create_telescope(
+                    telescope_definition,
+                    rfi_scans,
+                    interlock.clone(),
+                    data_model.tracker_telemetry.clone(),
+                    calibration,
+                    database.clone(),
+                ),
             )
         })
         .collect();
 
     Ok(Arc::new(RwLock::new(telescopes)))
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::angles::Degrees;
+    use crate::database::create_in_memory_database;
+    use crate::telescopes::test_utils::fake_telescope;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_shutdown_archives_and_parks() {
+        let db = create_in_memory_database();
+        let container = create_telescope(
+            fake_telescope("fake"),
+            vec![],
+            CollisionInterlock::default(),
+            None,
+            None,
+            db.clone(),
+        );
+        let telescope = container.telescope.clone();
+        {
+            let mut telescope = telescope.lock().await;
+            telescope
+                .set_target(TelescopeTarget::Horizontal {
+                    azimuth: Degrees(180.0).to_radians(),
+                    elevation: Degrees(45.0).to_radians(),
+                })
+                .await
+                .unwrap();
+            telescope
+                .set_receiver_configuration(ReceiverConfiguration {
+                    integrate: true,
+                    ..ReceiverConfiguration::stopped()
+                })
+                .await
+                .unwrap();
+        }
+        tokio::time::sleep(Duration::from_secs(10)).await;
+
+        let telescopes: TelescopeCollection = Arc::new(RwLock::new(HashMap::from([(
+            "fake".to_string(),
+            container,
+        )])));
+        shutdown_telescopes(&telescopes, &db).await;
+        assert!(telescopes.read().await.is_empty());
+        let data_model = db.get_data().await.unwrap();
+        assert_eq!(data_model.measurements.len(), 1);
+        assert!(data_model.measurement_checkpoints.is_empty());
+        let info = telescope.lock().await.get_info().await.unwrap();
+        assert_eq!(info.current_target, TelescopeTarget::Parked);
+        assert!(!info.measurement_in_progress);
+    }
+}
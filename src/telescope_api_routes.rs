@@ -1,23 +1,82 @@
+use crate::angles::{parse_declination, parse_degrees, parse_right_ascension, AngleParseError};
+use crate::archive::duplicates::{recent_duplicate, DuplicateObservation};
+use crate::authentication::Requester;
+use crate::auxiliary::AuxiliaryDeviceState;
+use crate::constants::{builtin_spectral_lines, lsr_velocities};
 use crate::coords::Direction;
+use crate::database::{DataBase, Storage};
+use crate::observation_log::{record_command, ObservationCommand};
+use crate::orbit::{parse_tle, TleError};
+use crate::park_policies::enforce_quiet_hours;
+use crate::plot::{render_spectrum, PlotError, PlotOptions};
+use crate::sessions::expiry::enforce_booking_end;
+use crate::telemetry::TrackerDecision;
 use crate::telescope::{Telescope, TelescopeCollection};
+use crate::telescope_updates::TelescopeUpdate;
 use crate::telescopes::{
-    ReceiverConfiguration, ReceiverError, TelescopeError, TelescopeInfo, TelescopeTarget,
+    CoordinateSystem, InvalidTarget, ReceiverConfiguration, TelescopeCapabilities, TelescopeError,
+    TelescopeInfo, TelescopeTarget,
 };
+use crate::ups::refuse_on_battery;
 use axum::{
-    extract::{Json, Path, State},
-    http::StatusCode,
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Json, Path, Query, State,
+    },
+    http::{header, StatusCode},
+    middleware,
     response::{IntoResponse, Response},
     routing::{get, post},
     Router,
 };
+use chrono::Utc;
+use serde::Deserialize;
+use tokio::sync::broadcast;
 
-pub fn routes(telescopes: TelescopeCollection) -> Router {
+pub fn routes(
+    telescopes: TelescopeCollection,
+    database: DataBase<impl Storage + 'static>,
+) -> Router {
+    let state = TelescopeState {
+        telescopes: telescopes.clone(),
+        database: database.clone(),
+    };
     let telescope_routes = Router::new()
         .route("/", get(get_telescope))
+        .route("/capabilities", get(get_capabilities))
         .route("/direction", get(get_direction))
-        .route("/target", get(get_target).post(set_target))
-        .route("/restart", post(restart))
-        .route("/receiver", post(set_receiver_configuration));
+        .route(
+            "/target",
+            get(get_target).post(set_target).with_state(state.clone()),
+        )
+        .route(
+            "/target/text",
+            post(set_target_from_text).with_state(state.clone()),
+        )
+        .route("/restart", post(restart).with_state(state.clone()))
+        .route(
+            "/receiver",
+            post(set_receiver_configuration).with_state(state.clone()),
+        )
+        .route(
+            "/target/satellite",
+            post(set_target_to_satellite).with_state(state),
+        )
+        .route("/auxiliary/:device_name", post(set_auxiliary_device))
+        .route("/spectrum/plot", get(get_spectrum_plot))
+        .route("/telemetry", get(get_telemetry))
+        .route("/health", get(get_health))
+        .route("/updates", get(get_updates));
+    let telescope_routes = telescope_routes
+        .route_layer(middleware::from_fn_with_state(
+            database.clone(),
+            enforce_quiet_hours,
+        ))
+        .route_layer(middleware::from_fn_with_state(
+            database.clone(),
+            enforce_booking_end,
+        ))
+        .route_layer(middleware::from_fn_with_state(database, refuse_on_battery));
     let router = Router::new()
         .route("/", get(get_telescopes))
         .nest("/:telescope_id", telescope_routes)
@@ -41,7 +100,7 @@ async fn get_telescopes(State(telescopes): State<TelescopeCollection>) -> Json<V
 }
 
 #[derive(Debug)]
-struct TelescopeNotFound;
+pub struct TelescopeNotFound;
 
 impl IntoResponse for TelescopeNotFound {
     fn into_response(self) -> Response {
@@ -49,7 +108,7 @@ impl IntoResponse for TelescopeNotFound {
     }
 }
 
-async fn extract_telescope(
+pub async fn extract_telescope(
     telescopes: TelescopeCollection,
     id: String,
 ) -> Result<tokio::sync::OwnedMutexGuard<dyn Telescope>, TelescopeNotFound> {
@@ -66,6 +125,14 @@ async fn get_telescope(
     Ok(Json(telescope.get_info().await))
 }
 
+async fn get_capabilities(
+    State(telescopes): State<TelescopeCollection>,
+    Path(telescope_id): Path<String>,
+) -> Result<Json<TelescopeCapabilities>, TelescopeNotFound> {
+    let telescope = extract_telescope(telescopes, telescope_id).await?;
+    Ok(Json(telescope.capabilities()))
+}
+
 async fn get_direction(
     State(telescopes): State<TelescopeCollection>,
     Path(telescope_id): Path<String>,
@@ -74,36 +141,660 @@ async fn get_direction(
     Ok(Json(telescope.get_direction().await))
 }
 
-async fn get_target(
-    State(telescopes): State<TelescopeCollection>,
+async fn get_target<StorageType: Storage>(
+    State(state): State<TelescopeState<StorageType>>,
     Path(telescope_id): Path<String>,
 ) -> Result<Json<Result<TelescopeTarget, TelescopeError>>, TelescopeNotFound> {
-    let telescope = extract_telescope(telescopes, telescope_id).await?;
+    let telescope = extract_telescope(state.telescopes, telescope_id).await?;
     Ok(Json(telescope.get_target().await))
 }
 
-async fn set_target(
-    State(telescopes): State<TelescopeCollection>,
+#[derive(Deserialize, Debug, Default)]
+struct SetTargetOptions {
+    /// Only return what setting the target would do, without moving the
+    /// telescope.
+    #[serde(default)]
+    dry_run: bool,
+}
+
+/// Set the target and record it in the observation log, or preview it if
+/// `options` asks for a dry run.
+async fn command_target<StorageType: Storage>(
+    state: TelescopeState<StorageType>,
+    telescope_id: String,
+    requester: Requester,
+    target: TelescopeTarget,
+    options: SetTargetOptions,
+) -> Result<Response, TelescopeNotFound> {
+    let mut telescope = extract_telescope(state.telescopes, telescope_id.clone()).await?;
+    if options.dry_run {
+        return Ok(Json(telescope.preview_target(target).await).into_response());
+    }
+    let result = telescope.set_target(target).await;
+    record_command(
+        &state.database,
+        &telescope_id,
+        requester.0,
+        ObservationCommand::SetTarget(target),
+        &result,
+        Utc::now(),
+    )
+    .await;
+    Ok(Json(result).into_response())
+}
+
+async fn set_target<StorageType: Storage>(
+    State(state): State<TelescopeState<StorageType>>,
     Path(telescope_id): Path<String>,
+    Query(options): Query<SetTargetOptions>,
+    requester: Requester,
     Json(target): Json<TelescopeTarget>,
-) -> Result<Json<Result<TelescopeTarget, TelescopeError>>, TelescopeNotFound> {
-    let mut telescope = extract_telescope(telescopes, telescope_id).await?;
-    Ok(Json(telescope.set_target(target).await))
+) -> Result<Result<Response, InvalidTarget>, TelescopeNotFound> {
+    let target = match target.validated() {
+        Ok(target) => target,
+        Err(error) => return Ok(Err(error)),
+    };
+    Ok(Ok(command_target(
+        state,
+        telescope_id,
+        requester,
+        target,
+        options,
+    )
+    .await?))
+}
+
+/// A target as typed by a user, e.g. `12:30:49.4` and `+12°23'28"`.
+#[derive(Deserialize, Debug, Clone)]
+struct TargetText {
+    system: CoordinateSystem,
+    /// Right ascension, galactic longitude or azimuth
+    longitude: String,
+    /// Declination, galactic latitude or elevation
+    latitude: String,
+}
+
+impl TargetText {
+    fn target(&self) -> Result<TelescopeTarget, AngleParseError> {
+        let longitude = match self.system {
+            CoordinateSystem::Equatorial => parse_right_ascension(&self.longitude)?,
+            CoordinateSystem::Galactic | CoordinateSystem::Horizontal => {
+                parse_degrees(&self.longitude)?
+            }
+        };
+        let latitude = parse_declination(&self.latitude)?;
+        Ok(self
+            .system
+            .target(longitude.to_radians().normalized(), latitude.to_radians()))
+    }
+}
+
+async fn set_target_from_text<StorageType: Storage>(
+    State(state): State<TelescopeState<StorageType>>,
+    Path(telescope_id): Path<String>,
+    Query(options): Query<SetTargetOptions>,
+    requester: Requester,
+    Json(text): Json<TargetText>,
+) -> Result<Result<Response, AngleParseError>, TelescopeNotFound> {
+    let target = match text.target() {
+        Ok(target) => target,
+        Err(error) => return Ok(Err(error)),
+    };
+    Ok(Ok(command_target(
+        state,
+        telescope_id,
+        requester,
+        target,
+        options,
+    )
+    .await?))
+}
+
+/// A satellite given by its TLE, or by its catalog number if it is among
+/// the satellites in the configuration.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+enum SatelliteText {
+    Tle { line1: String, line2: String },
+    CatalogNumber { catalog_number: u32 },
+}
+
+#[derive(Debug)]
+enum SatelliteError {
+    Tle(TleError),
+    NotConfigured(u32),
 }
 
-async fn restart(
+impl IntoResponse for SatelliteError {
+    fn into_response(self) -> Response {
+        match self {
+            SatelliteError::Tle(error) => (StatusCode::BAD_REQUEST, error.to_string()),
+            SatelliteError::NotConfigured(catalog_number) => (
+                StatusCode::NOT_FOUND,
+                format!("Satellite {} is not configured", catalog_number),
+            ),
+        }
+        .into_response()
+    }
+}
+
+async fn set_target_to_satellite<StorageType: Storage>(
+    State(state): State<TelescopeState<StorageType>>,
+    Path(telescope_id): Path<String>,
+    Query(options): Query<SetTargetOptions>,
+    requester: Requester,
+    Json(text): Json<SatelliteText>,
+) -> Result<Result<Response, SatelliteError>, TelescopeNotFound> {
+    let (line1, line2) = match text {
+        SatelliteText::Tle { line1, line2 } => (line1, line2),
+        SatelliteText::CatalogNumber { catalog_number } => {
+            let satellites = state
+                .database
+                .get_data()
+                .await
+                .expect(
+                    "As long as no one is manually editing the database, this should never fail.",
+                )
+                .satellites;
+            let satellite = satellites.into_iter().find(|s| {
+                parse_tle(&s.line1, &s.line2).is_ok_and(|e| e.catalog_number == catalog_number)
+            });
+            match satellite {
+                Some(satellite) => (satellite.line1, satellite.line2),
+                None => return Ok(Err(SatelliteError::NotConfigured(catalog_number))),
+            }
+        }
+    };
+    let elements = match parse_tle(&line1, &line2) {
+        Ok(elements) => elements,
+        Err(error) => return Ok(Err(SatelliteError::Tle(error))),
+    };
+    Ok(Ok(command_target(
+        state,
+        telescope_id,
+        requester,
+        TelescopeTarget::Satellite { elements },
+        options,
+    )
+    .await?))
+}
+
+/// Stream the decisions of the tracker over a websocket, one JSON message
+/// per tick, if tracker telemetry is enabled in the configuration.
+async fn get_health(
+    State(telescopes): State<TelescopeCollection>,
+    Path(telescope_id): Path<String>,
+) -> Result<Response, TelescopeNotFound> {
+    let health = extract_telescope(telescopes, telescope_id)
+        .await?
+        .connection_health();
+    let Some(health) = health else {
+        return Ok((
+            StatusCode::NOT_FOUND,
+            "Telescope has no connection to hardware",
+        )
+            .into_response());
+    };
+    Ok(Json(health).into_response())
+}
+
+async fn get_telemetry(
+    State(telescopes): State<TelescopeCollection>,
+    Path(telescope_id): Path<String>,
+    upgrade: WebSocketUpgrade,
+) -> Result<Response, TelescopeNotFound> {
+    let telemetry = extract_telescope(telescopes, telescope_id)
+        .await?
+        .tracker_telemetry();
+    let Some(telemetry) = telemetry else {
+        return Ok((
+            StatusCode::NOT_FOUND,
+            "Tracker telemetry is not enabled for this telescope",
+        )
+            .into_response());
+    };
+    let decisions = telemetry.subscribe();
+    Ok(upgrade.on_upgrade(move |socket| stream_telemetry(socket, decisions)))
+}
+
+async fn stream_telemetry(
+    mut socket: WebSocket,
+    mut decisions: broadcast::Receiver<TrackerDecision>,
+) {
+    loop {
+        let decision = match decisions.recv().await {
+            Ok(decision) => decision,
+            // A slow client just misses some ticks.
+            Err(broadcast::error::RecvError::Lagged(_)) => continue,
+            Err(broadcast::error::RecvError::Closed) => return,
+        };
+        let message = serde_json::to_string(&decision).expect("Decisions can always be serialized");
+        if socket.send(Message::Text(message)).await.is_err() {
+            // The client has gone away.
+            return;
+        }
+    }
+}
+
+/// Push the state of the telescope after each update, and new spectra, as
+/// JSON messages tagged with their `type`.
+async fn get_updates(
     State(telescopes): State<TelescopeCollection>,
     Path(telescope_id): Path<String>,
+    upgrade: WebSocketUpgrade,
+) -> Result<Response, TelescopeNotFound> {
+    let updates = telescopes
+        .read()
+        .await
+        .get(&telescope_id)
+        .ok_or(TelescopeNotFound)?
+        .updates
+        .subscribe();
+    Ok(upgrade.on_upgrade(move |socket| stream_updates(socket, updates)))
+}
+
+async fn stream_updates(mut socket: WebSocket, mut updates: broadcast::Receiver<TelescopeUpdate>) {
+    loop {
+        let update = match updates.recv().await {
+            Ok(update) => update,
+            // A slow client gets the next state instead.
+            Err(broadcast::error::RecvError::Lagged(_)) => continue,
+            Err(broadcast::error::RecvError::Closed) => return,
+        };
+        let message = serde_json::to_string(&update).expect("Updates can always be serialized");
+        if socket.send(Message::Text(message)).await.is_err() {
+            // The client has gone away.
+            return;
+        }
+    }
+}
+
+async fn restart<StorageType: Storage>(
+    State(state): State<TelescopeState<StorageType>>,
+    Path(telescope_id): Path<String>,
+    requester: Requester,
 ) -> Result<Json<Result<(), TelescopeError>>, TelescopeNotFound> {
+    let mut telescope = extract_telescope(state.telescopes, telescope_id.clone()).await?;
+    let result = telescope.restart().await;
+    record_command(
+        &state.database,
+        &telescope_id,
+        requester.0,
+        ObservationCommand::Restart,
+        &result,
+        Utc::now(),
+    )
+    .await;
+    Ok(Json(result))
+}
+
+#[derive(Clone)]
+pub struct TelescopeState<StorageType: Storage> {
+    pub telescopes: TelescopeCollection,
+    pub database: DataBase<StorageType>,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+struct ReceiverOptions {
+    /// Start integrating even if it repeats a measurement that just
+    /// finished.
+    #[serde(default)]
+    allow_duplicate: bool,
+}
+
+impl IntoResponse for DuplicateObservation {
+    fn into_response(self) -> Response {
+        (StatusCode::CONFLICT, Json(self)).into_response()
+    }
+}
+
+/// Starting an integration that repeats a recent measurement is refused
+/// with the measurement, which can be stacked with the new one at
+/// `/api/archive/stack` when it is done, unless `allow_duplicate` is set.
+async fn set_receiver_configuration<StorageType: Storage>(
+    State(state): State<TelescopeState<StorageType>>,
+    Path(telescope_id): Path<String>,
+    Query(options): Query<ReceiverOptions>,
+    requester: Requester,
+    Json(configuration): Json<ReceiverConfiguration>,
+) -> Result<Response, TelescopeNotFound> {
+    let mut telescope = extract_telescope(state.telescopes, telescope_id.clone()).await?;
+    let integrating = telescope.measurement_in_progress().await.is_some();
+    let starting = configuration.integrate && !integrating;
+    if starting && !options.allow_duplicate {
+        if let Ok(target) = telescope.get_target().await {
+            let data_model = state.database.get_data().await.expect(
+                "As long as no one is manually editing the database, this should never fail.",
+            );
+            if let Some(duplicate) = recent_duplicate(
+                &data_model,
+                &telescope_id,
+                target,
+                &configuration,
+                Utc::now(),
+            ) {
+                return Ok(duplicate.into_response());
+            }
+        }
+    }
+    let result = telescope.set_receiver_configuration(configuration).await;
+    if let Some(command) = ObservationCommand::receiver(integrating, &configuration) {
+        record_command(
+            &state.database,
+            &telescope_id,
+            requester.0,
+            command,
+            &result.as_ref().map_err(|error| format!("{:?}", error)),
+            Utc::now(),
+        )
+        .await;
+    }
+    Ok(Json(result).into_response())
+}
+
+async fn set_auxiliary_device(
+    State(telescopes): State<TelescopeCollection>,
+    Path((telescope_id, device_name)): Path<(String, String)>,
+    Json(on): Json<bool>,
+) -> Result<Json<Result<AuxiliaryDeviceState, TelescopeError>>, TelescopeNotFound> {
     let mut telescope = extract_telescope(telescopes, telescope_id).await?;
-    Ok(Json(telescope.restart().await))
+    Ok(Json(telescope.set_auxiliary_device(&device_name, on).await))
+}
+
+impl IntoResponse for AngleParseError {
+    fn into_response(self) -> Response {
+        (StatusCode::BAD_REQUEST, self.to_string()).into_response()
+    }
+}
+
+impl IntoResponse for InvalidTarget {
+    fn into_response(self) -> Response {
+        (StatusCode::UNPROCESSABLE_ENTITY, self.to_string()).into_response()
+    }
+}
+
+impl IntoResponse for PlotError {
+    fn into_response(self) -> Response {
+        let status_code = match self {
+            PlotError::EmptySpectrum => StatusCode::NOT_FOUND,
+            PlotError::InvalidSize(_, _) => StatusCode::BAD_REQUEST,
+            PlotError::DrawingError(_) | PlotError::EncodingError(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        };
+        (status_code, self.to_string()).into_response()
+    }
 }
 
-async fn set_receiver_configuration(
+async fn get_spectrum_plot(
     State(telescopes): State<TelescopeCollection>,
     Path(telescope_id): Path<String>,
-    Json(target): Json<ReceiverConfiguration>,
-) -> Result<Json<Result<ReceiverConfiguration, ReceiverError>>, TelescopeNotFound> {
-    let mut telescope = extract_telescope(telescopes, telescope_id).await?;
-    Ok(Json(telescope.set_receiver_configuration(target).await))
+    Query(mut options): Query<PlotOptions>,
+) -> Result<Response, Response> {
+    options.spectral_lines = builtin_spectral_lines();
+    let telescope = extract_telescope(telescopes, telescope_id)
+        .await
+        .map_err(IntoResponse::into_response)?;
+    let info = telescope
+        .get_info()
+        .await
+        .map_err(|err| (StatusCode::SERVICE_UNAVAILABLE, err.to_string()).into_response())?;
+    drop(telescope);
+    let spectrum = info
+        .latest_observation
+        .ok_or_else(|| PlotError::EmptySpectrum.into_response())?;
+    if let Some(line) = &options.line {
+        let axis = lsr_velocities(
+            &options.spectral_lines,
+            line,
+            &spectrum.frequencies,
+            info.current_target,
+            Utc::now(),
+        )
+        .map_err(IntoResponse::into_response)?;
+        options.velocities = Some(axis.velocities);
+    }
+
+    let plot = render_spectrum(&spectrum, &options).map_err(IntoResponse::into_response)?;
+    Ok((
+        [(header::CONTENT_TYPE, options.format().content_type())],
+        plot,
+    )
+        .into_response())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::angles::{Radians, RIGHT_ANGLE};
+    use crate::authentication::AuthenticatedUser;
+    use crate::database::create_in_memory_database;
+    use crate::interlock::CollisionInterlock;
+    use crate::supervisor::TelescopeSupervisor;
+    use crate::telescope::TelescopeContainer;
+    use crate::telescopes::TargetPreview;
+    use crate::users::{Role, User};
+    use axum::{
+        body::Body,
+        http::{self, Request},
+    };
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+    use std::collections::HashMap;
+    use std::f64::consts::PI;
+    use std::sync::Arc;
+    use tokio::sync::{Mutex, RwLock};
+    use tower::ServiceExt;
+
+    fn fake_telescopes() -> TelescopeCollection {
+        let supervisor = TelescopeSupervisor::new("fake");
+        let telescope = crate::fake_telescope::create(
+            "fake".to_string(),
+            None,
+            vec![],
+            supervisor.clone(),
+            CollisionInterlock::default(),
+        );
+        Arc::new(RwLock::new(HashMap::from([(
+            "fake".to_string(),
+            TelescopeContainer {
+                telescope: Arc::new(Mutex::new(telescope)),
+                supervisor,
+                updates: Default::default(),
+            },
+        )])))
+    }
+
+    /// A database with the admin anna, who may command the telescopes
+    /// without a booking.
+    async fn admin_database() -> DataBase<impl Storage> {
+        let db = create_in_memory_database();
+        db.update_data(|mut data_model| {
+            data_model.users.push(User {
+                role: Role::Admin,
+                ..User::new("anna")
+            });
+            data_model
+        })
+        .await
+        .unwrap();
+        db
+    }
+
+    async fn post_target(telescopes: &TelescopeCollection, body: String) -> Response {
+        routes(telescopes.clone(), admin_database().await)
+            .oneshot(
+                Request::builder()
+                    .method(http::Method::POST)
+                    .uri("/fake/target")
+                    .header(header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                    .extension(AuthenticatedUser("anna".to_string()))
+                    .body(Body::from(body))
+                    .unwrap(),
+            )
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_set_target_validation() {
+        let telescopes = fake_telescopes();
+
+        // Close to the celestial pole, so always above the horizon.
+        let target = TelescopeTarget::Equatorial {
+            ra: Radians(-1.0),
+            dec: Radians(1.5),
+        };
+        let response = post_target(&telescopes, serde_json::to_string(&target).unwrap()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let result: Result<TelescopeTarget, TelescopeError> =
+            serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            result,
+            Ok(TelescopeTarget::Equatorial {
+                ra: Radians(2.0 * PI - 1.0),
+                dec: Radians(1.5),
+            })
+        );
+
+        let horizontal = TelescopeTarget::Horizontal {
+            azimuth: Radians(-PI / 2.0),
+            elevation: Radians(0.5),
+        };
+        let response = post_target(&telescopes, serde_json::to_string(&horizontal).unwrap()).await;
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let result: Result<TelescopeTarget, TelescopeError> =
+            serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            result,
+            Ok(TelescopeTarget::Horizontal {
+                azimuth: Radians(1.5 * PI),
+                elevation: Radians(0.5),
+            })
+        );
+
+        let beyond_pole = TelescopeTarget::Galactic {
+            l: Radians(0.0),
+            b: Radians(2.0),
+        };
+        let response = post_target(&telescopes, serde_json::to_string(&beyond_pole).unwrap()).await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        // JSON has no NaN or infinity, so those are rejected as malformed.
+        let response = post_target(
+            &telescopes,
+            r#"{"Galactic": {"l": 1e999, "b": 0.0}}"#.to_string(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_set_target_dry_run() {
+        let telescopes = fake_telescopes();
+        let target = TelescopeTarget::Equatorial {
+            ra: Radians(1.0),
+            dec: Radians(1.5),
+        };
+        let response = routes(telescopes.clone(), admin_database().await)
+            .oneshot(
+                Request::builder()
+                    .method(http::Method::POST)
+                    .uri("/fake/target?dry_run=true")
+                    .header(header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                    .extension(AuthenticatedUser("anna".to_string()))
+                    .body(Body::from(serde_json::to_string(&target).unwrap()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let preview: TargetPreview = serde_json::from_slice(&body).unwrap();
+        assert_eq!(preview.target, target);
+        assert_eq!(preview.error, None);
+        let commanded = preview.commanded_horizontal.unwrap();
+        // The fake telescope starts out parked at the zenith.
+        let expected = (RIGHT_ANGLE - commanded.altitude).0
+            / crate::fake_telescope::FAKE_TELESCOPE_SLEWING_SPEED.0;
+        assert!(preview.slew_time.unwrap().as_secs_f64() >= expected - 1e-9);
+
+        // Nothing was commanded.
+        let telescope = telescopes.read().await["fake"].telescope.clone();
+        let telescope = telescope.lock().await;
+        assert_eq!(telescope.get_target().await, Ok(TelescopeTarget::Parked));
+    }
+
+    #[tokio::test]
+    async fn test_set_target_junk() {
+        let telescopes = fake_telescopes();
+        let valid = serde_json::to_string(&TelescopeTarget::Galactic {
+            l: Radians(1.0),
+            b: Radians(0.5),
+        })
+        .unwrap();
+        let alphabet = b"{}[]:,.-+eE0123456789\"GalacticEquatorialParkedlbradec nul";
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut bodies: Vec<String> = ["", "null", "[]", "{}", "\"Nowhere\"", "{\"Galactic\": {}}"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+        for _ in 0..200 {
+            let mut body = valid.clone().into_bytes();
+            for _ in 0..rng.gen_range(1..4) {
+                let index = rng.gen_range(0..body.len());
+                body[index] = alphabet[rng.gen_range(0..alphabet.len())];
+            }
+            bodies.push(String::from_utf8(body).unwrap());
+        }
+
+        for body in bodies {
+            let status = post_target(&telescopes, body.clone()).await.status();
+            assert!(
+                [
+                    StatusCode::OK,
+                    StatusCode::BAD_REQUEST,
+                    StatusCode::UNPROCESSABLE_ENTITY
+                ]
+                .contains(&status),
+                "{}: {}",
+                body,
+                status
+            );
+        }
+        let target = telescopes.read().await["fake"]
+            .telescope
+            .lock()
+            .await
+            .get_target()
+            .await;
+        if let Ok(
+            TelescopeTarget::Galactic { l, b } | TelescopeTarget::Equatorial { ra: l, dec: b },
+        ) = target
+        {
+            assert!((0.0..2.0 * PI).contains(&l.0));
+            assert!(b.abs() <= RIGHT_ANGLE);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_capabilities() {
+        let response = routes(fake_telescopes(), create_in_memory_database())
+            .oneshot(
+                Request::builder()
+                    .uri("/fake/capabilities")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let capabilities: TelescopeCapabilities = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            capabilities.channels,
+            crate::fake_telescope::FAKE_TELESCOPE_CHANNELS
+        );
+        assert_eq!(capabilities.reference_band, None);
+        assert!(capabilities.observed_band.min < capabilities.observed_band.max);
+    }
 }
@@ -1,4 +1,5 @@
 use crate::coords::Direction;
+use crate::rot2prog::{decode_direction, detect_encoding, encode_angle, Rot2ProgEncoding};
 use crate::telescopes::TelescopeError;
 use hex_literal::hex;
 use std::io::{Read, Write};
@@ -23,25 +24,69 @@ pub enum TelescopeResponse {
 pub struct TelescopeController {
     // FIXME: Do we need to be able to mock at this level?
     stream: TcpStream,
+    encoding: Rot2ProgEncoding,
 }
 
 impl TelescopeController {
-    pub fn connect(address: &str) -> Result<TelescopeController, TelescopeError> {
-        let stream = create_connection(address)?;
-        Ok(TelescopeController { stream })
+    /// Connect to the controller at `address`.
+    ///
+    /// Without a known `encoding` of responses it is detected by asking for
+    /// the current direction and checking which encoding makes sense of it.
+    pub fn connect(
+        address: &str,
+        encoding: Option<Rot2ProgEncoding>,
+    ) -> Result<TelescopeController, TelescopeError> {
+        let mut stream = create_connection(address)?;
+        let encoding = match encoding {
+            Some(encoding) => encoding,
+            None => detect_response_encoding(&mut stream)?,
+        };
+        Ok(TelescopeController { stream, encoding })
+    }
+
+    pub fn encoding(&self) -> Rot2ProgEncoding {
+        self.encoding
     }
 
     pub fn execute(
         &mut self,
         command: TelescopeCommand,
     ) -> Result<TelescopeResponse, TelescopeError> {
-        // FIXME: Handle connection failure.
-        self.stream.write(&command.to_bytes()).unwrap();
-        let mut result = vec![0; 128];
-        // FIXME: Handle connection failure.
-        let response_length = self.stream.read(&mut result).unwrap();
-        result.truncate(response_length);
-        command.parse_response(&result)
+        let response = request(&mut self.stream, &command.to_bytes())?;
+        command.parse_response(&response, self.encoding)
+    }
+
+    /// Send `bytes` to the controller as they are and return the response
+    /// without trying to make sense of it.
+    pub fn execute_raw(&mut self, bytes: &[u8]) -> Result<Vec<u8>, TelescopeError> {
+        request(&mut self.stream, bytes)
+    }
+}
+
+fn request(stream: &mut TcpStream, bytes: &[u8]) -> Result<Vec<u8>, TelescopeError> {
+    stream.write_all(bytes)?;
+    let mut response = vec![0; 128];
+    let response_length = stream.read(&mut response)?;
+    response.truncate(response_length);
+    Ok(response)
+}
+
+fn detect_response_encoding(stream: &mut TcpStream) -> Result<Rot2ProgEncoding, TelescopeError> {
+    let response = request(stream, &TelescopeCommand::GetDirection.to_bytes())?;
+    let encoding = if is_direction_response(&response) {
+        detect_encoding(&response[1..=5], &response[6..=10])
+    } else {
+        None
+    };
+    match encoding {
+        Some(encoding) => {
+            log::info!("Detected {:?} encoding of controller responses", encoding);
+            Ok(encoding)
+        }
+        None => Err(TelescopeError::TelescopeIOError(format!(
+            "Could not detect encoding of response to get direction command: {:?}",
+            response
+        ))),
     }
 }
 
@@ -54,20 +99,35 @@ impl TelescopeCommand {
             TelescopeCommand::SetDirection(direction) => {
                 let mut bytes = Vec::with_capacity(13);
                 bytes.extend(hex!("57"));
-                bytes.extend(rot2prog_angle_to_bytes(direction.azimuth).as_slice());
-                bytes.extend(rot2prog_angle_to_bytes(direction.altitude).as_slice());
+                // Commands are always ASCII encoded.
+                bytes.extend(encode_angle(
+                    direction.azimuth.to_degrees(),
+                    Rot2ProgEncoding::Ascii,
+                ));
+                bytes.extend(encode_angle(
+                    direction.altitude.to_degrees(),
+                    Rot2ProgEncoding::Ascii,
+                ));
                 bytes.extend(hex!("5F20"));
                 bytes
             }
         }
     }
 
-    fn parse_response(&self, bytes: &[u8]) -> Result<TelescopeResponse, TelescopeError> {
+    fn parse_response(
+        &self,
+        bytes: &[u8],
+        encoding: Rot2ProgEncoding,
+    ) -> Result<TelescopeResponse, TelescopeError> {
         match self {
             TelescopeCommand::Stop => parse_ack_response(bytes, "stop"),
             TelescopeCommand::Restart => parse_ack_response(bytes, "restart"),
-            TelescopeCommand::GetDirection => parse_direction_response(bytes, "get direction"),
-            TelescopeCommand::SetDirection(_) => parse_direction_response(bytes, "set direction"),
+            TelescopeCommand::GetDirection => {
+                parse_direction_response(bytes, encoding, "get direction")
+            }
+            TelescopeCommand::SetDirection(_) => {
+                parse_direction_response(bytes, encoding, "set direction")
+            }
         }
     }
 }
@@ -86,22 +146,29 @@ fn parse_ack_response(
     }
 }
 
+fn is_direction_response(bytes: &[u8]) -> bool {
+    bytes.len() == 12 && bytes[0] == 0x58 && bytes[11] == 0x20
+}
+
 fn parse_direction_response(
     bytes: &[u8],
+    encoding: Rot2ProgEncoding,
     command_name: &str,
 ) -> Result<TelescopeResponse, TelescopeError> {
-    if bytes.len() == 12 && bytes[0] == 0x58 && bytes[11] == 0x20 {
-        let azimuth = rot2prog_bytes_to_angle(&bytes[1..=5]);
-        let altitude = rot2prog_bytes_to_angle(&bytes[6..=10]);
-        Ok(TelescopeResponse::CurrentDirection(Direction {
-            azimuth,
-            altitude,
-        }))
+    let direction = if is_direction_response(bytes) {
+        decode_direction(&bytes[1..=5], &bytes[6..=10], encoding)
     } else {
-        Err(TelescopeError::TelescopeIOError(format!(
+        None
+    };
+    match direction {
+        Some((azimuth, altitude)) => Ok(TelescopeResponse::CurrentDirection(Direction {
+            azimuth: azimuth.to_radians(),
+            altitude: altitude.to_radians(),
+        })),
+        None => Err(TelescopeError::TelescopeIOError(format!(
             "Unexpected response to {} command: {:?}",
             command_name, bytes,
-        )))
+        ))),
     }
 }
 
@@ -115,33 +182,11 @@ fn create_connection(address: &str) -> Result<TcpStream, std::io::Error> {
     Ok(stream)
 }
 
-fn rot2prog_bytes_to_int(bytes: &[u8]) -> u32 {
-    bytes
-        .iter()
-        .rev()
-        .enumerate()
-        .map(|(pos, &digit)| digit as u32 * 10_u32.pow(pos as u32))
-        .sum()
-}
-
-fn rot2prog_bytes_to_angle(bytes: &[u8]) -> f64 {
-    (rot2prog_bytes_to_int(bytes) as f64 / 100.0 - 360.0).to_radians()
-}
-
-fn rot2prog_angle_to_bytes(angle: f64) -> [u8; 5] {
-    let mut bytes = [0; 5];
-    let angle = ((angle.to_degrees() + 360.0) * 100.0).round();
-    bytes[0] = (angle / 10000.0) as u8 + 0x30;
-    bytes[1] = ((angle % 10000.0) / 1000.0) as u8 + 0x30;
-    bytes[2] = ((angle % 1000.0) / 100.0) as u8 + 0x30;
-    bytes[3] = ((angle % 100.0) / 10.0) as u8 + 0x30;
-    bytes[4] = (angle % 10.0) as u8 + 0x30;
-    bytes
-}
-
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::angles::{Degrees, Radians};
+    use crate::rot2prog::fake_controller::FakeController;
 
     #[test]
     fn test_parse_ack_response() {
@@ -167,54 +212,68 @@ mod test {
 
     #[test]
     fn test_parse_direction_response() {
-        let res =
-            parse_direction_response(&hex!("58 03 06 00 00 00 03 06 00 00 00 20"), "test").unwrap();
+        let res = parse_direction_response(
+            &hex!("58 03 06 00 00 00 03 06 00 00 00 20"),
+            Rot2ProgEncoding::Raw,
+            "test",
+        )
+        .unwrap();
         assert_eq!(
             res,
             TelescopeResponse::CurrentDirection(Direction {
-                azimuth: 0.0,
-                altitude: 0.0,
+                azimuth: Radians(0.0),
+                altitude: Radians(0.0),
             })
         );
     }
-    #[test]
-    fn test_rot2prog_bytes_to_int() {
-        assert_eq!(rot2prog_bytes_to_int(&hex!("00")), 0);
-        assert_eq!(rot2prog_bytes_to_int(&hex!("01")), 1);
-        assert_eq!(rot2prog_bytes_to_int(&hex!("00 01")), 1);
-        assert_eq!(rot2prog_bytes_to_int(&hex!("01 02")), 12);
-        assert_eq!(rot2prog_bytes_to_int(&hex!("09 09 09")), 999);
-    }
+    fn test_encoding_end_to_end(encoding: Rot2ProgEncoding) {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let server = std::thread::spawn(move || {
+            let mut fake_controller = FakeController::new(encoding);
+            for _ in 0..2 {
+                fake_controller.serve(listener.accept().unwrap().0);
+            }
+        });
 
-    #[test]
-    fn test_rot2prog_angle_to_bytes() {
-        assert_eq!(
-            rot2prog_angle_to_bytes(0.0),
-            hex!("3336303030"),
-            "0.0 should be 0x3336303030 (telescope expects angle + 360)"
-        );
+        let mut controller = TelescopeController::connect(&address, None).unwrap();
+        assert_eq!(controller.encoding(), encoding);
+        let direction = Direction {
+            azimuth: Degrees(123.45).to_radians(),
+            altitude: Degrees(12.34).to_radians(),
+        };
+        controller
+            .execute(TelescopeCommand::SetDirection(direction))
+            .unwrap();
+        drop(controller);
+
+        // The detected encoding can be reused when connecting again.
+        let mut controller = TelescopeController::connect(&address, Some(encoding)).unwrap();
+        let current = match controller.execute(TelescopeCommand::GetDirection).unwrap() {
+            TelescopeResponse::CurrentDirection(current) => current,
+            response => panic!("unexpected response {:?}", response),
+        };
+        let precision = Degrees(0.01).to_radians();
+        assert!((current.azimuth - direction.azimuth).abs() < precision);
+        assert!((current.altitude - direction.altitude).abs() < precision);
+        // Raw commands are passed through, e.g. stop.
         assert_eq!(
-            rot2prog_angle_to_bytes(5.54_f64.to_radians()),
-            hex!("3336353534"),
-            "5.54 should be 0x3336353534 (example from documentation)"
+            controller
+                .execute_raw(&hex!("57000000000000000000000F20"))
+                .unwrap(),
+            hex!("570000000000000000000020")
         );
+        drop(controller);
+        server.join().unwrap();
     }
 
     #[test]
-    fn test_rot2prog_bytes_to_angle() {
-        assert!((rot2prog_bytes_to_angle(&hex!("0306000000")) - 0.0).abs() < 0.01,);
+    fn test_raw_encoding_end_to_end() {
+        test_encoding_end_to_end(Rot2ProgEncoding::Raw);
     }
 
-    // Responses are documented as ascii encoded numbers, but the telescope seems to return the
-    // bytes directly.
-    fn rot2prog_response_angle_to_bytes(angle: f64) -> [u8; 5] {
-        let mut bytes = [0; 5];
-        let angle = ((angle.to_degrees() + 360.0) * 100.0).round();
-        bytes[0] = (angle / 10000.0) as u8;
-        bytes[1] = ((angle % 10000.0) / 1000.0) as u8;
-        bytes[2] = ((angle % 1000.0) / 100.0) as u8;
-        bytes[3] = ((angle % 100.0) / 10.0) as u8;
-        bytes[4] = (angle % 10.0) as u8;
-        bytes
+    #[test]
+    fn test_ascii_encoding_end_to_end() {
+        test_encoding_end_to_end(Rot2ProgEncoding::Ascii);
     }
 }
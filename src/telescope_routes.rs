@@ -1,22 +1,54 @@
+use crate::authentication::Requester;
 use crate::coords::Direction;
+use crate::database::{DataBase, Storage};
+use crate::observation_log::{record_command, ObservationCommand};
+use crate::park_policies::enforce_quiet_hours;
+use crate::sessions::expiry::enforce_booking_end;
 use crate::telescope::{Telescope, TelescopeCollection};
+use crate::telescope_api_routes::TelescopeState;
+use crate::telescopes::{InvalidTarget, TelescopeError, TelescopeInfo, TelescopeTarget};
 use crate::telescopes::{ReceiverConfiguration, ReceiverError};
-use crate::telescopes::{TelescopeError, TelescopeInfo, TelescopeTarget};
+use crate::ups::refuse_on_battery;
 use axum::{
     extract::{Json, Path, State},
     http::StatusCode,
+    middleware,
     response::{IntoResponse, Response},
     routing::{get, post},
     Router,
 };
+use chrono::Utc;
 
-pub fn routes(telescopes: TelescopeCollection) -> Router {
+pub fn routes(
+    telescopes: TelescopeCollection,
+    database: DataBase<impl Storage + 'static>,
+) -> Router {
+    let state = TelescopeState {
+        telescopes: telescopes.clone(),
+        database: database.clone(),
+    };
     let telescope_routes = Router::new()
         .route("/", get(get_telescope))
         .route("/direction", get(get_direction))
-        .route("/target", get(get_target).post(set_target))
-        .route("/restart", post(restart))
-        .route("/receiver", post(set_receiver_configuration));
+        .route(
+            "/target",
+            get(get_target).post(set_target).with_state(state.clone()),
+        )
+        .route("/restart", post(restart).with_state(state.clone()))
+        .route(
+            "/receiver",
+            post(set_receiver_configuration).with_state(state),
+        );
+    let telescope_routes = telescope_routes
+        .route_layer(middleware::from_fn_with_state(
+            database.clone(),
+            enforce_quiet_hours,
+        ))
+        .route_layer(middleware::from_fn_with_state(
+            database.clone(),
+            enforce_booking_end,
+        ))
+        .route_layer(middleware::from_fn_with_state(database, refuse_on_battery));
     let router = Router::new()
         .route("/", get(get_telescopes))
         .nest("/:telescope_id", telescope_routes)
@@ -73,36 +105,77 @@ async fn get_direction(
     Ok(Json(telescope.get_direction().await))
 }
 
-async fn get_target(
-    State(telescopes): State<TelescopeCollection>,
+async fn get_target<StorageType: Storage>(
+    State(state): State<TelescopeState<StorageType>>,
     Path(telescope_id): Path<String>,
 ) -> Result<Json<Result<TelescopeTarget, TelescopeError>>, TelescopeNotFound> {
-    let telescope = extract_telescope(telescopes, telescope_id).await?;
+    let telescope = extract_telescope(state.telescopes, telescope_id).await?;
     Ok(Json(telescope.get_target().await))
 }
 
-async fn set_target(
-    State(telescopes): State<TelescopeCollection>,
+async fn set_target<StorageType: Storage>(
+    State(state): State<TelescopeState<StorageType>>,
     Path(telescope_id): Path<String>,
+    requester: Requester,
     Json(target): Json<TelescopeTarget>,
-) -> Result<Json<Result<TelescopeTarget, TelescopeError>>, TelescopeNotFound> {
-    let mut telescope = extract_telescope(telescopes, telescope_id).await?;
-    Ok(Json(telescope.set_target(target).await))
+) -> Result<Result<Json<Result<TelescopeTarget, TelescopeError>>, InvalidTarget>, TelescopeNotFound>
+{
+    let target = match target.validated() {
+        Ok(target) => target,
+        Err(error) => return Ok(Err(error)),
+    };
+    let mut telescope = extract_telescope(state.telescopes, telescope_id.clone()).await?;
+    let result = telescope.set_target(target).await;
+    record_command(
+        &state.database,
+        &telescope_id,
+        requester.0,
+        ObservationCommand::SetTarget(target),
+        &result,
+        Utc::now(),
+    )
+    .await;
+    Ok(Ok(Json(result)))
 }
 
-async fn restart(
-    State(telescopes): State<TelescopeCollection>,
+async fn restart<StorageType: Storage>(
+    State(state): State<TelescopeState<StorageType>>,
     Path(telescope_id): Path<String>,
+    requester: Requester,
 ) -> Result<Json<Result<(), TelescopeError>>, TelescopeNotFound> {
-    let mut telescope = extract_telescope(telescopes, telescope_id).await?;
-    Ok(Json(telescope.restart().await))
+    let mut telescope = extract_telescope(state.telescopes, telescope_id.clone()).await?;
+    let result = telescope.restart().await;
+    record_command(
+        &state.database,
+        &telescope_id,
+        requester.0,
+        ObservationCommand::Restart,
+        &result,
+        Utc::now(),
+    )
+    .await;
+    Ok(Json(result))
 }
 
-async fn set_receiver_configuration(
-    State(telescopes): State<TelescopeCollection>,
+async fn set_receiver_configuration<StorageType: Storage>(
+    State(state): State<TelescopeState<StorageType>>,
     Path(telescope_id): Path<String>,
+    requester: Requester,
     Json(target): Json<ReceiverConfiguration>,
 ) -> Result<Json<Result<ReceiverConfiguration, ReceiverError>>, TelescopeNotFound> {
-    let mut telescope = extract_telescope(telescopes, telescope_id).await?;
-    Ok(Json(telescope.set_receiver_configuration(target).await))
+    let mut telescope = extract_telescope(state.telescopes, telescope_id.clone()).await?;
+    let integrating = telescope.measurement_in_progress().await.is_some();
+    let result = telescope.set_receiver_configuration(target).await;
+    if let Some(command) = ObservationCommand::receiver(integrating, &target) {
+        record_command(
+            &state.database,
+            &telescope_id,
+            requester.0,
+            command,
+            &result.as_ref().map_err(|error| format!("{:?}", error)),
+            Utc::now(),
+        )
+        .await;
+    }
+    Ok(Json(result))
 }
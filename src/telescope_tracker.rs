@@ -1,13 +1,28 @@
+use crate::angles::{Degrees, Radians};
+use crate::command_throttle::{CommandCounters, CommandThrottle};
+use crate::connection_health::{ConnectionHealth, ConnectionMonitor};
 use crate::coords::{horizontal_from_equatorial, horizontal_from_galactic};
 use crate::coords::{Direction, Location};
+use crate::interlock::{CollisionInterlock, Sweep};
+use crate::latency::{LatencyMonitor, LatencyStatistics};
+use crate::pointing::PointingCorrection;
+use crate::rot2prog::Rot2ProgEncoding;
+use crate::supervisor::TelescopeSupervisor;
+use crate::telemetry::{TrackerAction, TrackerDecision, TrackerTelemetry};
 use crate::telescope_controller::{TelescopeCommand, TelescopeController, TelescopeResponse};
-use crate::telescopes::{TelescopeError, TelescopeStatus, TelescopeTarget};
+use crate::telescopes::{TargetPreview, TelescopeError, TelescopeStatus, TelescopeTarget};
 use chrono::{DateTime, Utc};
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
+use tokio::sync::oneshot;
 use tokio::time::{sleep_until, Instant};
 
-pub const LOWEST_ALLOWED_ALTITUDE: f64 = 5.0f64 / 180.0f64 * std::f64::consts::PI;
+pub const LOWEST_ALLOWED_ALTITUDE: Radians = Radians(5.0f64 / 180.0f64 * std::f64::consts::PI);
+// FIXME: How do we handle static configuration like this?
+const LOCATION: Location = Location {
+    longitude: Radians(0.20802143022), //(11.0+55.0/60.0+7.5/3600.0) * PI / 180.0. Sign positive, handled in gmst calc
+    latitude: Radians(1.00170457462),  //(57.0+23.0/60.0+36.4/3600.0) * PI / 180.0
+};
 
 pub struct TelescopeTrackerInfo {
     pub target: TelescopeTarget,
@@ -15,39 +30,143 @@ pub struct TelescopeTrackerInfo {
     pub current_horizontal: Direction,
     pub status: TelescopeStatus,
     pub most_recent_error: Option<TelescopeError>,
+    pub controller_latency: Option<LatencyStatistics>,
+    pub controller_commands: CommandCounters,
 }
 
+#[derive(Clone)]
 pub struct TelescopeTracker {
+    name: String,
     // FIXME: Do we need to lock the whole state at a time?
     state: Arc<Mutex<TelescopeTrackerState>>,
+    interlock: CollisionInterlock,
 }
 
 impl TelescopeTracker {
-    pub fn new(controller_address: String) -> TelescopeTracker {
+    pub fn new(
+        name: String,
+        controller_address: String,
+        response_encoding: Option<Rot2ProgEncoding>,
+        interlock: CollisionInterlock,
+        telemetry: Option<TrackerTelemetry>,
+        supervisor: &TelescopeSupervisor,
+    ) -> TelescopeTracker {
         let state = Arc::new(Mutex::new(TelescopeTrackerState {
             target: TelescopeTarget::Stopped,
             commanded_horizontal: None,
             current_direction: None,
             most_recent_error: None,
             should_restart: false,
+            pointing: PointingCorrection::default(),
+            raw_commands: Vec::new(),
+            stop_requests: Vec::new(),
+            latency: LatencyMonitor::default(),
+            connection: ConnectionMonitor::default(),
+            throttle: CommandThrottle::default(),
+            azimuth_offset: Radians(0.0),
         }));
-        // FIXME: Keep track of this task and do a proper shutdown.
-        tokio::spawn(tracker_task_function(state.clone(), controller_address));
-        TelescopeTracker { state }
+        let task_state = state.clone();
+        let task_name = name.clone();
+        let task_interlock = interlock.clone();
+        supervisor.spawn("tracker", move || {
+            tracker_task_function(
+                task_name.clone(),
+                task_state.clone(),
+                controller_address.clone(),
+                response_encoding,
+                task_interlock.clone(),
+                telemetry.clone(),
+            )
+        });
+        TelescopeTracker {
+            name,
+            state,
+            interlock,
+        }
     }
 
     pub fn set_target(
         &mut self,
         target: TelescopeTarget,
     ) -> Result<TelescopeTarget, TelescopeError> {
+        let (pointing, current) = {
+            let state = self.state.lock().unwrap();
+            (state.pointing, state.current_direction)
+        };
+        if let Some(target_horizontal) = calculate_target_horizontal(target, LOCATION, Utc::now()) {
+            let commanded = pointing.apply(target_horizontal);
+            let sweep = Sweep::between(current.unwrap_or(commanded), commanded);
+            self.interlock.check(&self.name, sweep)?;
+        }
         self.state.lock().unwrap().target = target;
         Ok(target)
     }
 
+    /// What `set_target` would do right now, for a telescope slewing each
+    /// axis at `slewing_speed`.
+    pub fn preview_target(&self, target: TelescopeTarget, slewing_speed: Radians) -> TargetPreview {
+        let (pointing, current) = {
+            let state = self.state.lock().unwrap();
+            (state.pointing, state.current_direction)
+        };
+        let commanded = calculate_target_horizontal(target, LOCATION, Utc::now())
+            .map(|horizontal| pointing.apply(horizontal));
+        preview_target(
+            &self.name,
+            target,
+            commanded,
+            current,
+            slewing_speed,
+            &self.interlock,
+        )
+    }
+
+    pub fn pointing_correction(&self) -> PointingCorrection {
+        self.state.lock().unwrap().pointing
+    }
+
+    pub fn set_pointing_correction(&self, correction: PointingCorrection) {
+        self.state.lock().unwrap().pointing = correction;
+    }
+
+    /// Point `offset` in azimuth away from the target, e.g. to measure the
+    /// reference of a position switched observation. Parked and stopped
+    /// telescopes are not moved.
+    pub fn set_azimuth_offset(&self, offset: Radians) {
+        self.state.lock().unwrap().azimuth_offset = offset;
+    }
+
     pub fn restart(&self) {
         self.state.lock().unwrap().should_restart = true;
     }
 
+    /// Queue `bytes` to be sent as they are to the controller. The tracker
+    /// task owns the connection, so the response arrives once it has got
+    /// around to sending them.
+    pub fn send_raw_command(
+        &self,
+        bytes: Vec<u8>,
+    ) -> oneshot::Receiver<Result<Vec<u8>, TelescopeError>> {
+        let (reply, response) = oneshot::channel();
+        self.state
+            .lock()
+            .unwrap()
+            .raw_commands
+            .push(RawCommand { bytes, reply });
+        response
+    }
+
+    /// Stop tracking and queue a stop command to the controller, which is
+    /// sent without going through the throttle. The response arrives once
+    /// the tracker task has sent it.
+    pub fn stop(&self) -> oneshot::Receiver<Result<(), TelescopeError>> {
+        let (reply, response) = oneshot::channel();
+        let mut state = self.state.lock().unwrap();
+        state.target = TelescopeTarget::Stopped;
+        state.stop_requests.push(reply);
+        response
+    }
+
     pub fn info(&self) -> Result<TelescopeTrackerInfo, TelescopeError> {
         let current_horizontal = match self.state.lock().unwrap().current_direction {
             Some(current_horizontal) => current_horizontal,
@@ -65,9 +184,14 @@ impl TelescopeTracker {
             }
             None => TelescopeStatus::Idle,
         };
-        let (target, most_recent_error) = {
+        let (target, most_recent_error, controller_latency, controller_commands) = {
             let lock = self.state.lock().unwrap();
-            (lock.target, lock.most_recent_error.clone())
+            (
+                lock.target,
+                lock.most_recent_error.clone(),
+                lock.latency.statistics(),
+                lock.throttle.counters(),
+            )
         };
         Ok(TelescopeTrackerInfo {
             target,
@@ -75,9 +199,15 @@ impl TelescopeTracker {
             commanded_horizontal,
             status,
             most_recent_error,
+            controller_latency,
+            controller_commands,
         })
     }
 
+    pub fn connection_health(&self) -> ConnectionHealth {
+        self.state.lock().unwrap().connection.health(Utc::now())
+    }
+
     pub fn direction(&self) -> Result<Direction, TelescopeError> {
         match self.state.lock().unwrap().current_direction {
             Some(current_direction) => Ok(current_direction),
@@ -100,11 +230,28 @@ struct TelescopeTrackerState {
     current_direction: Option<Direction>,
     most_recent_error: Option<TelescopeError>,
     should_restart: bool,
+    pointing: PointingCorrection,
+    raw_commands: Vec<RawCommand>,
+    /// Replies to requests to stop the mount right away.
+    stop_requests: Vec<oneshot::Sender<Result<(), TelescopeError>>>,
+    latency: LatencyMonitor,
+    connection: ConnectionMonitor,
+    throttle: CommandThrottle,
+    azimuth_offset: Radians,
+}
+
+struct RawCommand {
+    bytes: Vec<u8>,
+    reply: oneshot::Sender<Result<Vec<u8>, TelescopeError>>,
 }
 
 async fn tracker_task_function(
+    name: String,
     state: Arc<Mutex<TelescopeTrackerState>>,
     controller_address: String,
+    mut response_encoding: Option<Rot2ProgEncoding>,
+    interlock: CollisionInterlock,
+    telemetry: Option<TrackerTelemetry>,
 ) {
     let mut connection_established = false;
 
@@ -112,21 +259,42 @@ async fn tracker_task_function(
         // 10 Hz update freq
         sleep_until(Instant::now() + Duration::from_millis(100)).await;
 
-        let mut controller = match TelescopeController::connect(&controller_address) {
-            Ok(controller) => controller,
-            Err(err) => {
-                state.lock().unwrap().most_recent_error = Some(err);
-                continue;
-            }
-        };
+        let mut controller =
+            match TelescopeController::connect(&controller_address, response_encoding) {
+                Ok(controller) => {
+                    // Only detect the encoding once.
+                    response_encoding = Some(controller.encoding());
+                    controller
+                }
+                Err(err) => {
+                    let mut state_guard = state.lock().unwrap();
+                    state_guard.connection.record_connection_failure();
+                    state_guard.most_recent_error = Some(err);
+                    continue;
+                }
+            };
 
         if !connection_established {
             let mut state_guard = state.lock().unwrap();
             state_guard.most_recent_error = controller.execute(TelescopeCommand::Stop).err();
             state_guard.commanded_horizontal = None;
+            state_guard.throttle.reset();
             connection_established = true;
         }
 
+        let raw_commands = std::mem::take(&mut state.lock().unwrap().raw_commands);
+        for command in raw_commands {
+            // The requester may have given up waiting.
+            let _ = command.reply.send(controller.execute_raw(&command.bytes));
+        }
+
+        let stop_requests = std::mem::take(&mut state.lock().unwrap().stop_requests);
+        for reply in stop_requests {
+            let result = controller.execute(TelescopeCommand::Stop).map(|_| ());
+            state.lock().unwrap().commanded_horizontal = None;
+            let _ = reply.send(result);
+        }
+
         if state.lock().unwrap().should_restart {
             state.lock().unwrap().most_recent_error =
                 controller.execute(TelescopeCommand::Restart).err();
@@ -136,51 +304,152 @@ async fn tracker_task_function(
             continue;
         }
 
-        let res = update_direction(&mut state.lock().unwrap(), Utc::now(), &mut controller);
-        state.lock().unwrap().most_recent_error = res.err();
+        let res = update_direction(
+            &name,
+            &mut state.lock().unwrap(),
+            Utc::now(),
+            &mut controller,
+            &interlock,
+            telemetry.as_ref(),
+        );
+        let mut state_guard = state.lock().unwrap();
+        // Other errors come after the controller has answered.
+        let succeeded = !matches!(res, Err(TelescopeError::TelescopeIOError(_)));
+        state_guard.connection.record_command(succeeded, Utc::now());
+        state_guard.most_recent_error = res.err();
     }
 }
 
 fn update_direction(
+    name: &str,
     state: &mut TelescopeTrackerState,
     when: DateTime<Utc>,
     controller: &mut TelescopeController,
+    interlock: &CollisionInterlock,
+    telemetry: Option<&TrackerTelemetry>,
 ) -> Result<(), TelescopeError> {
-    // FIXME: How do we handle static configuration like this?
-    let location = Location {
-        longitude: 0.20802143022, //(11.0+55.0/60.0+7.5/3600.0) * PI / 180.0. Sign positive, handled in gmst calc
-        latitude: 1.00170457462,  //(57.0+23.0/60.0+36.4/3600.0) * PI / 180.0
-    };
-    let target_horizontal = calculate_target_horizontal(state.target, location, when);
-    let current_horizontal = match controller.execute(TelescopeCommand::GetDirection)? {
+    let target_horizontal = calculate_target_horizontal(state.target, LOCATION, when)
+        .map(|direction| offset_direction(state.target, direction, state.azimuth_offset))
+        .map(|direction| state.pointing.apply(direction));
+    let mut action = TrackerAction::Nothing;
+    let result = measure_and_command_direction(
+        name,
+        state,
+        target_horizontal,
+        controller,
+        interlock,
+        &mut action,
+    );
+    if let Some(telemetry) = telemetry {
+        telemetry.record(TrackerDecision::new(
+            name,
+            when,
+            state.target,
+            target_horizontal,
+            state.current_direction,
+            action,
+            result.clone().err(),
+        ));
+    }
+    result
+}
+
+fn offset_direction(target: TelescopeTarget, direction: Direction, offset: Radians) -> Direction {
+    match target {
+        TelescopeTarget::Parked | TelescopeTarget::Stopped => direction,
+        _ => Direction {
+            azimuth: Radians(direction.azimuth.0 + offset.0).normalized(),
+            ..direction
+        },
+    }
+}
+
+fn measure_and_command_direction(
+    name: &str,
+    state: &mut TelescopeTrackerState,
+    target_horizontal: Option<Direction>,
+    controller: &mut TelescopeController,
+    interlock: &CollisionInterlock,
+    action: &mut TrackerAction,
+) -> Result<(), TelescopeError> {
+    let request_start = Instant::now();
+    let response = controller.execute(TelescopeCommand::GetDirection)?;
+    let latency = request_start.elapsed();
+    if state.latency.record(Instant::now(), latency) {
+        if let Some(statistics) = state.latency.statistics() {
+            log::warn!(
+                "Link to the controller of {} is slow, 95% of direction requests take up to {} ms",
+                name,
+                statistics.p95.as_millis()
+            );
+        }
+    }
+    let current_horizontal = match response {
         TelescopeResponse::CurrentDirection(direction) => Ok(direction),
         _ => Err(TelescopeError::TelescopeIOError(
             "Telescope did not respond with current direction".to_string(),
         )),
     }?;
     state.current_direction = Some(current_horizontal);
+    let result = command_direction(
+        name,
+        state,
+        target_horizontal,
+        controller,
+        interlock,
+        action,
+    );
+    interlock.update(name, current_horizontal, state.commanded_horizontal);
+    result
+}
 
+fn command_direction(
+    name: &str,
+    state: &mut TelescopeTrackerState,
+    target_horizontal: Option<Direction>,
+    controller: &mut TelescopeController,
+    interlock: &CollisionInterlock,
+    action: &mut TrackerAction,
+) -> Result<(), TelescopeError> {
+    let current_horizontal = state
+        .current_direction
+        .ok_or(TelescopeError::TelescopeNotConnected)?;
     match target_horizontal {
         Some(target_horizontal) => {
-            // FIXME: How to handle static configuration like this?
-            if target_horizontal.altitude < LOWEST_ALLOWED_ALTITUDE {
-                state.most_recent_error = Some(TelescopeError::TargetBelowHorizon);
-                state.commanded_horizontal = None;
-                return Err(TelescopeError::TargetBelowHorizon);
+            match reserve_target_direction(name, current_horizontal, target_horizontal, interlock) {
+                Ok(()) => {}
+                Err(TelescopeError::TargetBelowHorizon) => {
+                    state.most_recent_error = Some(TelescopeError::TargetBelowHorizon);
+                    state.commanded_horizontal = None;
+                    return Err(TelescopeError::TargetBelowHorizon);
+                }
+                Err(error) => {
+                    // Hold position until the other telescope has left the zone.
+                    if state.commanded_horizontal.is_some() {
+                        send_motion(state, controller, TelescopeCommand::Stop, action)?;
+                        state.commanded_horizontal = None;
+                    }
+                    return Err(error);
+                }
             }
 
             state.commanded_horizontal = Some(target_horizontal);
 
             // Check if more than 1 tolerance off, if so we need to send track command
             if !directions_are_close(target_horizontal, current_horizontal, 1.0) {
-                controller.execute(TelescopeCommand::SetDirection(target_horizontal))?;
+                send_motion(
+                    state,
+                    controller,
+                    TelescopeCommand::SetDirection(target_horizontal),
+                    action,
+                )?;
             }
 
             Ok(())
         }
         None => {
             if state.commanded_horizontal.is_some() {
-                controller.execute(TelescopeCommand::Stop)?;
+                send_motion(state, controller, TelescopeCommand::Stop, action)?;
                 state.commanded_horizontal = None;
             }
             Ok(())
@@ -188,6 +457,91 @@ fn update_direction(
     }
 }
 
+/// Send a motion command unless the throttle coalesces or holds it back,
+/// in which case the tracker tries again on a later tick.
+fn send_motion(
+    state: &mut TelescopeTrackerState,
+    controller: &mut TelescopeController,
+    command: TelescopeCommand,
+    action: &mut TrackerAction,
+) -> Result<(), TelescopeError> {
+    if !state.throttle.admit(command, Instant::now()) {
+        return Ok(());
+    }
+    *action = match command {
+        TelescopeCommand::SetDirection(direction) => TrackerAction::SetDirection(direction),
+        _ => TrackerAction::Stop,
+    };
+    controller.execute(command)?;
+    Ok(())
+}
+
+/// Check that telescope `name` may be commanded to `horizontal`, which
+/// already has the pointing correction applied.
+/// Check that telescope `name`, pointing at `current`, may slew to
+/// `horizontal`, without reserving the slew with the interlock.
+pub fn check_target_direction(
+    name: &str,
+    current: Direction,
+    horizontal: Direction,
+    interlock: &CollisionInterlock,
+) -> Result<(), TelescopeError> {
+    check_above_horizon(horizontal)?;
+    interlock.check(name, Sweep::between(current, horizontal))
+}
+
+/// Like [check_target_direction], but also reserves the slew with the
+/// interlock, so no other telescope is let into the way of it.
+pub fn reserve_target_direction(
+    name: &str,
+    current: Direction,
+    horizontal: Direction,
+    interlock: &CollisionInterlock,
+) -> Result<(), TelescopeError> {
+    check_above_horizon(horizontal)?;
+    interlock.reserve(name, Sweep::between(current, horizontal))
+}
+
+fn check_above_horizon(horizontal: Direction) -> Result<(), TelescopeError> {
+    // FIXME: How to handle static configuration like this?
+    if horizontal.altitude < LOWEST_ALLOWED_ALTITUDE {
+        return Err(TelescopeError::TargetBelowHorizon);
+    }
+    Ok(())
+}
+
+/// Preview of commanding telescope `name` to `commanded` when it is pointing
+/// at `current`, without sending anything to it.
+pub fn preview_target(
+    name: &str,
+    target: TelescopeTarget,
+    commanded: Option<Direction>,
+    current: Option<Direction>,
+    slewing_speed: Radians,
+    interlock: &CollisionInterlock,
+) -> TargetPreview {
+    TargetPreview {
+        target,
+        commanded_horizontal: commanded,
+        error: commanded.and_then(|commanded| {
+            let from = current.unwrap_or(commanded);
+            check_target_direction(name, from, commanded, interlock).err()
+        }),
+        slew_time: commanded
+            .zip(current)
+            .map(|(commanded, current)| estimate_slew_time(current, commanded, slewing_speed)),
+    }
+}
+
+/// Time to slew from `from` to `to`, with both axes moving at `slewing_speed`
+/// at the same time. The azimuth axis does not wrap around, so it may have to
+/// go the long way.
+pub fn estimate_slew_time(from: Direction, to: Direction, slewing_speed: Radians) -> Duration {
+    let azimuth = (to.azimuth - from.azimuth).abs();
+    let altitude = (to.altitude - from.altitude).abs();
+    Duration::from_secs_f64(azimuth.0.max(altitude.0) / slewing_speed.0)
+}
+
 fn calculate_target_horizontal(
     target: TelescopeTarget,
     location: Location,
@@ -198,6 +552,11 @@ fn calculate_target_horizontal(
             Some(horizontal_from_equatorial(location, when, ra, dec))
         }
         TelescopeTarget::Galactic { l, b } => Some(horizontal_from_galactic(location, when, l, b)),
+        TelescopeTarget::Horizontal { azimuth, elevation } => Some(Direction {
+            azimuth,
+            altitude: elevation,
+        }),
+        TelescopeTarget::Satellite { elements } => Some(elements.horizontal(location, when)),
         TelescopeTarget::Stopped => None,
         TelescopeTarget::Parked => None,
     }
@@ -209,6 +568,6 @@ fn directions_are_close(a: Direction, b: Direction, tol: f64) -> bool {
     // but to report tracking status we allow more, so that we do not flip
     // status between tracking/slewing (e.g. due to control unit rounding errors)
     // Therefore we have the "tol" multiplier here, which scales the allowed error.
-    let epsilon = tol * 0.1_f64.to_radians();
+    let epsilon = Degrees(0.1).to_radians() * tol;
     (a.azimuth - b.azimuth).abs() < epsilon && (a.altitude - b.altitude).abs() < epsilon
 }
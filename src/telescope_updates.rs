@@ -0,0 +1,189 @@
+//! Live state of a telescope pushed to websocket clients.
+//!
+//! After each update the telescope service publishes the status, where the
+//! telescope points and how the integration is going, and the spectrum
+//! whenever it has changed, so that clients do not need to poll `get_info`.
+
+use crate::angles::{Radians, FULL_CIRCLE};
+use crate::coords::Direction;
+use crate::telescopes::{
+    Measurement, ObservedSpectra, TelescopeError, TelescopeInfo, TelescopeStatus, TelescopeTarget,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+/// Updates buffered for each client. A client that falls further behind
+/// misses updates, which is fine as each state replaces the previous one.
+const CHANNEL_CAPACITY: usize = 16;
+
+/// Commanded minus current direction, with the azimuth difference the
+/// short way around.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+pub struct TrackingError {
+    pub azimuth: Radians,
+    pub altitude: Radians,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct IntegrationProgress {
+    pub start: DateTime<Utc>,
+    /// Time covered by the samples received so far.
+    pub integration_time: Duration,
+    /// Time left of a fixed integration, None if it runs until stopped.
+    pub remaining: Option<Duration>,
+    pub dropped_samples: u64,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct TelescopeState {
+    pub status: TelescopeStatus,
+    pub current_horizontal: Direction,
+    pub commanded_horizontal: Option<Direction>,
+    pub tracking_error: Option<TrackingError>,
+    pub current_target: TelescopeTarget,
+    pub most_recent_error: Option<TelescopeError>,
+    pub integration: Option<IntegrationProgress>,
+}
+
+/// A message on the websocket, tagged with its `type`.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(tag = "type", content = "data")]
+pub enum TelescopeUpdate {
+    State(TelescopeState),
+    Spectrum(ObservedSpectra),
+}
+
+fn tracking_error(commanded: Direction, current: Direction) -> TrackingError {
+    let azimuth = (commanded.azimuth.0 - current.azimuth.0 + FULL_CIRCLE.0 / 2.0)
+        .rem_euclid(FULL_CIRCLE.0)
+        - FULL_CIRCLE.0 / 2.0;
+    TrackingError {
+        azimuth: Radians(azimuth),
+        altitude: Radians(commanded.altitude.0 - current.altitude.0),
+    }
+}
+
+impl TelescopeState {
+    pub fn new(info: &TelescopeInfo, measurement: Option<&Measurement>) -> TelescopeState {
+        TelescopeState {
+            status: info.status,
+            current_horizontal: info.current_horizontal,
+            commanded_horizontal: info.commanded_horizontal,
+            tracking_error: info
+                .commanded_horizontal
+                .map(|commanded| tracking_error(commanded, info.current_horizontal)),
+            current_target: info.current_target,
+            most_recent_error: info.most_recent_error.clone(),
+            integration: measurement.map(|measurement| IntegrationProgress {
+                start: measurement.start,
+                integration_time: measurement.integration_time,
+                remaining: measurement
+                    .receiver_configuration
+                    .and_then(|configuration| {
+                        configuration
+                            .mode
+                            .remaining(measurement.requested_integration_time)
+                    }),
+                dropped_samples: measurement.dropped_samples,
+            }),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct TelescopeUpdates {
+    sender: broadcast::Sender<TelescopeUpdate>,
+}
+
+impl Default for TelescopeUpdates {
+    fn default() -> Self {
+        let (sender, _) = broadcast::channel(CHANNEL_CAPACITY);
+        TelescopeUpdates { sender }
+    }
+}
+
+impl TelescopeUpdates {
+    /// Publish the state after an update of the telescope, and the latest
+    /// spectrum if it differs from `previous_spectrum`, which is then
+    /// replaced.
+    pub fn publish(
+        &self,
+        info: &TelescopeInfo,
+        measurement: Option<&Measurement>,
+        previous_spectrum: &mut Option<ObservedSpectra>,
+    ) {
+        // Nobody listening is not an error.
+        let _ = self.sender.send(TelescopeUpdate::State(TelescopeState::new(
+            info,
+            measurement,
+        )));
+        if info.latest_observation != *previous_spectrum {
+            previous_spectrum.clone_from(&info.latest_observation);
+            if let Some(spectrum) = &info.latest_observation {
+                let _ = self
+                    .sender
+                    .send(TelescopeUpdate::Spectrum(spectrum.clone()));
+            }
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<TelescopeUpdate> {
+        self.sender.subscribe()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::angles::Degrees;
+
+    #[test]
+    fn test_publish_state_and_changed_spectrum() {
+        let direction = |azimuth, altitude| Direction {
+            azimuth: Degrees(azimuth).to_radians(),
+            altitude: Degrees(altitude).to_radians(),
+        };
+        let spectrum = ObservedSpectra {
+            frequencies: vec![1.42e9],
+            spectra: vec![1.0],
+            observation_time: Duration::from_secs(1),
+            cycles: 1,
+        };
+        let info = TelescopeInfo {
+            id: "fake".to_string(),
+            status: TelescopeStatus::Tracking,
+            commanded_horizontal: Some(direction(1.0, 30.0)),
+            current_horizontal: direction(359.0, 29.5),
+            current_target: TelescopeTarget::Stopped,
+            most_recent_error: None,
+            measurement_in_progress: false,
+            latest_observation: Some(spectrum.clone()),
+            auxiliary_devices: vec![],
+            tasks: vec![],
+            controller_latency: None,
+            controller_commands: None,
+        };
+        let updates = TelescopeUpdates::default();
+        let mut client = updates.subscribe();
+        let mut previous_spectrum = None;
+        updates.publish(&info, None, &mut previous_spectrum);
+        updates.publish(&info, None, &mut previous_spectrum);
+
+        let Ok(TelescopeUpdate::State(state)) = client.try_recv() else {
+            panic!("Expected the state first");
+        };
+        let error = state.tracking_error.unwrap();
+        assert!((error.azimuth.to_degrees().0 - 2.0).abs() < 1e-9);
+        assert!((error.altitude.to_degrees().0 - 0.5).abs() < 1e-9);
+        assert_eq!(
+            client.try_recv(),
+            Ok(TelescopeUpdate::Spectrum(spectrum.clone()))
+        );
+        // The unchanged spectrum is not sent again.
+        assert!(matches!(client.try_recv(), Ok(TelescopeUpdate::State(_))));
+        assert!(client.try_recv().is_err());
+        assert_eq!(previous_spectrum, Some(spectrum));
+    }
+}
@@ -1,23 +1,187 @@
-use crate::coords::{Direction, Location};
+use crate::angles::{Degrees, Radians, RIGHT_ANGLE};
+use crate::auxiliary::{AuxiliaryDeviceDefinition, AuxiliaryDeviceState};
+use crate::command_throttle::CommandCounters;
+use crate::coords::{
+    horizontal_from_equatorial, horizontal_from_galactic, vlsrcorr_from_equatorial,
+    vlsrcorr_from_galactic, Direction, Location,
+};
+use crate::latency::LatencyStatistics;
+use crate::orbit::OrbitalElements;
+use crate::park_policies::ParkPolicy;
+use crate::pointing::PointingModel;
+use crate::rfi::{flagging::RfiFlagging, RfiScanDefinition};
+use crate::rot2prog::Rot2ProgEncoding;
+use crate::supervisor::TaskHealth;
 use chrono::{offset::Utc, DateTime};
 use serde::{Deserialize, Serialize};
+use std::f64::consts::PI;
 use std::fmt::{Display, Formatter};
 use std::time::Duration;
 
 #[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
 pub enum TelescopeTarget {
     Equatorial {
-        ra: f64,  // in radians
-        dec: f64, // in radians
+        ra: Radians,
+        dec: Radians,
     },
     Galactic {
-        l: f64, // in radians
-        b: f64, // in radians
+        l: Radians,
+        b: Radians,
+    },
+    /// A fixed direction as seen from the telescope.
+    Horizontal {
+        azimuth: Radians,
+        elevation: Radians,
+    },
+    /// A satellite, followed along its orbit.
+    Satellite {
+        elements: OrbitalElements,
     },
     Parked,
     Stopped,
 }
 
+impl Display for TelescopeTarget {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TelescopeTarget::Galactic { l, b } => write!(
+                f,
+                "galactic l={:.1} b={:.1}",
+                l.normalized().to_degrees().0,
+                b.to_degrees().0
+            ),
+            TelescopeTarget::Equatorial { ra, dec } => write!(
+                f,
+                "equatorial ra={:.1} dec={:.1}",
+                ra.normalized().to_degrees().0,
+                dec.to_degrees().0
+            ),
+            TelescopeTarget::Horizontal { azimuth, elevation } => write!(
+                f,
+                "horizontal az={:.1} el={:.1}",
+                azimuth.normalized().to_degrees().0,
+                elevation.to_degrees().0
+            ),
+            TelescopeTarget::Satellite { elements } => {
+                write!(f, "satellite {}", elements.catalog_number)
+            }
+            TelescopeTarget::Parked => write!(f, "parked"),
+            TelescopeTarget::Stopped => write!(f, "stopped"),
+        }
+    }
+}
+
+/// Reason for rejecting a target given by a client.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+pub enum InvalidTarget {
+    NotFinite,
+    LatitudeOutOfRange(Radians),
+}
+
+impl Display for InvalidTarget {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InvalidTarget::NotFinite => f.write_str("Target coordinates must be finite numbers."),
+            InvalidTarget::LatitudeOutOfRange(latitude) => f.write_str(&format!(
+                "Target latitude {:.3} rad is beyond a pole, it must be within ±{:.3} rad.",
+                latitude.0, RIGHT_ANGLE.0
+            )),
+        }
+    }
+}
+
+impl TelescopeTarget {
+    /// The target with its longitude normalized to [0, 2π), or an error if
+    /// the coordinates cannot be tracked.
+    pub fn validated(self) -> Result<TelescopeTarget, InvalidTarget> {
+        let check = |longitude: Radians, latitude: Radians| {
+            if !longitude.0.is_finite() || !latitude.0.is_finite() {
+                return Err(InvalidTarget::NotFinite);
+            }
+            if latitude.abs() > RIGHT_ANGLE {
+                return Err(InvalidTarget::LatitudeOutOfRange(latitude));
+            }
+            Ok(longitude.normalized())
+        };
+        Ok(match self {
+            TelescopeTarget::Equatorial { ra, dec } => TelescopeTarget::Equatorial {
+                ra: check(ra, dec)?,
+                dec,
+            },
+            TelescopeTarget::Galactic { l, b } => TelescopeTarget::Galactic { l: check(l, b)?, b },
+            TelescopeTarget::Horizontal { azimuth, elevation } => TelescopeTarget::Horizontal {
+                azimuth: check(azimuth, elevation)?,
+                elevation,
+            },
+            TelescopeTarget::Satellite { .. }
+            | TelescopeTarget::Parked
+            | TelescopeTarget::Stopped => self,
+        })
+    }
+
+    /// Where the target is seen from `location` at `when`, None if it is not
+    /// a position on the sky.
+    pub fn horizontal(self, location: Location, when: DateTime<Utc>) -> Option<Direction> {
+        match self {
+            TelescopeTarget::Equatorial { ra, dec } => {
+                Some(horizontal_from_equatorial(location, when, ra, dec))
+            }
+            TelescopeTarget::Galactic { l, b } => {
+                Some(horizontal_from_galactic(location, when, l, b))
+            }
+            TelescopeTarget::Horizontal { azimuth, elevation } => Some(Direction {
+                azimuth,
+                altitude: elevation,
+            }),
+            TelescopeTarget::Satellite { elements } => Some(elements.horizontal(location, when)),
+            TelescopeTarget::Parked | TelescopeTarget::Stopped => None,
+        }
+    }
+
+    /// Correction in m/s from the topocentric frame to the LSR when
+    /// observing the target at `when`, None if it is not a fixed position on
+    /// the sky.
+    pub fn lsr_correction(self, when: DateTime<Utc>) -> Option<f64> {
+        match self {
+            TelescopeTarget::Equatorial { ra, dec } => {
+                Some(vlsrcorr_from_equatorial(ra, dec, when))
+            }
+            TelescopeTarget::Galactic { l, b } => Some(vlsrcorr_from_galactic(l, b, when)),
+            TelescopeTarget::Horizontal { .. }
+            | TelescopeTarget::Satellite { .. }
+            | TelescopeTarget::Parked
+            | TelescopeTarget::Stopped => None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+#[serde(rename_all = "lowercase")]
+pub enum CoordinateSystem {
+    Equatorial,
+    Galactic,
+    Horizontal,
+}
+
+impl CoordinateSystem {
+    pub fn target(self, longitude: Radians, latitude: Radians) -> TelescopeTarget {
+        match self {
+            CoordinateSystem::Equatorial => TelescopeTarget::Equatorial {
+                ra: longitude,
+                dec: latitude,
+            },
+            CoordinateSystem::Galactic => TelescopeTarget::Galactic {
+                l: longitude,
+                b: latitude,
+            },
+            CoordinateSystem::Horizontal => TelescopeTarget::Horizontal {
+                azimuth: longitude,
+                elevation: latitude,
+            },
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
 pub enum TelescopeStatus {
     Idle,
@@ -30,6 +194,9 @@ pub struct ObservedSpectra {
     pub frequencies: Vec<f64>,
     pub spectra: Vec<f64>,
     pub observation_time: Duration,
+    /// Cycles averaged into the spectra, which grows while integrating.
+    #[serde(default)]
+    pub cycles: u64,
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
@@ -42,12 +209,78 @@ pub struct TelescopeInfo {
     pub most_recent_error: Option<TelescopeError>,
     pub measurement_in_progress: bool,
     pub latest_observation: Option<ObservedSpectra>,
+    #[serde(default)]
+    pub auxiliary_devices: Vec<AuxiliaryDeviceState>,
+    #[serde(default)]
+    pub tasks: Vec<TaskHealth>,
+    /// Round trip latency of requests to the controller, for telescopes
+    /// with one.
+    #[serde(default)]
+    pub controller_latency: Option<LatencyStatistics>,
+    /// Motion commands sent to, or kept from, the controller.
+    #[serde(default)]
+    pub controller_commands: Option<CommandCounters>,
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub struct SalsaTelescopeDefinition {
     pub controller_address: String,
     pub receiver_address: String,
+    /// Telescopes without a switchable noise diode use a fixed system
+    /// temperature instead.
+    #[serde(default)]
+    pub noise_diode: Option<NoiseDiodeDefinition>,
+    /// Encoding of angles in responses from the controller, detected when
+    /// connecting if not given.
+    #[serde(default)]
+    pub response_encoding: Option<Rot2ProgEncoding>,
+    /// Polarization received at `receiver_address`.
+    #[serde(default)]
+    pub polarization: Polarization,
+    /// Receivers of the other polarizations of a dual polarization feed.
+    /// The USRPs have a single channel, so each needs its own.
+    #[serde(default)]
+    pub polarization_receivers: Vec<PolarizationReceiver>,
+    /// Keep the spectrum of each polarization, and not only their average.
+    #[serde(default)]
+    pub record_polarizations: bool,
+    /// Frequencies always flagged as interference, e.g. known transmitters
+    /// near the site.
+    #[serde(default)]
+    pub rfi_mask: Vec<FrequencyRange>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone, Default, Eq, Hash)]
+pub enum Polarization {
+    #[default]
+    X,
+    Y,
+    LeftCircular,
+    RightCircular,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct PolarizationReceiver {
+    pub address: String,
+    pub polarization: Polarization,
+}
+
+/// The spectrum of one polarization of a measurement.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct PolarizationSpectrum {
+    pub polarization: Polarization,
+    pub amps: Vec<f64>,
+}
+
+/// A noise diode switched on and off by sending commands over TCP, e.g. to
+/// a relay controller.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct NoiseDiodeDefinition {
+    pub address: String,
+    pub on_command: String,
+    pub off_command: String,
+    /// Equivalent noise temperature of the diode in K.
+    pub temperature: f64,
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
@@ -70,8 +303,16 @@ pub struct TelescopeDefinition {
     pub name: String,
     pub enabled: bool,
     pub location: Location,
-    pub min_altitude: f64,
+    pub min_altitude: Radians,
     pub telescope_type: TelescopeType,
+    #[serde(default)]
+    pub rfi_scan: Option<RfiScanDefinition>,
+    #[serde(default)]
+    pub auxiliary_devices: Vec<AuxiliaryDeviceDefinition>,
+    #[serde(default)]
+    pub park_policies: Vec<ParkPolicy>,
+    #[serde(default)]
+    pub pointing_model: PointingModel,
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
@@ -79,11 +320,20 @@ pub enum TelescopeError {
     TargetBelowHorizon,
     TelescopeIOError(String),
     TelescopeNotConnected,
+    UnknownAuxiliaryDevice(String),
+    /// Moving would risk a collision with the named telescope.
+    CollisionRisk(String),
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
 pub enum ReceiverError {
     IntegrationAlreadyRunning,
+    InvalidZoom,
+    /// The receiver can't be set up with the requested value.
+    UnsupportedSetting(ReceiverParameter),
+    AuxiliaryDeviceOff,
+    /// The setting can't be changed without restarting the integration.
+    ChangedDuringIntegration(ReceiverParameter),
 }
 
 impl Display for TelescopeError {
@@ -97,6 +347,13 @@ impl Display for TelescopeError {
                 message
             )),
             TelescopeError::TelescopeNotConnected => f.write_str("Telescope is not connected."),
+            TelescopeError::UnknownAuxiliaryDevice(name) => {
+                f.write_str(&format!("Unknown auxiliary device {}.", name))
+            }
+            TelescopeError::CollisionRisk(other) => f.write_str(&format!(
+                "Holding position, moving could collide with {}.",
+                other
+            )),
         }
     }
 }
@@ -107,22 +364,544 @@ impl From<std::io::Error> for TelescopeError {
     }
 }
 
+/// Window applied to the samples before each FFT in the receiver.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone, Default)]
+pub enum WindowFunction {
+    #[default]
+    Rectangular,
+    Hann,
+    Hamming,
+    BlackmanHarris,
+}
+
+impl WindowFunction {
+    pub const ALL: [WindowFunction; 4] = [
+        WindowFunction::Rectangular,
+        WindowFunction::Hann,
+        WindowFunction::Hamming,
+        WindowFunction::BlackmanHarris,
+    ];
+
+    pub fn coefficients(&self, length: usize) -> Vec<f64> {
+        let phase = |n: usize| 2.0 * PI * n as f64 / length as f64;
+        (0..length)
+            .map(|n| match self {
+                WindowFunction::Rectangular => 1.0,
+                WindowFunction::Hann => 0.5 - 0.5 * phase(n).cos(),
+                WindowFunction::Hamming => 0.54 - 0.46 * phase(n).cos(),
+                WindowFunction::BlackmanHarris => {
+                    0.35875 - 0.48829 * phase(n).cos() + 0.14128 * (2.0 * phase(n)).cos()
+                        - 0.01168 * (3.0 * phase(n)).cos()
+                }
+            })
+            .collect()
+    }
+}
+
+/// Digitally zoom in on a narrow part of the received band for higher
+/// spectral resolution.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+pub struct ZoomConfiguration {
+    /// Width of the zoomed band in Hz.
+    pub bandwidth: f64,
+    /// Offset in Hz of the center of the zoomed band from the observed
+    /// frequency.
+    #[serde(default)]
+    pub offset: f64,
+}
+
 #[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
 pub struct ReceiverConfiguration {
     pub integrate: bool,
+    /// Reference frequency in Hz for switched observations. If not set, a
+    /// clean frequency is picked from the RFI scans of the telescope.
+    #[serde(default)]
+    pub reference_frequency: Option<f64>,
+    #[serde(default)]
+    pub window: WindowFunction,
+    #[serde(default)]
+    pub zoom: Option<ZoomConfiguration>,
+    /// Sample rate in Hz, which is also the observed bandwidth. The
+    /// settings below use the telescope's default when not set.
+    #[serde(default)]
+    pub sample_rate: Option<f64>,
+    /// Frequency in Hz in the middle of the observed band.
+    #[serde(default)]
+    pub center_frequency: Option<f64>,
+    /// Number of samples in each FFT.
+    #[serde(default)]
+    pub fft_size: Option<usize>,
+    /// Number of channels the FFT is averaged down to.
+    #[serde(default)]
+    pub channels: Option<usize>,
+    /// Receiver gain in dB.
+    #[serde(default)]
+    pub gain: Option<f64>,
+    #[serde(default)]
+    pub switching: SwitchingMode,
+    /// What to do if the settings above change while integrating.
+    #[serde(default)]
+    pub during_integration: IntegrationChangePolicy,
+    /// Whether the integration runs until stopped or for a fixed time.
+    #[serde(default)]
+    pub mode: ObserveMode,
+    /// How interference is flagged in the spectra.
+    #[serde(default)]
+    pub rfi_flagging: RfiFlagging,
+}
+
+/// How long an integration runs.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone, Default)]
+pub enum ObserveMode {
+    /// Until the user stops it.
+    #[default]
+    UntilStop,
+    /// Stopped by the telescope after a fixed time, or earlier by the user.
+    Fixed { seconds: u64 },
+}
+
+impl ObserveMode {
+    /// Integration time left after `integration_time`, None if the
+    /// integration runs until stopped.
+    pub fn remaining(&self, integration_time: Duration) -> Option<Duration> {
+        match self {
+            ObserveMode::UntilStop => None,
+            ObserveMode::Fixed { seconds } => {
+                Some(Duration::from_secs(*seconds).saturating_sub(integration_time))
+            }
+        }
+    }
+
+    /// Whether an integration in this mode is done after `integration_time`.
+    pub fn is_complete(&self, integration_time: Duration) -> bool {
+        self.remaining(integration_time) == Some(Duration::ZERO)
+    }
+}
+
+impl ReceiverConfiguration {
+    /// Not integrating, with the telescope's defaults for everything else.
+    pub fn stopped() -> ReceiverConfiguration {
+        ReceiverConfiguration {
+            integrate: false,
+            reference_frequency: None,
+            window: Default::default(),
+            zoom: None,
+            sample_rate: None,
+            center_frequency: None,
+            fft_size: None,
+            channels: None,
+            gain: None,
+            switching: Default::default(),
+            during_integration: Default::default(),
+            mode: Default::default(),
+            rfi_flagging: Default::default(),
+        }
+    }
+
+    /// Settings that differ in `requested`, with what to do about each.
+    pub(crate) fn changes(
+        &self,
+        requested: &ReceiverConfiguration,
+    ) -> Vec<(ReceiverParameter, ChangeDuringIntegration)> {
+        let policy = requested.during_integration;
+        let mut changes = Vec::new();
+        if self.reference_frequency != requested.reference_frequency {
+            changes.push((
+                ReceiverParameter::ReferenceFrequency,
+                policy.reference_frequency,
+            ));
+        }
+        if self.window != requested.window {
+            changes.push((ReceiverParameter::Window, policy.window));
+        }
+        if self.zoom != requested.zoom {
+            changes.push((ReceiverParameter::Zoom, policy.zoom));
+        }
+        if self.sample_rate != requested.sample_rate {
+            changes.push((ReceiverParameter::SampleRate, policy.sample_rate));
+        }
+        if self.center_frequency != requested.center_frequency {
+            changes.push((ReceiverParameter::CenterFrequency, policy.center_frequency));
+        }
+        if self.fft_size != requested.fft_size {
+            changes.push((ReceiverParameter::FftSize, policy.fft_size));
+        }
+        if self.channels != requested.channels {
+            changes.push((ReceiverParameter::Channels, policy.channels));
+        }
+        if self.gain != requested.gain {
+            changes.push((ReceiverParameter::Gain, policy.gain));
+        }
+        if self.switching != requested.switching {
+            changes.push((ReceiverParameter::Switching, policy.switching));
+        }
+        if self.rfi_flagging != requested.rfi_flagging {
+            changes.push((ReceiverParameter::RfiFlagging, policy.rfi_flagging));
+        }
+        changes
+    }
+
+    /// How the receiver goes from this configuration to `requested`.
+    ///
+    /// Settings can't change under a running integration, since the
+    /// spectra averaged so far were measured with the old ones. Changes
+    /// are either rejected or restart the integration, as selected for
+    /// each parameter in `requested`.
+    pub fn transition(
+        &self,
+        requested: &ReceiverConfiguration,
+    ) -> Result<ReceiverTransition, ReceiverError> {
+        match (self.integrate, requested.integrate) {
+            (false, false) => Ok(ReceiverTransition::Configure),
+            (false, true) => Ok(ReceiverTransition::Start),
+            (true, false) => Ok(ReceiverTransition::Stop),
+            (true, true) => {
+                let mut transition = ReceiverTransition::Continue;
+                for (parameter, change) in self.changes(requested) {
+                    match change {
+                        ChangeDuringIntegration::Reject => {
+                            return Err(ReceiverError::ChangedDuringIntegration(parameter))
+                        }
+                        ChangeDuringIntegration::Restart => {
+                            transition = ReceiverTransition::Restart
+                        }
+                    }
+                }
+                Ok(transition)
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+pub enum ReceiverParameter {
+    ReferenceFrequency,
+    Window,
+    Zoom,
+    SampleRate,
+    CenterFrequency,
+    FftSize,
+    Channels,
+    Gain,
+    Switching,
+    RfiFlagging,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone, Default)]
+pub enum ChangeDuringIntegration {
+    /// Refuse the change and keep integrating with the old settings.
+    #[default]
+    Reject,
+    /// Finish the running integration and start a new one with the new
+    /// settings.
+    Restart,
+}
+
+/// What to do when each receiver setting is changed during an integration.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone, Default)]
+pub struct IntegrationChangePolicy {
+    #[serde(default)]
+    pub reference_frequency: ChangeDuringIntegration,
+    #[serde(default)]
+    pub window: ChangeDuringIntegration,
+    #[serde(default)]
+    pub zoom: ChangeDuringIntegration,
+    #[serde(default)]
+    pub sample_rate: ChangeDuringIntegration,
+    #[serde(default)]
+    pub center_frequency: ChangeDuringIntegration,
+    #[serde(default)]
+    pub fft_size: ChangeDuringIntegration,
+    #[serde(default)]
+    pub channels: ChangeDuringIntegration,
+    #[serde(default)]
+    pub gain: ChangeDuringIntegration,
+    #[serde(default)]
+    pub switching: ChangeDuringIntegration,
+    #[serde(default)]
+    pub rfi_flagging: ChangeDuringIntegration,
+}
+
+/// How the reference spectrum, that the bandpass of the receiver is
+/// divided out with, is measured.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone, Default)]
+pub enum SwitchingMode {
+    /// No reference, the spectra are the received power.
+    TotalPower,
+    /// On the target, at the reference frequency.
+    #[default]
+    FrequencySwitched,
+    /// At the observed frequency, with the telescope moved off the target
+    /// by `azimuth_offset` while the reference is measured.
+    PositionSwitched { azimuth_offset: Degrees },
+}
+
+/// Step the receiver takes to apply a new configuration.
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub enum ReceiverTransition {
+    /// Not integrating, only the settings change.
+    Configure,
+    Start,
+    Stop,
+    /// Keep integrating, no setting changed.
+    Continue,
+    /// Finish the integration and start a new one with the new settings.
+    Restart,
+}
+
+/// Frequencies from `min` to `max`, in Hz.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+pub struct FrequencyRange {
+    pub min: f64,
+    pub max: f64,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+pub enum ObservationMode {
+    /// Spectra of the observed band.
+    TotalPower,
+    /// Spectra of the observed band with a reference band subtracted.
+    FrequencySwitched,
+    /// Spectra of the target with a spectrum next to it subtracted.
+    PositionSwitched,
+    /// Higher spectral resolution in a part of the band.
+    Zoom,
+    /// Scans of a frequency range for interference.
+    RfiScan,
+}
+
+/// What a telescope supports, for clients to build their controls from
+/// instead of assuming things about each type of telescope.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct TelescopeCapabilities {
+    pub observed_band: FrequencyRange,
+    /// Allowed reference frequencies, if the telescope does switched
+    /// observations.
+    pub reference_band: Option<FrequencyRange>,
+    /// Bandwidths in Hz that can be zoomed in on, the full band first.
+    pub bandwidths: Vec<f64>,
+    /// Sample rates in Hz the receiver can be set to, each being the full
+    /// observed bandwidth.
+    pub sample_rates: Vec<f64>,
+    /// Number of channels in the spectra.
+    pub channels: usize,
+    pub window_functions: Vec<WindowFunction>,
+    pub observation_modes: Vec<ObservationMode>,
+    /// Slewing speed per second, of each axis.
+    pub slewing_speed: Radians,
+    /// Targets below this altitude are not tracked.
+    pub min_altitude: Radians,
+}
+
+/// What setting a target would do right now, worked out without moving the
+/// telescope.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct TargetPreview {
+    pub target: TelescopeTarget,
+    /// Direction the telescope would be commanded to, with the pointing
+    /// correction applied. None if the telescope would not move.
+    pub commanded_horizontal: Option<Direction>,
+    /// Why the target would be refused, or not tracked at the moment.
+    pub error: Option<TelescopeError>,
+    /// Estimated time to slew from the current direction.
+    pub slew_time: Option<Duration>,
+}
+
+/// Total power of the receiver averaged over a short interval, recorded
+/// alongside the spectrum to find interference bursts or pointing wobbles
+/// during an integration.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct ContinuumSample {
+    pub time: DateTime<Utc>,
+    /// Mean power of the received samples, in uncalibrated receiver units.
+    pub power: f64,
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub struct Measurement {
     pub amps: Vec<f64>,
     pub freqs: Vec<f64>,
-    //glon: f64,
-    //glat: f64,
+    pub target: TelescopeTarget,
     pub start: DateTime<Utc>,
     pub duration: Duration,
+    /// Window used in the FFT when the spectrum was measured.
+    #[serde(default)]
+    pub window: WindowFunction,
+    /// Time covered by the samples actually received.
+    #[serde(default)]
+    pub integration_time: Duration,
+    /// Integration time asked for, which is more than `integration_time` if
+    /// samples were lost.
+    #[serde(default)]
+    pub requested_integration_time: Duration,
+    #[serde(default)]
+    pub dropped_samples: u64,
+    /// System temperature in K measured with the noise diode, if the
+    /// telescope has one, or else from the latest hot and cold load
+    /// calibration.
+    #[serde(default)]
+    pub system_temperature: Option<f64>,
+    /// The backend stopped before the measurement was finished, so it was
+    /// recovered from a checkpoint.
+    #[serde(default)]
+    pub interrupted: bool,
+    /// Receiver settings the spectrum was measured with. Missing for
+    /// measurements from before they were recorded.
+    #[serde(default)]
+    pub receiver_configuration: Option<ReceiverConfiguration>,
+    /// Total power about once a second over the integration, from the same
+    /// samples as the spectrum. Empty for older measurements.
+    #[serde(default)]
+    pub continuum: Vec<ContinuumSample>,
+    /// Largest angle between the commanded and current direction while
+    /// integrating. Missing if the telescope does not record it.
+    #[serde(default)]
+    pub max_tracking_error: Option<Radians>,
+    /// Receiver cycles averaged into the spectrum. Zero for older
+    /// measurements.
+    #[serde(default)]
+    pub cycles: u64,
+    /// Spectra of the separate polarizations of a dual polarization feed,
+    /// if they were recorded. `amps` is their average.
+    #[serde(default)]
+    pub polarizations: Vec<PolarizationSpectrum>,
+    /// Fraction of each channel flagged as interference and replaced,
+    /// averaged over the cycles.
+    #[serde(default)]
+    pub flagged: Vec<f64>,
     //stop: Option<DateTime<Utc>>,
     //vlsr_correction: Option<f64>,
     //telname: String,
     //tellat: f64,
     //tellon: f64,
 }
+
+#[cfg(test)]
+pub mod test_utils {
+    use super::*;
+
+    fn telescope(name: &str, enabled: bool, telescope_type: TelescopeType) -> TelescopeDefinition {
+        TelescopeDefinition {
+            name: name.to_string(),
+            enabled,
+            location: Location {
+                longitude: Radians(0.0),
+                latitude: Radians(0.0),
+            },
+            min_altitude: Radians(0.0),
+            telescope_type,
+            rfi_scan: None,
+            auxiliary_devices: vec![],
+            park_policies: vec![],
+            pointing_model: PointingModel::default(),
+        }
+    }
+
+    pub fn fake_telescope(name: &str) -> TelescopeDefinition {
+        telescope(
+            name,
+            true,
+            TelescopeType::Fake {
+                definition: FakeTelescopeDefinition { slewing_speed: 1.0 },
+            },
+        )
+    }
+
+    /// A real telescope, disabled so that nothing tries to connect to it.
+    pub fn salsa_telescope(name: &str) -> TelescopeDefinition {
+        telescope(
+            name,
+            false,
+            TelescopeType::Salsa {
+                definition: SalsaTelescopeDefinition {
+                    controller_address: "127.0.0.1:3001".to_string(),
+                    receiver_address: "127.0.0.1:3002".to_string(),
+                    noise_diode: None,
+                    response_encoding: None,
+                    polarization: Default::default(),
+                    polarization_receivers: vec![],
+                    record_polarizations: false,
+                    rfi_mask: vec![],
+                },
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_observe_mode_remaining() {
+        let fixed = ObserveMode::Fixed { seconds: 60 };
+        assert_eq!(
+            fixed.remaining(Duration::from_secs(45)),
+            Some(Duration::from_secs(15))
+        );
+        assert!(!fixed.is_complete(Duration::from_secs(59)));
+        assert!(fixed.is_complete(Duration::from_secs(61)));
+        assert_eq!(
+            ObserveMode::UntilStop.remaining(Duration::from_secs(61)),
+            None
+        );
+        assert!(!ObserveMode::UntilStop.is_complete(Duration::from_secs(3600)));
+    }
+
+    fn configuration(integrate: bool, window: WindowFunction) -> ReceiverConfiguration {
+        ReceiverConfiguration {
+            integrate,
+            window,
+            ..ReceiverConfiguration::stopped()
+        }
+    }
+
+    #[test]
+    fn test_receiver_transition() {
+        let idle = configuration(false, WindowFunction::Rectangular);
+        let running = configuration(true, WindowFunction::Rectangular);
+        let hann = configuration(true, WindowFunction::Hann);
+        assert_eq!(
+            idle.transition(&configuration(false, WindowFunction::Hann)),
+            Ok(ReceiverTransition::Configure)
+        );
+        assert_eq!(idle.transition(&hann), Ok(ReceiverTransition::Start));
+        assert_eq!(running.transition(&idle), Ok(ReceiverTransition::Stop));
+        assert_eq!(
+            running.transition(&running),
+            Ok(ReceiverTransition::Continue)
+        );
+        assert_eq!(
+            running.transition(&hann),
+            Err(ReceiverError::ChangedDuringIntegration(
+                ReceiverParameter::Window
+            ))
+        );
+
+        let restart_window = ReceiverConfiguration {
+            during_integration: IntegrationChangePolicy {
+                window: ChangeDuringIntegration::Restart,
+                ..Default::default()
+            },
+            ..hann
+        };
+        assert_eq!(
+            running.transition(&restart_window),
+            Ok(ReceiverTransition::Restart)
+        );
+        // Other settings are still rejected.
+        let zoomed = ReceiverConfiguration {
+            zoom: Some(ZoomConfiguration {
+                bandwidth: 1e5,
+                offset: 0.0,
+            }),
+            ..restart_window
+        };
+        assert_eq!(
+            running.transition(&zoomed),
+            Err(ReceiverError::ChangedDuringIntegration(
+                ReceiverParameter::Zoom
+            ))
+        );
+    }
+}
@@ -0,0 +1,50 @@
+//! Helpers for the tests of the routes.
+
+use crate::authentication::AuthenticatedUser;
+use axum::{
+    body::Body,
+    http::{self, request, Request},
+    response::Response,
+    Router,
+};
+use tower::ServiceExt;
+
+/// A request authenticated by `api_token` if given, like from a script.
+pub fn with_token(method: http::Method, uri: &str, api_token: Option<&str>) -> request::Builder {
+    let request = Request::builder().method(method).uri(uri);
+    match api_token {
+        Some(api_token) => {
+            request.header(http::header::AUTHORIZATION, format!("Bearer {}", api_token))
+        }
+        None => request,
+    }
+}
+
+/// Send a request with a JSON `body` to `app`, as `user_name` if given.
+pub async fn send_as(
+    app: Router,
+    user_name: Option<&str>,
+    method: http::Method,
+    uri: &str,
+    body: String,
+) -> Response {
+    let mut request = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref());
+    if let Some(user_name) = user_name {
+        request = request.extension(AuthenticatedUser(user_name.to_string()));
+    }
+    app.oneshot(request.body(Body::from(body)).unwrap())
+        .await
+        .unwrap()
+}
+
+/// Send an anonymous request with a JSON `body` to `app`.
+pub async fn send(app: Router, method: http::Method, uri: &str, body: String) -> Response {
+    send_as(app, None, method, uri, body).await
+}
+
+pub async fn get(app: Router, uri: &str) -> Response {
+    send(app, http::Method::GET, uri, String::new()).await
+}
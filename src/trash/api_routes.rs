@@ -0,0 +1,119 @@
+use crate::database::{DataBase, Storage};
+use crate::trash::{restore, TrashError, TrashedEntry, TrashedItem};
+use axum::{
+    extract::{Json, Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Router,
+};
+
+pub fn routes(database: DataBase<impl Storage + 'static>) -> Router {
+    Router::new()
+        .route("/", get(get_trash))
+        .route("/:id/restore", post(restore_entry))
+        .with_state(database)
+}
+
+impl IntoResponse for TrashError {
+    fn into_response(self) -> Response {
+        match self {
+            TrashError::NotFound => {
+                (StatusCode::NOT_FOUND, "Entry not found".to_string()).into_response()
+            }
+            TrashError::NotAllowed => (
+                StatusCode::FORBIDDEN,
+                "Only the user who made the booking or an admin may change it".to_string(),
+            )
+                .into_response(),
+            TrashError::Conflict => (
+                StatusCode::CONFLICT,
+                "The telescope has been booked by someone else".to_string(),
+            )
+                .into_response(),
+            TrashError::ServiceUnavailable => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Failed to update the trash".to_string(),
+            )
+                .into_response(),
+        }
+    }
+}
+
+async fn get_trash(State(db): State<DataBase<impl Storage>>) -> Json<Vec<TrashedItem>> {
+    Json(
+        db.get_data()
+            .await
+            .expect("As long as no one is manually editing the database, this should never fail.")
+            .trash,
+    )
+}
+
+async fn restore_entry(
+    State(db): State<DataBase<impl Storage>>,
+    Path(id): Path<u64>,
+) -> Result<Json<TrashedEntry>, TrashError> {
+    Ok(Json(restore(&db, id).await?))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bookings::Booking;
+    use crate::database::create_in_memory_database;
+    use crate::test_utils::send_as;
+    use axum::http;
+    use chrono::{Duration, Utc};
+
+    #[tokio::test]
+    async fn test_delete_and_restore_booking() {
+        let db = create_in_memory_database();
+        let booking = Booking {
+            start_time: Utc::now(),
+            end_time: Utc::now() + Duration::hours(1),
+            telescope_name: "fake".to_string(),
+            user_name: "student".to_string(),
+        };
+        db.update_data(|mut data_model| {
+            data_model.bookings.push(booking.clone());
+            data_model
+        })
+        .await
+        .unwrap();
+
+        let response = send_as(
+            crate::bookings::api_routes::routes(db.clone()),
+            Some("student"),
+            http::Method::DELETE,
+            "/",
+            serde_json::to_string(&booking).unwrap(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let trashed: TrashedItem = serde_json::from_slice(&body).unwrap();
+        assert_eq!(trashed.entry, TrashedEntry::Booking(booking.clone()));
+        assert!(db.get_data().await.unwrap().bookings.is_empty());
+
+        let response = send_as(
+            routes(db.clone()),
+            Some("student"),
+            http::Method::POST,
+            &format!("/{}/restore", trashed.id),
+            String::new(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(db.get_data().await.unwrap().bookings, vec![booking]);
+
+        let response = send_as(
+            routes(db),
+            Some("student"),
+            http::Method::POST,
+            &format!("/{}/restore", trashed.id),
+            String::new(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,303 @@
+//! Deleted bookings and measurements.
+//!
+//! Deleting moves an entry from its list in the data model to the trash,
+//! so nothing else has to know about deleted entries. It can be restored
+//! until the retention period has passed, after which it is purged.
+
+use crate::archive::files::{delete_measurement_files, track_removed_measurements};
+use crate::archive::ArchivedMeasurement;
+use crate::bookings::{record_booking_change, Booking, BookingChangeKind};
+use crate::database::{DataBase, DataBaseError, DataModel, Storage};
+use crate::users::may_change_booking;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+pub mod api_routes;
+pub mod routes;
+
+/// How long deleted entries can be restored.
+pub const TRASH_RETENTION_DAYS: i64 = 30;
+/// How often entries past the retention period are purged.
+pub const TRASH_PURGE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(3600);
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub enum TrashedEntry {
+    Booking(Booking),
+    Measurement(Box<ArchivedMeasurement>),
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct TrashedItem {
+    pub id: u64,
+    pub deleted: DateTime<Utc>,
+    pub entry: TrashedEntry,
+}
+
+impl TrashedItem {
+    pub fn purge_time(&self) -> DateTime<Utc> {
+        self.deleted + Duration::days(TRASH_RETENTION_DAYS)
+    }
+
+    /// Id of the trashed measurement, if this is one.
+    pub fn measurement_id(&self) -> Option<u64> {
+        match &self.entry {
+            TrashedEntry::Measurement(measurement) => Some(measurement.id),
+            TrashedEntry::Booking(_) => None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum TrashError {
+    NotFound,
+    /// Only the user who made a booking, or an admin, may delete it.
+    NotAllowed,
+    /// The booking can not be restored since the telescope has been booked
+    /// by someone else since it was deleted.
+    Conflict,
+    ServiceUnavailable,
+}
+
+impl From<DataBaseError> for TrashError {
+    fn from(_source: DataBaseError) -> Self {
+        Self::ServiceUnavailable
+    }
+}
+
+fn move_to_trash(
+    data_model: &mut DataModel,
+    entry: TrashedEntry,
+    now: DateTime<Utc>,
+) -> TrashedItem {
+    let item = TrashedItem {
+        id: data_model.trash.iter().map(|t| t.id + 1).max().unwrap_or(1),
+        deleted: now,
+        entry,
+    };
+    data_model.trash.push(item.clone());
+    item
+}
+
+/// Move a booking to the trash. The booking has to match exactly, since
+/// bookings have no id.
+pub async fn trash_booking(
+    db: &DataBase<impl Storage>,
+    booking: &Booking,
+    requested_by: &str,
+    now: DateTime<Utc>,
+) -> Result<TrashedItem, TrashError> {
+    let mut result = Err(TrashError::NotFound);
+    db.update_data(|mut data_model| {
+        if !may_change_booking(&data_model, requested_by, booking) {
+            result = Err(TrashError::NotAllowed);
+        } else if let Some(index) = data_model.bookings.iter().position(|b| b == booking) {
+            let booking = data_model.bookings.remove(index);
+            record_booking_change(
+                &mut data_model,
+                requested_by,
+                &booking,
+                BookingChangeKind::Cancelled,
+                now,
+            );
+            result = Ok(move_to_trash(
+                &mut data_model,
+                TrashedEntry::Booking(booking),
+                now,
+            ));
+        }
+        data_model
+    })
+    .await?;
+    if result.is_ok() {
+        log::info!(
+            "Deleted booking of {} by {}",
+            booking.telescope_name,
+            booking.user_name
+        );
+    }
+    result
+}
+
+/// Move an archived measurement to the trash.
+pub async fn trash_measurement(
+    db: &DataBase<impl Storage>,
+    id: u64,
+    now: DateTime<Utc>,
+) -> Result<TrashedItem, TrashError> {
+    let mut result = Err(TrashError::NotFound);
+    db.update_data(|mut data_model| {
+        if let Some(index) = data_model.measurements.iter().position(|m| m.id == id) {
+            let measurement = data_model.measurements.remove(index);
+            result = Ok(move_to_trash(
+                &mut data_model,
+                TrashedEntry::Measurement(Box::new(measurement)),
+                now,
+            ));
+        }
+        data_model
+    })
+    .await?;
+    if result.is_ok() {
+        log::info!("Deleted measurement {}", id);
+    }
+    result
+}
+
+/// Put a trashed entry back where it was deleted from.
+pub async fn restore(db: &DataBase<impl Storage>, id: u64) -> Result<TrashedEntry, TrashError> {
+    let mut result = Err(TrashError::NotFound);
+    db.update_data(|mut data_model| {
+        let Some(index) = data_model.trash.iter().position(|t| t.id == id) else {
+            return data_model;
+        };
+        match &data_model.trash[index].entry {
+            TrashedEntry::Booking(booking) => {
+                if data_model
+                    .bookings
+                    .iter()
+                    .any(|b| b.telescope_name == booking.telescope_name && b.overlaps(booking))
+                {
+                    result = Err(TrashError::Conflict);
+                    return data_model;
+                }
+                data_model.bookings.push(booking.clone());
+            }
+            TrashedEntry::Measurement(measurement) => {
+                data_model.measurements.push(*measurement.clone());
+                data_model.measurements.sort_by_key(|m| m.id);
+            }
+        }
+        result = Ok(data_model.trash.remove(index).entry);
+        data_model
+    })
+    .await?;
+    if result.is_ok() {
+        log::info!("Restored entry {} from the trash", id);
+    }
+    result
+}
+
+/// Permanently remove entries that have been in the trash for longer than
+/// the retention period.
+pub fn purge_expired_trash(mut data_model: DataModel, now: DateTime<Utc>) -> DataModel {
+    let before = data_model.trash.len();
+    data_model.trash.retain(|t| t.purge_time() > now);
+    let purged = before - data_model.trash.len();
+    if purged > 0 {
+        log::info!("Purged {} entries from the trash", purged);
+    }
+    data_model
+}
+
+pub fn start_trash_purge_service<T>(database: DataBase<T>) -> tokio::task::JoinHandle<()>
+where
+    T: Storage + 'static,
+{
+    tokio::spawn(async move {
+        loop {
+            let now = Utc::now();
+            let mut removed = Vec::new();
+            if let Err(error) = database
+                .update_data(|data_model| {
+                    track_removed_measurements(
+                        data_model,
+                        |data_model| purge_expired_trash(data_model, now),
+                        &mut removed,
+                    )
+                })
+                .await
+            {
+                log::error!("Failed to purge the trash: {}", error);
+            }
+            delete_measurement_files(&database, removed).await;
+            tokio::time::sleep(TRASH_PURGE_INTERVAL).await;
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::angles::Degrees;
+    use crate::archive::archive_measurement;
+    use crate::archive::test_utils::galactic_measurement;
+    use crate::database::create_in_memory_database;
+
+    fn booking(user_name: &str, start_time: DateTime<Utc>) -> Booking {
+        Booking {
+            start_time,
+            end_time: start_time + Duration::hours(1),
+            telescope_name: "fake".to_string(),
+            user_name: user_name.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_trash_and_restore() {
+        let now = Utc::now();
+        let db = create_in_memory_database();
+        let deleted = booking("anna", now);
+        db.update_data(|mut data_model| {
+            data_model.bookings.push(deleted.clone());
+            data_model
+        })
+        .await
+        .unwrap();
+        let id = archive_measurement(&db, "fake", galactic_measurement(Degrees(30.0), now))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            trash_booking(&db, &deleted, "student", now).await,
+            Err(TrashError::NotAllowed)
+        );
+        let trashed_booking = trash_booking(&db, &deleted, "anna", now).await.unwrap();
+        assert_eq!(
+            trash_booking(&db, &deleted, "anna", now).await,
+            Err(TrashError::NotFound)
+        );
+        let trashed_measurement = trash_measurement(&db, id, now).await.unwrap();
+        let data_model = db.get_data().await.unwrap();
+        assert!(data_model.bookings.is_empty());
+        assert!(data_model.measurements.is_empty());
+        assert_eq!(data_model.trash.len(), 2);
+
+        // Ids of trashed measurements are not reused.
+        let next_id = archive_measurement(&db, "fake", galactic_measurement(Degrees(40.0), now))
+            .await
+            .unwrap();
+        assert!(next_id > id);
+
+        restore(&db, trashed_measurement.id).await.unwrap();
+        let ids: Vec<_> = db
+            .get_data()
+            .await
+            .unwrap()
+            .measurements
+            .iter()
+            .map(|m| m.id)
+            .collect();
+        assert_eq!(ids, vec![id, next_id]);
+
+        // Someone else booked the telescope after the booking was deleted.
+        db.update_data(|mut data_model| {
+            data_model.bookings.push(booking("bertil", now));
+            data_model
+        })
+        .await
+        .unwrap();
+        assert_eq!(
+            restore(&db, trashed_booking.id).await,
+            Err(TrashError::Conflict)
+        );
+
+        let data_model = purge_expired_trash(
+            db.get_data().await.unwrap(),
+            now + Duration::days(TRASH_RETENTION_DAYS - 1),
+        );
+        assert_eq!(data_model.trash.len(), 1);
+        let data_model =
+            purge_expired_trash(data_model, now + Duration::days(TRASH_RETENTION_DAYS));
+        assert!(data_model.trash.is_empty());
+    }
+}
@@ -0,0 +1,106 @@
+use crate::database::{DataBase, Storage};
+use crate::template::HtmlTemplate;
+use crate::trash::{restore, TrashError, TrashedEntry};
+use askama::Template;
+use axum::{
+    extract::{Path, State},
+    response::IntoResponse,
+    routing::{get, post},
+    Router,
+};
+
+pub fn routes(database: DataBase<impl Storage + 'static>) -> Router {
+    Router::new()
+        .route("/", get(get_trash))
+        .route("/:id/restore", post(restore_entry))
+        .with_state(database)
+}
+
+struct TrashEntry {
+    id: u64,
+    kind: &'static str,
+    description: String,
+    deleted: String,
+    purged: String,
+}
+
+#[derive(Template)]
+#[template(path = "trash.html")]
+struct TrashTemplate {
+    entries: Vec<TrashEntry>,
+    message: Option<String>,
+}
+
+async fn render_trash(
+    db: &DataBase<impl Storage>,
+    message: Option<String>,
+) -> HtmlTemplate<TrashTemplate> {
+    let trash = db
+        .get_data()
+        .await
+        .expect("As long as no one is manually editing the database, this should never fail.")
+        .trash;
+    let entries = trash
+        .iter()
+        .rev()
+        .map(|item| {
+            let (kind, description) = match &item.entry {
+                TrashedEntry::Booking(booking) => (
+                    "Booking",
+                    format!(
+                        "{} booked by {} at {}",
+                        booking.telescope_name,
+                        booking.user_name,
+                        booking.start_time.format("%Y-%m-%d %H:%M")
+                    ),
+                ),
+                TrashedEntry::Measurement(measurement) => (
+                    "Measurement",
+                    format!(
+                        "{} from {} at {}",
+                        measurement.id,
+                        measurement.telescope_name,
+                        measurement.measurement.start.format("%Y-%m-%d %H:%M")
+                    ),
+                ),
+            };
+            TrashEntry {
+                id: item.id,
+                kind,
+                description,
+                deleted: item.deleted.format("%Y-%m-%d %H:%M").to_string(),
+                purged: item.purge_time().format("%Y-%m-%d").to_string(),
+            }
+        })
+        .collect();
+    HtmlTemplate(TrashTemplate { entries, message })
+}
+
+async fn get_trash<StorageType>(State(db): State<DataBase<StorageType>>) -> impl IntoResponse
+where
+    StorageType: Storage,
+{
+    render_trash(&db, None).await
+}
+
+async fn restore_entry<StorageType>(
+    State(db): State<DataBase<StorageType>>,
+    Path(id): Path<u64>,
+) -> impl IntoResponse
+where
+    StorageType: Storage,
+{
+    let message = match restore(&db, id).await {
+        Ok(_) => None,
+        Err(TrashError::NotFound) => Some("The entry is no longer in the trash.".to_string()),
+        Err(TrashError::NotAllowed) => {
+            Some("Only the user who made the booking or an admin may change it.".to_string())
+        }
+        Err(TrashError::Conflict) => Some(
+            "The booking can not be restored since the telescope has been booked by someone else."
+                .to_string(),
+        ),
+        Err(TrashError::ServiceUnavailable) => Some("Failed to restore the entry.".to_string()),
+    };
+    render_trash(&db, message).await
+}
@@ -0,0 +1,56 @@
+use crate::database::{DataBase, Storage};
+use crate::ups::{power_status, OnBatteryPower, PowerEvent, PowerStatus};
+use axum::{
+    extract::{Json, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use serde::Serialize;
+
+pub fn routes(database: DataBase<impl Storage + 'static>) -> Router {
+    Router::new()
+        .route("/", get(get_ups_status))
+        .route("/events", get(get_power_events))
+        .with_state(database)
+}
+
+impl IntoResponse for OnBatteryPower {
+    fn into_response(self) -> Response {
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "The telescopes are parked while running on battery power".to_string(),
+        )
+            .into_response()
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct UpsStatus {
+    configured: bool,
+    status: PowerStatus,
+    /// The most recent change of power status.
+    since: Option<PowerEvent>,
+}
+
+async fn get_ups_status(State(db): State<DataBase<impl Storage>>) -> Json<UpsStatus> {
+    let data_model = db
+        .get_data()
+        .await
+        .expect("As long as no one is manually editing the database, this should never fail.");
+    Json(UpsStatus {
+        configured: data_model.ups.is_some(),
+        status: power_status(&data_model),
+        since: data_model.power_events.last().cloned(),
+    })
+}
+
+async fn get_power_events(State(db): State<DataBase<impl Storage>>) -> Json<Vec<PowerEvent>> {
+    Json(
+        db.get_data()
+            .await
+            .expect("As long as no one is manually editing the database, this should never fail.")
+            .power_events,
+    )
+}
@@ -0,0 +1,416 @@
+//! Monitoring of the UPS powering the telescopes.
+//!
+//! The UPS is read either from a NUT server (Network UPS Tools) or from a
+//! GPIO pin wired to its on-battery signal. When the power fails, the
+//! telescopes are put in a safe state while the battery lasts: integrations
+//! are stopped and the dishes parked. Until mains power is back, requests
+//! that would move a telescope are refused so that nobody undoes the park.
+
+use crate::database::{DataBase, DataBaseError, DataModel, Storage};
+use crate::telescope::TelescopeCollection;
+use crate::telescopes::{ReceiverConfiguration, TelescopeTarget};
+use axum::{
+    extract::State,
+    http::{Method, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use thiserror::Error;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::TcpStream;
+
+pub mod api_routes;
+pub mod routes;
+
+pub const UPS_POLL_INTERVAL: Duration = Duration::from_secs(5);
+const NUT_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub enum UpsDefinition {
+    /// A UPS monitored by a NUT server, `address` is e.g. `127.0.0.1:3493`.
+    Nut { address: String, ups_name: String },
+    /// A GPIO pin exported through sysfs, e.g.
+    /// `/sys/class/gpio/gpio17/value`, reading 1 when on battery unless
+    /// `active_low` is set.
+    Gpio {
+        value_path: String,
+        #[serde(default)]
+        active_low: bool,
+    },
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+pub enum PowerStatus {
+    Mains,
+    Battery,
+    LowBattery,
+}
+
+impl PowerStatus {
+    pub fn on_battery(&self) -> bool {
+        *self != PowerStatus::Mains
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct UpsReading {
+    pub status: PowerStatus,
+    /// Battery charge in percent, if the UPS reports it.
+    pub battery_charge: Option<f64>,
+}
+
+/// A change of power status.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct PowerEvent {
+    pub time: DateTime<Utc>,
+    #[serde(flatten)]
+    pub reading: UpsReading,
+}
+
+#[derive(Debug, Error)]
+pub enum UpsError {
+    #[error("failed to read UPS: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("UPS did not respond in time")]
+    Timeout,
+    #[error("unexpected response from UPS: {0}")]
+    Protocol(String),
+}
+
+/// Power status of the most recent power event, Mains if there is none.
+pub fn power_status(data_model: &DataModel) -> PowerStatus {
+    data_model
+        .power_events
+        .last()
+        .map(|e| e.reading.status)
+        .unwrap_or(PowerStatus::Mains)
+}
+
+/// Value of the variable `name` of `ups_name` in a NUT `VAR` response line,
+/// e.g. `VAR ups ups.status "OB LB"`.
+pub fn parse_nut_variable(line: &str, ups_name: &str, name: &str) -> Result<String, UpsError> {
+    let prefix = format!("VAR {} {} ", ups_name, name);
+    line.trim_end()
+        .strip_prefix(&prefix)
+        .and_then(|value| value.strip_prefix('"'))
+        .and_then(|value| value.strip_suffix('"'))
+        .map(str::to_string)
+        .ok_or_else(|| UpsError::Protocol(line.trim_end().to_string()))
+}
+
+/// Power status from the flags of the NUT variable `ups.status`.
+pub fn parse_nut_status(flags: &str) -> Result<PowerStatus, UpsError> {
+    let flags: Vec<&str> = flags.split_whitespace().collect();
+    if flags.contains(&"LB") {
+        Ok(PowerStatus::LowBattery)
+    } else if flags.contains(&"OB") {
+        Ok(PowerStatus::Battery)
+    } else if flags.contains(&"OL") {
+        Ok(PowerStatus::Mains)
+    } else {
+        Err(UpsError::Protocol(format!(
+            "unknown status {}",
+            flags.join(" ")
+        )))
+    }
+}
+
+async fn get_nut_variable(
+    writer: &mut OwnedWriteHalf,
+    lines: &mut Lines<BufReader<OwnedReadHalf>>,
+    ups_name: &str,
+    name: &str,
+) -> Result<String, UpsError> {
+    let command = format!("GET VAR {} {}\n", ups_name, name);
+    writer.write_all(command.as_bytes()).await?;
+    let line = lines
+        .next_line()
+        .await?
+        .ok_or_else(|| UpsError::Protocol("connection closed".to_string()))?;
+    parse_nut_variable(&line, ups_name, name)
+}
+
+async fn read_nut(address: &str, ups_name: &str) -> Result<UpsReading, UpsError> {
+    let stream = TcpStream::connect(address).await?;
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    let flags = get_nut_variable(&mut writer, &mut lines, ups_name, "ups.status").await?;
+    let status = parse_nut_status(&flags)?;
+    // Not every UPS reports its charge.
+    let battery_charge = get_nut_variable(&mut writer, &mut lines, ups_name, "battery.charge")
+        .await
+        .ok()
+        .and_then(|charge| charge.parse().ok());
+    Ok(UpsReading {
+        status,
+        battery_charge,
+    })
+}
+
+pub async fn read_ups(definition: &UpsDefinition) -> Result<UpsReading, UpsError> {
+    match definition {
+        UpsDefinition::Nut { address, ups_name } => {
+            tokio::time::timeout(NUT_TIMEOUT, read_nut(address, ups_name))
+                .await
+                .map_err(|_| UpsError::Timeout)?
+        }
+        UpsDefinition::Gpio {
+            value_path,
+            active_low,
+        } => {
+            let value = tokio::fs::read_to_string(value_path).await?;
+            let high = match value.trim() {
+                "1" => true,
+                "0" => false,
+                other => return Err(UpsError::Protocol(format!("GPIO value {}", other))),
+            };
+            let status = if high != *active_low {
+                PowerStatus::Battery
+            } else {
+                PowerStatus::Mains
+            };
+            Ok(UpsReading {
+                status,
+                battery_charge: None,
+            })
+        }
+    }
+}
+
+/// Stop all integrations and park every telescope.
+pub async fn enter_safe_state(telescopes: &TelescopeCollection) {
+    let telescopes = telescopes.read().await;
+    for (name, container) in telescopes.iter() {
+        let mut telescope = container.telescope.lock().await;
+        if telescope.measurement_in_progress().await.is_some() {
+            let stop = ReceiverConfiguration::stopped();
+            if let Err(error) = telescope.set_receiver_configuration(stop).await {
+                log::error!("Failed to stop integration on {}: {:?}", name, error);
+            }
+        }
+        match telescope.set_target(TelescopeTarget::Parked).await {
+            Ok(_) => log::warn!("Parking {} because of power failure", name),
+            Err(error) => log::error!("Failed to park {}: {}", name, error),
+        }
+    }
+}
+
+/// Record `reading` if the power status changed, and put the telescopes in
+/// a safe state if the power failed.
+pub async fn handle_ups_reading(
+    database: &DataBase<impl Storage>,
+    telescopes: &TelescopeCollection,
+    reading: UpsReading,
+    now: DateTime<Utc>,
+) -> Result<(), DataBaseError> {
+    let previous = power_status(&database.get_data().await?);
+    if previous == reading.status {
+        return Ok(());
+    }
+    if reading.status.on_battery() {
+        log::warn!("UPS reports {:?}, entering safe state", reading.status);
+        enter_safe_state(telescopes).await;
+    } else {
+        log::warn!("Mains power restored");
+    }
+    database
+        .update_data(|mut data_model| {
+            data_model
+                .power_events
+                .push(PowerEvent { time: now, reading });
+            data_model
+        })
+        .await
+}
+
+pub fn start_ups_monitor_service<T>(
+    database: DataBase<T>,
+    telescopes: TelescopeCollection,
+    definition: UpsDefinition,
+) -> tokio::task::JoinHandle<()>
+where
+    T: Storage + 'static,
+{
+    tokio::spawn(async move {
+        loop {
+            match read_ups(&definition).await {
+                Ok(reading) => {
+                    if let Err(error) =
+                        handle_ups_reading(&database, &telescopes, reading, Utc::now()).await
+                    {
+                        log::error!("Failed to record power event: {}", error);
+                    }
+                }
+                Err(error) => log::error!("{}", error),
+            }
+            tokio::time::sleep(UPS_POLL_INTERVAL).await;
+        }
+    })
+}
+
+#[derive(Debug)]
+pub struct OnBatteryPower;
+
+/// Middleware for the telescope routes, rejecting every request that changes
+/// the state of a telescope while running on battery.
+pub async fn refuse_on_battery<StorageType, B>(
+    State(db): State<DataBase<StorageType>>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response
+where
+    StorageType: Storage,
+{
+    if request.method() != Method::GET {
+        let data_model = db
+            .get_data()
+            .await
+            .expect("As long as no one is manually editing the database, this should never fail.");
+        if power_status(&data_model).on_battery() {
+            return OnBatteryPower.into_response();
+        }
+    }
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::database::create_in_memory_database;
+    use crate::interlock::CollisionInterlock;
+    use crate::supervisor::TelescopeSupervisor;
+    use crate::telescope::{Telescope, TelescopeContainer};
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpListener;
+    use tokio::sync::{Mutex, RwLock};
+
+    #[test]
+    fn test_parse_nut() {
+        assert_eq!(
+            parse_nut_variable("VAR ups ups.status \"OB LB\"\n", "ups", "ups.status").unwrap(),
+            "OB LB"
+        );
+        assert!(parse_nut_variable("ERR UNKNOWN-UPS", "ups", "ups.status").is_err());
+        assert_eq!(parse_nut_status("OL CHRG").unwrap(), PowerStatus::Mains);
+        assert_eq!(
+            parse_nut_status("OB DISCHRG").unwrap(),
+            PowerStatus::Battery
+        );
+        assert_eq!(parse_nut_status("OB LB").unwrap(), PowerStatus::LowBattery);
+        assert!(parse_nut_status("").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_read_nut() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buffer = [0u8; 256];
+            for response in [
+                "VAR ups ups.status \"OB\"\n",
+                "VAR ups battery.charge \"87\"\n",
+            ] {
+                let _ = stream.read(&mut buffer).await.unwrap();
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+
+        let definition = UpsDefinition::Nut {
+            address,
+            ups_name: "ups".to_string(),
+        };
+        assert_eq!(
+            read_ups(&definition).await.unwrap(),
+            UpsReading {
+                status: PowerStatus::Battery,
+                battery_charge: Some(87.0),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_power_failure() {
+        let db = create_in_memory_database();
+        let supervisor = TelescopeSupervisor::new("fake");
+        let telescope = Arc::new(Mutex::new(crate::fake_telescope::create(
+            "fake".to_string(),
+            None,
+            vec![],
+            supervisor.clone(),
+            CollisionInterlock::default(),
+        )));
+        let telescopes: TelescopeCollection = Arc::new(RwLock::new(HashMap::from([(
+            "fake".to_string(),
+            TelescopeContainer {
+                telescope: telescope.clone(),
+                supervisor,
+                updates: Default::default(),
+            },
+        )])));
+        let now = Utc::now();
+        let reading = |status| UpsReading {
+            status,
+            battery_charge: None,
+        };
+
+        telescope
+            .lock()
+            .await
+            .set_target(TelescopeTarget::Stopped)
+            .await
+            .unwrap();
+        telescope
+            .lock()
+            .await
+            .set_receiver_configuration(ReceiverConfiguration {
+                integrate: true,
+                ..ReceiverConfiguration::stopped()
+            })
+            .await
+            .unwrap();
+        handle_ups_reading(&db, &telescopes, reading(PowerStatus::Mains), now)
+            .await
+            .unwrap();
+        assert!(db.get_data().await.unwrap().power_events.is_empty());
+
+        handle_ups_reading(&db, &telescopes, reading(PowerStatus::Battery), now)
+            .await
+            .unwrap();
+        assert_eq!(
+            telescope.lock().await.get_target().await.unwrap(),
+            TelescopeTarget::Parked
+        );
+        assert!(telescope
+            .lock()
+            .await
+            .measurement_in_progress()
+            .await
+            .is_none());
+        let data_model = db.get_data().await.unwrap();
+        assert_eq!(power_status(&data_model), PowerStatus::Battery);
+
+        // Only changes are recorded.
+        handle_ups_reading(&db, &telescopes, reading(PowerStatus::Battery), now)
+            .await
+            .unwrap();
+        handle_ups_reading(&db, &telescopes, reading(PowerStatus::Mains), now)
+            .await
+            .unwrap();
+        let statuses: Vec<PowerStatus> = db
+            .get_data()
+            .await
+            .unwrap()
+            .power_events
+            .into_iter()
+            .map(|e| e.reading.status)
+            .collect();
+        assert_eq!(statuses, vec![PowerStatus::Battery, PowerStatus::Mains]);
+    }
+}
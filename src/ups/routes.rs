@@ -0,0 +1,46 @@
+use crate::database::{DataBase, Storage};
+use crate::format::{filters, Locale};
+use crate::template::HtmlTemplate;
+use crate::ups::{power_status, PowerStatus};
+use askama::Template;
+use axum::{extract::State, response::IntoResponse, routing::get, Router};
+
+pub fn routes(database: DataBase<impl Storage + 'static>) -> Router {
+    Router::new()
+        .route("/", get(get_ups_status))
+        .with_state(database)
+}
+
+#[derive(Template)]
+#[template(path = "ups_status.html")]
+struct UpsStatusTemplate {
+    configured: bool,
+    on_battery: bool,
+    status: PowerStatus,
+    since: Option<String>,
+    battery_charge: Option<f64>,
+    locale: Locale,
+}
+
+async fn get_ups_status<StorageType>(
+    State(db): State<DataBase<StorageType>>,
+    locale: Locale,
+) -> impl IntoResponse
+where
+    StorageType: Storage,
+{
+    let data_model = db
+        .get_data()
+        .await
+        .expect("As long as no one is manually editing the database, this should never fail.");
+    let status = power_status(&data_model);
+    let latest = data_model.power_events.last();
+    HtmlTemplate(UpsStatusTemplate {
+        configured: data_model.ups.is_some(),
+        on_battery: status.on_battery(),
+        status,
+        since: latest.map(|e| e.time.format("%Y-%m-%d %H:%M").to_string()),
+        battery_charge: latest.and_then(|e| e.reading.battery_charge),
+        locale,
+    })
+}
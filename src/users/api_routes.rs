@@ -0,0 +1,412 @@
+use crate::authentication::{issue_api_token, AuthenticationError, IssuedToken};
+use crate::database::{DataBase, Storage};
+use crate::users::dashboard::{class_progress, export_class_data, DashboardError, StudentProgress};
+use crate::users::demo::{create_demo_account, DemoAccount, DemoError};
+use crate::users::import::{import_users, ImportError, ImportRequest, ImportedRow};
+use crate::users::privacy::{
+    export_user_data, request_deletion, update_deletion, DeletionError, DeletionRequest,
+    MeasurementHandling,
+};
+use crate::users::tutorial::TutorialStep;
+use crate::users::{TrainingStep, User};
+use axum::{
+    extract::{Json, Path, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post, put},
+    Router,
+};
+use chrono::Utc;
+
+pub fn routes(database: DataBase<impl Storage + 'static>) -> Router {
+    Router::new()
+        .route("/", get(get_users))
+        .route("/demo", post(create_demo))
+        .route("/import", post(import))
+        .route("/:name", get(get_user))
+        .route("/:name/certify", post(certify_user))
+        .route("/:name/token", post(create_api_token))
+        .route("/:name/training/:step", post(complete_training_step))
+        .route("/:name/tutorial/skip", post(skip_tutorial))
+        .route("/:name/export", get(export_user))
+        .route("/:name/teaches", put(set_taught_groups))
+        .route("/:name/dashboard", get(get_dashboard))
+        .route("/:name/dashboard/export", get(export_class))
+        .route(
+            "/:name/deletion",
+            post(request_user_deletion).delete(cancel_user_deletion),
+        )
+        .route("/:name/deletion/confirm", post(confirm_user_deletion))
+        .with_state(database)
+}
+
+#[derive(Debug)]
+pub struct UserNotFound;
+
+impl IntoResponse for UserNotFound {
+    fn into_response(self) -> Response {
+        (StatusCode::NOT_FOUND, "User not found".to_string()).into_response()
+    }
+}
+
+pub async fn fetch_user(db: &DataBase<impl Storage>, name: &str) -> Result<User, UserNotFound> {
+    db.get_data()
+        .await
+        .expect("As long as no one is manually editing the database, this should never fail.")
+        .users
+        .into_iter()
+        .find(|u| u.name == name)
+        .ok_or(UserNotFound)
+}
+
+/// Apply `f` to the user called `name` and return the updated user.
+async fn update_user(
+    db: &DataBase<impl Storage>,
+    name: &str,
+    f: impl FnOnce(&mut User),
+) -> Result<User, UserNotFound> {
+    fetch_user(db, name).await?;
+    db.update_data(|mut data_model| {
+        if let Some(user) = data_model.users.iter_mut().find(|u| u.name == name) {
+            f(user);
+        }
+        data_model
+    })
+    .await
+    .expect("As long as no one is manually editing the database, this should never fail.");
+    fetch_user(db, name).await
+}
+
+async fn get_users(State(db): State<DataBase<impl Storage>>) -> Json<Vec<User>> {
+    Json(
+        db.get_data()
+            .await
+            .expect("As long as no one is manually editing the database, this should never fail.")
+            .users,
+    )
+}
+
+async fn get_user(
+    State(db): State<DataBase<impl Storage>>,
+    Path(name): Path<String>,
+) -> Result<Json<User>, UserNotFound> {
+    Ok(Json(fetch_user(&db, &name).await?))
+}
+
+async fn certify_user(
+    State(db): State<DataBase<impl Storage>>,
+    Path(name): Path<String>,
+) -> Result<Json<User>, UserNotFound> {
+    log::info!("Certifying user {}", name);
+    Ok(Json(
+        update_user(&db, &name, |user| user.certified = true).await?,
+    ))
+}
+
+/// Give the user a new API token, revoking their old one.
+async fn create_api_token(
+    State(db): State<DataBase<impl Storage>>,
+    Path(name): Path<String>,
+) -> Result<(StatusCode, Json<IssuedToken>), AuthenticationError> {
+    Ok((
+        StatusCode::CREATED,
+        Json(issue_api_token(&db, &name, Utc::now()).await?),
+    ))
+}
+
+async fn complete_training_step(
+    State(db): State<DataBase<impl Storage>>,
+    Path((name, step)): Path<(String, TrainingStep)>,
+) -> Result<Json<User>, UserNotFound> {
+    Ok(Json(
+        update_user(&db, &name, |user| {
+            if !user.completed_training.contains(&step) {
+                user.completed_training.push(step);
+            }
+        })
+        .await?,
+    ))
+}
+
+impl IntoResponse for DemoError {
+    fn into_response(self) -> Response {
+        match self {
+            DemoError::TooManyDemoAccounts => (
+                StatusCode::TOO_MANY_REQUESTS,
+                "Too many demo accounts, please try again later".to_string(),
+            ),
+            DemoError::ServiceUnavailable => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Failed to create demo account".to_string(),
+            ),
+        }
+        .into_response()
+    }
+}
+
+async fn create_demo(
+    State(db): State<DataBase<impl Storage>>,
+) -> Result<(StatusCode, Json<DemoAccount>), DemoError> {
+    Ok((
+        StatusCode::CREATED,
+        Json(create_demo_account(&db, Utc::now()).await?),
+    ))
+}
+
+async fn import(
+    State(db): State<DataBase<impl Storage>>,
+    Json(request): Json<ImportRequest>,
+) -> (StatusCode, Json<Result<Vec<ImportedRow>, ImportError>>) {
+    let result = import_users(&db, request).await;
+    let status_code = match result {
+        Ok(_) => StatusCode::OK,
+        Err(ImportError::ServiceUnavailable) => StatusCode::SERVICE_UNAVAILABLE,
+        Err(_) => StatusCode::BAD_REQUEST,
+    };
+    (status_code, Json(result))
+}
+
+async fn skip_tutorial(
+    State(db): State<DataBase<impl Storage>>,
+    Path(name): Path<String>,
+) -> Result<Json<User>, UserNotFound> {
+    Ok(Json(
+        update_user(&db, &name, |user| user.tutorial = TutorialStep::Completed).await?,
+    ))
+}
+
+impl IntoResponse for DeletionError {
+    fn into_response(self) -> Response {
+        match self {
+            DeletionError::UserNotFound => UserNotFound.into_response(),
+            DeletionError::UnknownTransferUser(name) => (
+                StatusCode::BAD_REQUEST,
+                format!("Can not transfer measurements to {}", name),
+            )
+                .into_response(),
+            DeletionError::NotRequested => (
+                StatusCode::CONFLICT,
+                "Deletion has not been requested".to_string(),
+            )
+                .into_response(),
+            DeletionError::ServiceUnavailable => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Failed to update deletion".to_string(),
+            )
+                .into_response(),
+        }
+    }
+}
+
+async fn export_user(
+    State(db): State<DataBase<impl Storage>>,
+    Path(name): Path<String>,
+) -> Result<impl IntoResponse, UserNotFound> {
+    let data_model = db
+        .get_data()
+        .await
+        .expect("As long as no one is manually editing the database, this should never fail.");
+    let export = export_user_data(&data_model, &name).ok_or(UserNotFound)?;
+    Ok((
+        [(
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}.json\"", name),
+        )],
+        Json(export),
+    ))
+}
+
+async fn request_user_deletion(
+    State(db): State<DataBase<impl Storage>>,
+    Path(name): Path<String>,
+    Json(measurements): Json<MeasurementHandling>,
+) -> Result<Json<DeletionRequest>, DeletionError> {
+    Ok(Json(
+        request_deletion(&db, &name, measurements, Utc::now()).await?,
+    ))
+}
+
+async fn cancel_user_deletion(
+    State(db): State<DataBase<impl Storage>>,
+    Path(name): Path<String>,
+) -> Result<StatusCode, DeletionError> {
+    log::info!("Deletion of user {} cancelled", name);
+    update_deletion(&db, &name, |deletion| *deletion = None).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn confirm_user_deletion(
+    State(db): State<DataBase<impl Storage>>,
+    Path(name): Path<String>,
+) -> Result<Json<DeletionRequest>, DeletionError> {
+    log::info!("Deletion of user {} confirmed", name);
+    update_deletion(&db, &name, |deletion| {
+        if let Some(deletion) = deletion {
+            deletion.confirmed = true;
+        }
+    })
+    .await?
+    .map(Json)
+    .ok_or(DeletionError::NotRequested)
+}
+
+async fn set_taught_groups(
+    State(db): State<DataBase<impl Storage>>,
+    Path(name): Path<String>,
+    Json(groups): Json<Vec<String>>,
+) -> Result<Json<User>, UserNotFound> {
+    log::info!("{} now teaches {:?}", name, groups);
+    Ok(Json(
+        update_user(&db, &name, |user| user.teaches = groups).await?,
+    ))
+}
+
+impl IntoResponse for DashboardError {
+    fn into_response(self) -> Response {
+        match self {
+            DashboardError::UserNotFound => UserNotFound.into_response(),
+            DashboardError::NotATeacher => (
+                StatusCode::FORBIDDEN,
+                "Only teachers have a dashboard".to_string(),
+            )
+                .into_response(),
+        }
+    }
+}
+
+async fn get_dashboard(
+    State(db): State<DataBase<impl Storage>>,
+    Path(name): Path<String>,
+) -> Result<Json<Vec<StudentProgress>>, DashboardError> {
+    let data_model = db
+        .get_data()
+        .await
+        .expect("As long as no one is manually editing the database, this should never fail.");
+    Ok(Json(class_progress(&data_model, &name)?))
+}
+
+async fn export_class(
+    State(db): State<DataBase<impl Storage>>,
+    Path(name): Path<String>,
+) -> Result<impl IntoResponse, DashboardError> {
+    let data_model = db
+        .get_data()
+        .await
+        .expect("As long as no one is manually editing the database, this should never fail.");
+    let export = export_class_data(&data_model, &name)?;
+    Ok((
+        [(
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}-students.json\"", name),
+        )],
+        Json(export),
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::database::create_in_memory_database;
+    use crate::test_utils::{send, with_token};
+    use crate::users::{ensure_user, Role};
+    use axum::{body::Body, http};
+    use tower::ServiceExt;
+
+    async fn post(app: Router, uri: &str) -> Response {
+        send(app, http::Method::POST, uri, String::new()).await
+    }
+
+    #[tokio::test]
+    async fn test_training_and_certification() {
+        let db = create_in_memory_database();
+        ensure_user(&db, "student").await.unwrap();
+
+        let response = post(routes(db.clone()), "/student/training/SafetyIntroduction").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = post(routes(db.clone()), "/student/certify").await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let user = fetch_user(&db, "student").await.unwrap();
+        assert!(user.certified);
+        assert_eq!(
+            user.completed_training,
+            vec![TrainingStep::SafetyIntroduction]
+        );
+
+        let response = post(routes(db), "/teacher/certify").await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_certification_needs_admin() {
+        let db = create_in_memory_database();
+        ensure_user(&db, "student").await.unwrap();
+        let token = issue_api_token(&db, "student", Utc::now())
+            .await
+            .unwrap()
+            .token;
+
+        let response = crate::create_router(Default::default(), db.clone())
+            .oneshot(
+                with_token(
+                    http::Method::POST,
+                    "/api/users/student/certify",
+                    Some(&token),
+                )
+                .body(Body::empty())
+                .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert!(!fetch_user(&db, "student").await.unwrap().certified);
+    }
+
+    #[tokio::test]
+    async fn test_export_only_for_self_or_admin() {
+        let db = create_in_memory_database();
+        db.update_data(|mut data_model| {
+            data_model.users = vec![
+                User {
+                    role: Role::Admin,
+                    ..User::new("anna")
+                },
+                User::new("bertil"),
+                User::new("cecilia"),
+            ];
+            data_model
+        })
+        .await
+        .unwrap();
+        let export = |user_name: Option<&str>| {
+            let db = db.clone();
+            let user_name = user_name.map(str::to_string);
+            async move {
+                let token = match user_name {
+                    Some(user_name) => Some(
+                        issue_api_token(&db, &user_name, Utc::now())
+                            .await
+                            .unwrap()
+                            .token,
+                    ),
+                    None => None,
+                };
+                let request = with_token(
+                    http::Method::GET,
+                    "/api/users/bertil/export",
+                    token.as_deref(),
+                );
+                crate::create_router(Default::default(), db)
+                    .oneshot(request.body(Body::empty()).unwrap())
+                    .await
+                    .unwrap()
+                    .status()
+            }
+        };
+
+        assert_eq!(export(None).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(export(Some("cecilia")).await, StatusCode::FORBIDDEN);
+        assert_eq!(export(Some("bertil")).await, StatusCode::OK);
+        assert_eq!(export(Some("anna")).await, StatusCode::OK);
+    }
+}
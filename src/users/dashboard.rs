@@ -0,0 +1,167 @@
+use crate::database::DataModel;
+use crate::sessions::SessionEventKind;
+use crate::users::privacy::{export_user_data, UserDataExport};
+use crate::users::tutorial::TutorialStep;
+use crate::users::{TrainingStep, User};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// How far a student in one of the teacher's groups has come.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct StudentProgress {
+    pub name: String,
+    pub group: String,
+    pub certified: bool,
+    pub completed_training: Vec<TrainingStep>,
+    pub tutorial_completed: bool,
+    pub sessions: usize,
+    /// Ids of the archived measurements saved by the student.
+    pub measurements: Vec<u64>,
+    pub last_observation: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum DashboardError {
+    UserNotFound,
+    NotATeacher,
+}
+
+fn teacher<'a>(data_model: &'a DataModel, name: &str) -> Result<&'a User, DashboardError> {
+    let user = data_model
+        .users
+        .iter()
+        .find(|u| u.name == name)
+        .ok_or(DashboardError::UserNotFound)?;
+    if user.teaches.is_empty() {
+        return Err(DashboardError::NotATeacher);
+    }
+    Ok(user)
+}
+
+/// The students in the groups taught by `teacher`, by group and name.
+fn students<'a>(data_model: &'a DataModel, teacher: &User) -> Vec<&'a User> {
+    let mut students: Vec<&User> = data_model
+        .users
+        .iter()
+        .filter(|u| u.name != teacher.name)
+        .filter(|u| {
+            u.group
+                .as_ref()
+                .is_some_and(|g| teacher.teaches.contains(g))
+        })
+        .collect();
+    students.sort_by(|a, b| (&a.group, &a.name).cmp(&(&b.group, &b.name)));
+    students
+}
+
+fn student_progress(data_model: &DataModel, student: &User) -> StudentProgress {
+    let sessions: Vec<_> = data_model
+        .sessions
+        .iter()
+        .filter(|s| s.user_name == student.name)
+        .collect();
+    let measurements = sessions
+        .iter()
+        .flat_map(|s| s.events.iter())
+        .filter_map(|e| match e.kind {
+            SessionEventKind::MeasurementSaved(id) => Some(id),
+            _ => None,
+        })
+        .filter(|id| data_model.measurements.iter().any(|m| m.id == *id))
+        .collect();
+    StudentProgress {
+        name: student.name.clone(),
+        group: student.group.clone().unwrap_or_default(),
+        certified: student.certified,
+        completed_training: student.completed_training.clone(),
+        tutorial_completed: student.tutorial == TutorialStep::Completed,
+        sessions: sessions.len(),
+        measurements,
+        last_observation: sessions.iter().map(|s| s.start_time).max(),
+    }
+}
+
+/// Progress of all students in the groups taught by `teacher_name`.
+pub fn class_progress(
+    data_model: &DataModel,
+    teacher_name: &str,
+) -> Result<Vec<StudentProgress>, DashboardError> {
+    let teacher = teacher(data_model, teacher_name)?;
+    Ok(students(data_model, teacher)
+        .into_iter()
+        .map(|student| student_progress(data_model, student))
+        .collect())
+}
+
+/// Everything stored about the students of `teacher_name`, for reviewing
+/// their data outside of SALSA.
+pub fn export_class_data(
+    data_model: &DataModel,
+    teacher_name: &str,
+) -> Result<Vec<UserDataExport>, DashboardError> {
+    let teacher = teacher(data_model, teacher_name)?;
+    Ok(students(data_model, teacher)
+        .into_iter()
+        .filter_map(|student| export_user_data(data_model, &student.name))
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::sessions::{RecordedSession, SessionEvent};
+
+    fn student(name: &str, group: &str) -> User {
+        let mut user = User::new(name);
+        user.group = Some(group.to_string());
+        user
+    }
+
+    #[test]
+    fn test_class_progress() {
+        let mut teacher = student("teacher", "3A");
+        teacher.teaches = vec!["3A".to_string(), "3B".to_string()];
+        let mut bertil = student("bertil", "3A");
+        bertil.completed_training = vec![TrainingStep::SafetyIntroduction];
+        let data_model = DataModel {
+            users: vec![
+                teacher,
+                bertil,
+                student("anna", "3B"),
+                student("cecilia", "4C"),
+                User::new("david"),
+            ],
+            sessions: vec![RecordedSession {
+                id: 1,
+                telescope_name: "fake".to_string(),
+                user_name: "bertil".to_string(),
+                start_time: Utc::now(),
+                end_time: Utc::now(),
+                events: vec![SessionEvent {
+                    time: Utc::now(),
+                    // Deleted from the archive, so not listed.
+                    kind: SessionEventKind::MeasurementSaved(3),
+                }],
+            }],
+            ..Default::default()
+        };
+
+        let progress = class_progress(&data_model, "teacher").unwrap();
+        let names: Vec<_> = progress.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, vec!["bertil", "anna"]);
+        assert_eq!(progress[0].completed_training.len(), 1);
+        assert_eq!(progress[0].sessions, 1);
+        assert!(progress[0].measurements.is_empty());
+        assert!(progress[0].last_observation.is_some());
+        assert_eq!(export_class_data(&data_model, "teacher").unwrap().len(), 2);
+
+        assert_eq!(
+            class_progress(&data_model, "anna"),
+            Err(DashboardError::NotATeacher)
+        );
+        assert_eq!(
+            class_progress(&data_model, "nobody"),
+            Err(DashboardError::UserNotFound)
+        );
+    }
+}
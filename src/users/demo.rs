@@ -0,0 +1,247 @@
+use crate::authentication::add_api_token;
+use crate::bookings::Booking;
+use crate::database::{DataBase, DataBaseError, DataModel, Storage};
+use crate::sessions::SessionEventKind;
+use crate::telescopes::TelescopeType;
+use crate::users::{User, UserKind};
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use std::collections::HashSet;
+
+/// How long a demo account lives before it is deleted with all its data.
+pub const DEMO_ACCOUNT_LIFETIME_HOURS: i64 = 3;
+/// Length of the booking made for a new demo account, and the longest
+/// booking a demo account may make.
+pub const DEMO_BOOKING_MINUTES: i64 = 30;
+/// Limit on the number of demo accounts that exist at the same time, to
+/// keep a busy outreach event from filling the database.
+pub const MAX_DEMO_ACCOUNTS: usize = 20;
+
+#[derive(Debug, PartialEq)]
+pub enum DemoError {
+    TooManyDemoAccounts,
+    ServiceUnavailable,
+}
+
+impl From<DataBaseError> for DemoError {
+    fn from(_source: DataBaseError) -> Self {
+        Self::ServiceUnavailable
+    }
+}
+
+/// A new demo account together with its booking, if a fake telescope was
+/// free, and the API token to use it with.
+#[derive(Serialize, Debug)]
+pub struct DemoAccount {
+    pub user: User,
+    pub booking: Option<Booking>,
+    pub token: String,
+}
+
+/// Create a temporary account and book the first free fake telescope for it.
+pub async fn create_demo_account(
+    db: &DataBase<impl Storage>,
+    now: DateTime<Utc>,
+) -> Result<DemoAccount, DemoError> {
+    let mut result = Err(DemoError::TooManyDemoAccounts);
+    db.update_data(|mut data_model| {
+        let demo_accounts = data_model.users.iter().filter(|u| u.is_demo()).count();
+        if demo_accounts >= MAX_DEMO_ACCOUNTS {
+            return data_model;
+        }
+        let number = (1..)
+            .find(|n| {
+                let name = format!("demo-{}", n);
+                !data_model.users.iter().any(|u| u.name == name)
+            })
+            .expect("There is always a free name");
+        let mut user = User::new(&format!("demo-{}", number));
+        user.created = now;
+        user.kind = UserKind::Demo {
+            expires: now + Duration::hours(DEMO_ACCOUNT_LIFETIME_HOURS),
+        };
+        let booking = free_fake_telescope(&data_model, now).map(|telescope_name| Booking {
+            start_time: now,
+            end_time: now + Duration::minutes(DEMO_BOOKING_MINUTES),
+            telescope_name,
+            user_name: user.name.clone(),
+        });
+        log::info!("Creating demo account {}", user.name);
+        data_model.users.push(user.clone());
+        if let Some(booking) = &booking {
+            data_model.bookings.push(booking.clone());
+        }
+        let token = add_api_token(&mut data_model, &user.name, now);
+        result = Ok(DemoAccount {
+            user,
+            booking,
+            token,
+        });
+        data_model
+    })
+    .await?;
+    result
+}
+
+fn free_fake_telescope(data_model: &DataModel, now: DateTime<Utc>) -> Option<String> {
+    let slot = Booking {
+        start_time: now,
+        end_time: now + Duration::minutes(DEMO_BOOKING_MINUTES),
+        telescope_name: String::new(),
+        user_name: String::new(),
+    };
+    data_model
+        .telescopes
+        .iter()
+        .filter(|t| t.enabled && matches!(t.telescope_type, TelescopeType::Fake { .. }))
+        .find(|t| {
+            !data_model
+                .bookings
+                .iter()
+                .any(|b| b.telescope_name == t.name && b.overlaps(&slot))
+        })
+        .map(|t| t.name.clone())
+}
+
+/// Demo accounts may only hold one short booking at a time, which has to
+/// end before the account expires.
+pub fn demo_booking_allowed(
+    data_model: &DataModel,
+    user: &User,
+    booking: &Booking,
+    now: DateTime<Utc>,
+) -> bool {
+    let UserKind::Demo { expires } = user.kind else {
+        return true;
+    };
+    booking.end_time <= expires
+        && booking.end_time - booking.start_time <= Duration::minutes(DEMO_BOOKING_MINUTES)
+        && !data_model
+            .bookings
+            .iter()
+            .any(|b| b.user_name == user.name && b.end_time > now)
+}
+
+/// Remove expired demo accounts along with their bookings, sessions and the
+/// measurements saved during those sessions.
+pub fn remove_expired_demo_accounts(mut data_model: DataModel, now: DateTime<Utc>) -> DataModel {
+    let expired: HashSet<String> = data_model
+        .users
+        .iter()
+        .filter(|u| matches!(u.kind, UserKind::Demo { expires } if expires <= now))
+        .map(|u| u.name.clone())
+        .collect();
+    if expired.is_empty() {
+        return data_model;
+    }
+    log::info!("Removing expired demo accounts {:?}", expired);
+    let measurements: HashSet<u64> = data_model
+        .sessions
+        .iter()
+        .filter(|s| expired.contains(&s.user_name))
+        .flat_map(|s| s.events.iter())
+        .filter_map(|e| match e.kind {
+            SessionEventKind::MeasurementSaved(id) => Some(id),
+            _ => None,
+        })
+        .collect();
+    data_model.users.retain(|u| !expired.contains(&u.name));
+    data_model
+        .api_tokens
+        .retain(|t| !expired.contains(&t.user_name));
+    data_model
+        .bookings
+        .retain(|b| !expired.contains(&b.user_name));
+    data_model
+        .sessions
+        .retain(|s| !expired.contains(&s.user_name));
+    data_model
+        .measurements
+        .retain(|m| !measurements.contains(&m.id));
+    data_model
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::angles::Degrees;
+    use crate::archive::test_utils::galactic_measurement;
+    use crate::archive::ArchivedMeasurement;
+    use crate::authentication::token_user;
+    use crate::database::create_in_memory_database;
+    use crate::sessions::{RecordedSession, SessionEvent};
+    use crate::telescopes::test_utils::fake_telescope;
+
+    #[tokio::test]
+    async fn test_demo_account_lifecycle() {
+        let db = create_in_memory_database();
+        let now = Utc::now();
+        db.update_data(|mut data_model| {
+            data_model.telescopes.push(fake_telescope("fake"));
+            data_model
+        })
+        .await
+        .unwrap();
+
+        let first = create_demo_account(&db, now).await.unwrap();
+        let second = create_demo_account(&db, now).await.unwrap();
+        assert_eq!(first.user.name, "demo-1");
+        assert_eq!(
+            token_user(&db.get_data().await.unwrap(), &first.token),
+            Some("demo-1".to_string())
+        );
+        assert_eq!(first.booking.unwrap().telescope_name, "fake");
+        // The only fake telescope is already booked by the first account.
+        assert_eq!(second.booking, None);
+
+        let data_model = db.get_data().await.unwrap();
+        let booking = Booking {
+            start_time: now + Duration::hours(1),
+            end_time: now + Duration::hours(1) + Duration::minutes(10),
+            telescope_name: "fake".to_string(),
+            user_name: "demo-1".to_string(),
+        };
+        assert!(!demo_booking_allowed(
+            &data_model,
+            &first.user,
+            &booking,
+            now
+        ));
+        assert!(demo_booking_allowed(
+            &data_model,
+            &second.user,
+            &booking,
+            now
+        ));
+
+        db.update_data(|mut data_model| {
+            data_model.measurements.push(ArchivedMeasurement::new(
+                1,
+                "fake",
+                galactic_measurement(Degrees(0.0), now),
+            ));
+            data_model.sessions.push(RecordedSession {
+                id: 1,
+                telescope_name: "fake".to_string(),
+                user_name: "demo-1".to_string(),
+                start_time: now,
+                end_time: now,
+                events: vec![SessionEvent {
+                    time: now,
+                    kind: SessionEventKind::MeasurementSaved(1),
+                }],
+            });
+            data_model
+        })
+        .await
+        .unwrap();
+
+        let later = now + Duration::hours(DEMO_ACCOUNT_LIFETIME_HOURS);
+        let data_model = remove_expired_demo_accounts(db.get_data().await.unwrap(), later);
+        assert!(data_model.users.is_empty());
+        assert!(data_model.bookings.is_empty());
+        assert!(data_model.sessions.is_empty());
+        assert!(data_model.measurements.is_empty());
+        assert!(data_model.api_tokens.is_empty());
+    }
+}
@@ -0,0 +1,343 @@
+use crate::bookings::api_routes::add_booking;
+use crate::bookings::{AddBookingResult, Booking};
+use crate::database::{DataBase, DataBaseError, Storage};
+use crate::users::User;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Columns that may be used in an import. Only `name` is required, and the
+/// booking columns have to be given together.
+pub const IMPORT_COLUMNS: [&str; 6] = ["name", "email", "external_id", "telescope", "start", "end"];
+
+/// A class of students to import.
+///
+/// The CSV has a header row naming the columns, followed by one student per
+/// row. Values are separated by commas and can not be quoted. Start and end
+/// of bookings are given in RFC 3339, e.g. `2024-03-01T13:00:00Z`.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ImportRequest {
+    #[serde(default)]
+    pub group: Option<String>,
+    pub csv: String,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub enum ImportOutcome {
+    Created,
+    /// An existing account was found and updated instead of creating a new.
+    Updated,
+    Skipped(String),
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct ImportedRow {
+    /// Line in the CSV, starting from 1 for the header.
+    pub line: usize,
+    /// Name of the account, which is the existing name if one was found.
+    pub name: String,
+    pub outcome: ImportOutcome,
+    pub booking: Option<AddBookingResult>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub enum ImportError {
+    UnknownColumn(String),
+    MissingNameColumn,
+    InvalidRow { line: usize, message: String },
+    ServiceUnavailable,
+}
+
+impl From<DataBaseError> for ImportError {
+    fn from(_source: DataBaseError) -> Self {
+        Self::ServiceUnavailable
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct StudentRow {
+    line: usize,
+    name: String,
+    email: Option<String>,
+    external_id: Option<String>,
+    lab_session: Option<(String, DateTime<Utc>, DateTime<Utc>)>,
+}
+
+fn parse_csv(csv: &str) -> Result<Vec<StudentRow>, ImportError> {
+    let mut lines = csv
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty());
+    let header: Vec<String> = match lines.next() {
+        Some((_, header)) => header.split(',').map(|c| c.trim().to_lowercase()).collect(),
+        None => return Ok(vec![]),
+    };
+    if let Some(column) = header
+        .iter()
+        .find(|c| !IMPORT_COLUMNS.contains(&c.as_str()))
+    {
+        return Err(ImportError::UnknownColumn(column.clone()));
+    }
+    if !header.iter().any(|c| c == "name") {
+        return Err(ImportError::MissingNameColumn);
+    }
+
+    lines
+        .map(|(line, text)| {
+            let invalid = |message: String| ImportError::InvalidRow { line, message };
+            let values: Vec<&str> = text.split(',').map(str::trim).collect();
+            if values.len() != header.len() {
+                return Err(invalid(format!(
+                    "expected {} values, found {}",
+                    header.len(),
+                    values.len()
+                )));
+            }
+            let row: HashMap<&str, &str> = header
+                .iter()
+                .map(String::as_str)
+                .zip(values)
+                .filter(|(_, value)| !value.is_empty())
+                .collect();
+            let time = |column: &str| {
+                row.get(column)
+                    .map(|value| {
+                        DateTime::parse_from_rfc3339(value)
+                            .map(|time| time.with_timezone(&Utc))
+                            .map_err(|error| invalid(format!("invalid {}: {}", column, error)))
+                    })
+                    .transpose()
+            };
+            let lab_session = match (row.get("telescope"), time("start")?, time("end")?) {
+                (Some(telescope), Some(start), Some(end)) => {
+                    Some((telescope.to_string(), start, end))
+                }
+                (None, None, None) => None,
+                _ => {
+                    return Err(invalid(
+                        "telescope, start and end have to be given together".to_string(),
+                    ))
+                }
+            };
+            Ok(StudentRow {
+                line,
+                name: row
+                    .get("name")
+                    .ok_or_else(|| invalid("name is missing".to_string()))?
+                    .to_string(),
+                email: row.get("email").map(|email| email.to_lowercase()),
+                external_id: row.get("external_id").map(ToString::to_string),
+                lab_session,
+            })
+        })
+        .collect()
+}
+
+/// Find the account a row refers to.
+///
+/// Email and external id identify a person, so a match on them wins even if
+/// the name differs. A matching name is only used if it does not belong to
+/// someone with another email or external id.
+fn find_user(users: &[User], row: &StudentRow) -> Result<Option<usize>, String> {
+    let same = |a: &Option<String>, b: &Option<String>| a.is_some() && a == b;
+    if let Some(index) = users
+        .iter()
+        .position(|u| same(&u.external_id, &row.external_id) || same(&u.email, &row.email))
+    {
+        return Ok(Some(index));
+    }
+    match users.iter().position(|u| u.name == row.name) {
+        Some(index) => {
+            let user = &users[index];
+            let conflicting =
+                |a: &Option<String>, b: &Option<String>| a.is_some() && b.is_some() && a != b;
+            if conflicting(&user.email, &row.email)
+                || conflicting(&user.external_id, &row.external_id)
+            {
+                Err(format!("name {} is used by another person", row.name))
+            } else {
+                Ok(Some(index))
+            }
+        }
+        None => Ok(None),
+    }
+}
+
+fn is_same_student(a: &StudentRow, b: &StudentRow) -> bool {
+    a.name == b.name
+        || (a.email.is_some() && a.email == b.email)
+        || (a.external_id.is_some() && a.external_id == b.external_id)
+}
+
+fn import_row(users: &mut Vec<User>, row: &StudentRow, group: &Option<String>) -> ImportedRow {
+    let skipped = |name: &str, reason: String| ImportedRow {
+        line: row.line,
+        name: name.to_string(),
+        outcome: ImportOutcome::Skipped(reason),
+        booking: None,
+    };
+    let (index, outcome) = match find_user(users, row) {
+        Ok(Some(index)) if users[index].is_demo() => {
+            return skipped(
+                &row.name,
+                format!("{} is a demo account", users[index].name),
+            );
+        }
+        Ok(Some(index)) => (index, ImportOutcome::Updated),
+        Ok(None) => {
+            users.push(User::new(&row.name));
+            (users.len() - 1, ImportOutcome::Created)
+        }
+        Err(reason) => return skipped(&row.name, reason),
+    };
+    let user = &mut users[index];
+    if row.email.is_some() {
+        user.email = row.email.clone();
+    }
+    if row.external_id.is_some() {
+        user.external_id = row.external_id.clone();
+    }
+    if group.is_some() {
+        user.group = group.clone();
+    }
+    ImportedRow {
+        line: row.line,
+        name: user.name.clone(),
+        outcome,
+        booking: None,
+    }
+}
+
+/// Create or update an account for every student in the CSV and book their
+/// lab sessions.
+///
+/// Nothing is imported if the CSV can not be parsed. Rows that can not be
+/// imported, e.g. because they appear twice, are skipped and reported.
+pub async fn import_users(
+    db: &DataBase<impl Storage>,
+    request: ImportRequest,
+) -> Result<Vec<ImportedRow>, ImportError> {
+    let rows = parse_csv(&request.csv)?;
+    let mut imported: Vec<ImportedRow> = vec![];
+    db.update_data(|mut data_model| {
+        for row in &rows {
+            let duplicate = imported
+                .iter()
+                .zip(rows.iter())
+                .find(|(previous, previous_row)| {
+                    !matches!(previous.outcome, ImportOutcome::Skipped(_))
+                        && is_same_student(previous_row, row)
+                });
+            let result = match duplicate {
+                Some((previous, _)) => ImportedRow {
+                    line: row.line,
+                    name: row.name.clone(),
+                    outcome: ImportOutcome::Skipped(format!("duplicate of line {}", previous.line)),
+                    booking: None,
+                },
+                None => import_row(&mut data_model.users, row, &request.group),
+            };
+            imported.push(result);
+        }
+        data_model
+    })
+    .await?;
+
+    for (result, row) in imported.iter_mut().zip(rows) {
+        if matches!(result.outcome, ImportOutcome::Skipped(_)) {
+            continue;
+        }
+        if let Some((telescope_name, start_time, end_time)) = row.lab_session {
+            let booking = Booking {
+                start_time,
+                end_time,
+                telescope_name,
+                user_name: result.name.clone(),
+            };
+            result.booking = Some(add_booking(db.clone(), booking).await);
+        }
+    }
+    log::info!("Imported {} users", imported.len());
+    Ok(imported)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bookings::AddBookingError;
+    use crate::database::create_in_memory_database;
+
+    #[tokio::test]
+    async fn test_import_users() {
+        let db = create_in_memory_database();
+        db.update_data(|mut data_model| {
+            let mut existing = User::new("anna");
+            existing.email = Some("anna@example.com".to_string());
+            data_model.users.push(existing);
+            data_model
+        })
+        .await
+        .unwrap();
+
+        let csv = "name,email,telescope,start,end\n\
+                   Anna L,ANNA@example.com,,,\n\
+                   bertil,,fake,2024-03-01T13:00:00Z,2024-03-01T14:00:00Z\n\
+                   \n\
+                   bertil,,,,\n\
+                   anna,anna.b@example.com,,,\n\
+                   cecilia,,fake,2024-03-01T13:30:00Z,2024-03-01T14:30:00Z\n";
+        let imported = import_users(
+            &db,
+            ImportRequest {
+                group: Some("Class 3A".to_string()),
+                csv: csv.to_string(),
+            },
+        )
+        .await
+        .unwrap();
+
+        let outcomes: Vec<_> = imported
+            .iter()
+            .map(|row| (row.line, row.name.as_str(), row.outcome.clone()))
+            .collect();
+        assert_eq!(
+            outcomes,
+            vec![
+                (2, "anna", ImportOutcome::Updated),
+                (3, "bertil", ImportOutcome::Created),
+                (
+                    5,
+                    "bertil",
+                    ImportOutcome::Skipped("duplicate of line 3".to_string())
+                ),
+                (
+                    6,
+                    "anna",
+                    ImportOutcome::Skipped("name anna is used by another person".to_string())
+                ),
+                (7, "cecilia", ImportOutcome::Created),
+            ]
+        );
+        assert_eq!(imported[1].booking, Some(Ok(1)));
+        assert_eq!(imported[4].booking, Some(Err(AddBookingError::Conflict)));
+
+        let users = db.get_data().await.unwrap().users;
+        assert_eq!(users.len(), 3);
+        assert!(users
+            .iter()
+            .all(|u| u.group == Some("Class 3A".to_string())));
+
+        assert_eq!(
+            parse_csv("email\nanna@example.com"),
+            Err(ImportError::MissingNameColumn)
+        );
+        assert_eq!(
+            parse_csv("name,start\nanna,2024-03-01T13:00:00Z"),
+            Err(ImportError::InvalidRow {
+                line: 2,
+                message: "telescope, start and end have to be given together".to_string()
+            })
+        );
+    }
+}
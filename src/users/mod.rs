@@ -0,0 +1,382 @@
+use crate::archive::files::{delete_measurement_files, track_removed_measurements};
+use crate::bookings::{current_booking, AddBookingError, Booking};
+use crate::database::{DataBase, DataModel, Storage};
+use crate::telescopes::TelescopeType;
+use crate::users::demo::{demo_booking_allowed, remove_expired_demo_accounts};
+use crate::users::privacy::{delete_confirmed_accounts, DeletionRequest};
+use crate::users::tutorial::TutorialStep;
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+pub mod api_routes;
+pub mod dashboard;
+pub mod demo;
+pub mod import;
+pub mod privacy;
+pub mod routes;
+pub mod tutorial;
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+pub enum TrainingStep {
+    SafetyIntroduction,
+    FakeTelescopePointing,
+    FakeTelescopeIntegration,
+    SupervisedObservation,
+}
+
+impl TrainingStep {
+    pub const ALL: [TrainingStep; 4] = [
+        TrainingStep::SafetyIntroduction,
+        TrainingStep::FakeTelescopePointing,
+        TrainingStep::FakeTelescopeIntegration,
+        TrainingStep::SupervisedObservation,
+    ];
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            TrainingStep::SafetyIntroduction => "Read the introduction to safe telescope operation",
+            TrainingStep::FakeTelescopePointing => "Point the fake telescope at a galactic target",
+            TrainingStep::FakeTelescopeIntegration => "Make an integration with the fake telescope",
+            TrainingStep::SupervisedObservation => {
+                "Observe with a real telescope together with a certified user"
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone, Default)]
+pub enum UserKind {
+    #[default]
+    Regular,
+    /// Temporary account for trying the fake telescope, deleted with all its
+    /// data when it expires.
+    Demo { expires: DateTime<Utc> },
+}
+
+/// What a user may do, beyond observing with the telescopes they book.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone, Default)]
+pub enum Role {
+    /// May restart telescopes, change their configuration and change the
+    /// bookings of other users. Appointed by editing the database.
+    Admin,
+    #[default]
+    Observer,
+    /// May only use the fake telescopes, whether certified or not.
+    Guest,
+}
+
+/// A user of the telescopes, identified by the name used in bookings.
+///
+/// Users are created the first time they book a telescope and may only use
+/// fake telescopes until an admin has certified them.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct User {
+    pub name: String,
+    pub created: DateTime<Utc>,
+    #[serde(default)]
+    pub certified: bool,
+    #[serde(default)]
+    pub completed_training: Vec<TrainingStep>,
+    #[serde(default)]
+    pub tutorial: TutorialStep,
+    #[serde(default)]
+    pub kind: UserKind,
+    #[serde(default)]
+    pub email: Option<String>,
+    /// Id of the person in an external system, e.g. the school's.
+    #[serde(default)]
+    pub external_id: Option<String>,
+    /// Class or course the user belongs to.
+    #[serde(default)]
+    pub group: Option<String>,
+    #[serde(default)]
+    pub deletion: Option<DeletionRequest>,
+    /// Groups whose students the user follows as a teacher.
+    #[serde(default)]
+    pub teaches: Vec<String>,
+    #[serde(default)]
+    pub role: Role,
+}
+
+impl User {
+    pub fn new(name: &str) -> User {
+        User {
+            name: name.to_string(),
+            created: Utc::now(),
+            certified: false,
+            completed_training: vec![],
+            tutorial: TutorialStep::default(),
+            kind: UserKind::Regular,
+            email: None,
+            external_id: None,
+            group: None,
+            deletion: None,
+            teaches: vec![],
+            role: Role::Observer,
+        }
+    }
+
+    pub fn is_demo(&self) -> bool {
+        matches!(self.kind, UserKind::Demo { .. })
+    }
+
+    pub fn is_admin(&self) -> bool {
+        self.role == Role::Admin
+    }
+}
+
+/// Why a requester may not control a real telescope.
+#[derive(Debug, PartialEq)]
+pub enum ControlNotAllowed {
+    /// The requester has no booking of the telescope going on.
+    NotBooked(String),
+    /// The requester has to be certified by an admin first.
+    NotCertified(String),
+}
+
+impl IntoResponse for ControlNotAllowed {
+    fn into_response(self) -> Response {
+        let message = match self {
+            ControlNotAllowed::NotBooked(telescope_name) => {
+                format!("Book {} to control it", telescope_name)
+            }
+            ControlNotAllowed::NotCertified(user_name) => format!(
+                "{} has to be certified by an admin before using real telescopes",
+                user_name
+            ),
+        };
+        (StatusCode::FORBIDDEN, message).into_response()
+    }
+}
+
+pub fn is_certified(data_model: &DataModel, user_name: &str) -> bool {
+    data_model
+        .users
+        .iter()
+        .any(|u| u.name == user_name && u.certified && !u.is_demo() && u.role != Role::Guest)
+}
+
+pub fn is_admin(data_model: &DataModel, user_name: &str) -> bool {
+    data_model
+        .users
+        .iter()
+        .any(|u| u.name == user_name && u.is_admin())
+}
+
+/// Bookings may be changed by the user who made them and by admins.
+pub fn may_change_booking(data_model: &DataModel, user_name: &str, booking: &Booking) -> bool {
+    booking.user_name == user_name || is_admin(data_model, user_name)
+}
+
+/// Real telescopes can be damaged by careless use, while fake telescopes
+/// can be used by anyone.
+fn is_hardware(data_model: &DataModel, telescope_name: &str) -> bool {
+    data_model.telescopes.iter().any(|t| {
+        t.name == telescope_name && matches!(t.telescope_type, TelescopeType::Salsa { .. })
+    })
+}
+
+/// Uncertified users may only book fake telescopes, and demo accounts only
+/// within their limits. Bookings of users other than admins have to follow
+/// the booking policy.
+pub fn check_booking_allowed(
+    data_model: &DataModel,
+    booking: &Booking,
+) -> Result<(), AddBookingError> {
+    if is_hardware(data_model, &booking.telescope_name)
+        && !is_certified(data_model, &booking.user_name)
+    {
+        return Err(AddBookingError::NotCertified);
+    }
+    if !is_admin(data_model, &booking.user_name) {
+        data_model
+            .booking_policy
+            .check(&data_model.bookings, booking, Utc::now())?;
+    }
+    let user = data_model
+        .users
+        .iter()
+        .find(|u| u.name == booking.user_name);
+    if let Some(user) = user {
+        if !demo_booking_allowed(data_model, user, booking, Utc::now()) {
+            return Err(AddBookingError::DemoLimitExceeded);
+        }
+    }
+    Ok(())
+}
+
+/// Only admins, and certified users during their booking of it, may
+/// control a real telescope.
+pub fn check_control_allowed(
+    data_model: &DataModel,
+    telescope_name: &str,
+    user_name: &str,
+    now: DateTime<Utc>,
+) -> Result<(), ControlNotAllowed> {
+    if !is_hardware(data_model, telescope_name) || is_admin(data_model, user_name) {
+        return Ok(());
+    }
+    match current_booking(&data_model.bookings, telescope_name, now) {
+        Some(booking) if !user_name.is_empty() && booking.user_name == user_name => {
+            if is_certified(data_model, user_name) {
+                Ok(())
+            } else {
+                Err(ControlNotAllowed::NotCertified(user_name.to_string()))
+            }
+        }
+        _ => Err(ControlNotAllowed::NotBooked(telescope_name.to_string())),
+    }
+}
+
+/// Create the user if it does not exist yet.
+#[cfg(test)]
+pub async fn ensure_user(
+    db: &DataBase<impl Storage>,
+    user_name: &str,
+) -> Result<(), crate::database::DataBaseError> {
+    if db
+        .get_data()
+        .await?
+        .users
+        .iter()
+        .any(|u| u.name == user_name)
+    {
+        return Ok(());
+    }
+    db.update_data(|mut data_model| {
+        if !data_model.users.iter().any(|u| u.name == user_name) {
+            log::info!("Creating user {}", user_name);
+            data_model.users.push(User::new(user_name));
+        }
+        data_model
+    })
+    .await
+}
+
+/// How often expired demo accounts and accounts due for deletion are
+/// removed.
+pub const ACCOUNT_CLEANUP_INTERVAL: std::time::Duration = std::time::Duration::from_secs(600);
+
+pub fn start_account_cleanup_service<T>(database: DataBase<T>) -> tokio::task::JoinHandle<()>
+where
+    T: Storage + 'static,
+{
+    tokio::spawn(async move {
+        loop {
+            let now = Utc::now();
+            let mut removed = Vec::new();
+            if let Err(error) = database
+                .update_data(|data_model| {
+                    track_removed_measurements(
+                        data_model,
+                        |data_model| {
+                            delete_confirmed_accounts(
+                                remove_expired_demo_accounts(data_model, now),
+                                now,
+                            )
+                        },
+                        &mut removed,
+                    )
+                })
+                .await
+            {
+                log::error!("Failed to remove accounts: {}", error);
+            }
+            delete_measurement_files(&database, removed).await;
+            tokio::time::sleep(ACCOUNT_CLEANUP_INTERVAL).await;
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::telescopes::test_utils::salsa_telescope;
+
+    #[test]
+    fn test_check_booking_allowed() {
+        let mut data_model = DataModel {
+            telescopes: vec![salsa_telescope("brage")],
+            users: vec![User::new("student")],
+            ..Default::default()
+        };
+        let booking = |telescope_name: &str| Booking {
+            start_time: Utc::now(),
+            end_time: Utc::now(),
+            telescope_name: telescope_name.to_string(),
+            user_name: "student".to_string(),
+        };
+        assert_eq!(
+            check_booking_allowed(&data_model, &booking("brage")),
+            Err(AddBookingError::NotCertified)
+        );
+        assert_eq!(check_booking_allowed(&data_model, &booking("fake")), Ok(()));
+
+        data_model.users[0].certified = true;
+        assert_eq!(
+            check_booking_allowed(&data_model, &booking("brage")),
+            Ok(())
+        );
+
+        data_model.users[0].role = Role::Guest;
+        assert_eq!(
+            check_booking_allowed(&data_model, &booking("brage")),
+            Err(AddBookingError::NotCertified)
+        );
+    }
+
+    #[test]
+    fn test_check_control_allowed() {
+        let now = Utc::now();
+        let mut data_model = DataModel {
+            telescopes: vec![salsa_telescope("brage")],
+            users: vec![
+                User {
+                    role: Role::Admin,
+                    ..User::new("anna")
+                },
+                User::new("student"),
+                User::new("bertil"),
+            ],
+            bookings: vec![Booking {
+                start_time: now - chrono::Duration::hours(1),
+                end_time: now + chrono::Duration::hours(1),
+                telescope_name: "brage".to_string(),
+                user_name: "student".to_string(),
+            }],
+            ..Default::default()
+        };
+        let check = |data_model: &DataModel, user_name| {
+            check_control_allowed(data_model, "brage", user_name, now)
+        };
+        assert_eq!(
+            check(&data_model, "student"),
+            Err(ControlNotAllowed::NotCertified("student".to_string()))
+        );
+        data_model.users[1].certified = true;
+        data_model.users[2].certified = true;
+        assert_eq!(check(&data_model, "student"), Ok(()));
+        // Being certified is not enough without the booking.
+        assert_eq!(
+            check(&data_model, "bertil"),
+            Err(ControlNotAllowed::NotBooked("brage".to_string()))
+        );
+        assert_eq!(
+            check(&data_model, ""),
+            Err(ControlNotAllowed::NotBooked("brage".to_string()))
+        );
+        assert_eq!(check(&data_model, "anna"), Ok(()));
+        assert_eq!(check_control_allowed(&data_model, "fake", "", now), Ok(()));
+
+        data_model.bookings.clear();
+        assert_eq!(
+            check(&data_model, "student"),
+            Err(ControlNotAllowed::NotBooked("brage".to_string()))
+        );
+        assert_eq!(check(&data_model, "anna"), Ok(()));
+    }
+}
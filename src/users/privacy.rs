@@ -0,0 +1,334 @@
+use crate::archive::ArchivedMeasurementSummary;
+use crate::bookings::Booking;
+use crate::database::{DataBase, DataBaseError, DataModel, Storage};
+use crate::sessions::SessionEventKind;
+use crate::trash::TrashedEntry;
+use crate::users::User;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+/// Time from a deletion request until the account is deleted, during which
+/// the user can change their mind.
+pub const DELETION_GRACE_PERIOD_DAYS: i64 = 14;
+/// Name put in place of the user in bookings that are kept after deletion.
+pub const DELETED_USER_NAME: &str = "deleted user";
+
+/// What to do with the measurements of a deleted account.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Default)]
+pub enum MeasurementHandling {
+    #[default]
+    Delete,
+    /// Give the measurements, and the sessions they were made in, to
+    /// another user, e.g. the teacher of a class.
+    TransferTo(String),
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct DeletionRequest {
+    pub requested: DateTime<Utc>,
+    #[serde(default)]
+    pub measurements: MeasurementHandling,
+    /// Accounts are only deleted once an admin has confirmed the request.
+    #[serde(default)]
+    pub confirmed: bool,
+}
+
+impl DeletionRequest {
+    pub fn deletion_time(&self) -> DateTime<Utc> {
+        self.requested + Duration::days(DELETION_GRACE_PERIOD_DAYS)
+    }
+}
+
+/// A session without its events, which are mostly spectra.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct SessionSummary {
+    pub id: u64,
+    pub telescope_name: String,
+    pub start_time: DateTime<Utc>,
+    pub end_time: DateTime<Utc>,
+}
+
+/// Everything stored about a user.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct UserDataExport {
+    pub user: User,
+    pub bookings: Vec<Booking>,
+    pub sessions: Vec<SessionSummary>,
+    pub measurements: Vec<ArchivedMeasurementSummary>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum DeletionError {
+    UserNotFound,
+    UnknownTransferUser(String),
+    NotRequested,
+    ServiceUnavailable,
+}
+
+impl From<DataBaseError> for DeletionError {
+    fn from(_source: DataBaseError) -> Self {
+        Self::ServiceUnavailable
+    }
+}
+
+/// Ids of the archived measurements saved in the sessions of a user.
+pub fn measurement_ids(data_model: &DataModel, user_name: &str) -> HashSet<u64> {
+    data_model
+        .sessions
+        .iter()
+        .filter(|s| s.user_name == user_name)
+        .flat_map(|s| s.events.iter())
+        .filter_map(|e| match e.kind {
+            SessionEventKind::MeasurementSaved(id) => Some(id),
+            _ => None,
+        })
+        .collect()
+}
+
+pub fn export_user_data(data_model: &DataModel, user_name: &str) -> Option<UserDataExport> {
+    let user = data_model.users.iter().find(|u| u.name == user_name)?;
+    let measurements = measurement_ids(data_model, user_name);
+    Some(UserDataExport {
+        user: user.clone(),
+        bookings: data_model
+            .bookings
+            .iter()
+            .filter(|b| b.user_name == user_name)
+            .cloned()
+            .collect(),
+        sessions: data_model
+            .sessions
+            .iter()
+            .filter(|s| s.user_name == user_name)
+            .map(|s| SessionSummary {
+                id: s.id,
+                telescope_name: s.telescope_name.clone(),
+                start_time: s.start_time,
+                end_time: s.end_time,
+            })
+            .collect(),
+        measurements: data_model
+            .measurements
+            .iter()
+            .filter(|m| measurements.contains(&m.id))
+            .map(|m| m.summary())
+            .collect(),
+    })
+}
+
+/// Ask for the account to be deleted once the grace period has passed and
+/// an admin has confirmed it.
+pub async fn request_deletion(
+    db: &DataBase<impl Storage>,
+    user_name: &str,
+    measurements: MeasurementHandling,
+    now: DateTime<Utc>,
+) -> Result<DeletionRequest, DeletionError> {
+    let data_model = db.get_data().await?;
+    if !data_model.users.iter().any(|u| u.name == user_name) {
+        return Err(DeletionError::UserNotFound);
+    }
+    if let MeasurementHandling::TransferTo(receiver) = &measurements {
+        if receiver == user_name || !data_model.users.iter().any(|u| &u.name == receiver) {
+            return Err(DeletionError::UnknownTransferUser(receiver.clone()));
+        }
+    }
+    let request = DeletionRequest {
+        requested: now,
+        measurements,
+        confirmed: false,
+    };
+    log::info!("Deletion of user {} requested", user_name);
+    update_deletion(db, user_name, |deletion| *deletion = Some(request.clone())).await?;
+    Ok(request)
+}
+
+/// Apply `f` to the deletion request of a user, and return the result.
+pub async fn update_deletion(
+    db: &DataBase<impl Storage>,
+    user_name: &str,
+    f: impl FnOnce(&mut Option<DeletionRequest>),
+) -> Result<Option<DeletionRequest>, DeletionError> {
+    let mut result = Err(DeletionError::UserNotFound);
+    db.update_data(|mut data_model| {
+        if let Some(user) = data_model.users.iter_mut().find(|u| u.name == user_name) {
+            f(&mut user.deletion);
+            result = Ok(user.deletion.clone());
+        }
+        data_model
+    })
+    .await?;
+    result
+}
+
+/// Delete the accounts whose deletion has been confirmed and whose grace
+/// period has passed.
+///
+/// Past bookings are kept for the statistics of the telescopes, but without
+/// the name, while future bookings are cancelled to free the telescope.
+/// Deleted bookings of the user are removed from the trash right away.
+pub fn delete_confirmed_accounts(mut data_model: DataModel, now: DateTime<Utc>) -> DataModel {
+    let due: Vec<(String, MeasurementHandling)> = data_model
+        .users
+        .iter()
+        .filter_map(|u| match &u.deletion {
+            Some(deletion) if deletion.confirmed && deletion.deletion_time() <= now => {
+                Some((u.name.clone(), deletion.measurements.clone()))
+            }
+            _ => None,
+        })
+        .collect();
+    for (user_name, measurements) in due {
+        log::info!("Deleting user {}", user_name);
+        match measurements {
+            MeasurementHandling::Delete => {
+                let ids = measurement_ids(&data_model, &user_name);
+                data_model.measurements.retain(|m| !ids.contains(&m.id));
+                data_model
+                    .trash
+                    .retain(|t| !t.measurement_id().is_some_and(|id| ids.contains(&id)));
+                data_model.sessions.retain(|s| s.user_name != user_name);
+            }
+            MeasurementHandling::TransferTo(receiver) => {
+                data_model.sessions.retain(|s| {
+                    s.user_name != user_name
+                        || s.events
+                            .iter()
+                            .any(|e| matches!(e.kind, SessionEventKind::MeasurementSaved(_)))
+                });
+                for session in &mut data_model.sessions {
+                    if session.user_name == user_name {
+                        session.user_name = receiver.clone();
+                    }
+                }
+            }
+        }
+        data_model
+            .bookings
+            .retain(|b| b.user_name != user_name || b.start_time < now);
+        data_model.trash.retain(|t| match &t.entry {
+            TrashedEntry::Booking(booking) => booking.user_name != user_name,
+            TrashedEntry::Measurement(_) => true,
+        });
+        for booking in &mut data_model.bookings {
+            if booking.user_name == user_name {
+                booking.user_name = DELETED_USER_NAME.to_string();
+            }
+        }
+        for change in &mut data_model.booking_changes {
+            if change.booking.user_name == user_name {
+                change.booking.user_name = DELETED_USER_NAME.to_string();
+            }
+            if change.changed_by == user_name {
+                change.changed_by = DELETED_USER_NAME.to_string();
+            }
+        }
+        data_model
+            .calendar_tokens
+            .retain(|t| t.user_name != user_name);
+        data_model.api_tokens.retain(|t| t.user_name != user_name);
+        data_model
+            .confirmation_secrets
+            .retain(|s| s.user_name != user_name);
+        data_model.users.retain(|u| u.name != user_name);
+    }
+    data_model
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::angles::Degrees;
+    use crate::archive::test_utils::galactic_measurement;
+    use crate::archive::ArchivedMeasurement;
+    use crate::database::create_in_memory_database;
+    use crate::sessions::{RecordedSession, SessionEvent};
+
+    fn session(id: u64, user_name: &str, measurement: Option<u64>) -> RecordedSession {
+        RecordedSession {
+            id,
+            telescope_name: "fake".to_string(),
+            user_name: user_name.to_string(),
+            start_time: Utc::now(),
+            end_time: Utc::now(),
+            events: measurement
+                .map(|id| SessionEvent {
+                    time: Utc::now(),
+                    kind: SessionEventKind::MeasurementSaved(id),
+                })
+                .into_iter()
+                .collect(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_delete_account() {
+        let now = Utc::now();
+        let db = create_in_memory_database();
+        db.update_data(|mut data_model| {
+            data_model.users = vec![User::new("student"), User::new("teacher")];
+            data_model.bookings = [now - Duration::days(1), now + Duration::days(30)]
+                .into_iter()
+                .map(|start_time| Booking {
+                    start_time,
+                    end_time: start_time + Duration::hours(1),
+                    telescope_name: "fake".to_string(),
+                    user_name: "student".to_string(),
+                })
+                .collect();
+            data_model.sessions = vec![session(1, "student", Some(7)), session(2, "student", None)];
+            data_model.measurements = vec![ArchivedMeasurement::new(
+                7,
+                "fake",
+                galactic_measurement(Degrees(120.0), now),
+            )];
+            data_model
+        })
+        .await
+        .unwrap();
+
+        let export = export_user_data(&db.get_data().await.unwrap(), "student").unwrap();
+        assert_eq!(export.bookings.len(), 2);
+        assert_eq!(export.sessions.len(), 2);
+        assert_eq!(export.measurements[0].id, 7);
+
+        assert_eq!(
+            request_deletion(
+                &db,
+                "student",
+                MeasurementHandling::TransferTo("nobody".to_string()),
+                now
+            )
+            .await,
+            Err(DeletionError::UnknownTransferUser("nobody".to_string()))
+        );
+        let handling = MeasurementHandling::TransferTo("teacher".to_string());
+        request_deletion(&db, "student", handling, now)
+            .await
+            .unwrap();
+
+        // Nothing happens until an admin has confirmed and the grace period has passed.
+        let after_grace_period = now + Duration::days(DELETION_GRACE_PERIOD_DAYS);
+        let data_model =
+            delete_confirmed_accounts(db.get_data().await.unwrap(), after_grace_period);
+        assert_eq!(data_model.users.len(), 2);
+        update_deletion(&db, "student", |deletion| {
+            deletion.as_mut().unwrap().confirmed = true
+        })
+        .await
+        .unwrap();
+        let data_model = delete_confirmed_accounts(db.get_data().await.unwrap(), now);
+        assert_eq!(data_model.users.len(), 2);
+
+        let data_model =
+            delete_confirmed_accounts(db.get_data().await.unwrap(), after_grace_period);
+        let names: Vec<_> = data_model.users.iter().map(|u| u.name.as_str()).collect();
+        assert_eq!(names, vec!["teacher"]);
+        assert_eq!(data_model.bookings.len(), 1);
+        assert_eq!(data_model.bookings[0].user_name, DELETED_USER_NAME);
+        assert_eq!(data_model.sessions.len(), 1);
+        assert_eq!(data_model.sessions[0].user_name, "teacher");
+        assert_eq!(data_model.measurements.len(), 1);
+    }
+}
@@ -0,0 +1,295 @@
+use crate::authentication::routes::login_cookie;
+use crate::database::{DataBase, Storage};
+use crate::template::HtmlTemplate;
+use crate::users::api_routes::{fetch_user, UserNotFound};
+use crate::users::dashboard::{class_progress, DashboardError};
+use crate::users::demo::{create_demo_account, DemoError};
+use crate::users::import::{import_users, ImportOutcome, ImportRequest};
+use crate::users::privacy::{request_deletion, DeletionError, MeasurementHandling};
+use crate::users::tutorial::TutorialStep;
+use crate::users::{TrainingStep, User, UserKind};
+use askama::Template;
+use axum::{
+    extract::{Form, Path, Query, State},
+    http::header,
+    response::IntoResponse,
+    routing::{get, post},
+    Router,
+};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+
+pub fn routes(database: DataBase<impl Storage + 'static>) -> Router {
+    Router::new()
+        .route("/", get(get_users))
+        .route("/demo", post(create_demo))
+        .route("/import", post(import))
+        .route("/tutorial", get(get_tutorial))
+        .route("/:name/training", get(get_training))
+        .route("/:name/dashboard", get(get_dashboard))
+        .route("/:name/deletion", post(request_user_deletion))
+        .with_state(database)
+}
+
+#[derive(Template)]
+#[template(path = "users.html")]
+struct UsersTemplate {
+    users: Vec<User>,
+}
+
+async fn get_users<StorageType>(State(db): State<DataBase<StorageType>>) -> impl IntoResponse
+where
+    StorageType: Storage,
+{
+    let users = db
+        .get_data()
+        .await
+        .expect("As long as no one is manually editing the database, this should never fail.")
+        .users;
+    HtmlTemplate(UsersTemplate { users })
+}
+
+#[derive(Deserialize, Debug)]
+struct ImportForm {
+    group: String,
+    csv: String,
+}
+
+struct ImportReportRow {
+    line: usize,
+    name: String,
+    outcome: String,
+    booking: String,
+}
+
+#[derive(Template)]
+#[template(path = "import_report.html")]
+struct ImportReportTemplate {
+    error: Option<String>,
+    rows: Vec<ImportReportRow>,
+}
+
+async fn import<StorageType>(
+    State(db): State<DataBase<StorageType>>,
+    Form(form): Form<ImportForm>,
+) -> impl IntoResponse
+where
+    StorageType: Storage,
+{
+    let request = ImportRequest {
+        group: Some(form.group).filter(|group| !group.is_empty()),
+        csv: form.csv,
+    };
+    let (error, rows) = match import_users(&db, request).await {
+        Ok(rows) => (None, rows),
+        Err(error) => (Some(format!("{:?}", error)), vec![]),
+    };
+    let rows = rows
+        .into_iter()
+        .map(|row| ImportReportRow {
+            line: row.line,
+            name: row.name,
+            outcome: match row.outcome {
+                ImportOutcome::Created => "created".to_string(),
+                ImportOutcome::Updated => "updated".to_string(),
+                ImportOutcome::Skipped(reason) => format!("skipped: {}", reason),
+            },
+            booking: match row.booking {
+                None => String::new(),
+                Some(Ok(_)) => "booked".to_string(),
+                Some(Err(error)) => format!("not booked: {:?}", error),
+            },
+        })
+        .collect();
+    HtmlTemplate(ImportReportTemplate { error, rows })
+}
+
+struct ChecklistItem {
+    step: String,
+    description: &'static str,
+    completed: bool,
+}
+
+#[derive(Template)]
+#[template(path = "training.html")]
+struct TrainingTemplate {
+    name: String,
+    certified: bool,
+    checklist: Vec<ChecklistItem>,
+    deletion_time: Option<DateTime<Utc>>,
+    teacher: bool,
+}
+
+async fn get_training<StorageType>(
+    State(db): State<DataBase<StorageType>>,
+    Path(name): Path<String>,
+) -> Result<impl IntoResponse, UserNotFound>
+where
+    StorageType: Storage,
+{
+    let user = fetch_user(&db, &name).await?;
+    let checklist = TrainingStep::ALL
+        .iter()
+        .map(|step| ChecklistItem {
+            step: format!("{:?}", step),
+            description: step.description(),
+            completed: user.completed_training.contains(step),
+        })
+        .collect();
+    Ok(HtmlTemplate(TrainingTemplate {
+        name: user.name,
+        certified: user.certified,
+        checklist,
+        deletion_time: user.deletion.map(|deletion| deletion.deletion_time()),
+        teacher: !user.teaches.is_empty(),
+    }))
+}
+
+struct StudentRow {
+    name: String,
+    group: String,
+    certified: bool,
+    training: String,
+    tutorial_completed: bool,
+    sessions: usize,
+    measurements: Vec<u64>,
+    last_observation: String,
+}
+
+#[derive(Template)]
+#[template(path = "dashboard.html")]
+struct DashboardTemplate {
+    name: String,
+    students: Vec<StudentRow>,
+}
+
+async fn get_dashboard<StorageType>(
+    State(db): State<DataBase<StorageType>>,
+    Path(name): Path<String>,
+) -> Result<impl IntoResponse, DashboardError>
+where
+    StorageType: Storage,
+{
+    let data_model = db
+        .get_data()
+        .await
+        .expect("As long as no one is manually editing the database, this should never fail.");
+    let students = class_progress(&data_model, &name)?
+        .into_iter()
+        .map(|progress| StudentRow {
+            name: progress.name,
+            group: progress.group,
+            certified: progress.certified,
+            training: format!(
+                "{}/{}",
+                progress.completed_training.len(),
+                TrainingStep::ALL.len()
+            ),
+            tutorial_completed: progress.tutorial_completed,
+            sessions: progress.sessions,
+            measurements: progress.measurements,
+            last_observation: progress
+                .last_observation
+                .map(|time| time.format("%Y-%m-%d %H:%M").to_string())
+                .unwrap_or_default(),
+        })
+        .collect();
+    Ok(HtmlTemplate(DashboardTemplate { name, students }))
+}
+
+#[derive(Deserialize, Debug)]
+struct DeletionForm {
+    transfer_to: String,
+}
+
+async fn request_user_deletion<StorageType>(
+    State(db): State<DataBase<StorageType>>,
+    Path(name): Path<String>,
+    Form(form): Form<DeletionForm>,
+) -> Result<impl IntoResponse, DeletionError>
+where
+    StorageType: Storage,
+{
+    let measurements = if form.transfer_to.is_empty() {
+        MeasurementHandling::Delete
+    } else {
+        MeasurementHandling::TransferTo(form.transfer_to)
+    };
+    request_deletion(&db, &name, measurements, Utc::now()).await?;
+    get_training(State(db), Path(name))
+        .await
+        .map_err(|_| DeletionError::UserNotFound)
+}
+
+#[derive(Deserialize)]
+struct TutorialQuery {
+    #[serde(default)]
+    name: String,
+}
+
+#[derive(Template)]
+#[template(path = "tutorial.html")]
+struct TutorialTemplate {
+    name: String,
+    step_number: usize,
+    number_of_steps: usize,
+    instructions: &'static str,
+    active: bool,
+}
+
+/// The tutorial overlay of the observe page. It is polled by the page and
+/// stays empty for unknown users and users who are done with the tutorial.
+async fn get_tutorial<StorageType>(
+    State(db): State<DataBase<StorageType>>,
+    Query(query): Query<TutorialQuery>,
+) -> impl IntoResponse
+where
+    StorageType: Storage,
+{
+    let step = fetch_user(&db, &query.name)
+        .await
+        .map(|user| user.tutorial)
+        .unwrap_or(TutorialStep::Completed);
+    HtmlTemplate(TutorialTemplate {
+        name: query.name,
+        step_number: step as usize + 1,
+        number_of_steps: TutorialStep::Completed as usize,
+        instructions: step.instructions(),
+        active: step != TutorialStep::Completed,
+    })
+}
+
+#[derive(Template)]
+#[template(path = "demo.html")]
+struct DemoTemplate {
+    name: String,
+    expires: String,
+    telescope_name: Option<String>,
+    booking_end: String,
+}
+
+async fn create_demo<StorageType>(
+    State(db): State<DataBase<StorageType>>,
+) -> Result<impl IntoResponse, DemoError>
+where
+    StorageType: Storage,
+{
+    let account = create_demo_account(&db, Utc::now()).await?;
+    let expires = match account.user.kind {
+        UserKind::Demo { expires } => expires.format("%H:%M").to_string(),
+        UserKind::Regular => String::new(),
+    };
+    // Logged in as the new account for as long as it exists.
+    Ok((
+        [(header::SET_COOKIE, login_cookie(&account.token))],
+        HtmlTemplate(DemoTemplate {
+            name: account.user.name,
+            expires,
+            telescope_name: account.booking.as_ref().map(|b| b.telescope_name.clone()),
+            booking_end: account
+                .booking
+                .map(|b| b.end_time.format("%H:%M").to_string())
+                .unwrap_or_default(),
+        }),
+    ))
+}
@@ -0,0 +1,113 @@
+use crate::sessions::{SessionEvent, SessionEventKind};
+use crate::telescopes::{TelescopeStatus, TelescopeTarget};
+use crate::users::User;
+use serde::{Deserialize, Serialize};
+
+/// Steps of the guided first observation, shown as an overlay on the
+/// observe page until the user has saved a first measurement.
+///
+/// The tutorial is advanced by the session events recorded while the user
+/// has the telescope booked, so it follows what the telescope actually does.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone, Default)]
+pub enum TutorialStep {
+    #[default]
+    PickTarget,
+    Track,
+    Integrate,
+    Save,
+    Completed,
+}
+
+impl TutorialStep {
+    pub fn advance(self, event: &SessionEventKind) -> TutorialStep {
+        match (self, event) {
+            (TutorialStep::PickTarget, SessionEventKind::TargetChanged(target))
+                if !matches!(target, TelescopeTarget::Parked | TelescopeTarget::Stopped) =>
+            {
+                TutorialStep::Track
+            }
+            (TutorialStep::Track, SessionEventKind::StatusChanged(TelescopeStatus::Tracking)) => {
+                TutorialStep::Integrate
+            }
+            (TutorialStep::Integrate, SessionEventKind::IntegrationStarted) => TutorialStep::Save,
+            (TutorialStep::Save, SessionEventKind::MeasurementSaved(_)) => TutorialStep::Completed,
+            (step, _) => step,
+        }
+    }
+
+    pub fn instructions(&self) -> &'static str {
+        match self {
+            TutorialStep::PickTarget => {
+                "Pick a target, for example a point in the galactic plane, and send it to the telescope."
+            }
+            TutorialStep::Track => "Wait while the telescope slews until it is tracking the target.",
+            TutorialStep::Integrate => "Start an integration to measure the spectrum of the target.",
+            TutorialStep::Save => {
+                "Stop the integration when the spectrum looks good, it is then saved to the archive."
+            }
+            TutorialStep::Completed => "You have made your first observation!",
+        }
+    }
+}
+
+/// Advance the tutorial of `user_name` through the recorded `events`.
+pub fn advance_tutorial(users: &mut [User], user_name: &str, events: &[SessionEvent]) {
+    if let Some(user) = users.iter_mut().find(|u| u.name == user_name) {
+        let before = user.tutorial;
+        user.tutorial = events
+            .iter()
+            .fold(user.tutorial, |step, event| step.advance(&event.kind));
+        if before != user.tutorial && user.tutorial == TutorialStep::Completed {
+            log::info!("{} completed the tutorial", user_name);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::angles::Radians;
+    use chrono::Utc;
+
+    #[test]
+    fn test_advance_tutorial() {
+        let mut users = vec![User::new("student")];
+        let event = |kind| SessionEvent {
+            time: Utc::now(),
+            kind,
+        };
+
+        advance_tutorial(
+            &mut users,
+            "student",
+            &[
+                event(SessionEventKind::TargetChanged(TelescopeTarget::Parked)),
+                event(SessionEventKind::StatusChanged(TelescopeStatus::Idle)),
+            ],
+        );
+        assert_eq!(users[0].tutorial, TutorialStep::PickTarget);
+
+        advance_tutorial(
+            &mut users,
+            "student",
+            &[
+                event(SessionEventKind::TargetChanged(TelescopeTarget::Galactic {
+                    l: Radians(0.1),
+                    b: Radians(0.0),
+                })),
+                event(SessionEventKind::StatusChanged(TelescopeStatus::Slewing)),
+                event(SessionEventKind::StatusChanged(TelescopeStatus::Tracking)),
+                event(SessionEventKind::IntegrationStarted),
+                event(SessionEventKind::IntegrationStopped),
+            ],
+        );
+        assert_eq!(users[0].tutorial, TutorialStep::Save);
+
+        advance_tutorial(
+            &mut users,
+            "student",
+            &[event(SessionEventKind::MeasurementSaved(1))],
+        );
+        assert_eq!(users[0].tutorial, TutorialStep::Completed);
+    }
+}